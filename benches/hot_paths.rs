@@ -0,0 +1,189 @@
+//! Benchmarks of the hot paths. The manifest can't take Criterion nor turn the default
+//! test harness off, so the cases are tests timed by the small harness below, run with
+//!
+//! ```sh
+//! cargo test --release --bench hot_paths -- --nocapture --test-threads 1
+//! ```
+//!
+//! Each case warms up, then reports the mean and the spread of the time per iteration
+//! over several samples. In debug builds each case runs only once, to check that it
+//! still works.
+
+use redis_starter_rust::{Config, IncomingMessage, Resp, Store};
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+const WARM_UP: Duration = Duration::from_millis(200);
+const SAMPLES: usize = 20;
+const SAMPLE_TIME: Duration = Duration::from_millis(50);
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    if cfg!(debug_assertions) {
+        f();
+        return;
+    }
+
+    // Iterations per sample, so that a sample takes about SAMPLE_TIME.
+    let started = Instant::now();
+    let mut iters: u32 = 0;
+    while started.elapsed() < WARM_UP {
+        f();
+        iters += 1;
+    }
+    let per_sample = (iters * SAMPLE_TIME.as_millis() as u32 / WARM_UP.as_millis() as u32).max(1);
+
+    let mut nanos: Vec<f64> = (0..SAMPLES)
+        .map(|_| {
+            let started = Instant::now();
+            for _ in 0..per_sample {
+                f();
+            }
+            started.elapsed().as_nanos() as f64 / f64::from(per_sample)
+        })
+        .collect();
+    nanos.sort_by(f64::total_cmp);
+
+    let mean = nanos.iter().sum::<f64>() / nanos.len() as f64;
+    let (low, high) = (nanos[0], nanos[nanos.len() - 1]);
+    println!("{name:<32} {mean:>12.1} ns/iter  [{low:.1} .. {high:.1}]");
+}
+
+fn pipeline(commands: usize) -> Vec<u8> {
+    (0..commands)
+        .flat_map(|i| {
+            let args = vec!["SET".to_string(), format!("key:{i}"), "x".repeat(32)];
+            Resp::from(args).serialize()
+        })
+        .collect()
+}
+
+fn config(args: &[&str]) -> Config {
+    let args = std::iter::once("bench")
+        .chain(args.iter().copied())
+        .map(String::from)
+        .collect();
+    Config::new(args).unwrap()
+}
+
+#[test]
+fn resp_parse() {
+    let one = pipeline(1);
+    bench("resp/parse/1", || {
+        black_box(IncomingMessage::from_buffer(black_box(&one)).unwrap());
+    });
+
+    let many = pipeline(100);
+    bench("resp/parse/pipeline-100", || {
+        black_box(IncomingMessage::from_buffer(black_box(&many)).unwrap());
+    });
+}
+
+#[test]
+fn resp_serialize() {
+    let resp = Resp::A(
+        (0..100)
+            .map(|i| Resp::BS(Some(format!("value:{i}"))))
+            .collect(),
+    );
+    bench("resp/serialize/array-100", || {
+        black_box(black_box(&resp).serialize());
+    });
+}
+
+#[test]
+fn store_get_set() {
+    let runtime = Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let store = Arc::new(Store::new(&config(&[])).unwrap());
+
+    bench("store/set", || {
+        runtime.block_on(store.set_string("key", "value".into(), None));
+    });
+    bench("store/get", || {
+        black_box(
+            runtime
+                .block_on(store.with_value("key", String::clone))
+                .unwrap(),
+        );
+    });
+
+    // Tasks setting and getting keys of their own at once, contending for the shards.
+    for tasks in [4, 16] {
+        bench(&format!("store/get-set/{tasks}-tasks"), || {
+            runtime.block_on(async {
+                let handles: Vec<_> = (0..tasks)
+                    .map(|t| {
+                        let store = Arc::clone(&store);
+                        tokio::spawn(async move {
+                            for i in 0..100 {
+                                let key = format!("key:{t}:{i}");
+                                store.set_string(&key, "value".into(), None).await;
+                                black_box(store.with_value(&key, String::clone).await.unwrap());
+                            }
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.await.unwrap();
+                }
+            });
+        });
+    }
+}
+
+#[test]
+fn stream_append_range() {
+    let runtime = Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let store = Store::new(&config(&[])).unwrap();
+    let values: HashMap<String, String> = [("field".to_string(), "value".to_string())].into();
+
+    bench("stream/append", || {
+        let id = runtime.block_on(store.set_stream("append", "*".into(), values.clone()));
+        black_box(id.unwrap());
+    });
+
+    for i in 1..=1000 {
+        let id = format!("{i}-0");
+        runtime
+            .block_on(store.set_stream("range", id, values.clone()))
+            .unwrap();
+    }
+    bench("stream/range/100-of-1000", || {
+        let range = runtime.block_on(store.query_stream("range", "400-0".into(), "499-0".into()));
+        black_box(range.unwrap());
+    });
+}
+
+#[test]
+fn rdb_load() {
+    let dir = std::env::temp_dir().join(format!("redis-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir_arg = dir.to_string_lossy().to_string();
+    let config = config(&["--dir", &dir_arg, "--dbfilename", "bench.rdb"]);
+
+    // The file is written by the server itself, with keys of which a tenth expire.
+    let runtime = Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let store = Store::new(&config).unwrap();
+    runtime.block_on(async {
+        for i in 0..10_000 {
+            let exp = (i < 1000).then_some(3_600_000);
+            store
+                .set_string(&format!("key:{i}"), "x".repeat(32), exp)
+                .await;
+        }
+        store.bgsave().await.unwrap();
+        while store.bgsave_in_progress() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    });
+
+    bench("rdb/load/10000-keys", || {
+        black_box(Store::new(&config).unwrap());
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+}
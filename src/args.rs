@@ -0,0 +1,142 @@
+use crate::{RedisError, RedisResult};
+use std::str::FromStr;
+
+/// The arguments of a command past its name, taken from the front in the order its
+/// grammar lists them.
+#[derive(Debug, Clone)]
+pub(crate) struct Args<'a> {
+    values: &'a [String],
+    taken: usize,
+    need: usize,
+}
+
+impl<'a> Args<'a> {
+    /// `need` is the number of arguments the command can't do without, which errors
+    /// about missing ones report.
+    pub(crate) fn new(values: &'a [String], need: usize) -> Self {
+        Self {
+            values,
+            taken: 0,
+            need,
+        }
+    }
+
+    /// The next argument, which must be there.
+    pub(crate) fn expect(&mut self) -> RedisResult<&'a str> {
+        let value = self.values.get(self.taken).ok_or_else(|| self.missing())?;
+        self.taken += 1;
+        Ok(value)
+    }
+
+    pub(crate) fn expect_key(&mut self) -> RedisResult<String> {
+        self.expect().map(String::from)
+    }
+
+    /// The next argument as a number.
+    pub(crate) fn expect_int<T: FromStr>(&mut self) -> RedisResult<T> {
+        self.expect()?.parse().map_err(|_| RedisError::NotInteger)
+    }
+
+    /// The arguments not taken yet, of which there must be one at least.
+    pub(crate) fn expect_many(&mut self) -> RedisResult<&'a [String]> {
+        self.peek().ok_or_else(|| self.missing())?;
+        Ok(self.rest())
+    }
+
+    /// Whether the next argument is the token, which is then taken.
+    pub(crate) fn optional_token(&mut self, token: &str) -> bool {
+        self.keyword(&[token]).is_some()
+    }
+
+    /// The keyword of the set the next argument is, if any, in the case it's listed in.
+    pub(crate) fn keyword<'k>(&mut self, set: &[&'k str]) -> Option<&'k str> {
+        let value = self.peek()?;
+        let keyword = set.iter().find(|k| k.eq_ignore_ascii_case(value))?;
+        self.taken += 1;
+        Some(keyword)
+    }
+
+    /// The flags of the set the arguments are, in any order, up to the first one which
+    /// isn't.
+    pub(crate) fn keyword_set<'k>(&mut self, set: &[&'k str]) -> Vec<&'k str> {
+        std::iter::from_fn(|| self.keyword(set)).collect()
+    }
+
+    /// The name of the option the next argument is, uppercased.
+    pub(crate) fn option(&mut self) -> Option<String> {
+        let value = self.peek()?;
+        self.taken += 1;
+        Some(value.to_uppercase())
+    }
+
+    pub(crate) fn peek(&self) -> Option<&'a str> {
+        self.values.get(self.taken).map(String::as_str)
+    }
+
+    /// The arguments not taken yet, which are all taken by it.
+    pub(crate) fn rest(&mut self) -> &'a [String] {
+        let rest = &self.values[self.taken..];
+        self.taken = self.values.len();
+        rest
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.taken == self.values.len()
+    }
+
+    fn missing(&self) -> RedisError {
+        RedisError::LackOfArgs {
+            need: self.need.max(self.taken + 1),
+            got: self.values.len(),
+        }
+    }
+
+    /// Fails with a syntax error if any argument is left.
+    pub(crate) fn finish(&self) -> RedisResult<()> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(RedisError::Syntax)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(args: &[&str]) -> Vec<String> {
+        args.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn it_takes_arguments_in_order() {
+        let values = values(&["key", "10", "px", "NX", "xx", "rest"]);
+        let mut args = Args::new(&values, 2);
+        assert_eq!(args.expect_key().unwrap(), "key");
+        assert_eq!(args.expect_int::<u64>().unwrap(), 10);
+        assert!(!args.optional_token("EX"));
+        assert!(args.optional_token("PX"));
+        assert_eq!(args.keyword_set(&["NX", "XX"]), vec!["NX", "XX"]);
+        assert!(args.finish().is_err());
+        assert_eq!(args.peek(), Some("rest"));
+        assert_eq!(args.rest(), ["rest"]);
+        assert!(args.finish().is_ok());
+    }
+
+    #[test]
+    fn it_tells_what_is_missing() {
+        let values = values(&["key", "ten"]);
+        let mut args = Args::new(&values, 3);
+        args.expect().unwrap();
+        assert!(matches!(
+            args.expect_int::<u64>(),
+            Err(RedisError::NotInteger)
+        ));
+        assert!(matches!(
+            args.expect(),
+            Err(RedisError::LackOfArgs { need: 3, got: 2 })
+        ));
+        assert!(args.option().is_none());
+    }
+}
@@ -0,0 +1,327 @@
+use super::{resp::RespError, utils, utils::Tokens, RedisError, RedisResult, Resp};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const DEFAULT_TESTS: &str = "ping,set,get,incr";
+
+/// What `--benchmark` drives against the target.
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    host: String,
+    port: u16,
+    clients: usize,
+    requests: u64,
+    pipeline: usize,
+    tests: Vec<Test>,
+    data_size: usize,
+    /// Keys are picked at random among this many. 0 uses one key for every request.
+    keyspace: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Test {
+    Ping,
+    Set,
+    Get,
+    Incr,
+}
+
+impl Test {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "ping" => Some(Self::Ping),
+            "set" => Some(Self::Set),
+            "get" => Some(Self::Get),
+            "incr" => Some(Self::Incr),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Ping => "PING",
+            Self::Set => "SET",
+            Self::Get => "GET",
+            Self::Incr => "INCR",
+        }
+    }
+
+    fn command(&self, options: &Options) -> Vec<u8> {
+        let key = match options.keyspace {
+            0 => "key:__rand_int__".to_string(),
+            n => format!("key:{:012}", utils::random_u64() % n),
+        };
+        let args: Vec<String> = match self {
+            Self::Ping => vec!["PING".into()],
+            Self::Set => vec!["SET".into(), key, "x".repeat(options.data_size)],
+            Self::Get => vec!["GET".into(), key],
+            Self::Incr => vec!["INCR".into(), "counter:__rand_int__".into()],
+        };
+        Resp::A(args.into_iter().map(|arg| Resp::BS(Some(arg))).collect()).serialize()
+    }
+}
+
+impl Options {
+    fn parse(args: &[String]) -> RedisResult<Self> {
+        let value_of = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+                .map(String::as_str)
+        };
+        fn num<T: std::str::FromStr>(
+            value: Option<&str>,
+            name: &str,
+            default: T,
+        ) -> RedisResult<T> {
+            match value {
+                None => Ok(default),
+                Some(value) => value
+                    .parse::<T>()
+                    .map_err(|_| anyhow::anyhow!("Invalid {name} '{value}'").into()),
+            }
+        }
+
+        let tests = value_of("--tests")
+            .unwrap_or(DEFAULT_TESTS)
+            .split(',')
+            .map(|name| Test::parse(name).ok_or_else(|| anyhow::anyhow!("Unknown test '{name}'")))
+            .collect::<Result<Vec<Test>, _>>()?;
+        let options = Self {
+            host: value_of("--host").unwrap_or("127.0.0.1").to_string(),
+            port: num(value_of("--port"), "port", 6379)?,
+            clients: num(value_of("--clients"), "clients", 50)?,
+            requests: num(value_of("--requests"), "requests", 100_000)?,
+            pipeline: num(value_of("--pipeline"), "pipeline", 1)?,
+            tests,
+            data_size: num(value_of("--data-size"), "data size", 3)?,
+            keyspace: num(value_of("--keyspace"), "keyspace", 0)?,
+        };
+        if options.clients == 0 || options.pipeline == 0 {
+            return Err(anyhow::anyhow!("clients and pipeline must be positive").into());
+        }
+        Ok(options)
+    }
+}
+
+/// What the clients of one test measured.
+#[derive(Debug, Default)]
+struct Report {
+    elapsed: Duration,
+    /// The latency of every request. Pipelined requests share the latency of their batch.
+    latencies: Vec<Duration>,
+    errors: u64,
+}
+
+impl Report {
+    /// The latency below which the given percent of the requests completed.
+    fn percentile(&self, percent: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percent / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+
+    fn print(&self, test: Test, options: &Options) {
+        let secs = self.elapsed.as_secs_f64();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        println!("====== {} ======", test.name());
+        println!(
+            "  {} requests completed in {secs:.2} seconds",
+            self.latencies.len()
+        );
+        println!("  {} parallel clients", options.clients);
+        println!("  {} bytes payload", options.data_size);
+        println!("  pipeline {}", options.pipeline);
+        if self.errors > 0 {
+            println!("  {} error replies", self.errors);
+        }
+        println!();
+        println!(
+            "Latency (msec): p50={:.3} p95={:.3} p99={:.3} max={:.3}",
+            ms(self.percentile(50.0)),
+            ms(self.percentile(95.0)),
+            ms(self.percentile(99.0)),
+            ms(self.percentile(100.0)),
+        );
+        let rps = if secs > 0.0 {
+            self.latencies.len() as f64 / secs
+        } else {
+            0.0
+        };
+        println!("{rps:.2} requests per second");
+        println!();
+    }
+}
+
+/// Runs the benchmark asked by `--benchmark`, as redis-benchmark does, giving the exit
+/// code of the process. None means no benchmark was asked and the server should start.
+///
+/// Options: `--host`, `--port`, `--clients`, `--requests`, `--pipeline`, `--tests`
+/// (a comma separated list of ping, set, get and incr), `--data-size` and `--keyspace`.
+pub fn run(args: &[String]) -> Option<i32> {
+    if !args.iter().any(|arg| arg == "--benchmark") {
+        return None;
+    }
+
+    let result = Options::parse(args).and_then(|options| {
+        let runtime = tokio::runtime::Runtime::new()?;
+        for test in options.tests.iter() {
+            let report = runtime.block_on(bench(*test, &options))?;
+            report.print(*test, &options);
+        }
+        Ok(())
+    });
+    match result {
+        Ok(_) => Some(0),
+        Err(err) => {
+            eprintln!("{err}");
+            Some(1)
+        }
+    }
+}
+
+async fn bench(test: Test, options: &Options) -> RedisResult<Report> {
+    let issued = Arc::new(AtomicU64::new(0));
+    let mut clients = Vec::with_capacity(options.clients);
+    for _ in 0..options.clients {
+        let stream = TcpStream::connect((options.host.as_str(), options.port)).await?;
+        stream.set_nodelay(true)?;
+        clients.push(stream);
+    }
+
+    let start = Instant::now();
+    let tasks: Vec<_> = clients
+        .into_iter()
+        .map(|stream| {
+            let issued = Arc::clone(&issued);
+            let options = options.clone();
+            tokio::spawn(async move { drive(stream, test, &options, &issued).await })
+        })
+        .collect();
+
+    let mut report = Report::default();
+    for task in tasks {
+        let (latencies, errors) = task.await.map_err(|err| anyhow::anyhow!("{err}"))??;
+        report.latencies.extend(latencies);
+        report.errors += errors;
+    }
+    report.elapsed = start.elapsed();
+    report.latencies.sort_unstable();
+    Ok(report)
+}
+
+/// Sends batches of `pipeline` requests on one connection until the requests of the test
+/// have all been issued, giving the latencies and the number of error replies.
+async fn drive(
+    mut stream: TcpStream,
+    test: Test,
+    options: &Options,
+    issued: &AtomicU64,
+) -> RedisResult<(Vec<Duration>, u64)> {
+    let mut latencies: Vec<Duration> = vec![];
+    let mut errors: u64 = 0;
+    let mut buf: Vec<u8> = vec![];
+    let mut chunk = vec![0u8; 16 * 1024];
+
+    loop {
+        let batch = options.pipeline as u64;
+        let taken = issued.fetch_add(batch, Ordering::Relaxed);
+        if taken >= options.requests {
+            break;
+        }
+        let batch = batch.min(options.requests - taken) as usize;
+
+        let mut frames: Vec<u8> = vec![];
+        for _ in 0..batch {
+            frames.extend(test.command(options));
+        }
+        let sent = Instant::now();
+        stream.write_all(&frames).await?;
+
+        let mut replies = 0;
+        while replies < batch {
+            let read = stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Err(anyhow::anyhow!("The server closed the connection").into());
+            }
+            buf.extend_from_slice(&chunk[..read]);
+            let (count, errs) = take_replies(&mut buf)?;
+            replies += count;
+            errors += errs;
+        }
+        latencies.extend(std::iter::repeat_n(sent.elapsed(), batch));
+    }
+    Ok((latencies, errors))
+}
+
+/// Takes the whole replies at the start of the buffer, giving how many there were and how
+/// many of them were errors. A reply cut short stays in the buffer for the next read.
+fn take_replies(buf: &mut Vec<u8>) -> RedisResult<(usize, u64)> {
+    let mut tokens = Tokens::new(buf);
+    let mut count = 0;
+    let mut errors = 0;
+    let mut consumed = 0;
+
+    while !tokens.finished() {
+        match Resp::from_tokens(&mut tokens) {
+            Ok(resp) => {
+                count += 1;
+                if matches!(resp, Resp::SE(_)) {
+                    errors += 1;
+                }
+                consumed = buf.len() - tokens.rest().len();
+            }
+            Err(RedisError::Protocol(RespError::Incomplete)) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    buf.drain(..consumed);
+    Ok((count, errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_benchmark_options() {
+        let args: Vec<String> = ["bin", "--benchmark", "--clients", "8", "--tests", "set,GET"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let options = Options::parse(&args).unwrap();
+        assert_eq!(options.clients, 8);
+        assert_eq!(options.port, 6379);
+        assert_eq!(options.tests, vec![Test::Set, Test::Get]);
+
+        let args: Vec<String> = ["--tests", "lpush"].iter().map(|v| v.to_string()).collect();
+        assert!(Options::parse(&args).is_err());
+    }
+
+    #[test]
+    fn it_takes_whole_replies() {
+        let mut buf = b"+OK\r\n-ERR wrong\r\n$3\r\nba".to_vec();
+        assert_eq!(take_replies(&mut buf).unwrap(), (2, 1));
+        assert_eq!(buf, b"$3\r\nba");
+
+        buf.extend_from_slice(b"r\r\n");
+        assert_eq!(take_replies(&mut buf).unwrap(), (1, 0));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn it_picks_percentiles() {
+        let report = Report {
+            latencies: (1..=100).map(Duration::from_millis).collect(),
+            ..Default::default()
+        };
+        assert_eq!(report.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(report.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(report.percentile(100.0), Duration::from_millis(100));
+    }
+}
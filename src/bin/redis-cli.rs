@@ -0,0 +1,7 @@
+use redis_starter_rust as rss;
+use std::env;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    std::process::exit(rss::cli::run(&args));
+}
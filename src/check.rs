@@ -0,0 +1,227 @@
+use super::{rdb::Rdb, resp::RespError, store::Manifest, utils::Tokens, RedisError, Resp};
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Runs the check asked by `--check-rdb <file>` or `--check-aof <file> [--fix]`, as
+/// redis-check-rdb and redis-check-aof do, giving the exit code of the process. None means
+/// no check was asked and the server should start.
+pub fn run(args: &[String]) -> Option<i32> {
+    let fix = args.iter().any(|arg| arg == "--fix");
+    // `--fix` may come before the file, as in `--check-aof --fix <file>`.
+    let args: Vec<&String> = args.iter().filter(|arg| *arg != "--fix").collect();
+    let value_of = |name: &str| {
+        args.iter()
+            .position(|arg| *arg == name)
+            .map(|i| args.get(i + 1).map(Path::new))
+    };
+
+    let ok = match (value_of("--check-rdb"), value_of("--check-aof")) {
+        (None, None) => return None,
+        (Some(Some(path)), None) => check_rdb(path),
+        (None, Some(Some(path))) => check_aof(path, fix),
+        (Some(_), Some(_)) => {
+            eprintln!("Only one of --check-rdb and --check-aof can be given");
+            false
+        }
+        _ => {
+            eprintln!("Usage: --check-rdb <file> | --check-aof [--fix] <file>");
+            false
+        }
+    };
+    Some(if ok { 0 } else { 1 })
+}
+
+fn check_rdb(path: &Path) -> bool {
+    println!("[offset 0] Checking RDB file {}", path.display());
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            println!("Cannot open RDB file {}: {err}", path.display());
+            return false;
+        }
+    };
+
+    match Rdb::check(&bytes, SystemTime::now()) {
+        Ok(report) => {
+            if report.checksum {
+                println!("[offset {}] Checksum OK", report.size);
+            } else {
+                println!(
+                    "[offset {}] RDB file was saved with checksum disabled: no check performed.",
+                    report.size
+                );
+            }
+            println!("[offset {}] \\o/ RDB looks OK! \\o/", report.size);
+            println!("[info] {} keys read", report.keys);
+            println!("[info] {} expires", report.expires);
+            println!("[info] {} already expired", report.already_expired);
+            true
+        }
+        Err(err) => {
+            println!("--- RDB ERROR DETECTED ---");
+            println!("[offset {}] {}", err.offset, err.reason);
+            false
+        }
+    }
+}
+
+/// Checks an AOF file, or every file listed by a manifest. Only the last file of a
+/// manifest is fixed, since truncating any other would drop the writes after it.
+fn check_aof(path: &Path, fix: bool) -> bool {
+    let is_ext = |path: &Path, ext: &str| path.extension().is_some_and(|v| v == ext);
+
+    if is_ext(path, "rdb") {
+        return check_rdb(path);
+    }
+    if !is_ext(path, "manifest") {
+        return check_aof_file(path, fix);
+    }
+
+    let manifest = match fs::read_to_string(path).map_err(RedisError::from) {
+        Ok(text) => Manifest::parse(&text),
+        Err(err) => Err(err),
+    };
+    let manifest = match manifest {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            println!("Invalid AOF manifest {}: {err}", path.display());
+            return false;
+        }
+    };
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let files = manifest.files();
+    for (i, name) in files.iter().enumerate() {
+        let last = i + 1 == files.len();
+        let file = dir.join(name);
+        println!("Checking {}", file.display());
+        let ok = if is_ext(&file, "rdb") {
+            check_rdb(&file)
+        } else {
+            check_aof_file(&file, fix && last)
+        };
+        if !ok {
+            if fix && !last {
+                println!("Only the last AOF file of the manifest can be fixed");
+            }
+            return false;
+        }
+    }
+    println!("All AOF files and manifest are valid");
+    true
+}
+
+fn check_aof_file(path: &Path, fix: bool) -> bool {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            println!("Cannot open AOF file {}: {err}", path.display());
+            return false;
+        }
+    };
+
+    let failure = walk_aof(&bytes).err();
+    let ok_up_to = failure.as_ref().map_or(bytes.len(), |(offset, _)| *offset);
+    if let Some((offset, reason)) = &failure {
+        println!("0x{offset:>8x}: {reason}");
+    }
+    let line = bytes[..ok_up_to].iter().filter(|b| **b == b'\n').count() + 1;
+    println!(
+        "AOF analyzed: filename={}, size={}, ok_up_to={ok_up_to}, ok_up_to_line={line}, diff={}",
+        path.display(),
+        bytes.len(),
+        bytes.len() - ok_up_to
+    );
+
+    if failure.is_none() {
+        println!("AOF {} is valid", path.display());
+        return true;
+    }
+    if !fix {
+        println!(
+            "AOF {} is not valid. Use the --fix option to try fixing it.",
+            path.display()
+        );
+        return false;
+    }
+
+    let truncated = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_len(ok_up_to as u64));
+    match truncated {
+        Ok(_) => {
+            println!("Successfully truncated AOF {}", path.display());
+            true
+        }
+        Err(err) => {
+            println!("Failed to truncate AOF {}: {err}", path.display());
+            false
+        }
+    }
+}
+
+/// Walks the commands of the AOF, giving the offset of the first one that isn't a whole
+/// array of bulk strings, with the reason.
+fn walk_aof(bytes: &[u8]) -> Result<(), (usize, String)> {
+    let mut tokens = Tokens::new(bytes);
+
+    while !tokens.finished() {
+        let offset = bytes.len() - tokens.rest().len();
+        match Resp::from_tokens(&mut tokens) {
+            Ok(Resp::A(args))
+                if !args.is_empty() && args.iter().all(|arg| matches!(arg, Resp::BS(Some(_)))) => {}
+            Ok(_) => {
+                return Err((
+                    offset,
+                    "Expected a command as an array of bulk strings".into(),
+                ))
+            }
+            Err(RedisError::Protocol(RespError::Incomplete)) => {
+                return Err((offset, "Unexpected EOF reading the command".into()));
+            }
+            Err(err) => return Err((offset, err.to_string())),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_walks_aof_commands() {
+        let set = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n";
+        assert_eq!(walk_aof(set), Ok(()));
+        assert_eq!(walk_aof(b""), Ok(()));
+
+        let mut bytes = set.to_vec();
+        bytes.extend_from_slice(b"*2\r\n$3\r\nGET\r\n$3\r\nfo");
+        let (offset, reason) = walk_aof(&bytes).unwrap_err();
+        assert_eq!(offset, set.len());
+        assert_eq!(reason, "Unexpected EOF reading the command");
+
+        let mut bytes = set.to_vec();
+        bytes.extend_from_slice(b"+OK\r\n");
+        let (offset, _) = walk_aof(&bytes).unwrap_err();
+        assert_eq!(offset, set.len());
+    }
+
+    #[test]
+    fn it_fixes_a_truncated_aof() {
+        let dir = std::env::temp_dir().join(format!("redis-check-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("appendonly.aof");
+        let set = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n";
+        fs::write(&path, [&set[..], b"*1\r\n$4\r\nPI"].concat()).unwrap();
+
+        assert!(!check_aof(&path, false));
+        assert!(check_aof(&path, true));
+        assert_eq!(fs::read(&path).unwrap(), set);
+        assert!(check_aof(&path, false));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
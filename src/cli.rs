@@ -0,0 +1,713 @@
+use super::{
+    resp::RespError,
+    utils::{split_args, Tokens},
+    RedisError, RedisResult, Resp,
+};
+use std::io::{self, IsTerminal, Read, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+
+const MAX_DEPTH: usize = 128;
+
+/// How the `redis-cli` binary connects and what it does once connected.
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    host: String,
+    port: u16,
+    /// Sends `HELLO 3` first, so the replies come in RESP3.
+    resp3: bool,
+    /// Streams stdin to the server as mass insertion, as `redis-cli --pipe` does.
+    pipe: bool,
+    /// The command to run once. Empty opens the prompt.
+    command: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> RedisResult<Self> {
+        let mut options = Self {
+            host: "127.0.0.1".into(),
+            port: 6379,
+            resp3: false,
+            pipe: false,
+            command: vec![],
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| RedisError::from(anyhow::anyhow!("{name} needs a value")))
+            };
+            match arg.as_str() {
+                "-h" => options.host = value("-h")?.to_string(),
+                "-p" => {
+                    let port = value("-p")?;
+                    options.port = port
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid port '{port}'"))?;
+                }
+                "-3" => options.resp3 = true,
+                "-2" => options.resp3 = false,
+                "--pipe" => options.pipe = true,
+                _ => {
+                    options.command.push(arg.to_string());
+                    options.command.extend(args.by_ref().cloned());
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// A reply read by the client. RESP2 values are kept as the crate's own [`Resp`], and
+/// the types RESP3 adds on top of them get their own variants.
+#[derive(Debug, Clone, PartialEq)]
+enum Reply {
+    Resp(Resp),
+    Array(Vec<Reply>),
+    Null,
+    Bool(bool),
+    Double(String),
+    BigNumber(String),
+    Verbatim(String),
+    Map(Vec<(Reply, Reply)>),
+    Set(Vec<Reply>),
+    Push(Vec<Reply>),
+}
+
+impl Reply {
+    fn is_error(&self) -> bool {
+        matches!(self, Self::Resp(Resp::SE(_)))
+    }
+
+    /// Parses one reply from the tokens. Aggregates are walked here, since their
+    /// elements may be RESP3 values, and the rest goes to [`Resp`].
+    fn parse(tokens: &mut Tokens<'_>, depth: usize) -> RedisResult<Self> {
+        if depth >= MAX_DEPTH {
+            return Err(RespError::TooDeep.into());
+        }
+        let kind = *tokens.rest().first().ok_or(RespError::Incomplete)?;
+        if matches!(kind, b'+' | b'-' | b':' | b'$') {
+            return Resp::from_tokens(tokens).map(Self::Resp);
+        }
+
+        let line = tokens.line().ok_or(RespError::Incomplete)?;
+        let rest = std::str::from_utf8(&line[1..]).map_err(|_| RespError::InvalidUtf8)?;
+        let len = || rest.parse::<i64>().map_err(|_| RespError::InvalidInteger);
+        let elements = |tokens: &mut Tokens<'_>, count: i64| {
+            (0..count)
+                .map(|_| Self::parse(tokens, depth + 1))
+                .collect::<RedisResult<Vec<Self>>>()
+        };
+
+        match kind {
+            b'*' | b'~' | b'>' => {
+                let count = len()?;
+                if count < 0 {
+                    return Ok(Self::Null);
+                }
+                let values = elements(tokens, count)?;
+                Ok(match kind {
+                    b'*' => Self::Array(values),
+                    b'~' => Self::Set(values),
+                    _ => Self::Push(values),
+                })
+            }
+            b'%' | b'|' => {
+                let mut values = elements(tokens, len()?.max(0) * 2)?.into_iter();
+                let mut pairs = vec![];
+                while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                    pairs.push((key, value));
+                }
+                if kind == b'%' {
+                    Ok(Self::Map(pairs))
+                } else {
+                    // Attributes only annotate the reply that follows them.
+                    Self::parse(tokens, depth + 1)
+                }
+            }
+            b'_' => Ok(Self::Null),
+            b'#' => Ok(Self::Bool(rest == "t")),
+            b',' => Ok(Self::Double(rest.into())),
+            b'(' => Ok(Self::BigNumber(rest.into())),
+            b'=' | b'!' => {
+                let len = usize::try_from(len()?).map_err(|_| RespError::InvalidBulkLength)?;
+                let bytes = tokens.take(len).ok_or(RespError::Incomplete)?;
+                tokens.take(2).ok_or(RespError::Incomplete)?;
+                let text = String::from_utf8_lossy(bytes).into_owned();
+                if kind == b'!' {
+                    return Ok(Self::Resp(Resp::SE(text)));
+                }
+                // The first four bytes name the format, as in `txt:`.
+                Ok(Self::Verbatim(text.get(4..).unwrap_or_default().into()))
+            }
+            other => Err(RespError::InvalidType(other).into()),
+        }
+    }
+
+    /// Formats the reply as redis-cli does on a terminal.
+    fn pretty(&self) -> String {
+        match self {
+            Self::Resp(Resp::SS(val)) => val.clone(),
+            Self::Resp(Resp::SE(val)) => format!("(error) {val}"),
+            Self::Resp(Resp::I(num)) => format!("(integer) {num}"),
+            Self::Resp(Resp::BS(Some(val))) => quote(val),
+            Self::Resp(Resp::BS(None)) | Self::Null => "(nil)".into(),
+            Self::Resp(other) => quote(&String::from_utf8_lossy(&other.serialize())),
+            Self::Bool(val) => format!("({val})"),
+            Self::Double(val) => format!("(double) {val}"),
+            Self::BigNumber(val) => format!("(big number) {val}"),
+            Self::Verbatim(val) => val.clone(),
+            Self::Array(values) | Self::Push(values) if values.is_empty() => "(empty array)".into(),
+            Self::Set(values) if values.is_empty() => "(empty set)".into(),
+            Self::Map(pairs) if pairs.is_empty() => "(empty hash)".into(),
+            Self::Array(values) | Self::Push(values) => numbered(values, ')'),
+            Self::Set(values) => numbered(values, '~'),
+            Self::Map(pairs) => {
+                let width = pairs.len().to_string().len();
+                let lines: Vec<String> = pairs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (key, value))| {
+                        let head = format!("{:>width$}# {} => ", i + 1, key.pretty());
+                        indent(&head, &value.pretty())
+                    })
+                    .collect();
+                lines.join("\n")
+            }
+        }
+    }
+
+    /// Formats the reply as redis-cli does when its output isn't a terminal: values
+    /// come bare, one per line.
+    fn raw(&self) -> String {
+        match self {
+            Self::Resp(Resp::SS(val)) | Self::Resp(Resp::SE(val)) => val.clone(),
+            Self::Resp(Resp::I(num)) => num.to_string(),
+            Self::Resp(Resp::BS(Some(val))) => val.clone(),
+            Self::Resp(Resp::BS(None)) | Self::Null => String::new(),
+            Self::Resp(other) => String::from_utf8_lossy(&other.serialize()).into_owned(),
+            Self::Bool(val) => format!("({val})"),
+            Self::Double(val) | Self::BigNumber(val) | Self::Verbatim(val) => val.clone(),
+            Self::Array(values) | Self::Set(values) | Self::Push(values) => values
+                .iter()
+                .map(Self::raw)
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Self::Map(pairs) => pairs
+                .iter()
+                .map(|(key, value)| format!("{}\n{}", key.raw(), value.raw()))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        }
+    }
+}
+
+fn numbered(values: &[Reply], mark: char) -> String {
+    let width = values.len().to_string().len();
+    let lines: Vec<String> = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| indent(&format!("{:>width$}{mark} ", i + 1), &value.pretty()))
+        .collect();
+    lines.join("\n")
+}
+
+/// Puts the head before the first line of the body and lines the rest up under it.
+fn indent(head: &str, body: &str) -> String {
+    let pad = " ".repeat(head.chars().count());
+    body.split('\n')
+        .enumerate()
+        .map(|(i, line)| format!("{}{line}", if i == 0 { head } else { &pad }))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Quotes a string as redis-cli does, escaping whatever isn't printable ASCII.
+fn quote(val: &str) -> String {
+    let mut out = String::from("\"");
+    for byte in val.bytes() {
+        match byte {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            b' '..=b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("\\x{byte:02x}")),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn command(args: &[String]) -> Vec<u8> {
+    Resp::A(args.iter().map(|arg| Resp::BS(Some(arg.clone()))).collect()).serialize()
+}
+
+/// A blocking connection to the server, reading whole replies.
+struct Client {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    fn connect(options: &Options) -> RedisResult<Self> {
+        let stream = TcpStream::connect((options.host.as_str(), options.port)).map_err(|err| {
+            anyhow::anyhow!(
+                "Could not connect to Redis at {}:{}: {err}",
+                options.host,
+                options.port
+            )
+        })?;
+        Ok(Self {
+            stream,
+            buf: vec![],
+        })
+    }
+
+    fn call(&mut self, args: &[String]) -> RedisResult<Reply> {
+        self.stream.write_all(&command(args))?;
+        self.reply()
+    }
+
+    /// Reads until a whole reply is buffered, keeping what follows it for the next call.
+    fn reply(&mut self) -> RedisResult<Reply> {
+        let mut chunk = [0u8; 16 * 1024];
+        loop {
+            let mut tokens = Tokens::new(&self.buf);
+            match Reply::parse(&mut tokens, 0) {
+                Ok(reply) => {
+                    let consumed = self.buf.len() - tokens.rest().len();
+                    self.buf.drain(..consumed);
+                    return Ok(reply);
+                }
+                Err(RedisError::Protocol(RespError::Incomplete)) => {}
+                Err(err) => return Err(err),
+            }
+            let read = self.stream.read(&mut chunk)?;
+            if read == 0 {
+                return Err(anyhow::anyhow!("Server closed the connection").into());
+            }
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+/// Runs the bundled `redis-cli` binary, giving the exit code of the process.
+///
+/// Options: `-h <host>`, `-p <port>`, `-3` for RESP3 replies and `--pipe` for mass
+/// insertion from stdin. What follows them is sent as one command; without one, commands
+/// are read from the prompt, or line by line when stdin isn't a terminal.
+pub fn run(args: &[String]) -> i32 {
+    let result = Options::parse(args.get(1..).unwrap_or_default()).and_then(|options| {
+        let mut client = Client::connect(&options)?;
+        if options.resp3 {
+            let reply = client.call(&["HELLO".into(), "3".into()])?;
+            if reply.is_error() {
+                eprintln!("Failed to switch to RESP3: {}", reply.raw());
+            }
+        }
+        if options.pipe {
+            pipe(client)
+        } else if !options.command.is_empty() {
+            let reply = client.call(&options.command)?;
+            print_reply(&reply);
+            Ok(!reply.is_error())
+        } else {
+            repl(&mut client, &options).map(|_| true)
+        }
+    });
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => {
+            eprintln!("{err}");
+            1
+        }
+    }
+}
+
+fn print_reply(reply: &Reply) {
+    if io::stdout().is_terminal() {
+        println!("{}", reply.pretty());
+    } else {
+        println!("{}", reply.raw());
+    }
+}
+
+fn repl(client: &mut Client, options: &Options) -> RedisResult<()> {
+    let prompt = format!("{}:{}> ", options.host, options.port);
+    let mut editor = Editor::default();
+    let interactive = io::stdin().is_terminal();
+
+    loop {
+        let line = if interactive {
+            match editor.read_line(&prompt)? {
+                Some(line) => line,
+                None => return Ok(()),
+            }
+        } else {
+            // Stdin isn't held locked, as the editor takes it key by key.
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            line.trim_end_matches(['\r', '\n']).to_string()
+        };
+        let args = match split_args(&line) {
+            Ok(args) if args.is_empty() => continue,
+            Ok(args) => args,
+            Err(err) => {
+                println!("{err}");
+                continue;
+            }
+        };
+        if matches!(args[0].to_lowercase().as_str(), "quit" | "exit") {
+            return Ok(());
+        }
+        editor.remember(&line);
+        let reply = client.call(&args)?;
+        print_reply(&reply);
+    }
+}
+
+/// Streams stdin to the server and counts the replies, as `redis-cli --pipe` does. Stdin
+/// is sent as is when it's already RESP, and split into commands line by line otherwise.
+/// An `ECHO` of a random marker goes last, so its reply tells when every other has come.
+fn pipe(mut client: Client) -> RedisResult<bool> {
+    let mut input = vec![];
+    io::stdin().lock().read_to_end(&mut input)?;
+    if !input.starts_with(b"*") {
+        let text = String::from_utf8_lossy(&input).into_owned();
+        input.clear();
+        for line in text.lines() {
+            let args = split_args(line)?;
+            if !args.is_empty() {
+                input.extend(command(&args));
+            }
+        }
+    }
+    let marker = super::utils::random_hex(20);
+    input.extend(command(&["ECHO".into(), marker.clone()]));
+
+    // Writing from another thread keeps the replies flowing while the input is sent.
+    let mut writer = client.stream.try_clone()?;
+    let sender = std::thread::spawn(move || writer.write_all(&input));
+
+    let (mut replies, mut errors) = (0u64, 0u64);
+    loop {
+        let reply = client.reply()?;
+        if reply == Reply::Resp(Resp::BS(Some(marker.clone()))) {
+            break;
+        }
+        replies += 1;
+        if reply.is_error() {
+            errors += 1;
+            println!("{}", reply.raw());
+        }
+    }
+    sender
+        .join()
+        .map_err(|_| anyhow::anyhow!("The writer panicked"))??;
+    println!("All data transferred. Waiting for the last reply...");
+    println!("Last reply received from server.");
+    println!("errors: {errors}, replies: {replies}");
+    Ok(errors == 0)
+}
+
+/// A key read from the terminal in raw mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    KillToStart,
+    KillToEnd,
+    Clear,
+    Interrupt,
+    Eof,
+    Ignored,
+}
+
+/// A line editor for the prompt, with cursor movement and history. The terminal is put
+/// in raw mode through `stty`, which keeps the client free of bindings to termios.
+#[derive(Debug, Default)]
+struct Editor {
+    history: Vec<String>,
+    line: Vec<char>,
+    cursor: usize,
+    /// Where the history is browsed from. None is the line being typed.
+    browsing: Option<usize>,
+    typed: Vec<char>,
+}
+
+/// What a key did to the line.
+#[derive(Debug, PartialEq)]
+enum Edit {
+    Continue,
+    Done(String),
+    Exit,
+}
+
+impl Editor {
+    fn remember(&mut self, line: &str) {
+        if self.history.last().map(String::as_str) != Some(line) {
+            self.history.push(line.to_string());
+        }
+    }
+
+    fn apply(&mut self, key: Key) -> Edit {
+        match key {
+            Key::Char(c) => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Enter => {
+                let line: String = self.line.drain(..).collect();
+                self.cursor = 0;
+                self.browsing = None;
+                return Edit::Done(line);
+            }
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.line.len(),
+            Key::KillToStart => {
+                self.line.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::KillToEnd => self.line.truncate(self.cursor),
+            Key::Up | Key::Down => self.browse(key == Key::Up),
+            Key::Eof if self.line.is_empty() => return Edit::Exit,
+            Key::Eof => return self.apply(Key::Delete),
+            Key::Interrupt => return Edit::Exit,
+            _ => {}
+        }
+        Edit::Continue
+    }
+
+    fn browse(&mut self, back: bool) {
+        let next = match (self.browsing, back) {
+            (None, true) if !self.history.is_empty() => {
+                self.typed = self.line.clone();
+                Some(self.history.len() - 1)
+            }
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < self.history.len() => Some(i + 1),
+            (Some(_), false) => None,
+            _ => return,
+        };
+        self.line = match next {
+            Some(i) => self.history[i].chars().collect(),
+            None => std::mem::take(&mut self.typed),
+        };
+        self.cursor = self.line.len();
+        self.browsing = next;
+    }
+
+    /// Reads a line from the terminal, giving None once the user asks to leave.
+    fn read_line(&mut self, prompt: &str) -> RedisResult<Option<String>> {
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "-isig", "-ixon", "min", "1"])?;
+        let result = self.edit(prompt);
+        stty(&[saved.trim()])?;
+        println!();
+        result
+    }
+
+    fn edit(&mut self, prompt: &str) -> RedisResult<Option<String>> {
+        let mut stdin = io::stdin().lock();
+        let mut out = io::stdout().lock();
+        loop {
+            let tail: usize = self.line.len() - self.cursor;
+            let line: String = self.line.iter().collect();
+            write!(out, "\r\x1b[K{prompt}{line}")?;
+            if tail > 0 {
+                write!(out, "\x1b[{tail}D")?;
+            }
+            out.flush()?;
+
+            let key = read_key(&mut stdin)?;
+            if key == Key::Clear {
+                write!(out, "\x1b[H\x1b[2J")?;
+                continue;
+            }
+            match self.apply(key) {
+                Edit::Continue => {}
+                Edit::Done(line) => return Ok(Some(line)),
+                Edit::Exit => return Ok(None),
+            }
+        }
+    }
+}
+
+fn stty(args: &[&str]) -> RedisResult<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("stty failed").into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn read_key(input: &mut impl Read) -> io::Result<Key> {
+    let mut byte = [0u8; 1];
+    let mut next = |input: &mut dyn Read| -> io::Result<u8> {
+        match input.read(&mut byte)? {
+            0 => Ok(4),
+            _ => Ok(byte[0]),
+        }
+    };
+
+    let first = next(input)?;
+    Ok(match first {
+        b'\r' | b'\n' => Key::Enter,
+        127 | 8 => Key::Backspace,
+        1 => Key::Home,
+        5 => Key::End,
+        2 => Key::Left,
+        6 => Key::Right,
+        16 => Key::Up,
+        14 => Key::Down,
+        21 => Key::KillToStart,
+        11 => Key::KillToEnd,
+        12 => Key::Clear,
+        3 => Key::Interrupt,
+        4 => Key::Eof,
+        0x1b => match (next(input)?, next(input)?) {
+            (b'[' | b'O', b'A') => Key::Up,
+            (b'[' | b'O', b'B') => Key::Down,
+            (b'[' | b'O', b'C') => Key::Right,
+            (b'[' | b'O', b'D') => Key::Left,
+            (b'[' | b'O', b'H') => Key::Home,
+            (b'[' | b'O', b'F') => Key::End,
+            (b'[', b'3') if next(input)? == b'~' => Key::Delete,
+            _ => Key::Ignored,
+        },
+        byte if byte < 0x20 => Key::Ignored,
+        byte => {
+            // Multibyte characters are gathered up to their last byte.
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.push(next(input)?);
+            }
+            String::from_utf8(bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+                .map_or(Key::Ignored, Key::Char)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> RedisResult<Reply> {
+        Reply::parse(&mut Tokens::new(bytes), 0)
+    }
+
+    #[test]
+    fn it_parses_resp2_and_resp3_replies() {
+        assert_eq!(
+            parse(b"+OK\r\n").unwrap(),
+            Reply::Resp(Resp::SS("OK".into()))
+        );
+        assert_eq!(parse(b"*-1\r\n").unwrap(), Reply::Null);
+        assert_eq!(
+            parse(b"%1\r\n+key\r\n*2\r\n:1\r\n#t\r\n").unwrap(),
+            Reply::Map(vec![(
+                Reply::Resp(Resp::SS("key".into())),
+                Reply::Array(vec![Reply::Resp(Resp::I(1)), Reply::Bool(true)]),
+            )])
+        );
+        assert_eq!(
+            parse(b"=8\r\ntxt:some\r\n").unwrap(),
+            Reply::Verbatim("some".into())
+        );
+        assert_eq!(
+            parse(b"|1\r\n+ttl\r\n:3\r\n,1.5\r\n").unwrap(),
+            Reply::Double("1.5".into())
+        );
+        assert!(matches!(
+            parse(b"*2\r\n:1\r\n"),
+            Err(RedisError::Protocol(RespError::Incomplete))
+        ));
+    }
+
+    #[test]
+    fn it_formats_replies_like_redis_cli() {
+        let reply = parse(b"*3\r\n$3\r\nfoo\r\n*2\r\n:1\r\n$-1\r\n-ERR bad\r\n").unwrap();
+        assert_eq!(
+            reply.pretty(),
+            "1) \"foo\"\n2) 1) (integer) 1\n   2) (nil)\n3) (error) ERR bad"
+        );
+        assert_eq!(reply.raw(), "foo\n1\n\nERR bad");
+        assert_eq!(Reply::Array(vec![]).pretty(), "(empty array)");
+        assert_eq!(quote("a\tb\u{1}"), "\"a\\tb\\x01\"");
+    }
+
+    #[test]
+    fn it_edits_lines_with_history() {
+        let mut editor = Editor::default();
+        for key in "GT k".chars().map(Key::Char) {
+            editor.apply(key);
+        }
+        for key in [
+            Key::Home,
+            Key::Right,
+            Key::Char('E'),
+            Key::End,
+            Key::Backspace,
+        ] {
+            editor.apply(key);
+        }
+        assert_eq!(editor.apply(Key::Enter), Edit::Done("GET ".into()));
+
+        editor.remember("PING");
+        editor.remember("GET a");
+        editor.apply(Key::Char('x'));
+        editor.apply(Key::Up);
+        editor.apply(Key::Up);
+        assert_eq!(editor.line.iter().collect::<String>(), "PING");
+        editor.apply(Key::Down);
+        editor.apply(Key::Down);
+        assert_eq!(editor.line.iter().collect::<String>(), "x");
+        assert_eq!(editor.apply(Key::Interrupt), Edit::Exit);
+    }
+
+    #[test]
+    fn it_reads_escape_sequences_as_keys() {
+        let mut input: &[u8] = b"\x1b[A\x1b[3~\xc3\xa9a";
+        assert_eq!(read_key(&mut input).unwrap(), Key::Up);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Delete);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Char('é'));
+        assert_eq!(read_key(&mut input).unwrap(), Key::Char('a'));
+        assert_eq!(read_key(&mut input).unwrap(), Key::Eof);
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt::Debug;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Source of the current time and timers, so that expiry, stream ids and timeouts can run
+/// on virtual time in tests.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    /// A future completing once the duration has passed on this clock.
+    fn sleep(&self, duration: Duration) -> Sleep;
+
+    fn unix_millis(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// The wall clock with tokio timers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// A clock which only moves when `advance` is called.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: watch::Sender<SystemTime>,
+}
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> Self {
+        let (now, _) = watch::channel(now);
+        Self { now }
+    }
+
+    /// Moves the clock forward, firing the timers which are due.
+    pub fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.borrow()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let deadline = self.now() + duration;
+        let mut rx = self.now.subscribe();
+        Box::pin(async move {
+            if rx.wait_for(|now| *now >= deadline).await.is_err() {
+                // The clock is gone, so the time never comes.
+                future::pending::<()>().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_fires_timers_when_advanced() {
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let mut sleep = clock.sleep(Duration::from_millis(100));
+
+        clock.advance(Duration::from_millis(99));
+        assert!(poll_briefly(&mut sleep).await.is_none());
+
+        clock.advance(Duration::from_millis(1));
+        sleep.await;
+        assert_eq!(clock.unix_millis(), 100);
+    }
+
+    async fn poll_briefly(sleep: &mut Sleep) -> Option<()> {
+        tokio::time::timeout(Duration::from_millis(10), sleep)
+            .await
+            .ok()
+    }
+}
@@ -0,0 +1,424 @@
+use super::{Cluster, ClusterNode, CLUSTER_PORT_INCR};
+use crate::{RedisError, RedisResult, Resp};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+const BUS_TIMEOUT: Duration = Duration::from_secs(1);
+/// Upper bound of a message length, to drop garbage from something which isn't a node.
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+/// Messages exchanged on the cluster bus. A MEET introduces a node which isn't known
+/// yet, an MFSTART has a master hold writes back for its replica to take over, and
+/// every other message is answered with a PONG.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum MessageType {
+    Meet,
+    Ping,
+    Pong,
+    MfStart,
+}
+
+impl MessageType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Meet => "MEET",
+            Self::Ping => "PING",
+            Self::Pong => "PONG",
+            Self::MfStart => "MFSTART",
+        }
+    }
+}
+
+/// The sender's own id, client port, epochs, replication offset and slots, followed by
+/// what it knows about the other nodes.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Message {
+    kind: MessageType,
+    id: String,
+    port: u16,
+    config_epoch: u64,
+    current_epoch: u64,
+    offset: u64,
+    slots: Vec<(u16, u16)>,
+    gossip: Vec<(String, SocketAddr)>,
+}
+
+impl From<&Message> for Resp {
+    fn from(msg: &Message) -> Self {
+        let mut values = vec![
+            msg.kind.as_str().to_string(),
+            msg.id.clone(),
+            msg.port.to_string(),
+            msg.config_epoch.to_string(),
+            msg.current_epoch.to_string(),
+            msg.offset.to_string(),
+            msg.slots.len().to_string(),
+        ];
+        for (start, end) in msg.slots.iter() {
+            values.push(start.to_string());
+            values.push(end.to_string());
+        }
+        for (id, addr) in msg.gossip.iter() {
+            values.push(id.clone());
+            values.push(addr.to_string());
+        }
+        values.into()
+    }
+}
+
+impl TryFrom<Resp> for Message {
+    type Error = RedisError;
+
+    fn try_from(resp: Resp) -> RedisResult<Self> {
+        let invalid = || RedisError::from(anyhow::anyhow!("Invalid cluster bus message"));
+        let Resp::A(values) = resp else {
+            return Err(invalid());
+        };
+        let values = values
+            .into_iter()
+            .map(|v| match v {
+                Resp::BS(Some(v)) => Ok(v),
+                _ => Err(invalid()),
+            })
+            .collect::<RedisResult<Vec<String>>>()?;
+        let mut values = values.into_iter();
+        let mut next = || values.next().ok_or_else(invalid);
+
+        let kind = match next()?.as_str() {
+            "MEET" => MessageType::Meet,
+            "PING" => MessageType::Ping,
+            "PONG" => MessageType::Pong,
+            "MFSTART" => MessageType::MfStart,
+            _ => return Err(invalid()),
+        };
+        let id = next()?;
+        let port = next()?.parse()?;
+        let config_epoch = next()?.parse()?;
+        let current_epoch = next()?.parse()?;
+        let offset = next()?.parse()?;
+        let num_ranges: usize = next()?.parse()?;
+        let slots = (0..num_ranges)
+            .map(|_| Ok((next()?.parse()?, next()?.parse()?)))
+            .collect::<RedisResult<Vec<(u16, u16)>>>()?;
+
+        let mut gossip = vec![];
+        while let Ok(id) = next() {
+            let addr = next()?.parse().map_err(|_| invalid())?;
+            gossip.push((id, addr));
+        }
+
+        Ok(Self {
+            kind,
+            id,
+            port,
+            config_epoch,
+            current_epoch,
+            offset,
+            slots,
+            gossip,
+        })
+    }
+}
+
+impl Cluster {
+    pub(crate) fn message(&self, kind: MessageType) -> Message {
+        let myself = self.myself();
+        Message {
+            kind,
+            id: myself.id.clone(),
+            port: myself.addr.port(),
+            config_epoch: myself.config_epoch,
+            current_epoch: self.current_epoch,
+            offset: self.repl_offset(),
+            slots: self
+                .slot_ranges()
+                .into_iter()
+                .filter(|(_, _, owner)| *owner == 0)
+                .map(|(start, end, _)| (start as u16, end as u16))
+                .collect(),
+            gossip: self.nodes[1..]
+                .iter()
+                .map(|node| (node.id.clone(), node.addr))
+                .collect(),
+        }
+    }
+
+    /// Updates the view of the cluster with a message received from a node at `ip`.
+    pub(crate) fn process(&mut self, msg: &Message, ip: IpAddr) {
+        if msg.id == self.myself().id {
+            return;
+        }
+        let addr = SocketAddr::new(ip, msg.port);
+        let sender = match self.nodes.iter().position(|node| node.id == msg.id) {
+            Some(idx) => idx,
+            // Nodes join by MEET only. A PING can come from a node forgotten by restart.
+            None if matches!(msg.kind, MessageType::Ping | MessageType::MfStart) => return,
+            None => {
+                println!("Cluster node {} joined from {addr}", msg.id);
+                self.nodes.push(ClusterNode {
+                    id: msg.id.clone(),
+                    addr,
+                    config_epoch: 0,
+                    repl_offset: 0,
+                });
+                self.nodes.len() - 1
+            }
+        };
+        self.nodes[sender].addr = addr;
+        self.nodes[sender].config_epoch = msg.config_epoch;
+        self.nodes[sender].repl_offset = msg.offset;
+        self.current_epoch = self.current_epoch.max(msg.current_epoch);
+
+        self.handle_epoch_collision(sender);
+        self.update_slots(sender, &msg.slots);
+        if msg.kind == MessageType::MfStart {
+            self.pause_writes();
+        }
+
+        for (id, addr) in msg.gossip.iter() {
+            if !self.nodes.iter().any(|node| &node.id == id) {
+                self.nodes.push(ClusterNode {
+                    id: id.clone(),
+                    addr: *addr,
+                    config_epoch: 0,
+                    repl_offset: 0,
+                });
+            }
+        }
+    }
+
+    /// Two nodes with the same config epoch can't tell whose slot claims are newer,
+    /// so the one with the smaller id moves to a new epoch, as Redis does.
+    fn handle_epoch_collision(&mut self, sender: usize) {
+        let myself = self.myself();
+        let other = &self.nodes[sender];
+        if other.config_epoch != myself.config_epoch || other.id <= myself.id {
+            return;
+        }
+        self.current_epoch += 1;
+        self.nodes[0].config_epoch = self.current_epoch;
+    }
+
+    /// A node is authoritative for the slots it claims unless their current owner has a
+    /// newer epoch, and slots it stopped claiming are no longer its.
+    fn update_slots(&mut self, sender: usize, claimed: &[(u16, u16)]) {
+        let epoch = self.nodes[sender].config_epoch;
+        let mut claims = vec![false; self.slots.len()];
+        for (start, end) in claimed {
+            let end = (*end as usize).min(claims.len() - 1);
+            if let Some(range) = claims.get_mut(*start as usize..=end) {
+                range.fill(true);
+            }
+        }
+
+        for (slot, owner) in self.slots.iter_mut().enumerate() {
+            match *owner {
+                Some(idx) if idx == sender && !claims[slot] => *owner = None,
+                Some(idx)
+                    if idx != sender && claims[slot] && self.nodes[idx].config_epoch < epoch =>
+                {
+                    *owner = Some(sender)
+                }
+                None if claims[slot] => *owner = Some(sender),
+                _ => {}
+            }
+        }
+
+        let slots = &self.slots;
+        self.migrating
+            .retain(|slot, _| slots[*slot as usize] == Some(0));
+        self.importing
+            .retain(|slot, _| slots[*slot as usize] != Some(0));
+        // Writes held back for a replica taking over go on to the new owner.
+        if !slots.contains(&Some(0)) {
+            self.paused_until = None;
+        }
+    }
+
+    /// Addresses of the cluster buses of the other nodes.
+    pub(crate) fn peers(&self) -> Vec<SocketAddr> {
+        self.nodes[1..].iter().map(bus_addr).collect()
+    }
+}
+
+fn bus_addr(node: &ClusterNode) -> SocketAddr {
+    SocketAddr::new(node.addr.ip(), node.bus_port())
+}
+
+/// Accepts connections from the other nodes on the port `CLUSTER_PORT_INCR` above `addr`.
+pub(crate) async fn listen(cluster: Arc<Mutex<Cluster>>, addr: SocketAddr) -> RedisResult<()> {
+    let addr = SocketAddr::new(addr.ip(), addr.port().wrapping_add(CLUSTER_PORT_INCR));
+    let listener = TcpListener::bind(addr).await?;
+    println!("Cluster bus listening on {addr}");
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let cluster = Arc::clone(&cluster);
+        tokio::spawn(async move {
+            if let Err(err) = serve(cluster, stream, peer.ip()).await {
+                eprintln!("Cluster bus link from {peer} failed. {err}");
+            }
+        });
+    }
+}
+
+async fn serve(cluster: Arc<Mutex<Cluster>>, mut stream: TcpStream, ip: IpAddr) -> RedisResult<()> {
+    while let Some(msg) = read_message(&mut stream).await? {
+        let pong = {
+            let mut cluster = cluster.lock().await;
+            cluster.process(&msg, ip);
+            cluster.message(MessageType::Pong)
+        };
+        write_message(&mut stream, &pong).await?;
+    }
+    Ok(())
+}
+
+/// Sends a MEET, PING or MFSTART to the bus at `addr` and processes the PONG.
+pub(crate) async fn send(
+    cluster: Arc<Mutex<Cluster>>,
+    addr: SocketAddr,
+    kind: MessageType,
+) -> RedisResult<()> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(addr).await?;
+        let msg = cluster.lock().await.message(kind);
+        write_message(&mut stream, &msg).await?;
+        read_message(&mut stream).await
+    };
+    let pong = tokio::time::timeout(BUS_TIMEOUT, exchange)
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out"))??
+        .ok_or_else(|| anyhow::anyhow!("Connection closed"))?;
+    cluster.lock().await.process(&pong, addr.ip());
+    Ok(())
+}
+
+/// Messages are framed by their length as a 4-byte big-endian integer.
+async fn write_message(stream: &mut TcpStream, msg: &Message) -> RedisResult<()> {
+    let bytes = Resp::from(msg).serialize();
+    stream.write_u32(bytes.len() as u32).await?;
+    stream.write_all(&bytes).await?;
+    Ok(())
+}
+
+async fn read_message(stream: &mut TcpStream) -> RedisResult<Option<Message>> {
+    let len = match stream.read_u32().await {
+        Ok(len) => len as usize,
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if len > MAX_MESSAGE_LEN {
+        return Err(anyhow::anyhow!("Cluster bus message too long").into());
+    }
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf).await?;
+    Message::try_from(Resp::new(&buf)?).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::SetSlot;
+    use std::time::Instant;
+
+    #[test]
+    fn it_agrees_on_slot_owners() {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let mut a = Cluster::new(SocketAddr::new(ip, 7000));
+        let mut b = Cluster::new(SocketAddr::new(ip, 7001));
+
+        let msg = a.message(MessageType::Meet);
+        let resp = Resp::new(&Resp::from(&msg).serialize()).unwrap();
+        assert_eq!(Message::try_from(resp).unwrap(), msg);
+
+        b.process(&msg, ip);
+        a.process(&b.message(MessageType::Pong), ip);
+
+        // Both claimed every slot on the same epoch until one of them moved to a new one.
+        for _ in 0..2 {
+            b.process(&a.message(MessageType::Ping), ip);
+            a.process(&b.message(MessageType::Pong), ip);
+        }
+
+        let winner = if a.myself().id < b.myself().id {
+            a.myself().id.clone()
+        } else {
+            b.myself().id.clone()
+        };
+        for cluster in [&a, &b] {
+            assert_eq!(cluster.nodes.len(), 2);
+            assert_eq!(cluster.owner(0).map(|n| &n.id), Some(&winner));
+            assert_eq!(cluster.owner(16383).map(|n| &n.id), Some(&winner));
+        }
+    }
+
+    #[test]
+    fn it_hands_over_migrated_slots() {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let mut a = Cluster::new(SocketAddr::new(ip, 7000));
+        let mut b = Cluster::new(SocketAddr::new(ip, 7001));
+        b.slots.fill(None);
+
+        b.process(&a.message(MessageType::Meet), ip);
+        a.process(&b.message(MessageType::Pong), ip);
+        b.process(&a.message(MessageType::Ping), ip);
+        let (a_id, b_id) = (a.myself().id.clone(), b.myself().id.clone());
+
+        a.set_slot(0, SetSlot::Migrating(b_id.clone())).unwrap();
+        b.set_slot(0, SetSlot::Importing(a_id.clone())).unwrap();
+        assert!(b.set_slot(1, SetSlot::Importing("unknown".into())).is_err());
+        assert!(b.importing(0));
+
+        b.set_slot(0, SetSlot::Node(b_id.clone())).unwrap();
+        a.process(&b.message(MessageType::Ping), ip);
+        b.process(&a.message(MessageType::Pong), ip);
+
+        for cluster in [&a, &b] {
+            assert_eq!(cluster.owner(0).map(|n| &n.id), Some(&b_id));
+            assert_eq!(cluster.owner(1).map(|n| &n.id), Some(&a_id));
+        }
+        assert!(a.migrating_to(0).is_none());
+        assert!(!b.importing(0));
+    }
+
+    #[test]
+    fn it_fails_over_to_a_replica() {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let offset = Arc::new(std::sync::atomic::AtomicUsize::new(42));
+        let a_addr = SocketAddr::new(ip, 7000);
+        let mut a = Cluster::new(a_addr).with_repl_offset(Arc::clone(&offset));
+        let mut b = Cluster::replica(SocketAddr::new(ip, 7001), a_addr);
+
+        b.process(&a.message(MessageType::Meet), ip);
+        a.process(&b.message(MessageType::Pong), ip);
+        let a_id = a.myself().id.clone();
+        assert_eq!(b.master().map(|n| &n.id), Some(&a_id));
+        assert_eq!(b.owner(0).map(|n| &n.id), Some(&a_id));
+
+        // A node unknown to the master can't have it pause.
+        let stranger = Cluster::new(SocketAddr::new(ip, 7002));
+        a.process(&stranger.message(MessageType::MfStart), ip);
+        assert!(!a.writes_paused(Instant::now()));
+
+        a.process(&b.message(MessageType::MfStart), ip);
+        assert!(a.writes_paused(Instant::now()));
+        b.process(&a.message(MessageType::Pong), ip);
+        assert_eq!(b.node(&a_id).map(|n| n.repl_offset), Some(42));
+
+        b.take_over(&a_id).unwrap();
+        a.process(&b.message(MessageType::Ping), ip);
+        let b_id = b.myself().id.clone();
+        for cluster in [&a, &b] {
+            assert_eq!(cluster.owner(0).map(|n| &n.id), Some(&b_id));
+            assert_eq!(cluster.owner(16383).map(|n| &n.id), Some(&b_id));
+        }
+        assert!(!b.is_replica());
+        assert!(!a.writes_paused(Instant::now()));
+        assert!(b.take_over("unknown").is_err());
+    }
+}
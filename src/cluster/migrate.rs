@@ -0,0 +1,92 @@
+use crate::{OutgoingMessage, RedisError, RedisResult, Resp, BUF_SIZE};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+/// A connection to the node keys are migrated to. Every command is sent after ASKING so
+/// that the target accepts keys of slots it is still importing.
+#[derive(Debug)]
+pub(crate) struct MigrateTarget {
+    stream: TcpStream,
+    timeout: Duration,
+    buf: Vec<u8>,
+}
+
+impl MigrateTarget {
+    pub(crate) async fn connect(addr: SocketAddr, timeout: Duration) -> RedisResult<Self> {
+        let stream = tokio::time::timeout(timeout, TcpStream::connect(addr))
+            .await
+            .map_err(|_| ioerr_timeout(addr))??;
+        Ok(Self {
+            stream,
+            timeout,
+            buf: vec![],
+        })
+    }
+
+    /// Sends the commands in a pipeline and returns their replies. An error reply fails
+    /// the whole call.
+    pub(crate) async fn call(&mut self, commands: Vec<Vec<String>>) -> RedisResult<Vec<Resp>> {
+        let msg: OutgoingMessage = commands
+            .iter()
+            .flat_map(|command| {
+                [
+                    Resp::from(vec!["ASKING".to_string()]),
+                    Resp::from(command.clone()),
+                ]
+            })
+            .collect::<Vec<Resp>>()
+            .into();
+        let addr = self.stream.peer_addr()?;
+        let timeout = self.timeout;
+
+        let exchange = async {
+            msg.write_to(&mut self.stream).await?;
+            let mut replies = vec![];
+            for _ in 0..commands.len() * 2 {
+                replies.push(self.read_reply().await?);
+            }
+            Ok::<Vec<Resp>, RedisError>(replies)
+        };
+        let replies = tokio::time::timeout(timeout, exchange)
+            .await
+            .map_err(|_| ioerr_timeout(addr))??;
+
+        // Keep the replies to the commands, dropping those to ASKING.
+        replies
+            .into_iter()
+            .skip(1)
+            .step_by(2)
+            .map(|reply| match reply {
+                Resp::SE(err) => {
+                    Err(anyhow::anyhow!("ERR Target instance replied with error: {err}").into())
+                }
+                reply => Ok(reply),
+            })
+            .collect()
+    }
+
+    async fn read_reply(&mut self) -> RedisResult<Resp> {
+        loop {
+            // A reply is only complete at a line end, so a partial one isn't parsed.
+            if self.buf.ends_with(b"\r\n") {
+                if let Ok(reply) = Resp::new(&self.buf) {
+                    let len = reply.serialize().len();
+                    self.buf.drain(..len.min(self.buf.len()));
+                    return Ok(reply);
+                }
+            }
+            let mut chunk = [0; BUF_SIZE];
+            let size = self.stream.read(&mut chunk).await?;
+            if size == 0 {
+                return Err(anyhow::anyhow!("ERR Target instance closed the connection").into());
+            }
+            self.buf.extend_from_slice(&chunk[..size]);
+        }
+    }
+}
+
+fn ioerr_timeout(addr: SocketAddr) -> RedisError {
+    anyhow::anyhow!("IOERR error or timeout connecting to the client {addr}").into()
+}
@@ -0,0 +1,424 @@
+mod bus;
+mod migrate;
+mod slot;
+
+pub(crate) use bus::{listen, send, MessageType};
+pub(crate) use migrate::MigrateTarget;
+pub use slot::key_hash_slot;
+
+use super::{utils, RedisError, RedisResult, Resp};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Number of hash slots the keyspace is divided into.
+pub const CLUSTER_SLOTS: usize = 16384;
+/// The cluster bus listens on the client port plus this offset.
+pub const CLUSTER_PORT_INCR: u16 = 10000;
+
+const NODE_ID_LEN: usize = 40;
+
+/// How long a replica taking over with CLUSTER FAILOVER waits to catch up with its
+/// master. The master holds writes back for twice as long, as Redis does.
+pub const MF_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Converts a slot given by a client, returning None when it is out of range.
+pub fn valid_slot(slot: i64) -> Option<u16> {
+    u16::try_from(slot)
+        .ok()
+        .filter(|slot| (*slot as usize) < CLUSTER_SLOTS)
+}
+
+/// How CLUSTER SETSLOT changes the state of a slot, with the id of the other node.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetSlot {
+    Importing(String),
+    Migrating(String),
+    Node(String),
+    Stable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterNode {
+    pub id: String,
+    pub addr: SocketAddr,
+    pub config_epoch: u64,
+    /// The replication offset the node last told of.
+    pub repl_offset: u64,
+}
+
+impl ClusterNode {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            id: utils::random_hex(NODE_ID_LEN),
+            addr,
+            config_epoch: 0,
+            repl_offset: 0,
+        }
+    }
+
+    pub fn bus_port(&self) -> u16 {
+        self.addr.port().wrapping_add(CLUSTER_PORT_INCR)
+    }
+}
+
+/// This node's view of the cluster: the known nodes and which of them owns each slot.
+#[derive(Debug)]
+pub struct Cluster {
+    /// Known nodes. This node is always the first one.
+    nodes: Vec<ClusterNode>,
+    /// Index in `nodes` of the owner of each slot.
+    slots: Vec<Option<usize>>,
+    /// Slots this node is handing over, with the index of the receiving node.
+    migrating: HashMap<u16, usize>,
+    /// Slots this node is taking over, with the index of the node handing them over.
+    importing: HashMap<u16, usize>,
+    /// The greatest epoch known in the cluster.
+    current_epoch: u64,
+    /// The client address of the master while this node is its replica, which serves
+    /// no slots of its own.
+    master: Option<SocketAddr>,
+    /// The replication offset of this node, told to the other nodes.
+    repl_offset: Arc<AtomicUsize>,
+    /// Until when this master holds writes back for a replica taking over its slots.
+    paused_until: Option<Instant>,
+}
+
+impl Cluster {
+    /// A single-node cluster serving every slot.
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            nodes: vec![ClusterNode::new(addr)],
+            slots: vec![Some(0); CLUSTER_SLOTS],
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+            current_epoch: 0,
+            master: None,
+            repl_offset: Arc::new(AtomicUsize::new(0)),
+            paused_until: None,
+        }
+    }
+
+    /// A replica of the master at `master`, learning the slots from the nodes it meets.
+    pub fn replica(addr: SocketAddr, master: SocketAddr) -> Self {
+        Self {
+            slots: vec![None; CLUSTER_SLOTS],
+            master: Some(master),
+            ..Self::new(addr)
+        }
+    }
+
+    /// Tells the other nodes the offset counted by `offset`, instead of zero.
+    pub(crate) fn with_repl_offset(self, offset: Arc<AtomicUsize>) -> Self {
+        Self {
+            repl_offset: offset,
+            ..self
+        }
+    }
+
+    pub(crate) fn repl_offset(&self) -> u64 {
+        self.repl_offset.load(Ordering::Relaxed) as u64
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.master.is_some()
+    }
+
+    /// The master of this replica, once it has met it.
+    pub fn master(&self) -> Option<&ClusterNode> {
+        let addr = self.master?;
+        self.nodes[1..].iter().find(|node| node.addr == addr)
+    }
+
+    pub fn node(&self, id: &str) -> Option<&ClusterNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    /// Whether writes are held back at `now`, as a replica takes over.
+    pub fn writes_paused(&self, now: Instant) -> bool {
+        self.paused_until.is_some_and(|until| now < until)
+    }
+
+    /// Holds writes back while a replica takes over, when this node serves slots.
+    pub(crate) fn pause_writes(&mut self) {
+        if self.slots.contains(&Some(0)) {
+            self.paused_until = Some(Instant::now() + MF_TIMEOUT * 2);
+        }
+    }
+
+    /// Makes this replica the master of the slots of the node `id`, on an epoch newer
+    /// than every other node's, so that the others give the slots to it.
+    pub(crate) fn take_over(&mut self, id: &str) -> RedisResult<()> {
+        let idx = self
+            .nodes
+            .iter()
+            .position(|node| node.id == id)
+            .filter(|idx| *idx > 0)
+            .ok_or_else(|| anyhow::anyhow!("ERR I don't know about node {id}"))?;
+        for owner in self.slots.iter_mut().filter(|owner| **owner == Some(idx)) {
+            *owner = Some(0);
+        }
+        self.current_epoch += 1;
+        self.nodes[0].config_epoch = self.current_epoch;
+        self.master = None;
+        Ok(())
+    }
+
+    pub fn myself(&self) -> &ClusterNode {
+        &self.nodes[0]
+    }
+
+    /// The node serving the slot, None when the slot is unassigned.
+    pub fn owner(&self, slot: u16) -> Option<&ClusterNode> {
+        self.slots
+            .get(slot as usize)
+            .copied()
+            .flatten()
+            .map(|idx| &self.nodes[idx])
+    }
+
+    pub fn is_myself(&self, node: &ClusterNode) -> bool {
+        node.id == self.myself().id
+    }
+
+    /// The node the slot is being migrated to, if this node is migrating it.
+    pub fn migrating_to(&self, slot: u16) -> Option<&ClusterNode> {
+        self.migrating.get(&slot).map(|idx| &self.nodes[*idx])
+    }
+
+    /// Whether this node is taking over the slot. It serves the slot only to the clients
+    /// sent by ASK redirections until the slot is assigned to it.
+    pub fn importing(&self, slot: u16) -> bool {
+        self.importing.contains_key(&slot)
+    }
+
+    pub fn set_slot(&mut self, slot: u16, action: SetSlot) -> RedisResult<()> {
+        let err = |msg: String| RedisError::from(anyhow::anyhow!(msg));
+        let node = |id: &str| {
+            self.nodes
+                .iter()
+                .position(|node| node.id == id)
+                .ok_or_else(|| err(format!("ERR I don't know about node {id}")))
+        };
+        let mine = self.slots[slot as usize] == Some(0);
+
+        match action {
+            SetSlot::Migrating(id) => {
+                if !mine {
+                    return Err(err(format!("ERR I'm not the owner of hash slot {slot}")));
+                }
+                let idx = node(&id)?;
+                if idx == 0 {
+                    return Err(err("ERR Target node is myself".into()));
+                }
+                self.migrating.insert(slot, idx);
+            }
+            SetSlot::Importing(id) => {
+                if mine {
+                    return Err(err(format!(
+                        "ERR I'm already the owner of hash slot {slot}"
+                    )));
+                }
+                let idx = node(&id)?;
+                if idx == 0 {
+                    return Err(err("ERR Source node is myself".into()));
+                }
+                self.importing.insert(slot, idx);
+            }
+            SetSlot::Node(id) => {
+                let idx = node(&id)?;
+                self.slots[slot as usize] = Some(idx);
+                self.migrating.remove(&slot);
+                if self.importing.remove(&slot).is_some() && idx == 0 {
+                    // Move to an epoch newer than every other node's, so that the old
+                    // owner gives the slot up when this node claims it.
+                    self.current_epoch += 1;
+                    self.nodes[0].config_epoch = self.current_epoch;
+                }
+            }
+            SetSlot::Stable => {
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+            }
+        }
+        Ok(())
+    }
+
+    /// Contiguous ranges of slots owned by the same node, as (start, end, node index).
+    fn slot_ranges(&self) -> Vec<(usize, usize, usize)> {
+        let mut ranges: Vec<(usize, usize, usize)> = vec![];
+        for (slot, owner) in self.slots.iter().enumerate() {
+            let Some(owner) = *owner else {
+                continue;
+            };
+            match ranges.last_mut() {
+                Some((_, end, node)) if *node == owner && *end + 1 == slot => *end = slot,
+                _ => ranges.push((slot, slot, owner)),
+            }
+        }
+        ranges
+    }
+
+    fn slots_assigned(&self) -> usize {
+        self.slots.iter().filter(|owner| owner.is_some()).count()
+    }
+
+    /// The reply to CLUSTER INFO.
+    pub fn info(&self) -> String {
+        let assigned = self.slots_assigned();
+        let state = if assigned == CLUSTER_SLOTS {
+            "ok"
+        } else {
+            "fail"
+        };
+        let masters = self
+            .slot_ranges()
+            .iter()
+            .map(|(_, _, node)| *node)
+            .collect::<std::collections::HashSet<usize>>()
+            .len();
+        [
+            "cluster_enabled:1".to_string(),
+            format!("cluster_state:{state}"),
+            format!("cluster_slots_assigned:{assigned}"),
+            format!("cluster_slots_ok:{assigned}"),
+            "cluster_slots_pfail:0".to_string(),
+            "cluster_slots_fail:0".to_string(),
+            format!("cluster_known_nodes:{}", self.nodes.len()),
+            format!("cluster_size:{masters}"),
+            format!("cluster_current_epoch:{}", self.current_epoch),
+            format!("cluster_my_epoch:{}", self.myself().config_epoch),
+        ]
+        .join("\r\n")
+    }
+
+    /// The reply to CLUSTER SLOTS.
+    pub fn slots_resp(&self) -> Resp {
+        let ranges = self.slot_ranges().into_iter().map(|(start, end, idx)| {
+            let node = &self.nodes[idx];
+            Resp::A(vec![
+                Resp::I(start as i64),
+                Resp::I(end as i64),
+                Resp::A(vec![
+                    Resp::BS(Some(node.addr.ip().to_string())),
+                    Resp::I(node.addr.port() as i64),
+                    Resp::BS(Some(node.id.clone())),
+                ]),
+            ])
+        });
+        Resp::A(ranges.collect())
+    }
+
+    /// The reply to CLUSTER SHARDS, with one shard per node.
+    pub fn shards_resp(&self) -> Resp {
+        let ranges = self.slot_ranges();
+        let shards = self.nodes.iter().enumerate().map(|(idx, node)| {
+            let slots: Vec<Resp> = ranges
+                .iter()
+                .filter(|(_, _, owner)| *owner == idx)
+                .flat_map(|(start, end, _)| [Resp::I(*start as i64), Resp::I(*end as i64)])
+                .collect();
+            let ip = node.addr.ip().to_string();
+            let offset = match idx {
+                0 => self.repl_offset(),
+                _ => node.repl_offset,
+            };
+            let node = Resp::A(vec![
+                Resp::BS(Some("id".into())),
+                Resp::BS(Some(node.id.clone())),
+                Resp::BS(Some("port".into())),
+                Resp::I(node.addr.port() as i64),
+                Resp::BS(Some("ip".into())),
+                Resp::BS(Some(ip.clone())),
+                Resp::BS(Some("endpoint".into())),
+                Resp::BS(Some(ip)),
+                Resp::BS(Some("role".into())),
+                Resp::BS(Some(
+                    if idx == 0 && self.is_replica() {
+                        "replica"
+                    } else {
+                        "master"
+                    }
+                    .into(),
+                )),
+                Resp::BS(Some("replication-offset".into())),
+                Resp::I(offset as i64),
+                Resp::BS(Some("health".into())),
+                Resp::BS(Some("online".into())),
+            ]);
+            Resp::A(vec![
+                Resp::BS(Some("slots".into())),
+                Resp::A(slots),
+                Resp::BS(Some("nodes".into())),
+                Resp::A(vec![node]),
+            ])
+        });
+        Resp::A(shards.collect())
+    }
+
+    /// The reply to CLUSTER NODES, one line per node.
+    pub fn nodes_description(&self) -> String {
+        let ranges = self.slot_ranges();
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| {
+                let flags = match idx {
+                    0 if self.is_replica() => "myself,slave",
+                    0 => "myself,master",
+                    _ => "master",
+                };
+                let mut line = format!(
+                    "{} {}:{}@{} {flags} - 0 0 {} connected",
+                    node.id,
+                    node.addr.ip(),
+                    node.addr.port(),
+                    node.bus_port(),
+                    node.config_epoch,
+                );
+                for (start, end, _) in ranges.iter().filter(|(_, _, owner)| *owner == idx) {
+                    if start == end {
+                        line.push_str(&format!(" {start}"));
+                    } else {
+                        line.push_str(&format!(" {start}-{end}"));
+                    }
+                }
+                if idx == 0 {
+                    let mut migrating: Vec<(&u16, &usize)> = self.migrating.iter().collect();
+                    migrating.sort();
+                    for (slot, to) in migrating {
+                        line.push_str(&format!(" [{slot}->-{}]", self.nodes[*to].id));
+                    }
+                    let mut importing: Vec<(&u16, &usize)> = self.importing.iter().collect();
+                    importing.sort();
+                    for (slot, from) in importing {
+                        line.push_str(&format!(" [{slot}-<-{}]", self.nodes[*from].id));
+                    }
+                }
+                line.push('\n');
+                line
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_serves_every_slot_by_itself() {
+        let cluster = Cluster::new("127.0.0.1:7000".parse().unwrap());
+        let id = cluster.myself().id.clone();
+        assert_eq!(id.len(), NODE_ID_LEN);
+        assert_eq!(cluster.slot_ranges(), vec![(0, CLUSTER_SLOTS - 1, 0)]);
+
+        assert!(cluster.info().contains("cluster_state:ok"));
+        assert_eq!(
+            cluster.nodes_description(),
+            format!("{id} 127.0.0.1:7000@17000 myself,master - 0 0 0 connected 0-16383\n")
+        );
+    }
+}
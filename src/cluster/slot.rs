@@ -0,0 +1,52 @@
+use super::CLUSTER_SLOTS;
+
+/// The hash slot of a key: CRC16/XMODEM of its hash tag modulo the number of slots.
+/// The hash tag is what is between the first `{` and the next `}` when that is not
+/// empty, and the whole key otherwise, so that related keys can be kept in one slot.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    (crc16(hash_tag(key)) as usize % CLUSTER_SLOTS) as u16
+}
+
+fn hash_tag(key: &[u8]) -> &[u8] {
+    let Some(start) = key.iter().position(|&b| b == b'{') else {
+        return key;
+    };
+    match key[start + 1..].iter().position(|&b| b == b'}') {
+        Some(len) if len > 0 => &key[start + 1..start + 1 + len],
+        _ => key,
+    }
+}
+
+/// CRC16/XMODEM: polynomial 0x1021, initial value 0, no reflection.
+fn crc16(buf: &[u8]) -> u16 {
+    buf.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_computes_key_slots() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_hash_slot(b"foo"), 12182);
+        assert_eq!(key_hash_slot(b"user:1000"), 1649);
+        assert_eq!(key_hash_slot(b"{user1000}.following"), 3443);
+        assert_eq!(
+            key_hash_slot(b"{user1000}.followers"),
+            key_hash_slot(b"user1000")
+        );
+        assert_eq!(hash_tag(b"foo{}{bar}"), b"foo{}{bar}");
+        assert_eq!(hash_tag(b"foo{{bar}}zap"), b"{bar");
+        assert_eq!(hash_tag(b"foo{bar}{zap}"), b"bar");
+        assert_eq!(hash_tag(b"foo{bar"), b"foo{bar");
+    }
+}
@@ -1,20 +1,35 @@
-use super::{value::StreamEntry, OutgoingMessage, RedisError, RedisResult, Resp, Store};
-use std::{collections::HashMap, time::Duration};
-use std::{net::SocketAddr, sync::Arc};
-use tokio::sync::{mpsc, oneshot::Sender};
-use tokio::time::sleep;
+use super::{
+    args::Args,
+    cluster::{self, SetSlot},
+    help, rdb,
+    script::Library,
+    utils,
+    value::{
+        BloomFilter, CuckooFilter, DeletePolicy, ExpireCond, Json, JsonPath, SetCond, StreamEntry,
+        TopK, Value, VectorQuery,
+    },
+    Client, KeyEvent, Protocol, RedisError, RedisResult, Reply, Resp, RestorePolicy, ServerState,
+    Store, Unblocked,
+};
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot::Sender;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct ContextBuilder {
     mode: CommandMode,
     addr: SocketAddr,
+    client: Arc<Client>,
 }
 
 impl ContextBuilder {
-    pub fn build(&self, sender: Sender<OutgoingMessage>) -> Context {
+    pub fn build(&self, sender: Sender<Reply>) -> Context {
         Context {
             mode: self.mode,
             addr: self.addr,
+            client: Arc::clone(&self.client),
             sender: Some(sender),
         }
     }
@@ -24,12 +39,28 @@ impl ContextBuilder {
 pub struct Context {
     mode: CommandMode,
     addr: SocketAddr,
-    sender: Option<Sender<OutgoingMessage>>,
+    client: Arc<Client>,
+    sender: Option<Sender<Reply>>,
 }
 
 impl Context {
-    pub fn builder(mode: CommandMode, addr: SocketAddr) -> ContextBuilder {
-        ContextBuilder { mode, addr }
+    pub fn builder(mode: CommandMode, addr: SocketAddr, client: Arc<Client>) -> ContextBuilder {
+        ContextBuilder { mode, addr, client }
+    }
+
+    /// The protocol replies to the client are serialized in.
+    pub fn protocol(&self) -> Protocol {
+        self.client.protocol()
+    }
+
+    /// A context without a connection to reply to, for commands called from scripts.
+    pub(crate) fn detached(mode: CommandMode, addr: SocketAddr, client: Arc<Client>) -> Self {
+        Self {
+            mode,
+            addr,
+            client,
+            sender: None,
+        }
     }
 }
 
@@ -43,23 +74,171 @@ pub enum CommandMode {
 pub enum Command {
     Ping,
     Echo(String),
+    Auth {
+        username: Option<String>,
+        password: String,
+    },
     Get {
         key: String,
     },
     Set {
         key: String,
         value: String,
-        exp: Option<u64>,
+        exp: Option<Expiry>,
     },
     Incr {
         key: String,
     },
+    GetRange {
+        key: String,
+        start: i64,
+        end: i64,
+    },
+    SetRange {
+        key: String,
+        offset: i64,
+        value: String,
+    },
+    Append {
+        key: String,
+        value: String,
+    },
+    Lpush {
+        key: String,
+        elements: Vec<String>,
+    },
+    Rpush {
+        key: String,
+        elements: Vec<String>,
+    },
+    Lpop {
+        key: String,
+        count: Option<usize>,
+    },
+    Rpop {
+        key: String,
+        count: Option<usize>,
+    },
+    /// BLPOP, where no timeout waits forever.
+    Blpop {
+        keys: Vec<String>,
+        timeout: Option<Duration>,
+    },
+    Brpop {
+        keys: Vec<String>,
+        timeout: Option<Duration>,
+    },
+    Lrange {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    Llen {
+        key: String,
+    },
+    Linsert {
+        key: String,
+        before: bool,
+        pivot: String,
+        element: String,
+    },
+    Lset {
+        key: String,
+        index: i64,
+        element: String,
+    },
+    Lrem {
+        key: String,
+        count: i64,
+        element: String,
+    },
+    Ltrim {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    Hset {
+        key: String,
+        pairs: Vec<(String, String)>,
+    },
+    Hincrby {
+        key: String,
+        field: String,
+        increment: i64,
+    },
+    HincrbyFloat {
+        key: String,
+        field: String,
+        increment: f64,
+    },
+    Hrandfield {
+        key: String,
+        count: Option<i64>,
+        withvalues: bool,
+    },
+    Hdel {
+        key: String,
+        fields: Vec<String>,
+    },
+    Sadd {
+        key: String,
+        members: Vec<String>,
+    },
+    Srem {
+        key: String,
+        members: Vec<String>,
+    },
+    Smembers {
+        key: String,
+    },
+    Sismember {
+        key: String,
+        member: String,
+    },
+    Scard {
+        key: String,
+    },
+    Hgetall {
+        key: String,
+    },
+    /// HEXPIRE, HPEXPIRE, HEXPIREAT and HPEXPIREAT.
+    Hexpire {
+        key: String,
+        expiry: Expiry,
+        cond: Option<ExpireCond>,
+        fields: Vec<String>,
+    },
+    /// HTTL, or HPTTL replying milliseconds.
+    Httl {
+        key: String,
+        fields: Vec<String>,
+        millis: bool,
+    },
+    Hpersist {
+        key: String,
+        fields: Vec<String>,
+    },
+    Del {
+        keys: Vec<String>,
+    },
     Type {
         key: String,
     },
+    Hello {
+        protover: Option<Protocol>,
+        /// The username and password of the AUTH option.
+        auth: Option<(String, String)>,
+    },
+    Select {
+        index: i64,
+    },
     Multi,
     Exec,
     Discard,
+    Watch {
+        keys: Vec<String>,
+    },
+    Unwatch,
     Xadd {
         key: String,
         id: String,
@@ -74,8 +253,125 @@ pub enum Command {
         block: Option<u64>,
         stream: Vec<(String, String)>,
     },
+    Xdelex {
+        key: String,
+        policy: DeletePolicy,
+        ids: Vec<String>,
+    },
+    Xackdel {
+        key: String,
+        group: String,
+        policy: DeletePolicy,
+        ids: Vec<String>,
+    },
+    Vadd {
+        key: String,
+        member: String,
+        vector: Vec<f32>,
+    },
+    Vsim {
+        key: String,
+        query: VectorQuery,
+        count: usize,
+        withscores: bool,
+    },
+    Vcard {
+        key: String,
+    },
+    JsonSet {
+        key: String,
+        path: JsonPath,
+        value: Json,
+        cond: Option<SetCond>,
+    },
+    JsonGet {
+        key: String,
+        paths: Vec<JsonPath>,
+    },
+    JsonDel {
+        key: String,
+        path: JsonPath,
+    },
+    JsonNumIncrBy {
+        key: String,
+        path: JsonPath,
+        value: Json,
+    },
+    BfReserve {
+        key: String,
+        error_rate: f64,
+        capacity: u64,
+        expansion: Option<u32>,
+    },
+    BfAdd {
+        key: String,
+        items: Vec<String>,
+        multi: bool,
+    },
+    BfExists {
+        key: String,
+        items: Vec<String>,
+        multi: bool,
+    },
+    CfReserve {
+        key: String,
+        capacity: u64,
+        bucket_size: usize,
+        max_iterations: u32,
+        expansion: u32,
+    },
+    CfAdd {
+        key: String,
+        item: String,
+        nx: bool,
+    },
+    CfExists {
+        key: String,
+        item: String,
+    },
+    CfCount {
+        key: String,
+        item: String,
+    },
+    CfDel {
+        key: String,
+        item: String,
+    },
+    TopkReserve {
+        key: String,
+        k: usize,
+        width: usize,
+        depth: usize,
+        decay: f64,
+    },
+    TopkIncrBy {
+        key: String,
+        items: Vec<(String, u32)>,
+        /// TOPK.ADD, which counts every item once.
+        add: bool,
+    },
+    TopkQuery {
+        key: String,
+        items: Vec<String>,
+    },
+    TopkList {
+        key: String,
+        withcount: bool,
+    },
     ConfigGet(String),
-    Keys,
+    ConfigSet(Vec<(String, String)>),
+    ConfigResetStat,
+    Keys {
+        pattern: String,
+    },
+    Scan {
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+        type_name: Option<String>,
+    },
+    Bgsave,
+    Bgrewriteaof,
     Wait {
         num_replicas: usize,
         exp: u64,
@@ -86,44 +382,281 @@ pub enum Command {
         value: String,
     },
     Psync,
+    Debug(DebugCommand),
+    Cluster(ClusterCommand),
+    Asking,
+    Readonly,
+    Readwrite,
+    Migrate {
+        host: String,
+        port: u16,
+        keys: Vec<String>,
+        timeout: u64,
+        copy: bool,
+        replace: bool,
+    },
+    ClientNoEvict(bool),
+    /// `CLIENT KILL addr` when `legacy`, which replies OK, or `CLIENT KILL` with filters,
+    /// which replies the number of clients killed.
+    ClientKill {
+        id: Option<u64>,
+        addr: Option<String>,
+        skipme: bool,
+        legacy: bool,
+    },
+    Shutdown {
+        save: Option<bool>,
+    },
+    /// `readonly` is set for EVAL_RO, as for the other `_RO` variants.
+    Eval {
+        script: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+        readonly: bool,
+    },
+    EvalSha {
+        sha: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+        readonly: bool,
+    },
+    Script(ScriptCommand),
+    Function(FunctionCommand),
+    Fcall {
+        function: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+        readonly: bool,
+    },
+    Acl(AclCommand),
+    ObjectEncoding {
+        key: String,
+    },
+    /// `<command> HELP` for a command with subcommands, by its lowercase name.
+    Help(&'static str),
     Unknown,
 }
 
+/// The time a key or the fields of a hash are made to expire at, relative to now or as
+/// a unix time. It's resolved against the clock of the store as the command runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expiry {
+    Seconds(u64),
+    Millis(u64),
+    UnixSeconds(u64),
+    UnixMillis(u64),
+}
+
+impl Expiry {
+    fn unix_millis(self, now: u64) -> u64 {
+        match self {
+            Self::Seconds(secs) => now.saturating_add(secs.saturating_mul(1000)),
+            Self::Millis(millis) => now.saturating_add(millis),
+            Self::UnixSeconds(secs) => secs.saturating_mul(1000),
+            Self::UnixMillis(millis) => millis,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugCommand {
+    Sleep(Duration),
+    Object {
+        key: String,
+    },
+    SetActiveExpire(bool),
+    ChangeReplId,
+    ReloadConfig,
+    /// Prints the listpacks the value of the key is kept in.
+    Listpack {
+        key: String,
+    },
+    QuicklistPackedThreshold(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptCommand {
+    Load(String),
+    Exists(Vec<String>),
+    Flush,
+    Kill,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FunctionCommand {
+    Load {
+        code: String,
+        replace: bool,
+    },
+    List {
+        pattern: Option<String>,
+        with_code: bool,
+    },
+    Delete(String),
+    Flush,
+    Dump,
+    Restore {
+        payload: String,
+        policy: RestorePolicy,
+    },
+    Kill,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AclCommand {
+    SetUser { name: String, rules: Vec<String> },
+    DelUser(Vec<String>),
+    List,
+    Users,
+    WhoAmI,
+    Load,
+    Save,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClusterCommand {
+    Info,
+    Myid,
+    Slots,
+    Shards,
+    Nodes,
+    KeySlot(String),
+    CountKeysInSlot(i64),
+    GetKeysInSlot {
+        slot: i64,
+        count: i64,
+    },
+    Meet {
+        ip: String,
+        port: u16,
+        bus_port: Option<u16>,
+    },
+    SetSlot {
+        slot: i64,
+        action: SetSlot,
+    },
+    /// FORCE and TAKEOVER both skip the handshake with the master, as there is no
+    /// election to skip besides.
+    Failover {
+        force: bool,
+    },
+}
+
 impl Command {
     pub fn new(resp: Resp) -> RedisResult<Self> {
         let args = command_args(resp);
         Self::from_args(args)
     }
 
-    pub async fn execute(self, store: Arc<Store>, mut ctx: Context) {
-        let msg = if self.need_queue(&store, ctx.addr).await {
+    pub async fn execute(self, store: Arc<Store>, ctx: Context) {
+        let queuing = store.is_queuing(ctx.addr).await;
+        self.execute_queuing(store, ctx, queuing).await
+    }
+
+    /// Executes the command already knowing whether the client is queuing a transaction,
+    /// so that a batch of commands that can't start or end one checks that only once.
+    pub async fn execute_queuing(self, store: Arc<Store>, mut ctx: Context, queuing: bool) {
+        // Commands from the master run whatever this server is busy with.
+        let state = match ctx.mode {
+            CommandMode::Normal => store.server_state().await,
+            CommandMode::Sync => ServerState::Running,
+        };
+        if state == ServerState::Pausing && self.is_write() {
+            store.writes_resumed().await;
+        }
+        let route = match ctx.mode {
+            CommandMode::Normal if matches!(self, Self::Asking) => Ok(()),
+            CommandMode::Normal => {
+                let asking = ctx.client.take_asking();
+                let replica_read = ctx.client.readonly() && self.is_readonly();
+                store.route(&self.keys(), asking, replica_read).await
+            }
+            CommandMode::Sync => Ok(()),
+        };
+
+        // Until it authenticates, a client may only run AUTH, or HELLO with its AUTH.
+        let authenticating = matches!(self, Self::Auth { .. } | Self::Hello { auth: Some(_), .. });
+        let denied =
+            ctx.mode == CommandMode::Normal && !ctx.client.authenticated() && !authenticating;
+        let acl = match ctx.mode {
+            CommandMode::Normal if !denied && !authenticating => {
+                store.acl_check(&ctx.client, &self)
+            }
+            _ => Ok(()),
+        };
+        let oom = ctx.mode == CommandMode::Normal && self.denies_oom() && store.over_maxmemory();
+
+        let rejection = if denied {
+            Some(RedisError::NoAuth)
+        } else if let Err(err) = acl {
+            Some(err)
+        } else if let Some(err) = state.rejection(&self) {
+            Some(err)
+        } else if oom {
+            Some(RedisError::Oom)
+        } else if let Err(err) = route {
+            Some(err)
+        } else if queuing && matches!(self, Self::Unknown) {
+            Some(RedisError::UnknownCommand)
+        } else if queuing && !self.allowed_in_multi() {
+            Some(anyhow::anyhow!("ERR Command not allowed inside a transaction").into())
+        } else {
+            None
+        };
+
+        let msg = if let Some(err) = rejection {
+            // A command rejected while queued aborts the transaction.
+            if queuing {
+                store.abort_queuing(ctx.addr).await;
+            }
+            Resp::from(err).into()
+        } else if queuing && matches!(self, Self::Watch { .. }) {
+            Resp::SE("ERR WATCH inside MULTI is not allowed".into()).into()
+        } else if queuing && matches!(self, Self::Multi) {
+            Resp::SE("ERR MULTI calls can not be nested".into()).into()
+        } else if queuing && !matches!(self, Self::Exec | Self::Discard) {
             store.queue(ctx.addr, self).await;
             Resp::SS("QUEUED".into()).into()
+        } else if matches!(self, Self::Exec) && !queuing {
+            Resp::SE("ERR EXEC without MULTI".into()).into()
         } else if matches!(self, Self::Exec) {
-            if store.is_queuing(ctx.addr).await {
+            // Other clients don't write from the check of the keys watched until the
+            // commands queued have run.
+            let _exec = store.exec_guard().await;
+            if store.queuing_aborted(ctx.addr).await {
+                let _ = store.drain_trans(ctx.addr).await;
+                store.unwatch(ctx.addr).await;
+                Resp::from(RedisError::ExecAbort).into()
+            } else if store.unwatch(ctx.addr).await {
+                // A watched key changed, so the transaction is aborted.
+                let _ = store.drain_trans(ctx.addr).await;
+                Resp::NullArray.into()
+            } else {
                 let mut resps: Vec<Resp> = vec![];
 
+                // Blocking commands don't block in a transaction, which they find out by
+                // having no connection to reply to later.
+                let sender = ctx.sender.take();
                 for cmd in store.drain_trans(ctx.addr).await {
                     match cmd.run(Arc::clone(&store), &mut ctx).await {
                         Ok(Some(resp)) => {
                             resps.push(resp);
                         }
-                        Ok(None) => {
-                            println!("No return message");
-                        }
+                        // Commands replying nothing, as REPLCONF ACK does, add nothing.
+                        Ok(None) => {}
                         Err(err) => {
                             resps.push(Resp::from(err));
                         }
                     }
                 }
+                ctx.sender = sender;
 
                 Resp::A(resps).into()
-            } else {
-                Resp::SE("ERR EXEC without MULTI".into()).into()
             }
         } else if matches!(self, Self::Discard) {
-            if store.is_queuing(ctx.addr).await {
+            if queuing {
                 let _ = store.drain_trans(ctx.addr).await;
+                store.unwatch(ctx.addr).await;
                 Resp::SS("OK".into()).into()
             } else {
                 Resp::SE("ERR DISCARD without MULTI".into()).into()
@@ -131,20 +664,20 @@ impl Command {
         } else {
             let need_return = self.return_message(ctx.mode);
 
-            self.run(store, &mut ctx)
+            let _write = if self.is_write() || self.is_script_write() {
+                Some(store.write_guard().await)
+            } else {
+                None
+            };
+            self.run(Arc::clone(&store), &mut ctx)
                 .await
                 .unwrap_or_else(|err| {
                     eprintln!("Failed to run command. {err}");
                     Some(Resp::from(err))
                 })
-                .map(|v| {
-                    if need_return {
-                        OutgoingMessage::from(v)
-                    } else {
-                        OutgoingMessage::empty()
-                    }
-                })
-                .unwrap_or_else(OutgoingMessage::empty)
+                .filter(|_| need_return)
+                .map(Reply::from)
+                .unwrap_or_else(Reply::empty)
         };
 
         if let Some(sender) = ctx.sender {
@@ -157,16 +690,20 @@ impl Command {
     pub async fn run(self, store: Arc<Store>, ctx: &mut Context) -> RedisResult<Option<Resp>> {
         let opt = match self {
             Self::Ping => Some(Resp::SS("PONG".into())),
+            Self::Auth { username, password } => {
+                store
+                    .authenticate(&ctx.client, username.as_deref(), &password)
+                    .await?;
+                Some(Resp::SS("OK".into()))
+            }
             Self::Echo(val) => Some(Resp::BS(Some(val))),
             Self::Get { key } => {
-                let value = store
-                    .get_string(&key)
-                    .await
-                    .map(|v| Resp::BS(Some(v)))
-                    .unwrap_or(Resp::BS(None));
-                Some(value)
+                let value = store.with_value(&key, String::clone).await?;
+                Some(Resp::BS(value))
             }
             Self::Set { key, value, exp } => {
+                let now = store.clock().unix_millis();
+                let exp = exp.map(|exp| exp.unix_millis(now).saturating_sub(now));
                 store.set_string(&key, value, exp).await;
                 Some(Resp::SS("OK".into()))
             }
@@ -174,6 +711,156 @@ impl Command {
                 let num = store.increment(&key).await?;
                 Some(Resp::I(num))
             }
+            Self::GetRange { key, start, end } => {
+                Some(Resp::BS(Some(store.getrange(&key, start, end).await?)))
+            }
+            Self::SetRange { key, offset, value } => {
+                Some(Resp::I(store.setrange(&key, offset, &value).await?))
+            }
+            Self::Append { key, value } => Some(Resp::I(store.append(&key, &value).await?)),
+            Self::Lpush { key, elements } => {
+                Some(Resp::I(store.push(&key, &elements, true).await? as i64))
+            }
+            Self::Rpush { key, elements } => {
+                Some(Resp::I(store.push(&key, &elements, false).await? as i64))
+            }
+            Self::Lpop { key, count } => Some(pop(&store, &key, count, true).await?),
+            Self::Rpop { key, count } => Some(pop(&store, &key, count, false).await?),
+            Self::Blpop { keys, timeout } => bpop(store, ctx, keys, timeout, true).await?,
+            Self::Brpop { keys, timeout } => bpop(store, ctx, keys, timeout, false).await?,
+            Self::Lrange { key, start, stop } => {
+                let elements = store.lrange(&key, start, stop).await?;
+                Some(Resp::A(
+                    elements.into_iter().map(|e| Resp::BS(Some(e))).collect(),
+                ))
+            }
+            Self::Llen { key } => Some(Resp::I(store.llen(&key).await? as i64)),
+            Self::Linsert {
+                key,
+                before,
+                pivot,
+                element,
+            } => Some(Resp::I(
+                store.linsert(&key, before, &pivot, &element).await?,
+            )),
+            Self::Lset {
+                key,
+                index,
+                element,
+            } => {
+                store.lset(&key, index, &element).await?;
+                Some(Resp::SS("OK".into()))
+            }
+            Self::Lrem {
+                key,
+                count,
+                element,
+            } => Some(Resp::I(store.lrem(&key, count, &element).await? as i64)),
+            Self::Ltrim { key, start, stop } => {
+                store.ltrim(&key, start, stop).await?;
+                Some(Resp::SS("OK".into()))
+            }
+            Self::Hset { key, pairs } => Some(Resp::I(store.hset(&key, &pairs).await? as i64)),
+            Self::Hincrby {
+                key,
+                field,
+                increment,
+            } => Some(Resp::I(store.hincrby(&key, &field, increment).await?)),
+            Self::HincrbyFloat {
+                key,
+                field,
+                increment,
+            } => Some(Resp::BS(Some(
+                store.hincrbyfloat(&key, &field, increment).await?,
+            ))),
+            Self::Hdel { key, fields } => Some(Resp::I(store.hdel(&key, &fields).await? as i64)),
+            Self::Sadd { key, members } => Some(Resp::I(store.sadd(&key, &members).await? as i64)),
+            Self::Srem { key, members } => Some(Resp::I(store.srem(&key, &members).await? as i64)),
+            Self::Smembers { key } => Some(Resp::Set(
+                store
+                    .smembers(&key)
+                    .await?
+                    .into_iter()
+                    .map(|member| Resp::BS(Some(member)))
+                    .collect(),
+            )),
+            Self::Sismember { key, member } => {
+                Some(Resp::I(store.sismember(&key, &member).await? as i64))
+            }
+            Self::Scard { key } => Some(Resp::I(store.scard(&key).await? as i64)),
+            Self::Hgetall { key } => Some(Resp::Map(
+                store
+                    .hgetall(&key)
+                    .await?
+                    .into_iter()
+                    .map(|(field, value)| (Resp::BS(Some(field)), Resp::BS(Some(value))))
+                    .collect(),
+            )),
+            Self::Hexpire {
+                key,
+                expiry,
+                cond,
+                fields,
+            } => {
+                let at = expiry.unix_millis(store.clock().unix_millis());
+                let replies = store.hexpire(&key, at, cond, &fields).await?;
+                Some(Resp::A(replies.into_iter().map(Resp::I).collect()))
+            }
+            Self::Httl {
+                key,
+                fields,
+                millis,
+            } => {
+                let ttls = store.hpttl(&key, &fields).await?;
+                // Seconds left are rounded up, so that a field about to expire has 1.
+                let ttls = ttls.into_iter().map(|ttl| match ttl {
+                    0.. if !millis => Resp::I((ttl + 999) / 1000),
+                    _ => Resp::I(ttl),
+                });
+                Some(Resp::A(ttls.collect()))
+            }
+            Self::Hpersist { key, fields } => {
+                let replies = store.hpersist(&key, &fields).await?;
+                Some(Resp::A(replies.into_iter().map(Resp::I).collect()))
+            }
+            Self::Hrandfield {
+                key,
+                count,
+                withvalues,
+            } => {
+                let fields = store.hrandfield(&key, count).await?;
+                let resp = match (fields, count) {
+                    (None, None) => Resp::BS(None),
+                    (None, Some(_)) => Resp::A(vec![]),
+                    (Some(mut fields), None) => Resp::BS(fields.pop().map(|(field, _)| field)),
+                    // RESP3 clients get each field paired with its value.
+                    (Some(fields), Some(_)) if withvalues && ctx.protocol() == Protocol::Resp3 => {
+                        Resp::A(
+                            fields
+                                .into_iter()
+                                .map(|(field, value)| Resp::from(vec![field, value]))
+                                .collect(),
+                        )
+                    }
+                    (Some(fields), Some(_)) if withvalues => Resp::from(
+                        fields
+                            .into_iter()
+                            .flat_map(|(field, value)| [field, value])
+                            .collect::<Vec<String>>(),
+                    ),
+                    (Some(fields), Some(_)) => Resp::from(
+                        fields
+                            .into_iter()
+                            .map(|(field, _)| field)
+                            .collect::<Vec<String>>(),
+                    ),
+                };
+                Some(resp)
+            }
+            Self::Del { keys } => {
+                let num = store.del(&keys).await;
+                Some(Resp::I(num))
+            }
             Self::Type { key } => {
                 let value = store
                     .get(&key)
@@ -182,10 +869,255 @@ impl Command {
                     .unwrap_or(Resp::SS("none".into()));
                 Some(value)
             }
+            Self::Hello { protover, auth } => {
+                // A wrong password leaves both the user and the protocol as they were.
+                if let Some((username, password)) = auth {
+                    store
+                        .authenticate(&ctx.client, Some(&username), &password)
+                        .await?;
+                }
+                if let Some(protocol) = protover {
+                    ctx.client.set_protocol(protocol);
+                }
+                let mode = if store.cluster().await.is_ok() {
+                    "cluster"
+                } else {
+                    "standalone"
+                };
+                let role = match store.role().await {
+                    "master" => "master",
+                    _ => "replica",
+                };
+                let field = |name: &str| Resp::BS(Some(name.into()));
+                Some(Resp::Map(vec![
+                    (field("server"), field("redis")),
+                    (field("version"), field(rdb::REDIS_VER)),
+                    (field("proto"), Resp::I(ctx.protocol().version())),
+                    (field("id"), Resp::I(ctx.client.id() as i64)),
+                    (field("mode"), field(mode)),
+                    (field("role"), field(role)),
+                    (field("modules"), Resp::A(vec![])),
+                ]))
+            }
+            // There is a single database, so masters propagating to database 0 are
+            // followed, and any other one doesn't exist.
+            Self::Select { index: 0 } => Some(Resp::SS("OK".into())),
+            Self::Select { .. } => {
+                return Err(anyhow::anyhow!("ERR DB index is out of range").into());
+            }
             Self::Multi => {
                 store.start_queuing(ctx.addr).await;
                 Some(Resp::SS("OK".into()))
             }
+            Self::Watch { keys } => {
+                store.watch(ctx.addr, &keys).await;
+                Some(Resp::SS("OK".into()))
+            }
+            Self::Unwatch => {
+                store.unwatch(ctx.addr).await;
+                Some(Resp::SS("OK".into()))
+            }
+            Self::Xdelex { key, policy, ids } => {
+                let replies = store.delete_stream_entries(&key, &ids, policy).await?;
+                Some(Resp::A(replies.into_iter().map(Resp::I).collect()))
+            }
+            Self::Xackdel {
+                key,
+                group,
+                policy,
+                ids,
+            } => {
+                let replies = store
+                    .ack_delete_stream_entries(&key, &group, &ids, policy)
+                    .await?;
+                Some(Resp::A(replies.into_iter().map(Resp::I).collect()))
+            }
+            Self::Vadd {
+                key,
+                member,
+                vector,
+            } => {
+                let added = store.vadd(&key, &member, vector).await?;
+                Some(Resp::I(i64::from(added)))
+            }
+            Self::Vsim {
+                key,
+                query,
+                count,
+                withscores,
+            } => {
+                let found = store.vsim(&key, &query, count).await?;
+                let resp = if withscores {
+                    Resp::Map(
+                        found
+                            .into_iter()
+                            .map(|(member, score)| (Resp::BS(Some(member)), Resp::Double(score)))
+                            .collect(),
+                    )
+                } else {
+                    Resp::A(
+                        found
+                            .into_iter()
+                            .map(|(member, _)| Resp::BS(Some(member)))
+                            .collect(),
+                    )
+                };
+                Some(resp)
+            }
+            Self::Vcard { key } => Some(Resp::I(store.vcard(&key).await? as i64)),
+            Self::JsonSet {
+                key,
+                path,
+                value,
+                cond,
+            } => {
+                let set = store.json_set(&key, &path, value, cond).await?;
+                Some(if set {
+                    Resp::SS("OK".into())
+                } else {
+                    Resp::BS(None)
+                })
+            }
+            Self::JsonGet { key, paths } => Some(Resp::BS(store.json_get(&key, &paths).await?)),
+            Self::JsonDel { key, path } => Some(Resp::I(store.json_del(&key, &path).await? as i64)),
+            Self::BfReserve {
+                key,
+                error_rate,
+                capacity,
+                expansion,
+            } => {
+                let mut tokens = vec![
+                    "BF.RESERVE".to_string(),
+                    key.clone(),
+                    error_rate.to_string(),
+                    capacity.to_string(),
+                ];
+                match expansion {
+                    Some(expansion) => tokens.extend(["EXPANSION".into(), expansion.to_string()]),
+                    None => tokens.push("NONSCALING".into()),
+                }
+                let filter = BloomFilter::new(error_rate, capacity, expansion)?;
+                store.reserve(&key, Value::Bloom(filter), tokens).await?;
+                Some(Resp::SS("OK".into()))
+            }
+            Self::BfAdd { key, items, multi } => {
+                let mut added = store.bf_add(&key, &items).await?;
+                if multi {
+                    let replies = added.into_iter().map(|added| match added {
+                        Ok(added) => Resp::I(i64::from(added)),
+                        Err(err) => Resp::SE(err.to_string()),
+                    });
+                    Some(Resp::A(replies.collect()))
+                } else {
+                    Some(Resp::I(i64::from(added.remove(0)?)))
+                }
+            }
+            Self::BfExists { key, items, multi } => {
+                let found = store.bf_exists(&key, &items).await?;
+                let mut replies = found.into_iter().map(|found| Resp::I(i64::from(found)));
+                if multi {
+                    Some(Resp::A(replies.collect()))
+                } else {
+                    replies.next()
+                }
+            }
+            Self::CfReserve {
+                key,
+                capacity,
+                bucket_size,
+                max_iterations,
+                expansion,
+            } => {
+                let tokens = vec![
+                    "CF.RESERVE".to_string(),
+                    key.clone(),
+                    capacity.to_string(),
+                    "BUCKETSIZE".into(),
+                    bucket_size.to_string(),
+                    "MAXITERATIONS".into(),
+                    max_iterations.to_string(),
+                    "EXPANSION".into(),
+                    expansion.to_string(),
+                ];
+                let filter = CuckooFilter::new(capacity, bucket_size, max_iterations, expansion)?;
+                store.reserve(&key, Value::Cuckoo(filter), tokens).await?;
+                Some(Resp::SS("OK".into()))
+            }
+            Self::CfAdd { key, item, nx } => {
+                let added = store.cf_add(&key, &item, nx).await?;
+                Some(Resp::I(i64::from(added)))
+            }
+            Self::CfExists { key, item } => {
+                let count = store.cf_count(&key, &item).await?;
+                Some(Resp::I(i64::from(count > 0)))
+            }
+            Self::CfCount { key, item } => Some(Resp::I(store.cf_count(&key, &item).await? as i64)),
+            Self::CfDel { key, item } => {
+                let deleted = store.cf_del(&key, &item).await?;
+                Some(Resp::I(i64::from(deleted)))
+            }
+            Self::TopkReserve {
+                key,
+                k,
+                width,
+                depth,
+                decay,
+            } => {
+                let tokens = vec![
+                    "TOPK.RESERVE".to_string(),
+                    key.clone(),
+                    k.to_string(),
+                    width.to_string(),
+                    depth.to_string(),
+                    decay.to_string(),
+                ];
+                let topk = TopK::new(k, width, depth, decay);
+                store.reserve(&key, Value::TopK(topk), tokens).await?;
+                Some(Resp::SS("OK".into()))
+            }
+            Self::TopkIncrBy { key, items, .. } => {
+                let expelled = store.topk_incrby(&key, &items).await?;
+                Some(Resp::A(expelled.into_iter().map(Resp::BS).collect()))
+            }
+            Self::TopkQuery { key, items } => {
+                let found = store
+                    .with_topk(&key, |topk| {
+                        items
+                            .iter()
+                            .map(|item| Resp::I(i64::from(topk.contains(item))))
+                            .collect()
+                    })
+                    .await?;
+                Some(Resp::A(found))
+            }
+            Self::TopkList { key, withcount } => {
+                let list = store.with_topk(&key, |topk| topk.list().to_vec()).await?;
+                let mut replies = vec![];
+                for (item, count) in list {
+                    replies.push(Resp::BS(Some(item)));
+                    if withcount {
+                        replies.push(Resp::I(i64::from(count)));
+                    }
+                }
+                Some(Resp::A(replies))
+            }
+            Self::JsonNumIncrBy { key, path, value } => {
+                let results = store.json_numincrby(&key, &path, &value).await?;
+                // A legacy path replies the last number it changed.
+                let reply = if path.is_legacy() {
+                    results.into_iter().flatten().last().ok_or_else(|| {
+                        anyhow::anyhow!("ERR Path '{path}' does not exist or is not a number")
+                    })?
+                } else {
+                    Json::Array(
+                        results
+                            .into_iter()
+                            .map(|v| v.unwrap_or(Json::Null))
+                            .collect(),
+                    )
+                };
+                Some(Resp::BS(Some(reply.to_string())))
+            }
             Self::Xadd { key, id, values } => {
                 let resp = store
                     .set_stream(&key, id, values)
@@ -200,59 +1132,44 @@ impl Command {
             Self::Xread { block, stream } => {
                 let stream = store.parse_find_stream_args(stream).await?;
                 match block {
-                    Some(0) => {
+                    Some(milli) => {
                         if let Some(sender) = ctx.sender.take() {
+                            let client = Arc::clone(&ctx.client);
                             tokio::spawn(async move {
-                                let store_cp = Arc::clone(&store);
-                                let stream_cp = stream.clone();
+                                let keys: Vec<String> =
+                                    stream.iter().map(|(key, _)| key.to_string()).collect();
+                                // BLOCK 0 waits forever.
+                                let timeout = Some(Duration::from_millis(milli))
+                                    .filter(|timeout| !timeout.is_zero());
+                                let mut blocked = store.block_client(
+                                    &client,
+                                    &keys,
+                                    KeyEvent::StreamAdd,
+                                    timeout,
+                                );
 
-                                let mut msg: Option<OutgoingMessage> =
-                                    read_stream(store_cp, stream_cp)
+                                let mut msg: Option<Resp> =
+                                    read_stream(Arc::clone(&store), stream.clone())
                                         .await
-                                        .map(|v| Resp::from(v).into());
+                                        .map(Resp::from);
 
                                 while msg.is_none() {
-                                    // 1. Ask store to inform after adding any stream entry.
-                                    let (tx, mut rx) = mpsc::channel::<()>(stream.len());
-                                    for (key, _) in stream.iter() {
-                                        let tx = tx.clone();
-                                        store.subscribe_stream(key, tx).await;
-                                    }
-
-                                    // 2. Wait until notification from the store arrives.
-                                    if rx.recv().await.is_none() {
-                                        eprintln!("Mpsc sender dropped before sending");
-                                        return;
+                                    match blocked.wait().await {
+                                        Unblocked::Ready(_) => {
+                                            msg = read_stream(Arc::clone(&store), stream.clone())
+                                                .await
+                                                .map(Resp::from);
+                                        }
+                                        Unblocked::TimedOut => {
+                                            msg = Some(Resp::NullArray);
+                                        }
+                                        Unblocked::Disconnected => {
+                                            return;
+                                        }
                                     }
-
-                                    // 3. Get stream again.
-                                    let store_cp = Arc::clone(&store);
-                                    let stream_cp = stream.clone();
-
-                                    msg = read_stream(store_cp, stream_cp)
-                                        .await
-                                        .map(|v| Resp::from(v).into());
                                 }
 
-                                if sender.send(msg.unwrap()).is_err() {
-                                    eprintln!("Oneshot receiver dropped before sending");
-                                }
-                            });
-                        }
-                        None
-                    }
-                    Some(milli) => {
-                        if let Some(sender) = ctx.sender.take() {
-                            tokio::spawn(async move {
-                                sleep(Duration::from_millis(milli)).await;
-
-                                let msg: OutgoingMessage = read_stream(store, stream)
-                                    .await
-                                    .map(Resp::from)
-                                    .unwrap_or(Resp::BS(None))
-                                    .into();
-
-                                if sender.send(msg).is_err() {
+                                if sender.send(msg.unwrap().into()).is_err() {
                                     eprintln!("Oneshot receiver dropped before sending");
                                 }
                             });
@@ -263,28 +1180,47 @@ impl Command {
                         let resp = read_stream(store, stream)
                             .await
                             .map(Resp::from)
-                            .unwrap_or(Resp::BS(None));
+                            .unwrap_or(Resp::NullArray);
                         Some(resp)
                     }
                 }
             }
-            Self::ConfigGet(key) => {
-                let val = match key.as_str() {
-                    "dir" => store.rdb_dir().await,
-                    "dbfilename" => store.rdb_dbfilename().await,
-                    _ => None,
-                };
-
+            Self::ConfigGet(pattern) => {
+                let pairs = store
+                    .config_get(&pattern)
+                    .await
+                    .into_iter()
+                    .map(|(key, val)| (Resp::BS(Some(key.into())), Resp::BS(val)))
+                    .collect();
+                Some(Resp::Map(pairs))
+            }
+            Self::ConfigSet(params) => {
+                store.config_set(&params, ctx.addr).await?;
+                Some(Resp::SS("OK".into()))
+            }
+            Self::ConfigResetStat => {
+                store.reset_stats();
+                Some(Resp::SS("OK".into()))
+            }
+            Self::Scan {
+                cursor,
+                pattern,
+                count,
+                type_name,
+            } => {
+                let (next, keys) = store
+                    .scan(cursor, pattern.as_deref(), count, type_name.as_deref())
+                    .await;
                 let resp = Resp::A(vec![
-                    Resp::BS(Some(key)),
-                    val.map(|v| Resp::BS(Some(v))).unwrap_or(Resp::BS(None)),
+                    Resp::BS(Some(format!("{next}"))),
+                    Resp::A(keys.into_iter().map(|v| Resp::BS(Some(v))).collect()),
                 ]);
                 Some(resp)
             }
-            Self::Keys => {
+            Self::Keys { pattern } => {
                 let resp = Resp::A(
                     store
-                        .keys()
+                        .keys(&pattern)
                         .await
                         .into_iter()
                         .map(|v| Resp::BS(Some(v)))
@@ -292,16 +1228,86 @@ impl Command {
                 );
                 Some(resp)
             }
+            Self::Bgsave => {
+                store.bgsave().await?;
+                Some(Resp::SS("Background saving started".into()))
+            }
+            Self::Bgrewriteaof => {
+                store.bgrewriteaof().await?;
+                Some(Resp::SS(
+                    "Background append only file rewriting started".into(),
+                ))
+            }
             Self::Wait { num_replicas, exp } => {
                 let synced = store.wait(num_replicas, exp).await;
                 Some(Resp::I(synced))
             }
             Self::Info => {
                 let role = store.role().await;
-                let repl_id = store.repl_id();
+                let repl_id = store.repl_id().await;
                 let repl_offset = store.repl_offset();
+                let replicas = store.replicas().await;
+                let now = store.clock().unix_millis();
+                let slaves: String = replicas
+                    .iter()
+                    .enumerate()
+                    .map(|(i, r)| {
+                        format!(
+                            "slave{i}:ip={},port={},state=online,offset={},lag={}\r\n",
+                            r.ip,
+                            r.port,
+                            r.offset,
+                            r.lag(now)
+                        )
+                    })
+                    .collect();
+                // Databases without keys are left out, as Redis does.
+                let keyspace = store.keyspace_stats().await;
+                let db0 = if keyspace.keys > 0 {
+                    format!(
+                        "\r\ndb0:keys={},expires={},avg_ttl={}",
+                        keyspace.keys,
+                        keyspace.expires,
+                        keyspace.avg_ttl(now)
+                    )
+                } else {
+                    String::new()
+                };
+                let latencies: String = store
+                    .latency_percentiles()
+                    .iter()
+                    .map(|line| format!("\r\n{line}"))
+                    .collect();
+                let (stale_perc, time_cap_reached) = store.expire_cycle_stats();
+                let loading = store.loading_info();
                 let resp = Resp::BS(Some(format!(
-                    "role:{role}\r\nmaster_repl_offset:{repl_offset}\r\nmaster_replid:{repl_id}"
+                    "role:{role}\r\nconnected_slaves:{}\r\n{slaves}\
+                     master_repl_offset:{repl_offset}\r\nmaster_replid:{repl_id}\r\n\
+                     blocked_clients:{}\r\n\
+                     client_query_buffer_limit_disconnections:{}\r\n\
+                     client_query_timeout_disconnections:{}\r\n\
+                     total_connections_received:{}\r\n\
+                     total_commands_processed:{}\r\ninstantaneous_ops_per_sec:{}\r\n\
+                     total_error_replies:{}\r\nkeyspace_hits:{}\r\nkeyspace_misses:{}\r\n\
+                     expired_stale_perc:{stale_perc:.2}\r\n\
+                     expired_time_cap_reached_count:{time_cap_reached}\r\n{loading}\
+                     rdb_changes_since_last_save:{}\r\nrdb_bgsave_in_progress:{}\r\nrdb_last_save_time:{}\r\n\
+                     aof_enabled:{}\r\naof_rewrite_in_progress:{}{latencies}{db0}",
+                    replicas.len(),
+                    store.blocked_clients(),
+                    store.query_buffer_disconnections(),
+                    store.query_timeout_disconnections(),
+                    store.total_connections_received(),
+                    store.total_commands_processed(),
+                    store.instantaneous_ops_per_sec(),
+                    store.total_error_replies(),
+                    store.keyspace_hits(),
+                    store.keyspace_misses(),
+                    store.changes_since_last_save(),
+                    u8::from(store.bgsave_in_progress()),
+                    store.lastsave(),
+                    u8::from(store.aof_enabled()),
+                    u8::from(store.aof_rewrite_in_progress()),
                 )));
                 Some(resp)
             }
@@ -319,11 +1325,21 @@ impl Command {
                     store.receive_replica_ack(ctx.addr, ack).await;
                     None
                 }
+                "LISTENING-PORT" => {
+                    let port = value.parse::<u16>().map_err(|_| RedisError::NotInteger)?;
+                    ctx.client.set_listening_port(port);
+                    Some(Resp::SS("OK".into()))
+                }
+                "IP-ADDRESS" => {
+                    ctx.client.set_announced_ip(value);
+                    Some(Resp::SS("OK".into()))
+                }
                 _ => Some(Resp::SS("OK".into())),
             },
             Self::Psync => {
-                let repl_id = store.repl_id();
-                let repl_offset = store.repl_offset();
+                let repl_id = store.repl_id().await;
+                // The stream propagated since the replica registered follows the RDB.
+                let repl_offset = ctx.client.sync_offset();
 
                 let order = Resp::SS(format!("FULLRESYNC {repl_id} {repl_offset}"));
                 let rdb = store.rdb(repl_offset);
@@ -335,6 +1351,307 @@ impl Command {
 
                 Some(Resp::RAW(vec![order.serialize(), rdb_serialized]))
             }
+            Self::Debug(cmd) => {
+                if !store.debug_command_allowed(ctx.addr) {
+                    return Err(RedisError::from(anyhow::anyhow!(
+                        "ERR DEBUG command not allowed. If the enable-debug-command option is set to \"local\", you can run it from a local connection, otherwise you need to set this option in the configuration file, and then restart the server."
+                    )));
+                }
+
+                let resp = match cmd {
+                    DebugCommand::Sleep(duration) => {
+                        store.block_for(duration).await;
+                        Resp::SS("OK".into())
+                    }
+                    DebugCommand::Object { key } => {
+                        let value = store.get(&key).await.ok_or(RedisError::NoSuchKey)?;
+                        Resp::SS(format!(
+                            "Value at:0x0 refcount:1 encoding:{} serializedlength:{} lru:0 lru_seconds_idle:0",
+                            value.encoding(),
+                            value.serialized_len(),
+                        ))
+                    }
+                    DebugCommand::SetActiveExpire(enabled) => {
+                        store.set_active_expire(enabled).await;
+                        Resp::SS("OK".into())
+                    }
+                    DebugCommand::ChangeReplId => {
+                        store.change_repl_id().await;
+                        Resp::SS("OK".into())
+                    }
+                    DebugCommand::ReloadConfig => {
+                        store.reload_config().await?;
+                        Resp::SS("OK".into())
+                    }
+                    DebugCommand::Listpack { key } => {
+                        let value = store.get(&key).await.ok_or(RedisError::NoSuchKey)?;
+                        println!("{}", rdb::listpack_repr(&value)?);
+                        Resp::SS("Listpack structure printed on stdout".into())
+                    }
+                    DebugCommand::QuicklistPackedThreshold(bytes) => {
+                        store.set_quicklist_packed_threshold(bytes)?;
+                        Resp::SS("OK".into())
+                    }
+                };
+                Some(resp)
+            }
+            Self::Cluster(cmd) => {
+                let cluster = store.cluster().await?;
+                let invalid_slot = || RedisError::from(anyhow::anyhow!("ERR Invalid slot"));
+                let resp = match cmd {
+                    ClusterCommand::Info => Resp::BS(Some(cluster.info())),
+                    ClusterCommand::Myid => Resp::BS(Some(cluster.myself().id.clone())),
+                    ClusterCommand::Slots => cluster.slots_resp(),
+                    ClusterCommand::Shards => cluster.shards_resp(),
+                    ClusterCommand::Nodes => Resp::BS(Some(cluster.nodes_description())),
+                    ClusterCommand::Meet { ip, port, bus_port } => {
+                        let bus_port =
+                            bus_port.unwrap_or(port.wrapping_add(cluster::CLUSTER_PORT_INCR));
+                        let addr = format!("{ip}:{bus_port}")
+                            .to_socket_addrs()
+                            .ok()
+                            .and_then(|mut addrs| addrs.next())
+                            .ok_or_else(|| {
+                                RedisError::from(anyhow::anyhow!(
+                                    "ERR Invalid node address specified: {ip}:{port}"
+                                ))
+                            })?;
+                        store.cluster_meet(addr);
+                        Resp::SS("OK".into())
+                    }
+                    ClusterCommand::SetSlot { slot, action } => {
+                        let slot = cluster::valid_slot(slot).ok_or_else(invalid_slot)?;
+                        let mut cluster = cluster;
+                        if let SetSlot::Node(id) = &action {
+                            let from_myself = cluster
+                                .owner(slot)
+                                .is_some_and(|owner| cluster.is_myself(owner));
+                            if from_myself
+                                && *id != cluster.myself().id
+                                && !store.keys_in_slot(slot, 1).await.is_empty()
+                            {
+                                return Err(anyhow::anyhow!("ERR Can't assign hashslot {slot} to a different node while I still hold keys for this hash slot.").into());
+                            }
+                        }
+                        cluster.set_slot(slot, action)?;
+                        Resp::SS("OK".into())
+                    }
+                    ClusterCommand::Failover { force } => {
+                        drop(cluster);
+                        store.cluster_failover(force).await?;
+                        Resp::SS("OK".into())
+                    }
+                    ClusterCommand::KeySlot(key) => {
+                        Resp::I(cluster::key_hash_slot(key.as_bytes()) as i64)
+                    }
+                    ClusterCommand::CountKeysInSlot(slot) => {
+                        let slot = cluster::valid_slot(slot).ok_or_else(invalid_slot)?;
+                        drop(cluster);
+                        Resp::I(store.keys_in_slot(slot, usize::MAX).await.len() as i64)
+                    }
+                    ClusterCommand::GetKeysInSlot { slot, count } => {
+                        let slot = cluster::valid_slot(slot).ok_or_else(invalid_slot)?;
+                        let count = usize::try_from(count).map_err(|_| {
+                            RedisError::from(anyhow::anyhow!("ERR Invalid number of keys"))
+                        })?;
+                        drop(cluster);
+                        let keys = store.keys_in_slot(slot, count).await;
+                        Resp::A(keys.into_iter().map(|key| Resp::BS(Some(key))).collect())
+                    }
+                };
+                Some(resp)
+            }
+            Self::Asking => {
+                drop(store.cluster().await?);
+                ctx.client.set_asking(true);
+                Some(Resp::SS("OK".into()))
+            }
+            Self::Readonly | Self::Readwrite => {
+                drop(store.cluster().await?);
+                ctx.client.set_readonly(matches!(self, Self::Readonly));
+                Some(Resp::SS("OK".into()))
+            }
+            Self::Migrate {
+                host,
+                port,
+                keys,
+                timeout,
+                copy,
+                replace,
+            } => {
+                let addr = format!("{host}:{port}")
+                    .to_socket_addrs()
+                    .ok()
+                    .and_then(|mut addrs| addrs.next())
+                    .ok_or_else(|| {
+                        RedisError::from(anyhow::anyhow!(
+                            "IOERR error or timeout connecting to the client {host}:{port}"
+                        ))
+                    })?;
+                let timeout = Duration::from_millis(if timeout == 0 { 1000 } else { timeout });
+                let migrated = store.migrate(addr, &keys, timeout, copy, replace).await?;
+                Some(Resp::SS(if migrated { "OK" } else { "NOKEY" }.into()))
+            }
+            Self::ClientNoEvict(no_evict) => {
+                ctx.client.set_no_evict(no_evict);
+                Some(Resp::SS("OK".into()))
+            }
+            Self::ClientKill {
+                id,
+                addr,
+                skipme,
+                legacy,
+            } => {
+                let me = ctx.addr;
+                let killed = store
+                    .kill_clients(|client| {
+                        id.is_none_or(|id| client.id() == id)
+                            && addr
+                                .as_deref()
+                                .is_none_or(|a| client.addr().to_string() == a)
+                            && !(skipme && client.addr() == me)
+                    })
+                    .await;
+                if !legacy {
+                    Some(Resp::I(killed as i64))
+                } else if killed == 0 {
+                    return Err(anyhow::anyhow!("ERR No such client").into());
+                } else {
+                    Some(Resp::SS("OK".into()))
+                }
+            }
+            Self::Shutdown { save } => {
+                // The connection closes along with the others, so there is no reply.
+                store.shutdown(save).await?;
+                None
+            }
+            Self::Eval {
+                script,
+                keys,
+                args,
+                readonly,
+            } => {
+                let sha = store.script_load(&script);
+                let store = Arc::clone(&store);
+                Some(store.eval(&sha, ctx.addr, keys, args, readonly).await?)
+            }
+            Self::EvalSha {
+                sha,
+                keys,
+                args,
+                readonly,
+            } => {
+                let store = Arc::clone(&store);
+                Some(store.eval(&sha, ctx.addr, keys, args, readonly).await?)
+            }
+            Self::Script(cmd) => {
+                let resp = match cmd {
+                    ScriptCommand::Load(script) => Resp::BS(Some(store.script_load(&script))),
+                    ScriptCommand::Exists(shas) => Resp::A(
+                        store
+                            .script_exists(&shas)
+                            .into_iter()
+                            .map(|exists| Resp::I(i64::from(exists)))
+                            .collect(),
+                    ),
+                    ScriptCommand::Flush => {
+                        store.script_flush();
+                        Resp::SS("OK".into())
+                    }
+                    ScriptCommand::Kill => {
+                        store.script_kill()?;
+                        Resp::SS("OK".into())
+                    }
+                };
+                Some(resp)
+            }
+            Self::Function(cmd) => {
+                let resp = match cmd {
+                    FunctionCommand::Load { code, replace } => {
+                        Resp::BS(Some(store.function_load(code, replace).await?))
+                    }
+                    FunctionCommand::List { pattern, with_code } => Resp::A(
+                        store
+                            .function_list(pattern.as_deref())?
+                            .iter()
+                            .map(|lib| library_resp(lib, with_code))
+                            .collect(),
+                    ),
+                    FunctionCommand::Delete(name) => {
+                        store.function_delete(&name).await?;
+                        Resp::SS("OK".into())
+                    }
+                    FunctionCommand::Flush => {
+                        store.function_flush().await?;
+                        Resp::SS("OK".into())
+                    }
+                    FunctionCommand::Dump => Resp::BS(Some(store.function_dump()?)),
+                    FunctionCommand::Restore { payload, policy } => {
+                        store.function_restore(payload, policy).await?;
+                        Resp::SS("OK".into())
+                    }
+                    FunctionCommand::Kill => {
+                        store.script_kill()?;
+                        Resp::SS("OK".into())
+                    }
+                };
+                Some(resp)
+            }
+            Self::Fcall {
+                function,
+                keys,
+                args,
+                readonly,
+            } => {
+                let store = Arc::clone(&store);
+                Some(
+                    store
+                        .fcall(&function, ctx.addr, keys, args, readonly)
+                        .await?,
+                )
+            }
+            Self::Acl(cmd) => {
+                let resp = match cmd {
+                    AclCommand::SetUser { name, rules } => {
+                        store.acl_setuser(&name, &rules)?;
+                        Resp::SS("OK".into())
+                    }
+                    AclCommand::DelUser(names) => Resp::I(store.acl_deluser(&names)?),
+                    AclCommand::List => Resp::A(
+                        store
+                            .acl_list()?
+                            .into_iter()
+                            .map(|user| Resp::BS(Some(user)))
+                            .collect(),
+                    ),
+                    AclCommand::Users => Resp::A(
+                        store
+                            .acl_users()?
+                            .into_iter()
+                            .map(|name| Resp::BS(Some(name)))
+                            .collect(),
+                    ),
+                    AclCommand::WhoAmI => Resp::BS(Some(ctx.client.user())),
+                    AclCommand::Load => {
+                        store.acl_load().await?;
+                        Resp::SS("OK".into())
+                    }
+                    AclCommand::Save => {
+                        store.acl_save()?;
+                        Resp::SS("OK".into())
+                    }
+                };
+                Some(resp)
+            }
+            Self::ObjectEncoding { key } => Some(Resp::BS(
+                store
+                    .get(&key)
+                    .await
+                    .map(|value| value.encoding().to_string()),
+            )),
+            Self::Help(name) => Some(Resp::A(
+                help::lines(name).into_iter().map(Resp::SS).collect(),
+            )),
             _ => {
                 return Err(RedisError::UnknownCommand);
             }
@@ -344,100 +1661,495 @@ impl Command {
     }
 
     fn from_args(args: Vec<String>) -> RedisResult<Self> {
+        if let [name, sub] = args.as_slice() {
+            match help::command(name) {
+                Some(name) if sub.eq_ignore_ascii_case("HELP") => return Ok(Self::Help(name)),
+                _ => {}
+            }
+        }
         let cmd = if let Some(first) = args.first() {
-            match first.to_uppercase().as_str() {
+            let cmd_name = first.to_uppercase();
+            match cmd_name.as_str() {
                 "PING" => Self::Ping,
-                "ECHO" => {
-                    let arg = args
-                        .get(1)
-                        .ok_or(RedisError::LackOfArgs { need: 1, got: 0 })?;
-                    Self::Echo(arg.into())
+                "ECHO" => Self::Echo(Args::new(&args[1..], 1).expect()?.into()),
+                "AUTH" => match &args[1..] {
+                    [password] => Self::Auth {
+                        username: None,
+                        password: password.clone(),
+                    },
+                    [username, password] => Self::Auth {
+                        username: Some(username.clone()),
+                        password: password.clone(),
+                    },
+                    [] => return Err(RedisError::LackOfArgs { need: 1, got: 0 }),
+                    _ => return Err(RedisError::Syntax),
+                },
+                "GET" => Self::Get {
+                    key: Args::new(&args[1..], 1).expect_key()?,
+                },
+                "SET" => set_args(&args[1..])?,
+                "INCR" => Self::Incr {
+                    key: Args::new(&args[1..], 1).expect_key()?,
+                },
+                "GETRANGE" => {
+                    let mut args = Args::new(&args[1..], 3);
+                    let cmd = Self::GetRange {
+                        key: args.expect_key()?,
+                        start: args.expect_int()?,
+                        end: args.expect_int()?,
+                    };
+                    args.finish()?;
+                    cmd
                 }
-                "GET" => {
-                    let key = args
-                        .get(1)
-                        .ok_or(RedisError::LackOfArgs { need: 1, got: 0 })?
-                        .to_string();
-                    Self::Get { key }
+                "SETRANGE" => {
+                    let mut args = Args::new(&args[1..], 3);
+                    let cmd = Self::SetRange {
+                        key: args.expect_key()?,
+                        offset: args.expect_int()?,
+                        value: args.expect()?.to_string(),
+                    };
+                    args.finish()?;
+                    cmd
                 }
-                "SET" => {
-                    let key = args
-                        .get(1)
-                        .ok_or(RedisError::LackOfArgs { need: 2, got: 0 })?
-                        .to_string();
-                    let value = args
-                        .get(2)
-                        .ok_or(RedisError::LackOfArgs { need: 2, got: 1 })?
-                        .to_string();
-                    let exp = args
-                        .get(3)
-                        .and_then(|opt| {
-                            if opt.to_uppercase().as_str() == "PX" {
-                                args.get(4)
-                            } else {
-                                None
-                            }
+                "APPEND" => {
+                    let mut args = Args::new(&args[1..], 2);
+                    let cmd = Self::Append {
+                        key: args.expect_key()?,
+                        value: args.expect()?.to_string(),
+                    };
+                    args.finish()?;
+                    cmd
+                }
+                "LPUSH" | "RPUSH" => {
+                    let (key, elements) = key_and_items(&args[1..], true)?;
+                    if cmd_name == "LPUSH" {
+                        Self::Lpush { key, elements }
+                    } else {
+                        Self::Rpush { key, elements }
+                    }
+                }
+                "LPOP" | "RPOP" => {
+                    let mut args = Args::new(&args[1..], 1);
+                    let key = args.expect_key()?;
+                    let count = args
+                        .peek()
+                        .map(|_| {
+                            args.expect_int::<usize>().map_err(|_| {
+                                anyhow::anyhow!("ERR value is out of range, must be positive")
+                            })
                         })
-                        .and_then(|v| v.parse::<u64>().ok());
-                    Self::Set { key, value, exp }
+                        .transpose()?;
+                    args.finish()?;
+                    if cmd_name == "LPOP" {
+                        Self::Lpop { key, count }
+                    } else {
+                        Self::Rpop { key, count }
+                    }
+                }
+                "BLPOP" | "BRPOP" => {
+                    let (timeout, keys) = args[1..]
+                        .split_last()
+                        .filter(|(_, keys)| !keys.is_empty())
+                        .ok_or(RedisError::LackOfArgs {
+                            need: 2,
+                            got: args.len() - 1,
+                        })?;
+                    let timeout = timeout
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|secs| secs.is_finite())
+                        .ok_or(anyhow::anyhow!(
+                            "ERR timeout is not a float or out of range"
+                        ))?;
+                    if timeout < 0.0 {
+                        return Err(anyhow::anyhow!("ERR timeout is negative").into());
+                    }
+                    let keys = keys.to_vec();
+                    // A zero timeout blocks forever.
+                    let timeout = Some(Duration::from_secs_f64(timeout)).filter(|t| !t.is_zero());
+                    if cmd_name == "BLPOP" {
+                        Self::Blpop { keys, timeout }
+                    } else {
+                        Self::Brpop { keys, timeout }
+                    }
+                }
+                "LRANGE" => {
+                    let mut args = Args::new(&args[1..], 3);
+                    let cmd = Self::Lrange {
+                        key: args.expect_key()?,
+                        start: args.expect_int()?,
+                        stop: args.expect_int()?,
+                    };
+                    args.finish()?;
+                    cmd
+                }
+                "LLEN" => {
+                    let mut args = Args::new(&args[1..], 1);
+                    let key = args.expect_key()?;
+                    args.finish()?;
+                    Self::Llen { key }
+                }
+                "LINSERT" => {
+                    let mut args = Args::new(&args[1..], 4);
+                    let key = args.expect_key()?;
+                    let before = match args.expect()?.to_uppercase().as_str() {
+                        "BEFORE" => true,
+                        "AFTER" => false,
+                        _ => return Err(RedisError::Syntax),
+                    };
+                    let cmd = Self::Linsert {
+                        key,
+                        before,
+                        pivot: args.expect_key()?,
+                        element: args.expect_key()?,
+                    };
+                    args.finish()?;
+                    cmd
+                }
+                "LSET" => {
+                    let mut args = Args::new(&args[1..], 3);
+                    let cmd = Self::Lset {
+                        key: args.expect_key()?,
+                        index: args.expect_int()?,
+                        element: args.expect_key()?,
+                    };
+                    args.finish()?;
+                    cmd
+                }
+                "LREM" => {
+                    let mut args = Args::new(&args[1..], 3);
+                    let cmd = Self::Lrem {
+                        key: args.expect_key()?,
+                        count: args.expect_int()?,
+                        element: args.expect_key()?,
+                    };
+                    args.finish()?;
+                    cmd
+                }
+                "LTRIM" => {
+                    let mut args = Args::new(&args[1..], 3);
+                    let cmd = Self::Ltrim {
+                        key: args.expect_key()?,
+                        start: args.expect_int()?,
+                        stop: args.expect_int()?,
+                    };
+                    args.finish()?;
+                    cmd
+                }
+                "HSET" => {
+                    // The key and a field with its value at least, and values to every field.
+                    let got = args.len() - 1;
+                    if got < 3 || got.is_multiple_of(2) {
+                        return Err(RedisError::LackOfArgs {
+                            need: (got + 1).max(3),
+                            got,
+                        });
+                    }
+                    Self::Hset {
+                        key: args[1].clone(),
+                        pairs: args[2..]
+                            .chunks_exact(2)
+                            .map(|pair| (pair[0].clone(), pair[1].clone()))
+                            .collect(),
+                    }
+                }
+                "HINCRBY" => {
+                    let mut args = Args::new(&args[1..], 3);
+                    let cmd = Self::Hincrby {
+                        key: args.expect_key()?,
+                        field: args.expect_key()?,
+                        increment: args.expect_int()?,
+                    };
+                    args.finish()?;
+                    cmd
+                }
+                "HINCRBYFLOAT" => {
+                    let mut args = Args::new(&args[1..], 3);
+                    let key = args.expect_key()?;
+                    let field = args.expect_key()?;
+                    let increment = args
+                        .expect()?
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|num| num.is_finite())
+                        .ok_or(anyhow::anyhow!("ERR value is not a valid float"))?;
+                    args.finish()?;
+                    Self::HincrbyFloat {
+                        key,
+                        field,
+                        increment,
+                    }
+                }
+                "HRANDFIELD" => {
+                    let mut args = Args::new(&args[1..], 1);
+                    let key = args.expect_key()?;
+                    let count = args.peek().map(|_| args.expect_int::<i64>()).transpose()?;
+                    let withvalues = count.is_some() && args.optional_token("WITHVALUES");
+                    args.finish()?;
+                    Self::Hrandfield {
+                        key,
+                        count,
+                        withvalues,
+                    }
+                }
+                "HDEL" => {
+                    let mut args = Args::new(&args[1..], 2);
+                    Self::Hdel {
+                        key: args.expect_key()?,
+                        fields: args.expect_many()?.to_vec(),
+                    }
+                }
+                "SADD" | "SREM" => {
+                    let (key, members) = key_and_items(&args[1..], true)?;
+                    if cmd_name == "SADD" {
+                        Self::Sadd { key, members }
+                    } else {
+                        Self::Srem { key, members }
+                    }
+                }
+                "SMEMBERS" | "SCARD" => {
+                    let mut args = Args::new(&args[1..], 1);
+                    let key = args.expect_key()?;
+                    args.finish()?;
+                    if cmd_name == "SMEMBERS" {
+                        Self::Smembers { key }
+                    } else {
+                        Self::Scard { key }
+                    }
+                }
+                "SISMEMBER" => {
+                    let (key, mut members) = key_and_items(&args[1..], false)?;
+                    Self::Sismember {
+                        key,
+                        member: members.remove(0),
+                    }
+                }
+                "HGETALL" => {
+                    let mut args = Args::new(&args[1..], 1);
+                    let key = args.expect_key()?;
+                    args.finish()?;
+                    Self::Hgetall { key }
+                }
+                "HEXPIRE" => hexpire_args(&args[1..], Expiry::Seconds)?,
+                "HPEXPIRE" => hexpire_args(&args[1..], Expiry::Millis)?,
+                "HEXPIREAT" => hexpire_args(&args[1..], Expiry::UnixSeconds)?,
+                "HPEXPIREAT" => hexpire_args(&args[1..], Expiry::UnixMillis)?,
+                "HTTL" | "HPTTL" => {
+                    let mut args = Args::new(&args[1..], 4);
+                    Self::Httl {
+                        key: args.expect_key()?,
+                        fields: hash_fields(&mut args)?,
+                        millis: cmd_name == "HPTTL",
+                    }
                 }
-                "INCR" => {
-                    let key = args
-                        .get(1)
-                        .ok_or(RedisError::LackOfArgs { need: 1, got: 0 })?
-                        .to_string();
-                    Self::Incr { key }
+                "HPERSIST" => {
+                    let mut args = Args::new(&args[1..], 4);
+                    Self::Hpersist {
+                        key: args.expect_key()?,
+                        fields: hash_fields(&mut args)?,
+                    }
                 }
-                "TYPE" => {
-                    let key = args
+                "DEL" => Self::Del {
+                    keys: Args::new(&args[1..], 1).expect_many()?.to_vec(),
+                },
+                "TYPE" => Self::Type {
+                    key: Args::new(&args[1..], 1).expect_key()?,
+                },
+                "HELLO" => {
+                    let protover = args
                         .get(1)
-                        .ok_or(RedisError::LackOfArgs { need: 1, got: 0 })?
-                        .to_string();
-                    Self::Type { key }
+                        .map(|arg| {
+                            let version = arg.parse::<i64>().map_err(|_| {
+                                anyhow::anyhow!(
+                                    "ERR Protocol version is not an integer or out of range"
+                                )
+                            })?;
+                            Protocol::from_version(version).ok_or_else(|| {
+                                anyhow::anyhow!("NOPROTO unsupported protocol version")
+                            })
+                        })
+                        .transpose()?;
+                    let auth = match args.get(2..).unwrap_or_default() {
+                        [] => None,
+                        [opt, username, password] if opt.eq_ignore_ascii_case("AUTH") => {
+                            Some((username.clone(), password.clone()))
+                        }
+                        [opt, ..] => {
+                            return Err(
+                                anyhow::anyhow!("ERR Syntax error in HELLO option '{opt}'").into()
+                            )
+                        }
+                    };
+                    Self::Hello { protover, auth }
                 }
+                "SELECT" => Self::Select {
+                    index: Args::new(&args[1..], 1).expect_int()?,
+                },
                 "MULTI" => Self::Multi,
                 "EXEC" => Self::Exec,
                 "DISCARD" => Self::Discard,
-                "XADD" => {
-                    if args.len() < 5 {
-                        return Err(RedisError::LackOfArgs {
-                            need: 5,
-                            got: args.len(),
-                        });
-                    }
-
-                    let key = args
-                        .get(1)
-                        .ok_or(RedisError::LackOfArgs { need: 5, got: 0 })?
-                        .to_string();
-                    let id = args
-                        .get(2)
-                        .ok_or(RedisError::LackOfArgs { need: 5, got: 1 })?
-                        .to_string();
-                    let values = into_hashmap(&args[3..]);
-
-                    Self::Xadd { key, id, values }
-                }
+                "WATCH" => Self::Watch {
+                    keys: Args::new(&args[1..], 1).expect_many()?.to_vec(),
+                },
+                "UNWATCH" => Self::Unwatch,
+                "XADD" => xadd_args(&args[1..])?,
                 "XRANGE" => {
-                    let key = args
-                        .get(1)
-                        .ok_or(RedisError::LackOfArgs { need: 3, got: 0 })?
-                        .to_string();
-                    let start = args
-                        .get(2)
-                        .ok_or(RedisError::LackOfArgs { need: 3, got: 1 })?
-                        .to_string();
-                    let end = args
-                        .get(3)
-                        .ok_or(RedisError::LackOfArgs { need: 3, got: 2 })?
-                        .to_string();
-
-                    Self::Xrange { key, start, end }
+                    let mut args = Args::new(&args[1..], 3);
+                    Self::Xrange {
+                        key: args.expect_key()?,
+                        start: args.expect()?.to_string(),
+                        end: args.expect()?.to_string(),
+                    }
                 }
                 "XREAD" => {
                     let (block, stream) = xread_args(&args[1..])?;
                     Self::Xread { block, stream }
                 }
+                "VADD" => vadd_args(Args::new(&args[1..], 5))?,
+                "VSIM" => vsim_args(Args::new(&args[1..], 3))?,
+                "VCARD" => Self::Vcard {
+                    key: Args::new(&args[1..], 1).expect_key()?,
+                },
+                "JSON.SET" => {
+                    let mut args = Args::new(&args[1..], 3);
+                    let key = args.expect_key()?;
+                    let path = args.expect()?;
+                    let value = args.expect()?;
+                    let cond = match args.keyword(&["NX", "XX"]) {
+                        Some("NX") => Some(SetCond::Nx),
+                        Some(_) => Some(SetCond::Xx),
+                        None => None,
+                    };
+                    args.finish()?;
+                    Self::JsonSet {
+                        key,
+                        path: JsonPath::parse(path)?,
+                        value: Json::parse(value)?,
+                        cond,
+                    }
+                }
+                "JSON.GET" => {
+                    let mut args = Args::new(&args[1..], 1);
+                    let key = args.expect_key()?;
+                    let paths = json_get_paths(args)?;
+                    Self::JsonGet { key, paths }
+                }
+                "JSON.DEL" => {
+                    let mut args = Args::new(&args[1..], 1);
+                    let key = args.expect_key()?;
+                    let path = JsonPath::parse(args.expect().unwrap_or("$"))?;
+                    args.finish()?;
+                    Self::JsonDel { key, path }
+                }
+                "JSON.NUMINCRBY" => {
+                    let mut args = Args::new(&args[1..], 3);
+                    let key = args.expect_key()?;
+                    let path = args.expect()?;
+                    let value = args.expect()?;
+                    args.finish()?;
+                    let value = Json::parse(value)
+                        .ok()
+                        .filter(Json::is_number)
+                        .ok_or(anyhow::anyhow!("ERR expected a number"))?;
+                    Self::JsonNumIncrBy {
+                        key,
+                        path: JsonPath::parse(path)?,
+                        value,
+                    }
+                }
+                "BF.RESERVE" => bf_reserve_args(&args[1..])?,
+                "BF.ADD" | "BF.MADD" | "BF.EXISTS" | "BF.MEXISTS" => {
+                    let multi = cmd_name.starts_with("BF.M");
+                    let (key, items) = key_and_items(&args[1..], multi)?;
+                    if cmd_name.ends_with("ADD") {
+                        Self::BfAdd { key, items, multi }
+                    } else {
+                        Self::BfExists { key, items, multi }
+                    }
+                }
+                "CF.RESERVE" => cf_reserve_args(&args[1..])?,
+                "CF.ADD" | "CF.ADDNX" | "CF.EXISTS" | "CF.COUNT" | "CF.DEL" => {
+                    let (key, mut items) = key_and_items(&args[1..], false)?;
+                    let item = items.remove(0);
+                    match cmd_name.as_str() {
+                        "CF.ADD" => Self::CfAdd {
+                            key,
+                            item,
+                            nx: false,
+                        },
+                        "CF.ADDNX" => Self::CfAdd {
+                            key,
+                            item,
+                            nx: true,
+                        },
+                        "CF.EXISTS" => Self::CfExists { key, item },
+                        "CF.COUNT" => Self::CfCount { key, item },
+                        _ => Self::CfDel { key, item },
+                    }
+                }
+                "TOPK.RESERVE" => topk_reserve_args(&args[1..])?,
+                "TOPK.ADD" => {
+                    let (key, items) = key_and_items(&args[1..], true)?;
+                    let items = items.into_iter().map(|item| (item, 1)).collect();
+                    Self::TopkIncrBy {
+                        key,
+                        items,
+                        add: true,
+                    }
+                }
+                "TOPK.INCRBY" => {
+                    let (key, items) = key_and_items(&args[1..], true)?;
+                    if items.len() % 2 != 0 {
+                        return Err(RedisError::Syntax);
+                    }
+                    let items = items
+                        .chunks(2)
+                        .map(|pair| {
+                            let by = pair[1]
+                                .parse::<u32>()
+                                .ok()
+                                .filter(|by| (1..=100_000).contains(by))
+                                .ok_or(anyhow::anyhow!(
+                                    "ERR TopK: increment must be an integer greater or equal to 1 and less than or equal to 100000"
+                                ))?;
+                            Ok((pair[0].clone(), by))
+                        })
+                        .collect::<RedisResult<Vec<_>>>()?;
+                    Self::TopkIncrBy {
+                        key,
+                        items,
+                        add: false,
+                    }
+                }
+                "TOPK.QUERY" => {
+                    let (key, items) = key_and_items(&args[1..], true)?;
+                    Self::TopkQuery { key, items }
+                }
+                "TOPK.LIST" => {
+                    let mut args = Args::new(&args[1..], 1);
+                    let key = args.expect_key()?;
+                    let withcount = args.optional_token("WITHCOUNT");
+                    args.finish()?;
+                    Self::TopkList { key, withcount }
+                }
+                "XDELEX" => {
+                    let mut args = Args::new(&args[1..], 4);
+                    let key = args.expect_key()?;
+                    let (policy, ids) = delete_args(args)?;
+                    Self::Xdelex { key, policy, ids }
+                }
+                "XACKDEL" => {
+                    let mut args = Args::new(&args[1..], 5);
+                    let key = args.expect_key()?;
+                    let group = args.expect()?.to_string();
+                    let (policy, ids) = delete_args(args)?;
+                    Self::Xackdel {
+                        key,
+                        group,
+                        policy,
+                        ids,
+                    }
+                }
                 "CONFIG" => match args.get(1) {
                     Some(cmd) if cmd.to_uppercase().as_str() == "GET" => {
                         let key = args
@@ -446,33 +2158,86 @@ impl Command {
                             .ok_or(RedisError::LackOfArgs { need: 1, got: 0 })?;
                         Self::ConfigGet(key)
                     }
+                    Some(cmd) if cmd.to_uppercase().as_str() == "SET" => {
+                        let pairs = args[2..].chunks_exact(2);
+                        if args.len() == 2 || !pairs.remainder().is_empty() {
+                            return Err(RedisError::LackOfArgs {
+                                need: (args.len() - 1).max(2),
+                                got: args.len() - 2,
+                            });
+                        }
+                        let params = pairs
+                            .map(|pair| (pair[0].clone(), pair[1].clone()))
+                            .collect();
+                        Self::ConfigSet(params)
+                    }
+                    Some(cmd) if cmd.to_uppercase().as_str() == "RESETSTAT" => {
+                        Self::ConfigResetStat
+                    }
                     _ => Self::Unknown,
                 },
-                "KEYS" => Self::Keys,
+                "KEYS" => Self::Keys {
+                    pattern: Args::new(&args[1..], 1).expect()?.to_string(),
+                },
+                "SCAN" => scan_args(&args[1..])?,
+                "ASKING" => Self::Asking,
+                "READONLY" => Self::Readonly,
+                "READWRITE" => Self::Readwrite,
+                "MIGRATE" => migrate_args(&args[1..])?,
+                "EVAL" | "EVALSHA" | "FCALL" | "EVAL_RO" | "EVALSHA_RO" | "FCALL_RO" => {
+                    eval_args(&args)?
+                }
+                "SCRIPT" => script_args(&args[1..])
+                    .map(Self::Script)
+                    .unwrap_or(Self::Unknown),
+                "FUNCTION" => function_args(&args[1..])?
+                    .map(Self::Function)
+                    .unwrap_or(Self::Unknown),
+                "BGSAVE" => Self::Bgsave,
+                "BGREWRITEAOF" => Self::Bgrewriteaof,
                 "WAIT" => {
-                    let num_replicas = args
-                        .get(1)
-                        .ok_or(RedisError::LackOfArgs { need: 2, got: 0 })?
-                        .parse::<usize>()?;
-                    let exp = args
-                        .get(2)
-                        .ok_or(RedisError::LackOfArgs { need: 2, got: 1 })?
-                        .parse::<u64>()?;
-                    Self::Wait { num_replicas, exp }
+                    let mut args = Args::new(&args[1..], 2);
+                    Self::Wait {
+                        num_replicas: args.expect_int()?,
+                        exp: args.expect_int()?,
+                    }
                 }
                 "INFO" => Self::Info,
                 "REPLCONF" => {
-                    let key = args
-                        .get(1)
-                        .ok_or(RedisError::LackOfArgs { need: 2, got: 0 })?
-                        .to_string();
-                    let value = args
-                        .get(2)
-                        .ok_or(RedisError::LackOfArgs { need: 2, got: 1 })?
-                        .to_string();
-                    Self::ReplConf { key, value }
+                    let mut args = Args::new(&args[1..], 2);
+                    Self::ReplConf {
+                        key: args.expect()?.to_string(),
+                        value: args.expect()?.to_string(),
+                    }
                 }
                 "PSYNC" => Self::Psync,
+                "CLIENT" => match args.get(1) {
+                    Some(cmd) if cmd.to_uppercase().as_str() == "NO-EVICT" => {
+                        match args.get(2).map(|v| v.to_lowercase()).as_deref() {
+                            Some("on") => Self::ClientNoEvict(true),
+                            Some("off") => Self::ClientNoEvict(false),
+                            _ => Self::Unknown,
+                        }
+                    }
+                    Some(cmd) if cmd.to_uppercase().as_str() == "KILL" => {
+                        client_kill_args(&args[2..])?
+                    }
+                    _ => Self::Unknown,
+                },
+                "OBJECT" => match &args[1..] {
+                    [sub, key] if sub.eq_ignore_ascii_case("ENCODING") => Self::ObjectEncoding {
+                        key: key.to_string(),
+                    },
+                    _ => Self::Unknown,
+                },
+                "SHUTDOWN" => shutdown_args(&args[1..])?,
+                "DEBUG" => debug_args(&args[1..])
+                    .map(Self::Debug)
+                    .unwrap_or(Self::Unknown),
+                "ACL" => acl_args(&args[1..]).map(Self::Acl).unwrap_or(Self::Unknown),
+                "CLUSTER" => cluster_args(&args[1..])
+                    .map(Self::Cluster)
+                    .unwrap_or(Self::Unknown),
                 _ => Self::Unknown,
             }
         } else {
@@ -482,6 +2247,409 @@ impl Command {
         Ok(cmd)
     }
 
+    /// The lowercase name the command is allowed or denied by in ACL rules, none for
+    /// unknown commands.
+    pub fn name(&self) -> Option<&'static str> {
+        let name = match self {
+            Self::Ping => "ping",
+            Self::Echo(_) => "echo",
+            Self::Auth { .. } => "auth",
+            Self::Get { .. } => "get",
+            Self::Set { .. } => "set",
+            Self::Incr { .. } => "incr",
+            Self::GetRange { .. } => "getrange",
+            Self::SetRange { .. } => "setrange",
+            Self::Append { .. } => "append",
+            Self::Lpush { .. } => "lpush",
+            Self::Rpush { .. } => "rpush",
+            Self::Lpop { .. } => "lpop",
+            Self::Rpop { .. } => "rpop",
+            Self::Blpop { .. } => "blpop",
+            Self::Brpop { .. } => "brpop",
+            Self::Lrange { .. } => "lrange",
+            Self::Llen { .. } => "llen",
+            Self::Linsert { .. } => "linsert",
+            Self::Lset { .. } => "lset",
+            Self::Lrem { .. } => "lrem",
+            Self::Ltrim { .. } => "ltrim",
+            Self::Hset { .. } => "hset",
+            Self::Hincrby { .. } => "hincrby",
+            Self::HincrbyFloat { .. } => "hincrbyfloat",
+            Self::Hrandfield { .. } => "hrandfield",
+            Self::Hdel { .. } => "hdel",
+            Self::Sadd { .. } => "sadd",
+            Self::Srem { .. } => "srem",
+            Self::Smembers { .. } => "smembers",
+            Self::Sismember { .. } => "sismember",
+            Self::Scard { .. } => "scard",
+            Self::Hgetall { .. } => "hgetall",
+            Self::Hexpire { expiry, .. } => match expiry {
+                Expiry::Seconds(_) => "hexpire",
+                Expiry::Millis(_) => "hpexpire",
+                Expiry::UnixSeconds(_) => "hexpireat",
+                Expiry::UnixMillis(_) => "hpexpireat",
+            },
+            Self::Httl { millis: false, .. } => "httl",
+            Self::Httl { millis: true, .. } => "hpttl",
+            Self::Hpersist { .. } => "hpersist",
+            Self::Del { .. } => "del",
+            Self::Type { .. } => "type",
+            Self::Hello { .. } => "hello",
+            Self::Select { .. } => "select",
+            Self::Multi => "multi",
+            Self::Exec => "exec",
+            Self::Discard => "discard",
+            Self::Watch { .. } => "watch",
+            Self::Unwatch => "unwatch",
+            Self::Xadd { .. } => "xadd",
+            Self::Xrange { .. } => "xrange",
+            Self::Xread { .. } => "xread",
+            Self::Xdelex { .. } => "xdelex",
+            Self::Vadd { .. } => "vadd",
+            Self::Vsim { .. } => "vsim",
+            Self::Vcard { .. } => "vcard",
+            Self::JsonSet { .. } => "json.set",
+            Self::JsonGet { .. } => "json.get",
+            Self::JsonDel { .. } => "json.del",
+            Self::JsonNumIncrBy { .. } => "json.numincrby",
+            Self::BfReserve { .. } => "bf.reserve",
+            Self::BfAdd { multi: false, .. } => "bf.add",
+            Self::BfAdd { multi: true, .. } => "bf.madd",
+            Self::BfExists { multi: false, .. } => "bf.exists",
+            Self::BfExists { multi: true, .. } => "bf.mexists",
+            Self::CfReserve { .. } => "cf.reserve",
+            Self::CfAdd { nx: false, .. } => "cf.add",
+            Self::CfAdd { nx: true, .. } => "cf.addnx",
+            Self::CfExists { .. } => "cf.exists",
+            Self::CfCount { .. } => "cf.count",
+            Self::CfDel { .. } => "cf.del",
+            Self::TopkReserve { .. } => "topk.reserve",
+            Self::TopkIncrBy { add: true, .. } => "topk.add",
+            Self::TopkIncrBy { add: false, .. } => "topk.incrby",
+            Self::TopkQuery { .. } => "topk.query",
+            Self::TopkList { .. } => "topk.list",
+            Self::Xackdel { .. } => "xackdel",
+            Self::ConfigGet(_) | Self::ConfigSet(_) | Self::ConfigResetStat => "config",
+            Self::Keys { .. } => "keys",
+            Self::Scan { .. } => "scan",
+            Self::Bgsave => "bgsave",
+            Self::Bgrewriteaof => "bgrewriteaof",
+            Self::Wait { .. } => "wait",
+            Self::Info => "info",
+            Self::ReplConf { .. } => "replconf",
+            Self::Psync => "psync",
+            Self::Debug(_) => "debug",
+            Self::Cluster(_) => "cluster",
+            Self::Asking => "asking",
+            Self::Readonly => "readonly",
+            Self::Readwrite => "readwrite",
+            Self::Migrate { .. } => "migrate",
+            Self::ClientNoEvict(_) | Self::ClientKill { .. } => "client",
+            Self::Shutdown { .. } => "shutdown",
+            Self::Eval {
+                readonly: false, ..
+            } => "eval",
+            Self::Eval { readonly: true, .. } => "eval_ro",
+            Self::EvalSha {
+                readonly: false, ..
+            } => "evalsha",
+            Self::EvalSha { readonly: true, .. } => "evalsha_ro",
+            Self::Script(_) => "script",
+            Self::Function(_) => "function",
+            Self::Fcall {
+                readonly: false, ..
+            } => "fcall",
+            Self::Fcall { readonly: true, .. } => "fcall_ro",
+            Self::Acl(_) => "acl",
+            Self::ObjectEncoding { .. } => "object",
+            Self::Help(name) => name,
+            Self::Unknown => return None,
+        };
+        Some(name)
+    }
+
+    /// The keys the command reads or writes.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Self::Get { key }
+            | Self::Set { key, .. }
+            | Self::Incr { key }
+            | Self::GetRange { key, .. }
+            | Self::SetRange { key, .. }
+            | Self::Append { key, .. }
+            | Self::Lpush { key, .. }
+            | Self::Rpush { key, .. }
+            | Self::Lpop { key, .. }
+            | Self::Rpop { key, .. }
+            | Self::Lrange { key, .. }
+            | Self::Llen { key }
+            | Self::Linsert { key, .. }
+            | Self::Lset { key, .. }
+            | Self::Lrem { key, .. }
+            | Self::Ltrim { key, .. }
+            | Self::Hset { key, .. }
+            | Self::Hincrby { key, .. }
+            | Self::HincrbyFloat { key, .. }
+            | Self::Hrandfield { key, .. }
+            | Self::Hdel { key, .. }
+            | Self::Sadd { key, .. }
+            | Self::Srem { key, .. }
+            | Self::Smembers { key }
+            | Self::Sismember { key, .. }
+            | Self::Scard { key }
+            | Self::Hgetall { key }
+            | Self::Hexpire { key, .. }
+            | Self::Httl { key, .. }
+            | Self::Hpersist { key, .. }
+            | Self::ObjectEncoding { key }
+            | Self::Type { key }
+            | Self::Xadd { key, .. }
+            | Self::Xrange { key, .. }
+            | Self::Xdelex { key, .. }
+            | Self::Xackdel { key, .. }
+            | Self::Vadd { key, .. }
+            | Self::Vsim { key, .. }
+            | Self::Vcard { key }
+            | Self::JsonSet { key, .. }
+            | Self::JsonGet { key, .. }
+            | Self::JsonDel { key, .. }
+            | Self::JsonNumIncrBy { key, .. }
+            | Self::BfReserve { key, .. }
+            | Self::BfAdd { key, .. }
+            | Self::BfExists { key, .. }
+            | Self::CfReserve { key, .. }
+            | Self::CfAdd { key, .. }
+            | Self::CfExists { key, .. }
+            | Self::CfCount { key, .. }
+            | Self::CfDel { key, .. }
+            | Self::TopkReserve { key, .. }
+            | Self::TopkIncrBy { key, .. }
+            | Self::TopkQuery { key, .. }
+            | Self::TopkList { key, .. } => vec![key.as_str()],
+            Self::Del { keys }
+            | Self::Watch { keys }
+            | Self::Blpop { keys, .. }
+            | Self::Brpop { keys, .. }
+            | Self::Migrate { keys, .. }
+            | Self::Eval { keys, .. }
+            | Self::EvalSha { keys, .. }
+            | Self::Fcall { keys, .. } => keys.iter().map(String::as_str).collect(),
+            Self::Xread { stream, .. } => stream.iter().map(|(key, _)| key.as_str()).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Whether the command only reads the keyspace.
+    pub fn is_readonly(&self) -> bool {
+        matches!(
+            self,
+            Self::Get { .. }
+                | Self::GetRange { .. }
+                | Self::Lrange { .. }
+                | Self::Llen { .. }
+                | Self::Hrandfield { .. }
+                | Self::Hgetall { .. }
+                | Self::Httl { .. }
+                | Self::Smembers { .. }
+                | Self::Sismember { .. }
+                | Self::Scard { .. }
+                | Self::Type { .. }
+                | Self::ObjectEncoding { .. }
+                | Self::Xrange { .. }
+                | Self::Xread { .. }
+                | Self::Vsim { .. }
+                | Self::Vcard { .. }
+                | Self::JsonGet { .. }
+                | Self::BfExists { .. }
+                | Self::CfExists { .. }
+                | Self::CfCount { .. }
+                | Self::TopkQuery { .. }
+                | Self::TopkList { .. }
+                | Self::Keys { .. }
+                | Self::Scan { .. }
+                | Self::Eval { readonly: true, .. }
+                | Self::EvalSha { readonly: true, .. }
+                | Self::Fcall { readonly: true, .. }
+        )
+    }
+
+    /// Whether the command writes to the keyspace.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Self::Set { .. }
+                | Self::Incr { .. }
+                | Self::SetRange { .. }
+                | Self::Append { .. }
+                | Self::Lpush { .. }
+                | Self::Rpush { .. }
+                | Self::Lpop { .. }
+                | Self::Rpop { .. }
+                | Self::Blpop { .. }
+                | Self::Brpop { .. }
+                | Self::Linsert { .. }
+                | Self::Lset { .. }
+                | Self::Lrem { .. }
+                | Self::Ltrim { .. }
+                | Self::Hset { .. }
+                | Self::Hincrby { .. }
+                | Self::HincrbyFloat { .. }
+                | Self::Hdel { .. }
+                | Self::Hexpire { .. }
+                | Self::Sadd { .. }
+                | Self::Srem { .. }
+                | Self::Hpersist { .. }
+                | Self::Del { .. }
+                | Self::Xadd { .. }
+                | Self::Xdelex { .. }
+                | Self::Xackdel { .. }
+                | Self::Vadd { .. }
+                | Self::JsonSet { .. }
+                | Self::JsonDel { .. }
+                | Self::JsonNumIncrBy { .. }
+                | Self::BfReserve { .. }
+                | Self::BfAdd { .. }
+                | Self::CfReserve { .. }
+                | Self::CfAdd { .. }
+                | Self::CfDel { .. }
+                | Self::TopkReserve { .. }
+                | Self::TopkIncrBy { .. }
+                | Self::Migrate { .. }
+        )
+    }
+
+    /// Whether the command is refused over maxmemory, as it may grow the keyspace.
+    pub fn denies_oom(&self) -> bool {
+        matches!(
+            self,
+            Self::Set { .. }
+                | Self::Incr { .. }
+                | Self::SetRange { .. }
+                | Self::Append { .. }
+                | Self::Lpush { .. }
+                | Self::Rpush { .. }
+                | Self::Linsert { .. }
+                | Self::Lset { .. }
+                | Self::Hset { .. }
+                | Self::Hincrby { .. }
+                | Self::HincrbyFloat { .. }
+                | Self::Sadd { .. }
+                | Self::Xadd { .. }
+                | Self::Vadd { .. }
+                | Self::JsonSet { .. }
+                | Self::JsonNumIncrBy { .. }
+                | Self::BfReserve { .. }
+                | Self::BfAdd { .. }
+                | Self::CfReserve { .. }
+                | Self::CfAdd { .. }
+                | Self::TopkReserve { .. }
+        )
+    }
+
+    /// Whether the command runs a script which may write.
+    fn is_script_write(&self) -> bool {
+        matches!(
+            self,
+            Self::Eval {
+                readonly: false,
+                ..
+            } | Self::EvalSha {
+                readonly: false,
+                ..
+            } | Self::Fcall {
+                readonly: false,
+                ..
+            }
+        )
+    }
+
+    /// Whether the command runs while the dataset is loaded at startup. It's mostly
+    /// those inspecting or configuring the server.
+    pub fn allowed_while_loading(&self) -> bool {
+        matches!(
+            self,
+            Self::Auth { .. }
+                | Self::Hello { .. }
+                | Self::Select { .. }
+                | Self::Multi
+                | Self::Exec
+                | Self::Discard
+                | Self::Info
+                | Self::ConfigGet(_)
+                | Self::ConfigSet(_)
+                | Self::ConfigResetStat
+                | Self::ReplConf { .. }
+                | Self::ClientNoEvict(_)
+                | Self::ClientKill { .. }
+                | Self::Shutdown { .. }
+                | Self::Acl(_)
+                | Self::Debug(_)
+                | Self::Help(_)
+        )
+    }
+
+    /// Whether the command runs while a script runs too long: SCRIPT KILL, FUNCTION KILL
+    /// and SHUTDOWN NOSAVE.
+    pub fn allowed_while_busy(&self) -> bool {
+        matches!(
+            self,
+            Self::Script(ScriptCommand::Kill)
+                | Self::Function(FunctionCommand::Kill)
+                | Self::Shutdown { save: Some(false) }
+        )
+    }
+
+    /// Whether MULTI may queue the command. Any other is rejected as it's queued.
+    pub fn allowed_in_multi(&self) -> bool {
+        !matches!(self, Self::Psync)
+    }
+
+    /// Whether scripts may call the command.
+    pub fn allowed_in_script(&self) -> bool {
+        !matches!(
+            self,
+            Self::Auth { .. }
+                | Self::Hello { .. }
+                | Self::Multi
+                | Self::Exec
+                | Self::Discard
+                | Self::Watch { .. }
+                | Self::Unwatch
+                | Self::Eval { .. }
+                | Self::EvalSha { .. }
+                | Self::Script(_)
+                | Self::Function(_)
+                | Self::Fcall { .. }
+                | Self::ClientKill { .. }
+                | Self::Shutdown { .. }
+                | Self::ConfigResetStat
+                | Self::Bgrewriteaof
+                | Self::Psync
+                | Self::ReplConf { .. }
+                | Self::Wait { .. }
+                | Self::Acl(_)
+        )
+    }
+
+    /// Whether the command may run in a batch of pipelined commands. It must not block, nor
+    /// start or end a transaction, nor read the replication offset the batch settles at its end.
+    pub fn batchable(&self) -> bool {
+        !matches!(
+            self,
+            Self::Multi
+                | Self::Exec
+                | Self::Discard
+                | Self::Wait { .. }
+                | Self::Xread { block: Some(_), .. }
+                | Self::Blpop { .. }
+                | Self::Brpop { .. }
+                | Self::Debug(DebugCommand::Sleep(_))
+                | Self::ReplConf { .. }
+                | Self::Psync
+                | Self::Shutdown { .. }
+        )
+    }
+
     pub fn store_connection(&self) -> bool {
         matches!(self, Self::Psync)
     }
@@ -493,13 +2661,9 @@ impl Command {
             true
         }
     }
-
-    async fn need_queue(&self, store: &Arc<Store>, addr: SocketAddr) -> bool {
-        store.is_queuing(addr).await && !matches!(self, Self::Exec | Self::Discard)
-    }
 }
 
-fn command_args(message: Resp) -> Vec<String> {
+pub(crate) fn command_args(message: Resp) -> Vec<String> {
     match message {
         Resp::A(args) => args
             .into_iter()
@@ -562,6 +2726,757 @@ fn xread_args(values: &[String]) -> RedisResult<XreadArgs> {
     }
 }
 
+/// Parses `SET key value [PX milliseconds | PXAT unix-time-milliseconds]`.
+fn set_args(values: &[String]) -> RedisResult<Command> {
+    let mut args = Args::new(values, 2);
+    let key = args.expect_key()?;
+    let value = args.expect()?.to_string();
+    // Writes are propagated with absolute expiries.
+    let exp = match args.keyword(&["PX", "PXAT"]) {
+        Some("PX") => Some(Expiry::Millis(args.expect_int()?)),
+        Some(_) => Some(Expiry::UnixMillis(args.expect_int()?)),
+        None => None,
+    };
+    args.finish()?;
+    Ok(Command::Set { key, value, exp })
+}
+
+/// Parses `HEXPIRE key time [NX | XX | GT | LT] FIELDS numfields field [field ...]` and
+/// its variants, whose times differ in unit.
+fn hexpire_args(values: &[String], expiry: fn(u64) -> Expiry) -> RedisResult<Command> {
+    let mut args = Args::new(values, 5);
+    let key = args.expect_key()?;
+    let time = args.expect_int::<i64>()?;
+    let invalid = || anyhow::anyhow!("ERR invalid expire time, must be >= 0 and < 2^48");
+    let time = u64::try_from(time).map_err(|_| invalid())?;
+    let expiry = expiry(time);
+    // Redis keeps the expiries of fields in 48 bits.
+    if expiry.unix_millis(0) >= 1 << 48 {
+        return Err(invalid().into());
+    }
+    let cond = match args.keyword(&["NX", "XX", "GT", "LT"]) {
+        Some("NX") => Some(ExpireCond::Nx),
+        Some("XX") => Some(ExpireCond::Xx),
+        Some("GT") => Some(ExpireCond::Gt),
+        Some(_) => Some(ExpireCond::Lt),
+        None => None,
+    };
+    let fields = hash_fields(&mut args)?;
+    Ok(Command::Hexpire {
+        key,
+        expiry,
+        cond,
+        fields,
+    })
+}
+
+/// Parses the `FIELDS numfields field [field ...]` closing the commands on field
+/// expiries.
+fn hash_fields(args: &mut Args) -> RedisResult<Vec<String>> {
+    if !args.optional_token("FIELDS") {
+        return Err(anyhow::anyhow!(
+            "ERR Mandatory argument FIELDS is missing or not at the right position"
+        )
+        .into());
+    }
+    let num = args.expect_int::<i64>()?;
+    if num <= 0 {
+        return Err(anyhow::anyhow!("ERR Parameter `numFields` should be greater than 0").into());
+    }
+    let fields = args.rest();
+    if fields.len() as i64 != num {
+        return Err(anyhow::anyhow!(
+            "ERR The `numfields` parameter must match the number of arguments"
+        )
+        .into());
+    }
+    Ok(fields.to_vec())
+}
+
+/// Parses `XADD key id field value [field value ...]`.
+fn xadd_args(values: &[String]) -> RedisResult<Command> {
+    let mut args = Args::new(values, 4);
+    let key = args.expect_key()?;
+    let id = args.expect()?.to_string();
+    let fields = args.expect_many()?;
+    if fields.len() % 2 != 0 {
+        return Err(RedisError::LackOfArgs {
+            need: values.len() + 1,
+            got: values.len(),
+        });
+    }
+    Ok(Command::Xadd {
+        key,
+        id,
+        values: into_hashmap(fields),
+    })
+}
+
+/// The `VALUES num value [value ...]` a vector is given as.
+fn vector_args(args: &mut Args) -> RedisResult<Vec<f32>> {
+    match args.keyword(&["VALUES", "FP32"]) {
+        Some("VALUES") => {}
+        Some(_) => {
+            return Err(anyhow::anyhow!("ERR FP32 vectors are not supported, use VALUES").into())
+        }
+        None => return Err(RedisError::Syntax),
+    }
+    let invalid = || anyhow::anyhow!("ERR invalid vector specification");
+
+    let num = args
+        .expect_int::<usize>()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(invalid)?;
+    (0..num)
+        .map(|_| {
+            args.expect()
+                .ok()
+                .and_then(|v| v.parse::<f32>().ok())
+                .filter(|v| v.is_finite())
+        })
+        .collect::<Option<Vec<f32>>>()
+        .ok_or_else(|| invalid().into())
+}
+
+/// Parses `VADD key VALUES num value [value ...] element [options]`. The options tune
+/// how Redis quantizes vectors and builds its graph, neither of which applies to sets
+/// searched exhaustively.
+fn vadd_args(mut args: Args) -> RedisResult<Command> {
+    let key = args.expect_key()?;
+    let vector = vector_args(&mut args)?;
+    let member = args.expect()?.to_string();
+    while let Some(option) = args.option() {
+        match option.as_str() {
+            "CAS" | "NOQUANT" | "Q8" | "BIN" => {}
+            "EF" | "M" => {
+                args.expect_int::<u64>()
+                    .map_err(|_| RedisError::NotInteger)?;
+            }
+            "SETATTR" => {
+                return Err(anyhow::anyhow!("ERR SETATTR is not supported").into());
+            }
+            _ => return Err(RedisError::Syntax),
+        }
+    }
+    Ok(Command::Vadd {
+        key,
+        member,
+        vector,
+    })
+}
+
+/// The key and the items after it, of which there must be one, or with `many` any
+/// number but none.
+fn key_and_items(values: &[String], many: bool) -> RedisResult<(String, Vec<String>)> {
+    let mut args = Args::new(values, 2);
+    let key = args.expect_key()?;
+    let items = if many {
+        args.expect_many()?.to_vec()
+    } else {
+        let item = args.expect()?.to_string();
+        args.finish()?;
+        vec![item]
+    };
+    Ok((key, items))
+}
+
+fn bf_reserve_args(values: &[String]) -> RedisResult<Command> {
+    let mut args = Args::new(values, 3);
+    let key = args.expect_key()?;
+    let error_rate = args.expect()?;
+    let capacity = args.expect()?;
+    let error_rate = error_rate
+        .parse::<f64>()
+        .ok()
+        .filter(|rate| *rate > 0.0 && *rate < 1.0)
+        .ok_or(anyhow::anyhow!("ERR (0 < error rate range < 1)"))?;
+    let capacity = capacity
+        .parse::<u64>()
+        .ok()
+        .filter(|capacity| *capacity > 0)
+        .ok_or(anyhow::anyhow!("ERR (capacity should be larger than 0)"))?;
+
+    let mut expansion = Some(2);
+    while let Some(option) = args.option() {
+        match option.as_str() {
+            "NONSCALING" => expansion = None,
+            "EXPANSION" => {
+                let n = args
+                    .expect_int::<u32>()
+                    .ok()
+                    .filter(|n| (1..=32768).contains(n))
+                    .ok_or(anyhow::anyhow!(
+                        "ERR expansion should be between 1 and 32768"
+                    ))?;
+                expansion = expansion.map(|_| n);
+            }
+            _ => return Err(RedisError::Syntax),
+        }
+    }
+    Ok(Command::BfReserve {
+        key,
+        error_rate,
+        capacity,
+        expansion,
+    })
+}
+
+fn cf_reserve_args(values: &[String]) -> RedisResult<Command> {
+    let mut args = Args::new(values, 2);
+    let key = args.expect_key()?;
+    let capacity = args
+        .expect()?
+        .parse::<u64>()
+        .ok()
+        .filter(|capacity| *capacity > 0)
+        .ok_or(anyhow::anyhow!("ERR (capacity should be larger than 0)"))?;
+
+    let mut bucket_size = 2;
+    let mut max_iterations = 20;
+    let mut expansion = 1;
+    while let Some(option) = args.option() {
+        let value = args.expect().map_err(|_| RedisError::Syntax)?;
+        match option.as_str() {
+            "BUCKETSIZE" => {
+                bucket_size = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| (1..=255).contains(n))
+                    .ok_or(anyhow::anyhow!("ERR Bucket size must be between 1 and 255"))?;
+            }
+            "MAXITERATIONS" => {
+                max_iterations = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|n| (1..=65535).contains(n))
+                    .ok_or(anyhow::anyhow!(
+                        "ERR Max iterations must be between 1 and 65535"
+                    ))?;
+            }
+            "EXPANSION" => {
+                expansion = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|n| *n <= 32768)
+                    .ok_or(anyhow::anyhow!("ERR Expansion must be between 0 and 32768"))?;
+            }
+            _ => return Err(RedisError::Syntax),
+        }
+    }
+    Ok(Command::CfReserve {
+        key,
+        capacity,
+        bucket_size,
+        max_iterations,
+        expansion,
+    })
+}
+
+/// TOPK.RESERVE, where the width, depth and decay of the sketch come together or not at
+/// all.
+fn topk_reserve_args(args: &[String]) -> RedisResult<Command> {
+    let (key, k, width, depth, decay) = match args {
+        [key, k] => (key, k, "8", "7", "0.9"),
+        [key, k, width, depth, decay] => (key, k, width.as_str(), depth.as_str(), decay.as_str()),
+        [_, _, ..] => return Err(RedisError::Syntax),
+        _ => {
+            return Err(RedisError::LackOfArgs {
+                need: 2,
+                got: args.len(),
+            })
+        }
+    };
+    let positive = |value: &str, what: &str| {
+        value
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or(RedisError::from(anyhow::anyhow!(
+                "ERR TopK: invalid {what}"
+            )))
+    };
+    let decay = decay
+        .parse::<f64>()
+        .ok()
+        .filter(|decay| *decay > 0.0 && *decay <= 1.0)
+        .ok_or(anyhow::anyhow!(
+            "ERR TopK: invalid decay value. must be '<= 1' & '> 0'"
+        ))?;
+    Ok(Command::TopkReserve {
+        key: key.clone(),
+        k: positive(k, "k")?,
+        width: positive(width, "width")?,
+        depth: positive(depth, "depth")?,
+        decay,
+    })
+}
+
+/// The paths JSON.GET reads, after the formatting options, which are left out as
+/// replies are always written compactly. No path reads the root.
+fn json_get_paths(mut args: Args) -> RedisResult<Vec<JsonPath>> {
+    while args.keyword(&["INDENT", "NEWLINE", "SPACE"]).is_some() {
+        args.expect().map_err(|_| RedisError::Syntax)?;
+    }
+    let paths = args
+        .rest()
+        .iter()
+        .map(|path| JsonPath::parse(path))
+        .collect::<RedisResult<Vec<_>>>()?;
+    if paths.is_empty() {
+        return Ok(vec![JsonPath::parse(".")?]);
+    }
+    Ok(paths)
+}
+
+fn vsim_args(mut args: Args) -> RedisResult<Command> {
+    let key = args.expect_key()?;
+    let query = if args.optional_token("ELE") {
+        let member = args.expect().map_err(|_| RedisError::Syntax)?;
+        VectorQuery::Element(member.into())
+    } else {
+        VectorQuery::Values(vector_args(&mut args)?)
+    };
+
+    let mut count: usize = 10;
+    let mut withscores = false;
+    while let Some(option) = args.option() {
+        match option.as_str() {
+            "WITHSCORES" => withscores = true,
+            "COUNT" => {
+                count = args
+                    .expect_int::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or(anyhow::anyhow!("ERR COUNT must be a positive integer"))?;
+            }
+            "EF" | "FILTER-EF" => {
+                args.expect_int::<u64>()
+                    .map_err(|_| RedisError::NotInteger)?;
+            }
+            // Searches are always exhaustive and run on the calling task.
+            "TRUTH" | "NOTHREAD" => {}
+            "FILTER" => {
+                return Err(anyhow::anyhow!("ERR FILTER is not supported").into());
+            }
+            _ => return Err(RedisError::Syntax),
+        }
+    }
+
+    Ok(Command::Vsim {
+        key,
+        query,
+        count,
+        withscores,
+    })
+}
+
+/// The `[KEEPREF | DELREF | ACKED] IDS numids id [id ...]` arguments of XDELEX and
+/// XACKDEL.
+fn delete_args(mut args: Args) -> RedisResult<(DeletePolicy, Vec<String>)> {
+    let policy = match args.peek().and_then(DeletePolicy::parse) {
+        Some(policy) => {
+            args.expect()?;
+            policy
+        }
+        None => DeletePolicy::default(),
+    };
+    if !args.optional_token("IDS") {
+        return Err(RedisError::Syntax);
+    }
+
+    let numids = args
+        .expect_int::<usize>()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or(anyhow::anyhow!(
+            "ERR Number of IDs must be a positive integer"
+        ))?;
+    let ids = args.rest();
+    if ids.len() != numids {
+        return Err(anyhow::anyhow!(
+            "ERR The `numids` parameter must match the number of arguments"
+        )
+        .into());
+    }
+    Ok((policy, ids.to_vec()))
+}
+
+fn scan_args(values: &[String]) -> RedisResult<Command> {
+    let mut args = Args::new(values, 1);
+    let cursor = args
+        .expect()?
+        .parse::<u64>()
+        .map_err(|_| anyhow::anyhow!("ERR invalid cursor"))?;
+
+    let mut pattern: Option<String> = None;
+    let mut count: usize = 10;
+    let mut type_name: Option<String> = None;
+
+    while let Some(opt) = args.option() {
+        let value = args.expect().map_err(|_| RedisError::Syntax)?;
+        match opt.as_str() {
+            "MATCH" => pattern = Some(value.to_string()),
+            "COUNT" => {
+                count = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|v| *v > 0)
+                    .ok_or(RedisError::Syntax)?;
+            }
+            "TYPE" => type_name = Some(value.to_string()),
+            _ => return Err(RedisError::Syntax),
+        }
+    }
+
+    Ok(Command::Scan {
+        cursor,
+        pattern,
+        count,
+        type_name,
+    })
+}
+
+fn migrate_args(values: &[String]) -> RedisResult<Command> {
+    let mut args = Args::new(values, 5);
+    let host = args.expect()?.to_string();
+    let port = args.expect_int::<u16>().map_err(|_| RedisError::Syntax)?;
+    let key = args.expect_key()?;
+    args.expect()?;
+    let timeout = args.expect_int::<u64>().map_err(|_| RedisError::Syntax)?;
+
+    let flags = args.keyword_set(&["COPY", "REPLACE"]);
+    let copy = flags.contains(&"COPY");
+    let replace = flags.contains(&"REPLACE");
+    let keys = if args.optional_token("KEYS") {
+        if !key.is_empty() {
+            return Err(anyhow::anyhow!("ERR When using MIGRATE KEYS option, the key argument must be set to the empty string").into());
+        }
+        args.rest().to_vec()
+    } else {
+        args.finish()?;
+        vec![key]
+    };
+
+    Ok(Command::Migrate {
+        host,
+        port,
+        keys,
+        timeout,
+        copy,
+        replace,
+    })
+}
+
+/// Parses `EVAL script numkeys [key ...] [arg ...]` and its EVALSHA and FCALL counterparts,
+/// along with their `_RO` variants.
+fn eval_args(values: &[String]) -> RedisResult<Command> {
+    let body = values
+        .get(1)
+        .ok_or(RedisError::LackOfArgs { need: 2, got: 0 })?
+        .to_string();
+    let numkeys = values
+        .get(2)
+        .ok_or(RedisError::LackOfArgs { need: 2, got: 1 })?
+        .parse::<i64>()
+        .map_err(|_| RedisError::NotInteger)?;
+    let rest = &values[3..];
+    let numkeys = usize::try_from(numkeys)
+        .map_err(|_| anyhow::anyhow!("ERR Number of keys can't be negative"))?;
+    if numkeys > rest.len() {
+        return Err(
+            anyhow::anyhow!("ERR Number of keys can't be greater than number of args").into(),
+        );
+    }
+
+    let keys = rest[..numkeys].to_vec();
+    let args = rest[numkeys..].to_vec();
+    let name = values[0].to_uppercase();
+    let (name, readonly) = match name.strip_suffix("_RO") {
+        Some(name) => (name, true),
+        None => (name.as_str(), false),
+    };
+    let cmd = match name {
+        "EVALSHA" => Command::EvalSha {
+            sha: body.to_lowercase(),
+            keys,
+            args,
+            readonly,
+        },
+        "FCALL" => Command::Fcall {
+            function: body,
+            keys,
+            args,
+            readonly,
+        },
+        _ => Command::Eval {
+            script: body,
+            keys,
+            args,
+            readonly,
+        },
+    };
+    Ok(cmd)
+}
+
+/// Parses `CLIENT KILL addr` and `CLIENT KILL [ID id] [ADDR addr] [SKIPME yes|no]`.
+fn client_kill_args(values: &[String]) -> RedisResult<Command> {
+    if let [addr] = values {
+        return Ok(Command::ClientKill {
+            id: None,
+            addr: Some(addr.to_string()),
+            skipme: false,
+            legacy: true,
+        });
+    }
+
+    let mut id = None;
+    let mut addr = None;
+    let mut skipme = true;
+    let mut args = Args::new(values, 0);
+    while let Some(name) = args.option() {
+        let value = args.expect().map_err(|_| RedisError::Syntax)?;
+        match name.as_str() {
+            "ID" => {
+                let value = value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|v| *v > 0)
+                    .ok_or_else(|| anyhow::anyhow!("ERR client-id should be greater than 0"))?;
+                id = Some(value);
+            }
+            "ADDR" => addr = Some(value.to_string()),
+            "SKIPME" => match value.to_lowercase().as_str() {
+                "yes" => skipme = true,
+                "no" => skipme = false,
+                _ => return Err(RedisError::Syntax),
+            },
+            _ => return Err(RedisError::Syntax),
+        }
+    }
+    Ok(Command::ClientKill {
+        id,
+        addr,
+        skipme,
+        legacy: false,
+    })
+}
+
+/// Parses `SHUTDOWN [NOSAVE|SAVE] [NOW] [FORCE]`. NOW and FORCE change nothing here, as
+/// there are no replicas to wait for nor files beside the RDB one to write.
+fn shutdown_args(values: &[String]) -> RedisResult<Command> {
+    let mut args = Args::new(values, 0);
+    let flags = args.keyword_set(&["NOSAVE", "SAVE", "NOW", "FORCE"]);
+    args.finish()?;
+    let save = match (flags.contains(&"SAVE"), flags.contains(&"NOSAVE")) {
+        (true, true) => return Err(RedisError::Syntax),
+        (true, false) => Some(true),
+        (false, true) => Some(false),
+        (false, false) => None,
+    };
+    Ok(Command::Shutdown { save })
+}
+
+/// DEBUG SLEEP holds every key, so it's refused a longer sleep than this.
+const DEBUG_SLEEP_MAX: Duration = Duration::from_secs(60);
+
+fn debug_args(values: &[String]) -> Option<DebugCommand> {
+    let cmd = match values.first()?.to_uppercase().as_str() {
+        "SLEEP" => {
+            let secs = values.get(1)?.parse::<f64>().ok()?;
+            let duration = Duration::try_from_secs_f64(secs).ok()?;
+            DebugCommand::Sleep(Some(duration).filter(|d| *d <= DEBUG_SLEEP_MAX)?)
+        }
+        "OBJECT" => DebugCommand::Object {
+            key: values.get(1)?.to_string(),
+        },
+        "SET-ACTIVE-EXPIRE" => match values.get(1)?.as_str() {
+            "0" => DebugCommand::SetActiveExpire(false),
+            "1" => DebugCommand::SetActiveExpire(true),
+            _ => return None,
+        },
+        "CHANGE-REPL-ID" => DebugCommand::ChangeReplId,
+        "RELOAD-CONFIG" => DebugCommand::ReloadConfig,
+        "LISTPACK" => DebugCommand::Listpack {
+            key: values.get(1)?.to_string(),
+        },
+        "QUICKLIST-PACKED-THRESHOLD" => {
+            DebugCommand::QuicklistPackedThreshold(utils::parse_memory(values.get(1)?)?)
+        }
+        _ => return None,
+    };
+    Some(cmd)
+}
+
+fn script_args(values: &[String]) -> Option<ScriptCommand> {
+    let cmd = match values.first()?.to_uppercase().as_str() {
+        "LOAD" => ScriptCommand::Load(values.get(1)?.to_string()),
+        "EXISTS" if values.len() > 1 => {
+            ScriptCommand::Exists(values[1..].iter().map(|sha| sha.to_lowercase()).collect())
+        }
+        "FLUSH" => match values.get(1).map(|v| v.to_uppercase()).as_deref() {
+            None | Some("ASYNC") | Some("SYNC") => ScriptCommand::Flush,
+            _ => return None,
+        },
+        "KILL" => ScriptCommand::Kill,
+        _ => return None,
+    };
+    Some(cmd)
+}
+
+fn acl_args(values: &[String]) -> Option<AclCommand> {
+    let cmd = match values.first()?.to_uppercase().as_str() {
+        "SETUSER" => AclCommand::SetUser {
+            name: values.get(1)?.to_string(),
+            rules: values[2..].to_vec(),
+        },
+        "DELUSER" if values.len() > 1 => AclCommand::DelUser(values[1..].to_vec()),
+        "LIST" => AclCommand::List,
+        "USERS" => AclCommand::Users,
+        "WHOAMI" => AclCommand::WhoAmI,
+        "LOAD" => AclCommand::Load,
+        "SAVE" => AclCommand::Save,
+        _ => return None,
+    };
+    Some(cmd)
+}
+
+fn function_args(values: &[String]) -> RedisResult<Option<FunctionCommand>> {
+    let Some(sub) = values.first() else {
+        return Ok(None);
+    };
+    let cmd = match sub.to_uppercase().as_str() {
+        "LOAD" => match values[1..] {
+            [ref code] => FunctionCommand::Load {
+                code: code.to_string(),
+                replace: false,
+            },
+            [ref opt, ref code] if opt.eq_ignore_ascii_case("REPLACE") => FunctionCommand::Load {
+                code: code.to_string(),
+                replace: true,
+            },
+            _ => return Ok(None),
+        },
+        "LIST" => {
+            let mut pattern = None;
+            let mut with_code = false;
+            let mut opts = values[1..].iter();
+            while let Some(opt) = opts.next() {
+                match opt.to_uppercase().as_str() {
+                    "WITHCODE" => with_code = true,
+                    "LIBRARYNAME" => {
+                        let value = opts.next().ok_or_else(|| {
+                            anyhow::anyhow!("ERR library name argument was not given")
+                        })?;
+                        pattern = Some(value.to_string());
+                    }
+                    _ => return Err(anyhow::anyhow!("ERR Unknown argument {opt}").into()),
+                }
+            }
+            FunctionCommand::List { pattern, with_code }
+        }
+        "DELETE" => match values.get(1) {
+            Some(name) => FunctionCommand::Delete(name.to_string()),
+            None => return Ok(None),
+        },
+        "FLUSH" => match values.get(1).map(|v| v.to_uppercase()).as_deref() {
+            None | Some("ASYNC") | Some("SYNC") => FunctionCommand::Flush,
+            _ => return Ok(None),
+        },
+        "DUMP" => FunctionCommand::Dump,
+        "RESTORE" => {
+            let Some(payload) = values.get(1) else {
+                return Ok(None);
+            };
+            let policy = match values.get(2).map(|v| v.to_uppercase()).as_deref() {
+                None | Some("APPEND") => RestorePolicy::Append,
+                Some("REPLACE") => RestorePolicy::Replace,
+                Some("FLUSH") => RestorePolicy::Flush,
+                Some(_) => return Err(anyhow::anyhow!("ERR Wrong restore policy given").into()),
+            };
+            FunctionCommand::Restore {
+                payload: payload.to_string(),
+                policy,
+            }
+        }
+        "KILL" => FunctionCommand::Kill,
+        _ => return Ok(None),
+    };
+    Ok(Some(cmd))
+}
+
+/// A library as FUNCTION LIST replies it.
+fn library_resp(library: &Library, with_code: bool) -> Resp {
+    let bulk = |s: &str| Resp::BS(Some(s.to_string()));
+    let functions = library
+        .functions
+        .iter()
+        .map(|f| {
+            Resp::A(vec![
+                bulk("name"),
+                bulk(&f.name),
+                bulk("description"),
+                Resp::BS(f.description.clone()),
+                bulk("flags"),
+                Resp::A(f.flags.iter().map(|flag| Resp::SS(flag.clone())).collect()),
+            ])
+        })
+        .collect();
+
+    let mut resp = vec![
+        bulk("library_name"),
+        bulk(&library.name),
+        bulk("engine"),
+        bulk("LUA"),
+        bulk("functions"),
+        Resp::A(functions),
+    ];
+    if with_code {
+        resp.extend([bulk("library_code"), bulk(&library.code)]);
+    }
+    Resp::A(resp)
+}
+
+fn cluster_args(values: &[String]) -> Option<ClusterCommand> {
+    let cmd = match values.first()?.to_uppercase().as_str() {
+        "INFO" => ClusterCommand::Info,
+        "MYID" => ClusterCommand::Myid,
+        "SLOTS" => ClusterCommand::Slots,
+        "SHARDS" => ClusterCommand::Shards,
+        "NODES" => ClusterCommand::Nodes,
+        "MEET" => ClusterCommand::Meet {
+            ip: values.get(1)?.to_string(),
+            port: values.get(2)?.parse().ok()?,
+            bus_port: values.get(3).map(|v| v.parse()).transpose().ok()?,
+        },
+        "SETSLOT" => ClusterCommand::SetSlot {
+            slot: values.get(1)?.parse().ok()?,
+            action: match values.get(2)?.to_uppercase().as_str() {
+                "IMPORTING" => SetSlot::Importing(values.get(3)?.to_string()),
+                "MIGRATING" => SetSlot::Migrating(values.get(3)?.to_string()),
+                "NODE" => SetSlot::Node(values.get(3)?.to_string()),
+                "STABLE" => SetSlot::Stable,
+                _ => return None,
+            },
+        },
+        "KEYSLOT" => ClusterCommand::KeySlot(values.get(1)?.to_string()),
+        "COUNTKEYSINSLOT" => ClusterCommand::CountKeysInSlot(values.get(1)?.parse().ok()?),
+        "GETKEYSINSLOT" => ClusterCommand::GetKeysInSlot {
+            slot: values.get(1)?.parse().ok()?,
+            count: values.get(2)?.parse().ok()?,
+        },
+        "FAILOVER" => ClusterCommand::Failover {
+            force: match values.get(1).map(|v| v.to_uppercase()).as_deref() {
+                None => false,
+                Some("FORCE" | "TAKEOVER") => true,
+                Some(_) => return None,
+            },
+        },
+        _ => return None,
+    };
+    Some(cmd)
+}
+
 fn arg_starts_at(values: &[String], arg: &str) -> Option<usize> {
     values.iter().position(|v| v.as_str() == arg).map(|v| v + 1)
 }
@@ -575,11 +3490,70 @@ fn zip_pairs(values: &[String]) -> Vec<(String, String)> {
         .collect()
 }
 
+/// Pops an element off the first of the lists having any as BLPOP and BRPOP do, replying
+/// with its key and the element. Without any, the client blocks until one is pushed.
+async fn bpop(
+    store: Arc<Store>,
+    ctx: &mut Context,
+    keys: Vec<String>,
+    timeout: Option<Duration>,
+    head: bool,
+) -> RedisResult<Option<Resp>> {
+    if let Some(resp) = pop_first(&store, &keys, head).await? {
+        return Ok(Some(resp));
+    }
+    let Some(sender) = ctx.sender.take() else {
+        return Ok(Some(Resp::NullArray));
+    };
+    let client = Arc::clone(&ctx.client);
+    tokio::spawn(async move {
+        let mut blocked = store.block_client(&client, &keys, KeyEvent::ListPush, timeout);
+        // Popping once more after blocking catches what was pushed in between.
+        let resp = loop {
+            match pop_first(&store, &keys, head).await {
+                Ok(Some(resp)) => break resp,
+                Ok(None) => {}
+                Err(err) => break Resp::from(err),
+            }
+            match blocked.wait().await {
+                Unblocked::Ready(_) => {}
+                Unblocked::TimedOut => break Resp::NullArray,
+                Unblocked::Disconnected => return,
+            }
+        };
+        if sender.send(resp.into()).is_err() {
+            eprintln!("Oneshot receiver dropped before sending");
+        }
+    });
+    Ok(None)
+}
+
+async fn pop_first(store: &Store, keys: &[String], head: bool) -> RedisResult<Option<Resp>> {
+    for key in keys {
+        if let Some(element) = store.pop(key, 1, head).await?.and_then(|mut p| p.pop()) {
+            return Ok(Some(Resp::from(vec![key.clone(), element])));
+        }
+    }
+    Ok(None)
+}
+
+/// Pops off the list as LPOP and RPOP do, which reply with the element alone when no
+/// count is given.
+async fn pop(store: &Store, key: &str, count: Option<usize>, head: bool) -> RedisResult<Resp> {
+    let popped = store.pop(key, count.unwrap_or(1), head).await?;
+    let resp = match (popped, count) {
+        (Some(popped), Some(_)) => Resp::A(popped.into_iter().map(|e| Resp::BS(Some(e))).collect()),
+        (None, Some(_)) => Resp::NullArray,
+        (popped, None) => Resp::BS(popped.and_then(|mut popped| popped.pop())),
+    };
+    Ok(resp)
+}
+
 async fn read_stream(
     store: Arc<Store>,
     pairs: Vec<(String, String)>,
-) -> Option<Vec<(String, StreamEntry)>> {
-    let mut responses: Vec<(String, StreamEntry)> = vec![];
+) -> Option<Vec<(String, Arc<StreamEntry>)>> {
+    let mut responses: Vec<(String, Arc<StreamEntry>)> = vec![];
     for (key, start) in pairs {
         match store.find_stream(&key, start).await {
             Ok(Some(entry)) => {
@@ -614,6 +3588,31 @@ mod tests {
         assert_eq!(cmd, expected);
     }
 
+    #[test]
+    fn it_parses_auth_command() {
+        let args = vec!["AUTH".to_string(), "secret".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Auth {
+            username: None,
+            password: "secret".into(),
+        };
+        assert_eq!(cmd, expected);
+
+        let args = vec![
+            "auth".to_string(),
+            "default".to_string(),
+            "secret".to_string(),
+        ];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Auth {
+            username: Some("default".into()),
+            password: "secret".into(),
+        };
+        assert_eq!(cmd, expected);
+
+        assert!(Command::from_args(vec!["AUTH".to_string()]).is_err());
+    }
+
     #[test]
     fn it_parses_get_command() {
         let args = vec!["GET".to_string(), "foo".to_string()];
@@ -641,30 +3640,90 @@ mod tests {
             "100".to_string(),
         ];
         let cmd = Command::from_args(args).unwrap();
-        let expected = Command::Set {
-            key: "foo".into(),
-            value: "bar".into(),
-            exp: Some(100),
+        let expected = Command::Set {
+            key: "foo".into(),
+            value: "bar".into(),
+            exp: Some(Expiry::Millis(100)),
+        };
+        assert_eq!(cmd, expected);
+    }
+
+    #[test]
+    fn it_parses_config_get_command() {
+        let args = vec!["CONFIG".to_string(), "GET".to_string(), "foo".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::ConfigGet("foo".into());
+        assert_eq!(cmd, expected);
+
+        let args = vec!["config".to_string(), "resetstat".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(cmd, Command::ConfigResetStat);
+    }
+
+    #[test]
+    fn it_parses_config_set_command() {
+        let args: Vec<String> = ["CONFIG", "set", "trace-proto", "yes", "hz", "20"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::ConfigSet(vec![
+            ("trace-proto".into(), "yes".into()),
+            ("hz".into(), "20".into()),
+        ]);
+        assert_eq!(cmd, expected);
+
+        let args: Vec<String> = ["CONFIG", "SET", "trace-proto"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        assert!(Command::from_args(args).is_err());
+    }
+
+    #[test]
+    fn it_parses_keys_command() {
+        let args = vec!["KEYS".to_string(), "*".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Keys {
+            pattern: "*".into(),
         };
         assert_eq!(cmd, expected);
     }
 
     #[test]
-    fn it_parses_config_get_command() {
-        let args = vec!["CONFIG".to_string(), "GET".to_string(), "foo".to_string()];
+    fn it_parses_scan_command() {
+        let args: Vec<String> = vec!["SCAN", "0", "match", "key:*", "COUNT", "100"]
+            .into_iter()
+            .map(String::from)
+            .collect();
         let cmd = Command::from_args(args).unwrap();
-        let expected = Command::ConfigGet("foo".into());
+        let expected = Command::Scan {
+            cursor: 0,
+            pattern: Some("key:*".into()),
+            count: 100,
+            type_name: None,
+        };
         assert_eq!(cmd, expected);
+
+        let args = vec!["SCAN".to_string(), "0".to_string(), "COUNT".to_string()];
+        assert!(Command::from_args(args).is_err());
     }
 
     #[test]
-    fn it_parses_keys_command() {
-        let args = vec!["KEYS".to_string(), "*".to_string()];
+    fn it_parses_bgsave_command() {
+        let args = vec!["BGSAVE".to_string()];
         let cmd = Command::from_args(args).unwrap();
-        let expected = Command::Keys;
+        let expected = Command::Bgsave;
         assert_eq!(cmd, expected);
     }
 
+    #[test]
+    fn it_parses_bgrewriteaof_command() {
+        let args = vec!["BGREWRITEAOF".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(cmd, Command::Bgrewriteaof);
+    }
+
     #[test]
     fn it_parses_info_command() {
         let args = vec!["INFO".to_string()];
@@ -707,6 +3766,16 @@ mod tests {
         assert_eq!(cmd, expected);
     }
 
+    #[test]
+    fn it_parses_del_command() {
+        let args = vec!["DEL".to_string(), "foo".to_string(), "bar".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Del {
+            keys: vec!["foo".into(), "bar".into()],
+        };
+        assert_eq!(cmd, expected);
+    }
+
     #[test]
     fn it_parses_type_command() {
         let args = vec!["TYPE".to_string(), "some_key".to_string()];
@@ -735,6 +3804,43 @@ mod tests {
         assert_eq!(cmd, expected);
     }
 
+    #[test]
+    fn it_parses_the_options_of_set_and_xadd() {
+        let values = |args: &[&str]| args.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        assert!(matches!(
+            set_args(&values(&["k", "v", "PX", "100"])),
+            Ok(Command::Set {
+                exp: Some(Expiry::Millis(100)),
+                ..
+            })
+        ));
+        assert!(matches!(
+            set_args(&values(&["k", "v", "pxat", "1"])),
+            Ok(Command::Set {
+                exp: Some(Expiry::UnixMillis(1)),
+                ..
+            })
+        ));
+        assert!(matches!(
+            set_args(&values(&["k", "v", "PX", "soon"])),
+            Err(RedisError::NotInteger)
+        ));
+        assert!(matches!(
+            set_args(&values(&["k", "v", "PX", "1", "NX"])),
+            Err(RedisError::Syntax)
+        ));
+        assert!(matches!(
+            set_args(&values(&["k"])),
+            Err(RedisError::LackOfArgs { need: 2, got: 1 })
+        ));
+
+        assert!(matches!(
+            xadd_args(&values(&["s", "*"])),
+            Err(RedisError::LackOfArgs { need: 4, got: 2 })
+        ));
+        assert!(xadd_args(&values(&["s", "*", "a", "1", "b"])).is_err());
+    }
+
     #[test]
     fn it_parses_xrange_command() {
         let args = vec![
@@ -752,6 +3858,197 @@ mod tests {
         assert_eq!(cmd, expected);
     }
 
+    #[test]
+    fn it_parses_vector_set_commands() {
+        let cmd =
+            |args: &[&str]| Command::from_args(args.iter().map(|arg| arg.to_string()).collect());
+        assert_eq!(
+            cmd(&["VADD", "v", "VALUES", "2", "0.5", "-1", "a", "Q8", "EF", "200"]).unwrap(),
+            Command::Vadd {
+                key: "v".into(),
+                member: "a".into(),
+                vector: vec![0.5, -1.0],
+            }
+        );
+        assert!(cmd(&["VADD", "v", "VALUES", "2", "0.5", "a"]).is_err());
+        assert!(cmd(&["VADD", "v", "VALUES", "1", "nan", "a"]).is_err());
+        assert!(cmd(&["VADD", "v", "VALUES", "1", "1", "a", "SETATTR", "{}"]).is_err());
+
+        assert_eq!(
+            cmd(&["VSIM", "v", "ELE", "a", "withscores", "COUNT", "3"]).unwrap(),
+            Command::Vsim {
+                key: "v".into(),
+                query: VectorQuery::Element("a".into()),
+                count: 3,
+                withscores: true,
+            }
+        );
+        assert_eq!(
+            cmd(&["VSIM", "v", "values", "1", "2"]).unwrap(),
+            Command::Vsim {
+                key: "v".into(),
+                query: VectorQuery::Values(vec![2.0]),
+                count: 10,
+                withscores: false,
+            }
+        );
+        assert!(cmd(&["VSIM", "v", "ELE", "a", "COUNT", "0"]).is_err());
+        assert_eq!(
+            cmd(&["VCARD", "v"]).unwrap(),
+            Command::Vcard { key: "v".into() }
+        );
+    }
+
+    #[test]
+    fn it_parses_json_commands() {
+        let cmd =
+            |args: &[&str]| Command::from_args(args.iter().map(|arg| arg.to_string()).collect());
+        let path = |path: &str| JsonPath::parse(path).unwrap();
+        assert_eq!(
+            cmd(&["JSON.SET", "doc", "$.a", "[1,2]", "nx"]).unwrap(),
+            Command::JsonSet {
+                key: "doc".into(),
+                path: path("$.a"),
+                value: Json::Array(vec![Json::Int(1), Json::Int(2)]),
+                cond: Some(SetCond::Nx),
+            }
+        );
+        assert!(cmd(&["JSON.SET", "doc", "$", "{"]).is_err());
+        assert!(cmd(&["JSON.SET", "doc", "$", "1", "GT"]).is_err());
+        assert_eq!(
+            cmd(&["json.get", "doc", "INDENT", "  ", "$.a", ".b"]).unwrap(),
+            Command::JsonGet {
+                key: "doc".into(),
+                paths: vec![path("$.a"), path(".b")],
+            }
+        );
+        assert_eq!(
+            cmd(&["JSON.GET", "doc"]).unwrap(),
+            Command::JsonGet {
+                key: "doc".into(),
+                paths: vec![path(".")],
+            }
+        );
+        assert_eq!(
+            cmd(&["JSON.DEL", "doc"]).unwrap(),
+            Command::JsonDel {
+                key: "doc".into(),
+                path: path("$"),
+            }
+        );
+        assert!(cmd(&["JSON.NUMINCRBY", "doc", "$.a", "\"1\""]).is_err());
+        assert_eq!(
+            cmd(&["JSON.NUMINCRBY", "doc", "$.a", "1.5"]).unwrap(),
+            Command::JsonNumIncrBy {
+                key: "doc".into(),
+                path: path("$.a"),
+                value: Json::Float(1.5),
+            }
+        );
+    }
+
+    #[test]
+    fn it_parses_sketch_commands() {
+        let cmd =
+            |args: &[&str]| Command::from_args(args.iter().map(|arg| arg.to_string()).collect());
+        assert_eq!(
+            cmd(&["BF.RESERVE", "bf", "0.001", "1000", "nonscaling"]).unwrap(),
+            Command::BfReserve {
+                key: "bf".into(),
+                error_rate: 0.001,
+                capacity: 1000,
+                expansion: None,
+            }
+        );
+        assert!(cmd(&["BF.RESERVE", "bf", "1", "1000"]).is_err());
+        assert!(cmd(&["BF.RESERVE", "bf", "0.1", "0"]).is_err());
+        assert!(cmd(&["BF.RESERVE", "bf", "0.1", "10", "EXPANSION", "65536"]).is_err());
+        assert_eq!(
+            cmd(&["bf.madd", "bf", "a", "b"]).unwrap(),
+            Command::BfAdd {
+                key: "bf".into(),
+                items: vec!["a".into(), "b".into()],
+                multi: true,
+            }
+        );
+        assert!(cmd(&["BF.EXISTS", "bf", "a", "b"]).is_err());
+
+        assert_eq!(
+            cmd(&["CF.RESERVE", "cf", "100", "BUCKETSIZE", "4"]).unwrap(),
+            Command::CfReserve {
+                key: "cf".into(),
+                capacity: 100,
+                bucket_size: 4,
+                max_iterations: 20,
+                expansion: 1,
+            }
+        );
+        assert!(cmd(&["CF.RESERVE", "cf", "100", "BUCKETSIZE", "0"]).is_err());
+        assert_eq!(
+            cmd(&["CF.ADDNX", "cf", "a"]).unwrap(),
+            Command::CfAdd {
+                key: "cf".into(),
+                item: "a".into(),
+                nx: true,
+            }
+        );
+
+        assert_eq!(
+            cmd(&["TOPK.RESERVE", "tk", "3"]).unwrap(),
+            Command::TopkReserve {
+                key: "tk".into(),
+                k: 3,
+                width: 8,
+                depth: 7,
+                decay: 0.9,
+            }
+        );
+        assert!(cmd(&["TOPK.RESERVE", "tk", "3", "8"]).is_err());
+        assert_eq!(
+            cmd(&["TOPK.INCRBY", "tk", "a", "5"]).unwrap(),
+            Command::TopkIncrBy {
+                key: "tk".into(),
+                items: vec![("a".into(), 5)],
+                add: false,
+            }
+        );
+        assert!(cmd(&["TOPK.INCRBY", "tk", "a", "0"]).is_err());
+        assert!(cmd(&["TOPK.INCRBY", "tk", "a"]).is_err());
+        assert_eq!(
+            cmd(&["TOPK.LIST", "tk", "WITHCOUNT"]).unwrap(),
+            Command::TopkList {
+                key: "tk".into(),
+                withcount: true,
+            }
+        );
+    }
+
+    #[test]
+    fn it_parses_stream_deletions() {
+        let cmd =
+            |args: &[&str]| Command::from_args(args.iter().map(|arg| arg.to_string()).collect());
+        assert_eq!(
+            cmd(&["XDELEX", "s", "IDS", "2", "1-1", "2"]).unwrap(),
+            Command::Xdelex {
+                key: "s".into(),
+                policy: DeletePolicy::KeepRef,
+                ids: vec!["1-1".into(), "2".into()],
+            }
+        );
+        assert_eq!(
+            cmd(&["XACKDEL", "s", "g", "acked", "ids", "1", "1-1"]).unwrap(),
+            Command::Xackdel {
+                key: "s".into(),
+                group: "g".into(),
+                policy: DeletePolicy::Acked,
+                ids: vec!["1-1".into()],
+            }
+        );
+        assert!(cmd(&["XDELEX", "s", "IDS", "2", "1-1"]).is_err());
+        assert!(cmd(&["XDELEX", "s", "IDS", "0"]).is_err());
+        assert!(cmd(&["XDELEX", "s", "DELREF", "1-1"]).is_err());
+    }
+
     #[test]
     fn it_parses_xread_command() {
         let args = vec![
@@ -826,6 +4123,248 @@ mod tests {
         assert_eq!(cmd, expected);
     }
 
+    #[test]
+    fn it_parses_range_commands() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            Command::from_args(args)
+        };
+        assert_eq!(
+            parse(&["GETRANGE", "key", "0", "-1"]).unwrap(),
+            Command::GetRange {
+                key: "key".into(),
+                start: 0,
+                end: -1,
+            }
+        );
+        assert_eq!(
+            parse(&["setrange", "key", "6", "redis"]).unwrap(),
+            Command::SetRange {
+                key: "key".into(),
+                offset: 6,
+                value: "redis".into(),
+            }
+        );
+        assert!(matches!(
+            parse(&["GETRANGE", "key", "a", "1"]),
+            Err(RedisError::NotInteger)
+        ));
+        assert!(parse(&["SETRANGE", "key", "1"]).is_err());
+        assert_eq!(
+            parse(&["APPEND", "key", "more"]).unwrap(),
+            Command::Append {
+                key: "key".into(),
+                value: "more".into(),
+            }
+        );
+        assert!(parse(&["APPEND", "key"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_list_commands() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            Command::from_args(args)
+        };
+        assert_eq!(
+            parse(&["RPUSH", "list", "a", "b"]).unwrap(),
+            Command::Rpush {
+                key: "list".into(),
+                elements: vec!["a".into(), "b".into()],
+            }
+        );
+        assert_eq!(
+            parse(&["lpush", "list", "a"]).unwrap(),
+            Command::Lpush {
+                key: "list".into(),
+                elements: vec!["a".into()],
+            }
+        );
+        assert_eq!(
+            parse(&["LRANGE", "list", "-2", "-1"]).unwrap(),
+            Command::Lrange {
+                key: "list".into(),
+                start: -2,
+                stop: -1,
+            }
+        );
+        assert_eq!(
+            parse(&["LLEN", "list"]).unwrap(),
+            Command::Llen { key: "list".into() }
+        );
+        assert_eq!(
+            parse(&["LPOP", "list"]).unwrap(),
+            Command::Lpop {
+                key: "list".into(),
+                count: None,
+            }
+        );
+        assert_eq!(
+            parse(&["rpop", "list", "2"]).unwrap(),
+            Command::Rpop {
+                key: "list".into(),
+                count: Some(2),
+            }
+        );
+        assert_eq!(
+            parse(&["BLPOP", "a", "b", "1.5"]).unwrap(),
+            Command::Blpop {
+                keys: vec!["a".into(), "b".into()],
+                timeout: Some(Duration::from_millis(1500)),
+            }
+        );
+        assert_eq!(
+            parse(&["brpop", "a", "0"]).unwrap(),
+            Command::Brpop {
+                keys: vec!["a".into()],
+                timeout: None,
+            }
+        );
+        assert_eq!(
+            parse(&["LINSERT", "list", "after", "b", "c"]).unwrap(),
+            Command::Linsert {
+                key: "list".into(),
+                before: false,
+                pivot: "b".into(),
+                element: "c".into(),
+            }
+        );
+        assert_eq!(
+            parse(&["LREM", "list", "-2", "a"]).unwrap(),
+            Command::Lrem {
+                key: "list".into(),
+                count: -2,
+                element: "a".into(),
+            }
+        );
+        assert!(matches!(
+            parse(&["LINSERT", "list", "AROUND", "b", "c"]),
+            Err(RedisError::Syntax)
+        ));
+        assert!(parse(&["LSET", "list", "x", "a"]).is_err());
+        assert!(parse(&["LTRIM", "list", "0"]).is_err());
+        assert!(parse(&["BLPOP", "a", "-1"]).is_err());
+        assert!(parse(&["BLPOP", "0"]).is_err());
+        assert!(parse(&["LPOP", "list", "-1"]).is_err());
+        assert!(parse(&["LPOP", "list", "1", "2"]).is_err());
+        assert!(parse(&["RPUSH", "list"]).is_err());
+        assert!(matches!(
+            parse(&["LRANGE", "list", "0", "x"]),
+            Err(RedisError::NotInteger)
+        ));
+        assert!(parse(&["LLEN", "a", "b"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_hash_commands() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            Command::from_args(args)
+        };
+        assert_eq!(
+            parse(&["hset", "h", "a", "1", "b", "2"]).unwrap(),
+            Command::Hset {
+                key: "h".into(),
+                pairs: vec![("a".into(), "1".into()), ("b".into(), "2".into())],
+            }
+        );
+        assert!(matches!(
+            parse(&["HSET", "h", "a", "1", "b"]),
+            Err(RedisError::LackOfArgs { need: 5, got: 4 })
+        ));
+        assert!(parse(&["HSET", "h", "a"]).is_err());
+        assert_eq!(
+            parse(&["HINCRBY", "h", "f", "-3"]).unwrap(),
+            Command::Hincrby {
+                key: "h".into(),
+                field: "f".into(),
+                increment: -3,
+            }
+        );
+        assert_eq!(
+            parse(&["hincrbyfloat", "h", "f", "0.5"]).unwrap(),
+            Command::HincrbyFloat {
+                key: "h".into(),
+                field: "f".into(),
+                increment: 0.5,
+            }
+        );
+        assert_eq!(
+            parse(&["HRANDFIELD", "h", "-2", "withvalues"]).unwrap(),
+            Command::Hrandfield {
+                key: "h".into(),
+                count: Some(-2),
+                withvalues: true,
+            }
+        );
+        assert!(matches!(
+            parse(&["HINCRBY", "h", "f", "1.5"]),
+            Err(RedisError::NotInteger)
+        ));
+        assert!(parse(&["HINCRBYFLOAT", "h", "f", "inf"]).is_err());
+        assert!(parse(&["HRANDFIELD", "h", "WITHVALUES"]).is_err());
+        assert!(parse(&["HRANDFIELD", "h", "1", "WITHVALUES", "x"]).is_err());
+
+        assert_eq!(
+            parse(&["HPEXPIRE", "h", "1500", "gt", "FIELDS", "2", "a", "b"]).unwrap(),
+            Command::Hexpire {
+                key: "h".into(),
+                expiry: Expiry::Millis(1500),
+                cond: Some(ExpireCond::Gt),
+                fields: vec!["a".into(), "b".into()],
+            }
+        );
+        assert_eq!(
+            parse(&["HPTTL", "h", "FIELDS", "1", "a"]).unwrap(),
+            Command::Httl {
+                key: "h".into(),
+                fields: vec!["a".into()],
+                millis: true,
+            }
+        );
+        assert!(parse(&["HEXPIRE", "h", "-1", "FIELDS", "1", "a"]).is_err());
+        assert!(parse(&["HEXPIREAT", "h", "281474976710656", "FIELDS", "1", "a"]).is_err());
+        assert!(parse(&["HEXPIRE", "h", "10", "a"]).is_err());
+        assert!(parse(&["HPERSIST", "h", "FIELDS", "0"]).is_err());
+        assert!(parse(&["HPERSIST", "h", "FIELDS", "2", "a"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_set_commands() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            Command::from_args(args)
+        };
+        assert_eq!(
+            parse(&["SADD", "s", "a", "b"]).unwrap(),
+            Command::Sadd {
+                key: "s".into(),
+                members: vec!["a".into(), "b".into()],
+            }
+        );
+        assert_eq!(
+            parse(&["srem", "s", "a"]).unwrap(),
+            Command::Srem {
+                key: "s".into(),
+                members: vec!["a".into()],
+            }
+        );
+        assert_eq!(
+            parse(&["SISMEMBER", "s", "a"]).unwrap(),
+            Command::Sismember {
+                key: "s".into(),
+                member: "a".into(),
+            }
+        );
+        assert_eq!(
+            parse(&["SCARD", "s"]).unwrap(),
+            Command::Scard { key: "s".into() }
+        );
+        assert!(parse(&["SADD", "s"]).is_err());
+        assert!(parse(&["SISMEMBER", "s", "a", "b"]).is_err());
+        assert!(parse(&["SMEMBERS", "s", "a"]).is_err());
+    }
+
     #[test]
     fn it_parses_incr_command() {
         let args = vec!["INCR".to_string(), "some_key".to_string()];
@@ -844,6 +4383,62 @@ mod tests {
         assert_eq!(cmd, expected);
     }
 
+    #[test]
+    fn it_parses_watch_commands() {
+        let cmd = Command::from_args(vec!["WATCH".into(), "foo".into(), "bar".into()]).unwrap();
+        let expected = Command::Watch {
+            keys: vec!["foo".into(), "bar".into()],
+        };
+        assert_eq!(cmd, expected);
+        assert_eq!(cmd.keys(), ["foo", "bar"]);
+
+        let cmd = Command::from_args(vec!["unwatch".into()]).unwrap();
+        assert_eq!(cmd, Command::Unwatch);
+        assert!(Command::from_args(vec!["WATCH".into()]).is_err());
+    }
+
+    #[test]
+    fn it_parses_hello_command() {
+        let cmd = Command::from_args(vec!["HELLO".into(), "3".into()]).unwrap();
+        let expected = Command::Hello {
+            protover: Some(Protocol::Resp3),
+            auth: None,
+        };
+        assert_eq!(cmd, expected);
+        let cmd = Command::from_args(vec!["hello".into()]).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Hello {
+                protover: None,
+                auth: None,
+            }
+        );
+        let args = ["HELLO", "2", "auth", "alice", "secret"];
+        let cmd = Command::from_args(args.iter().map(|v| v.to_string()).collect()).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Hello {
+                protover: Some(Protocol::Resp2),
+                auth: Some(("alice".into(), "secret".into())),
+            }
+        );
+        let args = ["HELLO", "3", "AUTH", "alice"];
+        assert!(Command::from_args(args.iter().map(|v| v.to_string()).collect()).is_err());
+
+        let err = Command::from_args(vec!["HELLO".into(), "4".into()]).unwrap_err();
+        assert_eq!(err.code(), "NOPROTO");
+        assert!(Command::from_args(vec!["HELLO".into(), "three".into()]).is_err());
+        assert!(Command::from_args(vec!["HELLO".into(), "3".into(), "X".into()]).is_err());
+    }
+
+    #[test]
+    fn it_parses_select_command() {
+        let cmd = Command::from_args(vec!["SELECT".into(), "0".into()]).unwrap();
+        assert_eq!(cmd, Command::Select { index: 0 });
+        assert!(Command::from_args(vec!["SELECT".into(), "zero".into()]).is_err());
+        assert!(Command::from_args(vec!["SELECT".into()]).is_err());
+    }
+
     #[test]
     fn it_parses_exec_command() {
         let args = vec!["EXEC".to_string()];
@@ -852,6 +4447,402 @@ mod tests {
         assert_eq!(cmd, expected);
     }
 
+    #[test]
+    fn it_parses_debug_command() {
+        let args = vec!["DEBUG".to_string(), "sleep".to_string(), "0.5".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Debug(DebugCommand::Sleep(Duration::from_millis(500)));
+        assert_eq!(cmd, expected);
+
+        let args = vec!["DEBUG".to_string(), "OBJECT".to_string(), "foo".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Debug(DebugCommand::Object { key: "foo".into() });
+        assert_eq!(cmd, expected);
+
+        let args = vec![
+            "DEBUG".to_string(),
+            "SET-ACTIVE-EXPIRE".to_string(),
+            "0".to_string(),
+        ];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Debug(DebugCommand::SetActiveExpire(false));
+        assert_eq!(cmd, expected);
+
+        let args = vec!["DEBUG".to_string(), "CHANGE-REPL-ID".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Debug(DebugCommand::ChangeReplId);
+        assert_eq!(cmd, expected);
+
+        let args = vec!["DEBUG".to_string(), "reload-config".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(cmd, Command::Debug(DebugCommand::ReloadConfig));
+
+        let args = vec!["DEBUG".to_string(), "LISTPACK".to_string(), "s".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Debug(DebugCommand::Listpack { key: "s".into() });
+        assert_eq!(cmd, expected);
+
+        let args = vec![
+            "DEBUG".to_string(),
+            "QUICKLIST-PACKED-THRESHOLD".to_string(),
+            "1kb".to_string(),
+        ];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Debug(DebugCommand::QuicklistPackedThreshold(1024));
+        assert_eq!(cmd, expected);
+
+        for secs in ["-1", "60.5", "1e30"] {
+            let args = vec!["DEBUG".to_string(), "SLEEP".to_string(), secs.to_string()];
+            let cmd = Command::from_args(args).unwrap();
+            assert_eq!(cmd, Command::Unknown);
+        }
+    }
+
+    #[test]
+    fn it_tells_batchable_commands() {
+        let cmd = |args: &[&str]| Command::from_args(args.iter().map(|v| v.to_string()).collect());
+        assert!(cmd(&["SET", "foo", "bar"]).unwrap().batchable());
+        assert!(cmd(&["XREAD", "streams", "s", "0"]).unwrap().batchable());
+        assert!(!cmd(&["XREAD", "block", "0", "streams", "s", "0"])
+            .unwrap()
+            .batchable());
+        assert!(!cmd(&["MULTI"]).unwrap().batchable());
+        assert!(!cmd(&["WAIT", "1", "0"]).unwrap().batchable());
+    }
+
+    #[test]
+    fn it_lists_command_keys() {
+        let args = vec!["DEL".to_string(), "foo".to_string(), "bar".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(cmd.keys(), vec!["foo", "bar"]);
+        assert!(!cmd.is_readonly());
+
+        let args = vec![
+            "XREAD".to_string(),
+            "streams".to_string(),
+            "foo".to_string(),
+            "bar".to_string(),
+            "0-0".to_string(),
+            "0-0".to_string(),
+        ];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(cmd.keys(), vec!["foo", "bar"]);
+        assert!(cmd.is_readonly());
+
+        let cmd = Command::from_args(vec!["PING".to_string()]).unwrap();
+        assert!(cmd.keys().is_empty());
+    }
+
+    #[test]
+    fn it_parses_migrate_command() {
+        let args: Vec<String> = ["MIGRATE", "127.0.0.1", "7001", "", "0", "5000", "REPLACE"]
+            .into_iter()
+            .chain(["KEYS", "foo", "bar"])
+            .map(String::from)
+            .collect();
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Migrate {
+            host: "127.0.0.1".into(),
+            port: 7001,
+            keys: vec!["foo".into(), "bar".into()],
+            timeout: 5000,
+            copy: false,
+            replace: true,
+        };
+        assert_eq!(cmd, expected);
+
+        let args: Vec<String> = [
+            "MIGRATE",
+            "127.0.0.1",
+            "7001",
+            "foo",
+            "0",
+            "5000",
+            "KEYS",
+            "bar",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert!(Command::from_args(args).is_err());
+    }
+
+    #[test]
+    fn it_parses_eval_command() {
+        let args: Vec<String> = ["eval", "return 1", "2", "k1", "k2", "a1"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Eval {
+            script: "return 1".into(),
+            keys: vec!["k1".into(), "k2".into()],
+            args: vec!["a1".into()],
+            readonly: false,
+        };
+        assert_eq!(cmd, expected);
+        assert_eq!(cmd.keys(), vec!["k1", "k2"]);
+        assert!(!cmd.is_readonly());
+
+        let args: Vec<String> = ["EVALSHA", "ABC", "0"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::EvalSha {
+            sha: "abc".into(),
+            keys: vec![],
+            args: vec![],
+            readonly: false,
+        };
+        assert_eq!(cmd, expected);
+
+        let args: Vec<String> = ["evalsha_ro", "abc", "1", "k1"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::EvalSha {
+            sha: "abc".into(),
+            keys: vec!["k1".into()],
+            args: vec![],
+            readonly: true,
+        };
+        assert_eq!(cmd, expected);
+        assert!(cmd.is_readonly());
+
+        for numkeys in ["2", "-1", "x"] {
+            let args: Vec<String> = ["EVAL", "return 1", numkeys, "k1"]
+                .into_iter()
+                .map(String::from)
+                .collect();
+            assert!(Command::from_args(args).is_err());
+        }
+    }
+
+    #[test]
+    fn it_parses_script_command() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            Command::from_args(args).unwrap()
+        };
+        assert_eq!(
+            parse(&["SCRIPT", "load", "return 1"]),
+            Command::Script(ScriptCommand::Load("return 1".into()))
+        );
+        assert_eq!(
+            parse(&["SCRIPT", "EXISTS", "ABC", "def"]),
+            Command::Script(ScriptCommand::Exists(vec!["abc".into(), "def".into()]))
+        );
+        assert_eq!(
+            parse(&["SCRIPT", "FLUSH", "ASYNC"]),
+            Command::Script(ScriptCommand::Flush)
+        );
+        assert_eq!(parse(&["SCRIPT", "EXISTS"]), Command::Unknown);
+        assert_eq!(parse(&["SCRIPT", "FLUSH", "LATER"]), Command::Unknown);
+    }
+
+    #[test]
+    fn it_parses_help_and_object_commands() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            Command::from_args(args).unwrap()
+        };
+        assert_eq!(parse(&["OBJECT", "help"]), Command::Help("object"));
+        assert_eq!(parse(&["config", "HELP"]), Command::Help("config"));
+        assert_eq!(
+            parse(&["OBJECT", "ENCODING", "foo"]),
+            Command::ObjectEncoding { key: "foo".into() }
+        );
+        assert_eq!(parse(&["OBJECT", "FREQ", "foo"]), Command::Unknown);
+        assert_eq!(parse(&["ECHO", "help"]), Command::Echo("help".into()));
+    }
+
+    #[test]
+    fn it_parses_acl_command() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            Command::from_args(args).unwrap()
+        };
+        assert_eq!(
+            parse(&["ACL", "setuser", "alice", "on", ">secret"]),
+            Command::Acl(AclCommand::SetUser {
+                name: "alice".into(),
+                rules: vec!["on".into(), ">secret".into()],
+            })
+        );
+        assert_eq!(parse(&["acl", "SAVE"]), Command::Acl(AclCommand::Save));
+        assert_eq!(parse(&["ACL", "LOAD"]), Command::Acl(AclCommand::Load));
+        assert_eq!(parse(&["ACL", "DELUSER"]), Command::Unknown);
+        assert_eq!(parse(&["ACL", "SETUSER"]), Command::Unknown);
+    }
+
+    #[test]
+    fn it_parses_function_command() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            Command::from_args(args)
+        };
+        assert_eq!(
+            parse(&["FUNCTION", "load", "replace", "code"]).unwrap(),
+            Command::Function(FunctionCommand::Load {
+                code: "code".into(),
+                replace: true,
+            })
+        );
+        assert_eq!(
+            parse(&["FUNCTION", "LIST", "withcode", "LIBRARYNAME", "my*"]).unwrap(),
+            Command::Function(FunctionCommand::List {
+                pattern: Some("my*".into()),
+                with_code: true,
+            })
+        );
+        assert_eq!(
+            parse(&["FUNCTION", "RESTORE", "payload", "flush"]).unwrap(),
+            Command::Function(FunctionCommand::Restore {
+                payload: "payload".into(),
+                policy: RestorePolicy::Flush,
+            })
+        );
+        assert_eq!(
+            parse(&["FCALL", "echo", "1", "k1", "a1"]).unwrap(),
+            Command::Fcall {
+                function: "echo".into(),
+                keys: vec!["k1".into()],
+                args: vec!["a1".into()],
+                readonly: false,
+            }
+        );
+        assert_eq!(parse(&["FUNCTION", "DELETE"]).unwrap(), Command::Unknown);
+        assert!(parse(&["FUNCTION", "LIST", "LIBRARYNAME"]).is_err());
+        assert!(parse(&["FUNCTION", "RESTORE", "payload", "MERGE"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_cluster_command() {
+        let args = vec!["CLUSTER".to_string(), "slots".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(cmd, Command::Cluster(ClusterCommand::Slots));
+
+        let args = vec!["CLUSTER".to_string(), "MYID".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(cmd, Command::Cluster(ClusterCommand::Myid));
+
+        let args = vec![
+            "CLUSTER".to_string(),
+            "GETKEYSINSLOT".to_string(),
+            "12182".to_string(),
+            "10".to_string(),
+        ];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Cluster(ClusterCommand::GetKeysInSlot {
+            slot: 12182,
+            count: 10,
+        });
+        assert_eq!(cmd, expected);
+
+        let args = vec![
+            "CLUSTER".to_string(),
+            "SETSLOT".to_string(),
+            "0".to_string(),
+            "migrating".to_string(),
+            "abc".to_string(),
+        ];
+        let cmd = Command::from_args(args).unwrap();
+        let expected = Command::Cluster(ClusterCommand::SetSlot {
+            slot: 0,
+            action: SetSlot::Migrating("abc".into()),
+        });
+        assert_eq!(cmd, expected);
+
+        let args = vec!["CLUSTER".to_string(), "failover".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Cluster(ClusterCommand::Failover { force: false })
+        );
+
+        let args = vec![
+            "CLUSTER".to_string(),
+            "FAILOVER".to_string(),
+            "takeover".to_string(),
+        ];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Cluster(ClusterCommand::Failover { force: true })
+        );
+
+        let args = vec!["CLUSTER".to_string(), "FOO".to_string()];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(cmd, Command::Unknown);
+    }
+
+    #[test]
+    fn it_parses_client_no_evict_command() {
+        let args = vec![
+            "CLIENT".to_string(),
+            "NO-EVICT".to_string(),
+            "on".to_string(),
+        ];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(cmd, Command::ClientNoEvict(true));
+
+        let args = vec![
+            "client".to_string(),
+            "no-evict".to_string(),
+            "OFF".to_string(),
+        ];
+        let cmd = Command::from_args(args).unwrap();
+        assert_eq!(cmd, Command::ClientNoEvict(false));
+    }
+
+    #[test]
+    fn it_parses_client_kill_command() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            Command::from_args(args)
+        };
+        assert_eq!(
+            parse(&["CLIENT", "KILL", "127.0.0.1:5000"]).unwrap(),
+            Command::ClientKill {
+                id: None,
+                addr: Some("127.0.0.1:5000".into()),
+                skipme: false,
+                legacy: true,
+            }
+        );
+        assert_eq!(
+            parse(&["client", "kill", "id", "3", "SKIPME", "no"]).unwrap(),
+            Command::ClientKill {
+                id: Some(3),
+                addr: None,
+                skipme: false,
+                legacy: false,
+            }
+        );
+        assert!(parse(&["CLIENT", "KILL", "ID", "0"]).is_err());
+        assert!(parse(&["CLIENT", "KILL", "ID", "3", "ADDR"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_shutdown_command() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            Command::from_args(args)
+        };
+        assert_eq!(
+            parse(&["SHUTDOWN"]).unwrap(),
+            Command::Shutdown { save: None }
+        );
+        assert_eq!(
+            parse(&["shutdown", "nosave", "now"]).unwrap(),
+            Command::Shutdown { save: Some(false) }
+        );
+        assert!(parse(&["SHUTDOWN", "SAVE", "NOSAVE"]).is_err());
+        assert!(parse(&["SHUTDOWN", "ABORT"]).is_err());
+    }
+
     #[test]
     fn it_parses_discard_command() {
         let args = vec!["DISCARD".to_string()];
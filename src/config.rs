@@ -1,25 +1,380 @@
+use super::{resp::MAX_BULK_LEN, utils, RedisResult, BUF_SIZE};
+use std::fs;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
 
 #[derive(Debug, Clone)]
 pub struct Config {
+    /// The config file given before the options of the command line, in the format of
+    /// redis.conf. It's read again when the configuration is reloaded.
+    pub config_file: Option<String>,
+    /// The command line, kept so that its options still win over the file on a reload.
+    args: Vec<String>,
     pub dir: Option<String>,
     pub dbfilename: Option<String>,
     pub port: u16,
     pub master: Option<SocketAddr>,
+    /// Who may run DEBUG, nobody unless enabled.
+    pub enable_debug_command: EnableOption,
+    /// Who may change the parameters CONFIG SET protects, like `dir` and `dbfilename`,
+    /// which decide where the server writes files.
+    pub enable_protected_configs: EnableOption,
+    pub maxmemory_clients: usize,
+    /// Times per second the periodic maintenance runs.
+    pub hz: u64,
+    /// Seconds after which idle clients are closed. 0 never closes them.
+    pub timeout: u64,
+    /// Pairs of seconds and changes: the keyspace is saved when it has been changed
+    /// that many times within the seconds of the last save.
+    pub save: Vec<(u64, u64)>,
+    pub repl_ping_replica_period: u64,
+    pub cluster_enabled: bool,
+    /// Milliseconds a script runs before other clients are told the server is busy.
+    pub busy_script_time_limit: u64,
+    /// Connections accepted at most at a time. Connections beyond it are refused.
+    pub maxclients: usize,
+    /// Bytes of a command a client may send before the command is complete. Clients
+    /// going past it are disconnected.
+    pub client_query_buffer_limit: usize,
+    /// Milliseconds a client may take to send the rest of a command it started. Clients
+    /// taking longer are disconnected. 0 lets them take as long as they like.
+    pub client_query_timeout: u64,
+    /// Listening sockets bound to the port, each with its own accept loop. More than one
+    /// are bound with SO_REUSEPORT, so that the kernel spreads new connections over them.
+    pub acceptors: usize,
+    /// What connections read and write their sockets through.
+    pub io_backend: IoBackend,
+    /// Bytes a connection reads at first. Reads grow past it while clients send more.
+    pub io_buf_size: usize,
+    /// Bytes a bulk string of a request, or a string value, may be at most.
+    pub proto_max_bulk_len: usize,
+    /// Hashes with more fields than this are converted from a listpack to a hashtable.
+    pub hash_max_listpack_entries: usize,
+    /// Hashes with a field or value longer than this are converted to a hashtable.
+    pub hash_max_listpack_value: usize,
+    /// Size of each listpack node of a list. A positive value limits the entries of a
+    /// node, and -1 to -5 limit its bytes to 4, 8, 16, 32 or 64 KB.
+    pub list_max_listpack_size: i64,
+    /// Sets of integers with more members than this are converted from an intset to a
+    /// hashtable.
+    pub set_max_intset_entries: usize,
+    /// Sorted sets with more members than this are converted from a listpack to a
+    /// skiplist.
+    pub zset_max_listpack_entries: usize,
+    /// File the logs are appended to. Logs go to the standard output without it.
+    pub logfile: Option<String>,
+    /// Whether the server detaches from the terminal and runs in the background.
+    pub daemonize: bool,
+    /// File the process ID is written to. A daemonized server writes it to
+    /// `/var/run/redis.pid` when it isn't given.
+    pub pidfile: Option<String>,
+    /// Whether writes are logged to the append only file, which is loaded instead of
+    /// the RDB file on start.
+    pub appendonly: bool,
+    /// Directory under `dir` holding the files of the AOF and their manifest.
+    pub appenddirname: String,
+    /// Prefix of the names of the AOF files.
+    pub appendfilename: String,
+    /// When the AOF is fsynced: after every write, once a second, or never by itself.
+    pub appendfsync: AppendFsync,
+    /// Whether an AOF ending in the middle of a command is loaded and truncated there,
+    /// rather than refused.
+    pub aof_load_truncated: bool,
+    /// Whether every frame read from or written to a connection is logged. It can be
+    /// turned on and off with CONFIG SET while the server runs.
+    pub trace_proto: bool,
+    /// The least severe messages logged.
+    pub loglevel: LogLevel,
+    /// Bytes the keyspace may take before commands adding to it are refused. 0 means
+    /// no limit.
+    pub maxmemory: usize,
+    /// Password clients must AUTH with before running any other command.
+    pub requirepass: Option<String>,
+    /// File the ACL users are loaded from on start and by ACL LOAD, and saved to by
+    /// ACL SAVE.
+    pub aclfile: Option<String>,
+}
+
+/// How verbose the logs are, from the most to the least.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+impl LogLevel {
+    /// Every level, in the order of their discriminants.
+    pub(crate) const ALL: [Self; 4] = [Self::Debug, Self::Verbose, Self::Notice, Self::Warning];
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "debug" => Some(Self::Debug),
+            "verbose" => Some(Self::Verbose),
+            "notice" => Some(Self::Notice),
+            "warning" => Some(Self::Warning),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Verbose => "verbose",
+            Self::Notice => "notice",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppendFsync {
+    Always,
+    EverySec,
+    No,
+}
+
+impl AppendFsync {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "always" => Some(Self::Always),
+            "everysec" => Some(Self::EverySec),
+            "no" => Some(Self::No),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::EverySec => "everysec",
+            Self::No => "no",
+        }
+    }
+}
+
+/// Who may use a feature guarded by one of the `enable-*` options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnableOption {
+    No,
+    Yes,
+    /// Only clients connected from the loopback.
+    Local,
+}
+
+impl EnableOption {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "no" => Some(Self::No),
+            "yes" => Some(Self::Yes),
+            "local" => Some(Self::Local),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::No => "no",
+            Self::Yes => "yes",
+            Self::Local => "local",
+        }
+    }
+
+    /// Whether a client connected from the address may use the feature.
+    pub fn allows(&self, addr: SocketAddr) -> bool {
+        match self {
+            Self::No => false,
+            Self::Yes => true,
+            Self::Local => addr.ip().is_loopback(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoBackend {
+    /// The readiness based sockets of the runtime.
+    Tokio,
+    /// An io_uring ring shared by all connections. It's experimental, and available only
+    /// on Linux.
+    Uring,
+}
+
+impl IoBackend {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "tokio" => Some(Self::Tokio),
+            "io_uring" | "uring" => Some(Self::Uring),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tokio => "tokio",
+            Self::Uring => "io_uring",
+        }
+    }
 }
 
 impl Config {
-    pub fn new(args: Vec<String>) -> Self {
-        Self {
+    /// Reads the configuration from the command line arguments, and from the config file
+    /// when one is given first. Values which can't be used, like a master that can't be
+    /// resolved, are reported rather than ignored.
+    pub fn new(args: Vec<String>) -> RedisResult<Self> {
+        let config_file = config_file_arg(&args);
+        // get_arg takes the first occurrence, so the file goes after the command line
+        // for the command line to win.
+        let mut options = args.clone();
+        if let Some(path) = config_file.as_deref() {
+            options.extend(read_config_file(path)?);
+        }
+
+        let mut config = Self::parse(options)?;
+        config.config_file = config_file;
+        config.args = args;
+        Ok(config)
+    }
+
+    /// Reads the config file and the command line again, for the parameters which can be
+    /// changed while the server runs.
+    pub fn reload(&self) -> RedisResult<Self> {
+        Self::new(self.args.clone())
+    }
+
+    fn parse(args: Vec<String>) -> RedisResult<Self> {
+        let master = get_arg(&args, "--replicaof")
+            .map(|v| parse_replicaof(&v))
+            .transpose()?
+            .flatten();
+        // The server is built without TLS, so replication links can only be plain TCP.
+        if get_arg(&args, "--tls-replication").is_some_and(|v| v.as_str() == "yes") {
+            return Err(anyhow::anyhow!(
+                "tls-replication yes is not supported: the server is built without TLS"
+            )
+            .into());
+        }
+        // Every key lives in database 0, the only one there is.
+        if get_arg(&args, "--databases").is_some_and(|v| v.as_str() != "1") {
+            return Err(anyhow::anyhow!(
+                "databases other than 1 are not supported: the server has a single database"
+            )
+            .into());
+        }
+
+        Ok(Self {
+            config_file: None,
+            args: vec![],
             dir: get_arg(&args, "--dir"),
             dbfilename: get_arg(&args, "--dbfilename"),
             port: get_arg(&args, "--port")
                 .and_then(|v| v.parse::<u16>().ok())
                 .unwrap_or(6379),
-            master: get_arg(&args, "--replicaof")
-                .and_then(|v| v.replace(" ", ":").to_socket_addrs().ok())
-                .and_then(|mut v| v.next()),
-        }
+            master,
+            enable_debug_command: get_arg(&args, "--enable-debug-command")
+                .and_then(|v| EnableOption::parse(&v))
+                .unwrap_or(EnableOption::No),
+            enable_protected_configs: get_arg(&args, "--enable-protected-configs")
+                .and_then(|v| EnableOption::parse(&v))
+                .unwrap_or(EnableOption::Local),
+            maxmemory_clients: get_arg(&args, "--maxmemory-clients")
+                .and_then(|v| utils::parse_memory(&v))
+                .unwrap_or(0),
+            hz: get_arg(&args, "--hz")
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(10)
+                .clamp(1, 500),
+            timeout: get_arg(&args, "--timeout")
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(0),
+            save: get_arg(&args, "--save")
+                .map(|v| parse_save(&v))
+                .unwrap_or_default(),
+            repl_ping_replica_period: get_arg(&args, "--repl-ping-replica-period")
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(10),
+            cluster_enabled: get_arg(&args, "--cluster-enabled")
+                .map(|v| v.as_str() == "yes")
+                .unwrap_or(false),
+            busy_script_time_limit: get_arg(&args, "--busy-script-time-limit")
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(5000),
+            maxclients: get_arg(&args, "--maxclients")
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(10000),
+            // Redis doesn't take less than 1mb either.
+            client_query_buffer_limit: get_arg(&args, "--client-query-buffer-limit")
+                .and_then(|v| utils::parse_memory(&v))
+                .filter(|v| *v >= 1024 * 1024)
+                .unwrap_or(1024 * 1024 * 1024),
+            client_query_timeout: get_arg(&args, "--client-query-timeout")
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(0),
+            acceptors: get_arg(&args, "--acceptors")
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(1),
+            io_backend: get_arg(&args, "--io-backend")
+                .and_then(|v| IoBackend::parse(&v))
+                .unwrap_or(IoBackend::Tokio),
+            io_buf_size: get_arg(&args, "--io-buf-size")
+                .and_then(|v| utils::parse_memory(&v))
+                .filter(|v| *v > 0)
+                .unwrap_or(BUF_SIZE),
+            // Redis doesn't take less than 1mb either.
+            proto_max_bulk_len: get_arg(&args, "--proto-max-bulk-len")
+                .and_then(|v| utils::parse_memory(&v))
+                .filter(|v| *v >= 1024 * 1024)
+                .unwrap_or(MAX_BULK_LEN),
+            hash_max_listpack_entries: get_arg(&args, "--hash-max-listpack-entries")
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(128),
+            hash_max_listpack_value: get_arg(&args, "--hash-max-listpack-value")
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(64),
+            list_max_listpack_size: get_arg(&args, "--list-max-listpack-size")
+                .and_then(|v| v.parse::<i64>().ok())
+                .filter(|v| *v > 0 || (-5..=-1).contains(v))
+                .unwrap_or(-2),
+            set_max_intset_entries: get_arg(&args, "--set-max-intset-entries")
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(512),
+            zset_max_listpack_entries: get_arg(&args, "--zset-max-listpack-entries")
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(128),
+            logfile: get_arg(&args, "--logfile").filter(|v| !v.is_empty()),
+            daemonize: get_arg(&args, "--daemonize")
+                .map(|v| v.as_str() == "yes")
+                .unwrap_or(false),
+            pidfile: get_arg(&args, "--pidfile").filter(|v| !v.is_empty()),
+            appendonly: get_arg(&args, "--appendonly")
+                .map(|v| v.as_str() == "yes")
+                .unwrap_or(false),
+            appenddirname: get_arg(&args, "--appenddirname")
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| "appendonlydir".into()),
+            appendfilename: get_arg(&args, "--appendfilename")
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| "appendonly.aof".into()),
+            appendfsync: get_arg(&args, "--appendfsync")
+                .and_then(|v| AppendFsync::parse(&v))
+                .unwrap_or(AppendFsync::EverySec),
+            aof_load_truncated: get_arg(&args, "--aof-load-truncated")
+                .map(|v| v.as_str() != "no")
+                .unwrap_or(true),
+            // Given alone, the flag turns tracing on.
+            trace_proto: args.iter().any(|v| v.as_str() == "--trace-proto")
+                && get_arg(&args, "--trace-proto").as_deref() != Some("no"),
+            loglevel: get_arg(&args, "--loglevel")
+                .and_then(|v| LogLevel::parse(&v))
+                .unwrap_or(LogLevel::Notice),
+            maxmemory: get_arg(&args, "--maxmemory")
+                .and_then(|v| utils::parse_memory(&v))
+                .unwrap_or(0),
+            requirepass: get_arg(&args, "--requirepass").filter(|v| !v.is_empty()),
+            aclfile: get_arg(&args, "--aclfile").filter(|v| !v.is_empty()),
+        })
     }
 
     pub fn socket_addr(&self) -> SocketAddr {
@@ -29,6 +384,109 @@ impl Config {
     pub fn master_addr(&self) -> &Option<SocketAddr> {
         &self.master
     }
+
+    /// Parameters readable by CONFIG GET, with their current values.
+    pub fn params(&self) -> Vec<(&'static str, Option<String>)> {
+        let save = utils::format_save(&self.save);
+        vec![
+            ("dir", self.dir.clone()),
+            ("dbfilename", self.dbfilename.clone()),
+            ("port", Some(self.port.to_string())),
+            (
+                "maxmemory-clients",
+                Some(self.maxmemory_clients.to_string()),
+            ),
+            ("hz", Some(self.hz.to_string())),
+            ("timeout", Some(self.timeout.to_string())),
+            ("save", Some(save)),
+            (
+                "repl-ping-replica-period",
+                Some(self.repl_ping_replica_period.to_string()),
+            ),
+            (
+                "enable-debug-command",
+                Some(self.enable_debug_command.as_str().into()),
+            ),
+            (
+                "enable-protected-configs",
+                Some(self.enable_protected_configs.as_str().into()),
+            ),
+            (
+                "cluster-enabled",
+                Some(if self.cluster_enabled { "yes" } else { "no" }.into()),
+            ),
+            (
+                "busy-script-time-limit",
+                Some(self.busy_script_time_limit.to_string()),
+            ),
+            ("maxclients", Some(self.maxclients.to_string())),
+            (
+                "client-query-buffer-limit",
+                Some(self.client_query_buffer_limit.to_string()),
+            ),
+            (
+                "client-query-timeout",
+                Some(self.client_query_timeout.to_string()),
+            ),
+            ("acceptors", Some(self.acceptors.to_string())),
+            ("io-backend", Some(self.io_backend.as_str().into())),
+            ("io-buf-size", Some(self.io_buf_size.to_string())),
+            (
+                "proto-max-bulk-len",
+                Some(self.proto_max_bulk_len.to_string()),
+            ),
+            (
+                "hash-max-listpack-entries",
+                Some(self.hash_max_listpack_entries.to_string()),
+            ),
+            (
+                "hash-max-listpack-value",
+                Some(self.hash_max_listpack_value.to_string()),
+            ),
+            (
+                "list-max-listpack-size",
+                Some(self.list_max_listpack_size.to_string()),
+            ),
+            (
+                "set-max-intset-entries",
+                Some(self.set_max_intset_entries.to_string()),
+            ),
+            (
+                "zset-max-listpack-entries",
+                Some(self.zset_max_listpack_entries.to_string()),
+            ),
+            ("logfile", Some(self.logfile.clone().unwrap_or_default())),
+            (
+                "daemonize",
+                Some(if self.daemonize { "yes" } else { "no" }.into()),
+            ),
+            ("pidfile", Some(self.pidfile.clone().unwrap_or_default())),
+            (
+                "appendonly",
+                Some(if self.appendonly { "yes" } else { "no" }.into()),
+            ),
+            ("appenddirname", Some(self.appenddirname.clone())),
+            ("appendfilename", Some(self.appendfilename.clone())),
+            ("appendfsync", Some(self.appendfsync.as_str().into())),
+            (
+                "aof-load-truncated",
+                Some(if self.aof_load_truncated { "yes" } else { "no" }.into()),
+            ),
+            (
+                "trace-proto",
+                Some(if self.trace_proto { "yes" } else { "no" }.into()),
+            ),
+            ("loglevel", Some(self.loglevel.as_str().into())),
+            ("maxmemory", Some(self.maxmemory.to_string())),
+            (
+                "requirepass",
+                Some(self.requirepass.clone().unwrap_or_default()),
+            ),
+            ("aclfile", Some(self.aclfile.clone().unwrap_or_default())),
+            ("tls-replication", Some("no".into())),
+            ("databases", Some("1".into())),
+        ]
+    }
 }
 
 fn get_arg(args: &[String], opt: &str) -> Option<String> {
@@ -37,6 +495,91 @@ fn get_arg(args: &[String], opt: &str) -> Option<String> {
         .and_then(|pos| args.get(pos + 1).cloned())
 }
 
+/// The config file is the first argument after the program name, as it is for
+/// redis-server, unless it's an option.
+fn config_file_arg(args: &[String]) -> Option<String> {
+    match args {
+        [program, file, ..] if !program.starts_with('-') && !file.starts_with('-') => {
+            Some(file.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Reads a file in the format of redis.conf, a parameter and its arguments per line, into
+/// the command line options the same parameters would be given as.
+fn read_config_file(path: &str) -> RedisResult<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("Can't open config file '{path}'. {err}"))?;
+
+    let mut options: Vec<(String, String)> = vec![];
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let args = utils::split_args(line).map_err(|_| {
+            anyhow::anyhow!("Bad directive in config file '{path}' at line {}", n + 1)
+        })?;
+        let Some((name, values)) = args.split_first() else {
+            continue;
+        };
+        let name = format!("--{}", name.to_lowercase());
+        let value = values.join(" ");
+
+        // Each save line adds save points. Other parameters take their last value.
+        match options.iter_mut().find(|(option, _)| *option == name) {
+            Some((_, points)) if name == "--save" && !value.is_empty() => {
+                points.push(' ');
+                points.push_str(&value);
+            }
+            Some((_, last)) => *last = value,
+            None => options.push((name, value)),
+        }
+    }
+    Ok(options
+        .into_iter()
+        .flat_map(|(name, value)| [name, value])
+        .collect())
+}
+
+/// Parses `<host> <port>`, resolving the host, or `no one` for no master at all.
+fn parse_replicaof(value: &str) -> RedisResult<Option<SocketAddr>> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [host, port] = parts[..] else {
+        return Err(anyhow::anyhow!(
+            "Invalid replicaof '{value}'. It must be \"<host> <port>\" or \"no one\""
+        )
+        .into());
+    };
+    if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+        return Ok(None);
+    }
+
+    let port = port
+        .parse::<u16>()
+        .ok()
+        .filter(|port| *port > 0)
+        .ok_or_else(|| anyhow::anyhow!("Invalid master port '{port}' in replicaof"))?;
+    let mut addrs = (host, port)
+        .to_socket_addrs()
+        .map_err(|err| anyhow::anyhow!("Can't resolve master host '{host}'. {err}"))?;
+    let addr = addrs
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Master host '{host}' has no address"))?;
+    Ok(Some(addr))
+}
+
+fn parse_save(value: &str) -> Vec<(u64, u64)> {
+    let nums: Vec<u64> = value
+        .split_whitespace()
+        .filter_map(|v| v.parse::<u64>().ok())
+        .collect();
+    nums.chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,4 +600,124 @@ mod tests {
         let dbfilename = get_arg(&args, "--dbfilename");
         assert_eq!(dbfilename, Some("dump.rdb".into()));
     }
+
+    #[test]
+    fn it_parses_save_rules() {
+        assert_eq!(parse_save("3600 1 300 100"), vec![(3600, 1), (300, 100)]);
+        assert!(parse_save("").is_empty());
+    }
+
+    #[test]
+    fn it_parses_replicaof() {
+        let addr = parse_replicaof("127.0.0.1 6380").unwrap();
+        assert_eq!(addr, Some("127.0.0.1:6380".parse().unwrap()));
+
+        let addr = parse_replicaof("localhost 6380").unwrap().unwrap();
+        assert!(addr.ip().is_loopback());
+        assert_eq!(addr.port(), 6380);
+
+        assert_eq!(parse_replicaof("NO ONE").unwrap(), None);
+
+        for value in [
+            "",
+            "127.0.0.1",
+            "127.0.0.1:6380",
+            "127.0.0.1 0",
+            "127.0.0.1 port",
+        ] {
+            assert!(parse_replicaof(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn it_parses_enable_options() {
+        let config = Config::new(vec![]).unwrap();
+        assert_eq!(config.enable_debug_command, EnableOption::No);
+        assert_eq!(config.enable_protected_configs, EnableOption::Local);
+
+        let args = [
+            "--enable-debug-command",
+            "yes",
+            "--enable-protected-configs",
+            "no",
+        ];
+        let config = Config::new(args.iter().map(|v| v.to_string()).collect()).unwrap();
+        assert_eq!(config.enable_debug_command, EnableOption::Yes);
+        assert_eq!(config.enable_protected_configs, EnableOption::No);
+
+        let local: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let remote: SocketAddr = "10.0.0.1:50000".parse().unwrap();
+        assert!(EnableOption::Local.allows(local));
+        assert!(!EnableOption::Local.allows(remote));
+        assert!(EnableOption::Yes.allows(remote));
+        assert!(!EnableOption::No.allows(local));
+    }
+
+    #[test]
+    fn it_reads_the_config_file() {
+        let path = std::env::temp_dir().join(format!("redis-conf-{}.conf", std::process::id()));
+        fs::write(
+            &path,
+            "# comment\nport 7000\nsave 900 1\nsave 300 10\nrequirepass \"s3 cret\"\nloglevel warning\nmaxmemory 1mb\n",
+        )
+        .unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        let args = ["redis-server", &path, "--port", "7001"];
+        let config = Config::new(args.iter().map(|v| v.to_string()).collect()).unwrap();
+        assert_eq!(config.config_file.as_deref(), Some(path.as_str()));
+        // The command line wins over the file.
+        assert_eq!(config.port, 7001);
+        assert_eq!(config.save, vec![(900, 1), (300, 10)]);
+        assert_eq!(config.requirepass.as_deref(), Some("s3 cret"));
+        assert_eq!(config.loglevel, LogLevel::Warning);
+        assert_eq!(config.maxmemory, 1024 * 1024);
+
+        fs::write(&path, "port 7000\nloglevel debug\n").unwrap();
+        let config = config.reload().unwrap();
+        assert_eq!(config.port, 7001);
+        assert!(config.save.is_empty());
+        assert_eq!(config.loglevel, LogLevel::Debug);
+        fs::remove_file(&path).unwrap();
+
+        assert!(config.reload().is_err());
+        let args = ["--port", "7001"];
+        let config = Config::new(args.iter().map(|v| v.to_string()).collect()).unwrap();
+        assert_eq!(config.config_file, None);
+    }
+
+    #[test]
+    fn it_parses_encoding_thresholds() {
+        let config = Config::new(vec![]).unwrap();
+        assert_eq!(config.hash_max_listpack_entries, 128);
+        assert_eq!(config.list_max_listpack_size, -2);
+
+        let args = [
+            "--list-max-listpack-size",
+            "-6",
+            "--set-max-intset-entries",
+            "16",
+        ];
+        let config = Config::new(args.iter().map(|v| v.to_string()).collect()).unwrap();
+        assert_eq!(config.list_max_listpack_size, -2);
+        assert_eq!(config.set_max_intset_entries, 16);
+    }
+
+    #[test]
+    fn it_refuses_tls_replication() {
+        let args = ["--tls-replication", "no"];
+        assert!(Config::new(args.iter().map(|v| v.to_string()).collect()).is_ok());
+
+        let args = ["--tls-replication", "yes"];
+        assert!(Config::new(args.iter().map(|v| v.to_string()).collect()).is_err());
+    }
+
+    #[test]
+    fn it_refuses_more_than_one_database() {
+        let args = ["--databases", "1"];
+        assert!(Config::new(args.iter().map(|v| v.to_string()).collect()).is_ok());
+
+        let args = ["--databases", "16"];
+        assert!(Config::new(args.iter().map(|v| v.to_string()).collect()).is_err());
+    }
 }
@@ -1,14 +1,36 @@
 use super::{
-    Command, CommandMode, Context, IncomingMessage, OutgoingMessage, RedisResult, Resp, Store,
-    BUF_SIZE,
+    cmd::command_args,
+    message::{self, write_frames, Direction, MessageDecoder},
+    uring::{self, Uring},
+    Command, CommandCall, CommandMode, Context, IncomingMessage, LogLevel, OutgoingMessage,
+    RedisError, RedisResult, Reply, Resp, Store,
 };
+use bytes::Bytes;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{tcp::OwnedWriteHalf, TcpStream};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::net::{
+    tcp::{OwnedReadHalf, OwnedWriteHalf},
+    TcpStream,
+};
 use tokio::sync::{
     mpsc::{self, Receiver},
     oneshot,
 };
+use tokio::task::JoinHandle;
+use tokio::time::sleep_until;
+
+/// How many times the initial size a read buffer may grow to.
+const MAX_BUF_GROWTH: usize = 64;
+
+/// Frames the writer takes off the queue for one write at most.
+const MAX_BATCH: usize = 64;
+
+/// How long the error a client is disconnected with may take to be written.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Debug)]
 pub struct Connection {
@@ -21,114 +43,379 @@ impl Connection {
         Self { stream, mode }
     }
 
-    pub async fn start_streaming(self, store: &Arc<Store>) -> RedisResult<()> {
+    /// Starts the tasks serving the connection. The returned task finishes once the
+    /// connection is closed and its last command has run.
+    pub async fn start_streaming(self, store: &Arc<Store>) -> RedisResult<JoinHandle<()>> {
         let Self { stream, mode } = self;
         let addr = stream.peer_addr()?;
-        let ctx_builder = Context::builder(mode, addr);
+        let client = store.register_client(addr).await;
+        if mode == CommandMode::Sync {
+            // The link to the master is never evicted to save client memory.
+            client.set_no_evict(true);
+            client.set_repl_link(true);
+        }
+        let ctx_builder = Context::builder(mode, addr, Arc::clone(&client));
 
         let store = Arc::clone(store);
-        let (mut rs, mut ws) = stream.into_split();
+        let uring = store.uring().cloned();
+        let fd = uring::raw_fd(&stream);
+        let (rs, mut ws) = stream.into_split();
+        let mut reader = Reader {
+            rs,
+            uring: uring.clone(),
+            fd,
+        };
         let (tx_in, mut rx_in) = mpsc::channel::<IncomingMessage>(100);
-        let (tx_by, mut rx_by) = mpsc::channel::<Vec<u8>>(100);
+        let (tx_by, mut rx_by) = mpsc::channel::<Bytes>(100);
 
+        let reader_client = Arc::clone(&client);
+        let reader_store = Arc::clone(&store);
         tokio::spawn(async move {
-            let mut buf = [0; BUF_SIZE];
+            let base = reader_store.io_buf_size();
+            let mut buf = vec![0; base];
+            let mut decoder = MessageDecoder::new(reader_store.proto_max_bulk_len());
+            // The master may send an RDB file of any size, at any pace.
+            let limited = mode != CommandMode::Sync;
+            let query_timeout = reader_store.client_query_timeout().filter(|_| limited);
+            // When the command read in part so far started coming.
+            let mut partial_since: Option<Instant> = None;
 
-            while let Ok(size) = rs.read(&mut buf).await {
-                if size > 0 {
+            loop {
+                let deadline = partial_since
+                    .zip(query_timeout)
+                    .map(|(since, timeout)| since + timeout);
+                let size = tokio::select! {
+                    res = reader.read(&mut buf) => match res {
+                        Ok(0) => {
+                            println!("Client {addr} closed the connection");
+                            break;
+                        }
+                        Ok(size) => size,
+                        Err(_) => break,
+                    },
+                    _ = reader_client.killed() => {
+                        println!("Client {addr} killed");
+                        break;
+                    }
+                    _ = sleep_until(deadline.unwrap_or_else(Instant::now).into()), if deadline.is_some() => {
+                        println!("Client {addr} took too long to send a command");
+                        reader_store.incr_query_timeout_disconnections();
+                        break;
+                    }
+                };
+
+                if reader_store.log_enabled(LogLevel::Debug) {
                     println!("Get {size} byte data!");
+                }
+                reader_client.add_query_buf(size);
+                reader_client.touch(reader_store.clock().unix_millis());
 
-                    match IncomingMessage::from_buffer(&buf[..size]) {
-                        Ok(messages) => {
-                            for message in messages {
-                                if tx_in.send(message).await.is_err() {
-                                    eprintln!("Receiver dropped");
-                                    break;
-                                }
+                let mut completed = false;
+                match decoder.feed(&buf[..size]) {
+                    Ok(frames) => {
+                        completed = !frames.is_empty();
+                        let trace = reader_store.trace_proto();
+                        for (message, frame) in frames {
+                            if trace {
+                                message::trace(addr, Direction::In, &frame);
+                            }
+                            if tx_in.send(message).await.is_err() {
+                                eprintln!("Receiver dropped");
+                                break;
                             }
                         }
-                        Err(err) => {
-                            eprintln!("ERROR parsing incoming message. {err}")
+                    }
+                    // Clients get the error and are disconnected, as where their next
+                    // command starts can't be told. The master is read on regardless.
+                    Err(RedisError::Protocol(err)) if mode != CommandMode::Sync => {
+                        eprintln!("ERROR parsing incoming message from {addr}. {err}");
+                        if tx_in.send(IncomingMessage::Invalid(err)).await.is_ok() {
+                            // The executor kills the connection once the error is queued.
+                            reader_client.killed().await;
                         }
+                        break;
+                    }
+                    Err(err) => {
+                        eprintln!("ERROR parsing incoming message. {err}")
                     }
                 }
-                buf = [0; BUF_SIZE];
+                if decoder.pending() > 0 && reader_store.log_enabled(LogLevel::Debug) {
+                    println!("Wait for the rest of {} byte data", decoder.pending());
+                }
+                if limited && decoder.pending() > reader_store.client_query_buffer_limit() {
+                    println!("Client {addr} reached the query buffer limit");
+                    reader_store.incr_query_buffer_disconnections();
+                    break;
+                }
+                partial_since = match decoder.pending() {
+                    0 => None,
+                    _ if completed => Some(Instant::now()),
+                    _ => partial_since.or_else(|| Some(Instant::now())),
+                };
+
+                let len = next_buf_len(buf.len(), size, base);
+                if len != buf.len() {
+                    buf.resize(len, 0);
+                    buf.shrink_to_fit();
+                }
             }
+
+            // Dropping the sender stops the executor once its current command has run.
+            drop(tx_in);
+            reader_store.unregister_client(&reader_client).await;
         });
 
         if mode == CommandMode::Sync {
             // handshaking process
-            ping(&mut ws, &mut rx_in).await?;
-            repl_conf(&mut ws, &mut rx_in, store.port().await).await?;
-            psync(&mut ws, &mut rx_in).await?;
+            let trace = store.trace_proto().then_some(addr);
+            ping(&mut ws, &mut rx_in, trace).await?;
+            repl_conf(&mut ws, &mut rx_in, store.port().await, trace).await?;
+            // The stream is counted on from the offset the master resyncs at.
+            let offset = psync(&mut ws, &mut rx_in, trace).await?;
+            store.add_ack_offset(offset).await;
+            store.link_master(tx_by.clone()).await;
         }
 
-        tokio::spawn(async move {
+        let writer_client = Arc::clone(&client);
+        let writer_store = Arc::clone(&store);
+        let mut writer = tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(MAX_BATCH);
             while let Some(msg) = rx_by.recv().await {
-                if let Err(err) = ws.write_all(&msg).await {
+                // Frames queued meanwhile go out with the same write.
+                batch.push(msg);
+                while batch.len() < MAX_BATCH {
+                    match rx_by.try_recv() {
+                        Ok(msg) => batch.push(msg),
+                        Err(_) => break,
+                    }
+                }
+
+                if writer_store.trace_proto() {
+                    for frame in batch.iter() {
+                        message::trace(addr, Direction::Out, frame);
+                    }
+                }
+                let written = match &uring {
+                    Some(uring) => uring.send_frames(fd, &batch).await,
+                    None => write_frames(&mut ws, &batch).await,
+                };
+                if let Err(err) = written {
                     eprintln!("Error sending message to {addr}. {err}");
                 }
+                writer_client.sub_output_buf(batch.iter().map(Bytes::len).sum());
+                batch.clear();
             }
             eprintln!("Channel closed. Stop reading bytes from {addr}");
         });
 
-        tokio::spawn(async move {
+        let handle = tokio::spawn(async move {
+            let mut batch: Vec<IncomingMessage> = Vec::with_capacity(MAX_BATCH);
+            let mut protocol_error = false;
             while let Some(msg) = rx_in.recv().await {
-                match msg {
-                    IncomingMessage::Resp(resp) => {
-                        let size = resp.len();
-
-                        match Command::new(resp) {
-                            Ok(cmd) => {
-                                if cmd.store_connection() {
-                                    store.subscribe(addr, tx_by.clone()).await;
+                // Frames already queued, as pipelined commands of one read are, run as one
+                // batch.
+                batch.push(msg);
+                while batch.len() < MAX_BATCH {
+                    match rx_in.try_recv() {
+                        Ok(msg) => batch.push(msg),
+                        Err(_) => break,
+                    }
+                }
+
+                // Bytes of the batch not yet added to the replication offset.
+                let mut unsettled: usize = 0;
+                // Whether the client is queuing a transaction, known since the last command
+                // that could change it.
+                let mut queuing: Option<bool> = None;
+
+                for msg in batch.drain(..) {
+                    if client.is_closed() {
+                        break;
+                    }
+
+                    let resp = match msg {
+                        IncomingMessage::Resp(resp) => resp,
+                        IncomingMessage::Rdb(_) => {
+                            println!("Received RDB file");
+                            continue;
+                        }
+                        IncomingMessage::Invalid(err) => {
+                            store.incr_error_replies();
+                            let bytes = Bytes::from(Resp::from(RedisError::from(err)).serialize());
+                            client.add_output_buf(bytes.len());
+                            if tx_by.send(bytes).await.is_err() {
+                                eprintln!("Receiver dropped");
+                            }
+                            client.kill();
+                            protocol_error = true;
+                            break;
+                        }
+                    };
+                    let size = resp.len();
+
+                    // Hooks see the command before it's parsed, and may rewrite or reject it.
+                    let mut call: Option<(CommandCall, Instant)> = None;
+                    let parsed = if store.hooks().is_empty() {
+                        Command::new(resp)
+                    } else {
+                        let started = Instant::now();
+                        let mut hooked = CommandCall {
+                            args: command_args(resp),
+                            addr,
+                            mode,
+                        };
+                        let parsed = store
+                            .hooks()
+                            .before(&mut hooked)
+                            .and_then(|_| Command::new(Resp::from(hooked.args.clone())));
+                        call = Some((hooked, started));
+                        parsed
+                    };
+
+                    match parsed {
+                        Ok(cmd) => {
+                            // Not while queuing, as MULTI rejects PSYNC.
+                            if cmd.store_connection() && !store.is_queuing(addr).await {
+                                store.subscribe(&client, tx_by.clone()).await;
+                            }
+
+                            let (tx, rx) = oneshot::channel::<Reply>();
+                            let ctx = ctx_builder.build(tx);
+                            let name = cmd.name();
+                            let executed = Instant::now();
+                            if cmd.batchable() {
+                                let known = match queuing {
+                                    Some(known) => known,
+                                    None => store.is_queuing(addr).await,
+                                };
+                                queuing = Some(known);
+                                cmd.execute_queuing(Arc::clone(&store), ctx, known).await;
+                            } else {
+                                queuing = None;
+                                if unsettled > 0 {
+                                    store.settle_batch(unsettled).await;
+                                    unsettled = 0;
                                 }
+                                cmd.execute(Arc::clone(&store), ctx).await;
+                            }
 
-                                let tx_by0 = tx_by.clone();
-                                let (tx, rx) = oneshot::channel::<OutgoingMessage>();
-
-                                tokio::spawn(async move {
-                                    match rx.await {
-                                        Ok(msg) => {
-                                            for bytes in msg.into_iter() {
-                                                if tx_by0.send(bytes).await.is_err() {
-                                                    eprintln!("Receiver dropped");
-                                                    break;
-                                                }
-                                            }
+                            // Replies are forwarded in the order of the commands, so that
+                            // pipelined commands get their replies in order.
+                            match rx.await {
+                                Ok(reply) => {
+                                    if let Some(name) = name {
+                                        store.record_latency(name, executed.elapsed());
+                                    }
+                                    // Serialized only now, as HELLO replies in the protocol
+                                    // it switches to.
+                                    let frames: Vec<Bytes> =
+                                        reply.into_message(client.protocol()).into_iter().collect();
+                                    if let Some((call, started)) = &call {
+                                        store.hooks().after(call, &frames, started.elapsed());
+                                    }
+                                    for bytes in frames {
+                                        if bytes.first() == Some(&b'-') {
+                                            store.incr_error_replies();
                                         }
-                                        Err(_) => {
-                                            eprintln!(
-                                                "Oneshot sender dropped before sending message!"
-                                            );
+                                        client.add_output_buf(bytes.len());
+                                        if tx_by.send(bytes).await.is_err() {
+                                            eprintln!("Receiver dropped");
+                                            break;
                                         }
                                     }
-                                });
-
-                                let ctx = ctx_builder.build(tx);
-                                cmd.execute(Arc::clone(&store), ctx).await;
-                                store.add_ack_offset(size).await;
+                                }
+                                Err(_) => {
+                                    eprintln!("Oneshot sender dropped before sending message!");
+                                }
                             }
-                            Err(err) => {
-                                eprintln!("Failed to get command from RESP. {err}");
+                            unsettled += size;
+                            store.incr_commands();
+                        }
+                        Err(err) => {
+                            eprintln!("Failed to get command from RESP. {err}");
+                            // The master doesn't expect replies to what it propagates.
+                            if mode != CommandMode::Sync {
+                                // As do other rejections, it aborts a transaction being
+                                // queued.
+                                store.abort_queuing(addr).await;
+                                store.incr_error_replies();
+                                let bytes = Bytes::from(Resp::from(err).serialize());
+                                client.add_output_buf(bytes.len());
+                                if tx_by.send(bytes).await.is_err() {
+                                    eprintln!("Receiver dropped");
+                                }
                             }
                         }
                     }
-                    IncomingMessage::Rdb(_) => {
-                        println!("Received RDB file");
-                    }
+                }
+                batch.clear();
+                store.settle_batch(unsettled).await;
+                if client.is_closed() || protocol_error {
+                    break;
+                }
+
+                if rx_in.is_empty() {
+                    client.reset_query_buf();
                 }
             }
             eprintln!("Channel closed. Stop reading IncomingMessage from {addr}");
+
+            if protocol_error {
+                // The writer stops once it has written the error, and the replies queued
+                // before it, to the peer still reading them.
+                drop(tx_by);
+                let _ = tokio::time::timeout(FLUSH_TIMEOUT, &mut writer).await;
+            }
+            // The peer is gone, so the replies still queued have nowhere to go.
+            writer.abort();
         });
 
-        Ok(())
+        Ok(handle)
+    }
+}
+
+/// The read half of a connection, read with the runtime or through io_uring.
+struct Reader {
+    rs: OwnedReadHalf,
+    uring: Option<Uring>,
+    fd: i32,
+}
+
+impl Reader {
+    async fn read(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        match &self.uring {
+            // The ring owns the buffer while reading. It stays empty if the read is dropped,
+            // which only happens as the connection closes.
+            Some(uring) => {
+                let (read, filled) = uring.recv(self.fd, mem::take(buf)).await?;
+                *buf = filled;
+                Ok(read)
+            }
+            None => self.rs.read(buf).await,
+        }
+    }
+}
+
+/// The size of the read buffer after a read of `read` bytes. A read filling the buffer
+/// doubles it, so that large payloads take fewer reads, and reads using a small part of
+/// a grown buffer halve it back toward `base`.
+fn next_buf_len(len: usize, read: usize, base: usize) -> usize {
+    if read == len {
+        (len * 2).min(base * MAX_BUF_GROWTH)
+    } else if read < len / 4 && len > base {
+        (len / 2).max(base)
+    } else {
+        len
     }
 }
 
-async fn ping(ws: &mut OwnedWriteHalf, rx: &mut Receiver<IncomingMessage>) -> RedisResult<()> {
+async fn ping(
+    ws: &mut OwnedWriteHalf,
+    rx: &mut Receiver<IncomingMessage>,
+    trace: Option<SocketAddr>,
+) -> RedisResult<()> {
     let msg = vec!["PING".to_string()];
-    send_resp(ws, msg).await?;
+    send_resp(ws, msg, trace).await?;
     let recv = rx
         .recv()
         .await
@@ -141,13 +428,14 @@ async fn repl_conf(
     ws: &mut OwnedWriteHalf,
     rx: &mut Receiver<IncomingMessage>,
     port: u16,
+    trace: Option<SocketAddr>,
 ) -> RedisResult<()> {
     let msg = vec![
         "REPLCONF".to_string(),
         "listening-port".to_string(),
         format!("{port}"),
     ];
-    send_resp(ws, msg).await?;
+    send_resp(ws, msg, trace).await?;
     let recv = rx
         .recv()
         .await
@@ -159,7 +447,7 @@ async fn repl_conf(
         "capa".to_string(),
         "psync2".to_string(),
     ];
-    send_resp(ws, msg).await?;
+    send_resp(ws, msg, trace).await?;
     let recv = rx
         .recv()
         .await
@@ -169,18 +457,57 @@ async fn repl_conf(
     Ok(())
 }
 
-async fn psync(ws: &mut OwnedWriteHalf, rx: &mut Receiver<IncomingMessage>) -> RedisResult<()> {
+/// Asks the master for a full resync, and returns the offset it resyncs at.
+async fn psync(
+    ws: &mut OwnedWriteHalf,
+    rx: &mut Receiver<IncomingMessage>,
+    trace: Option<SocketAddr>,
+) -> RedisResult<usize> {
     let msg = vec!["PSYNC".to_string(), "?".to_string(), "-1".to_string()];
-    send_resp(ws, msg).await?;
+    send_resp(ws, msg, trace).await?;
     let recv = rx
         .recv()
         .await
         .expect("Error expected receiving FULLRESYNC after sending PSYNC");
     println!("Received! PSYNC response: {recv}");
-    Ok(())
+    match recv {
+        IncomingMessage::Resp(Resp::SS(reply)) => reply
+            .split(' ')
+            .nth(2)
+            .and_then(|offset| offset.parse::<usize>().ok())
+            .ok_or_else(|| anyhow::anyhow!("Invalid FULLRESYNC reply '{reply}'").into()),
+        _ => Err(anyhow::anyhow!("Expected FULLRESYNC, got {recv}").into()),
+    }
 }
 
-async fn send_resp(ws: &mut OwnedWriteHalf, msg: Vec<String>) -> RedisResult<()> {
-    ws.write_all(&Resp::from(msg).serialize()).await?;
+/// Sends a handshake command to the master, tracing it for the master's address if given.
+async fn send_resp(
+    ws: &mut OwnedWriteHalf,
+    msg: Vec<String>,
+    trace: Option<SocketAddr>,
+) -> RedisResult<()> {
+    let msg = OutgoingMessage::from(Resp::from(msg));
+    if let Some(addr) = trace {
+        for frame in msg.frames() {
+            message::trace(addr, Direction::Out, frame);
+        }
+    }
+    msg.write_to(ws).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_sizes_read_buffers_adaptively() {
+        assert_eq!(next_buf_len(1024, 1024, 1024), 2048);
+        assert_eq!(next_buf_len(2048, 2048, 1024), 4096);
+        assert_eq!(next_buf_len(65536, 65536, 1024), 65536);
+        assert_eq!(next_buf_len(1024, 10, 1024), 1024);
+        assert_eq!(next_buf_len(4096, 3000, 1024), 4096);
+        assert_eq!(next_buf_len(4096, 10, 1024), 2048);
+        assert_eq!(next_buf_len(2048, 0, 1024), 1024);
+    }
+}
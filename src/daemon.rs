@@ -0,0 +1,108 @@
+use super::{Config, RedisResult};
+use std::fs::{self, File, OpenOptions};
+use std::path::PathBuf;
+
+const DEFAULT_PIDFILE: &str = "/var/run/redis.pid";
+
+/// Sets the process up as `logfile`, `daemonize` and `pidfile` ask. It has to be called
+/// before the runtime starts, since the child of a fork keeps only the calling thread.
+pub fn init(config: &Config) -> RedisResult<()> {
+    // Opened before detaching, so that a wrong path is still reported on the terminal.
+    let logfile = config
+        .logfile
+        .as_ref()
+        .map(|path| OpenOptions::new().create(true).append(true).open(path))
+        .transpose()?;
+
+    if config.daemonize {
+        sys::detach()?;
+        let devnull = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")?;
+        sys::redirect(&devnull, &[0])?;
+        sys::redirect(logfile.as_ref().unwrap_or(&devnull), &[1, 2])?;
+    } else if let Some(logfile) = &logfile {
+        sys::redirect(logfile, &[1, 2])?;
+    }
+
+    if let Some(path) = pidfile(config) {
+        // Failing to write the pidfile isn't fatal, as with redis-server.
+        if let Err(err) = fs::write(&path, format!("{}\n", std::process::id())) {
+            eprintln!("Failed to write PID file {}. {err}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Removes the pidfile written by `init`, as the server exits.
+pub fn cleanup(config: &Config) {
+    if let Some(path) = pidfile(config) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn pidfile(config: &Config) -> Option<PathBuf> {
+    match &config.pidfile {
+        Some(path) => Some(PathBuf::from(path)),
+        None if config.daemonize => Some(PathBuf::from(DEFAULT_PIDFILE)),
+        None => None,
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::{File, RedisResult};
+    use std::io;
+    use std::os::fd::AsRawFd;
+
+    extern "C" {
+        fn fork() -> i32;
+        fn setsid() -> i32;
+        fn dup2(src: i32, dst: i32) -> i32;
+    }
+
+    /// Forks into the background. The parent exits at once and the child leads a
+    /// session of its own, away from the terminal.
+    pub(super) fn detach() -> RedisResult<()> {
+        // SAFETY: no other thread has been started yet, so the child gets a consistent
+        // copy of the process.
+        match unsafe { fork() } {
+            -1 => Err(io::Error::last_os_error().into()),
+            0 => {
+                // SAFETY: setsid has no preconditions. It fails only for a process group
+                // leader, which a forked child never is.
+                if unsafe { setsid() } == -1 {
+                    return Err(io::Error::last_os_error().into());
+                }
+                Ok(())
+            }
+            _ => std::process::exit(0),
+        }
+    }
+
+    /// Points the file descriptors at the file.
+    pub(super) fn redirect(file: &File, fds: &[i32]) -> RedisResult<()> {
+        for fd in fds {
+            // SAFETY: both descriptors stay valid for the call; the file is borrowed and
+            // the standard ones are never closed.
+            if unsafe { dup2(file.as_raw_fd(), *fd) } == -1 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use super::{File, RedisResult};
+
+    pub(super) fn detach() -> RedisResult<()> {
+        Err(anyhow::anyhow!("daemonize isn't supported on this platform").into())
+    }
+
+    pub(super) fn redirect(_file: &File, _fds: &[i32]) -> RedisResult<()> {
+        Err(anyhow::anyhow!("logfile isn't supported on this platform").into())
+    }
+}
@@ -1,44 +1,198 @@
-use super::Resp;
+use super::{Resp, RespError};
 use thiserror::Error;
 
+/// Errors replied to clients. Each message starts with the error code Redis replies
+/// with, which is what clients match on; `code` gives the code alone.
 #[derive(Debug, Error)]
 pub enum RedisError {
-    #[error("Resp syntax error")]
-    RespSyntax,
+    #[error("ERR Protocol error: {0}")]
+    Protocol(#[from] RespError),
 
-    #[error("Failed to parse into string: {0}")]
+    #[error("ERR Failed to parse into string: {0}")]
     ParseString(#[from] std::str::Utf8Error),
 
-    #[error("Failed to parse into integer: {0}")]
+    #[error("ERR Failed to parse into integer: {0}")]
     ParseInt(#[from] std::num::ParseIntError),
 
-    #[error("Need {need} arguments, but got {got}")]
+    #[error("ERR Need {need} arguments, but got {got}")]
     LackOfArgs { need: usize, got: usize },
 
-    #[error("Mutex lock error: {0}")]
+    #[error("ERR Mutex lock error: {0}")]
     Lock(String),
 
-    #[error("Unknown command received")]
+    #[error("ERR unknown command")]
     UnknownCommand,
 
-    #[error("Unexpected encoding")]
+    #[error("ERR syntax error")]
+    Syntax,
+
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+
+    #[error("ERR no such key")]
+    NoSuchKey,
+
+    #[error("ERR Unexpected encoding")]
     Encoding,
 
-    #[error("IO error: {0}")]
+    #[error("ERR IO error: {0}")]
     Io(#[from] std::io::Error),
 
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+
     #[error("ERR The ID specified in XADD must be greater than 0-0")]
     InvalidStreamEntryId00,
 
     #[error("ERR The ID specified in XADD is equal or smaller than the target stream top item")]
     SmallerStreamEntryId,
 
+    #[error("BUSYKEY Target key name already exists.")]
+    BusyKey,
+
+    #[error("NOSCRIPT No matching script. Please use EVAL.")]
+    NoScript,
+
+    #[error("NOTBUSY No scripts in execution right now.")]
+    NotBusy,
+
+    #[error("READONLY You can't write against a read only replica.")]
+    ReadOnly,
+
+    #[error("EXECABORT Transaction discarded because of previous errors.")]
+    ExecAbort,
+
+    #[error("NOAUTH Authentication required.")]
+    NoAuth,
+
+    #[error("OOM command not allowed when used memory > 'maxmemory'.")]
+    Oom,
+
+    #[error("MOVED {slot} {addr}")]
+    Moved {
+        slot: u16,
+        addr: std::net::SocketAddr,
+    },
+
+    #[error("ASK {slot} {addr}")]
+    Ask {
+        slot: u16,
+        addr: std::net::SocketAddr,
+    },
+
+    #[error("CROSSSLOT Keys in request don't hash to the same slot")]
+    CrossSlot,
+
+    #[error("CLUSTERDOWN Hash slot not served")]
+    ClusterDown,
+
+    #[error(
+        "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSCRIPT."
+    )]
+    Busy,
+
+    #[error("LOADING Redis is loading the dataset in memory")]
+    Loading,
+
+    /// Any other error. Its message starts with its code, or gets ERR when replied.
     #[error("{0}")]
     Other(#[from] anyhow::Error),
 }
 
+/// The codes other errors may start with, besides ERR.
+const CODES: &[&str] = &[
+    "WRONGTYPE",
+    "BUSYKEY",
+    "NOSCRIPT",
+    "NOTBUSY",
+    "READONLY",
+    "EXECABORT",
+    "NOAUTH",
+    "NOPERM",
+    "NOPROTO",
+    "NOGROUP",
+    "WRONGPASS",
+    "OOM",
+    "MOVED",
+    "ASK",
+    "CROSSSLOT",
+    "CLUSTERDOWN",
+    "BUSY",
+    "IOERR",
+    "LOADING",
+    "MASTERDOWN",
+    "NOREPLICAS",
+];
+
+impl RedisError {
+    /// The error code, the first word of the reply.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::WrongType => "WRONGTYPE",
+            Self::BusyKey => "BUSYKEY",
+            Self::NoScript => "NOSCRIPT",
+            Self::NotBusy => "NOTBUSY",
+            Self::ReadOnly => "READONLY",
+            Self::ExecAbort => "EXECABORT",
+            Self::NoAuth => "NOAUTH",
+            Self::Oom => "OOM",
+            Self::Moved { .. } => "MOVED",
+            Self::Ask { .. } => "ASK",
+            Self::CrossSlot => "CROSSSLOT",
+            Self::ClusterDown => "CLUSTERDOWN",
+            Self::Busy => "BUSY",
+            Self::Loading => "LOADING",
+            Self::Other(err) => {
+                let msg = err.to_string();
+                let first = msg.split(' ').next().unwrap_or_default();
+                CODES
+                    .iter()
+                    .find(|code| **code == first)
+                    .copied()
+                    .unwrap_or("ERR")
+            }
+            _ => "ERR",
+        }
+    }
+}
+
 impl From<RedisError> for Resp {
     fn from(error: RedisError) -> Self {
-        Self::SE(format!("{error}"))
+        let msg = format!("{error}");
+        let code = error.code();
+        if msg.starts_with(code) && msg[code.len()..].starts_with(' ') {
+            Self::SE(msg)
+        } else {
+            Self::SE(format!("{code} {msg}"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_replies_errors_with_their_codes() {
+        let reply = |err: RedisError| match Resp::from(err) {
+            Resp::SE(msg) => msg,
+            resp => panic!("not an error: {resp:?}"),
+        };
+
+        assert_eq!(RedisError::WrongType.code(), "WRONGTYPE");
+        assert_eq!(reply(RedisError::Syntax), "ERR syntax error");
+        assert_eq!(
+            reply(RedisError::LackOfArgs { need: 2, got: 1 }),
+            "ERR Need 2 arguments, but got 1"
+        );
+
+        let other = RedisError::from(anyhow::anyhow!("IOERR error or timeout"));
+        assert_eq!(other.code(), "IOERR");
+        assert_eq!(reply(other), "IOERR error or timeout");
+
+        // Messages without a code get ERR.
+        let other = RedisError::from(anyhow::anyhow!("Connection closed"));
+        assert_eq!(other.code(), "ERR");
+        assert_eq!(reply(other), "ERR Connection closed");
     }
 }
@@ -0,0 +1,453 @@
+//! `--export-json <file>` writes the keys of the RDB file at `--dir`/`--dbfilename` as
+//! JSON, and `--import-json <file>` writes such JSON back as that RDB file. The JSON is
+//! an object with the version of the format and an array of the keys, one per line:
+//!
+//! ```text
+//! {"version":1,"keys":[
+//! {"key":"greeting","type":"string","value":"hello","expires_at":1767225600000},
+//! {"key":"clicks","type":"stream","value":[{"id":"1-0","fields":{"page":"home"}}]},
+//! {"key":"points","type":"vectorset","value":{"a":[1.0,0.5]}},
+//! {"key":"doc","type":"ReJSON-RL","value":{"tags":["x"]}},
+//! {"key":"seen","type":"MBbloom--","value":"<hex>"}
+//! ]}
+//! ```
+//!
+//! `type` is what TYPE replies for the key, and `expires_at` is the expiry in
+//! milliseconds since UNIX epoch, left out for keys that don't expire. Bloom filters,
+//! cuckoo filters and top-k sketches are kept as the hex of their data in RDB files,
+//! since the items added to them are gone. Vector sets and JSON documents aren't saved
+//! in RDB files, so they are left out of imports with a warning.
+use super::{
+    rdb::{self, Rdb},
+    value::{Json, RedisStream, StreamEntry, StreamEntryId, Value, VectorSet},
+    Config, RedisError, RedisResult,
+};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const VERSION: i64 = 1;
+
+/// Runs the export or the import asked by the arguments, giving the exit code of the
+/// process. None means neither was asked and the server should start.
+pub fn run(args: &[String]) -> Option<i32> {
+    let value_of = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .map(|i| args.get(i + 1).map(Path::new))
+    };
+
+    let result = match (value_of("--export-json"), value_of("--import-json")) {
+        (None, None) => return None,
+        (Some(Some(path)), None) => rdb_path(args).and_then(|rdb| export_file(&rdb, path)),
+        (None, Some(Some(path))) => rdb_path(args).and_then(|rdb| import_file(path, &rdb)),
+        (Some(_), Some(_)) => {
+            Err(anyhow::anyhow!("Only one of --export-json and --import-json can be given").into())
+        }
+        _ => Err(anyhow::anyhow!("Usage: --export-json <file> | --import-json <file>").into()),
+    };
+    match result {
+        Ok(done) => {
+            println!("{done}");
+            Some(0)
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            Some(1)
+        }
+    }
+}
+
+/// The RDB file the server would load with the same arguments.
+fn rdb_path(args: &[String]) -> RedisResult<PathBuf> {
+    let config = Config::new(args.to_vec())?;
+    let dir = config.dir.as_deref().unwrap_or(".");
+    let dbfilename = config.dbfilename.as_deref().unwrap_or("dump.rdb");
+    Ok(PathBuf::from(dir).join(dbfilename))
+}
+
+/// The RDB file at the path, or an empty one when there is none.
+fn read_rdb(path: &Path) -> RedisResult<Rdb> {
+    match File::open(path) {
+        Ok(f) => Ok(Rdb::new(f)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Rdb::default()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn export_file(rdb: &Path, out: &Path) -> RedisResult<String> {
+    let db = read_rdb(rdb)?.into_db();
+    let now = SystemTime::now();
+    fs::write(out, export(&db, now))?;
+    let keys = db.values().filter(|value| !value.expired(now)).count();
+    Ok(format!("{keys} keys exported to {}", out.display()))
+}
+
+/// Writes the keys as the RDB file, keeping the function libraries it already has.
+fn import_file(input: &Path, rdb: &Path) -> RedisResult<String> {
+    let mut db = import(&fs::read_to_string(input)?)?;
+    db.retain(|key, value| {
+        let saved = !matches!(value, Value::VectorSet(_) | Value::Json(_));
+        if !saved {
+            eprintln!(
+                "The {} at '{key}' can't be saved in an RDB file",
+                value.type_name()
+            );
+        }
+        saved
+    });
+
+    let functions = read_rdb(rdb)?.functions().to_vec();
+    let tmp = rdb.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    fs::write(&tmp, Rdb::dump(db.iter(), &functions, SystemTime::now()))?;
+    fs::rename(&tmp, rdb)?;
+    Ok(format!("{} keys imported to {}", db.len(), rdb.display()))
+}
+
+/// The keys not expired at `now` in the JSON format, ordered by key.
+pub(crate) fn export(db: &HashMap<String, Value>, now: SystemTime) -> String {
+    let mut keys: Vec<(&String, &Value)> =
+        db.iter().filter(|(_, value)| !value.expired(now)).collect();
+    keys.sort_by_key(|(key, _)| *key);
+
+    let lines: Vec<String> = keys
+        .into_iter()
+        .map(|(key, value)| key_json(key, value).to_string())
+        .collect();
+    if lines.is_empty() {
+        return format!("{{\"version\":{VERSION},\"keys\":[]}}\n");
+    }
+    format!(
+        "{{\"version\":{VERSION},\"keys\":[\n{}\n]}}\n",
+        lines.join(",\n")
+    )
+}
+
+fn key_json(key: &str, value: &Value) -> Json {
+    let mut pairs = vec![
+        ("key".to_string(), Json::String(key.into())),
+        ("type".to_string(), Json::String(value.type_name().into())),
+        ("value".to_string(), value_json(value)),
+    ];
+    if let Some(exp) = value.expiry() {
+        let millis = exp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        pairs.push(("expires_at".into(), Json::Int(millis)));
+    }
+    Json::Object(pairs)
+}
+
+fn value_json(value: &Value) -> Json {
+    match value {
+        Value::String { value, .. } => Json::String(value.clone()),
+        Value::List(list) => Json::Array(list.iter().cloned().map(Json::String).collect()),
+        Value::Hash(hash) => {
+            let mut fields: Vec<(String, Json)> = hash
+                .entries()
+                .map(|(field, value, _)| (field.clone(), Json::String(value.clone())))
+                .collect();
+            fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Json::Object(fields)
+        }
+        Value::Set(set) => {
+            let mut members: Vec<&String> = set.iter().collect();
+            members.sort_unstable();
+            Json::Array(members.into_iter().cloned().map(Json::String).collect())
+        }
+        Value::Stream(stream) => Json::Array(
+            stream
+                .entries()
+                .iter()
+                .map(|entry| {
+                    let mut fields: Vec<(String, Json)> = entry
+                        .values()
+                        .iter()
+                        .map(|(field, value)| (field.clone(), Json::String(value.clone())))
+                        .collect();
+                    fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    Json::Object(vec![
+                        ("id".into(), Json::String(entry.id().to_string())),
+                        ("fields".into(), Json::Object(fields)),
+                    ])
+                })
+                .collect(),
+        ),
+        Value::VectorSet(set) => {
+            let mut members: Vec<(String, Json)> = set
+                .members()
+                .map(|(member, vector)| {
+                    let vector = vector.iter().map(|v| Json::Float(f64::from(*v)));
+                    (member.clone(), Json::Array(vector.collect()))
+                })
+                .collect();
+            members.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Json::Object(members)
+        }
+        Value::Json(json) => json.clone(),
+        Value::Bloom(_) | Value::Cuckoo(_) | Value::TopK(_) => {
+            let mut buf = vec![];
+            rdb::encode_module(value, &mut buf);
+            Json::String(buf.iter().map(|b| format!("{b:02x}")).collect())
+        }
+    }
+}
+
+/// Reads the keys back from the JSON format.
+pub(crate) fn import(text: &str) -> RedisResult<HashMap<String, Value>> {
+    let json = Json::parse(text)?;
+    let bad_format = || RedisError::from(anyhow::anyhow!("Not a keyspace export"));
+    let Json::Object(pairs) = &json else {
+        return Err(bad_format());
+    };
+    match field(pairs, "version") {
+        Some(Json::Int(VERSION)) => {}
+        Some(Json::Int(version)) => {
+            return Err(anyhow::anyhow!("Unknown keyspace export version {version}").into());
+        }
+        _ => return Err(bad_format()),
+    }
+    let Some(Json::Array(keys)) = field(pairs, "keys") else {
+        return Err(bad_format());
+    };
+
+    let mut db = HashMap::new();
+    for (i, key) in keys.iter().enumerate() {
+        let (key, value) =
+            key_value(key).map_err(|err| anyhow::anyhow!("Bad key at index {i}. {err}"))?;
+        db.insert(key, value);
+    }
+    Ok(db)
+}
+
+fn field<'a>(pairs: &'a [(String, Json)], name: &str) -> Option<&'a Json> {
+    pairs.iter().find(|(key, _)| key == name).map(|(_, v)| v)
+}
+
+fn key_value(json: &Json) -> RedisResult<(String, Value)> {
+    let invalid = |what: &str| RedisError::from(anyhow::anyhow!("Invalid {what}"));
+    let Json::Object(pairs) = json else {
+        return Err(invalid("key"));
+    };
+    let Some(Json::String(key)) = field(pairs, "key") else {
+        return Err(invalid("key name"));
+    };
+    let Some(Json::String(kind)) = field(pairs, "type") else {
+        return Err(invalid("type"));
+    };
+    let value = field(pairs, "value").ok_or_else(|| invalid("value"))?;
+    let exp = match field(pairs, "expires_at") {
+        None => None,
+        Some(Json::Int(millis)) if *millis >= 0 => {
+            Some(UNIX_EPOCH + Duration::from_millis(*millis as u64))
+        }
+        Some(_) => return Err(invalid("expires_at")),
+    };
+    if exp.is_some() && kind != "string" {
+        return Err(anyhow::anyhow!("Only strings can expire, not the {kind} '{key}'").into());
+    }
+
+    let value = match (kind.as_str(), value) {
+        ("string", Json::String(value)) => Value::String {
+            value: value.clone(),
+            exp,
+        },
+        ("list", Json::Array(elements)) => Value::List(
+            elements
+                .iter()
+                .map(|element| match element {
+                    Json::String(element) => Ok(element.clone()),
+                    _ => Err(invalid("element")),
+                })
+                .collect::<RedisResult<VecDeque<String>>>()?,
+        ),
+        ("stream", Json::Array(entries)) => {
+            let mut stream = RedisStream::new();
+            for entry in entries {
+                stream.push(Arc::new(
+                    stream_entry(entry).ok_or_else(|| invalid("entry"))?,
+                ))?;
+            }
+            Value::Stream(stream)
+        }
+        ("vectorset", Json::Object(members)) => {
+            let dim = members.first().map(|(_, vector)| match vector {
+                Json::Array(values) => values.len(),
+                _ => 0,
+            });
+            let mut set = VectorSet::new(dim.ok_or_else(|| invalid("vector set"))?);
+            for (member, vector) in members {
+                let vector = float_array(vector).ok_or_else(|| invalid("vector"))?;
+                set.add(member.clone(), vector)?;
+            }
+            Value::VectorSet(set)
+        }
+        ("ReJSON-RL", json) => Value::Json(json.clone()),
+        (_, Json::String(hex))
+            if matches!(kind.as_str(), "MBbloom--" | "MBbloomCF" | "TopK-TYPE") =>
+        {
+            let bytes = from_hex(hex).ok_or_else(|| invalid("hex"))?;
+            let value: Value = rdb::read_module(&mut bytes.as_slice())?.into();
+            if value.type_name() != kind {
+                return Err(invalid("sketch"));
+            }
+            value
+        }
+        _ => return Err(anyhow::anyhow!("Invalid value for the type {kind}").into()),
+    };
+    Ok((key.clone(), value))
+}
+
+fn stream_entry(json: &Json) -> Option<StreamEntry> {
+    let Json::Object(pairs) = json else {
+        return None;
+    };
+    let Some(Json::String(id)) = field(pairs, "id") else {
+        return None;
+    };
+    let Some(Json::Object(fields)) = field(pairs, "fields") else {
+        return None;
+    };
+    let values = fields
+        .iter()
+        .map(|(field, value)| match value {
+            Json::String(value) => Some((field.clone(), value.clone())),
+            _ => None,
+        })
+        .collect::<Option<HashMap<String, String>>>()?;
+    Some(StreamEntry::new(StreamEntryId::parse(id).ok()?, values))
+}
+
+fn float_array(json: &Json) -> Option<Vec<f32>> {
+    let Json::Array(values) = json else {
+        return None;
+    };
+    values
+        .iter()
+        .map(|value| match value {
+            Json::Int(num) => Some(*num as f32),
+            Json::Float(num) => Some(*num as f32),
+            _ => None,
+        })
+        .collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::BloomFilter;
+
+    #[test]
+    fn it_exports_and_imports_keys() {
+        let mut db: HashMap<String, Value> = HashMap::new();
+        let exp = UNIX_EPOCH + Duration::from_millis(2_000);
+        db.insert(
+            "greeting".into(),
+            Value::String {
+                value: "hello \"you\"".into(),
+                exp: Some(exp),
+            },
+        );
+        db.insert(
+            "gone".into(),
+            Value::String {
+                value: "bye".into(),
+                exp: Some(UNIX_EPOCH),
+            },
+        );
+        let mut stream = RedisStream::new();
+        let fields = HashMap::from([("page".to_string(), "home".to_string())]);
+        let entry = StreamEntry::new(StreamEntryId::new(1, 0), fields);
+        stream.push(Arc::new(entry)).unwrap();
+        db.insert("clicks".into(), Value::Stream(stream));
+        let mut set = VectorSet::new(2);
+        set.add("a".into(), vec![1.0, 0.1]).unwrap();
+        db.insert("points".into(), Value::VectorSet(set));
+        db.insert(
+            "doc".into(),
+            Value::Json(Json::parse(r#"{"a":[1,2.5]}"#).unwrap()),
+        );
+        let mut bloom = BloomFilter::new(0.01, 10, Some(2)).unwrap();
+        bloom.add("x").unwrap();
+        db.insert("seen".into(), Value::Bloom(bloom.clone()));
+        let list = VecDeque::from(["a".to_string(), "b".to_string()]);
+        db.insert("queue".into(), Value::List(list));
+
+        let now = UNIX_EPOCH + Duration::from_millis(1_000);
+        let text = export(&db, now);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], r#"{"version":1,"keys":["#);
+        assert_eq!(
+            lines[1],
+            r#"{"key":"clicks","type":"stream","value":[{"id":"1-0","fields":{"page":"home"}}]},"#
+        );
+        assert_eq!(
+            lines[2],
+            r#"{"key":"doc","type":"ReJSON-RL","value":{"a":[1,2.5]}},"#
+        );
+        assert_eq!(
+            lines[3],
+            r#"{"key":"greeting","type":"string","value":"hello \"you\"","expires_at":2000},"#
+        );
+        assert_eq!(
+            lines[5],
+            r#"{"key":"queue","type":"list","value":["a","b"]},"#
+        );
+        assert_eq!(lines.len(), 8);
+
+        let imported = import(&text).unwrap();
+        assert_eq!(imported.len(), 6);
+        assert_eq!(export(&imported, now), text);
+        assert!(matches!(&imported["seen"], Value::Bloom(filter) if *filter == bloom));
+
+        assert_eq!(
+            export(&HashMap::new(), now),
+            "{\"version\":1,\"keys\":[]}\n"
+        );
+        assert!(import(r#"{"version":2,"keys":[]}"#).is_err());
+        assert!(import(r#"{"version":1,"keys":[{"key":"a","type":"set","value":[]}]}"#).is_err());
+        assert!(
+            import(r#"{"version":1,"keys":[{"key":"a","type":"MBbloom--","value":"0"}]}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn it_imports_into_the_rdb_file() {
+        let dir = std::env::temp_dir().join(format!("redis-export-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let json = dir.join("keys.json");
+        let rdb = dir.join("dump.rdb");
+        fs::write(
+            &json,
+            r#"{"version":1,"keys":[{"key":"foo","type":"string","value":"bar"}]}"#,
+        )
+        .unwrap();
+
+        import_file(&json, &rdb).unwrap();
+        let db = read_rdb(&rdb).unwrap().into_db();
+        assert!(matches!(&db["foo"], Value::String { value, exp: None } if value == "bar"));
+
+        let out = dir.join("out.json");
+        export_file(&rdb, &out).unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "{\"version\":1,\"keys\":[\n{\"key\":\"foo\",\"type\":\"string\",\"value\":\"bar\"}\n]}\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+/// A command with subcommands, and what `<command> HELP` replies about each of them:
+/// its usage and the lines describing it.
+struct CommandHelp {
+    name: &'static str,
+    subcommands: &'static [(&'static str, &'static [&'static str])],
+}
+
+const COMMANDS: &[CommandHelp] = &[
+    CommandHelp {
+        name: "acl",
+        subcommands: &[
+            ("DELUSER <username> [<username> ...]", &["Delete a list of users."]),
+            ("LIST", &["Show users details in config file format."]),
+            ("LOAD", &["Reload users from the ACL file."]),
+            ("SAVE", &["Save the current config to the ACL file."]),
+            (
+                "SETUSER <username> <attribute> [<attribute> ...]",
+                &["Create or modify a user with the specified attributes."],
+            ),
+            ("USERS", &["List all the registered usernames."]),
+            ("WHOAMI", &["Return the current connection username."]),
+        ],
+    },
+    CommandHelp {
+        name: "client",
+        subcommands: &[
+            (
+                "KILL <ip:port>",
+                &["Kill connection made from <ip:port>."],
+            ),
+            (
+                "KILL <option> <value> [<option> <value> [...]]",
+                &[
+                    "Kill connections. Options are:",
+                    "* ADDR (<ip:port>|<unixsocket>:0)",
+                    "  Kill connections made from the specified address",
+                    "* ID <client-id>",
+                    "  Kill connections by client id.",
+                    "* SKIPME (YES|NO)",
+                    "  Skip killing current connection (default: yes).",
+                ],
+            ),
+            (
+                "NO-EVICT (ON|OFF)",
+                &["Protect current client connection from eviction."],
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "cluster",
+        subcommands: &[
+            (
+                "COUNTKEYSINSLOT <slot>",
+                &["Return the number of keys in <slot>."],
+            ),
+            (
+                "GETKEYSINSLOT <slot> <count>",
+                &["Return key names stored by current node in a slot."],
+            ),
+            (
+                "FAILOVER [FORCE|TAKEOVER]",
+                &["Promote current replica node to being a master."],
+            ),
+            ("INFO", &["Return information about the cluster."]),
+            (
+                "KEYSLOT <key>",
+                &["Return the hash slot for <key>."],
+            ),
+            (
+                "MEET <ip> <port> [<bus-port>]",
+                &["Connect nodes into a working cluster."],
+            ),
+            ("MYID", &["Return the node id."]),
+            ("NODES", &["Return cluster configuration seen by node."]),
+            (
+                "SETSLOT <slot> (IMPORTING <node-id>|MIGRATING <node-id>|STABLE|NODE <node-id>)",
+                &["Set slot state."],
+            ),
+            (
+                "SHARDS",
+                &["Return information about slot range mappings and the nodes associated with them."],
+            ),
+            (
+                "SLOTS",
+                &["Return information about slots range mappings."],
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "config",
+        subcommands: &[
+            (
+                "GET <pattern>",
+                &["Return parameters matching the glob-like <pattern> and their values."],
+            ),
+            (
+                "SET <directive> <value> [<directive> <value> ...]",
+                &["Set the configuration <directive> to <value>."],
+            ),
+            (
+                "RESETSTAT",
+                &["Reset statistics reported by the INFO command."],
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "debug",
+        subcommands: &[
+            (
+                "CHANGE-REPL-ID",
+                &["Change the replication IDs of the instance."],
+            ),
+            ("LISTPACK <key>", &["Show low level info about the listpack encoding of <key>."]),
+            (
+                "OBJECT <key>",
+                &["Show low level info about the <key> and associated value."],
+            ),
+            (
+                "QUICKLIST-PACKED-THRESHOLD <size>",
+                &["Sets the threshold for elements to be inserted as plain vs packed nodes."],
+            ),
+            ("RELOAD-CONFIG", &["Read the config file again."]),
+            (
+                "SET-ACTIVE-EXPIRE (0|1)",
+                &["Setting it to 0 disables expiring keys in background when they are not accessed."],
+            ),
+            ("SLEEP <seconds>", &["Stop the server for <seconds>. Decimals allowed."]),
+        ],
+    },
+    CommandHelp {
+        name: "function",
+        subcommands: &[
+            (
+                "LOAD [REPLACE] <FUNCTION CODE>",
+                &[
+                    "Create a new library with the given library name and code.",
+                    "With REPLACE, a library of the same name is replaced.",
+                ],
+            ),
+            (
+                "DELETE <LIBRARY NAME>",
+                &["Delete the given library."],
+            ),
+            (
+                "LIST [LIBRARYNAME PATTERN] [WITHCODE]",
+                &["Return general information on all the libraries."],
+            ),
+            ("FLUSH [ASYNC|SYNC]", &["Delete all the libraries."]),
+            (
+                "KILL",
+                &["Kill the current running function."],
+            ),
+            (
+                "DUMP",
+                &["Return a serialized payload representing the current libraries."],
+            ),
+            (
+                "RESTORE <PAYLOAD> [FLUSH|APPEND|REPLACE]",
+                &["Restore the libraries represented by the given payload."],
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "object",
+        subcommands: &[(
+            "ENCODING <key>",
+            &["Return the kind of internal representation used in order to store the value associated with a <key>."],
+        )],
+    },
+    CommandHelp {
+        name: "script",
+        subcommands: &[
+            (
+                "EXISTS <sha1> [<sha1> ...]",
+                &["Return information about the existence of the scripts in the script cache."],
+            ),
+            ("FLUSH [ASYNC|SYNC]", &["Flush the Lua scripts cache."]),
+            (
+                "KILL",
+                &["Kill the currently executing Lua script."],
+            ),
+            (
+                "LOAD <script>",
+                &["Load a script into the scripts cache without executing it."],
+            ),
+        ],
+    },
+];
+
+/// The name of the command when it has subcommands to list with HELP, lowercase.
+pub(crate) fn command(name: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .find(|cmd| cmd.name.eq_ignore_ascii_case(name))
+        .map(|cmd| cmd.name)
+}
+
+/// The lines `<command> HELP` replies: the usage of every subcommand followed by its
+/// description, indented, and HELP itself last.
+pub(crate) fn lines(name: &str) -> Vec<String> {
+    let Some(cmd) = COMMANDS
+        .iter()
+        .find(|cmd| cmd.name.eq_ignore_ascii_case(name))
+    else {
+        return vec![];
+    };
+    let mut lines = vec![format!(
+        "{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+        cmd.name.to_uppercase()
+    )];
+    let help: (&str, &[&str]) = ("HELP", &["Print this help."]);
+    for (usage, description) in cmd.subcommands.iter().chain([&help]) {
+        lines.push(usage.to_string());
+        lines.extend(description.iter().map(|line| format!("    {line}")));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_lists_subcommands() {
+        let lines = lines("Config");
+        assert_eq!(
+            lines[0],
+            "CONFIG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:"
+        );
+        assert_eq!(lines[1], "GET <pattern>");
+        assert!(lines[2].starts_with("    Return parameters"));
+        assert_eq!(lines[lines.len() - 2..], ["HELP", "    Print this help."]);
+
+        assert_eq!(command("OBJECT"), Some("object"));
+        assert_eq!(command("GET"), None);
+        assert!(super::lines("GET").is_empty());
+    }
+}
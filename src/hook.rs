@@ -0,0 +1,197 @@
+use super::{CommandMode, RedisResult};
+use bytes::Bytes;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// A command on its way to be run, as hooks see it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandCall {
+    /// The command name and its arguments. Hooks running before the command may rewrite
+    /// them, and the command is parsed from what they leave.
+    pub args: Vec<String>,
+    /// The client sending the command. Commands called from scripts have the address of
+    /// the client running the script.
+    pub addr: SocketAddr,
+    /// Sync for the commands a replica gets from its master.
+    pub mode: CommandMode,
+}
+
+/// Code run around every command the server dispatches, whether sent by a client,
+/// propagated by the master or called from a script, registered with
+/// [`Store::add_hook`](crate::Store::add_hook).
+///
+/// Hooks run in the order they were registered before the command, and in the reverse
+/// order after it, so that the first one registered wraps all the others.
+pub trait CommandHook: Send + Sync {
+    /// Runs before the command is parsed. An error rejects the command: it isn't run, and
+    /// the error is replied in its place.
+    fn before(&self, _call: &mut CommandCall) -> RedisResult<()> {
+        Ok(())
+    }
+
+    /// Runs once the command has replied, with the frames of the reply and the time from
+    /// the first hook to the reply.
+    fn after(&self, _call: &CommandCall, _reply: &[Bytes], _elapsed: Duration) {}
+}
+
+/// The hooks registered on a store.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    hooks: RwLock<Vec<Arc<dyn CommandHook>>>,
+    /// Whether any hook is registered, checked without taking the lock since most
+    /// servers have none.
+    any: AtomicBool,
+}
+
+impl Hooks {
+    pub(crate) fn add(&self, hook: Arc<dyn CommandHook>) {
+        if let Ok(mut hooks) = self.hooks.write() {
+            hooks.push(hook);
+            self.any.store(true, Ordering::Release);
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        !self.any.load(Ordering::Acquire)
+    }
+
+    /// Runs the hooks before the command, stopping at the first one rejecting it.
+    pub(crate) fn before(&self, call: &mut CommandCall) -> RedisResult<()> {
+        for hook in self.snapshot() {
+            hook.before(call)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn after(&self, call: &CommandCall, reply: &[Bytes], elapsed: Duration) {
+        for hook in self.snapshot().iter().rev() {
+            hook.after(call, reply, elapsed);
+        }
+    }
+
+    /// The hooks are copied out, so that none runs with the lock held.
+    fn snapshot(&self) -> Vec<Arc<dyn CommandHook>> {
+        self.hooks
+            .read()
+            .map(|hooks| hooks.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.hooks.read().map(|hooks| hooks.len()).unwrap_or(0);
+        f.debug_struct("Hooks").field("count", &count).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::Node, Resp};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+
+    /// Records the order it runs in, and rejects one command.
+    struct Recorder {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl CommandHook for Recorder {
+        fn before(&self, call: &mut CommandCall) -> RedisResult<()> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} before", self.name));
+            if call.args[0] == "FLUSHALL" {
+                return Err(anyhow::anyhow!("ERR rejected by {}", self.name).into());
+            }
+            call.args[0] = call.args[0].to_uppercase();
+            Ok(())
+        }
+
+        fn after(&self, _call: &CommandCall, _reply: &[Bytes], _elapsed: Duration) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} after", self.name));
+        }
+    }
+
+    #[test]
+    fn it_runs_hooks_around_commands_in_order() {
+        let hooks = Hooks::default();
+        assert!(hooks.is_empty());
+        let log = Arc::new(Mutex::new(vec![]));
+        for name in ["outer", "inner"] {
+            let log = Arc::clone(&log);
+            hooks.add(Arc::new(Recorder { name, log }));
+        }
+        assert!(!hooks.is_empty());
+
+        let mut call = CommandCall {
+            args: vec!["get".into(), "foo".into()],
+            addr: "127.0.0.1:50000".parse().unwrap(),
+            mode: CommandMode::Normal,
+        };
+        hooks.before(&mut call).unwrap();
+        assert_eq!(call.args, vec!["GET", "foo"]);
+        hooks.after(&call, &[], Duration::ZERO);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["outer before", "inner before", "inner after", "outer after"]
+        );
+
+        log.lock().unwrap().clear();
+        call.args = vec!["FLUSHALL".into()];
+        let err = hooks.before(&mut call).unwrap_err();
+        assert_eq!(err.to_string(), "ERR rejected by outer");
+        assert_eq!(*log.lock().unwrap(), vec!["outer before"]);
+    }
+
+    /// Keeps clients off `secret:` keys and counts the replies sent.
+    #[derive(Default)]
+    struct Guard {
+        replies: AtomicUsize,
+    }
+
+    impl CommandHook for Guard {
+        fn before(&self, call: &mut CommandCall) -> RedisResult<()> {
+            match call.args.get(1) {
+                Some(key) if key.starts_with("secret:") => {
+                    Err(anyhow::anyhow!("NOPERM no access to {key}").into())
+                }
+                _ => Ok(()),
+            }
+        }
+
+        fn after(&self, _call: &CommandCall, _reply: &[Bytes], _elapsed: Duration) {
+            self.replies.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_runs_hooks_around_dispatched_commands() {
+        let node = Node::start(&[]).await.unwrap();
+        let guard = Arc::new(Guard::default());
+        node.store
+            .add_hook(Arc::clone(&guard) as Arc<dyn CommandHook>);
+
+        let mut client = node.connect().await.unwrap();
+        let reply = client.call(&["SET", "secret:a", "1"]).await.unwrap();
+        assert_eq!(reply, Resp::SE("NOPERM no access to secret:a".into()));
+        assert!(node.store.get("secret:a").await.is_none());
+
+        client.call(&["SET", "public", "1"]).await.unwrap();
+        let script = "return redis.pcall('GET', 'secret:b')['err']";
+        let reply = client.call(&["EVAL", script, "0"]).await.unwrap();
+        assert_eq!(reply, Resp::BS(Some("NOPERM no access to secret:b".into())));
+
+        // The rejected commands never replied through the hooks; SET and EVAL did.
+        assert_eq!(guard.replies.load(Ordering::Relaxed), 2);
+    }
+}
@@ -1,20 +1,49 @@
+mod args;
+pub mod benchmark;
+pub mod check;
+pub mod cli;
+mod clock;
+mod cluster;
 mod cmd;
 mod config;
 mod connection;
+pub mod daemon;
 mod error;
+pub mod export;
+mod help;
+mod hook;
+mod manager;
 mod message;
 mod rdb;
 mod resp;
+mod script;
 mod store;
+#[cfg(test)]
+mod testing;
+mod uring;
 mod utils;
 mod value;
 
-pub use cmd::{Command, CommandMode, Context};
-pub use config::Config;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use cluster::SetSlot;
+pub use cmd::{
+    AclCommand, ClusterCommand, Command, CommandMode, Context, DebugCommand, Expiry,
+    FunctionCommand, ScriptCommand,
+};
+pub use config::{AppendFsync, Config, EnableOption, IoBackend, LogLevel};
 pub use connection::Connection;
 pub use error::RedisError;
-pub use message::{IncomingMessage, OutgoingMessage};
-pub use resp::Resp;
-pub use store::Store;
+pub use hook::{CommandCall, CommandHook};
+pub use manager::{bind_listeners, ConnectionManager};
+pub use message::{IncomingMessage, OutgoingMessage, Reply};
+pub use resp::{Protocol, Resp, RespError};
+pub use store::{
+    Blocked, Client, KeyEvent, KeyRemoval, Notification, RemovalReason, RestorePolicy, ServerState,
+    Store, Subscription, Unblocked,
+};
+pub use value::{
+    BloomFilter, CuckooFilter, DeletePolicy, ExpireCond, Json, JsonPath, RedisHash, RedisStream,
+    SetCond, StreamEntry, StreamEntryId, TopK, Value, ValueType, VectorQuery, VectorSet,
+};
 pub type RedisResult<T> = Result<T, RedisError>;
 pub const BUF_SIZE: usize = 1024;
@@ -1,33 +1,103 @@
 use redis_starter_rust as rss;
-use rss::{CommandMode, Config, Connection, RedisResult, Store};
+use rss::{CommandMode, Config, Connection, ConnectionManager, RedisError, RedisResult, Store};
 use std::env;
 use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
 
-#[tokio::main]
-async fn main() {
+fn main() {
     let args: Vec<String> = env::args().collect();
-    let config = Config::new(args);
+    if let Some(code) = rss::check::run(&args)
+        .or_else(|| rss::export::run(&args))
+        .or_else(|| rss::benchmark::run(&args))
+    {
+        std::process::exit(code);
+    }
+
+    let config = match Config::new(args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+
+    // The runtime starts only once the process has been daemonized.
+    if let Err(err) = rss::daemon::init(&config) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
 
-    if let Err(err) = serve(config).await {
+    let result = tokio::runtime::Runtime::new()
+        .map_err(RedisError::from)
+        .and_then(|runtime| runtime.block_on(serve(config.clone())));
+    if let Err(err) = result {
         eprintln!("{err}");
     }
+    rss::daemon::cleanup(&config);
 }
 
 async fn serve(config: Config) -> RedisResult<()> {
-    let listener = TcpListener::bind(config.socket_addr()).await?;
+    let listeners = rss::bind_listeners(config.socket_addr(), config.acceptors)?;
     let store = Arc::new(Store::new(&config)?);
 
+    // The listeners share one manager, so that maxclients counts every connection. They
+    // accept while the dataset loads, replying LOADING to commands until it's done.
+    let manager = Arc::new(Mutex::new(ConnectionManager::new(
+        Arc::clone(&store),
+        config.maxclients,
+    )));
+    let mut acceptors = JoinSet::new();
+    for listener in listeners {
+        acceptors.spawn(accept_loop(listener, Arc::clone(&manager)));
+    }
+    if let Err(err) = store.load().await {
+        acceptors.abort_all();
+        return Err(err);
+    }
+
+    tokio::spawn(Arc::clone(&store).cron());
+    tokio::spawn(Arc::clone(&store).cluster_bus());
+    tokio::spawn(reload_on_sighup(Arc::clone(&store)));
+
     if let Some(addr) = config.master_addr() {
         let stream = TcpStream::connect(addr).await?;
         let conn = Connection::new(stream, CommandMode::Sync);
         conn.start_streaming(&store).await?;
     }
 
+    let result = tokio::select! {
+        Some(res) = acceptors.join_next() => res.unwrap_or(Ok(())),
+        _ = store.shutdown_requested() => Ok(()),
+    };
+    acceptors.abort_all();
+
+    manager.lock().await.close_all().await;
+    result?;
+    println!("Redis is now ready to exit, bye bye...");
+    Ok(())
+}
+
+/// Accepts connections until the listener fails.
+async fn accept_loop(
+    listener: TcpListener,
+    manager: Arc<Mutex<ConnectionManager>>,
+) -> RedisResult<()> {
     while let Ok((stream, _)) = listener.accept().await {
-        let conn = Connection::new(stream, CommandMode::Normal);
-        conn.start_streaming(&store).await?;
+        manager.lock().await.accept(stream).await?;
     }
+    Ok(())
+}
 
+/// Reloads the config file each time the process gets SIGHUP.
+async fn reload_on_sighup(store: Arc<Store>) -> RedisResult<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        if let Err(err) = store.reload_config().await {
+            eprintln!("Failed to reload the config. {err}");
+        }
+    }
     Ok(())
 }
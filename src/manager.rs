@@ -0,0 +1,126 @@
+use super::{CommandMode, Connection, RedisResult, Store};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+/// How long closing connections get to finish their commands before they are aborted.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The number of pending connections each listener queues.
+const BACKLOG: u32 = 1024;
+
+/// Binds `count` listeners to the address. More than one share the port through
+/// SO_REUSEPORT, and all take the port of the first, so that port 0 works for them too.
+pub fn bind_listeners(addr: SocketAddr, count: usize) -> RedisResult<Vec<TcpListener>> {
+    let first = bind(addr, count > 1)?;
+    let addr = first.local_addr()?;
+
+    let mut listeners = vec![first];
+    for _ in 1..count {
+        listeners.push(bind(addr, true)?);
+    }
+    Ok(listeners)
+}
+
+fn bind(addr: SocketAddr, reuseport: bool) -> RedisResult<TcpListener> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    socket.set_reuseaddr(true)?;
+    if reuseport {
+        socket.set_reuseport(true)?;
+    }
+    socket.bind(addr)?;
+    Ok(socket.listen(BACKLOG)?)
+}
+
+/// Owns the tasks of the accepted connections, so that they can be counted against
+/// `maxclients` and closed together when the server stops.
+#[derive(Debug)]
+pub struct ConnectionManager {
+    store: Arc<Store>,
+    maxclients: usize,
+    tasks: HashMap<SocketAddr, JoinHandle<()>>,
+}
+
+impl ConnectionManager {
+    pub fn new(store: Arc<Store>, maxclients: usize) -> Self {
+        Self {
+            store,
+            maxclients,
+            tasks: HashMap::new(),
+        }
+    }
+
+    /// Starts serving an accepted connection, or refuses it once `maxclients`
+    /// connections are open.
+    pub async fn accept(&mut self, mut stream: TcpStream) -> RedisResult<()> {
+        self.reap();
+        if self.tasks.len() >= self.maxclients {
+            stream
+                .write_all(b"-ERR max number of clients reached\r\n")
+                .await?;
+            return Ok(());
+        }
+
+        let addr = stream.peer_addr()?;
+        let conn = Connection::new(stream, CommandMode::Normal);
+        let handle = conn.start_streaming(&self.store).await?;
+        self.tasks.insert(addr, handle);
+        Ok(())
+    }
+
+    /// Drops the tasks of the connections which have been closed.
+    pub fn reap(&mut self) {
+        self.tasks.retain(|_, handle| !handle.is_finished());
+    }
+
+    /// The number of open connections.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Closes every connection, giving the commands they are running a while to finish.
+    pub async fn close_all(&mut self) {
+        self.store.kill_clients(|_| true).await;
+
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        for (addr, mut handle) in self.tasks.drain() {
+            if time::timeout_at(deadline, &mut handle).await.is_err() {
+                eprintln!("Connection {addr} didn't close in time. Aborting it");
+                handle.abort();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_binds_listeners_to_one_port() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let listeners = bind_listeners(addr, 3).unwrap();
+        assert_eq!(listeners.len(), 3);
+
+        let port = listeners[0].local_addr().unwrap().port();
+        assert_ne!(port, 0);
+        assert!(listeners
+            .iter()
+            .all(|l| l.local_addr().unwrap().port() == port));
+
+        TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    }
+}
@@ -1,52 +1,229 @@
 use super::{
     rdb::Rdb,
+    resp::{MAX_BULK_LEN, MAX_DEPTH},
     utils::{self, Tokens},
-    RedisResult, Resp,
+    Protocol, RedisError, RedisResult, Resp, RespError,
 };
+use bytes::Bytes;
 use std::fmt;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
+use std::io::{self, IoSlice};
+use std::net::SocketAddr;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Frames handed to a single vectored write at most.
+const MAX_IOV: usize = 64;
+
+/// Bytes of a frame a protocol trace shows at most.
+const TRACE_PREVIEW: usize = 64;
 
 #[derive(Debug, Clone)]
 pub enum IncomingMessage {
     Resp(Resp),
     Rdb(Rdb),
+    /// Bytes which aren't RESP, after which nothing more is read from the connection.
+    Invalid(RespError),
 }
 
 impl IncomingMessage {
     pub fn from_buffer(buf: &[u8]) -> RedisResult<Vec<Self>> {
+        let messages = Self::frames(buf)?;
+        Ok(messages.into_iter().map(|(message, _)| message).collect())
+    }
+
+    /// Parses the messages of the buffer, each with the bytes it was read from.
+    pub(crate) fn frames(buf: &[u8]) -> RedisResult<Vec<(Self, &[u8])>> {
         let mut tokens = Tokens::new(buf);
-        let mut messages: Vec<Self> = vec![];
+        let mut frames = vec![];
 
         while !tokens.finished() {
-            let message = Self::from_tokens(&mut tokens)?;
-            messages.push(message);
+            let start = buf.len() - tokens.rest().len();
+            let message = Self::from_tokens(&mut tokens, MAX_BULK_LEN)?;
+            let end = buf.len() - tokens.rest().len();
+            frames.push((message, &buf[start..end]));
         }
 
-        Ok(messages)
+        Ok(frames)
     }
 
-    fn from_tokens(tokens: &mut Tokens<'_>) -> RedisResult<Self> {
+    fn from_tokens(tokens: &mut Tokens<'_>, max_bulk_len: usize) -> RedisResult<Self> {
         if tokens.starts_with(b"*") || tokens.starts_with(b"+") {
             // Incoming message can be a RESP Simple String when handshaking.
             // Except for that, it is always an RESP Array.
-            let resp = Resp::from_tokens(tokens)?;
+            let resp = Resp::from_tokens_within(tokens, max_bulk_len)?;
             Ok(Self::Resp(resp))
         } else if tokens.starts_with(b"$") {
             // Incoming message as RDB is like "$<size>\r\n<contents>".
-            let size = tokens
-                .next()
-                .map(|token| utils::parse_usize(&token[1..]))
-                .transpose()?
-                .ok_or(anyhow::anyhow!("Failed to parse RDB file size"))?;
-
-            let contents = tokens
-                .proceed(size)
-                .ok_or(anyhow::anyhow!("Failed to get RDB file contents"))?;
+            let line = tokens.line().ok_or(RespError::Incomplete)?;
+            let size = utils::parse_usize(&line[1..]).map_err(|_| RespError::InvalidBulkLength)?;
+            let contents = tokens.take(size).ok_or(RespError::Incomplete)?;
 
             Ok(Self::Rdb(Rdb::new(contents)))
         } else {
-            Err(anyhow::anyhow!("Neither RESP nor RDB parsed").into())
+            // Neither RESP nor RDB, such as an inline command, is a protocol error, so that
+            // the client is told and disconnected.
+            let kind = tokens.rest().first().copied().unwrap_or_default();
+            Err(RespError::InvalidType(kind).into())
+        }
+    }
+}
+
+/// Splits what a connection reads into messages. A message cut by the end of a read is
+/// kept until the reads completing it come.
+pub(crate) struct MessageDecoder {
+    buf: Vec<u8>,
+    /// Bulk strings longer than this are a protocol error. RDB files aren't limited.
+    max_bulk_len: usize,
+    /// How far the message at the front of the buffer is known to go.
+    scan: Scan,
+}
+
+impl Default for MessageDecoder {
+    fn default() -> Self {
+        Self::new(MAX_BULK_LEN)
+    }
+}
+
+impl MessageDecoder {
+    pub(crate) fn new(max_bulk_len: usize) -> Self {
+        Self {
+            buf: vec![],
+            max_bulk_len,
+            scan: Scan::default(),
+        }
+    }
+
+    /// Appends the bytes read, and returns the messages they complete, each with the
+    /// bytes it was read from. Bytes which can't be parsed are dropped with everything
+    /// buffered, as there's no telling where the next message starts.
+    pub(crate) fn feed(&mut self, bytes: &[u8]) -> RedisResult<Vec<(IncomingMessage, Bytes)>> {
+        self.buf.extend_from_slice(bytes);
+
+        let mut messages = vec![];
+        let mut ends = vec![];
+        let mut start = 0;
+        while start < self.buf.len() {
+            // A message is parsed once it's all there, or to tell what's wrong with it.
+            if let Scanned::Incomplete = self.scan.resume(&self.buf[start..], self.max_bulk_len) {
+                break;
+            }
+            let mut tokens = Tokens::new(&self.buf[start..]);
+            match IncomingMessage::from_tokens(&mut tokens, self.max_bulk_len) {
+                Ok(message) => {
+                    messages.push(message);
+                    start = self.buf.len() - tokens.rest().len();
+                    ends.push(start);
+                    self.scan = Scan::default();
+                }
+                Err(RedisError::Protocol(RespError::Incomplete)) => break,
+                Err(err) => {
+                    self.buf.clear();
+                    self.scan = Scan::default();
+                    return Err(err);
+                }
+            }
+        }
+
+        let parsed = Bytes::copy_from_slice(&self.buf[..start]);
+        self.buf.drain(..start);
+
+        let starts = std::iter::once(0).chain(ends.iter().copied());
+        let frames = starts
+            .zip(ends.iter())
+            .map(|(start, end)| parsed.slice(start..*end));
+        Ok(messages.into_iter().zip(frames).collect())
+    }
+
+    /// Bytes of the message read only in part so far.
+    pub(crate) fn pending(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+/// How far a message read in part has been gone over, so that each read goes on from
+/// there rather than over the whole message again.
+#[derive(Debug, Default)]
+struct Scan {
+    /// Bytes of the message known to be complete elements or aggregate headers.
+    pos: usize,
+    /// Elements still to come of each aggregate the scan is in, the innermost last.
+    left: Vec<usize>,
+}
+
+enum Scanned {
+    Complete,
+    Incomplete,
+    /// Left for the parser to tell what's wrong.
+    Invalid,
+}
+
+impl Scan {
+    /// Goes on over the message at the front of `buf` from where the last read stopped.
+    fn resume(&mut self, buf: &[u8], max_bulk_len: usize) -> Scanned {
+        if self.pos == 0 {
+            match buf.first() {
+                None => return Scanned::Incomplete,
+                // An RDB file is its size and contents, with nothing nested to scan.
+                Some(b'$') => {
+                    let mut tokens = Tokens::new(buf);
+                    let Some(line) = tokens.line() else {
+                        return Scanned::Incomplete;
+                    };
+                    return match utils::parse_usize(&line[1..]) {
+                        Ok(size) if tokens.rest().len() >= size => Scanned::Complete,
+                        Ok(_) => Scanned::Incomplete,
+                        Err(_) => Scanned::Invalid,
+                    };
+                }
+                Some(b'*' | b'+') => {}
+                Some(_) => return Scanned::Invalid,
+            }
+        }
+
+        loop {
+            let mut tokens = Tokens::new(&buf[self.pos..]);
+            match buf.get(self.pos) {
+                None => return Scanned::Incomplete,
+                Some(b'*' | b'~' | b'>' | b'%') => {
+                    let Some(line) = tokens.line() else {
+                        return Scanned::Incomplete;
+                    };
+                    let len = match (line[0], utils::parse_i64(&line[1..])) {
+                        (b'*', Ok(-1)) => Some(0),
+                        _ if self.left.len() >= MAX_DEPTH => None,
+                        (b'%', Ok(len)) => {
+                            usize::try_from(len).ok().and_then(|len| len.checked_mul(2))
+                        }
+                        (_, Ok(len)) => usize::try_from(len).ok(),
+                        (_, Err(_)) => None,
+                    };
+                    let Some(len) = len else {
+                        return Scanned::Invalid;
+                    };
+                    self.pos = buf.len() - tokens.rest().len();
+                    if len > 0 {
+                        self.left.push(len);
+                        continue;
+                    }
+                }
+                // Anything else is read in a go, so it's simply parsed.
+                Some(_) => match Resp::from_tokens_within(&mut tokens, max_bulk_len) {
+                    Ok(_) => self.pos = buf.len() - tokens.rest().len(),
+                    Err(RedisError::Protocol(RespError::Incomplete)) => return Scanned::Incomplete,
+                    Err(_) => return Scanned::Invalid,
+                },
+            }
+
+            // An element is done, which may be the last of the aggregates it ends.
+            while let Some(left) = self.left.last_mut() {
+                *left -= 1;
+                if *left > 0 {
+                    break;
+                }
+                self.left.pop();
+            }
+            if self.left.is_empty() {
+                return Scanned::Complete;
+            }
         }
     }
 }
@@ -56,69 +233,442 @@ impl fmt::Display for IncomingMessage {
         match self {
             Self::Resp(resp) => write!(f, "{resp}"),
             Self::Rdb(_) => write!(f, "RDB binary data"),
+            Self::Invalid(err) => write!(f, "invalid message: {err}"),
         }
     }
 }
 
+/// Frames to write to a connection. Frames are reference counted, so cloning a message to
+/// send it to many connections doesn't copy the bytes.
 #[derive(Debug, Clone)]
-pub struct OutgoingMessage(Vec<Vec<u8>>);
+pub struct OutgoingMessage(Vec<Bytes>);
 
 impl OutgoingMessage {
     pub fn new(bytes: Vec<Vec<u8>>) -> Self {
-        Self(bytes)
+        Self(bytes.into_iter().map(Bytes::from).collect())
     }
 
     pub fn empty() -> Self {
         Self(vec![])
     }
 
-    pub async fn write_to(self, stream: &mut TcpStream) -> std::io::Result<()> {
-        for bytes in self.into_iter() {
-            stream.write_all(&bytes).await?;
+    pub(crate) fn frames(&self) -> &[Bytes] {
+        &self.0
+    }
+
+    /// Writes every frame to the sink, then flushes it once.
+    pub async fn write_to<W>(self, sink: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        write_frames(sink, &self.0).await
+    }
+}
+
+/// Writes the frames with as few vectored writes as the writer takes, picking up
+/// where a partial write stopped, and flushes the writer after the last one. Writers
+/// without vectored writes would take a frame per call, so the frames are copied into
+/// one buffer for them instead.
+pub(crate) async fn write_frames<W>(writer: &mut W, frames: &[Bytes]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    if !writer.is_write_vectored() && frames.len() > 1 {
+        writer.write_all(&frames.concat()).await?;
+        return writer.flush().await;
+    }
+
+    let mut frames = frames;
+    // Bytes of the first frame already written.
+    let mut offset = 0;
+
+    loop {
+        while frames.first().is_some_and(|frame| frame.len() == offset) {
+            frames = &frames[1..];
+            offset = 0;
+        }
+        let Some((first, rest)) = frames.split_first() else {
+            return writer.flush().await;
+        };
+
+        let slices: Vec<IoSlice<'_>> = std::iter::once(&first[offset..])
+            .chain(rest.iter().map(|frame| &frame[..]))
+            .take(MAX_IOV)
+            .map(IoSlice::new)
+            .collect();
+        let mut written = writer.write_vectored(&slices).await?;
+        if written == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+
+        while let Some(frame) = frames.first() {
+            let left = frame.len() - offset;
+            if written < left {
+                offset += written;
+                break;
+            }
+            written -= left;
+            frames = &frames[1..];
+            offset = 0;
         }
-        Ok(())
     }
 }
 
-impl From<Resp> for OutgoingMessage {
-    fn from(resp: Resp) -> Self {
+/// Which way a traced frame went.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Direction {
+    In,
+    Out,
+}
+
+/// Logs a frame as `trace-proto` asks: the peer, the direction, the length and the first
+/// bytes of the frame, with anything unprintable escaped.
+pub(crate) fn trace(addr: SocketAddr, direction: Direction, frame: &[u8]) {
+    println!("{}", trace_line(addr, direction, frame));
+}
+
+fn trace_line(addr: SocketAddr, direction: Direction, frame: &[u8]) -> String {
+    let arrow = match direction {
+        Direction::In => "<-",
+        Direction::Out => "->",
+    };
+    let shown = &frame[..frame.len().min(TRACE_PREVIEW)];
+    let more = if frame.len() > TRACE_PREVIEW {
+        "..."
+    } else {
+        ""
+    };
+    format!(
+        "[proto] {addr} {arrow} {} bytes \"{}{more}\"",
+        frame.len(),
+        shown.escape_ascii()
+    )
+}
+
+impl OutgoingMessage {
+    /// The reply serialized for a connection speaking the protocol.
+    pub fn reply(resp: Resp, protocol: Protocol) -> Self {
         match resp {
-            Resp::RAW(bytes) => Self(bytes),
-            _ => Self(vec![resp.serialize()]),
+            Resp::RAW(bytes) => Self::new(bytes),
+            _ => Self::from(resp.serialize_as(protocol)),
         }
     }
 }
 
+impl From<Resp> for OutgoingMessage {
+    fn from(resp: Resp) -> Self {
+        Self::reply(resp, Protocol::Resp2)
+    }
+}
+
 impl From<Vec<Resp>> for OutgoingMessage {
     fn from(resps: Vec<Resp>) -> Self {
-        Self(resps.iter().map(Resp::serialize).collect())
+        Self(
+            resps
+                .iter()
+                .map(|resp| Bytes::from(resp.serialize()))
+                .collect(),
+        )
     }
 }
 
 impl From<Vec<u8>> for OutgoingMessage {
     fn from(bytes: Vec<u8>) -> Self {
-        Self(vec![bytes])
+        Self(vec![Bytes::from(bytes)])
     }
 }
 
 impl<'a> From<&'a [u8]> for OutgoingMessage {
     fn from(buf: &'a [u8]) -> Self {
-        Self(vec![buf.to_vec()])
+        Self(vec![Bytes::copy_from_slice(buf)])
     }
 }
 
 impl IntoIterator for OutgoingMessage {
-    type Item = Vec<u8>;
-    type IntoIter = std::vec::IntoIter<Vec<u8>>;
+    type Item = Bytes;
+    type IntoIter = std::vec::IntoIter<Bytes>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.0.into_iter()
     }
 }
 
+/// What a command sends back to its connection. Replies are built in RESP3 terms and
+/// serialized by the connection in the protocol its client speaks, once the command ran.
+#[derive(Debug)]
+pub enum Reply {
+    Resp(Resp),
+    /// Frames already serialized, written as they are.
+    Frames(OutgoingMessage),
+}
+
+impl Reply {
+    pub fn empty() -> Self {
+        Self::Frames(OutgoingMessage::empty())
+    }
+
+    pub fn into_message(self, protocol: Protocol) -> OutgoingMessage {
+        match self {
+            Self::Resp(resp) => OutgoingMessage::reply(resp, protocol),
+            Self::Frames(msg) => msg,
+        }
+    }
+}
+
+impl From<Resp> for Reply {
+    fn from(resp: Resp) -> Self {
+        Self::Resp(resp)
+    }
+}
+
+impl From<OutgoingMessage> for Reply {
+    fn from(msg: OutgoingMessage) -> Self {
+        Self::Frames(msg)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    #[tokio::test]
+    async fn it_writes_frames_across_partial_writes() {
+        let msg = OutgoingMessage::from(vec![Resp::SS("OK".into()), Resp::I(42)]);
+        let mut sink: Vec<u8> = vec![];
+        msg.write_to(&mut sink).await.unwrap();
+        assert_eq!(sink, b"+OK\r\n:42\r\n");
+
+        let frames: Vec<Bytes> = ["+OK\r\n", "", "$5\r\nhello\r\n", ":42\r\n"]
+            .into_iter()
+            .map(Bytes::from)
+            .collect();
+        let expected: Vec<u8> = frames.iter().flatten().copied().collect();
+
+        // A pipe this narrow takes only a few bytes per write.
+        let (mut writer, mut reader) = tokio::io::duplex(4);
+        let write = async move {
+            write_frames(&mut writer, &frames).await.unwrap();
+        };
+        let read = async move {
+            let mut buf = vec![];
+            tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut buf)
+                .await
+                .unwrap();
+            buf
+        };
+        let ((), received) = tokio::join!(write, read);
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn it_serializes_replies_in_the_protocol_of_the_connection() {
+        let reply = || {
+            Reply::from(Resp::Map(vec![(
+                Resp::BS(Some("ok".into())),
+                Resp::Boolean(true),
+            )]))
+        };
+        let bytes = |msg: OutgoingMessage| -> Vec<u8> { msg.into_iter().flatten().collect() };
+        assert_eq!(
+            bytes(reply().into_message(Protocol::Resp2)),
+            b"*2\r\n$2\r\nok\r\n:1\r\n"
+        );
+        assert_eq!(
+            bytes(reply().into_message(Protocol::Resp3)),
+            b"%1\r\n$2\r\nok\r\n#t\r\n"
+        );
+
+        let frames = Reply::from(OutgoingMessage::from(b"+OK\r\n".as_slice()));
+        assert_eq!(bytes(frames.into_message(Protocol::Resp3)), b"+OK\r\n");
+    }
+
+    /// A writer taking at most `limit` bytes per write, which counts its writes.
+    struct Narrow {
+        buf: Vec<u8>,
+        writes: usize,
+        limit: usize,
+        vectored: bool,
+    }
+
+    impl AsyncWrite for Narrow {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let n = buf.len().min(self.limit);
+            self.buf.extend_from_slice(&buf[..n]);
+            self.writes += 1;
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_write_vectored(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> Poll<io::Result<usize>> {
+            if !self.vectored {
+                let first = bufs.iter().find(|buf| !buf.is_empty());
+                return self.poll_write(cx, first.map_or(&[][..], |buf| &buf[..]));
+            }
+            let joined: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter()).copied().collect();
+            self.poll_write(cx, &joined)
+        }
+
+        fn is_write_vectored(&self) -> bool {
+            self.vectored
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn it_writes_frames_in_one_call() {
+        let frames: Vec<Bytes> = ["+FULLRESYNC id 0\r\n", "$5\r\n", "REDIS", "*0\r\n"]
+            .into_iter()
+            .map(Bytes::from)
+            .collect();
+        let expected = frames.concat();
+
+        for (limit, vectored, writes) in [(1024, true, 1), (1024, false, 1), (8, true, 4)] {
+            let mut writer = Narrow {
+                buf: vec![],
+                writes: 0,
+                limit,
+                vectored,
+            };
+            write_frames(&mut writer, &frames).await.unwrap();
+            assert_eq!(writer.buf, expected);
+            assert_eq!(writer.writes, writes, "{limit} {vectored}");
+        }
+    }
+
+    #[test]
+    fn it_traces_frames_with_an_escaped_preview() {
+        let addr: SocketAddr = "127.0.0.1:6379".parse().unwrap();
+        assert_eq!(
+            trace_line(addr, Direction::In, b"*1\r\n$4\r\nPING\r\n"),
+            r#"[proto] 127.0.0.1:6379 <- 14 bytes "*1\r\n$4\r\nPING\r\n""#
+        );
+
+        let frame = [b"$100\r\n\x00\"".as_slice(), &[b'x'; 100]].concat();
+        let line = trace_line(addr, Direction::Out, &frame);
+        assert!(line.starts_with(r#"[proto] 127.0.0.1:6379 -> 108 bytes "$100\r\n\x00\"xx"#));
+        assert!(line.ends_with("xx...\""));
+    }
+
+    #[test]
+    fn it_decodes_messages_split_across_reads() {
+        let mut decoder = MessageDecoder::default();
+        let bytes = b"*2\r\n$4\r\nECHO\r\n$5\r\nhello\r\n*1\r\n$4\r\nPING\r\n";
+
+        // Every split gives the same messages.
+        for cut in 0..=bytes.len() {
+            let mut frames = decoder.feed(&bytes[..cut]).unwrap();
+            frames.extend(decoder.feed(&bytes[cut..]).unwrap());
+            assert_eq!(decoder.pending(), 0);
+
+            let frames: Vec<Bytes> = frames.into_iter().map(|(_, frame)| frame).collect();
+            assert_eq!(frames, [&bytes[..25], &bytes[25..]]);
+        }
+
+        // An RDB file may come in as many reads as it takes.
+        let frames = decoder.feed(b"$5\r\nREDIS").unwrap();
+        assert!(matches!(&frames[..], [(IncomingMessage::Rdb(_), _)]));
+        assert!(decoder.feed(b"$5\r\nRED").unwrap().is_empty());
+        assert_eq!(decoder.pending(), 7);
+        let frames = decoder.feed(b"IS+OK\r\n").unwrap();
+        assert_eq!(frames.len(), 2);
+
+        // Garbage drops what was buffered.
+        assert!(decoder.feed(b"*1\r\n$4\r\nPI").unwrap().is_empty());
+        assert!(decoder.feed(b"NG\r\n*x\r\n").is_err());
+        assert_eq!(decoder.pending(), 0);
+        let err = decoder.feed(b"PING\r\n").unwrap_err();
+        assert!(matches!(
+            err,
+            RedisError::Protocol(RespError::InvalidType(b'P'))
+        ));
+    }
+
+    #[test]
+    fn it_resumes_a_message_where_the_last_read_stopped() {
+        let bytes = b"*4\r\n*2\r\n:1\r\n%1\r\n+a\r\n$1\r\nb\r\n*-1\r\n$3\r\nfoo\r\n*0\r\n";
+        let mut decoder = MessageDecoder::default();
+        let mut frames = vec![];
+        for byte in bytes.iter() {
+            frames.extend(decoder.feed(&[*byte]).unwrap());
+        }
+        let [(IncomingMessage::Resp(resp), frame)] = &frames[..] else {
+            panic!("expected a single message, got {}", frames.len());
+        };
+        assert_eq!(resp, &Resp::from_tokens(&mut Tokens::new(bytes)).unwrap());
+        assert_eq!(&frame[..], bytes);
+
+        // The elements read are gone over only once.
+        let element = b"$5\r\nhello\r\n";
+        decoder.feed(b"*1000\r\n").unwrap();
+        for i in 1..1000 {
+            assert!(decoder.feed(element).unwrap().is_empty());
+            assert_eq!(decoder.scan.pos, 7 + i * element.len());
+            assert_eq!(decoder.scan.left, [1000 - i]);
+        }
+        let frames = decoder.feed(element).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(decoder.pending(), 0);
+        assert_eq!(decoder.scan.pos, 0);
+
+        // What's wrong with a message is told as if it was read at once.
+        decoder.feed(b"*2\r\n*1\r\n:1\r\n").unwrap();
+        let err = decoder.feed(b":x\r\n").unwrap_err();
+        assert!(matches!(
+            err,
+            RedisError::Protocol(RespError::InvalidInteger)
+        ));
+        assert_eq!(decoder.pending(), 0);
+        assert_eq!(decoder.feed(b"*1\r\n$4\r\nPING\r\n").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn it_decodes_an_rdb_larger_than_a_read() {
+        let db: std::collections::HashMap<String, crate::value::Value> = (0..1000)
+            .map(|i| {
+                let value = crate::value::Value::String {
+                    value: "x".repeat(100),
+                    exp: None,
+                };
+                (format!("key{i}"), value)
+            })
+            .collect();
+        let rdb = Rdb::dump(db.iter(), &[], std::time::SystemTime::now());
+        let bytes = [format!("${}\r\n", rdb.len()).as_bytes(), &rdb, b"+OK\r\n"].concat();
+        assert!(bytes.len() > 100 * crate::BUF_SIZE);
+
+        let mut decoder = MessageDecoder::default();
+        let mut frames = vec![];
+        for chunk in bytes.chunks(crate::BUF_SIZE) {
+            frames.extend(decoder.feed(chunk).unwrap());
+        }
+        assert_eq!(decoder.pending(), 0);
+
+        let mut frames = frames.into_iter();
+        let (message, frame) = frames.next().unwrap();
+        assert_eq!(frame.len(), bytes.len() - 5);
+        let IncomingMessage::Rdb(received) = message else {
+            panic!("expected an RDB, got {message}");
+        };
+        assert_eq!(received.into_db().len(), 1000);
+        assert!(matches!(frames.next(), Some((IncomingMessage::Resp(_), _))));
+        assert!(frames.next().is_none());
+    }
 
     #[test]
     fn it_parses_multiple_messages() {
@@ -8,6 +8,8 @@ const MASK_LAST_SIX: u8 = 0b00111111;
 pub(crate) enum EncSize {
     Integer(usize),
     String(String),
+    /// An LZF compressed string, decompressed.
+    Compressed(Vec<u8>),
 }
 
 impl EncSize {
@@ -24,6 +26,13 @@ impl EncSize {
                 let [byte1] = buf;
                 Ok(Self::Integer(size_0b01([byte0, byte1])))
             }
+            0b10000000 if byte0 == 0x81 => {
+                let mut buf = [0u8; 8];
+                r.read_exact(&mut buf)?;
+                usize::try_from(u64::from_be_bytes(buf))
+                    .map(Self::Integer)
+                    .map_err(|_| RedisError::Encoding)
+            }
             0b10000000 => {
                 let mut buf = [0u8; 4];
                 r.read_exact(&mut buf)?;
@@ -36,17 +45,24 @@ impl EncSize {
                 0xc0 => {
                     let mut buf = [0u8; 1];
                     r.read_exact(&mut buf)?;
-                    Ok(Self::String(u8::from_le_bytes(buf).to_string()))
+                    Ok(Self::String(i8::from_le_bytes(buf).to_string()))
                 }
                 0xc1 => {
                     let mut buf = [0u8; 2];
                     r.read_exact(&mut buf)?;
-                    Ok(Self::String(u16::from_le_bytes(buf).to_string()))
+                    Ok(Self::String(i16::from_le_bytes(buf).to_string()))
                 }
                 0xc2 => {
                     let mut buf = [0u8; 4];
                     r.read_exact(&mut buf)?;
-                    Ok(Self::String(u32::from_le_bytes(buf).to_string()))
+                    Ok(Self::String(i32::from_le_bytes(buf).to_string()))
+                }
+                0xc3 => {
+                    let compressed_len = Self::new(r)?.value().ok_or(RedisError::Encoding)?;
+                    let len = Self::new(r)?.value().ok_or(RedisError::Encoding)?;
+                    let mut buf = vec![0; compressed_len];
+                    r.read_exact(&mut buf)?;
+                    lzf_decompress(&buf, len).map(Self::Compressed)
                 }
                 _ => {
                     eprintln!(
@@ -67,6 +83,54 @@ impl EncSize {
     }
 }
 
+/// Reads a string as bytes, which may not be valid UTF-8.
+pub(crate) fn read_bytes<R: Read>(r: &mut R) -> RedisResult<Vec<u8>> {
+    match EncSize::new(r)? {
+        EncSize::Integer(size) => {
+            let mut buf = vec![0; size];
+            r.read_exact(&mut buf)?;
+            Ok(buf)
+        }
+        EncSize::String(value) => Ok(value.into_bytes()),
+        EncSize::Compressed(bytes) => Ok(bytes),
+    }
+}
+
+/// Decompresses LZF data into `len` bytes. A control byte below 32 starts a run of
+/// literals; any other one copies bytes already decompressed.
+fn lzf_decompress(input: &[u8], len: usize) -> RedisResult<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(len);
+    let mut bytes = input.iter().copied();
+
+    while let Some(ctrl) = bytes.next() {
+        let ctrl = usize::from(ctrl);
+        if ctrl < 32 {
+            for _ in 0..=ctrl {
+                out.push(bytes.next().ok_or(RedisError::Encoding)?);
+            }
+            continue;
+        }
+
+        let mut run = ctrl >> 5;
+        if run == 7 {
+            run += usize::from(bytes.next().ok_or(RedisError::Encoding)?);
+        }
+        let low = usize::from(bytes.next().ok_or(RedisError::Encoding)?);
+        let back = ((ctrl & 0x1f) << 8) + low + 1;
+        let start = out.len().checked_sub(back).ok_or(RedisError::Encoding)?;
+        // The copy may overlap the bytes it appends, so it goes one byte at a time.
+        for i in 0..run + 2 {
+            out.push(out[start + i]);
+        }
+    }
+
+    if out.len() == len {
+        Ok(out)
+    } else {
+        Err(RedisError::Encoding)
+    }
+}
+
 fn size_0b00(num: u8) -> usize {
     (num & MASK_LAST_SIX).into()
 }
@@ -96,6 +160,7 @@ impl EncString {
                 utils::stringify(&buf).map(|v| Self(v.into()))
             }
             EncSize::String(value) => Ok(Self(value)),
+            EncSize::Compressed(bytes) => utils::stringify(&bytes).map(|v| Self(v.into())),
         }
     }
 
@@ -104,6 +169,77 @@ impl EncString {
     }
 }
 
+/// Writes the size in the shortest of the integer size encodings.
+pub(crate) fn encode_size(size: usize, buf: &mut Vec<u8>) {
+    if size < 1 << 6 {
+        buf.push(size as u8);
+    } else if size < 1 << 14 {
+        buf.extend_from_slice(&[0b01000000 | (size >> 8) as u8, size as u8]);
+    } else if let Ok(size) = u32::try_from(size) {
+        buf.push(0b10000000);
+        buf.extend_from_slice(&size.to_be_bytes());
+    } else {
+        buf.push(0x81);
+        buf.extend_from_slice(&(size as u64).to_be_bytes());
+    }
+}
+
+/// Writes the string, as an integer when it is the representation of a 32 bit integer.
+pub(crate) fn encode_string(value: &str, buf: &mut Vec<u8>) {
+    match value.parse::<i32>() {
+        Ok(num) if num.to_string() == value => {
+            if let Ok(num) = i8::try_from(num) {
+                buf.push(0xc0);
+                buf.extend_from_slice(&num.to_le_bytes());
+            } else if let Ok(num) = i16::try_from(num) {
+                buf.push(0xc1);
+                buf.extend_from_slice(&num.to_le_bytes());
+            } else {
+                buf.push(0xc2);
+                buf.extend_from_slice(&num.to_le_bytes());
+            }
+        }
+        _ => encode_bytes(value.as_bytes(), buf),
+    }
+}
+
+/// Writes the bytes as a string as they are, for binary data like listpacks.
+pub(crate) fn encode_bytes(value: &[u8], buf: &mut Vec<u8>) {
+    encode_size(value.len(), buf);
+    buf.extend_from_slice(value);
+}
+
+/// The CRC-64 of the bytes with the Jones polynomial, as RDB files are checksummed.
+pub(crate) fn crc64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |crc, byte| {
+        CRC64_TABLE[((crc ^ u64::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+const fn crc64_table() -> [u64; 256] {
+    // The bit-reflected form of the polynomial 0xad93d23594c935a9.
+    const POLY: u64 = 0x95ac9329ac4bc9b5;
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -216,4 +352,48 @@ mod tests {
         let expected = EncString("1234567".into());
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn lzf_compressed_string_prefixed_with_0xc3() {
+        // "abc" as literals, then a back reference copying them again.
+        let bytes = [0xc3, 0x06, 0x06, 0x02, b'a', b'b', b'c', 0x20, 0x02];
+        let actual = EncString::new(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(actual, EncString("abcabc".into()));
+
+        // A back reference overlapping the bytes it copies.
+        let bytes = [0xc3, 0x04, 0x05, 0x00, b'a', 0x40, 0x00];
+        let actual = EncString::new(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(actual, EncString("aaaaa".into()));
+
+        let bytes = [0xc3, 0x04, 0x06, 0x00, b'a', 0x40, 0x00];
+        assert!(EncString::new(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn it_encodes_sizes() {
+        for size in [10, 700, 17000, 1 << 33] {
+            let mut buf: Vec<u8> = vec![];
+            encode_size(size, &mut buf);
+
+            let actual = EncSize::new(&mut Cursor::new(buf)).unwrap();
+            assert_eq!(actual, EncSize::Integer(size));
+        }
+    }
+
+    #[test]
+    fn it_encodes_strings() {
+        for value in ["Hello, World!", "123", "-5", "12345", "1234567", "0123", ""] {
+            let mut buf: Vec<u8> = vec![];
+            encode_string(value, &mut buf);
+
+            let actual = EncString::new(&mut Cursor::new(buf)).unwrap();
+            assert_eq!(actual, EncString(value.into()));
+        }
+    }
+
+    #[test]
+    fn it_computes_crc64() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+        assert_eq!(crc64(b""), 0);
+    }
 }
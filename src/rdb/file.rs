@@ -1,8 +1,15 @@
 use super::{
     enc::{EncSize, EncString},
+    hash::{self, TYPE_HASH, TYPE_HASH_METADATA},
+    list::{self, TYPE_LIST_QUICKLIST_2},
+    module::{self, ModuleValue, TYPE_MODULE_2},
+    set::{self, TYPE_SET},
+    stream::{self, TYPE_STREAM_V1, TYPE_STREAM_V2, TYPE_STREAM_V3},
     utils, RedisResult,
 };
-use std::io::{ErrorKind, Read};
+use crate::value::{RedisHash, RedisStream};
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, ErrorKind, Read};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, PartialEq)]
@@ -22,22 +29,70 @@ pub enum RdbElement {
         value: String,
         exp: Option<SystemTime>,
     },
+    List {
+        key: String,
+        list: VecDeque<String>,
+        exp: Option<SystemTime>,
+    },
+    Hash {
+        key: String,
+        hash: RedisHash,
+        exp: Option<SystemTime>,
+    },
+    Set {
+        key: String,
+        set: HashSet<String>,
+        exp: Option<SystemTime>,
+    },
+    Stream {
+        key: String,
+        stream: RedisStream,
+        exp: Option<SystemTime>,
+    },
+    /// A value of a module type.
+    Module {
+        key: String,
+        value: ModuleValue,
+        exp: Option<SystemTime>,
+    },
+    /// The code of a function library.
+    Function(String),
     Checksum([u8; 8]),
 }
 
 #[derive(Debug)]
 pub struct RdbFile<R: Read> {
-    inner: R,
+    inner: Counted<R>,
     eof: bool,
 }
 
 impl<R: Read> RdbFile<R> {
     pub fn new(r: R) -> Self {
         Self {
-            inner: r,
+            inner: Counted { inner: r, count: 0 },
             eof: false,
         }
     }
+
+    /// The bytes read so far, which is the offset of the next element.
+    pub fn offset(&self) -> u64 {
+        self.inner.count
+    }
+}
+
+/// A reader counting the bytes read through it.
+#[derive(Debug)]
+struct Counted<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
 }
 
 impl<R: Read> Iterator for RdbFile<R> {
@@ -53,9 +108,18 @@ impl<R: Read> Iterator for RdbFile<R> {
             Ok(_) => match one_byte {
                 [0x52] => read_header(&mut self.inner),
                 [0xfa] => read_metadata(&mut self.inner),
+                [0xf5] => read_function(&mut self.inner),
                 [0xfe] => read_db_index(&mut self.inner),
                 [0xfb] => read_hash_size(&mut self.inner),
-                [0x00] => read_hash_entry(&mut self.inner),
+                [value_type @ (0x00
+                | TYPE_LIST_QUICKLIST_2
+                | TYPE_HASH
+                | TYPE_HASH_METADATA
+                | TYPE_SET
+                | TYPE_STREAM_V1
+                | TYPE_STREAM_V2
+                | TYPE_STREAM_V3
+                | TYPE_MODULE_2)] => read_entry(&mut self.inner, value_type),
                 [0xfc] => read_hash_entry_exp_millis(&mut self.inner),
                 [0xfd] => read_hash_entry_exp_secs(&mut self.inner),
                 [0xff] => {
@@ -106,6 +170,13 @@ fn read_metadata<R: Read>(r: &mut R) -> Option<RdbElement> {
     Some(RdbElement::Meta { key, value })
 }
 
+fn read_function<R: Read>(r: &mut R) -> Option<RdbElement> {
+    EncString::new(r)
+        .inspect_err(|err| eprintln!("Failed to read rdb function library: {err}"))
+        .ok()
+        .map(|code| RdbElement::Function(code.value().to_string()))
+}
+
 fn read_db_index<R: Read>(r: &mut R) -> Option<RdbElement> {
     EncSize::new(r)
         .inspect_err(|err| eprintln!("Failed to read rdb database index value: {err}"))
@@ -144,6 +215,143 @@ fn read_hash_entry<R: Read>(r: &mut R) -> Option<RdbElement> {
     })
 }
 
+fn read_list_entry<R: Read>(r: &mut R) -> Option<RdbElement> {
+    let key = EncString::new(r)
+        .inspect_err(|err| eprintln!("Failed to read rdb list's key: {err}"))
+        .ok()?
+        .value()
+        .to_string();
+    let list = list::read_list(r)
+        .inspect_err(|err| eprintln!("Failed to read rdb list {key}: {err}"))
+        .ok()?;
+    Some(RdbElement::List {
+        key,
+        list,
+        exp: None,
+    })
+}
+
+fn read_hash_value_entry<R: Read>(r: &mut R, value_type: u8) -> Option<RdbElement> {
+    let key = EncString::new(r)
+        .inspect_err(|err| eprintln!("Failed to read rdb hash's key: {err}"))
+        .ok()?
+        .value()
+        .to_string();
+    let hash = hash::read_hash(r, value_type)
+        .inspect_err(|err| eprintln!("Failed to read rdb hash {key}: {err}"))
+        .ok()?;
+    Some(RdbElement::Hash {
+        key,
+        hash,
+        exp: None,
+    })
+}
+
+fn read_set_entry<R: Read>(r: &mut R) -> Option<RdbElement> {
+    let key = EncString::new(r)
+        .inspect_err(|err| eprintln!("Failed to read rdb set's key: {err}"))
+        .ok()?
+        .value()
+        .to_string();
+    let set = set::read_set(r)
+        .inspect_err(|err| eprintln!("Failed to read rdb set {key}: {err}"))
+        .ok()?;
+    Some(RdbElement::Set {
+        key,
+        set,
+        exp: None,
+    })
+}
+
+fn read_stream_entry<R: Read>(r: &mut R, value_type: u8) -> Option<RdbElement> {
+    let key = EncString::new(r)
+        .inspect_err(|err| eprintln!("Failed to read rdb stream's key: {err}"))
+        .ok()?
+        .value()
+        .to_string();
+    let stream = stream::read_stream(r, value_type)
+        .inspect_err(|err| eprintln!("Failed to read rdb stream {key}: {err}"))
+        .ok()?;
+    Some(RdbElement::Stream {
+        key,
+        stream,
+        exp: None,
+    })
+}
+
+fn read_module_entry<R: Read>(r: &mut R) -> Option<RdbElement> {
+    let key = EncString::new(r)
+        .inspect_err(|err| eprintln!("Failed to read rdb module value's key: {err}"))
+        .ok()?
+        .value()
+        .to_string();
+    let value = module::read_module(r)
+        .inspect_err(|err| eprintln!("Failed to read rdb module value {key}: {err}"))
+        .ok()?;
+    Some(RdbElement::Module {
+        key,
+        value,
+        exp: None,
+    })
+}
+
+/// Reads the entry of a key of the value type.
+fn read_entry<R: Read>(r: &mut R, value_type: u8) -> Option<RdbElement> {
+    match value_type {
+        0x00 => read_hash_entry(r),
+        TYPE_LIST_QUICKLIST_2 => read_list_entry(r),
+        TYPE_HASH | TYPE_HASH_METADATA => read_hash_value_entry(r, value_type),
+        TYPE_SET => read_set_entry(r),
+        TYPE_STREAM_V1 | TYPE_STREAM_V2 | TYPE_STREAM_V3 => read_stream_entry(r, value_type),
+        TYPE_MODULE_2 => read_module_entry(r),
+        _ => {
+            eprintln!("Unsupported rdb value type: {value_type:#04x}");
+            None
+        }
+    }
+}
+
+/// Reads the entry following an expiry, of any value type.
+fn read_entry_with_exp<R: Read>(r: &mut R, exp: SystemTime) -> Option<RdbElement> {
+    let value_type = next_one_byte(r)
+        .inspect_err(|err| eprintln!("Failed to read rdb value type: {err}"))
+        .ok()?;
+
+    match read_entry(r, value_type)? {
+        RdbElement::HashTableEntry { key, value, .. } => Some(RdbElement::HashTableEntry {
+            key,
+            value,
+            exp: Some(exp),
+        }),
+        RdbElement::List { key, list, .. } => Some(RdbElement::List {
+            key,
+            list,
+            exp: Some(exp),
+        }),
+        RdbElement::Hash { key, hash, .. } => Some(RdbElement::Hash {
+            key,
+            hash,
+            exp: Some(exp),
+        }),
+        RdbElement::Set { key, set, .. } => Some(RdbElement::Set {
+            key,
+            set,
+            exp: Some(exp),
+        }),
+        RdbElement::Stream { key, stream, .. } => Some(RdbElement::Stream {
+            key,
+            stream,
+            exp: Some(exp),
+        }),
+        RdbElement::Module { key, value, .. } => Some(RdbElement::Module {
+            key,
+            value,
+            exp: Some(exp),
+        }),
+        _ => None,
+    }
+}
+
 fn read_hash_entry_exp_millis<R: Read>(r: &mut R) -> Option<RdbElement> {
     let mut buf = [0u8; 8];
     r.read_exact(&mut buf)
@@ -153,22 +361,7 @@ fn read_hash_entry_exp_millis<R: Read>(r: &mut R) -> Option<RdbElement> {
         .ok()?;
     let exp = UNIX_EPOCH + Duration::from_millis(u64::from_le_bytes(buf));
 
-    // Proceed to one byte to use `read_hash_entry` function
-    if !next_one_byte(r).is_ok_and(|v| v == 0u8) {
-        eprintln!("FC entry doesn't start with 0x00");
-        return None;
-    }
-
-    if let Some(RdbElement::HashTableEntry { key, value, .. }) = read_hash_entry(r) {
-        Some(RdbElement::HashTableEntry {
-            key,
-            value,
-            exp: Some(exp),
-        })
-    } else {
-        eprintln!("Failed to read rdb hash table entry");
-        None
-    }
+    read_entry_with_exp(r, exp)
 }
 
 fn read_hash_entry_exp_secs<R: Read>(r: &mut R) -> Option<RdbElement> {
@@ -180,22 +373,7 @@ fn read_hash_entry_exp_secs<R: Read>(r: &mut R) -> Option<RdbElement> {
         .ok()?;
     let exp = UNIX_EPOCH + Duration::from_secs(u32::from_le_bytes(buf) as u64);
 
-    // Proceed to one byte to use `read_hash_entry` function
-    if !next_one_byte(r).is_ok_and(|v| v == 0u8) {
-        eprintln!("FD entry doesn't start with 0x00");
-        return None;
-    }
-
-    if let Some(RdbElement::HashTableEntry { key, value, .. }) = read_hash_entry(r) {
-        Some(RdbElement::HashTableEntry {
-            key,
-            value,
-            exp: Some(exp),
-        })
-    } else {
-        eprintln!("Failed to read rdb hash table entry");
-        None
-    }
+    read_entry_with_exp(r, exp)
 }
 
 fn read_eof<R: Read>(r: &mut R) -> Option<RdbElement> {
@@ -266,6 +444,7 @@ mod tests {
 
         let expected = RdbElement::Checksum([0x89, 0x3b, 0xb7, 0x4e, 0xf8, 0x0f, 0x77, 0x19]);
         assert_eq!(f.next().unwrap(), expected);
+        assert_eq!(f.offset(), bytes.len() as u64);
 
         assert_eq!(f.next(), None);
     }
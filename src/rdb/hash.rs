@@ -0,0 +1,69 @@
+use super::{
+    enc::{encode_size, encode_string, EncSize, EncString},
+    RedisError, RedisResult,
+};
+use crate::value::RedisHash;
+use std::io::Read;
+
+/// The value type of hashes saved field after value, as Redis saves them as hash tables.
+pub(crate) const TYPE_HASH: u8 = 0x04;
+/// The value type of hashes with field expiries. The smallest expiry comes first and
+/// each field is preceded by its expiry relative to it, plus one, or by 0 without one.
+pub(crate) const TYPE_HASH_METADATA: u8 = 0x18;
+
+/// The value type the hash is saved with.
+pub(crate) fn hash_type(hash: &RedisHash) -> u8 {
+    if hash.has_expiries() {
+        TYPE_HASH_METADATA
+    } else {
+        TYPE_HASH
+    }
+}
+
+/// Writes the fields in order, so that equal hashes are saved alike.
+pub(crate) fn encode_hash(hash: &RedisHash, buf: &mut Vec<u8>) {
+    let mut fields: Vec<_> = hash.entries().collect();
+    fields.sort_unstable();
+    let min_expiry = fields.iter().filter_map(|(_, _, at)| *at).min();
+    if let Some(min) = min_expiry {
+        buf.extend_from_slice(&min.to_le_bytes());
+    }
+    encode_size(fields.len(), buf);
+    for (field, value, at) in fields {
+        if let Some(min) = min_expiry {
+            let ttl = at.map(|at| at - min + 1).unwrap_or(0);
+            encode_size(ttl as usize, buf);
+        }
+        encode_string(field, buf);
+        encode_string(value, buf);
+    }
+}
+
+pub(crate) fn read_hash<R: Read>(r: &mut R, value_type: u8) -> RedisResult<RedisHash> {
+    let min_expiry = if value_type == TYPE_HASH_METADATA {
+        let mut buf = [0u8; 8];
+        r.read_exact(&mut buf)?;
+        Some(u64::from_le_bytes(buf))
+    } else {
+        None
+    };
+    let len = read_size(r)?;
+    let mut hash = RedisHash::new();
+    for _ in 0..len {
+        let ttl = match min_expiry {
+            Some(_) => read_size(r)? as u64,
+            None => 0,
+        };
+        let field = EncString::new(r)?.value().to_string();
+        let value = EncString::new(r)?.value().to_string();
+        hash.insert(field.clone(), value);
+        if let (Some(min), 1..) = (min_expiry, ttl) {
+            hash.set_expiry(&field, min + ttl - 1);
+        }
+    }
+    Ok(hash)
+}
+
+fn read_size<R: Read>(r: &mut R) -> RedisResult<usize> {
+    EncSize::new(r)?.value().ok_or(RedisError::Encoding)
+}
@@ -0,0 +1,90 @@
+use super::{
+    enc::{encode_bytes, encode_size, read_bytes, EncSize},
+    listpack::{self, LpEntry},
+    RedisError, RedisResult,
+};
+use std::collections::VecDeque;
+use std::io::Read;
+
+/// The value type of lists saved as quicklists of listpacks, as Redis 7 saves them.
+pub(crate) const TYPE_LIST_QUICKLIST_2: u8 = 0x12;
+
+/// A node holding a single element as is.
+const CONTAINER_PLAIN: usize = 1;
+/// A node holding a listpack of elements.
+const CONTAINER_PACKED: usize = 2;
+
+/// Bytes of elements per listpack node, as list-max-listpack-size defaults to.
+const NODE_MAX_BYTES: usize = 8 * 1024;
+
+pub(crate) fn encode_list(list: &VecDeque<String>, buf: &mut Vec<u8>) {
+    let nodes = encode_nodes(list);
+    encode_size(nodes.len(), buf);
+    for node in nodes {
+        encode_size(CONTAINER_PACKED, buf);
+        encode_bytes(&node, buf);
+    }
+}
+
+/// The listpacks the elements are saved in, in order.
+pub(crate) fn encode_nodes(list: &VecDeque<String>) -> Vec<Vec<u8>> {
+    let mut nodes: Vec<Vec<LpEntry>> = vec![];
+    let mut size = 0;
+    for element in list {
+        match nodes.last_mut() {
+            Some(node) if size + element.len() <= NODE_MAX_BYTES => {
+                size += element.len();
+                node.push(LpEntry::Str(element.as_bytes().to_vec()));
+            }
+            _ => {
+                size = element.len();
+                nodes.push(vec![LpEntry::Str(element.as_bytes().to_vec())]);
+            }
+        }
+    }
+    nodes.iter().map(|node| listpack::encode(node)).collect()
+}
+
+pub(crate) fn read_list<R: Read>(r: &mut R) -> RedisResult<VecDeque<String>> {
+    let mut list = VecDeque::new();
+    for _ in 0..read_size(r)? {
+        let container = read_size(r)?;
+        let bytes = read_bytes(r)?;
+        let elements = match container {
+            CONTAINER_PLAIN => vec![bytes],
+            CONTAINER_PACKED => listpack::decode(&bytes)?
+                .into_iter()
+                .map(LpEntry::into_bytes)
+                .collect(),
+            _ => return Err(RedisError::Encoding),
+        };
+        for element in elements {
+            list.push_back(String::from_utf8(element).map_err(|_| RedisError::Encoding)?);
+        }
+    }
+    Ok(list)
+}
+
+fn read_size<R: Read>(r: &mut R) -> RedisResult<usize> {
+    EncSize::new(r)?.value().ok_or(RedisError::Encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_saves_lists_in_nodes() {
+        let list: VecDeque<String> = ["a", "12", ""]
+            .into_iter()
+            .map(String::from)
+            .chain((0..100).map(|i| format!("{i:0>200}")))
+            .collect();
+        let mut buf = vec![];
+        encode_list(&list, &mut buf);
+
+        // 8kb takes 40 of the long elements, after which new nodes start.
+        assert_eq!(buf[0], 3);
+        assert_eq!(read_list(&mut buf.as_slice()).unwrap(), list);
+    }
+}
@@ -0,0 +1,265 @@
+use super::{RedisError, RedisResult};
+
+/// Bytes of the header, the total bytes as u32 and the number of entries as u16.
+const HEADER_LEN: usize = 6;
+const EOF: u8 = 0xff;
+
+/// An entry of a listpack. Strings holding integers are stored as integers, so entries
+/// read back may be either.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LpEntry {
+    Int(i64),
+    Str(Vec<u8>),
+}
+
+impl LpEntry {
+    pub(crate) fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(num) => Some(*num),
+            Self::Str(bytes) => int_of(bytes),
+        }
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Int(num) => num.to_string().into_bytes(),
+            Self::Str(bytes) => bytes,
+        }
+    }
+}
+
+/// Serializes the entries into a listpack.
+pub(crate) fn encode(entries: &[LpEntry]) -> Vec<u8> {
+    let mut buf: Vec<u8> = vec![0; HEADER_LEN];
+    for entry in entries {
+        let start = buf.len();
+        match entry {
+            LpEntry::Int(num) => encode_int(*num, &mut buf),
+            LpEntry::Str(bytes) => match int_of(bytes) {
+                Some(num) => encode_int(num, &mut buf),
+                None => encode_str(bytes, &mut buf),
+            },
+        }
+        encode_backlen(buf.len() - start, &mut buf);
+    }
+    buf.push(EOF);
+
+    let total = buf.len() as u32;
+    // Listpacks of more entries than u16 holds record the maximum, meaning unknown.
+    let count = u16::try_from(entries.len()).unwrap_or(u16::MAX);
+    buf[..4].copy_from_slice(&total.to_le_bytes());
+    buf[4..HEADER_LEN].copy_from_slice(&count.to_le_bytes());
+    buf
+}
+
+/// Reads the entries of a listpack.
+pub(crate) fn decode(bytes: &[u8]) -> RedisResult<Vec<LpEntry>> {
+    let total = slice(bytes, 0, 4)?;
+    if u32::from_le_bytes([total[0], total[1], total[2], total[3]]) as usize != bytes.len() {
+        return Err(RedisError::Encoding);
+    }
+
+    let mut entries: Vec<LpEntry> = vec![];
+    let mut pos = HEADER_LEN;
+    while *bytes.get(pos).ok_or(RedisError::Encoding)? != EOF {
+        let (entry, len) = decode_entry(&bytes[pos..])?;
+        pos += len + backlen_size(len);
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Describes the listpack entry by entry, with the offset each starts at and the bytes
+/// it takes, as DEBUG LISTPACK prints it.
+pub(crate) fn repr(bytes: &[u8]) -> RedisResult<String> {
+    let entries = decode(bytes)?;
+    let mut out = format!(
+        "{{total bytes {}}} {{num entries {}}}\n",
+        bytes.len(),
+        entries.len()
+    );
+    let mut pos = HEADER_LEN;
+    for (index, entry) in entries.into_iter().enumerate() {
+        let (_, len) = decode_entry(&bytes[pos..])?;
+        let value = match entry {
+            LpEntry::Int(num) => format!("[int]{num}"),
+            LpEntry::Str(bytes) => format!("[str]{}", String::from_utf8_lossy(&bytes)),
+        };
+        out.push_str(&format!(
+            "{{index {index}}} {{offset {pos}}} {{entry len {len}}} {{backlen {}}} {value}\n",
+            backlen_size(len)
+        ));
+        pos += len + backlen_size(len);
+    }
+    out.push_str(&format!("{{end}} {{offset {pos}}}\n"));
+    Ok(out)
+}
+
+fn int_of(bytes: &[u8]) -> Option<i64> {
+    let value = std::str::from_utf8(bytes).ok()?;
+    value
+        .parse::<i64>()
+        .ok()
+        .filter(|num| num.to_string() == value)
+}
+
+fn encode_int(num: i64, buf: &mut Vec<u8>) {
+    if (0..=127).contains(&num) {
+        buf.push(num as u8);
+    } else if (-4096..=4095).contains(&num) {
+        let num = (num as u16) & 0x1fff;
+        buf.extend_from_slice(&[0xc0 | (num >> 8) as u8, num as u8]);
+    } else if let Ok(num) = i16::try_from(num) {
+        buf.push(0xf1);
+        buf.extend_from_slice(&num.to_le_bytes());
+    } else if (-(1 << 23)..1 << 23).contains(&num) {
+        buf.push(0xf2);
+        buf.extend_from_slice(&num.to_le_bytes()[..3]);
+    } else if let Ok(num) = i32::try_from(num) {
+        buf.push(0xf3);
+        buf.extend_from_slice(&num.to_le_bytes());
+    } else {
+        buf.push(0xf4);
+        buf.extend_from_slice(&num.to_le_bytes());
+    }
+}
+
+fn encode_str(bytes: &[u8], buf: &mut Vec<u8>) {
+    let len = bytes.len();
+    if len < 1 << 6 {
+        buf.push(0x80 | len as u8);
+    } else if len < 1 << 12 {
+        buf.extend_from_slice(&[0xe0 | (len >> 8) as u8, len as u8]);
+    } else {
+        buf.push(0xf0);
+        buf.extend_from_slice(&(len as u32).to_le_bytes());
+    }
+    buf.extend_from_slice(bytes);
+}
+
+/// Writes the length of the entry after it, so that listpacks can be walked backwards.
+/// Each byte holds 7 bits, and all but the most significant one have the high bit set.
+fn encode_backlen(len: usize, buf: &mut Vec<u8>) {
+    let size = backlen_size(len);
+    for i in (0..size).rev() {
+        let byte = ((len >> (7 * i)) & 0x7f) as u8;
+        buf.push(if i + 1 < size { byte | 0x80 } else { byte });
+    }
+}
+
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// Reads the entry at the start of the bytes, returning it with the bytes it takes
+/// without its backlen.
+fn decode_entry(b: &[u8]) -> RedisResult<(LpEntry, usize)> {
+    let byte = b[0];
+    let entry = match byte {
+        0x00..=0x7f => (LpEntry::Int(byte.into()), 1),
+        0x80..=0xbf => {
+            let len = usize::from(byte & 0x3f);
+            (LpEntry::Str(slice(b, 1, len)?.to_vec()), 1 + len)
+        }
+        0xc0..=0xdf => {
+            let num = u16::from(byte & 0x1f) << 8 | u16::from(slice(b, 1, 1)?[0]);
+            // Shifting the 13 bits to the top and back extends the sign.
+            (LpEntry::Int(i64::from(((num << 3) as i16) >> 3)), 2)
+        }
+        0xe0..=0xef => {
+            let low = slice(b, 1, 1)?[0];
+            let len = usize::from(byte & 0x0f) << 8 | usize::from(low);
+            (LpEntry::Str(slice(b, 2, len)?.to_vec()), 2 + len)
+        }
+        0xf0 => {
+            let len = slice(b, 1, 4)?;
+            let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+            (LpEntry::Str(slice(b, 5, len)?.to_vec()), 5 + len)
+        }
+        0xf1 => {
+            let num = slice(b, 1, 2)?;
+            (LpEntry::Int(i16::from_le_bytes([num[0], num[1]]).into()), 3)
+        }
+        0xf2 => {
+            let num = slice(b, 1, 3)?;
+            let num = i32::from_le_bytes([0, num[0], num[1], num[2]]) >> 8;
+            (LpEntry::Int(num.into()), 4)
+        }
+        0xf3 => {
+            let num = slice(b, 1, 4)?;
+            let num = i32::from_le_bytes([num[0], num[1], num[2], num[3]]);
+            (LpEntry::Int(num.into()), 5)
+        }
+        0xf4 => {
+            let mut num = [0u8; 8];
+            num.copy_from_slice(slice(b, 1, 8)?);
+            (LpEntry::Int(i64::from_le_bytes(num)), 9)
+        }
+        _ => return Err(RedisError::Encoding),
+    };
+    Ok(entry)
+}
+
+fn slice(bytes: &[u8], start: usize, len: usize) -> RedisResult<&[u8]> {
+    bytes.get(start..start + len).ok_or(RedisError::Encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_encodes_listpacks() {
+        let bytes = encode(&[LpEntry::Int(1), LpEntry::Str(b"ab".to_vec())]);
+        let expected = [13, 0, 0, 0, 2, 0, 0x01, 0x01, 0x82, b'a', b'b', 0x03, 0xff];
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn it_describes_listpacks() {
+        let bytes = encode(&[LpEntry::Int(1), LpEntry::Str(b"ab".to_vec())]);
+        assert_eq!(
+            repr(&bytes).unwrap(),
+            "{total bytes 13} {num entries 2}\n\
+             {index 0} {offset 6} {entry len 1} {backlen 1} [int]1\n\
+             {index 1} {offset 8} {entry len 3} {backlen 1} [str]ab\n\
+             {end} {offset 12}\n"
+        );
+        assert!(repr(&bytes[..10]).is_err());
+    }
+
+    #[test]
+    fn it_decodes_encoded_listpacks() {
+        let mut entries: Vec<LpEntry> = [
+            0,
+            127,
+            -1,
+            4095,
+            -4096,
+            30000,
+            -8_000_000,
+            1 << 30,
+            i64::MIN,
+        ]
+        .into_iter()
+        .map(LpEntry::Int)
+        .collect();
+        entries.push(LpEntry::Str(b"hello".to_vec()));
+        entries.push(LpEntry::Str(vec![b'x'; 100]));
+        entries.push(LpEntry::Str(vec![b'y'; 5000]));
+        entries.push(LpEntry::Str(b"0123".to_vec()));
+
+        let decoded = decode(&encode(&entries)).unwrap();
+        assert_eq!(decoded, entries);
+
+        // Strings holding integers come back as integers.
+        let decoded = decode(&encode(&[LpEntry::Str(b"-42".to_vec())])).unwrap();
+        assert_eq!(decoded, vec![LpEntry::Int(-42)]);
+    }
+}
@@ -1,50 +1,390 @@
 mod enc;
 mod file;
+mod hash;
+mod list;
+mod listpack;
+mod module;
+mod set;
+mod stream;
 
 use super::{utils, value::Value, Config, RedisError, RedisResult};
+use enc::{crc64, encode_size, encode_string};
 use file::{RdbElement, RdbFile};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{ErrorKind, Read};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) use module::{encode_module, read_module};
+
+/// Describes the listpacks the value is saved in, node by node, for DEBUG LISTPACK.
+/// Lists and streams are the only values kept in listpacks.
+pub(crate) fn listpack_repr(value: &Value) -> RedisResult<String> {
+    let mut out = String::new();
+    match value {
+        Value::List(list) => {
+            for (n, node) in list::encode_nodes(list).iter().enumerate() {
+                out.push_str(&format!("{{node {n}}}\n"));
+                out.push_str(&listpack::repr(node)?);
+            }
+        }
+        Value::Stream(stream) => {
+            for (n, (master_id, node)) in stream::encode_nodes(stream).iter().enumerate() {
+                out.push_str(&format!("{{node {n}}} {{master id {master_id}}}\n"));
+                out.push_str(&listpack::repr(node)?);
+            }
+        }
+        _ => return Err(anyhow::anyhow!("ERR Not a listpack encoded object.").into()),
+    }
+    Ok(out)
+}
+
+const RDB_VERSION: &str = "REDIS0011";
+pub(crate) const REDIS_VER: &str = "7.2.0";
 
 #[derive(Debug, Clone, Default)]
-pub struct Rdb(HashMap<String, Value>);
+pub struct Rdb {
+    db: HashMap<String, Value>,
+    functions: Vec<String>,
+}
 
 impl Rdb {
     pub(crate) fn new<R: Read>(r: R) -> Self {
-        let mut rdb: HashMap<String, Value> = HashMap::new();
+        Self::load(r, |_, _| {})
+    }
+
+    /// Reads the file, telling `progress` the bytes read and the keys loaded so far after
+    /// every element.
+    pub(crate) fn load<R: Read>(r: R, mut progress: impl FnMut(u64, usize)) -> Self {
+        let mut rdb = Self::default();
+        let mut file = RdbFile::new(r);
 
-        for el in RdbFile::new(r) {
-            if let RdbElement::HashTableEntry { key, value, exp } = el {
-                let value = Value::String { value, exp };
-                rdb.insert(key, value);
+        while let Some(el) = file.next() {
+            match el {
+                RdbElement::HashTableEntry { key, value, exp } => {
+                    let value = Value::String { value, exp };
+                    rdb.db.insert(key, value);
+                }
+                // Lists and streams can't expire, so an expiry saved with one is dropped.
+                RdbElement::List { key, list, .. } => {
+                    rdb.db.insert(key, Value::List(list));
+                }
+                RdbElement::Hash { key, hash, .. } => {
+                    rdb.db.insert(key, Value::Hash(hash));
+                }
+                RdbElement::Set { key, set, .. } => {
+                    rdb.db.insert(key, Value::Set(set));
+                }
+                RdbElement::Stream { key, stream, .. } => {
+                    rdb.db.insert(key, Value::Stream(stream));
+                }
+                // Nor can the values of module types.
+                RdbElement::Module { key, value, .. } => {
+                    rdb.db.insert(key, value.into());
+                }
+                RdbElement::Function(code) => rdb.functions.push(code),
+                _ => {}
             }
+            progress(file.offset(), rdb.db.len());
         }
-        Self(rdb)
+        rdb
     }
 
-    pub(crate) fn from_conf(config: &Config) -> RedisResult<Self> {
+    /// Where the RDB file is loaded from at startup, when one is configured.
+    pub(crate) fn path(config: &Config) -> Option<PathBuf> {
         let Config {
             dir, dbfilename, ..
         } = config;
+        Some(Path::new(dir.as_ref()?).join(dbfilename.as_ref()?))
+    }
+
+    pub(crate) fn into_db(self) -> HashMap<String, Value> {
+        self.db
+    }
+
+    /// The code of the function libraries saved in the file.
+    pub(crate) fn functions(&self) -> &[String] {
+        &self.functions
+    }
+
+    /// Serializes the function libraries and the entries into an RDB file of database 0.
+    /// Keys expired at `now` are left out.
+    pub(crate) fn dump<'a>(
+        entries: impl Iterator<Item = (&'a String, &'a Value)>,
+        functions: &[String],
+        now: SystemTime,
+    ) -> Vec<u8> {
+        let mut body: Vec<u8> = vec![];
+        let mut size: usize = 0;
+        let mut expires: usize = 0;
 
-        if let (Some(dir), Some(dbfilename)) = (dir, dbfilename) {
-            let path = format!("{dir}/{dbfilename}");
+        for (key, value) in entries {
+            if value.expired(now) {
+                continue;
+            }
 
-            match File::open(path) {
-                Ok(f) => Ok(Self::new(f)),
-                Err(err) if err.kind() == ErrorKind::NotFound => {
-                    eprintln!("Not found rdb file");
-                    Ok(Self::default())
+            match value {
+                Value::String { value, exp } => {
+                    if let Some(exp) = exp {
+                        let millis = exp
+                            .duration_since(UNIX_EPOCH)
+                            .map(|d| d.as_millis() as u64)
+                            .unwrap_or(0);
+                        body.push(0xfc);
+                        body.extend_from_slice(&millis.to_le_bytes());
+                        expires += 1;
+                    }
+                    body.push(0x00);
+                    encode_string(key, &mut body);
+                    encode_string(value, &mut body);
+                }
+                Value::List(list) => {
+                    body.push(list::TYPE_LIST_QUICKLIST_2);
+                    encode_string(key, &mut body);
+                    list::encode_list(list, &mut body);
+                }
+                Value::Hash(hash) => {
+                    body.push(hash::hash_type(hash));
+                    encode_string(key, &mut body);
+                    hash::encode_hash(hash, &mut body);
+                }
+                Value::Set(set) => {
+                    body.push(set::TYPE_SET);
+                    encode_string(key, &mut body);
+                    set::encode_set(set, &mut body);
                 }
-                Err(err) => Err(RedisError::from(err)),
+                Value::Stream(stream) => {
+                    body.push(stream::TYPE_STREAM_V3);
+                    encode_string(key, &mut body);
+                    stream::encode_stream(stream, &mut body);
+                }
+                Value::Bloom(_) | Value::Cuckoo(_) | Value::TopK(_) => {
+                    body.push(module::TYPE_MODULE_2);
+                    encode_string(key, &mut body);
+                    module::encode_module(value, &mut body);
+                }
+                // Redis saves vector sets as the data of its module, which isn't
+                // written here, so they don't survive saves.
+                Value::VectorSet(_) => continue,
+                // The same goes for JSON documents.
+                Value::Json(_) => continue,
+            }
+            size += 1;
+        }
+
+        let mut buf: Vec<u8> = RDB_VERSION.as_bytes().to_vec();
+        buf.push(0xfa);
+        encode_string("redis-ver", &mut buf);
+        encode_string(REDIS_VER, &mut buf);
+
+        for code in functions {
+            buf.push(0xf5);
+            encode_string(code, &mut buf);
+        }
+
+        buf.extend_from_slice(&[0xfe, 0x00, 0xfb]);
+        encode_size(size, &mut buf);
+        encode_size(expires, &mut buf);
+        buf.extend(body);
+
+        buf.push(0xff);
+        let checksum = crc64(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+        buf
+    }
+
+    /// Walks every element of the RDB file, verifying their encodings and the checksum.
+    /// Keys are counted as expired against `now`.
+    pub(crate) fn check(bytes: &[u8], now: SystemTime) -> Result<RdbCheck, RdbCorruption> {
+        let corrupt = |offset: u64, reason: &str| RdbCorruption {
+            offset,
+            reason: reason.to_string(),
+        };
+        if !bytes.starts_with(b"REDIS") {
+            return Err(corrupt(0, "Wrong signature trying to load DB from file"));
+        }
+
+        let mut report = RdbCheck::default();
+        let mut file = RdbFile::new(bytes);
+        loop {
+            let offset = file.offset();
+            match file.next() {
+                Some(
+                    RdbElement::HashTableEntry { exp, .. }
+                    | RdbElement::List { exp, .. }
+                    | RdbElement::Hash { exp, .. }
+                    | RdbElement::Set { exp, .. }
+                    | RdbElement::Stream { exp, .. }
+                    | RdbElement::Module { exp, .. },
+                ) => {
+                    report.keys += 1;
+                    if let Some(exp) = exp {
+                        report.expires += 1;
+                        if exp <= now {
+                            report.already_expired += 1;
+                        }
+                    }
+                }
+                Some(RdbElement::Checksum(sum)) => {
+                    let end = file.offset() as usize;
+                    let expected = u64::from_le_bytes(sum);
+                    // A zero checksum tells readers that the checksum is disabled.
+                    if expected != 0 {
+                        let actual = crc64(&bytes[..end - sum.len()]);
+                        if actual != expected {
+                            let reason = format!(
+                                "RDB CRC error: expected {expected:016x}, got {actual:016x}"
+                            );
+                            return Err(corrupt(offset, &reason));
+                        }
+                        report.checksum = true;
+                    }
+                    if end < bytes.len() {
+                        return Err(corrupt(end as u64, "Unexpected bytes after the EOF"));
+                    }
+                    report.size = end as u64;
+                    return Ok(report);
+                }
+                Some(_) => {}
+                None => return Err(corrupt(offset, "Invalid or truncated element")),
             }
-        } else {
-            Ok(Self::default())
         }
     }
+}
+
+/// What checking a valid RDB file found.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct RdbCheck {
+    pub(crate) size: u64,
+    pub(crate) keys: usize,
+    pub(crate) expires: usize,
+    pub(crate) already_expired: usize,
+    /// Whether a checksum was present and verified, rather than disabled.
+    pub(crate) checksum: bool,
+}
+
+/// The first problem found checking an RDB file.
+#[derive(Debug, PartialEq)]
+pub(crate) struct RdbCorruption {
+    pub(crate) offset: u64,
+    pub(crate) reason: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::{BloomFilter, RedisHash, RedisStream, StreamEntry, StreamEntryId};
+    use std::collections::HashSet;
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn it_describes_the_listpacks_of_lists_and_streams() {
+        let mut stream = RedisStream::new();
+        let values = [("temp".to_string(), "20".to_string())].into();
+        let entry = StreamEntry::new(StreamEntryId::new(1, 0), values);
+        stream.push(Arc::new(entry)).unwrap();
+
+        let repr = listpack_repr(&Value::Stream(stream)).unwrap();
+        assert!(repr.starts_with("{node 0} {master id 1-0}\n{total bytes "));
+        assert!(repr.contains("[str]temp\n"));
+
+        let list = ["a".to_string(), "7".to_string()].into();
+        let repr = listpack_repr(&Value::List(list)).unwrap();
+        assert!(repr.starts_with("{node 0}\n{total bytes "));
+        assert!(repr.contains("[str]a\n"));
+
+        let value = Value::String {
+            value: "foo".into(),
+            exp: None,
+        };
+        assert!(listpack_repr(&value).is_err());
+    }
+
+    #[test]
+    fn it_dumps_and_loads_entries() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let exp = now + Duration::from_secs(10);
+        let mut db: HashMap<String, Value> = [
+            ("foo", "bar", None),
+            ("num", "42", Some(exp)),
+            ("gone", "baz", Some(now)),
+        ]
+        .into_iter()
+        .map(|(key, value, exp)| {
+            let value = Value::String {
+                value: value.into(),
+                exp,
+            };
+            (key.to_string(), value)
+        })
+        .collect();
+
+        let mut stream = RedisStream::new();
+        let values = [("temp".to_string(), "20".to_string())].into();
+        let entry = StreamEntry::new(StreamEntryId::new(1, 0), values);
+        stream.push(Arc::new(entry)).unwrap();
+        db.insert("events".into(), Value::Stream(stream.clone()));
+        let mut filter = BloomFilter::new(0.01, 10, Some(2)).unwrap();
+        filter.add("seen").unwrap();
+        db.insert("filter".into(), Value::Bloom(filter.clone()));
+        let mut hash = RedisHash::new();
+        hash.insert("n".into(), "1".into());
+        hash.insert("t".into(), "2".into());
+        hash.insert("u".into(), "3".into());
+        hash.set_expiry("t", 2_000_000_000_000);
+        hash.set_expiry("u", 2_000_000_000_500);
+        db.insert("hash".into(), Value::Hash(hash.clone()));
+        let set: HashSet<String> = ["x".to_string(), "1".to_string()].into();
+        db.insert("set".into(), Value::Set(set.clone()));
+
+        let functions = vec!["#!lua name=lib\nredis.register_function('f', f)".to_string()];
+        let bytes = Rdb::dump(db.iter(), &functions, now);
+        let rdb = Rdb::new(Cursor::new(bytes));
+        assert_eq!(rdb.functions(), functions.as_slice());
+
+        let loaded = rdb.into_db();
+
+        assert_eq!(loaded.len(), 6);
+        assert!(matches!(
+            loaded.get("foo"),
+            Some(Value::String { value, exp: None }) if value == "bar"
+        ));
+        assert!(matches!(
+            loaded.get("num"),
+            Some(Value::String { value, exp: Some(t) }) if value == "42" && *t == exp
+        ));
+        assert!(matches!(loaded.get("events"), Some(Value::Stream(s)) if *s == stream));
+        assert!(matches!(loaded.get("filter"), Some(Value::Bloom(f)) if *f == filter));
+        assert!(matches!(loaded.get("hash"), Some(Value::Hash(h)) if *h == hash));
+        assert!(matches!(loaded.get("set"), Some(Value::Set(s)) if *s == set));
+    }
+
+    #[test]
+    fn it_checks_rdb_files() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let value = Value::String {
+            value: "bar".into(),
+            exp: Some(now + Duration::from_secs(10)),
+        };
+        let db: HashMap<String, Value> = [("foo".to_string(), value)].into();
+        let mut bytes = Rdb::dump(db.iter(), &[], now);
+
+        let report = Rdb::check(&bytes, now + Duration::from_secs(20)).unwrap();
+        assert_eq!(report.keys, 1);
+        assert_eq!(report.expires, 1);
+        assert_eq!(report.already_expired, 1);
+        assert!(report.checksum);
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        let err = Rdb::check(&bytes, now).unwrap_err();
+        assert_eq!(err.offset, (bytes.len() - 9) as u64);
+        assert!(err.reason.starts_with("RDB CRC error"));
 
-    pub(crate) fn db(&self) -> &HashMap<String, Value> {
-        &self.0
+        let err = Rdb::check(&bytes[..bytes.len() - 12], now).unwrap_err();
+        assert!(err.offset < (bytes.len() - 12) as u64);
+        assert_eq!(err.reason, "Invalid or truncated element");
     }
 }
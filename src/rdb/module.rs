@@ -0,0 +1,325 @@
+use super::{
+    enc::{encode_bytes, encode_size, encode_string, read_bytes, EncSize, EncString},
+    RedisError, RedisResult,
+};
+use crate::value::{BloomFilter, BloomLayer, CuckooFilter, TopK, Value};
+use std::io::Read;
+
+/// The value type of the data of module types, which starts with the id of its type.
+pub(crate) const TYPE_MODULE_2: u8 = 0x07;
+
+/// Module data is a run of values, each after the opcode of its kind, up to an EOF.
+const OPCODE_EOF: usize = 0;
+const OPCODE_UINT: usize = 2;
+const OPCODE_DOUBLE: usize = 4;
+const OPCODE_STRING: usize = 5;
+
+/// The characters of the names of module types, six bits each.
+const NAME_CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// The names of the sketch types. Their data is laid out the way this server keeps the
+/// sketches, unlike RedisBloom's, so they are named apart from its types to keep a
+/// server with the module from loading them.
+const BLOOM: &str = "sketch-bf";
+const CUCKOO: &str = "sketch-cf";
+const TOPK: &str = "sketch-tk";
+const ENCVER: u64 = 1;
+
+/// A value of a module type read from the file.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ModuleValue {
+    Bloom(BloomFilter),
+    Cuckoo(CuckooFilter),
+    TopK(TopK),
+}
+
+impl From<ModuleValue> for Value {
+    fn from(value: ModuleValue) -> Self {
+        match value {
+            ModuleValue::Bloom(filter) => Value::Bloom(filter),
+            ModuleValue::Cuckoo(filter) => Value::Cuckoo(filter),
+            ModuleValue::TopK(topk) => Value::TopK(topk),
+        }
+    }
+}
+
+/// The id of the module type: its name, then the version of its encoding in the last
+/// ten bits.
+fn module_id(name: &str) -> u64 {
+    let name = name.bytes().fold(0u64, |id, c| {
+        let pos = NAME_CHARSET
+            .iter()
+            .position(|n| *n == c)
+            .unwrap_or_default();
+        id << 6 | pos as u64
+    });
+    name << 10 | ENCVER
+}
+
+/// Writes the value after `TYPE_MODULE_2` and the key, when it is of a module type this
+/// server saves. Tells whether it was written.
+pub(crate) fn encode_module(value: &Value, buf: &mut Vec<u8>) -> bool {
+    let mut w = Writer(buf);
+    match value {
+        Value::Bloom(filter) => {
+            w.id(BLOOM);
+            w.double(filter.error_rate());
+            w.uint(filter.expansion().map_or(0, u64::from));
+            w.uint(filter.layers().len() as u64);
+            for layer in filter.layers() {
+                w.uint(layer.capacity);
+                w.double(layer.error_rate);
+                w.uint(u64::from(layer.hashes));
+                w.uint(layer.items);
+                let bits: Vec<u8> = layer.bits.iter().flat_map(|w| w.to_le_bytes()).collect();
+                w.bytes(&bits);
+            }
+        }
+        Value::Cuckoo(filter) => {
+            w.id(CUCKOO);
+            w.uint(filter.bucket_size() as u64);
+            w.uint(u64::from(filter.max_iterations()));
+            w.uint(u64::from(filter.expansion()));
+            w.uint(filter.len());
+            w.uint(filter.deletes());
+            w.uint(filter.tables().len() as u64);
+            for table in filter.tables() {
+                w.bytes(table);
+            }
+        }
+        Value::TopK(topk) => {
+            w.id(TOPK);
+            w.uint(topk.k() as u64);
+            w.uint(topk.width() as u64);
+            w.uint(topk.depth() as u64);
+            w.double(topk.decay());
+            w.uint(topk.rng());
+            let counters: Vec<u8> = topk
+                .counters()
+                .iter()
+                .flat_map(|(fp, count)| [fp.to_le_bytes(), count.to_le_bytes()])
+                .flatten()
+                .collect();
+            w.bytes(&counters);
+            w.uint(topk.list().len() as u64);
+            for (item, count) in topk.list() {
+                w.string(item);
+                w.uint(u64::from(*count));
+            }
+        }
+        _ => return false,
+    }
+    encode_size(OPCODE_EOF, w.0);
+    true
+}
+
+/// Reads the value after `TYPE_MODULE_2` and the key.
+pub(crate) fn read_module<R: Read>(r: &mut R) -> RedisResult<ModuleValue> {
+    let id = EncSize::new(r)?.value().ok_or(RedisError::Encoding)? as u64;
+    let mut r = Reader(r);
+    let value = if id == module_id(BLOOM) {
+        let error_rate = r.double()?;
+        let expansion = Some(r.uint()? as u32).filter(|n| *n > 0);
+        let layers = (0..r.uint()?)
+            .map(|_| {
+                let capacity = r.uint()?;
+                let error_rate = r.double()?;
+                let hashes = r.uint()? as u32;
+                let items = r.uint()?;
+                let bits = r
+                    .bytes()?
+                    .chunks_exact(8)
+                    .map(|word| u64::from_le_bytes(word.try_into().unwrap_or_default()))
+                    .collect::<Vec<u64>>();
+                if bits.is_empty() || hashes == 0 {
+                    return Err(RedisError::Encoding);
+                }
+                Ok(BloomLayer {
+                    capacity,
+                    error_rate,
+                    hashes,
+                    items,
+                    bits,
+                })
+            })
+            .collect::<RedisResult<Vec<_>>>()?;
+        if layers.is_empty() {
+            return Err(RedisError::Encoding);
+        }
+        ModuleValue::Bloom(BloomFilter::from_layers(error_rate, expansion, layers))
+    } else if id == module_id(CUCKOO) {
+        let bucket_size = r.uint()? as usize;
+        let max_iterations = r.uint()? as u32;
+        let expansion = r.uint()? as u32;
+        let items = r.uint()?;
+        let deletes = r.uint()?;
+        let tables = (0..r.uint()?)
+            .map(|_| r.bytes())
+            .collect::<RedisResult<Vec<_>>>()?;
+        // Every table holds a power of two of whole buckets.
+        let whole = |table: &Vec<u8>| {
+            bucket_size > 0
+                && table.len().is_multiple_of(bucket_size)
+                && (table.len() / bucket_size).is_power_of_two()
+        };
+        if tables.is_empty() || !tables.iter().all(whole) {
+            return Err(RedisError::Encoding);
+        }
+        ModuleValue::Cuckoo(CuckooFilter::from_tables(
+            bucket_size,
+            max_iterations,
+            expansion,
+            items,
+            deletes,
+            tables,
+        ))
+    } else if id == module_id(TOPK) {
+        let k = r.uint()? as usize;
+        let width = r.uint()? as usize;
+        let depth = r.uint()? as usize;
+        let decay = r.double()?;
+        let rng = r.uint()?;
+        let counters: Vec<(u32, u32)> = r
+            .bytes()?
+            .chunks_exact(8)
+            .map(|c| {
+                let fp = u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+                (fp, u32::from_le_bytes([c[4], c[5], c[6], c[7]]))
+            })
+            .collect();
+        if width == 0 || counters.len() != width * depth {
+            return Err(RedisError::Encoding);
+        }
+        let top = (0..r.uint()?)
+            .map(|_| Ok((r.string()?, r.uint()? as u32)))
+            .collect::<RedisResult<Vec<_>>>()?;
+        ModuleValue::TopK(TopK::from_parts(
+            (k, width, depth, decay),
+            counters,
+            top,
+            rng,
+        ))
+    } else {
+        eprintln!("Unknown rdb module type id: {id:#x}");
+        return Err(RedisError::Encoding);
+    };
+
+    r.expect(OPCODE_EOF)?;
+    Ok(value)
+}
+
+struct Writer<'a>(&'a mut Vec<u8>);
+
+impl Writer<'_> {
+    fn id(&mut self, name: &str) {
+        encode_size(module_id(name) as usize, self.0);
+    }
+
+    fn uint(&mut self, value: u64) {
+        encode_size(OPCODE_UINT, self.0);
+        encode_size(value as usize, self.0);
+    }
+
+    fn double(&mut self, value: f64) {
+        encode_size(OPCODE_DOUBLE, self.0);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        encode_size(OPCODE_STRING, self.0);
+        encode_string(value, self.0);
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        encode_size(OPCODE_STRING, self.0);
+        encode_bytes(value, self.0);
+    }
+}
+
+struct Reader<'a, R>(&'a mut R);
+
+impl<R: Read> Reader<'_, R> {
+    fn size(&mut self) -> RedisResult<usize> {
+        EncSize::new(self.0)?.value().ok_or(RedisError::Encoding)
+    }
+
+    fn expect(&mut self, opcode: usize) -> RedisResult<()> {
+        if self.size()? == opcode {
+            Ok(())
+        } else {
+            Err(RedisError::Encoding)
+        }
+    }
+
+    fn uint(&mut self) -> RedisResult<u64> {
+        self.expect(OPCODE_UINT)?;
+        Ok(self.size()? as u64)
+    }
+
+    fn double(&mut self) -> RedisResult<f64> {
+        self.expect(OPCODE_DOUBLE)?;
+        let mut buf = [0u8; 8];
+        self.0.read_exact(&mut buf)?;
+        Ok(f64::from_le_bytes(buf))
+    }
+
+    fn string(&mut self) -> RedisResult<String> {
+        self.expect(OPCODE_STRING)?;
+        Ok(EncString::new(self.0)?.value().to_string())
+    }
+
+    fn bytes(&mut self) -> RedisResult<Vec<u8>> {
+        self.expect(OPCODE_STRING)?;
+        read_bytes(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn round_trip(value: Value) -> ModuleValue {
+        let mut buf = vec![];
+        assert!(encode_module(&value, &mut buf));
+        let mut r = Cursor::new(&buf);
+        let read = read_module(&mut r).unwrap();
+        assert_eq!(r.position() as usize, buf.len());
+        read
+    }
+
+    #[test]
+    fn it_writes_and_reads_sketches() {
+        let mut bloom = BloomFilter::new(0.01, 2, Some(2)).unwrap();
+        for item in ["a", "b", "c"] {
+            bloom.add(item).unwrap();
+        }
+        assert_eq!(
+            round_trip(Value::Bloom(bloom.clone())),
+            ModuleValue::Bloom(bloom)
+        );
+
+        let mut cuckoo = CuckooFilter::new(4, 2, 10, 0).unwrap();
+        cuckoo.add("a").unwrap();
+        cuckoo.add("a").unwrap();
+        cuckoo.delete("a");
+        assert_eq!(
+            round_trip(Value::Cuckoo(cuckoo.clone())),
+            ModuleValue::Cuckoo(cuckoo)
+        );
+
+        let mut topk = TopK::new(2, 8, 3, 0.9);
+        for item in ["a", "b", "a", "c"] {
+            topk.incr_by(item, 1);
+        }
+        assert_eq!(
+            round_trip(Value::TopK(topk.clone())),
+            ModuleValue::TopK(topk)
+        );
+
+        assert!(!encode_module(&Value::Json(crate::Json::Null), &mut vec![]));
+        let mut unknown = vec![];
+        encode_size(module_id("sketch-xx") as usize, &mut unknown);
+        assert!(read_module(&mut Cursor::new(unknown)).is_err());
+    }
+}
@@ -0,0 +1,26 @@
+use super::{
+    enc::{encode_size, encode_string, EncSize, EncString},
+    RedisError, RedisResult,
+};
+use std::collections::HashSet;
+use std::io::Read;
+
+/// The value type of sets saved member after member, as Redis saves them as hash tables.
+pub(crate) const TYPE_SET: u8 = 0x02;
+
+/// Writes the members in order, so that equal sets are saved alike.
+pub(crate) fn encode_set(set: &HashSet<String>, buf: &mut Vec<u8>) {
+    let mut members: Vec<&String> = set.iter().collect();
+    members.sort_unstable();
+    encode_size(members.len(), buf);
+    for member in members {
+        encode_string(member, buf);
+    }
+}
+
+pub(crate) fn read_set<R: Read>(r: &mut R) -> RedisResult<HashSet<String>> {
+    let len = EncSize::new(r)?.value().ok_or(RedisError::Encoding)?;
+    (0..len)
+        .map(|_| Ok(EncString::new(r)?.value().to_string()))
+        .collect()
+}
@@ -0,0 +1,402 @@
+use super::{
+    enc::{encode_bytes, encode_size, encode_string, read_bytes, EncSize, EncString},
+    listpack::{self, LpEntry},
+    RedisError, RedisResult,
+};
+use crate::value::{
+    Consumer, ConsumerGroup, PendingEntry, RedisStream, StreamEntry, StreamEntryId,
+};
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Arc;
+
+/// The value types of streams, one per version of their format. The second adds the
+/// first id, the deletion and addition counters and the entries read by groups; the
+/// third adds the time consumers were last active.
+pub(crate) const TYPE_STREAM_V1: u8 = 0x0f;
+pub(crate) const TYPE_STREAM_V2: u8 = 0x13;
+pub(crate) const TYPE_STREAM_V3: u8 = 0x15;
+
+/// Entries per listpack node, as stream-node-max-entries defaults to.
+const NODE_MAX_ENTRIES: usize = 100;
+const FLAG_DELETED: i64 = 1;
+const FLAG_SAMEFIELDS: i64 = 2;
+
+/// Written for groups which don't know how many entries they have read.
+const ENTRIES_READ_UNKNOWN: u64 = u64::MAX;
+
+/// Writes the stream in the latest format, `TYPE_STREAM_V3`.
+pub(crate) fn encode_stream(stream: &RedisStream, buf: &mut Vec<u8>) {
+    let nodes = encode_nodes(stream);
+    encode_size(nodes.len(), buf);
+    for (master_id, node) in nodes {
+        encode_bytes(&raw_id(master_id), buf);
+        encode_bytes(&node, buf);
+    }
+
+    let entries = stream.entries();
+    let zero = StreamEntryId::new(0, 0);
+    encode_size(entries.len(), buf);
+    encode_id(stream.last_id().unwrap_or(zero), buf);
+    encode_id(entries.first().map(|e| e.id()).unwrap_or(zero), buf);
+    encode_id(stream.max_deleted_id(), buf);
+    encode_size(stream.entries_added() as usize, buf);
+
+    encode_size(stream.groups().len(), buf);
+    for group in stream.groups() {
+        encode_string(&group.name, buf);
+        encode_id(group.last_id, buf);
+        encode_size(
+            group.entries_read.unwrap_or(ENTRIES_READ_UNKNOWN) as usize,
+            buf,
+        );
+
+        encode_size(group.pending.len(), buf);
+        for pending in group.pending.iter() {
+            buf.extend_from_slice(&raw_id(pending.id));
+            buf.extend_from_slice(&pending.delivery_time.to_le_bytes());
+            encode_size(pending.delivery_count as usize, buf);
+        }
+
+        encode_size(group.consumers.len(), buf);
+        for consumer in group.consumers.iter() {
+            encode_string(&consumer.name, buf);
+            buf.extend_from_slice(&consumer.seen_time.to_le_bytes());
+            buf.extend_from_slice(&consumer.active_time.to_le_bytes());
+            encode_size(consumer.pending.len(), buf);
+            for id in consumer.pending.iter() {
+                buf.extend_from_slice(&raw_id(*id));
+            }
+        }
+    }
+}
+
+/// The listpacks the entries are saved in, each with the id of its first entry, which
+/// the ids of the others are relative to.
+pub(crate) fn encode_nodes(stream: &RedisStream) -> Vec<(StreamEntryId, Vec<u8>)> {
+    stream
+        .entries()
+        .chunks(NODE_MAX_ENTRIES)
+        .map(|node| {
+            let master_id = node[0].id();
+            (master_id, encode_node(master_id, node))
+        })
+        .collect()
+}
+
+/// Reads a stream of any of the formats.
+pub(crate) fn read_stream<R: Read>(r: &mut R, value_type: u8) -> RedisResult<RedisStream> {
+    let mut stream = RedisStream::new();
+
+    for _ in 0..read_size(r)? {
+        let master_id = parse_raw_id(&read_bytes(r)?)?;
+        let node = listpack::decode(&read_bytes(r)?)?;
+        read_node(master_id, node, &mut stream)?;
+    }
+
+    // The length and the first id follow from the entries.
+    let _length = read_size(r)?;
+    let last_id = read_id(r)?;
+    if value_type == TYPE_STREAM_V1 {
+        stream.set_meta(last_id, StreamEntryId::new(0, 0), 0);
+    } else {
+        let _first_id = read_id(r)?;
+        let max_deleted_id = read_id(r)?;
+        let entries_added = read_size(r)? as u64;
+        stream.set_meta(last_id, max_deleted_id, entries_added);
+    }
+
+    let mut groups: Vec<ConsumerGroup> = vec![];
+    for _ in 0..read_size(r)? {
+        let name = EncString::new(r)?.value().to_string();
+        let last_id = read_id(r)?;
+        let entries_read = if value_type == TYPE_STREAM_V1 {
+            None
+        } else {
+            Some(read_size(r)? as u64).filter(|v| *v != ENTRIES_READ_UNKNOWN)
+        };
+
+        let mut pending: Vec<PendingEntry> = vec![];
+        for _ in 0..read_size(r)? {
+            pending.push(PendingEntry {
+                id: read_raw_id(r)?,
+                delivery_time: read_millis(r)?,
+                delivery_count: read_size(r)? as u64,
+            });
+        }
+
+        let mut consumers: Vec<Consumer> = vec![];
+        for _ in 0..read_size(r)? {
+            let name = EncString::new(r)?.value().to_string();
+            let seen_time = read_millis(r)?;
+            let active_time = if value_type == TYPE_STREAM_V3 {
+                read_millis(r)?
+            } else {
+                seen_time
+            };
+            let pending = (0..read_size(r)?)
+                .map(|_| read_raw_id(r))
+                .collect::<RedisResult<Vec<StreamEntryId>>>()?;
+            consumers.push(Consumer {
+                name,
+                seen_time,
+                active_time,
+                pending,
+            });
+        }
+
+        groups.push(ConsumerGroup {
+            name,
+            last_id,
+            entries_read,
+            pending,
+            consumers,
+        });
+    }
+    stream.set_groups(groups);
+
+    Ok(stream)
+}
+
+/// Lays the entries out in a listpack: a master entry with the fields of the first
+/// entry, then each entry with its id relative to the master id. Entries with the
+/// master fields store their values only.
+fn encode_node(master_id: StreamEntryId, node: &[Arc<StreamEntry>]) -> Vec<u8> {
+    let master_fields = sorted_fields(&node[0]);
+
+    let mut lp: Vec<LpEntry> = vec![
+        LpEntry::Int(node.len() as i64),
+        LpEntry::Int(0),
+        LpEntry::Int(master_fields.len() as i64),
+    ];
+    lp.extend(master_fields.iter().map(|f| str_entry(f)));
+    lp.push(LpEntry::Int(0));
+
+    for entry in node {
+        let id = entry.id();
+        let fields = sorted_fields(entry);
+        let same_fields = fields == master_fields;
+
+        lp.push(LpEntry::Int(if same_fields { FLAG_SAMEFIELDS } else { 0 }));
+        lp.push(LpEntry::Int(id.ms().wrapping_sub(master_id.ms()) as i64));
+        lp.push(LpEntry::Int(id.seq().wrapping_sub(master_id.seq()) as i64));
+
+        let values = entry.values();
+        if same_fields {
+            lp.extend(fields.iter().map(|f| str_entry(&values[*f])));
+            lp.push(LpEntry::Int(fields.len() as i64 + 3));
+        } else {
+            lp.push(LpEntry::Int(fields.len() as i64));
+            for field in fields.iter() {
+                lp.push(str_entry(field));
+                lp.push(str_entry(&values[*field]));
+            }
+            lp.push(LpEntry::Int(2 * fields.len() as i64 + 4));
+        }
+    }
+
+    listpack::encode(&lp)
+}
+
+fn read_node(
+    master_id: StreamEntryId,
+    node: Vec<LpEntry>,
+    stream: &mut RedisStream,
+) -> RedisResult<()> {
+    let mut items = node.into_iter();
+
+    let count = next_size(&mut items)?;
+    let deleted = next_size(&mut items)?;
+    let master_fields = (0..next_size(&mut items)?)
+        .map(|_| next_string(&mut items))
+        .collect::<RedisResult<Vec<String>>>()?;
+    if next_int(&mut items)? != 0 {
+        return Err(RedisError::Encoding);
+    }
+
+    for _ in 0..count + deleted {
+        let flags = next_int(&mut items)?;
+        let ms = master_id.ms().wrapping_add(next_int(&mut items)? as u64);
+        let seq = master_id.seq().wrapping_add(next_int(&mut items)? as u64);
+
+        let mut values: HashMap<String, String> = HashMap::new();
+        if flags & FLAG_SAMEFIELDS != 0 {
+            for field in master_fields.iter() {
+                values.insert(field.clone(), next_string(&mut items)?);
+            }
+        } else {
+            for _ in 0..next_size(&mut items)? {
+                let field = next_string(&mut items)?;
+                values.insert(field, next_string(&mut items)?);
+            }
+        }
+        let _lp_count = next_int(&mut items)?;
+
+        if flags & FLAG_DELETED == 0 {
+            let entry = StreamEntry::new(StreamEntryId::new(ms, seq), values);
+            stream.push(Arc::new(entry))?;
+        }
+    }
+    Ok(())
+}
+
+fn sorted_fields(entry: &StreamEntry) -> Vec<&String> {
+    let mut fields: Vec<&String> = entry.values().keys().collect();
+    fields.sort();
+    fields
+}
+
+fn str_entry(value: &str) -> LpEntry {
+    LpEntry::Str(value.as_bytes().to_vec())
+}
+
+fn next_int(items: &mut impl Iterator<Item = LpEntry>) -> RedisResult<i64> {
+    items
+        .next()
+        .and_then(|item| item.as_int())
+        .ok_or(RedisError::Encoding)
+}
+
+fn next_size(items: &mut impl Iterator<Item = LpEntry>) -> RedisResult<usize> {
+    usize::try_from(next_int(items)?).map_err(|_| RedisError::Encoding)
+}
+
+fn next_string(items: &mut impl Iterator<Item = LpEntry>) -> RedisResult<String> {
+    let bytes = items.next().ok_or(RedisError::Encoding)?.into_bytes();
+    String::from_utf8(bytes).map_err(|_| RedisError::Encoding)
+}
+
+/// The id as 16 big endian bytes, as node keys and pending entries store it.
+fn raw_id(id: StreamEntryId) -> [u8; 16] {
+    let mut buf = [0u8; 16];
+    buf[..8].copy_from_slice(&id.ms().to_be_bytes());
+    buf[8..].copy_from_slice(&id.seq().to_be_bytes());
+    buf
+}
+
+fn parse_raw_id(bytes: &[u8]) -> RedisResult<StreamEntryId> {
+    let bytes: [u8; 16] = bytes.try_into().map_err(|_| RedisError::Encoding)?;
+    let mut ms = [0u8; 8];
+    let mut seq = [0u8; 8];
+    ms.copy_from_slice(&bytes[..8]);
+    seq.copy_from_slice(&bytes[8..]);
+    Ok(StreamEntryId::new(
+        u64::from_be_bytes(ms),
+        u64::from_be_bytes(seq),
+    ))
+}
+
+fn read_raw_id<R: Read>(r: &mut R) -> RedisResult<StreamEntryId> {
+    let mut buf = [0u8; 16];
+    r.read_exact(&mut buf)?;
+    parse_raw_id(&buf)
+}
+
+fn encode_id(id: StreamEntryId, buf: &mut Vec<u8>) {
+    encode_size(id.ms() as usize, buf);
+    encode_size(id.seq() as usize, buf);
+}
+
+fn read_id<R: Read>(r: &mut R) -> RedisResult<StreamEntryId> {
+    let ms = read_size(r)? as u64;
+    let seq = read_size(r)? as u64;
+    Ok(StreamEntryId::new(ms, seq))
+}
+
+fn read_size<R: Read>(r: &mut R) -> RedisResult<usize> {
+    EncSize::new(r)?.value().ok_or(RedisError::Encoding)
+}
+
+fn read_millis<R: Read>(r: &mut R) -> RedisResult<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::DeletePolicy;
+    use std::io::Cursor;
+
+    fn entry(ms: u64, seq: u64, values: &[(&str, &str)]) -> Arc<StreamEntry> {
+        let values = values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Arc::new(StreamEntry::new(StreamEntryId::new(ms, seq), values))
+    }
+
+    #[test]
+    fn it_encodes_and_reads_streams() {
+        let mut stream = RedisStream::new();
+        for i in 0..150 {
+            stream
+                .push(entry(
+                    1000 + i / 3,
+                    i % 3,
+                    &[("temp", "20"), ("place", "kyoto")],
+                ))
+                .unwrap();
+        }
+        stream
+            .push(entry(2000, 0, &[("other", "fields"), ("n", "-7")]))
+            .unwrap();
+        stream.set_groups(vec![ConsumerGroup {
+            name: "group".into(),
+            last_id: StreamEntryId::new(1001, 2),
+            entries_read: Some(6),
+            pending: vec![PendingEntry {
+                id: StreamEntryId::new(1001, 2),
+                delivery_time: 1_700_000_000_000,
+                delivery_count: 2,
+            }],
+            consumers: vec![Consumer {
+                name: "alice".into(),
+                seen_time: 1_700_000_000_000,
+                active_time: 1_700_000_000_100,
+                pending: vec![StreamEntryId::new(1001, 2)],
+            }],
+        }]);
+
+        // Deleting the last entry leaves the ids the stream goes on from.
+        stream.delete(&[StreamEntryId::new(2000, 0)], DeletePolicy::KeepRef);
+
+        let mut buf: Vec<u8> = vec![];
+        encode_stream(&stream, &mut buf);
+        let read = read_stream(&mut Cursor::new(buf), TYPE_STREAM_V3).unwrap();
+        assert_eq!(read, stream);
+        assert_eq!(read.last_id(), Some(StreamEntryId::new(2000, 0)));
+    }
+
+    #[test]
+    fn it_skips_deleted_entries() {
+        // A node of two entries, of which the second is deleted.
+        let lp = listpack::encode(&[
+            LpEntry::Int(1),
+            LpEntry::Int(1),
+            LpEntry::Int(1),
+            str_entry("f"),
+            LpEntry::Int(0),
+            LpEntry::Int(FLAG_SAMEFIELDS),
+            LpEntry::Int(0),
+            LpEntry::Int(0),
+            str_entry("a"),
+            LpEntry::Int(4),
+            LpEntry::Int(FLAG_SAMEFIELDS | FLAG_DELETED),
+            LpEntry::Int(0),
+            LpEntry::Int(1),
+            str_entry("b"),
+            LpEntry::Int(4),
+        ]);
+
+        let mut stream = RedisStream::new();
+        read_node(
+            StreamEntryId::new(5, 0),
+            listpack::decode(&lp).unwrap(),
+            &mut stream,
+        )
+        .unwrap();
+        assert_eq!(stream.entries(), &[entry(5, 0, &[("f", "a")])]);
+    }
+}
@@ -3,6 +3,13 @@ use super::{
     RedisError, RedisResult,
 };
 use std::fmt;
+use thiserror::Error;
+
+/// Bulk strings longer than this are refused by default, as with Redis'
+/// `proto-max-bulk-len`.
+pub(crate) const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// Arrays nested deeper than this are refused, so that parsing can't exhaust the stack.
+pub(crate) const MAX_DEPTH: usize = 128;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Resp {
@@ -16,6 +23,26 @@ pub enum Resp {
     BS(Option<String>),
     /// Array
     A(Vec<Resp>),
+    /// Null array, which RESP3 connections get as the null
+    NullArray,
+    /// Map, which RESP2 connections get as an array of keys and values
+    Map(Vec<(Resp, Resp)>),
+    /// Set, which RESP2 connections get as an array
+    Set(Vec<Resp>),
+    /// Double, which RESP2 connections get as a bulk string
+    Double(f64),
+    /// Boolean, which RESP2 connections get as the integer 1 or 0
+    Boolean(bool),
+    /// BigNumber, its digits with an optional sign. RESP2 connections get a bulk string.
+    BigNumber(String),
+    /// VerbatimString, with the three letters of its format such as "txt". RESP2
+    /// connections get the text as a bulk string.
+    VerbatimString {
+        format: String,
+        text: String,
+    },
+    /// Push, out of band data which RESP2 connections get as an array
+    Push(Vec<Resp>),
     /// This is invalid RESP
     RAW(Vec<Vec<u8>>),
 }
@@ -27,8 +54,8 @@ impl fmt::Display for Resp {
             Self::SE(val) => write!(f, "{val}"),
             Self::I(val) => write!(f, "{val}"),
             Self::BS(Some(val)) => write!(f, "{val}"),
-            Self::BS(None) => write!(f, ""),
-            Self::A(els) => {
+            Self::BS(None) | Self::NullArray => write!(f, ""),
+            Self::A(els) | Self::Set(els) | Self::Push(els) => {
                 let els = els
                     .iter()
                     .map(|el| format!("{el}"))
@@ -36,6 +63,18 @@ impl fmt::Display for Resp {
                     .join(", ");
                 write!(f, "[{els}]")
             }
+            Self::Double(val) => write!(f, "{val}"),
+            Self::Boolean(val) => write!(f, "{val}"),
+            Self::BigNumber(val) => write!(f, "{val}"),
+            Self::VerbatimString { text, .. } => write!(f, "{text}"),
+            Self::Map(pairs) => {
+                let pairs = pairs
+                    .iter()
+                    .map(|(key, value)| format!("{key}: {value}"))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "{{{pairs}}}")
+            }
             Self::RAW(bytes) => {
                 for raw_bytes in bytes {
                     write!(f, "{}", String::from_utf8_lossy(raw_bytes))?;
@@ -46,6 +85,64 @@ impl fmt::Display for Resp {
     }
 }
 
+/// The version of RESP a connection speaks. Connections start with RESP2, and HELLO
+/// switches them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn from_version(version: i64) -> Option<Self> {
+        match version {
+            2 => Some(Self::Resp2),
+            3 => Some(Self::Resp3),
+            _ => None,
+        }
+    }
+
+    pub fn version(self) -> i64 {
+        match self {
+            Self::Resp2 => 2,
+            Self::Resp3 => 3,
+        }
+    }
+}
+
+/// Why bytes couldn't be parsed as RESP.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum RespError {
+    /// The bytes end before the value does. More bytes may complete it.
+    #[error("unexpected end of input")]
+    Incomplete,
+    #[error("expected a type byte, got '{}'", char::from(*.0).escape_default())]
+    InvalidType(u8),
+    #[error("empty line")]
+    EmptyLine,
+    #[error("invalid integer")]
+    InvalidInteger,
+    #[error("invalid bulk length")]
+    InvalidBulkLength,
+    #[error("invalid multibulk length")]
+    InvalidMultibulkLength,
+    #[error("invalid double")]
+    InvalidDouble,
+    #[error("invalid boolean")]
+    InvalidBoolean,
+    #[error("invalid big number")]
+    InvalidBigNumber,
+    #[error("expected a three letter format before the verbatim string")]
+    InvalidVerbatimFormat,
+    #[error("expected '\\r\\n' after the bulk string")]
+    MissingTerminator,
+    #[error("invalid UTF-8")]
+    InvalidUtf8,
+    #[error("arrays nested deeper than {MAX_DEPTH}")]
+    TooDeep,
+}
+
 impl Resp {
     pub fn new(buf: &[u8]) -> RedisResult<Self> {
         let mut tokens = Tokens::new(buf);
@@ -53,20 +150,57 @@ impl Resp {
     }
 
     pub fn serialize(&self) -> Vec<u8> {
+        self.serialize_as(Protocol::Resp2)
+    }
+
+    /// Serializes the value for a connection speaking the protocol, with the types
+    /// RESP2 lacks written as RESP2 ones.
+    pub fn serialize_as(&self, protocol: Protocol) -> Vec<u8> {
         match self {
             Self::SS(val) => format!("+{val}{TERM}").into_bytes(),
             Self::SE(val) => format!("-{val}{TERM}").into_bytes(),
             Self::I(num) => format!(":{num}{TERM}").into_bytes(),
             Self::BS(Some(val)) => format!("${}{TERM}{val}{TERM}", val.len()).into_bytes(),
+            Self::BS(None) | Self::NullArray if protocol == Protocol::Resp3 => {
+                format!("_{TERM}").into_bytes()
+            }
             Self::BS(None) => format!("$-1{TERM}").into_bytes(),
-            Self::A(vals) => {
-                let len = vals.len();
-                let elements = vals.iter().flat_map(Self::serialize);
-                format!("*{len}{TERM}")
-                    .into_bytes()
-                    .into_iter()
-                    .chain(elements)
-                    .collect()
+            Self::NullArray => format!("*-1{TERM}").into_bytes(),
+            Self::A(vals) => aggregate('*', vals, protocol),
+            Self::Set(vals) if protocol == Protocol::Resp3 => aggregate('~', vals, protocol),
+            Self::Push(vals) if protocol == Protocol::Resp3 => aggregate('>', vals, protocol),
+            Self::Set(vals) | Self::Push(vals) => aggregate('*', vals, protocol),
+            Self::Double(val) => {
+                let val = double(*val);
+                match protocol {
+                    Protocol::Resp2 => format!("${}{TERM}{val}{TERM}", val.len()).into_bytes(),
+                    Protocol::Resp3 => format!(",{val}{TERM}").into_bytes(),
+                }
+            }
+            Self::Boolean(val) => match protocol {
+                Protocol::Resp2 => format!(":{}{TERM}", u8::from(*val)).into_bytes(),
+                Protocol::Resp3 => format!("#{}{TERM}", if *val { 't' } else { 'f' }).into_bytes(),
+            },
+            Self::BigNumber(val) => match protocol {
+                Protocol::Resp2 => format!("${}{TERM}{val}{TERM}", val.len()).into_bytes(),
+                Protocol::Resp3 => format!("({val}{TERM}").into_bytes(),
+            },
+            Self::VerbatimString { format, text } => match protocol {
+                Protocol::Resp2 => format!("${}{TERM}{text}{TERM}", text.len()).into_bytes(),
+                Protocol::Resp3 => {
+                    format!("={}{TERM}{format}:{text}{TERM}", text.len() + 4).into_bytes()
+                }
+            },
+            Self::Map(pairs) => {
+                let header = match protocol {
+                    Protocol::Resp2 => format!("*{}{TERM}", pairs.len() * 2),
+                    Protocol::Resp3 => format!("%{}{TERM}", pairs.len()),
+                };
+                let elements = pairs
+                    .iter()
+                    .flat_map(|(key, value)| [key, value])
+                    .flat_map(|v| v.serialize_as(protocol));
+                header.into_bytes().into_iter().chain(elements).collect()
             }
             Self::RAW(bytes) => bytes.iter().flatten().copied().collect(),
         }
@@ -81,42 +215,150 @@ impl Resp {
     }
 
     pub(crate) fn from_tokens(tokens: &mut Tokens<'_>) -> RedisResult<Self> {
-        match tokens.next() {
-            Some(token) if token.starts_with(b"+") => {
-                let val = utils::stringify(&token[1..])?;
-                Ok(Self::SS(val.into()))
-            }
-            Some(token) if token.starts_with(b"-") => {
-                let val = utils::stringify(&token[1..])?;
-                Ok(Self::SE(val.into()))
+        Self::from_tokens_within(tokens, MAX_BULK_LEN)
+    }
+
+    /// Parses a value, refusing bulk strings longer than `max_bulk_len` bytes.
+    pub(crate) fn from_tokens_within(
+        tokens: &mut Tokens<'_>,
+        max_bulk_len: usize,
+    ) -> RedisResult<Self> {
+        Self::parse(tokens, 0, max_bulk_len).map_err(RedisError::from)
+    }
+
+    /// Parses a value whose lengths come from the peer, so every one is checked against
+    /// the bytes actually there.
+    fn parse(tokens: &mut Tokens<'_>, depth: usize, max_bulk: usize) -> Result<Self, RespError> {
+        let line = tokens.line().ok_or(RespError::Incomplete)?;
+        let (kind, rest) = line.split_first().ok_or(RespError::EmptyLine)?;
+
+        match kind {
+            b'+' => Ok(Self::SS(text(rest)?)),
+            b'-' => Ok(Self::SE(text(rest)?)),
+            b':' => Ok(Self::I(integer(rest)?)),
+            b'$' => {
+                if integer(rest)? == -1 {
+                    return Ok(Self::BS(None));
+                }
+                Ok(Self::BS(Some(text(blob(tokens, rest, max_bulk)?)?)))
             }
-            Some(token) if token.starts_with(b":") => {
-                let num = utils::parse_i64(&token[1..])?;
-                Ok(Self::I(num))
+            b'=' => {
+                let bytes = blob(tokens, rest, max_bulk)?;
+                match bytes.get(3) {
+                    Some(b':') => Ok(Self::VerbatimString {
+                        format: text(&bytes[..3])?,
+                        text: text(&bytes[4..])?,
+                    }),
+                    _ => Err(RespError::InvalidVerbatimFormat),
+                }
             }
-            Some(token) if token == b"$-1" => Ok(Self::BS(None)),
-            Some(token) if token.starts_with(b"$") => {
-                let len = utils::parse_usize(&token[1..])?;
-                tokens
-                    .next()
-                    .ok_or(RedisError::RespSyntax)
-                    .and_then(|v| utils::stringify(&v[..len]))
-                    .map(|v| Self::BS(Some(v.into())))
+            b'_' if rest.is_empty() => Ok(Self::BS(None)),
+            b',' => std::str::from_utf8(rest)
+                .ok()
+                .and_then(|val| val.parse::<f64>().ok())
+                .map(Self::Double)
+                .ok_or(RespError::InvalidDouble),
+            b'#' => match rest {
+                b"t" => Ok(Self::Boolean(true)),
+                b"f" => Ok(Self::Boolean(false)),
+                _ => Err(RespError::InvalidBoolean),
+            },
+            b'(' => {
+                let digits = rest.strip_prefix(b"-").or(rest.strip_prefix(b"+"));
+                match digits.unwrap_or(rest) {
+                    [] => Err(RespError::InvalidBigNumber),
+                    digits if digits.iter().all(u8::is_ascii_digit) => {
+                        Ok(Self::BigNumber(text(rest)?))
+                    }
+                    _ => Err(RespError::InvalidBigNumber),
+                }
             }
-            Some(token) if token.starts_with(b"*") => {
-                let len = utils::parse_usize(&token[1..])?;
-                let mut elements: Vec<Self> = vec![];
-
+            b'*' if integer(rest)? == -1 => Ok(Self::NullArray),
+            b'*' => Ok(Self::A(Self::elements(tokens, rest, depth, max_bulk)?)),
+            b'~' => Ok(Self::Set(Self::elements(tokens, rest, depth, max_bulk)?)),
+            b'>' => Ok(Self::Push(Self::elements(tokens, rest, depth, max_bulk)?)),
+            b'%' => {
+                let len = usize::try_from(integer(rest)?)
+                    .map_err(|_| RespError::InvalidMultibulkLength)?;
+                let mut pairs: Vec<(Self, Self)> = vec![];
                 for _ in 0..len {
-                    let element = Self::from_tokens(tokens)?;
-                    elements.push(element);
+                    if depth >= MAX_DEPTH {
+                        return Err(RespError::TooDeep);
+                    }
+                    let key = Self::parse(tokens, depth + 1, max_bulk)?;
+                    let value = Self::parse(tokens, depth + 1, max_bulk)?;
+                    pairs.push((key, value));
                 }
-
-                Ok(Self::A(elements))
+                Ok(Self::Map(pairs))
             }
-            _ => Err(RedisError::RespSyntax),
+            other => Err(RespError::InvalidType(*other)),
         }
     }
+
+    /// Parses the elements of an array, a set or a push, given the line with their number.
+    fn elements(
+        tokens: &mut Tokens<'_>,
+        len: &[u8],
+        depth: usize,
+        max_bulk: usize,
+    ) -> Result<Vec<Self>, RespError> {
+        if depth >= MAX_DEPTH {
+            return Err(RespError::TooDeep);
+        }
+        let len = usize::try_from(integer(len)?).map_err(|_| RespError::InvalidMultibulkLength)?;
+
+        // The length isn't trusted to reserve room, as the elements may never come.
+        let mut elements: Vec<Self> = vec![];
+        for _ in 0..len {
+            elements.push(Self::parse(tokens, depth + 1, max_bulk)?);
+        }
+        Ok(elements)
+    }
+}
+
+fn aggregate(kind: char, vals: &[Resp], protocol: Protocol) -> Vec<u8> {
+    let elements = vals.iter().flat_map(|v| v.serialize_as(protocol));
+    format!("{kind}{}{TERM}", vals.len())
+        .into_bytes()
+        .into_iter()
+        .chain(elements)
+        .collect()
+}
+
+/// A double as RESP3 writes it, with `inf`, `-inf` and `nan` for what isn't a number.
+fn double(val: f64) -> String {
+    if val.is_nan() {
+        "nan".into()
+    } else if val.is_infinite() {
+        if val > 0.0 { "inf" } else { "-inf" }.into()
+    } else {
+        format!("{val}")
+    }
+}
+
+/// Reads the bytes of a bulk or verbatim string, given the line with their length.
+fn blob<'a>(tokens: &mut Tokens<'a>, len: &[u8], max: usize) -> Result<&'a [u8], RespError> {
+    let len = usize::try_from(integer(len)?)
+        .ok()
+        .filter(|len| *len <= max)
+        .ok_or(RespError::InvalidBulkLength)?;
+
+    let bytes = tokens.take(len).ok_or(RespError::Incomplete)?;
+    match tokens.take(TERM.len()) {
+        Some(term) if term == TERM.as_bytes() => Ok(bytes),
+        Some(_) => Err(RespError::MissingTerminator),
+        None => Err(RespError::Incomplete),
+    }
+}
+
+fn text(bytes: &[u8]) -> Result<String, RespError> {
+    std::str::from_utf8(bytes)
+        .map(String::from)
+        .map_err(|_| RespError::InvalidUtf8)
+}
+
+fn integer(bytes: &[u8]) -> Result<i64, RespError> {
+    utils::parse_i64(bytes).map_err(|_| RespError::InvalidInteger)
 }
 
 impl From<Vec<String>> for Resp {
@@ -128,6 +370,217 @@ impl From<Vec<String>> for Resp {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::IncomingMessage;
+
+    /// A xorshift generator, seeded so that failing inputs can be reproduced.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn text(&mut self, line: bool) -> String {
+            let chars: &[u8] = if line {
+                b"abc XYZ019:+-$*"
+            } else {
+                b"abc XYZ019:+-$*\r\n"
+            };
+            (0..self.below(12))
+                .map(|_| char::from(chars[self.below(chars.len())]))
+                .collect()
+        }
+
+        fn resp(&mut self, depth: usize) -> Resp {
+            match self.below(if depth < 3 { 7 } else { 5 }) {
+                0 => Resp::SS(self.text(true)),
+                1 => Resp::SE(self.text(true)),
+                2 => Resp::I(self.next() as i64),
+                3 => Resp::BS(Some(self.text(false))),
+                4 => Resp::BS(None),
+                5 => Resp::NullArray,
+                _ => Resp::A((0..self.below(5)).map(|_| self.resp(depth + 1)).collect()),
+            }
+        }
+
+        /// A value of any type, which only RESP3 keeps as it is.
+        fn resp3(&mut self, depth: usize) -> Resp {
+            let elements = |rng: &mut Self| -> Vec<Resp> {
+                (0..rng.below(5)).map(|_| rng.resp3(depth + 1)).collect()
+            };
+            match self.below(if depth < 3 { 10 } else { 6 }) {
+                0 => Resp::Double(self.next() as i64 as f64 / 1024.0),
+                1 => Resp::Boolean(self.below(2) == 0),
+                2 => Resp::BigNumber(format!("-{}{}", self.next(), self.next())),
+                3 => Resp::VerbatimString {
+                    format: "txt".into(),
+                    text: self.text(false),
+                },
+                4 | 5 => self.resp(3),
+                6 => Resp::A(elements(self)),
+                7 => Resp::Set(elements(self)),
+                8 => Resp::Push(elements(self)),
+                _ => Resp::Map(
+                    (0..self.below(4))
+                        .map(|_| (self.resp3(depth + 1), self.resp3(depth + 1)))
+                        .collect(),
+                ),
+            }
+        }
+    }
+
+    fn iterations() -> usize {
+        std::env::var("RESP_FUZZ_ITERATIONS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2000)
+    }
+
+    fn parse_err(bytes: &[u8]) -> RespError {
+        match Resp::new(bytes) {
+            Err(RedisError::Protocol(err)) => err,
+            other => panic!("expected a protocol error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn it_reports_typed_errors() {
+        assert_eq!(parse_err(b""), RespError::Incomplete);
+        assert_eq!(parse_err(b"*2\r\n$3\r\nGET\r\n"), RespError::Incomplete);
+        assert_eq!(parse_err(b"$5\r\nhel"), RespError::Incomplete);
+        assert_eq!(parse_err(b"$5\r\nhello"), RespError::Incomplete);
+        assert_eq!(parse_err(b"$3\r\nhello\r\n"), RespError::MissingTerminator);
+        assert_eq!(parse_err(b"$-2\r\n"), RespError::InvalidBulkLength);
+        assert_eq!(parse_err(b"$x\r\n"), RespError::InvalidInteger);
+        assert_eq!(parse_err(b"*-3\r\n"), RespError::InvalidMultibulkLength);
+        assert_eq!(parse_err(b"?x\r\n"), RespError::InvalidType(b'?'));
+        assert_eq!(parse_err(b"\r\n"), RespError::EmptyLine);
+        assert_eq!(parse_err(b"$1\r\n\xff\r\n"), RespError::InvalidUtf8);
+        assert_eq!(parse_err(&b"*1\r\n".repeat(1000)), RespError::TooDeep);
+
+        // A bulk string is read by its length, so it may contain terminators.
+        assert_eq!(
+            Resp::new(b"$4\r\na\r\nb\r\n").unwrap(),
+            Resp::BS(Some("a\r\nb".into()))
+        );
+    }
+
+    #[test]
+    fn it_parses_what_it_serializes() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        for _ in 0..iterations() {
+            let resp = rng.resp(0);
+            let bytes = resp.serialize();
+            assert_eq!(Resp::new(&bytes).unwrap(), resp);
+
+            // Every prefix is a value cut short.
+            let cut = rng.below(bytes.len());
+            assert_eq!(parse_err(&bytes[..cut]), RespError::Incomplete, "{bytes:?}");
+        }
+    }
+
+    #[test]
+    fn it_parses_what_it_serializes_as_resp3() {
+        let mut rng = Rng(0x853c49e6748fea9b);
+        for _ in 0..iterations() {
+            let resp = rng.resp3(0);
+            let bytes = resp.serialize_as(Protocol::Resp3);
+            assert_eq!(Resp::new(&bytes).unwrap(), resp);
+
+            let cut = rng.below(bytes.len());
+            assert_eq!(parse_err(&bytes[..cut]), RespError::Incomplete, "{bytes:?}");
+        }
+    }
+
+    #[test]
+    fn it_parses_resp3_types() {
+        assert_eq!(Resp::new(b",1.5\r\n").unwrap(), Resp::Double(1.5));
+        assert_eq!(
+            Resp::new(b",-inf\r\n").unwrap(),
+            Resp::Double(f64::NEG_INFINITY)
+        );
+        assert_eq!(Resp::new(b",1e3\r\n").unwrap(), Resp::Double(1000.0));
+        assert_eq!(Resp::new(b"#t\r\n").unwrap(), Resp::Boolean(true));
+        assert_eq!(
+            Resp::new(b"(-3492890328409238509324850943850943825024385\r\n").unwrap(),
+            Resp::BigNumber("-3492890328409238509324850943850943825024385".into())
+        );
+        assert_eq!(
+            Resp::new(b"=15\r\ntxt:Some string\r\n").unwrap(),
+            Resp::VerbatimString {
+                format: "txt".into(),
+                text: "Some string".into(),
+            }
+        );
+        assert_eq!(Resp::new(b"_\r\n").unwrap(), Resp::BS(None));
+        assert_eq!(
+            Resp::new(b"%1\r\n+key\r\n~2\r\n:1\r\n:2\r\n").unwrap(),
+            Resp::Map(vec![(
+                Resp::SS("key".into()),
+                Resp::Set(vec![Resp::I(1), Resp::I(2)])
+            )])
+        );
+        assert_eq!(
+            Resp::new(b">2\r\n+message\r\n+hi\r\n").unwrap(),
+            Resp::Push(vec![Resp::SS("message".into()), Resp::SS("hi".into())])
+        );
+
+        assert_eq!(parse_err(b",x\r\n"), RespError::InvalidDouble);
+        assert_eq!(parse_err(b"#x\r\n"), RespError::InvalidBoolean);
+        assert_eq!(parse_err(b"(12a\r\n"), RespError::InvalidBigNumber);
+        assert_eq!(parse_err(b"(-\r\n"), RespError::InvalidBigNumber);
+        assert_eq!(
+            parse_err(b"=3\r\ntxt\r\n"),
+            RespError::InvalidVerbatimFormat
+        );
+        assert_eq!(parse_err(&b"%1\r\n".repeat(1000)), RespError::TooDeep);
+    }
+
+    #[test]
+    fn it_serializes_resp3_types_for_resp2() {
+        let resp = |val: Resp| String::from_utf8(val.serialize()).unwrap();
+        assert_eq!(resp(Resp::Double(1.5)), "$3\r\n1.5\r\n");
+        assert_eq!(resp(Resp::Double(f64::INFINITY)), "$3\r\ninf\r\n");
+        assert_eq!(resp(Resp::Boolean(true)), ":1\r\n");
+        assert_eq!(resp(Resp::BigNumber("123".into())), "$3\r\n123\r\n");
+        let verbatim = Resp::VerbatimString {
+            format: "txt".into(),
+            text: "hi".into(),
+        };
+        assert_eq!(resp(verbatim), "$2\r\nhi\r\n");
+        assert_eq!(resp(Resp::Set(vec![Resp::I(1)])), "*1\r\n:1\r\n");
+        assert_eq!(resp(Resp::Push(vec![])), "*0\r\n");
+    }
+
+    /// Fuzzes the parsers with random edits of valid values. Longer runs take
+    /// `RESP_FUZZ_ITERATIONS=1000000 cargo test --release resp::tests`.
+    #[test]
+    fn it_never_panics_on_mutated_input() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for _ in 0..iterations() {
+            let mut bytes = rng.resp3(0).serialize_as(Protocol::Resp3);
+            for _ in 0..=rng.below(4) {
+                let pos = rng.below(bytes.len() + 1);
+                match rng.below(4) {
+                    0 if pos < bytes.len() => bytes[pos] = rng.next() as u8,
+                    1 => bytes.insert(pos, b"\r\n$*:-+0123456789"[rng.below(17)]),
+                    2 if pos < bytes.len() => {
+                        bytes.remove(pos);
+                    }
+                    _ => bytes.truncate(pos),
+                }
+            }
+            let _ = Resp::new(&bytes);
+            let _ = IncomingMessage::from_buffer(&bytes);
+        }
+    }
 
     #[test]
     fn it_parses_into_simple_string() {
@@ -228,6 +681,56 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn it_serializes_for_the_protocol() {
+        let val = Resp::Map(vec![
+            (Resp::BS(Some("proto".into())), Resp::I(3)),
+            (Resp::BS(Some("none".into())), Resp::BS(None)),
+        ]);
+        assert_eq!(
+            val.serialize(),
+            b"*4\r\n$5\r\nproto\r\n:3\r\n$4\r\nnone\r\n$-1\r\n"
+        );
+        assert_eq!(
+            val.serialize_as(Protocol::Resp3),
+            b"%2\r\n$5\r\nproto\r\n:3\r\n$4\r\nnone\r\n_\r\n"
+        );
+
+        assert_eq!(Resp::NullArray.serialize(), b"*-1\r\n");
+        assert_eq!(Resp::NullArray.serialize_as(Protocol::Resp3), b"_\r\n");
+        assert_eq!(Resp::new(b"*-1\r\n").unwrap(), Resp::NullArray);
+
+        assert_eq!(Protocol::from_version(3), Some(Protocol::Resp3));
+        assert_eq!(Protocol::from_version(4), None);
+        assert_eq!(Protocol::Resp2.version(), 2);
+    }
+
+    #[test]
+    fn it_parses_and_serializes_integers() {
+        for num in [0, 1, -42, i64::MAX, i64::MIN] {
+            let bytes = Resp::I(num).serialize();
+            assert_eq!(bytes, format!(":{num}\r\n").as_bytes());
+            assert_eq!(Resp::new(&bytes).unwrap(), Resp::I(num));
+        }
+        assert_eq!(Resp::new(b":+7\r\n").unwrap(), Resp::I(7));
+
+        for bytes in [
+            &b":\r\n"[..],
+            b":1.5\r\n",
+            b":12a\r\n",
+            b":9223372036854775808\r\n",
+        ] {
+            assert!(matches!(
+                Resp::new(bytes),
+                Err(RedisError::Protocol(RespError::InvalidInteger))
+            ));
+        }
+        assert!(matches!(
+            Resp::new(b":12"),
+            Err(RedisError::Protocol(RespError::Incomplete))
+        ));
+    }
+
     #[test]
     fn it_serializes_into_array() {
         let val = Resp::A(vec![]);
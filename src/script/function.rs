@@ -0,0 +1,295 @@
+use super::interp::Interp;
+use super::parser::{self, FuncBody};
+use super::value::{LuaError, LuaValue, Table};
+use super::{into_reply, kill_hook, redis_lib, stdlib, string_array, Host};
+use crate::{RedisError, RedisResult, Resp};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long the code of a library may run while it's loaded.
+const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+const FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+/// A function as a library registers it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Function {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) flags: Vec<String>,
+}
+
+/// A library loaded by FUNCTION LOAD. Only its code is kept, which is run again to get
+/// the functions back whenever one of them is called.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Library {
+    pub(crate) name: String,
+    pub(crate) code: String,
+    pub(crate) functions: Vec<Function>,
+}
+
+type Registry = Rc<RefCell<Vec<(Function, LuaValue)>>>;
+
+impl Library {
+    /// Runs the code of a library to learn the functions it registers.
+    pub(crate) fn load(code: &str) -> RedisResult<Self> {
+        let (name, chunk) = compile(code)?;
+        let registry = Registry::default();
+        let mut interp = library_interp(redis_lib(None), &registry);
+        let deadline = Instant::now() + LOAD_TIMEOUT;
+        interp.hook = Some(Box::new(move || {
+            if Instant::now() > deadline {
+                Err(LuaError::fatal("ERR FUNCTION LOAD timeout"))
+            } else {
+                Ok(())
+            }
+        }));
+
+        interp.run(chunk, vec![]).map_err(|err| {
+            let msg = match &err.value {
+                LuaValue::Str(msg) if err.fatal => msg.to_string(),
+                _ => format!("ERR Error registering functions: {err}"),
+            };
+            RedisError::from(anyhow::anyhow!(msg))
+        })?;
+
+        let functions: Vec<Function> = registry.borrow().iter().map(|(f, _)| f.clone()).collect();
+        if functions.is_empty() {
+            return Err(anyhow::anyhow!("ERR No functions registered").into());
+        }
+        Ok(Self {
+            name,
+            code: code.to_string(),
+            functions,
+        })
+    }
+
+    /// Calls a function of the library with the keys and arguments of FCALL.
+    pub(crate) fn call(
+        &self,
+        host: Host,
+        function: &str,
+        keys: Vec<String>,
+        args: Vec<String>,
+    ) -> RedisResult<Resp> {
+        let (_, chunk) = compile(&self.code)?;
+        let registry = Registry::default();
+        let running = Arc::clone(&host.running);
+        let mut interp = library_interp(redis_lib(Some(host)), &registry);
+        interp.hook = Some(kill_hook(running));
+        if let Err(err) = interp.run(chunk, vec![]) {
+            return into_reply(Err(err), function);
+        }
+
+        let callback = registry
+            .borrow()
+            .iter()
+            .find(|(f, _)| f.name == function)
+            .map(|(_, callback)| callback.clone())
+            .ok_or_else(|| RedisError::from(anyhow::anyhow!("ERR Function not found")))?;
+        let result = interp.call(&callback, vec![string_array(keys), string_array(args)]);
+        into_reply(result, function)
+    }
+}
+
+/// Reads the `#!lua name=<library>` line the code starts with and compiles the rest.
+fn compile(code: &str) -> RedisResult<(String, Rc<FuncBody>)> {
+    let err = |msg: String| RedisError::from(anyhow::anyhow!(msg));
+    let (first, body) = code.split_once('\n').unwrap_or((code, ""));
+    let meta = first
+        .strip_prefix("#!")
+        .ok_or_else(|| err("ERR Missing library metadata".into()))?;
+
+    let mut parts = meta.split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(err(format!("ERR Engine '{engine}' not found")));
+    }
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value.to_string()),
+            None => return Err(err(format!("ERR Invalid metadata value given: {part}"))),
+        }
+    }
+    let name = name.ok_or_else(|| err("ERR Library name was not given".into()))?;
+    if !valid_name(&name) {
+        return Err(err("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".into()));
+    }
+
+    // The metadata line stays as a blank one, so that errors point at the right lines.
+    let chunk = parser::parse(&format!("\n{body}"))
+        .map_err(|e| err(format!("ERR Error compiling function: {e}")))?;
+    Ok((name, chunk))
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn library_interp(mut redis: Table, registry: &Registry) -> Interp {
+    let registry = Rc::clone(registry);
+    redis.set_str(
+        "register_function",
+        LuaValue::native("register_function", move |_, args| {
+            let function = registration(args)?;
+            let mut registry = registry.borrow_mut();
+            if registry.iter().any(|(f, _)| f.name == function.0.name) {
+                return Err(LuaError::fatal(
+                    "ERR Function already exists in the library",
+                ));
+            }
+            registry.push(function);
+            Ok(vec![])
+        }),
+    );
+
+    let mut interp = Interp::new();
+    stdlib::open(&mut interp);
+    interp.set_global("redis", LuaValue::table(redis));
+    interp.globals_locked = true;
+    interp
+}
+
+/// Reads the arguments of `redis.register_function`, either a name and a callback or a
+/// table of `function_name`, `callback`, `flags` and `description`.
+fn registration(args: Vec<LuaValue>) -> Result<(Function, LuaValue), LuaError> {
+    // Registration errors are replied as they are, so they are raised as fatal ones.
+    let err = |msg: &str| LuaError::fatal(format!("ERR {msg}"));
+    let (name, callback, flags, description) = match args.as_slice() {
+        [LuaValue::Table(t)] => {
+            let t = t.borrow();
+            (
+                t.get_str("function_name"),
+                t.get_str("callback"),
+                t.get_str("flags"),
+                t.get_str("description"),
+            )
+        }
+        [name, callback] => (name.clone(), callback.clone(), LuaValue::Nil, LuaValue::Nil),
+        _ => return Err(err("wrong number of arguments to redis.register_function")),
+    };
+
+    let LuaValue::Str(name) = name else {
+        return Err(err(
+            "function_name argument given to redis.register_function must be a string",
+        ));
+    };
+    if !valid_name(&name) {
+        return Err(err("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long"));
+    }
+    if !matches!(callback, LuaValue::Function(_)) {
+        return Err(err(
+            "callback argument given to redis.register_function must be a function",
+        ));
+    }
+    let flags = match flags {
+        LuaValue::Nil => vec![],
+        LuaValue::Table(t) => {
+            let mut flags = vec![];
+            for flag in t.borrow().array() {
+                match flag {
+                    LuaValue::Str(flag) if FLAGS.contains(&flag.as_ref()) => {
+                        flags.push(flag.to_string())
+                    }
+                    _ => return Err(err("unknown flag given")),
+                }
+            }
+            flags
+        }
+        _ => return Err(err(
+            "flags argument to redis.register_function must be a table representing function flags",
+        )),
+    };
+    let description = match description {
+        LuaValue::Nil => None,
+        LuaValue::Str(s) => Some(s.to_string()),
+        _ => {
+            return Err(err(
+                "description argument given to redis.register_function must be a string",
+            ))
+        }
+    };
+
+    let function = Function {
+        name: name.to_string(),
+        description,
+        flags,
+    };
+    Ok((function, callback))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(code: &str) -> Result<Library, String> {
+        let code = code.to_string();
+        super::super::spawn(move || Library::load(&code).map_err(|err| err.to_string()))
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn it_loads_libraries() {
+        let code = "#!lua name=mylib
+            local function echo(keys, args) return args[1] end
+            redis.register_function('echo', echo)
+            redis.register_function{
+                function_name = 'first_key',
+                callback = function(keys) return keys[1] end,
+                flags = {'no-writes'},
+            }";
+        let library = load(code).unwrap();
+        assert_eq!(library.name, "mylib");
+        assert_eq!(
+            library.functions,
+            vec![
+                Function {
+                    name: "echo".into(),
+                    description: None,
+                    flags: vec![],
+                },
+                Function {
+                    name: "first_key".into(),
+                    description: None,
+                    flags: vec!["no-writes".into()],
+                },
+            ]
+        );
+
+        assert_eq!(
+            load("return 1").unwrap_err(),
+            "ERR Missing library metadata"
+        );
+        assert_eq!(
+            load("#!js name=x\n").unwrap_err(),
+            "ERR Engine 'js' not found"
+        );
+        assert_eq!(
+            load("#!lua name=x\nlocal a = 1").unwrap_err(),
+            "ERR No functions registered"
+        );
+        assert_eq!(
+            load("#!lua name=x\nredis.register_function('f', function() end, 1)").unwrap_err(),
+            "ERR wrong number of arguments to redis.register_function"
+        );
+        assert_eq!(
+            load("#!lua name=x\nredis.call('GET', 'k')").unwrap_err(),
+            "ERR Error registering functions: user_script:2: attempt to call a nil value"
+        );
+        assert_eq!(
+            load("#!lua name=x\nwhile true do end").unwrap_err(),
+            "ERR FUNCTION LOAD timeout"
+        );
+    }
+}
@@ -0,0 +1,510 @@
+use super::parser::{BinOp, Block, Expr, Field, FuncBody, Stat, StatKind, UnOp};
+use super::value::{Function, LuaError, LuaResult, LuaValue, Scope, Table, TableRef};
+use std::rc::Rc;
+
+/// Nested calls allowed before a script fails with a stack overflow.
+const MAX_CALL_DEPTH: usize = 200;
+/// Statements run between two calls of the hook.
+const HOOK_INTERVAL: u64 = 1000;
+
+pub(crate) type Hook = Box<dyn FnMut() -> LuaResult<()>>;
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<LuaValue>),
+}
+
+/// A tree-walking interpreter of the Lua 5.1 subset scripts are written in.
+pub(crate) struct Interp {
+    pub(crate) globals: TableRef,
+    /// Backs method calls on strings such as `s:upper()`.
+    pub(crate) string_lib: Option<TableRef>,
+    /// Once set, scripts can neither create nor change globals.
+    pub(crate) globals_locked: bool,
+    /// Called periodically while a script runs, to stop it by returning an error.
+    pub(crate) hook: Option<Hook>,
+    depth: usize,
+    steps: u64,
+}
+
+impl Interp {
+    pub(crate) fn new() -> Self {
+        Self {
+            globals: Rc::new(Table::default().into()),
+            string_lib: None,
+            globals_locked: false,
+            hook: None,
+            depth: 0,
+            steps: 0,
+        }
+    }
+
+    pub(crate) fn set_global(&mut self, name: &str, value: LuaValue) {
+        self.globals.borrow_mut().set_str(name, value);
+    }
+
+    /// Runs a parsed chunk as a function of the given varargs.
+    pub(crate) fn run(
+        &mut self,
+        chunk: Rc<FuncBody>,
+        args: Vec<LuaValue>,
+    ) -> LuaResult<Vec<LuaValue>> {
+        let func = LuaValue::Function(Rc::new(Function::Lua {
+            body: chunk,
+            env: Rc::new(Scope::default()),
+        }));
+        self.call(&func, args)
+    }
+
+    pub(crate) fn call(
+        &mut self,
+        func: &LuaValue,
+        args: Vec<LuaValue>,
+    ) -> LuaResult<Vec<LuaValue>> {
+        let LuaValue::Function(func) = func else {
+            return Err(LuaError::runtime(format!(
+                "attempt to call a {} value",
+                func.type_name()
+            )));
+        };
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(LuaError::runtime("stack overflow"));
+        }
+
+        self.depth += 1;
+        let result = match func.as_ref() {
+            Function::Native { f, .. } => f(self, args),
+            Function::Lua { body, env } => {
+                let mut args = args.into_iter();
+                let params: Vec<LuaValue> = body
+                    .params
+                    .iter()
+                    .map(|_| args.next().unwrap_or_default())
+                    .collect();
+                let varargs = if body.vararg { args.collect() } else { vec![] };
+                let scope = Scope::function(env, varargs);
+                for (name, value) in body.params.iter().zip(params) {
+                    scope.declare(name, value);
+                }
+                match self.exec_block_in(&body.body, &scope) {
+                    Ok(Flow::Return(values)) => Ok(values),
+                    Ok(_) => Ok(vec![]),
+                    Err(err) => Err(err),
+                }
+            }
+        };
+        self.depth -= 1;
+        result
+    }
+
+    fn exec_block(&mut self, block: &Block, parent: &Rc<Scope>) -> LuaResult<Flow> {
+        let scope = Scope::child(parent);
+        self.exec_block_in(block, &scope)
+    }
+
+    fn exec_block_in(&mut self, block: &Block, scope: &Rc<Scope>) -> LuaResult<Flow> {
+        for stat in block {
+            match self
+                .exec(stat, scope)
+                .map_err(|err| err.locate(stat.line))?
+            {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn tick(&mut self) -> LuaResult<()> {
+        self.steps += 1;
+        if self.steps < HOOK_INTERVAL {
+            return Ok(());
+        }
+        self.steps = 0;
+        match self.hook.as_mut() {
+            Some(hook) => hook(),
+            None => Ok(()),
+        }
+    }
+
+    fn exec(&mut self, stat: &Stat, scope: &Rc<Scope>) -> LuaResult<Flow> {
+        self.tick()?;
+        match &stat.kind {
+            StatKind::Local(names, exprs) => {
+                let mut values = self.eval_list(exprs, scope)?.into_iter();
+                for name in names {
+                    scope.declare(name, values.next().unwrap_or_default());
+                }
+            }
+            StatKind::LocalFunction(name, body) => {
+                // Declared first so that the function can call itself.
+                scope.declare(name, LuaValue::Nil);
+                let func = self.closure(body, scope);
+                if let Some(cell) = scope.lookup(name) {
+                    *cell.borrow_mut() = func;
+                }
+            }
+            StatKind::Assign(targets, exprs) => {
+                let mut values = self.eval_list(exprs, scope)?.into_iter();
+                for target in targets {
+                    let value = values.next().unwrap_or_default();
+                    self.assign(target, value, scope)?;
+                }
+            }
+            StatKind::Call(expr) => {
+                self.eval_multi(expr, scope)?;
+            }
+            StatKind::If(branches, otherwise) => {
+                for (cond, block) in branches {
+                    if self.eval(cond, scope)?.truthy() {
+                        return self.exec_block(block, scope);
+                    }
+                }
+                if let Some(block) = otherwise {
+                    return self.exec_block(block, scope);
+                }
+            }
+            StatKind::While(cond, body) => {
+                while self.eval(cond, scope)?.truthy() {
+                    self.tick()?;
+                    match self.exec_block(body, scope)? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                }
+            }
+            StatKind::Repeat(body, cond) => loop {
+                self.tick()?;
+                // The condition sees the locals of the body.
+                let inner = Scope::child(scope);
+                match self.exec_block_in(body, &inner)? {
+                    Flow::Break => break,
+                    Flow::Return(values) => return Ok(Flow::Return(values)),
+                    Flow::Normal => {}
+                }
+                if self.eval(cond, &inner)?.truthy() {
+                    break;
+                }
+            },
+            StatKind::NumericFor {
+                var,
+                start,
+                limit,
+                step,
+                body,
+            } => {
+                let number = |value: LuaValue, what: &str| {
+                    value
+                        .to_number()
+                        .ok_or_else(|| LuaError::runtime(format!("'for' {what} must be a number")))
+                };
+                let mut i = number(self.eval(start, scope)?, "initial value")?;
+                let limit = number(self.eval(limit, scope)?, "limit")?;
+                let step = match step {
+                    Some(step) => number(self.eval(step, scope)?, "step")?,
+                    None => 1.0,
+                };
+                while (step > 0.0 && i <= limit) || (step <= 0.0 && i >= limit) {
+                    self.tick()?;
+                    let inner = Scope::child(scope);
+                    inner.declare(var, LuaValue::Number(i));
+                    match self.exec_block_in(body, &inner)? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                    i += step;
+                }
+            }
+            StatKind::GenericFor(names, exprs, body) => {
+                let mut values = self.eval_list(exprs, scope)?.into_iter();
+                let iter = values.next().unwrap_or_default();
+                let state = values.next().unwrap_or_default();
+                let mut control = values.next().unwrap_or_default();
+                loop {
+                    self.tick()?;
+                    let results = self.call(&iter, vec![state.clone(), control.clone()])?;
+                    let first = results.first().cloned().unwrap_or_default();
+                    if matches!(first, LuaValue::Nil) {
+                        break;
+                    }
+                    control = first;
+                    let inner = Scope::child(scope);
+                    let mut results = results.into_iter();
+                    for name in names {
+                        inner.declare(name, results.next().unwrap_or_default());
+                    }
+                    match self.exec_block_in(body, &inner)? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                }
+            }
+            StatKind::Return(exprs) => {
+                let values = self.eval_list(exprs, scope)?;
+                return Ok(Flow::Return(values));
+            }
+            StatKind::Break => return Ok(Flow::Break),
+            StatKind::Do(body) => return self.exec_block(body, scope),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn assign(&mut self, target: &Expr, value: LuaValue, scope: &Rc<Scope>) -> LuaResult<()> {
+        match target {
+            Expr::Name(name) => {
+                if let Some(cell) = scope.lookup(name) {
+                    *cell.borrow_mut() = value;
+                } else if self.globals_locked {
+                    return Err(LuaError::runtime("Attempt to modify a readonly table"));
+                } else {
+                    self.set_global(name, value);
+                }
+                Ok(())
+            }
+            Expr::Index(table, key) => {
+                let table = self.eval(table, scope)?;
+                let key = self.eval(key, scope)?;
+                match table {
+                    LuaValue::Table(t) if Rc::ptr_eq(&t, &self.globals) && self.globals_locked => {
+                        Err(LuaError::runtime("Attempt to modify a readonly table"))
+                    }
+                    LuaValue::Table(t) => t.borrow_mut().set(key, value),
+                    other => Err(LuaError::runtime(format!(
+                        "attempt to index a {} value",
+                        other.type_name()
+                    ))),
+                }
+            }
+            _ => Err(LuaError::runtime("cannot assign to this expression")),
+        }
+    }
+
+    fn closure(&self, body: &Rc<FuncBody>, scope: &Rc<Scope>) -> LuaValue {
+        LuaValue::Function(Rc::new(Function::Lua {
+            body: Rc::clone(body),
+            env: Rc::clone(scope),
+        }))
+    }
+
+    /// Evaluates a list of expressions, expanding the values of the last one.
+    pub(crate) fn eval_list(
+        &mut self,
+        exprs: &[Expr],
+        scope: &Rc<Scope>,
+    ) -> LuaResult<Vec<LuaValue>> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for (idx, expr) in exprs.iter().enumerate() {
+            if idx == exprs.len() - 1 && expr.is_multi() {
+                values.extend(self.eval_multi(expr, scope)?);
+            } else {
+                values.push(self.eval(expr, scope)?);
+            }
+        }
+        Ok(values)
+    }
+
+    fn eval_multi(&mut self, expr: &Expr, scope: &Rc<Scope>) -> LuaResult<Vec<LuaValue>> {
+        match expr {
+            Expr::Call(func, args) => {
+                let func = self.eval(func, scope)?;
+                let args = self.eval_list(args, scope)?;
+                self.call(&func, args)
+            }
+            Expr::Method(object, name, args) => {
+                let object = self.eval(object, scope)?;
+                let func = self.index(&object, &LuaValue::Str(Rc::clone(name)))?;
+                let mut values = vec![object];
+                values.extend(self.eval_list(args, scope)?);
+                self.call(&func, values)
+            }
+            Expr::Vararg => Ok(scope
+                .varargs()
+                .map(|args| args.as_ref().clone())
+                .unwrap_or_default()),
+            expr => Ok(vec![self.eval(expr, scope)?]),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr, scope: &Rc<Scope>) -> LuaResult<LuaValue> {
+        let value = match expr {
+            Expr::Nil => LuaValue::Nil,
+            Expr::True => LuaValue::Bool(true),
+            Expr::False => LuaValue::Bool(false),
+            Expr::Number(n) => LuaValue::Number(*n),
+            Expr::Str(s) => LuaValue::Str(Rc::clone(s)),
+            Expr::Function(body) => self.closure(body, scope),
+            Expr::Name(name) => match scope.lookup(name) {
+                Some(cell) => cell.borrow().clone(),
+                None => {
+                    let value = self.globals.borrow().get_str(name);
+                    if matches!(value, LuaValue::Nil) && self.globals_locked {
+                        return Err(LuaError::runtime(format!(
+                            "Script attempted to access nonexistent global variable '{name}'"
+                        )));
+                    }
+                    value
+                }
+            },
+            Expr::Index(table, key) => {
+                let table = self.eval(table, scope)?;
+                let key = self.eval(key, scope)?;
+                self.index(&table, &key)?
+            }
+            Expr::Call(..) | Expr::Method(..) | Expr::Vararg => self
+                .eval_multi(expr, scope)?
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            Expr::Paren(expr) => self.eval(expr, scope)?,
+            Expr::Table(fields) => self.table(fields, scope)?,
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand, scope)?;
+                unary(*op, value)?
+            }
+            Expr::Binary(BinOp::And, left, right) => {
+                let left = self.eval(left, scope)?;
+                if left.truthy() {
+                    self.eval(right, scope)?
+                } else {
+                    left
+                }
+            }
+            Expr::Binary(BinOp::Or, left, right) => {
+                let left = self.eval(left, scope)?;
+                if left.truthy() {
+                    left
+                } else {
+                    self.eval(right, scope)?
+                }
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left, scope)?;
+                let right = self.eval(right, scope)?;
+                binary(*op, left, right)?
+            }
+        };
+        Ok(value)
+    }
+
+    fn table(&mut self, fields: &[Field], scope: &Rc<Scope>) -> LuaResult<LuaValue> {
+        let mut table = Table::default();
+        let mut positional = 0;
+        for (idx, field) in fields.iter().enumerate() {
+            match field {
+                Field::Positional(expr) if idx == fields.len() - 1 && expr.is_multi() => {
+                    for value in self.eval_multi(expr, scope)? {
+                        positional += 1;
+                        table.set(LuaValue::Number(positional as f64), value)?;
+                    }
+                }
+                Field::Positional(expr) => {
+                    let value = self.eval(expr, scope)?;
+                    positional += 1;
+                    table.set(LuaValue::Number(positional as f64), value)?;
+                }
+                Field::Keyed(key, value) => {
+                    let key = self.eval(key, scope)?;
+                    let value = self.eval(value, scope)?;
+                    table.set(key, value)?;
+                }
+            }
+        }
+        Ok(LuaValue::table(table))
+    }
+
+    pub(crate) fn index(&self, value: &LuaValue, key: &LuaValue) -> LuaResult<LuaValue> {
+        match value {
+            LuaValue::Table(t) => Ok(t.borrow().get(key)),
+            LuaValue::Str(_) => match &self.string_lib {
+                Some(lib) => Ok(lib.borrow().get(key)),
+                None => Ok(LuaValue::Nil),
+            },
+            other => Err(LuaError::runtime(format!(
+                "attempt to index a {} value",
+                other.type_name()
+            ))),
+        }
+    }
+}
+
+fn unary(op: UnOp, value: LuaValue) -> LuaResult<LuaValue> {
+    match op {
+        UnOp::Not => Ok(LuaValue::Bool(!value.truthy())),
+        UnOp::Neg => value
+            .to_number()
+            .map(|n| LuaValue::Number(-n))
+            .ok_or_else(|| arith_error(&value)),
+        UnOp::Len => match &value {
+            LuaValue::Str(s) => Ok(LuaValue::Number(s.len() as f64)),
+            LuaValue::Table(t) => Ok(LuaValue::Number(t.borrow().len() as f64)),
+            other => Err(LuaError::runtime(format!(
+                "attempt to get length of a {} value",
+                other.type_name()
+            ))),
+        },
+    }
+}
+
+fn binary(op: BinOp, left: LuaValue, right: LuaValue) -> LuaResult<LuaValue> {
+    let arith = |f: fn(f64, f64) -> f64| match (left.to_number(), right.to_number()) {
+        (Some(a), Some(b)) => Ok(LuaValue::Number(f(a, b))),
+        (None, _) => Err(arith_error(&left)),
+        (_, None) => Err(arith_error(&right)),
+    };
+    match op {
+        BinOp::Add => arith(|a, b| a + b),
+        BinOp::Sub => arith(|a, b| a - b),
+        BinOp::Mul => arith(|a, b| a * b),
+        BinOp::Div => arith(|a, b| a / b),
+        BinOp::Mod => arith(|a, b| a - (a / b).floor() * b),
+        BinOp::Pow => arith(f64::powf),
+        BinOp::Concat => match (left.to_str(), right.to_str()) {
+            (Some(a), Some(b)) => Ok(LuaValue::str(&format!("{a}{b}"))),
+            _ => {
+                let bad = if left.to_str().is_none() {
+                    &left
+                } else {
+                    &right
+                };
+                Err(LuaError::runtime(format!(
+                    "attempt to concatenate a {} value",
+                    bad.type_name()
+                )))
+            }
+        },
+        BinOp::Eq => Ok(LuaValue::Bool(left.raw_eq(&right))),
+        BinOp::Ne => Ok(LuaValue::Bool(!left.raw_eq(&right))),
+        BinOp::Lt => less_than(&left, &right).map(LuaValue::Bool),
+        BinOp::Le => less_than(&right, &left).map(|lt| LuaValue::Bool(!lt)),
+        BinOp::Gt => less_than(&right, &left).map(LuaValue::Bool),
+        BinOp::Ge => less_than(&left, &right).map(|lt| LuaValue::Bool(!lt)),
+        BinOp::And | BinOp::Or => unreachable!("short-circuit operators are evaluated lazily"),
+    }
+}
+
+fn less_than(left: &LuaValue, right: &LuaValue) -> LuaResult<bool> {
+    match (left, right) {
+        (LuaValue::Number(a), LuaValue::Number(b)) => Ok(a < b),
+        (LuaValue::Str(a), LuaValue::Str(b)) => Ok(a < b),
+        (a, b) if a.type_name() == b.type_name() => Err(LuaError::runtime(format!(
+            "attempt to compare two {} values",
+            a.type_name()
+        ))),
+        (a, b) => Err(LuaError::runtime(format!(
+            "attempt to compare {} with {}",
+            a.type_name(),
+            b.type_name()
+        ))),
+    }
+}
+
+fn arith_error(value: &LuaValue) -> LuaError {
+    LuaError::runtime(format!(
+        "attempt to perform arithmetic on a {} value",
+        value.type_name()
+    ))
+}
@@ -0,0 +1,263 @@
+use super::LuaError;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Name(String),
+    Number(f64),
+    Str(Rc<str>),
+    Keyword(&'static str),
+    Symbol(&'static str),
+    Eof,
+}
+
+const KEYWORDS: [&str; 21] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+// Longest first, so that a symbol is never taken for its prefix.
+const SYMBOLS: [&str; 26] = [
+    "...", "..", "==", "~=", "<=", ">=", "+", "-", "*", "/", "%", "^", "#", "<", ">", "=", "(",
+    ")", "{", "}", "[", "]", ";", ":", ",", ".",
+];
+
+/// Splits a script into tokens, each with the line it starts on.
+pub(crate) fn tokenize(src: &str) -> Result<Vec<(Token, u32)>, LuaError> {
+    Lexer {
+        src: src.as_bytes(),
+        pos: 0,
+        line: 1,
+    }
+    .run()
+}
+
+struct Lexer<'a> {
+    src: &'a [u8],
+    pos: usize,
+    line: u32,
+}
+
+impl Lexer<'_> {
+    fn run(mut self) -> Result<Vec<(Token, u32)>, LuaError> {
+        let mut tokens = vec![];
+        loop {
+            self.skip_blank()?;
+            let line = self.line;
+            let Some(&c) = self.src.get(self.pos) else {
+                tokens.push((Token::Eof, line));
+                return Ok(tokens);
+            };
+
+            let token = if c.is_ascii_alphabetic() || c == b'_' {
+                let word = self.take_while(|c| c.is_ascii_alphanumeric() || c == b'_');
+                match KEYWORDS.iter().find(|k| **k == word) {
+                    Some(keyword) => Token::Keyword(keyword),
+                    None => Token::Name(word.to_string()),
+                }
+            } else if c.is_ascii_digit()
+                || (c == b'.' && self.peek(1).is_some_and(|c| c.is_ascii_digit()))
+            {
+                self.number()?
+            } else if c == b'"' || c == b'\'' {
+                self.pos += 1;
+                self.quoted(c)?
+            } else if c == b'[' && matches!(self.peek(1), Some(b'[') | Some(b'=')) {
+                match self.long_bracket()? {
+                    Some(s) => Token::Str(s.into()),
+                    None => {
+                        self.pos += 1;
+                        Token::Symbol("[")
+                    }
+                }
+            } else {
+                let rest = &self.src[self.pos..];
+                let symbol = SYMBOLS
+                    .iter()
+                    .find(|s| rest.starts_with(s.as_bytes()))
+                    .ok_or_else(|| self.error(format!("unexpected symbol near '{}'", c as char)))?;
+                self.pos += symbol.len();
+                Token::Symbol(symbol)
+            };
+            tokens.push((token, line));
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.src.get(self.pos + offset).copied()
+    }
+
+    fn error(&self, msg: String) -> LuaError {
+        LuaError::syntax(msg, self.line)
+    }
+
+    fn take_while(&mut self, f: impl Fn(u8) -> bool) -> &str {
+        let start = self.pos;
+        while self.src.get(self.pos).is_some_and(|c| f(*c)) {
+            self.pos += 1;
+        }
+        // Only ASCII bytes are taken, so the slice is valid UTF-8.
+        std::str::from_utf8(&self.src[start..self.pos]).unwrap_or_default()
+    }
+
+    fn skip_blank(&mut self) -> Result<(), LuaError> {
+        loop {
+            match self.src.get(self.pos) {
+                Some(b'\n') => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                Some(c) if c.is_ascii_whitespace() => self.pos += 1,
+                Some(b'-') if self.peek(1) == Some(b'-') => {
+                    self.pos += 2;
+                    let long = self.src.get(self.pos) == Some(&b'[')
+                        && matches!(self.peek(1), Some(b'[') | Some(b'='));
+                    if !long || self.long_bracket()?.is_none() {
+                        self.take_while(|c| c != b'\n');
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Token, LuaError> {
+        let start = self.pos;
+        let hex =
+            self.src[self.pos..].starts_with(b"0x") || self.src[self.pos..].starts_with(b"0X");
+        if hex {
+            self.pos += 2;
+            let digits = self.take_while(|c| c.is_ascii_hexdigit()).to_string();
+            return u64::from_str_radix(&digits, 16)
+                .map(|n| Token::Number(n as f64))
+                .map_err(|_| self.error("malformed number".into()));
+        }
+
+        self.take_while(|c| c.is_ascii_digit() || c == b'.');
+        if matches!(self.src.get(self.pos), Some(b'e') | Some(b'E')) {
+            self.pos += 1;
+            if matches!(self.src.get(self.pos), Some(b'+') | Some(b'-')) {
+                self.pos += 1;
+            }
+            self.take_while(|c| c.is_ascii_digit());
+        }
+        let text = std::str::from_utf8(&self.src[start..self.pos]).unwrap_or_default();
+        text.parse::<f64>()
+            .map(Token::Number)
+            .map_err(|_| self.error(format!("malformed number near '{text}'")))
+    }
+
+    fn quoted(&mut self, quote: u8) -> Result<Token, LuaError> {
+        let mut bytes: Vec<u8> = vec![];
+        loop {
+            let Some(&c) = self.src.get(self.pos) else {
+                return Err(self.error("unfinished string".into()));
+            };
+            self.pos += 1;
+            match c {
+                b'\n' => return Err(self.error("unfinished string".into())),
+                c if c == quote => break,
+                b'\\' => {
+                    let Some(&e) = self.src.get(self.pos) else {
+                        return Err(self.error("unfinished string".into()));
+                    };
+                    self.pos += 1;
+                    match e {
+                        b'n' => bytes.push(b'\n'),
+                        b't' => bytes.push(b'\t'),
+                        b'r' => bytes.push(b'\r'),
+                        b'a' => bytes.push(0x07),
+                        b'b' => bytes.push(0x08),
+                        b'f' => bytes.push(0x0c),
+                        b'v' => bytes.push(0x0b),
+                        b'\n' => {
+                            self.line += 1;
+                            bytes.push(b'\n');
+                        }
+                        e if e.is_ascii_digit() => {
+                            let mut num = (e - b'0') as u32;
+                            for _ in 0..2 {
+                                match self.src.get(self.pos) {
+                                    Some(d) if d.is_ascii_digit() => {
+                                        num = num * 10 + (d - b'0') as u32;
+                                        self.pos += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            let byte = u8::try_from(num)
+                                .map_err(|_| self.error("escape sequence too large".into()))?;
+                            bytes.push(byte);
+                        }
+                        e => bytes.push(e),
+                    }
+                }
+                c => bytes.push(c),
+            }
+        }
+        let s = String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8 string".into()))?;
+        Ok(Token::Str(s.into()))
+    }
+
+    /// Reads a `[[...]]` or `[==[...]==]` string. Returns None, consuming nothing, when
+    /// the bracket doesn't open one.
+    fn long_bracket(&mut self) -> Result<Option<String>, LuaError> {
+        let level = self.src[self.pos + 1..]
+            .iter()
+            .take_while(|c| **c == b'=')
+            .count();
+        if self.peek(level + 1) != Some(b'[') {
+            return Ok(None);
+        }
+        self.pos += level + 2;
+        if self.src.get(self.pos) == Some(&b'\n') {
+            self.line += 1;
+            self.pos += 1;
+        }
+
+        let close = format!("]{}]", "=".repeat(level));
+        let start = self.pos;
+        while !self.src[self.pos..].starts_with(close.as_bytes()) {
+            match self.src.get(self.pos) {
+                Some(b'\n') => self.line += 1,
+                Some(_) => {}
+                None => return Err(self.error("unfinished long string".into())),
+            }
+            self.pos += 1;
+        }
+        let s = String::from_utf8(self.src[start..self.pos].to_vec())
+            .map_err(|_| self.error("invalid UTF-8 string".into()))?;
+        self.pos += close.len();
+        Ok(Some(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_tokenizes_scripts() {
+        let src = "local a = 'x\\n' .. [[long]] -- comment\nreturn a ~= 0x10, ...";
+        let tokens: Vec<Token> = tokenize(src).unwrap().into_iter().map(|(t, _)| t).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Keyword("local"),
+                Token::Name("a".into()),
+                Token::Symbol("="),
+                Token::Str("x\n".into()),
+                Token::Symbol(".."),
+                Token::Str("long".into()),
+                Token::Keyword("return"),
+                Token::Name("a".into()),
+                Token::Symbol("~="),
+                Token::Number(16.0),
+                Token::Symbol(","),
+                Token::Symbol("..."),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(tokenize("a\n--[[ x\ny ]]\nb").unwrap()[1].1, 4);
+    }
+}
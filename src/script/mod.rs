@@ -0,0 +1,520 @@
+//! Lua scripting. Scripts run in a small interpreter of the Lua 5.1 language with the
+//! `redis` library bound to the command layer. The interpreter is the crate's own, as no
+//! Lua crate can be added to the fixed manifest, and it covers a subset of Lua:
+//!
+//! - Every statement and expression of Lua 5.1: locals, multiple assignment, `if`,
+//!   `while`, `repeat`, numeric and generic `for`, closures, varargs, methods, table
+//!   constructors, long strings and comments.
+//! - The base functions `assert`, `error`, `ipairs`, `next`, `pairs`, `pcall`, `rawequal`,
+//!   `rawget`, `rawset`, `select`, `tonumber`, `tostring`, `type` and `unpack`.
+//! - `string.len`, `sub`, `upper`, `lower`, `rep`, `reverse`, `byte`, `char` and `format`,
+//!   which takes `%d %i %x %X %c %f %g %s %q` with flags, width and precision.
+//! - `table.insert`, `remove`, `concat` and `getn`, and `math.abs`, `ceil`, `floor`,
+//!   `fmod`, `max`, `min`, `sqrt`, `huge` and `pi`.
+//! - `redis.call`, `pcall`, `error_reply`, `status_reply`, `sha1hex` and `log`.
+//!
+//! Anything else isn't there and fails as a call of a nil value: metatables, which
+//! `setmetatable` refuses, coroutines, string patterns (`find`, `match`, `gmatch`, `gsub`),
+//! `math.random`, `xpcall`, `loadstring`, and the `cjson`, `cmsgpack`, `bit` and `struct`
+//! libraries. Strings are UTF-8, as the values of the keyspace are, so they can't hold
+//! arbitrary bytes.
+
+mod function;
+mod interp;
+mod lexer;
+mod parser;
+mod stdlib;
+mod value;
+
+pub(crate) use function::Library;
+pub(crate) use value::LuaError;
+
+use super::{
+    utils, Client, Command, CommandCall, CommandMode, Context, RedisError, RedisResult, Resp, Store,
+};
+use bytes::Bytes;
+use interp::Interp;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use value::{fmt_number, LuaValue, Table};
+
+/// Stack size of the threads scripts run on, as the interpreter recurses per nested call.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Spawns a thread with room for the interpreter's recursion to run scripts on.
+pub(crate) fn spawn<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> std::io::Result<JoinHandle<T>> {
+    thread::Builder::new()
+        .name("script".into())
+        .stack_size(STACK_SIZE)
+        .spawn(f)
+}
+
+/// The script being run, watched by the server while it runs.
+#[derive(Debug)]
+pub(crate) struct Running {
+    started: Instant,
+    /// Set for EVAL_RO, FCALL_RO and functions flagged `no-writes`, which may not write.
+    readonly: bool,
+    wrote: AtomicBool,
+    killed: AtomicBool,
+}
+
+impl Running {
+    pub(crate) fn new(readonly: bool) -> Self {
+        Self {
+            started: Instant::now(),
+            readonly,
+            wrote: AtomicBool::new(false),
+            killed: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Whether the script has called a write command.
+    pub(crate) fn wrote(&self) -> bool {
+        self.wrote.load(Ordering::Relaxed)
+    }
+
+    /// Makes the script fail at its next check.
+    pub(crate) fn kill(&self) {
+        self.killed.store(true, Ordering::Relaxed);
+    }
+}
+
+/// What a script runs against: the store, through the runtime, and the client its
+/// commands run as.
+pub(crate) struct Host {
+    pub(crate) handle: Handle,
+    pub(crate) store: Arc<Store>,
+    pub(crate) client: Arc<Client>,
+    pub(crate) running: Arc<Running>,
+}
+
+/// Runs a script to completion on the current thread, dispatching `redis.call` to the
+/// store through the runtime handle.
+pub(crate) fn run(
+    host: Host,
+    sha: &str,
+    source: &str,
+    keys: Vec<String>,
+    args: Vec<String>,
+) -> RedisResult<Resp> {
+    let chunk = parser::parse(source).map_err(|err| {
+        RedisError::from(anyhow::anyhow!(
+            "ERR Error compiling script (new function): {err}"
+        ))
+    })?;
+
+    let running = Arc::clone(&host.running);
+    let mut interp = Interp::new();
+    stdlib::open(&mut interp);
+    interp.set_global("redis", LuaValue::table(redis_lib(Some(host))));
+    interp.set_global("KEYS", string_array(keys));
+    interp.set_global("ARGV", string_array(args));
+    interp.globals_locked = true;
+    interp.hook = Some(kill_hook(running));
+
+    into_reply(interp.run(chunk, vec![]), sha)
+}
+
+/// Stops the script once it's killed.
+fn kill_hook(running: Arc<Running>) -> interp::Hook {
+    Box::new(move || {
+        if running.killed.load(Ordering::Relaxed) {
+            Err(LuaError::fatal(
+                "ERR Script killed by user with SCRIPT KILL...",
+            ))
+        } else {
+            Ok(())
+        }
+    })
+}
+
+/// Converts what a script returns or raises into its reply. Errors tell the script they
+/// were raised in by `name`.
+fn into_reply(result: Result<Vec<LuaValue>, LuaError>, name: &str) -> RedisResult<Resp> {
+    let err = match result {
+        Ok(values) => return Ok(into_resp(values.first().unwrap_or(&LuaValue::Nil))),
+        Err(err) => err,
+    };
+    let msg = match &err.value {
+        LuaValue::Table(t) => match t.borrow().get_str("err") {
+            LuaValue::Str(msg) => msg.to_string(),
+            _ => err.to_string(),
+        },
+        LuaValue::Str(msg) if err.fatal => msg.to_string(),
+        _ => format!("ERR {err}"),
+    };
+    let msg = match err.line {
+        Some(line) => format!("{msg} script: {name}, on @user_script:{line}."),
+        None => format!("{msg} script: {name}"),
+    };
+    Err(anyhow::anyhow!(msg).into())
+}
+
+fn string_array(values: Vec<String>) -> LuaValue {
+    LuaValue::table(Table::from_array(
+        values.iter().map(|v| LuaValue::str(v)).collect(),
+    ))
+}
+
+/// The `redis` library. Without a host, as when functions are loaded, it can't call
+/// commands.
+fn redis_lib(host: Option<Host>) -> Table {
+    let mut lib = Table::default();
+
+    if let Some(host) = host {
+        let dispatch = Rc::new(move |args: Vec<String>| {
+            host.handle.block_on(call(
+                Arc::clone(&host.store),
+                Arc::clone(&host.client),
+                &host.running,
+                args,
+            ))
+        });
+        let call_dispatch = Rc::clone(&dispatch);
+        lib.set_str(
+            "call",
+            LuaValue::native("call", move |_, args| {
+                let reply = call_dispatch(command_args(args)?);
+                match reply {
+                    Resp::SE(_) => Err(LuaError::value(from_resp(reply), true)),
+                    reply => Ok(vec![from_resp(reply)]),
+                }
+            }),
+        );
+        lib.set_str(
+            "pcall",
+            LuaValue::native("pcall", move |_, args| {
+                let reply = match command_args(args) {
+                    Ok(args) => from_resp(dispatch(args)),
+                    Err(err) => error_table(&err.to_string()),
+                };
+                Ok(vec![reply])
+            }),
+        );
+    }
+
+    lib.set_str(
+        "error_reply",
+        LuaValue::native("error_reply", |_, args| {
+            Ok(vec![error_table(&reply_arg(&args, "error_reply")?)])
+        }),
+    );
+    lib.set_str(
+        "status_reply",
+        LuaValue::native("status_reply", |_, args| {
+            let mut table = Table::default();
+            table.set_str("ok", LuaValue::str(&reply_arg(&args, "status_reply")?));
+            Ok(vec![LuaValue::table(table)])
+        }),
+    );
+    lib.set_str(
+        "sha1hex",
+        LuaValue::native("sha1hex", |_, args| {
+            match args.first().and_then(LuaValue::to_str) {
+                Some(s) => Ok(vec![LuaValue::str(&utils::sha1_hex(s.as_bytes()))]),
+                None => Err(LuaError::runtime("wrong number of arguments")),
+            }
+        }),
+    );
+    lib.set_str(
+        "log",
+        LuaValue::native("log", |_, args| {
+            if args.len() < 2 {
+                return Err(LuaError::runtime(
+                    "redis.log() requires two arguments or more.",
+                ));
+            }
+            let msg: Vec<String> = args[1..].iter().map(|v| v.to_string()).collect();
+            println!("{}", msg.join(" "));
+            Ok(vec![])
+        }),
+    );
+    for (idx, level) in ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"]
+        .into_iter()
+        .enumerate()
+    {
+        lib.set_str(level, LuaValue::Number(idx as f64));
+    }
+
+    lib
+}
+
+fn reply_arg(args: &[LuaValue], func: &str) -> Result<String, LuaError> {
+    match args.first() {
+        Some(LuaValue::Str(s)) if args.len() == 1 => Ok(s.to_string()),
+        _ => Err(LuaError::runtime(format!(
+            "wrong number or type of arguments to redis.{func}"
+        ))),
+    }
+}
+
+fn error_table(msg: &str) -> LuaValue {
+    let mut table = Table::default();
+    table.set_str("err", LuaValue::str(msg));
+    LuaValue::table(table)
+}
+
+fn command_args(args: Vec<LuaValue>) -> Result<Vec<String>, LuaError> {
+    if args.is_empty() {
+        return Err(LuaError::runtime(
+            "Please specify at least one argument for this redis lib call",
+        ));
+    }
+    args.iter()
+        .map(|arg| match arg {
+            LuaValue::Str(s) => Ok(s.to_string()),
+            LuaValue::Number(n) => Ok(fmt_number(*n)),
+            _ => Err(LuaError::runtime(
+                "Lua redis lib command arguments must be strings or integers",
+            )),
+        })
+        .collect()
+}
+
+/// Runs a command on behalf of a script, returning errors as error replies. The hooks of
+/// the store run around it as they do around the commands of connections.
+async fn call(
+    store: Arc<Store>,
+    client: Arc<Client>,
+    running: &Running,
+    args: Vec<String>,
+) -> Resp {
+    if store.hooks().is_empty() {
+        return dispatch(store, client, running, args).await;
+    }
+
+    let started = Instant::now();
+    let mut hooked = CommandCall {
+        args,
+        addr: client.addr(),
+        mode: CommandMode::Normal,
+    };
+    if let Err(err) = store.hooks().before(&mut hooked) {
+        return Resp::from(err);
+    }
+    let reply = dispatch(Arc::clone(&store), client, running, hooked.args.clone()).await;
+    let frames = [Bytes::from(reply.serialize())];
+    store.hooks().after(&hooked, &frames, started.elapsed());
+    reply
+}
+
+async fn dispatch(
+    store: Arc<Store>,
+    client: Arc<Client>,
+    running: &Running,
+    args: Vec<String>,
+) -> Resp {
+    let cmd = match Command::new(Resp::from(args)) {
+        Ok(Command::Unknown) => {
+            return Resp::SE("ERR Unknown Redis command called from script".into())
+        }
+        Ok(cmd) if !cmd.allowed_in_script() => {
+            return Resp::SE("ERR This Redis command is not allowed from script".into())
+        }
+        // Scripts never block, so a blocking read returns at once.
+        Ok(Command::Xread { stream, .. }) => Command::Xread {
+            block: None,
+            stream,
+        },
+        Ok(cmd) => cmd,
+        Err(err) => return Resp::from(err),
+    };
+
+    if cmd.is_write() {
+        if running.readonly {
+            return Resp::SE("ERR Write commands are not allowed from read-only scripts.".into());
+        }
+        running.wrote.store(true, Ordering::Relaxed);
+    }
+    let addr: SocketAddr = client.addr();
+    let mut ctx = Context::detached(CommandMode::Normal, addr, client);
+    match cmd.run(store, &mut ctx).await {
+        Ok(Some(reply)) => reply,
+        Ok(None) => Resp::BS(None),
+        Err(err) => Resp::from(err),
+    }
+}
+
+/// Converts a command reply into the Lua value `redis.call` returns.
+fn from_resp(reply: Resp) -> LuaValue {
+    match reply {
+        Resp::I(n) => LuaValue::Number(n as f64),
+        Resp::BS(Some(s)) => LuaValue::str(&s),
+        Resp::BS(None) | Resp::NullArray => LuaValue::Bool(false),
+        Resp::SS(s) => {
+            let mut table = Table::default();
+            table.set_str("ok", LuaValue::str(&s));
+            LuaValue::table(table)
+        }
+        Resp::SE(err) => error_table(&err),
+        // Scripts get replies as RESP2 clients do.
+        Resp::A(values) | Resp::Set(values) | Resp::Push(values) => LuaValue::table(
+            Table::from_array(values.into_iter().map(from_resp).collect()),
+        ),
+        Resp::Double(n) => LuaValue::str(&n.to_string()),
+        Resp::Boolean(b) => LuaValue::Number(f64::from(u8::from(b))),
+        Resp::BigNumber(s) | Resp::VerbatimString { text: s, .. } => LuaValue::str(&s),
+        Resp::Map(pairs) => LuaValue::table(Table::from_array(
+            pairs
+                .into_iter()
+                .flat_map(|(key, value)| [key, value])
+                .map(from_resp)
+                .collect(),
+        )),
+        Resp::RAW(_) => LuaValue::Bool(false),
+    }
+}
+
+/// Converts the value a script returns into its reply.
+fn into_resp(value: &LuaValue) -> Resp {
+    match value {
+        LuaValue::Number(n) => Resp::I(*n as i64),
+        LuaValue::Str(s) => Resp::BS(Some(s.to_string())),
+        LuaValue::Bool(true) => Resp::I(1),
+        LuaValue::Bool(false) | LuaValue::Nil | LuaValue::Function(_) => Resp::BS(None),
+        LuaValue::Table(t) => {
+            let table = t.borrow();
+            if let LuaValue::Str(err) = table.get_str("err") {
+                return Resp::SE(err.to_string());
+            }
+            if let LuaValue::Str(ok) = table.get_str("ok") {
+                return Resp::SS(ok.to_string());
+            }
+            // An array ends at its first nil.
+            Resp::A(
+                table
+                    .array()
+                    .iter()
+                    .take_while(|v| !matches!(v, LuaValue::Nil))
+                    .map(into_resp)
+                    .collect(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the script on a thread of its own stack size, returning its reply or error.
+    fn eval(src: &str) -> Result<Resp, String> {
+        let src = src.to_string();
+        spawn(move || {
+            let mut interp = Interp::new();
+            stdlib::open(&mut interp);
+            interp.globals_locked = true;
+            let chunk = parser::parse(&src).map_err(|err| err.to_string())?;
+            interp
+                .run(chunk, vec![])
+                .map(|values| into_resp(values.first().unwrap_or(&LuaValue::Nil)))
+                .map_err(|err| err.to_string())
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+    }
+
+    fn reply(src: &str) -> Resp {
+        eval(src).unwrap()
+    }
+
+    #[test]
+    fn it_runs_scripts() {
+        let src = "
+            local function fib(n)
+                if n < 2 then return n end
+                return fib(n - 1) + fib(n - 2)
+            end
+            local t = {}
+            for i = 1, 10 do t[#t + 1] = fib(i) end
+            return table.concat(t, ',')
+        ";
+        assert_eq!(reply(src), Resp::BS(Some("1,1,2,3,5,8,13,21,34,55".into())));
+
+        let src = "
+            local sum = 0
+            for k, v in pairs({a = 1, b = 2, 3}) do sum = sum + v end
+            local s = string.format('%s=%05.1f', 'x', sum)
+            return {sum, s:upper(), select('#', 1, nil, 3), 'after', nil, 'dropped'}
+        ";
+        assert_eq!(
+            reply(src),
+            Resp::A(vec![
+                Resp::I(6),
+                Resp::BS(Some("X=006.0".into())),
+                Resp::I(3),
+                Resp::BS(Some("after".into())),
+            ])
+        );
+
+        let src = "
+            local ok, err = pcall(function() error('boom') end)
+            return {ok, err, 3.9, true}
+        ";
+        assert_eq!(
+            reply(src),
+            Resp::A(vec![
+                Resp::BS(None),
+                Resp::BS(Some("user_script:2: boom".into())),
+                Resp::I(3),
+                Resp::I(1),
+            ])
+        );
+        assert_eq!(
+            reply("return {err = 'ERR custom'}"),
+            Resp::SE("ERR custom".into())
+        );
+    }
+
+    #[test]
+    fn it_stops_killed_scripts() {
+        let running = Arc::new(Running::new(false));
+        running.kill();
+        let mut interp = Interp::new();
+        stdlib::open(&mut interp);
+        interp.hook = Some(Box::new(move || {
+            if running.killed.load(Ordering::Relaxed) {
+                Err(LuaError::fatal("killed"))
+            } else {
+                Ok(())
+            }
+        }));
+
+        // pcall doesn't catch the error that stops the script.
+        let chunk = parser::parse("pcall(function() while true do end end)").unwrap();
+        let err = interp.run(chunk, vec![]).unwrap_err();
+        assert!(err.fatal);
+        assert_eq!(err.to_string(), "user_script:1: killed");
+    }
+
+    #[test]
+    fn it_reports_runtime_errors() {
+        let err = eval("local a = 1\nreturn a + nil").unwrap_err();
+        assert_eq!(
+            err,
+            "user_script:2: attempt to perform arithmetic on a nil value"
+        );
+        let err = eval("x = 1").unwrap_err();
+        assert_eq!(err, "user_script:1: Attempt to modify a readonly table");
+        let err = eval("return undefined_var").unwrap_err();
+        assert_eq!(
+            err,
+            "user_script:1: Script attempted to access nonexistent global variable 'undefined_var'"
+        );
+        let err = eval("local function f() return f() + 1 end\nreturn f()").unwrap_err();
+        assert!(err.ends_with("stack overflow"));
+    }
+}
@@ -0,0 +1,643 @@
+use super::lexer::{tokenize, Token};
+use super::LuaError;
+use std::rc::Rc;
+
+pub(crate) type Block = Vec<Stat>;
+
+#[derive(Debug)]
+pub(crate) struct Stat {
+    pub(crate) kind: StatKind,
+    pub(crate) line: u32,
+}
+
+#[derive(Debug)]
+pub(crate) enum StatKind {
+    Local(Vec<String>, Vec<Expr>),
+    Assign(Vec<Expr>, Vec<Expr>),
+    Call(Expr),
+    If(Vec<(Expr, Block)>, Option<Block>),
+    While(Expr, Block),
+    Repeat(Block, Expr),
+    NumericFor {
+        var: String,
+        start: Expr,
+        limit: Expr,
+        step: Option<Expr>,
+        body: Block,
+    },
+    GenericFor(Vec<String>, Vec<Expr>, Block),
+    LocalFunction(String, Rc<FuncBody>),
+    Return(Vec<Expr>),
+    Break,
+    Do(Block),
+}
+
+#[derive(Debug)]
+pub(crate) struct FuncBody {
+    pub(crate) params: Vec<String>,
+    pub(crate) vararg: bool,
+    pub(crate) body: Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum UnOp {
+    Neg,
+    Not,
+    Len,
+}
+
+#[derive(Debug)]
+pub(crate) enum Expr {
+    Nil,
+    True,
+    False,
+    Number(f64),
+    Str(Rc<str>),
+    Vararg,
+    Function(Rc<FuncBody>),
+    Name(String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Method(Box<Expr>, Rc<str>, Vec<Expr>),
+    Table(Vec<Field>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    /// An expression in parentheses, which is truncated to one value.
+    Paren(Box<Expr>),
+}
+
+#[derive(Debug)]
+pub(crate) enum Field {
+    Positional(Expr),
+    Keyed(Expr, Expr),
+}
+
+impl Expr {
+    /// Whether the expression can produce several values in the last position of a list.
+    pub(crate) fn is_multi(&self) -> bool {
+        matches!(self, Self::Call(..) | Self::Method(..) | Self::Vararg)
+    }
+}
+
+/// Parses a whole script. The chunk is the body of a function taking varargs.
+pub(crate) fn parse(src: &str) -> Result<Rc<FuncBody>, LuaError> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        pos: 0,
+    };
+    let body = parser.block()?;
+    if parser.peek() != &Token::Eof {
+        return Err(parser.unexpected());
+    }
+    Ok(Rc::new(FuncBody {
+        params: vec![],
+        vararg: true,
+        body,
+    }))
+}
+
+struct Parser {
+    tokens: Vec<(Token, u32)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos.min(self.tokens.len() - 1)].0
+    }
+
+    fn line(&self) -> u32 {
+        self.tokens[self.pos.min(self.tokens.len() - 1)].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn check(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Token::Symbol(s) | Token::Keyword(s) if *s == symbol)
+    }
+
+    fn accept(&mut self, symbol: &str) -> bool {
+        let found = self.check(symbol);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), LuaError> {
+        if self.accept(symbol) {
+            Ok(())
+        } else {
+            Err(LuaError::syntax(
+                format!("'{symbol}' expected near {}", describe(self.peek())),
+                self.line(),
+            ))
+        }
+    }
+
+    fn unexpected(&self) -> LuaError {
+        LuaError::syntax(
+            format!("unexpected symbol near {}", describe(self.peek())),
+            self.line(),
+        )
+    }
+
+    fn name(&mut self) -> Result<String, LuaError> {
+        match self.next() {
+            Token::Name(name) => Ok(name),
+            token => Err(LuaError::syntax(
+                format!("<name> expected near {}", describe(&token)),
+                self.line(),
+            )),
+        }
+    }
+
+    fn block_end(&self) -> bool {
+        matches!(self.peek(), Token::Eof)
+            || ["end", "else", "elseif", "until"]
+                .iter()
+                .any(|k| self.check(k))
+    }
+
+    fn block(&mut self) -> Result<Block, LuaError> {
+        let mut stats = vec![];
+        while !self.block_end() {
+            if self.accept(";") {
+                continue;
+            }
+            let line = self.line();
+            if self.accept("return") {
+                let exprs = if self.block_end() || self.check(";") {
+                    vec![]
+                } else {
+                    self.expr_list()?
+                };
+                self.accept(";");
+                stats.push(Stat {
+                    kind: StatKind::Return(exprs),
+                    line,
+                });
+                if !self.block_end() {
+                    return Err(LuaError::syntax(
+                        format!("'end' expected near {}", describe(self.peek())),
+                        self.line(),
+                    ));
+                }
+                break;
+            }
+            let kind = self.statement()?;
+            stats.push(Stat { kind, line });
+        }
+        Ok(stats)
+    }
+
+    fn statement(&mut self) -> Result<StatKind, LuaError> {
+        if self.accept("break") {
+            return Ok(StatKind::Break);
+        }
+        if self.accept("do") {
+            let body = self.block()?;
+            self.expect("end")?;
+            return Ok(StatKind::Do(body));
+        }
+        if self.accept("while") {
+            let cond = self.expr()?;
+            self.expect("do")?;
+            let body = self.block()?;
+            self.expect("end")?;
+            return Ok(StatKind::While(cond, body));
+        }
+        if self.accept("repeat") {
+            let body = self.block()?;
+            self.expect("until")?;
+            let cond = self.expr()?;
+            return Ok(StatKind::Repeat(body, cond));
+        }
+        if self.accept("if") {
+            let mut branches = vec![];
+            let cond = self.expr()?;
+            self.expect("then")?;
+            branches.push((cond, self.block()?));
+            let mut otherwise = None;
+            loop {
+                if self.accept("elseif") {
+                    let cond = self.expr()?;
+                    self.expect("then")?;
+                    branches.push((cond, self.block()?));
+                } else if self.accept("else") {
+                    otherwise = Some(self.block()?);
+                    self.expect("end")?;
+                    break;
+                } else {
+                    self.expect("end")?;
+                    break;
+                }
+            }
+            return Ok(StatKind::If(branches, otherwise));
+        }
+        if self.accept("for") {
+            return self.for_statement();
+        }
+        if self.accept("function") {
+            // `function a.b:c() end` is an assignment of a function to `a.b.c`.
+            let mut target = Expr::Name(self.name()?);
+            let mut method = false;
+            loop {
+                if self.accept(".") {
+                    let key = Expr::Str(self.name()?.into());
+                    target = Expr::Index(Box::new(target), Box::new(key));
+                } else if self.accept(":") {
+                    let key = Expr::Str(self.name()?.into());
+                    target = Expr::Index(Box::new(target), Box::new(key));
+                    method = true;
+                    break;
+                } else {
+                    break;
+                }
+            }
+            let func = self.func_body(method)?;
+            return Ok(StatKind::Assign(vec![target], vec![Expr::Function(func)]));
+        }
+        if self.accept("local") {
+            if self.accept("function") {
+                let name = self.name()?;
+                let func = self.func_body(false)?;
+                return Ok(StatKind::LocalFunction(name, func));
+            }
+            let mut names = vec![self.name()?];
+            while self.accept(",") {
+                names.push(self.name()?);
+            }
+            let exprs = if self.accept("=") {
+                self.expr_list()?
+            } else {
+                vec![]
+            };
+            return Ok(StatKind::Local(names, exprs));
+        }
+
+        let expr = self.suffixed_expr()?;
+        if self.check("=") || self.check(",") {
+            let mut targets = vec![expr];
+            while self.accept(",") {
+                targets.push(self.suffixed_expr()?);
+            }
+            if targets
+                .iter()
+                .any(|t| !matches!(t, Expr::Name(_) | Expr::Index(..)))
+            {
+                return Err(LuaError::syntax(
+                    "syntax error near '='".into(),
+                    self.line(),
+                ));
+            }
+            self.expect("=")?;
+            let exprs = self.expr_list()?;
+            return Ok(StatKind::Assign(targets, exprs));
+        }
+        match expr {
+            Expr::Call(..) | Expr::Method(..) => Ok(StatKind::Call(expr)),
+            _ => Err(LuaError::syntax(
+                format!("syntax error near {}", describe(self.peek())),
+                self.line(),
+            )),
+        }
+    }
+
+    fn for_statement(&mut self) -> Result<StatKind, LuaError> {
+        let first = self.name()?;
+        if self.accept("=") {
+            let start = self.expr()?;
+            self.expect(",")?;
+            let limit = self.expr()?;
+            let step = if self.accept(",") {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            self.expect("do")?;
+            let body = self.block()?;
+            self.expect("end")?;
+            return Ok(StatKind::NumericFor {
+                var: first,
+                start,
+                limit,
+                step,
+                body,
+            });
+        }
+
+        let mut names = vec![first];
+        while self.accept(",") {
+            names.push(self.name()?);
+        }
+        self.expect("in")?;
+        let exprs = self.expr_list()?;
+        self.expect("do")?;
+        let body = self.block()?;
+        self.expect("end")?;
+        Ok(StatKind::GenericFor(names, exprs, body))
+    }
+
+    fn func_body(&mut self, method: bool) -> Result<Rc<FuncBody>, LuaError> {
+        let mut params = vec![];
+        if method {
+            params.push("self".to_string());
+        }
+        let mut vararg = false;
+        self.expect("(")?;
+        if !self.check(")") {
+            loop {
+                if self.accept("...") {
+                    vararg = true;
+                    break;
+                }
+                params.push(self.name()?);
+                if !self.accept(",") {
+                    break;
+                }
+            }
+        }
+        self.expect(")")?;
+        let body = self.block()?;
+        self.expect("end")?;
+        Ok(Rc::new(FuncBody {
+            params,
+            vararg,
+            body,
+        }))
+    }
+
+    fn expr_list(&mut self) -> Result<Vec<Expr>, LuaError> {
+        let mut exprs = vec![self.expr()?];
+        while self.accept(",") {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn expr(&mut self) -> Result<Expr, LuaError> {
+        self.binary_expr(0)
+    }
+
+    /// Precedence climbing over the binary operators binding tighter than `limit`.
+    fn binary_expr(&mut self, limit: u8) -> Result<Expr, LuaError> {
+        let mut left = if let Some(op) = self.unary_op() {
+            self.next();
+            let operand = self.binary_expr(UNARY_PRIORITY)?;
+            Expr::Unary(op, Box::new(operand))
+        } else {
+            self.simple_expr()?
+        };
+
+        while let Some(op) = self.binary_op() {
+            let (left_priority, right_priority) = priority(op);
+            if left_priority <= limit {
+                break;
+            }
+            self.next();
+            let right = self.binary_expr(right_priority)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary_op(&self) -> Option<UnOp> {
+        match self.peek() {
+            Token::Keyword("not") => Some(UnOp::Not),
+            Token::Symbol("-") => Some(UnOp::Neg),
+            Token::Symbol("#") => Some(UnOp::Len),
+            _ => None,
+        }
+    }
+
+    fn binary_op(&self) -> Option<BinOp> {
+        let op = match self.peek() {
+            Token::Symbol(s) | Token::Keyword(s) => *s,
+            _ => return None,
+        };
+        let op = match op {
+            "+" => BinOp::Add,
+            "-" => BinOp::Sub,
+            "*" => BinOp::Mul,
+            "/" => BinOp::Div,
+            "%" => BinOp::Mod,
+            "^" => BinOp::Pow,
+            ".." => BinOp::Concat,
+            "==" => BinOp::Eq,
+            "~=" => BinOp::Ne,
+            "<" => BinOp::Lt,
+            "<=" => BinOp::Le,
+            ">" => BinOp::Gt,
+            ">=" => BinOp::Ge,
+            "and" => BinOp::And,
+            "or" => BinOp::Or,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn simple_expr(&mut self) -> Result<Expr, LuaError> {
+        let expr = match self.peek().clone() {
+            Token::Number(n) => Expr::Number(n),
+            Token::Str(s) => Expr::Str(s),
+            Token::Keyword("nil") => Expr::Nil,
+            Token::Keyword("true") => Expr::True,
+            Token::Keyword("false") => Expr::False,
+            Token::Symbol("...") => Expr::Vararg,
+            Token::Keyword("function") => {
+                self.next();
+                return Ok(Expr::Function(self.func_body(false)?));
+            }
+            Token::Symbol("{") => return self.table(),
+            _ => return self.suffixed_expr(),
+        };
+        self.next();
+        Ok(expr)
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, LuaError> {
+        match self.peek().clone() {
+            Token::Name(name) => {
+                self.next();
+                Ok(Expr::Name(name))
+            }
+            Token::Symbol("(") => {
+                self.next();
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(Expr::Paren(Box::new(expr)))
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn suffixed_expr(&mut self) -> Result<Expr, LuaError> {
+        let mut expr = self.primary_expr()?;
+        loop {
+            match self.peek().clone() {
+                Token::Symbol(".") => {
+                    self.next();
+                    let key = Expr::Str(self.name()?.into());
+                    expr = Expr::Index(Box::new(expr), Box::new(key));
+                }
+                Token::Symbol("[") => {
+                    self.next();
+                    let key = self.expr()?;
+                    self.expect("]")?;
+                    expr = Expr::Index(Box::new(expr), Box::new(key));
+                }
+                Token::Symbol(":") => {
+                    self.next();
+                    let name = self.name()?;
+                    let args = self.call_args()?;
+                    expr = Expr::Method(Box::new(expr), name.into(), args);
+                }
+                Token::Symbol("(") | Token::Symbol("{") | Token::Str(_) => {
+                    let args = self.call_args()?;
+                    expr = Expr::Call(Box::new(expr), args);
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn call_args(&mut self) -> Result<Vec<Expr>, LuaError> {
+        match self.peek().clone() {
+            Token::Str(s) => {
+                self.next();
+                Ok(vec![Expr::Str(s)])
+            }
+            Token::Symbol("{") => Ok(vec![self.table()?]),
+            Token::Symbol("(") => {
+                self.next();
+                if self.accept(")") {
+                    return Ok(vec![]);
+                }
+                let args = self.expr_list()?;
+                self.expect(")")?;
+                Ok(args)
+            }
+            _ => Err(LuaError::syntax(
+                format!("function arguments expected near {}", describe(self.peek())),
+                self.line(),
+            )),
+        }
+    }
+
+    fn table(&mut self) -> Result<Expr, LuaError> {
+        self.expect("{")?;
+        let mut fields = vec![];
+        while !self.check("}") {
+            let field = if self.accept("[") {
+                let key = self.expr()?;
+                self.expect("]")?;
+                self.expect("=")?;
+                Field::Keyed(key, self.expr()?)
+            } else if matches!(self.peek(), Token::Name(_))
+                && matches!(self.tokens.get(self.pos + 1), Some((Token::Symbol("="), _)))
+            {
+                let key = Expr::Str(self.name()?.into());
+                self.expect("=")?;
+                Field::Keyed(key, self.expr()?)
+            } else {
+                Field::Positional(self.expr()?)
+            };
+            fields.push(field);
+            if !self.accept(",") && !self.accept(";") {
+                break;
+            }
+        }
+        self.expect("}")?;
+        Ok(Expr::Table(fields))
+    }
+}
+
+const UNARY_PRIORITY: u8 = 8;
+
+/// Left and right priorities as in Lua 5.1. Concatenation and power are right associative.
+fn priority(op: BinOp) -> (u8, u8) {
+    match op {
+        BinOp::Or => (1, 1),
+        BinOp::And => (2, 2),
+        BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => (3, 3),
+        BinOp::Concat => (5, 4),
+        BinOp::Add | BinOp::Sub => (6, 6),
+        BinOp::Mul | BinOp::Div | BinOp::Mod => (7, 7),
+        BinOp::Pow => (10, 9),
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Name(name) => format!("'{name}'"),
+        Token::Number(n) => format!("'{n}'"),
+        Token::Str(s) => format!("'{s}'"),
+        Token::Keyword(k) | Token::Symbol(k) => format!("'{k}'"),
+        Token::Eof => "<eof>".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_with_lua_precedence() {
+        let chunk = parse("return 1 + 2 * 3 ^ 2 ^ 0.5, not a == b, 'a' .. 'b' .. 'c'").unwrap();
+        let StatKind::Return(exprs) = &chunk.body[0].kind else {
+            panic!("not a return statement");
+        };
+        assert!(matches!(
+            &exprs[0],
+            Expr::Binary(BinOp::Add, _, right)
+                if matches!(right.as_ref(), Expr::Binary(BinOp::Mul, _, pow)
+                    if matches!(pow.as_ref(), Expr::Binary(BinOp::Pow, _, exp)
+                        if matches!(exp.as_ref(), Expr::Binary(BinOp::Pow, ..))))));
+        assert!(matches!(
+            &exprs[1],
+            Expr::Binary(BinOp::Eq, left, _) if matches!(left.as_ref(), Expr::Unary(UnOp::Not, _))
+        ));
+        assert!(matches!(
+            &exprs[2],
+            Expr::Binary(BinOp::Concat, _, right)
+                if matches!(right.as_ref(), Expr::Binary(BinOp::Concat, ..))
+        ));
+    }
+
+    #[test]
+    fn it_reports_syntax_errors() {
+        let err = parse("local x = \nif").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "user_script:2: unexpected symbol near 'if'"
+        );
+        assert!(parse("x").is_err());
+        assert!(parse("return 1 return 2").is_err());
+    }
+}
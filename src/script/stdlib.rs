@@ -0,0 +1,569 @@
+use super::interp::Interp;
+use super::value::{fmt_number, LuaError, LuaResult, LuaValue, Table};
+use std::rc::Rc;
+
+/// Installs the base functions and the `string`, `table` and `math` libraries.
+pub(crate) fn open(interp: &mut Interp) {
+    let base: [(&'static str, Builtin); 15] = [
+        ("type", lua_type),
+        ("tostring", lua_tostring),
+        ("tonumber", lua_tonumber),
+        ("pairs", lua_pairs),
+        ("ipairs", lua_ipairs),
+        ("next", lua_next),
+        ("select", lua_select),
+        ("unpack", lua_unpack),
+        ("error", lua_error),
+        ("pcall", lua_pcall),
+        ("assert", lua_assert),
+        ("rawget", lua_rawget),
+        ("rawset", lua_rawset),
+        ("rawequal", lua_rawequal),
+        ("setmetatable", lua_setmetatable),
+    ];
+    for (name, f) in base {
+        interp.set_global(name, LuaValue::native(name, f));
+    }
+
+    let string = library(&[
+        ("len", str_len),
+        ("sub", str_sub),
+        ("upper", str_upper),
+        ("lower", str_lower),
+        ("rep", str_rep),
+        ("reverse", str_reverse),
+        ("byte", str_byte),
+        ("char", str_char),
+        ("format", str_format),
+    ]);
+    if let LuaValue::Table(lib) = &string {
+        interp.string_lib = Some(Rc::clone(lib));
+    }
+    interp.set_global("string", string);
+
+    interp.set_global(
+        "table",
+        library(&[
+            ("insert", table_insert),
+            ("remove", table_remove),
+            ("concat", table_concat),
+            ("getn", table_getn),
+        ]),
+    );
+
+    let math = library(&[
+        ("floor", math_floor),
+        ("ceil", math_ceil),
+        ("abs", math_abs),
+        ("sqrt", math_sqrt),
+        ("fmod", math_fmod),
+        ("max", math_max),
+        ("min", math_min),
+    ]);
+    if let LuaValue::Table(lib) = &math {
+        let mut lib = lib.borrow_mut();
+        lib.set_str("huge", LuaValue::Number(f64::INFINITY));
+        lib.set_str("pi", LuaValue::Number(std::f64::consts::PI));
+    }
+    interp.set_global("math", math);
+}
+
+/// Values `unpack` returns at most, as bounded by the Lua stack.
+const MAX_UNPACK: i64 = 8000;
+
+type Builtin = fn(&mut Interp, Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>;
+
+fn library(functions: &[(&'static str, Builtin)]) -> LuaValue {
+    let mut table = Table::default();
+    for (name, f) in functions {
+        table.set_str(name, LuaValue::native(name, *f));
+    }
+    LuaValue::table(table)
+}
+
+fn arg(args: &[LuaValue], idx: usize) -> LuaValue {
+    args.get(idx).cloned().unwrap_or_default()
+}
+
+fn bad_arg(idx: usize, func: &str, msg: &str) -> LuaError {
+    LuaError::runtime(format!("bad argument #{} to '{func}' ({msg})", idx + 1))
+}
+
+fn check_number(args: &[LuaValue], idx: usize, func: &str) -> LuaResult<f64> {
+    let value = arg(args, idx);
+    value.to_number().ok_or_else(|| {
+        bad_arg(
+            idx,
+            func,
+            &format!("number expected, got {}", arg_type(&value)),
+        )
+    })
+}
+
+fn opt_number(args: &[LuaValue], idx: usize, func: &str, default: f64) -> LuaResult<f64> {
+    match arg(args, idx) {
+        LuaValue::Nil => Ok(default),
+        _ => check_number(args, idx, func),
+    }
+}
+
+fn check_str(args: &[LuaValue], idx: usize, func: &str) -> LuaResult<Rc<str>> {
+    let value = arg(args, idx);
+    value.to_str().ok_or_else(|| {
+        bad_arg(
+            idx,
+            func,
+            &format!("string expected, got {}", arg_type(&value)),
+        )
+    })
+}
+
+fn check_table(
+    args: &[LuaValue],
+    idx: usize,
+    func: &str,
+) -> LuaResult<Rc<std::cell::RefCell<Table>>> {
+    match arg(args, idx) {
+        LuaValue::Table(t) => Ok(t),
+        value => Err(bad_arg(
+            idx,
+            func,
+            &format!("table expected, got {}", arg_type(&value)),
+        )),
+    }
+}
+
+fn arg_type(value: &LuaValue) -> &'static str {
+    match value {
+        LuaValue::Nil => "no value",
+        value => value.type_name(),
+    }
+}
+
+fn lua_type(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    if args.is_empty() {
+        return Err(bad_arg(0, "type", "value expected"));
+    }
+    Ok(vec![LuaValue::str(args[0].type_name())])
+}
+
+fn lua_tostring(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    Ok(vec![LuaValue::str(&arg(&args, 0).to_string())])
+}
+
+fn lua_tonumber(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let value = arg(&args, 0);
+    let base = opt_number(&args, 1, "tonumber", 10.0)?;
+    let n = if base == 10.0 {
+        value.to_number()
+    } else {
+        value
+            .to_str()
+            .and_then(|s| i64::from_str_radix(s.trim(), base as u32).ok())
+            .map(|n| n as f64)
+    };
+    Ok(vec![n.map(LuaValue::Number).unwrap_or_default()])
+}
+
+fn lua_next(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let table = check_table(&args, 0, "next")?;
+    let next = table.borrow().next(&arg(&args, 1))?;
+    Ok(match next {
+        Some((key, value)) => vec![key, value],
+        None => vec![LuaValue::Nil],
+    })
+}
+
+fn lua_pairs(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let table = check_table(&args, 0, "pairs")?;
+    Ok(vec![
+        LuaValue::native("next", lua_next),
+        LuaValue::Table(table),
+        LuaValue::Nil,
+    ])
+}
+
+fn lua_ipairs(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let table = check_table(&args, 0, "ipairs")?;
+    let iter = LuaValue::native("ipairs_aux", |_, args| {
+        let table = check_table(&args, 0, "ipairs")?;
+        let i = arg(&args, 1).to_number().unwrap_or(0.0) + 1.0;
+        let value = table.borrow().get(&LuaValue::Number(i));
+        Ok(match value {
+            LuaValue::Nil => vec![LuaValue::Nil],
+            value => vec![LuaValue::Number(i), value],
+        })
+    });
+    Ok(vec![iter, LuaValue::Table(table), LuaValue::Number(0.0)])
+}
+
+fn lua_select(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    if let LuaValue::Str(s) = arg(&args, 0) {
+        if &*s == "#" {
+            return Ok(vec![LuaValue::Number((args.len() - 1) as f64)]);
+        }
+    }
+    let n = check_number(&args, 0, "select")? as i64;
+    let rest = args.len() as i64 - 1;
+    let start = match n {
+        n if n < 0 && -n <= rest => rest + n,
+        n if n > 0 => n - 1,
+        _ => return Err(bad_arg(0, "select", "index out of range")),
+    };
+    Ok(args.into_iter().skip(1 + start as usize).collect())
+}
+
+fn lua_unpack(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let table = check_table(&args, 0, "unpack")?;
+    let table = table.borrow();
+    let start = opt_number(&args, 1, "unpack", 1.0)? as i64;
+    let end = opt_number(&args, 2, "unpack", table.len() as f64)? as i64;
+    if end.saturating_sub(start) >= MAX_UNPACK {
+        return Err(LuaError::runtime("too many results to unpack"));
+    }
+    Ok((start..=end)
+        .map(|i| table.get(&LuaValue::Number(i as f64)))
+        .collect())
+}
+
+fn lua_error(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let level = opt_number(&args, 1, "error", 1.0)?;
+    Err(LuaError::value(arg(&args, 0), level > 0.0))
+}
+
+fn lua_pcall(interp: &mut Interp, mut args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    if args.is_empty() {
+        return Err(bad_arg(0, "pcall", "value expected"));
+    }
+    let func = args.remove(0);
+    match interp.call(&func, args) {
+        Ok(mut values) => {
+            values.insert(0, LuaValue::Bool(true));
+            Ok(values)
+        }
+        Err(err) if err.fatal => Err(err),
+        Err(err) => Ok(vec![LuaValue::Bool(false), err.into_value()]),
+    }
+}
+
+fn lua_assert(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    if arg(&args, 0).truthy() {
+        return Ok(args);
+    }
+    match arg(&args, 1) {
+        LuaValue::Nil => Err(LuaError::runtime("assertion failed!")),
+        msg => Err(LuaError::value(msg, false)),
+    }
+}
+
+fn lua_rawget(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let table = check_table(&args, 0, "rawget")?;
+    let value = table.borrow().get(&arg(&args, 1));
+    Ok(vec![value])
+}
+
+fn lua_rawset(interp: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let table = check_table(&args, 0, "rawset")?;
+    if interp.globals_locked && Rc::ptr_eq(&table, &interp.globals) {
+        return Err(LuaError::runtime("Attempt to modify a readonly table"));
+    }
+    table.borrow_mut().set(arg(&args, 1), arg(&args, 2))?;
+    Ok(vec![LuaValue::Table(table)])
+}
+
+fn lua_rawequal(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    Ok(vec![LuaValue::Bool(arg(&args, 0).raw_eq(&arg(&args, 1)))])
+}
+
+fn lua_setmetatable(_: &mut Interp, _: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    Err(LuaError::runtime("metatables are not supported"))
+}
+
+/// Converts Lua's 1-based, possibly negative, string positions into a byte range.
+fn str_range(len: usize, start: f64, end: f64) -> std::ops::Range<usize> {
+    let pos = |i: f64| {
+        let i = i as i64;
+        if i < 0 {
+            (len as i64 + i + 1).max(0)
+        } else {
+            i
+        }
+    };
+    let start = pos(start).max(1) as usize;
+    let end = (pos(end) as usize).min(len);
+    if start > end {
+        0..0
+    } else {
+        start - 1..end
+    }
+}
+
+fn substring(s: &str, range: std::ops::Range<usize>) -> LuaValue {
+    LuaValue::str(&String::from_utf8_lossy(&s.as_bytes()[range]))
+}
+
+fn str_len(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let s = check_str(&args, 0, "len")?;
+    Ok(vec![LuaValue::Number(s.len() as f64)])
+}
+
+fn str_sub(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let s = check_str(&args, 0, "sub")?;
+    let start = opt_number(&args, 1, "sub", 1.0)?;
+    let end = opt_number(&args, 2, "sub", -1.0)?;
+    Ok(vec![substring(&s, str_range(s.len(), start, end))])
+}
+
+fn str_upper(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let s = check_str(&args, 0, "upper")?;
+    Ok(vec![LuaValue::str(&s.to_ascii_uppercase())])
+}
+
+fn str_lower(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let s = check_str(&args, 0, "lower")?;
+    Ok(vec![LuaValue::str(&s.to_ascii_lowercase())])
+}
+
+fn str_rep(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let s = check_str(&args, 0, "rep")?;
+    let n = check_number(&args, 1, "rep")?.max(0.0) as usize;
+    if s.len().saturating_mul(n) > 512 * 1024 * 1024 {
+        return Err(LuaError::runtime("resulting string too large"));
+    }
+    Ok(vec![LuaValue::str(&s.repeat(n))])
+}
+
+fn str_reverse(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let s = check_str(&args, 0, "reverse")?;
+    Ok(vec![LuaValue::str(&s.chars().rev().collect::<String>())])
+}
+
+fn str_byte(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let s = check_str(&args, 0, "byte")?;
+    let start = opt_number(&args, 1, "byte", 1.0)?;
+    let end = opt_number(&args, 2, "byte", start)?;
+    Ok(s.as_bytes()[str_range(s.len(), start, end)]
+        .iter()
+        .map(|b| LuaValue::Number(*b as f64))
+        .collect())
+}
+
+fn str_char(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let mut bytes = vec![];
+    for idx in 0..args.len() {
+        let n = check_number(&args, idx, "char")?;
+        let byte = u8::try_from(n as i64).map_err(|_| bad_arg(idx, "char", "invalid value"))?;
+        bytes.push(byte);
+    }
+    Ok(vec![LuaValue::str(&String::from_utf8_lossy(&bytes))])
+}
+
+/// Supports the `%d`, `%i`, `%s`, `%q`, `%f`, `%g`, `%x`, `%X`, `%c` and `%%` specifiers
+/// with flags, width and precision.
+fn str_format(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let fmt = check_str(&args, 0, "format")?;
+    let mut out = String::new();
+    let mut chars = fmt.chars().peekable();
+    let mut next_arg = 1;
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut spec = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_ascii_digit() || "-+ #.".contains(c) {
+                spec.push(c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let Some(conv) = chars.next() else {
+            return Err(LuaError::runtime(
+                "invalid option '%' to 'format'".to_string(),
+            ));
+        };
+        if conv == '%' {
+            out.push('%');
+            continue;
+        }
+
+        let left = spec.contains('-');
+        let zero = spec
+            .trim_start_matches(['-', '+', ' ', '#'])
+            .starts_with('0');
+        let plus = spec.contains('+');
+        let (width, precision): (usize, Option<usize>) = match spec
+            .trim_start_matches(['-', '+', ' ', '#', '0'])
+            .split_once('.')
+        {
+            Some((w, p)) => (w.parse().unwrap_or(0), Some(p.parse().unwrap_or(0))),
+            None => (
+                spec.trim_start_matches(['-', '+', ' ', '#', '0'])
+                    .parse()
+                    .unwrap_or(0),
+                None,
+            ),
+        };
+
+        let idx = next_arg;
+        next_arg += 1;
+        let body = match conv {
+            'd' | 'i' => {
+                let n = check_number(&args, idx, "format")? as i64;
+                if plus && n >= 0 {
+                    format!("+{n}")
+                } else {
+                    n.to_string()
+                }
+            }
+            'x' => format!("{:x}", check_number(&args, idx, "format")? as i64),
+            'X' => format!("{:X}", check_number(&args, idx, "format")? as i64),
+            'c' => char::from(check_number(&args, idx, "format")? as u8).to_string(),
+            'f' => {
+                let n = check_number(&args, idx, "format")?;
+                let s = format!("{:.*}", precision.unwrap_or(6), n);
+                if plus && n >= 0.0 {
+                    format!("+{s}")
+                } else {
+                    s
+                }
+            }
+            'g' => fmt_number(check_number(&args, idx, "format")?),
+            's' => {
+                let s = arg(&args, idx).to_string();
+                match precision {
+                    Some(p) => s.chars().take(p).collect(),
+                    None => s,
+                }
+            }
+            'q' => format!("{:?}", check_str(&args, idx, "format")?.as_ref()),
+            c => {
+                return Err(LuaError::runtime(format!(
+                    "invalid option '%{c}' to 'format'"
+                )))
+            }
+        };
+
+        let pad = width.saturating_sub(body.chars().count());
+        if left {
+            out.push_str(&body);
+            out.push_str(&" ".repeat(pad));
+        } else if zero && !matches!(conv, 's' | 'q' | 'c') {
+            let (sign, digits) = match body.strip_prefix(['-', '+']) {
+                Some(digits) => (&body[..1], digits),
+                None => ("", body.as_str()),
+            };
+            out.push_str(sign);
+            out.push_str(&"0".repeat(pad));
+            out.push_str(digits);
+        } else {
+            out.push_str(&" ".repeat(pad));
+            out.push_str(&body);
+        }
+    }
+    Ok(vec![LuaValue::str(&out)])
+}
+
+fn table_insert(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let table = check_table(&args, 0, "insert")?;
+    let mut table = table.borrow_mut();
+    match args.len() {
+        2 => {
+            let len = table.len();
+            table.insert(len, arg(&args, 1));
+        }
+        3 => {
+            let pos = check_number(&args, 1, "insert")? as usize;
+            table.insert(pos.saturating_sub(1), arg(&args, 2));
+        }
+        _ => return Err(LuaError::runtime("wrong number of arguments to 'insert'")),
+    }
+    Ok(vec![])
+}
+
+fn table_remove(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let table = check_table(&args, 0, "remove")?;
+    let mut table = table.borrow_mut();
+    let len = table.len();
+    if len == 0 {
+        return Ok(vec![LuaValue::Nil]);
+    }
+    let pos = opt_number(&args, 1, "remove", len as f64)? as usize;
+    Ok(vec![table.remove(pos.saturating_sub(1))])
+}
+
+fn table_concat(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let table = check_table(&args, 0, "concat")?;
+    let table = table.borrow();
+    let sep = match arg(&args, 1) {
+        LuaValue::Nil => "".into(),
+        _ => check_str(&args, 1, "concat")?,
+    };
+    let start = opt_number(&args, 2, "concat", 1.0)? as i64;
+    let end = opt_number(&args, 3, "concat", table.len() as f64)? as i64;
+
+    let mut parts: Vec<Rc<str>> = vec![];
+    for i in start..=end {
+        let value = table.get(&LuaValue::Number(i as f64));
+        let part = value.to_str().ok_or_else(|| {
+            LuaError::runtime(format!(
+                "invalid value (at index {i}) in table for 'concat'"
+            ))
+        })?;
+        parts.push(part);
+    }
+    Ok(vec![LuaValue::str(&parts.join(&sep))])
+}
+
+fn table_getn(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let table = check_table(&args, 0, "getn")?;
+    let len = table.borrow().len();
+    Ok(vec![LuaValue::Number(len as f64)])
+}
+
+fn math_floor(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    Ok(vec![LuaValue::Number(
+        check_number(&args, 0, "floor")?.floor(),
+    )])
+}
+
+fn math_ceil(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    Ok(vec![LuaValue::Number(
+        check_number(&args, 0, "ceil")?.ceil(),
+    )])
+}
+
+fn math_abs(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    Ok(vec![LuaValue::Number(check_number(&args, 0, "abs")?.abs())])
+}
+
+fn math_sqrt(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    Ok(vec![LuaValue::Number(
+        check_number(&args, 0, "sqrt")?.sqrt(),
+    )])
+}
+
+fn math_fmod(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let a = check_number(&args, 0, "fmod")?;
+    let b = check_number(&args, 1, "fmod")?;
+    Ok(vec![LuaValue::Number(a % b)])
+}
+
+fn math_max(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let mut max = check_number(&args, 0, "max")?;
+    for idx in 1..args.len() {
+        max = max.max(check_number(&args, idx, "max")?);
+    }
+    Ok(vec![LuaValue::Number(max)])
+}
+
+fn math_min(_: &mut Interp, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let mut min = check_number(&args, 0, "min")?;
+    for idx in 1..args.len() {
+        min = min.min(check_number(&args, idx, "min")?);
+    }
+    Ok(vec![LuaValue::Number(min)])
+}
@@ -0,0 +1,524 @@
+use super::interp::Interp;
+use super::parser::FuncBody;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+pub(crate) type TableRef = Rc<RefCell<Table>>;
+pub(crate) type NativeFn = Rc<dyn Fn(&mut Interp, Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>>;
+pub(crate) type LuaResult<T> = Result<T, LuaError>;
+
+#[derive(Clone, Default)]
+pub(crate) enum LuaValue {
+    #[default]
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(Rc<str>),
+    Table(TableRef),
+    Function(Rc<Function>),
+}
+
+pub(crate) enum Function {
+    Lua { body: Rc<FuncBody>, env: Rc<Scope> },
+    Native { name: &'static str, f: NativeFn },
+}
+
+impl LuaValue {
+    pub(crate) fn str(s: &str) -> Self {
+        Self::Str(s.into())
+    }
+
+    pub(crate) fn native(
+        name: &'static str,
+        f: impl Fn(&mut Interp, Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> + 'static,
+    ) -> Self {
+        Self::Function(Rc::new(Function::Native {
+            name,
+            f: Rc::new(f),
+        }))
+    }
+
+    pub(crate) fn table(table: Table) -> Self {
+        Self::Table(Rc::new(RefCell::new(table)))
+    }
+
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Self::Nil => "nil",
+            Self::Bool(_) => "boolean",
+            Self::Number(_) => "number",
+            Self::Str(_) => "string",
+            Self::Table(_) => "table",
+            Self::Function(_) => "function",
+        }
+    }
+
+    pub(crate) fn truthy(&self) -> bool {
+        !matches!(self, Self::Nil | Self::Bool(false))
+    }
+
+    /// The number the value converts to in arithmetic, including numeric strings.
+    pub(crate) fn to_number(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            Self::Str(s) => parse_number(s),
+            _ => None,
+        }
+    }
+
+    /// The string the value converts to in concatenation.
+    pub(crate) fn to_str(&self) -> Option<Rc<str>> {
+        match self {
+            Self::Str(s) => Some(Rc::clone(s)),
+            Self::Number(n) => Some(fmt_number(*n).into()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn raw_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Nil, Self::Nil) => true,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Number(a), Self::Number(b)) => a == b,
+            (Self::Str(a), Self::Str(b)) => a == b,
+            (Self::Table(a), Self::Table(b)) => Rc::ptr_eq(a, b),
+            (Self::Function(a), Self::Function(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// What `tostring` returns.
+impl fmt::Display for LuaValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nil => write!(f, "nil"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) => write!(f, "{}", fmt_number(*n)),
+            Self::Str(s) => write!(f, "{s}"),
+            Self::Table(t) => write!(f, "table: {:p}", Rc::as_ptr(t)),
+            Self::Function(func) => match func.as_ref() {
+                Function::Native { name, .. } => write!(f, "builtin: {name}"),
+                Function::Lua { .. } => write!(f, "function: {:p}", Rc::as_ptr(func)),
+            },
+        }
+    }
+}
+
+impl fmt::Debug for LuaValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Str(s) => write!(f, "{s:?}"),
+            value => write!(f, "{value}"),
+        }
+    }
+}
+
+/// Formats a number like Lua's `%.14g`.
+pub(crate) fn fmt_number(n: f64) -> String {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.into();
+    }
+    if n.is_infinite() {
+        return if n < 0.0 { "-inf" } else { "inf" }.into();
+    }
+    if n == n.trunc() && n.abs() < 1e15 {
+        return format!("{}", n as i64);
+    }
+
+    let exp = n.abs().log10().floor() as i32;
+    if !(-4..14).contains(&exp) {
+        let s = format!("{:.13e}", n);
+        let (mantissa, exp) = s.split_once('e').unwrap_or((&s, "0"));
+        let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+        let exp: i32 = exp.parse().unwrap_or(0);
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{mantissa}e{sign}{:02}", exp.abs())
+    } else {
+        let decimals = (13 - exp).max(0) as usize;
+        let s = format!("{:.*}", decimals, n);
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+/// Parses a number the way `tonumber` does, allowing surrounding spaces and hex.
+pub(crate) fn parse_number(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        let n = u64::from_str_radix(hex, 16).ok()? as f64;
+        return Some(if negative { -n } else { n });
+    }
+    // Rust accepts "inf" and "nan", which Lua doesn't.
+    if s.is_empty()
+        || s.chars()
+            .any(|c| c.is_ascii_alphabetic() && c != 'e' && c != 'E')
+    {
+        return None;
+    }
+    s.parse::<f64>().ok()
+}
+
+/// Variables of a block. Closures keep the scopes they were created in alive.
+#[derive(Debug, Default)]
+pub(crate) struct Scope {
+    vars: RefCell<Vec<(String, Rc<RefCell<LuaValue>>)>>,
+    varargs: Option<Rc<Vec<LuaValue>>>,
+    parent: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    pub(crate) fn child(parent: &Rc<Scope>) -> Rc<Self> {
+        Rc::new(Self {
+            parent: Some(Rc::clone(parent)),
+            ..Default::default()
+        })
+    }
+
+    pub(crate) fn function(parent: &Rc<Scope>, varargs: Vec<LuaValue>) -> Rc<Self> {
+        Rc::new(Self {
+            parent: Some(Rc::clone(parent)),
+            varargs: Some(Rc::new(varargs)),
+            ..Default::default()
+        })
+    }
+
+    pub(crate) fn declare(&self, name: &str, value: LuaValue) {
+        self.vars
+            .borrow_mut()
+            .push((name.to_string(), Rc::new(RefCell::new(value))));
+    }
+
+    pub(crate) fn lookup(&self, name: &str) -> Option<Rc<RefCell<LuaValue>>> {
+        let found = self
+            .vars
+            .borrow()
+            .iter()
+            .rev()
+            .find(|(var, _)| var == name)
+            .map(|(_, cell)| Rc::clone(cell));
+        found.or_else(|| self.parent.as_ref().and_then(|p| p.lookup(name)))
+    }
+
+    pub(crate) fn varargs(&self) -> Option<Rc<Vec<LuaValue>>> {
+        self.varargs
+            .clone()
+            .or_else(|| self.parent.as_ref().and_then(|p| p.varargs()))
+    }
+}
+
+/// A key as hashed in a table. Numbers with an integer value are the same key whether
+/// written as 1 or 1.0, and tables and functions are keyed by identity.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Bool(bool),
+    Int(i64),
+    Float(u64),
+    Str(Rc<str>),
+    Ref(usize),
+}
+
+impl Key {
+    fn new(value: &LuaValue) -> Option<Self> {
+        let key = match value {
+            LuaValue::Nil => return None,
+            LuaValue::Bool(b) => Self::Bool(*b),
+            LuaValue::Number(n) if n.is_nan() => return None,
+            LuaValue::Number(n) if *n == n.trunc() && n.abs() < 9e15 => Self::Int(*n as i64),
+            LuaValue::Number(n) => Self::Float(n.to_bits()),
+            LuaValue::Str(s) => Self::Str(Rc::clone(s)),
+            LuaValue::Table(t) => Self::Ref(Rc::as_ptr(t) as *const u8 as usize),
+            LuaValue::Function(f) => Self::Ref(Rc::as_ptr(f) as *const u8 as usize),
+        };
+        Some(key)
+    }
+}
+
+/// A Lua table with an array part for the keys 1..n and an insertion-ordered hash part.
+#[derive(Debug, Default)]
+pub(crate) struct Table {
+    array: Vec<LuaValue>,
+    entries: Vec<(LuaValue, LuaValue)>,
+    index: HashMap<Key, usize>,
+}
+
+impl Table {
+    pub(crate) fn from_array(values: Vec<LuaValue>) -> Self {
+        let mut table = Self {
+            array: values,
+            ..Default::default()
+        };
+        table.trim();
+        table
+    }
+
+    pub(crate) fn get(&self, key: &LuaValue) -> LuaValue {
+        if let Some(idx) = self.array_index(key) {
+            return self.array.get(idx).cloned().unwrap_or_default();
+        }
+        Key::new(key)
+            .and_then(|k| self.index.get(&k))
+            .map(|idx| self.entries[*idx].1.clone())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn get_str(&self, key: &str) -> LuaValue {
+        self.get(&LuaValue::str(key))
+    }
+
+    pub(crate) fn set(&mut self, key: LuaValue, value: LuaValue) -> LuaResult<()> {
+        if let Some(idx) = self.array_index(&key) {
+            if idx < self.array.len() {
+                self.array[idx] = value;
+                self.trim();
+                return Ok(());
+            }
+            if idx == self.array.len() && !matches!(value, LuaValue::Nil) {
+                self.array.push(value);
+                self.migrate();
+                return Ok(());
+            }
+        }
+
+        let k = match Key::new(&key) {
+            Some(k) => k,
+            None if matches!(key, LuaValue::Nil) => {
+                return Err(LuaError::runtime("table index is nil"))
+            }
+            None => return Err(LuaError::runtime("table index is NaN")),
+        };
+        match self.index.get(&k) {
+            Some(idx) => self.entries[*idx].1 = value,
+            None if matches!(value, LuaValue::Nil) => {}
+            None => {
+                self.index.insert(k, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_str(&mut self, key: &str, value: LuaValue) {
+        // A string key is never nil or NaN.
+        let _ = self.set(LuaValue::str(key), value);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    pub(crate) fn array(&self) -> &[LuaValue] {
+        &self.array
+    }
+
+    pub(crate) fn insert(&mut self, pos: usize, value: LuaValue) {
+        let pos = pos.min(self.array.len());
+        self.array.insert(pos, value);
+        self.migrate();
+        self.trim();
+    }
+
+    pub(crate) fn remove(&mut self, pos: usize) -> LuaValue {
+        if pos >= self.array.len() {
+            return LuaValue::Nil;
+        }
+        let value = self.array.remove(pos);
+        self.trim();
+        value
+    }
+
+    /// The entry after `key` in iteration order, the array part first.
+    pub(crate) fn next(&self, key: &LuaValue) -> LuaResult<Option<(LuaValue, LuaValue)>> {
+        let mut start_entry = 0;
+        match key {
+            LuaValue::Nil => {
+                if let Some(pair) = self.next_in_array(0) {
+                    return Ok(Some(pair));
+                }
+            }
+            key => {
+                if let Some(idx) = self.array_index(key).filter(|i| *i < self.array.len()) {
+                    if let Some(pair) = self.next_in_array(idx + 1) {
+                        return Ok(Some(pair));
+                    }
+                } else {
+                    let idx = Key::new(key)
+                        .and_then(|k| self.index.get(&k))
+                        .ok_or_else(|| LuaError::runtime("invalid key to 'next'"))?;
+                    start_entry = idx + 1;
+                }
+            }
+        }
+        Ok(self.entries[start_entry..]
+            .iter()
+            .find(|(_, value)| !matches!(value, LuaValue::Nil))
+            .cloned())
+    }
+
+    fn next_in_array(&self, from: usize) -> Option<(LuaValue, LuaValue)> {
+        self.array
+            .iter()
+            .enumerate()
+            .skip(from)
+            .find(|(_, value)| !matches!(value, LuaValue::Nil))
+            .map(|(idx, value)| (LuaValue::Number((idx + 1) as f64), value.clone()))
+    }
+
+    /// The position in the array part a key maps to, if it is a positive integer.
+    fn array_index(&self, key: &LuaValue) -> Option<usize> {
+        match key {
+            LuaValue::Number(n) if *n >= 1.0 && *n == n.trunc() && *n <= usize::MAX as f64 => {
+                Some(*n as usize - 1)
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves the entries following the array part from the hash part into it.
+    fn migrate(&mut self) {
+        loop {
+            let next = Key::Int(self.array.len() as i64 + 1);
+            let Some(idx) = self.index.remove(&next) else {
+                return;
+            };
+            let value = std::mem::take(&mut self.entries[idx].1);
+            if matches!(value, LuaValue::Nil) {
+                return;
+            }
+            self.array.push(value);
+        }
+    }
+
+    fn trim(&mut self) {
+        while matches!(self.array.last(), Some(LuaValue::Nil)) {
+            self.array.pop();
+        }
+    }
+}
+
+/// An error raised in a script, carrying any Lua value. String messages get the line
+/// they were raised at.
+#[derive(Debug, Clone)]
+pub(crate) struct LuaError {
+    pub(crate) value: LuaValue,
+    pub(crate) line: Option<u32>,
+    located: bool,
+    /// Aborts the script, even through `pcall`.
+    pub(crate) fatal: bool,
+}
+
+impl LuaError {
+    pub(crate) fn runtime(msg: impl Into<String>) -> Self {
+        Self {
+            value: LuaValue::Str(msg.into().into()),
+            line: None,
+            located: false,
+            fatal: false,
+        }
+    }
+
+    pub(crate) fn fatal(msg: impl Into<String>) -> Self {
+        Self {
+            fatal: true,
+            ..Self::runtime(msg)
+        }
+    }
+
+    pub(crate) fn syntax(msg: String, line: u32) -> Self {
+        Self {
+            value: LuaValue::Str(msg.into()),
+            line: Some(line),
+            located: true,
+            fatal: false,
+        }
+    }
+
+    /// An error raised by `error`. Only string values show the position they get.
+    pub(crate) fn value(value: LuaValue, locate: bool) -> Self {
+        Self {
+            value,
+            line: None,
+            located: !locate,
+            fatal: false,
+        }
+    }
+
+    pub(crate) fn locate(mut self, line: u32) -> Self {
+        if !self.located {
+            self.line = Some(line);
+            self.located = true;
+        }
+        self
+    }
+
+    /// The message as `pcall` passes it on, including the position.
+    pub(crate) fn into_value(self) -> LuaValue {
+        match (&self.value, self.line) {
+            (LuaValue::Str(_), Some(_)) => LuaValue::str(&self.to_string()),
+            _ => self.value,
+        }
+    }
+}
+
+impl fmt::Display for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.value, self.line) {
+            (LuaValue::Str(s), Some(line)) => write!(f, "user_script:{line}: {s}"),
+            (LuaValue::Str(s), None) => write!(f, "{s}"),
+            (value, _) => write!(f, "(error object is a {} value)", value.type_name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_formats_numbers_like_lua() {
+        assert_eq!(fmt_number(3.0), "3");
+        assert_eq!(fmt_number(-0.5), "-0.5");
+        assert_eq!(fmt_number(1.0 / 3.0), "0.33333333333333");
+        assert_eq!(fmt_number(1e20), "1e+20");
+        assert_eq!(fmt_number(2.5e-7), "2.5e-07");
+        assert_eq!(parse_number(" 0x10 "), Some(16.0));
+        assert_eq!(parse_number("1e3"), Some(1000.0));
+        assert_eq!(parse_number("inf"), None);
+    }
+
+    #[test]
+    fn it_keeps_array_and_hash_parts() {
+        let mut table = Table::default();
+        table
+            .set(LuaValue::Number(2.0), LuaValue::str("b"))
+            .unwrap();
+        table
+            .set(LuaValue::Number(1.0), LuaValue::str("a"))
+            .unwrap();
+        table.set_str("x", LuaValue::Bool(true));
+        assert_eq!(table.len(), 2);
+        assert!(table
+            .get(&LuaValue::Number(2.0))
+            .raw_eq(&LuaValue::str("b")));
+
+        let mut keys = vec![];
+        let mut key = LuaValue::Nil;
+        while let Some((k, _)) = table.next(&key).unwrap() {
+            keys.push(k.to_string());
+            key = k;
+        }
+        assert_eq!(keys, vec!["1", "2", "x"]);
+
+        table.set(LuaValue::Number(2.0), LuaValue::Nil).unwrap();
+        assert_eq!(table.len(), 1);
+        assert!(table.set(LuaValue::Nil, LuaValue::Nil).is_err());
+    }
+}
@@ -0,0 +1,505 @@
+use super::{Client, Command, RedisError, RedisResult, Store};
+use crate::utils;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+const DEFAULT_USER: &str = "default";
+const SHA256_HEX_LEN: usize = 64;
+
+/// A user clients authenticate as, and the commands and keys it may use.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct User {
+    name: String,
+    enabled: bool,
+    /// Whether any password authenticates the user.
+    nopass: bool,
+    /// SHA-256 digests of the passwords, in hex.
+    passwords: BTreeSet<String>,
+    /// Whether the user may run every command but the exceptions, or none but them.
+    all_commands: bool,
+    /// Lowercase names of the commands the user may run, or may not with `all_commands`.
+    exceptions: BTreeSet<String>,
+    /// Patterns of the keys the user may access.
+    keys: Vec<String>,
+}
+
+impl User {
+    /// A user as ACL SETUSER creates it: off, without passwords, commands nor keys.
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            enabled: false,
+            nopass: false,
+            passwords: BTreeSet::new(),
+            all_commands: false,
+            exceptions: BTreeSet::new(),
+            keys: vec![],
+        }
+    }
+
+    /// The default user, which may do anything, with requirepass as its password.
+    fn default_user(requirepass: Option<&str>) -> Self {
+        let mut user = Self::new(DEFAULT_USER);
+        user.enabled = true;
+        user.all_commands = true;
+        user.keys = vec!["*".into()];
+        user.set_password(requirepass);
+        user
+    }
+
+    /// Has the password be the only one of the user, or no password needed without it.
+    fn set_password(&mut self, password: Option<&str>) {
+        self.passwords.clear();
+        self.nopass = password.is_none();
+        if let Some(password) = password {
+            self.passwords
+                .insert(utils::sha256_hex(password.as_bytes()));
+        }
+    }
+
+    /// Applies a rule of ACL SETUSER, as the ACL file has them too. The error tells what
+    /// is wrong with the rule.
+    fn apply(&mut self, rule: &str) -> Result<(), &'static str> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.keys = vec!["*".into()],
+            "resetkeys" => self.keys.clear(),
+            "allcommands" | "+@all" => {
+                self.all_commands = true;
+                self.exceptions.clear();
+            }
+            "nocommands" | "-@all" => {
+                self.all_commands = false;
+                self.exceptions.clear();
+            }
+            "reset" => *self = Self::new(&self.name),
+            _ => {
+                let mut chars = rule.chars();
+                let op = chars.next().ok_or("Syntax error")?;
+                let arg = chars.as_str();
+                match op {
+                    '>' => {
+                        self.nopass = false;
+                        self.passwords.insert(utils::sha256_hex(arg.as_bytes()));
+                    }
+                    '<' => {
+                        if !self.passwords.remove(&utils::sha256_hex(arg.as_bytes())) {
+                            return Err("no such password");
+                        }
+                    }
+                    '#' => {
+                        if arg.len() != SHA256_HEX_LEN
+                            || !arg.bytes().all(|b| b.is_ascii_hexdigit())
+                        {
+                            return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters");
+                        }
+                        self.nopass = false;
+                        self.passwords.insert(arg.to_lowercase());
+                    }
+                    '!' => {
+                        if !self.passwords.remove(&arg.to_lowercase()) {
+                            return Err("no such password");
+                        }
+                    }
+                    '~' => {
+                        if !self.keys.iter().any(|key| key == arg) {
+                            self.keys.push(arg.into());
+                        }
+                    }
+                    '+' | '-' if arg.starts_with('@') => {
+                        return Err("Unknown command or category name in ACL");
+                    }
+                    '+' | '-' if !arg.is_empty() => {
+                        let name = arg.to_lowercase();
+                        if (op == '+') == self.all_commands {
+                            self.exceptions.remove(&name);
+                        } else {
+                            self.exceptions.insert(name);
+                        }
+                    }
+                    _ => return Err("Syntax error"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The rules recreating the user, as ACL LIST shows it and the ACL file saves it.
+    fn describe(&self) -> String {
+        let mut rules = vec![
+            format!("user {}", self.name),
+            if self.enabled { "on" } else { "off" }.to_string(),
+        ];
+        if self.nopass {
+            rules.push("nopass".into());
+        }
+        rules.extend(self.passwords.iter().map(|hash| format!("#{hash}")));
+        rules.extend(self.keys.iter().map(|key| format!("~{key}")));
+        let (all, exception) = if self.all_commands {
+            ("+@all", '-')
+        } else {
+            ("-@all", '+')
+        };
+        rules.push(all.into());
+        rules.extend(
+            self.exceptions
+                .iter()
+                .map(|name| format!("{exception}{name}")),
+        );
+        rules.join(" ")
+    }
+
+    fn check_password(&self, password: &str) -> bool {
+        self.enabled
+            && (self.nopass
+                || self
+                    .passwords
+                    .contains(&utils::sha256_hex(password.as_bytes())))
+    }
+
+    fn can_run(&self, name: &str) -> bool {
+        self.all_commands != self.exceptions.contains(name)
+    }
+
+    fn can_access(&self, key: &str) -> bool {
+        self.keys
+            .iter()
+            .any(|pattern| utils::glob_match(pattern.as_bytes(), key.as_bytes(), false))
+    }
+}
+
+/// The users by name. The default user always exists.
+#[derive(Debug)]
+pub(crate) struct Acl {
+    users: Mutex<BTreeMap<String, User>>,
+}
+
+impl Acl {
+    pub(crate) fn new(requirepass: Option<&str>) -> Self {
+        let default = User::default_user(requirepass);
+        Self {
+            users: Mutex::new(BTreeMap::from([(default.name.clone(), default)])),
+        }
+    }
+
+    /// Replaces the users with the ones of the ACL file, all at once, or keeps them all
+    /// when a line of the file is wrong. Without a default user in the file, the default
+    /// user takes requirepass as its password.
+    pub(crate) fn load(&self, path: &str, requirepass: Option<&str>) -> RedisResult<()> {
+        let content = fs::read_to_string(path).map_err(|err| {
+            anyhow::anyhow!("ERR Error loading ACLs, opening file '{path}': {err}")
+        })?;
+        let users = parse_users(&content, requirepass)
+            .map_err(|(n, reason)| anyhow::anyhow!("ERR {path}:{n}: {reason}"))?;
+        *self.lock()? = users;
+        Ok(())
+    }
+
+    /// Writes every user to the ACL file. It is written to a temporary file first and
+    /// renamed, so the file at `path` is never a partial one.
+    pub(crate) fn save(&self, path: &str) -> RedisResult<()> {
+        let content: String = self
+            .lock()?
+            .values()
+            .map(|user| format!("{}\n", user.describe()))
+            .collect();
+        let path = Path::new(path);
+        let tmp = path.with_file_name(format!("temp-{}.acl", std::process::id()));
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Whether clients are authenticated as the default user when they connect.
+    pub(crate) fn default_nopass(&self) -> bool {
+        self.lock()
+            .ok()
+            .and_then(|users| {
+                users
+                    .get(DEFAULT_USER)
+                    .map(|user| user.enabled && user.nopass)
+            })
+            .unwrap_or(false)
+    }
+
+    /// Sets the password of the default user, as requirepass does.
+    pub(crate) fn set_requirepass(&self, requirepass: Option<&str>) -> RedisResult<()> {
+        if let Some(user) = self.lock()?.get_mut(DEFAULT_USER) {
+            user.set_password(requirepass);
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> RedisResult<MutexGuard<'_, BTreeMap<String, User>>> {
+        self.users
+            .lock()
+            .map_err(|err| RedisError::Lock(err.to_string()))
+    }
+}
+
+/// Parses the lines of an ACL file, `user <name> <rule>...` each, failing with the line
+/// number and the reason.
+fn parse_users(
+    content: &str,
+    requirepass: Option<&str>,
+) -> Result<BTreeMap<String, User>, (usize, String)> {
+    let mut users: BTreeMap<String, User> = BTreeMap::new();
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let [directive, name, rules @ ..] = words.as_slice() else {
+            return Err((n + 1, "should start with user keyword".into()));
+        };
+        if !directive.eq_ignore_ascii_case("user") {
+            return Err((n + 1, "should start with user keyword".into()));
+        }
+        if users.contains_key(*name) {
+            return Err((n + 1, format!("Duplicate user '{name}' found")));
+        }
+        let mut user = User::new(name);
+        for rule in rules {
+            user.apply(rule).map_err(|reason| {
+                (
+                    n + 1,
+                    format!("Error in applying operation '{rule}': {reason}"),
+                )
+            })?;
+        }
+        users.insert(user.name.clone(), user);
+    }
+    users
+        .entry(DEFAULT_USER.into())
+        .or_insert_with(|| User::default_user(requirepass));
+    Ok(users)
+}
+
+impl Store {
+    /// Checks the password of AUTH, the user being `default` when not given. When the
+    /// default user needs no password, AUTH with the password only is refused.
+    pub async fn authenticate(
+        &self,
+        client: &Client,
+        username: Option<&str>,
+        password: &str,
+    ) -> RedisResult<()> {
+        let users = self.acl.lock()?;
+        let user = users.get(username.unwrap_or(DEFAULT_USER));
+        if username.is_none() && user.is_some_and(|user| user.nopass) {
+            return Err(anyhow::anyhow!(
+                "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"
+            )
+            .into());
+        }
+        match user {
+            Some(user) if user.check_password(password) => {
+                client.set_user(&user.name);
+                client.set_authenticated(true);
+                Ok(())
+            }
+            _ => Err(anyhow::anyhow!(
+                "WRONGPASS invalid username-password pair or user is disabled."
+            )
+            .into()),
+        }
+    }
+
+    /// Checks that the user the client authenticated as may run the command on its keys.
+    pub fn acl_check(&self, client: &Client, cmd: &Command) -> RedisResult<()> {
+        let Some(name) = cmd.name() else {
+            return Ok(());
+        };
+        let username = client.user();
+        let users = self.acl.lock()?;
+        let Some(user) = users.get(&username).filter(|user| user.enabled) else {
+            return Err(anyhow::anyhow!("NOPERM User {username} is disabled").into());
+        };
+        if !user.can_run(name) {
+            return Err(anyhow::anyhow!(
+                "NOPERM User {username} has no permissions to run the '{name}' command"
+            )
+            .into());
+        }
+        if !cmd.keys().iter().all(|key| user.can_access(key)) {
+            return Err(anyhow::anyhow!("NOPERM No permissions to access a key").into());
+        }
+        Ok(())
+    }
+
+    /// Creates the user, or changes it, applying the rules in order. A wrong rule leaves
+    /// the user as it was.
+    pub fn acl_setuser(&self, name: &str, rules: &[String]) -> RedisResult<()> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(
+                anyhow::anyhow!("ERR Usernames can't contain spaces or null characters").into(),
+            );
+        }
+        let mut users = self.acl.lock()?;
+        let mut user = users.get(name).cloned().unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.apply(rule).map_err(|reason| {
+                anyhow::anyhow!("ERR Error in ACL SETUSER modifier '{rule}': {reason}")
+            })?;
+        }
+        users.insert(name.into(), user);
+        Ok(())
+    }
+
+    /// Deletes the users and returns how many existed. The default user can't be deleted.
+    pub fn acl_deluser(&self, names: &[String]) -> RedisResult<i64> {
+        if names.iter().any(|name| name == DEFAULT_USER) {
+            return Err(anyhow::anyhow!("ERR The 'default' user cannot be removed").into());
+        }
+        let mut users = self.acl.lock()?;
+        Ok(names
+            .iter()
+            .filter(|name| users.remove(name.as_str()).is_some())
+            .count() as i64)
+    }
+
+    /// Every user, described by the rules recreating it.
+    pub fn acl_list(&self) -> RedisResult<Vec<String>> {
+        Ok(self.acl.lock()?.values().map(User::describe).collect())
+    }
+
+    pub fn acl_users(&self) -> RedisResult<Vec<String>> {
+        Ok(self.acl.lock()?.keys().cloned().collect())
+    }
+
+    /// Replaces the users with the ones of the ACL file.
+    pub async fn acl_load(&self) -> RedisResult<()> {
+        let path = self.aclfile()?;
+        let requirepass = self.lock().await.requirepass.clone();
+        self.acl.load(path, requirepass.as_deref())
+    }
+
+    /// Writes the users to the ACL file.
+    pub fn acl_save(&self) -> RedisResult<()> {
+        self.acl.save(self.aclfile()?)
+    }
+
+    fn aclfile(&self) -> RedisResult<&str> {
+        self.config.aclfile.as_deref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "ERR This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration."
+            )
+            .into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(rules: &str) -> User {
+        let mut user = User::new("alice");
+        for rule in rules.split_whitespace() {
+            user.apply(rule).unwrap();
+        }
+        user
+    }
+
+    #[test]
+    fn it_applies_rules() {
+        let alice = user("on >secret ~cache:* +@all -debug");
+        assert!(alice.check_password("secret"));
+        assert!(!alice.check_password("other"));
+        assert!(alice.can_run("get"));
+        assert!(!alice.can_run("debug"));
+        assert!(alice.can_access("cache:1"));
+        assert!(!alice.can_access("other"));
+
+        let alice = user("on nopass -@all +get +debug -debug");
+        assert!(alice.check_password("anything"));
+        assert!(alice.can_run("get"));
+        assert!(!alice.can_run("debug"));
+        assert!(!alice.can_run("set"));
+
+        assert!(!user("off nopass").check_password("anything"));
+        assert_eq!(user("on >secret allkeys reset"), User::new("alice"));
+
+        let mut alice = User::new("alice");
+        for rule in ["#abc", "+@read", "<none", "*", ""] {
+            assert!(alice.apply(rule).is_err(), "{rule}");
+        }
+    }
+
+    #[test]
+    fn it_describes_users_as_their_rules() {
+        let hash = utils::sha256_hex(b"secret");
+        let alice = user("on >secret ~cache:* ~tmp:* +@all -debug");
+        assert_eq!(
+            alice.describe(),
+            format!("user alice on #{hash} ~cache:* ~tmp:* +@all -debug")
+        );
+        assert_eq!(
+            User::default_user(None).describe(),
+            "user default on nopass ~* +@all"
+        );
+
+        let described = alice.describe();
+        let mut parsed = User::new("alice");
+        for rule in described.split_whitespace().skip(2) {
+            parsed.apply(rule).unwrap();
+        }
+        assert_eq!(parsed, alice);
+    }
+
+    #[test]
+    fn it_parses_acl_files() {
+        let users = parse_users(
+            "# users\nuser alice on >secret ~* +@all\n\nuser bob off -@all +get\n",
+            Some("foo"),
+        )
+        .unwrap();
+        assert_eq!(
+            users.keys().collect::<Vec<_>>(),
+            vec!["alice", "bob", "default"]
+        );
+        assert!(users["default"].check_password("foo"));
+        assert!(!users["default"].nopass);
+
+        let err = parse_users("user alice on\nuser alice off\n", None).unwrap_err();
+        assert_eq!(err, (2, "Duplicate user 'alice' found".into()));
+        assert_eq!(parse_users("alice on\n", None).unwrap_err().0, 1);
+        assert_eq!(parse_users("user alice +@read\n", None).unwrap_err().0, 1);
+    }
+
+    #[test]
+    fn it_saves_and_loads_the_acl_file() {
+        let path = std::env::temp_dir().join(format!("redis-users-{}.acl", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+
+        let acl = Acl::new(Some("foo"));
+        acl.lock()
+            .unwrap()
+            .insert("alice".into(), user("on >secret ~cache:* -@all +get"));
+        acl.save(&path).unwrap();
+        let saved = acl.lock().unwrap().clone();
+
+        let loaded = Acl::new(None);
+        loaded.load(&path, None).unwrap();
+        assert_eq!(*loaded.lock().unwrap(), saved);
+        assert!(!loaded.default_nopass());
+
+        std::fs::write(&path, "user alice on\nuser bob +@read\n").unwrap();
+        let err = loaded.load(&path, None).unwrap_err();
+        assert!(err.to_string().starts_with(&format!("ERR {path}:2: ")));
+        assert_eq!(*loaded.lock().unwrap(), saved);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,611 @@
+use super::{Client, Command, Config, RedisError, RedisResult, Resp, Snapshot, Store};
+use crate::{resp::RespError, utils::Tokens, AppendFsync, CommandMode, Context, OutgoingMessage};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+
+/// The files making up the AOF: a base file written by the last rewrite, then the incr
+/// files holding the writes since, in the order they are replayed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Manifest {
+    base: Option<AofFile>,
+    incrs: Vec<AofFile>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct AofFile {
+    name: String,
+    seq: u64,
+}
+
+impl Manifest {
+    /// Parses lines like `file appendonly.aof.1.base.rdb seq 1 type b`.
+    pub(crate) fn parse(text: &str) -> RedisResult<Self> {
+        let mut manifest = Self::default();
+
+        for line in text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let field = |name: &str| {
+                tokens
+                    .chunks_exact(2)
+                    .find(|pair| pair[0] == name)
+                    .map(|pair| pair[1])
+            };
+            let invalid = || RedisError::from(anyhow::anyhow!("Invalid AOF manifest line: {line}"));
+
+            let file = AofFile {
+                name: field("file").ok_or_else(invalid)?.to_string(),
+                seq: field("seq")
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .ok_or_else(invalid)?,
+            };
+            match field("type") {
+                Some("b") => manifest.base = Some(file),
+                Some("i") => manifest.incrs.push(file),
+                // Files superseded by a rewrite, only waiting to be deleted.
+                Some("h") => {}
+                _ => return Err(invalid()),
+            }
+        }
+
+        manifest.incrs.sort_by_key(|file| file.seq);
+        Ok(manifest)
+    }
+
+    fn serialize(&self) -> String {
+        let base = self
+            .base
+            .iter()
+            .map(|file| format!("file {} seq {} type b\n", file.name, file.seq));
+        let incrs = self
+            .incrs
+            .iter()
+            .map(|file| format!("file {} seq {} type i\n", file.name, file.seq));
+        base.chain(incrs).collect()
+    }
+
+    /// All the files, the base first.
+    pub(crate) fn files(&self) -> Vec<&str> {
+        self.base
+            .iter()
+            .chain(self.incrs.iter())
+            .map(|file| file.name.as_str())
+            .collect()
+    }
+
+    /// The files replayed as commands, which is all but a base written as RDB.
+    fn replayed(&self) -> Vec<&str> {
+        self.base
+            .iter()
+            .filter(|file| !file.name.ends_with(".rdb"))
+            .chain(self.incrs.iter())
+            .map(|file| file.name.as_str())
+            .collect()
+    }
+}
+
+/// The append only file. Writes are buffered and appended to the last incr file by the
+/// cron, or at once with `appendfsync always`.
+#[derive(Debug)]
+pub(crate) struct Aof {
+    dir: PathBuf,
+    filename: String,
+    fsync: AppendFsync,
+    load_truncated: bool,
+    state: Mutex<AofState>,
+    rewriting: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct AofState {
+    manifest: Manifest,
+    /// The incr file writes go to. Until the AOF is loaded there is none, so that the
+    /// commands replayed aren't appended again.
+    file: Option<File>,
+    buf: Vec<u8>,
+    /// Whether bytes were written since the last fsync.
+    unsynced: bool,
+    last_fsync: u64,
+}
+
+impl Aof {
+    pub(crate) fn new(config: &Config) -> Self {
+        let dir = config.dir.as_deref().unwrap_or(".");
+        Self {
+            dir: PathBuf::from(dir).join(&config.appenddirname),
+            filename: config.appendfilename.clone(),
+            fsync: config.appendfsync,
+            load_truncated: config.aof_load_truncated,
+            state: Mutex::new(AofState::default()),
+            rewriting: AtomicBool::new(false),
+        }
+    }
+
+    /// The base file when it is an RDB file, and the files replayed over it. Gives `None`
+    /// when there is no manifest, in which case the RDB file is loaded instead.
+    pub(crate) fn load_files(&self) -> RedisResult<Option<(Option<PathBuf>, Vec<PathBuf>)>> {
+        let Some(manifest) = self.read_manifest()? else {
+            return Ok(None);
+        };
+        let base = manifest
+            .base
+            .as_ref()
+            .filter(|base| base.name.ends_with(".rdb"))
+            .map(|base| self.dir.join(&base.name));
+        let replayed = manifest
+            .replayed()
+            .iter()
+            .map(|name| self.dir.join(name))
+            .collect();
+        Ok(Some((base, replayed)))
+    }
+
+    /// Queues the write to be appended to the AOF.
+    pub(crate) fn feed(&self, msg: &OutgoingMessage) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let state = &mut *state;
+        let Some(file) = state.file.as_mut() else {
+            return;
+        };
+
+        for frame in msg.frames() {
+            state.buf.extend_from_slice(frame);
+        }
+        if self.fsync == AppendFsync::Always {
+            let written = file.write_all(&state.buf).and_then(|_| file.sync_data());
+            if let Err(err) = written {
+                eprintln!("Error writing to the AOF. {err}");
+            }
+            state.buf.clear();
+        }
+    }
+
+    /// Appends the queued writes to the incr file, and fsyncs it once a second with
+    /// `appendfsync everysec`. `now` is in milliseconds since UNIX epoch.
+    pub(crate) fn flush(&self, now: u64) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let state = &mut *state;
+        let Some(file) = state.file.as_mut() else {
+            return;
+        };
+
+        if !state.buf.is_empty() {
+            // The writes are kept queued on failure, to be appended on the next flush.
+            if let Err(err) = file.write_all(&state.buf) {
+                eprintln!("Error writing to the AOF. {err}");
+                return;
+            }
+            state.buf.clear();
+            state.unsynced = true;
+        }
+
+        if self.fsync == AppendFsync::EverySec
+            && state.unsynced
+            && now.saturating_sub(state.last_fsync) >= 1000
+        {
+            if let Err(err) = file.sync_data() {
+                eprintln!("Error fsyncing the AOF. {err}");
+            }
+            state.unsynced = false;
+            state.last_fsync = now;
+        }
+    }
+
+    /// Appends the queued writes and fsyncs the incr file whatever the policy, as the
+    /// server exits.
+    pub(crate) fn sync(&self) {
+        self.flush(u64::MAX);
+        if let Ok(mut state) = self.state.lock() {
+            if let Some(file) = state.file.as_mut() {
+                if let Err(err) = file.sync_data() {
+                    eprintln!("Error fsyncing the AOF. {err}");
+                }
+            }
+        }
+    }
+
+    pub(crate) fn rewrite_in_progress(&self) -> bool {
+        self.rewriting.load(Ordering::SeqCst)
+    }
+
+    fn read_manifest(&self) -> RedisResult<Option<Manifest>> {
+        match fs::read_to_string(self.manifest_path()) {
+            Ok(text) => Manifest::parse(&text).map(Some),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the manifest to a temporary file first and renames it, so that a crash
+    /// leaves either the old manifest or the new one.
+    fn write_manifest(&self, manifest: &Manifest) -> RedisResult<()> {
+        let path = self.manifest_path();
+        let tmp = self.dir.join(format!("temp-{}.manifest", self.filename));
+        fs::write(&tmp, manifest.serialize())?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.manifest", self.filename))
+    }
+
+    /// Has the writes appended to the last incr file of the manifest, creating one when
+    /// the manifest has none.
+    fn open(&self, mut manifest: Manifest) -> RedisResult<()> {
+        let file = match manifest.incrs.last() {
+            Some(incr) => OpenOptions::new()
+                .append(true)
+                .open(self.dir.join(&incr.name))?,
+            None => {
+                let (incr, file) = self.create_incr(&manifest)?;
+                manifest.incrs.push(incr);
+                self.write_manifest(&manifest)?;
+                file
+            }
+        };
+
+        let mut state = self.lock()?;
+        state.manifest = manifest;
+        state.file = Some(file);
+        Ok(())
+    }
+
+    /// Starts a rewrite by having the writes go to a new incr file, which is added to
+    /// the manifest at once so that it is loaded even if the rewrite never finishes.
+    /// Returns the seq of the new incr file.
+    fn switch_incr(&self) -> RedisResult<u64> {
+        let mut state = self.lock()?;
+        let state = &mut *state;
+        if let Some(file) = state.file.as_mut() {
+            file.write_all(&state.buf)?;
+            file.sync_data()?;
+            state.buf.clear();
+        }
+
+        let (incr, file) = self.create_incr(&state.manifest)?;
+        let seq = incr.seq;
+        let mut manifest = state.manifest.clone();
+        manifest.incrs.push(incr);
+        self.write_manifest(&manifest)?;
+
+        state.manifest = manifest;
+        state.file = Some(file);
+        state.unsynced = false;
+        Ok(seq)
+    }
+
+    /// Finishes a rewrite once the base file is written: the manifest keeps the incr
+    /// files from `seq` on, and the files it drops are deleted.
+    fn finish_rewrite(&self, base: String, base_seq: u64, seq: u64) -> RedisResult<()> {
+        let mut state = self.lock()?;
+        let old = state.manifest.clone();
+        let manifest = Manifest {
+            base: Some(AofFile {
+                name: base,
+                seq: base_seq,
+            }),
+            incrs: old
+                .incrs
+                .iter()
+                .filter(|incr| incr.seq >= seq)
+                .cloned()
+                .collect(),
+        };
+        self.write_manifest(&manifest)?;
+        state.manifest = manifest;
+        drop(state);
+
+        let dropped = old
+            .base
+            .iter()
+            .chain(old.incrs.iter().filter(|incr| incr.seq < seq));
+        for file in dropped {
+            if let Err(err) = fs::remove_file(self.dir.join(&file.name)) {
+                eprintln!("Failed to remove the old AOF file {}. {err}", file.name);
+            }
+        }
+        Ok(())
+    }
+
+    fn create_incr(&self, manifest: &Manifest) -> RedisResult<(AofFile, File)> {
+        let seq = manifest.incrs.last().map(|incr| incr.seq + 1).unwrap_or(1);
+        let name = format!("{}.{seq}.incr.aof", self.filename);
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(self.dir.join(&name))?;
+        Ok((AofFile { name, seq }, file))
+    }
+
+    fn lock(&self) -> RedisResult<std::sync::MutexGuard<'_, AofState>> {
+        self.state
+            .lock()
+            .map_err(|err| RedisError::Lock(err.to_string()))
+    }
+}
+
+impl Store {
+    /// Replays the AOF over its base, loaded by `load` before, then has writes appended
+    /// to it. Without a manifest, the AOF is created from the keyspace loaded from the RDB
+    /// file instead.
+    pub(crate) async fn load_aof(self: &Arc<Self>) -> RedisResult<()> {
+        let Some(aof) = &self.aof else {
+            return Ok(());
+        };
+
+        let Some(manifest) = aof.read_manifest()? else {
+            fs::create_dir_all(&aof.dir)?;
+            let rewrite = self.rewrite_aof().await?;
+            return rewrite
+                .await
+                .map_err(|err| RedisError::from(anyhow::anyhow!(err)))?;
+        };
+
+        let files = manifest.replayed();
+        let mut loaded = self.loading.loaded_bytes();
+        for (i, name) in files.iter().enumerate() {
+            let last = i + 1 == files.len();
+            loaded += self
+                .replay_aof_file(&aof.dir.join(name), last && aof.load_truncated, loaded)
+                .await?;
+        }
+        println!("DB loaded from append only file");
+        aof.open(manifest)
+    }
+
+    /// Rewrites the AOF in the background, so that it holds the keyspace rather than
+    /// every write that led to it.
+    pub async fn bgrewriteaof(&self) -> RedisResult<()> {
+        self.rewrite_aof().await.map(drop)
+    }
+
+    pub fn aof_enabled(&self) -> bool {
+        self.aof.is_some()
+    }
+
+    pub fn aof_rewrite_in_progress(&self) -> bool {
+        self.aof
+            .as_ref()
+            .is_some_and(|aof| aof.rewrite_in_progress())
+    }
+
+    /// Switches the writes to a new incr file while the keyspace is locked for the
+    /// snapshot, then writes the snapshot as the new base file. A write made just
+    /// before the switch may be in both the base and the new incr file; replaying it
+    /// again leaves the same value.
+    async fn rewrite_aof(&self) -> RedisResult<JoinHandle<RedisResult<()>>> {
+        let Some(aof) = &self.aof else {
+            return Err(
+                anyhow::anyhow!("ERR Background AOF rewriting requires appendonly yes").into(),
+            );
+        };
+        if aof.rewriting.swap(true, Ordering::SeqCst) {
+            return Err(anyhow::anyhow!(
+                "ERR Background append only file rewriting already in progress"
+            )
+            .into());
+        }
+
+        let (shards, seq) = {
+            let shards = self.keyspace.lock_all().await;
+            match aof.switch_incr() {
+                Ok(seq) => (shards.iter().map(|shard| (**shard).clone()).collect(), seq),
+                Err(err) => {
+                    aof.rewriting.store(false, Ordering::SeqCst);
+                    return Err(err);
+                }
+            }
+        };
+        let snapshot = Snapshot::new(shards, self.function_codes(), self.clock.now());
+
+        let base_seq = aof
+            .lock()?
+            .manifest
+            .base
+            .as_ref()
+            .map(|base| base.seq + 1)
+            .unwrap_or(1);
+        let base = format!("{}.{base_seq}.base.rdb", aof.filename);
+        let path = aof.dir.join(&base);
+        let aof = Arc::clone(aof);
+
+        Ok(tokio::task::spawn_blocking(move || {
+            let result = snapshot
+                .save(&path)
+                .and_then(|_| aof.finish_rewrite(base, base_seq, seq));
+            match &result {
+                Ok(_) => println!("Background AOF rewrite terminated with success"),
+                Err(err) => eprintln!("Background AOF rewrite error. {err}"),
+            }
+            aof.rewriting.store(false, Ordering::SeqCst);
+            result
+        }))
+    }
+
+    /// Runs the commands of the file, giving the bytes it read. A command cut off at the
+    /// end of the file is dropped and the file truncated before it when `truncate` is set;
+    /// anything else which doesn't parse fails the load. `loaded` is the bytes of the
+    /// dataset loaded before the file.
+    async fn replay_aof_file(
+        self: &Arc<Self>,
+        path: &Path,
+        truncate: bool,
+        loaded: u64,
+    ) -> RedisResult<u64> {
+        let bytes = fs::read(path)?;
+        let addr = SocketAddr::from(([0, 0, 0, 0], 0));
+        let client = Arc::new(Client::new(addr, self.clock.unix_millis()));
+        let mut tokens = Tokens::new(&bytes);
+
+        while !tokens.finished() {
+            let offset = bytes.len() - tokens.rest().len();
+            self.loading
+                .progress(loaded + offset as u64, None, self.clock.unix_millis());
+            let resp = match Resp::from_tokens(&mut tokens) {
+                Ok(resp) => resp,
+                Err(RedisError::Protocol(RespError::Incomplete)) if truncate => {
+                    eprintln!(
+                        "!!! Warning: short read while loading the AOF file {}!!!",
+                        path.display()
+                    );
+                    OpenOptions::new()
+                        .write(true)
+                        .open(path)?
+                        .set_len(offset as u64)?;
+                    eprintln!("AOF {} truncated at offset {offset}", path.display());
+                    break;
+                }
+                Err(err) => {
+                    return Err(anyhow::anyhow!(
+                        "Bad file format reading the append only file {} at offset {offset}. {err}",
+                        path.display()
+                    )
+                    .into());
+                }
+            };
+
+            let mut ctx = Context::detached(CommandMode::Sync, addr, Arc::clone(&client));
+            let result = match Command::new(resp) {
+                Ok(cmd) => cmd.run(Arc::clone(self), &mut ctx).await.map(drop),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                eprintln!(
+                    "Failed to replay the command at offset {offset} of {}. {err}",
+                    path.display()
+                );
+            }
+        }
+        Ok(bytes.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn store_in(dir: &Path) -> Arc<Store> {
+        let args = [
+            "bin",
+            "--dir",
+            &dir.to_string_lossy(),
+            "--appendonly",
+            "yes",
+        ];
+        let config = Config::new(args.iter().map(|v| v.to_string()).collect()).unwrap();
+        Arc::new(Store::new(&config).unwrap())
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("redis-aof-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("appendonlydir")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn it_parses_manifests() {
+        let text = "file appendonly.aof.2.base.rdb seq 2 type b\n\
+                    file appendonly.aof.1.base.rdb seq 1 type h\n\
+                    file appendonly.aof.4.incr.aof seq 4 type i\n\
+                    file appendonly.aof.3.incr.aof seq 3 type i\n";
+        let manifest = Manifest::parse(text).unwrap();
+        assert_eq!(
+            manifest.replayed(),
+            vec!["appendonly.aof.3.incr.aof", "appendonly.aof.4.incr.aof"]
+        );
+        assert_eq!(
+            manifest.serialize(),
+            "file appendonly.aof.2.base.rdb seq 2 type b\n\
+             file appendonly.aof.3.incr.aof seq 3 type i\n\
+             file appendonly.aof.4.incr.aof seq 4 type i\n"
+        );
+
+        assert!(Manifest::parse("file appendonly.aof.1.incr.aof seq x type i").is_err());
+        assert!(Manifest::parse("file appendonly.aof.1.incr.aof seq 1 type z").is_err());
+    }
+
+    #[tokio::test]
+    async fn it_replays_the_aof_and_truncates_a_partial_tail() {
+        let dir = temp_dir("replay");
+        let aof_dir = dir.join("appendonlydir");
+        fs::write(
+            aof_dir.join("appendonly.aof.manifest"),
+            "file appendonly.aof.1.incr.aof seq 1 type i\n",
+        )
+        .unwrap();
+        let set = Resp::from(vec!["SET".to_string(), "a".into(), "1".into()]).serialize();
+        let mut bytes = set.clone();
+        bytes.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\nb");
+        let incr = aof_dir.join("appendonly.aof.1.incr.aof");
+        fs::write(&incr, bytes).unwrap();
+
+        let store = store_in(&dir);
+        store.load().await.unwrap();
+        assert!(store.get("a").await.is_some());
+        assert!(store.get("b").await.is_none());
+        assert_eq!(fs::read(&incr).unwrap(), set);
+
+        store.set_string("c", "3".into(), Some(60_000)).await;
+        store.aof.as_ref().unwrap().flush(0);
+        let appended = String::from_utf8(fs::read(&incr).unwrap()).unwrap();
+        assert!(appended.contains("$1\r\nc\r\n$1\r\n3\r\n$4\r\nPXAT\r\n"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_rewrites_the_aof_into_a_new_base() {
+        let dir = temp_dir("rewrite");
+        let aof_dir = dir.join("appendonlydir");
+
+        // Without a manifest, the AOF starts from a rewrite of what was loaded.
+        let store = store_in(&dir);
+        store.load().await.unwrap();
+        store.set_string("foo", "bar".into(), None).await;
+        let manifest = fs::read_to_string(aof_dir.join("appendonly.aof.manifest")).unwrap();
+        assert_eq!(
+            manifest,
+            "file appendonly.aof.1.base.rdb seq 1 type b\n\
+             file appendonly.aof.1.incr.aof seq 1 type i\n"
+        );
+
+        store.bgrewriteaof().await.unwrap();
+        while store.aof_rewrite_in_progress() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        store.set_string("baz", "qux".into(), None).await;
+        store.aof.as_ref().unwrap().sync();
+
+        let manifest = fs::read_to_string(aof_dir.join("appendonly.aof.manifest")).unwrap();
+        assert_eq!(
+            manifest,
+            "file appendonly.aof.2.base.rdb seq 2 type b\n\
+             file appendonly.aof.2.incr.aof seq 2 type i\n"
+        );
+        assert!(!aof_dir.join("appendonly.aof.1.base.rdb").exists());
+        assert!(!aof_dir.join("appendonly.aof.1.incr.aof").exists());
+
+        let store = store_in(&dir);
+        store.load().await.unwrap();
+        assert!(store.get("foo").await.is_some());
+        assert!(store.get("baz").await.is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
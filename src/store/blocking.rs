@@ -0,0 +1,172 @@
+use super::notify::{KeyEvent, Notification, Notifier, Subscription};
+use crate::clock::{Clock, Sleep};
+use std::collections::HashMap;
+use std::fmt;
+use std::future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How a blocked client got released.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unblocked {
+    /// One of the keys got the event the client waits for.
+    Ready(Notification),
+    TimedOut,
+    /// The connection was closed while the client was blocked.
+    Disconnected,
+}
+
+/// Registry of the clients parked in blocking commands, keyed by client id.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BlockedClients(Arc<Mutex<HashMap<u64, Arc<Notify>>>>);
+
+impl BlockedClients {
+    /// Parks the client until the event happens on any of the keys or the timeout elapses.
+    /// The keys are subscribed immediately so nothing happening before `Blocked::wait` is
+    /// missed. Waiters on the same key are woken in the order they blocked.
+    pub(crate) fn block(
+        &self,
+        notifier: &Notifier,
+        clock: &dyn Clock,
+        client_id: u64,
+        keys: &[String],
+        event: KeyEvent,
+        timeout: Option<Duration>,
+    ) -> Blocked {
+        let cancel = Arc::new(Notify::new());
+        if let Ok(mut waiters) = self.0.lock() {
+            waiters.insert(client_id, Arc::clone(&cancel));
+        }
+
+        Blocked {
+            client_id,
+            subscription: notifier.subscribe(keys, event),
+            timer: match timeout {
+                Some(timeout) => clock.sleep(timeout),
+                None => Box::pin(future::pending()),
+            },
+            timed_out: false,
+            cancel,
+            registry: self.clone(),
+        }
+    }
+
+    /// Releases the client, if blocked, as disconnected.
+    pub(crate) fn cancel(&self, client_id: u64) {
+        if let Ok(waiters) = self.0.lock() {
+            if let Some(cancel) = waiters.get(&client_id) {
+                cancel.notify_one();
+            }
+        }
+    }
+
+    pub(crate) fn contains(&self, client_id: u64) -> bool {
+        self.0
+            .lock()
+            .map(|waiters| waiters.contains_key(&client_id))
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.lock().map(|waiters| waiters.len()).unwrap_or(0)
+    }
+
+    fn remove(&self, client_id: u64, cancel: &Arc<Notify>) {
+        if let Ok(mut waiters) = self.0.lock() {
+            // The client may have blocked again since; leave the newer registration alone.
+            if let Some(registered) = waiters.get(&client_id) {
+                if Arc::ptr_eq(registered, cancel) {
+                    waiters.remove(&client_id);
+                }
+            }
+        }
+    }
+}
+
+/// A client registered as blocked. Dropping it removes the registration and stops its timer.
+pub struct Blocked {
+    client_id: u64,
+    subscription: Subscription,
+    timer: Sleep,
+    timed_out: bool,
+    cancel: Arc<Notify>,
+    registry: BlockedClients,
+}
+
+impl Blocked {
+    /// Waits for the next event. It can be called again when the event turns out not to
+    /// satisfy the client, keeping the original deadline.
+    pub async fn wait(&mut self) -> Unblocked {
+        if self.timed_out {
+            return Unblocked::TimedOut;
+        }
+
+        tokio::select! {
+            notification = self.subscription.recv() => match notification {
+                Some(notification) => Unblocked::Ready(notification),
+                None => Unblocked::Disconnected,
+            },
+            _ = &mut self.timer => {
+                self.timed_out = true;
+                Unblocked::TimedOut
+            }
+            _ = self.cancel.notified() => Unblocked::Disconnected,
+        }
+    }
+}
+
+impl fmt::Debug for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Blocked")
+            .field("client_id", &self.client_id)
+            .field("timed_out", &self.timed_out)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Blocked {
+    fn drop(&mut self) {
+        self.registry.remove(self.client_id, &self.cancel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::time::UNIX_EPOCH;
+
+    #[tokio::test]
+    async fn it_unblocks_clients() {
+        let notifier = Notifier::default();
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let registry = BlockedClients::default();
+        let keys = vec!["foo".to_string()];
+        let timeout = Some(Duration::from_millis(100));
+
+        let mut first = registry.block(&notifier, &clock, 1, &keys, KeyEvent::StreamAdd, None);
+        let mut second = registry.block(&notifier, &clock, 2, &keys, KeyEvent::StreamAdd, timeout);
+        let mut third = registry.block(&notifier, &clock, 3, &keys, KeyEvent::StreamAdd, None);
+        assert_eq!(registry.len(), 3);
+
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(second.wait().await, Unblocked::TimedOut);
+        assert_eq!(second.wait().await, Unblocked::TimedOut);
+        drop(second);
+
+        registry.cancel(3);
+        assert_eq!(third.wait().await, Unblocked::Disconnected);
+        drop(third);
+
+        notifier.notify("foo", KeyEvent::StreamAdd);
+        let expected = Notification {
+            key: "foo".into(),
+            event: KeyEvent::StreamAdd,
+        };
+        assert_eq!(first.wait().await, Unblocked::Ready(expected));
+
+        drop(first);
+        assert_eq!(registry.len(), 0);
+    }
+}
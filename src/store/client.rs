@@ -0,0 +1,267 @@
+use crate::Protocol;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Per-connection state shared between the connection tasks and the store's client registry.
+#[derive(Debug)]
+pub struct Client {
+    id: u64,
+    addr: SocketAddr,
+    query_buf: AtomicUsize,
+    output_buf: AtomicUsize,
+    no_evict: AtomicBool,
+    repl_link: AtomicBool,
+    asking: AtomicBool,
+    readonly: AtomicBool,
+    authenticated: AtomicBool,
+    /// The ACL user the client authenticated as.
+    user: Mutex<String>,
+    /// The version of the protocol the client speaks.
+    protocol: AtomicU8,
+    last_interaction: AtomicU64,
+    listening_port: AtomicU16,
+    /// The replication offset when the client registered as a replica.
+    sync_offset: AtomicUsize,
+    announced_ip: Mutex<Option<String>>,
+    kill: Notify,
+    closed: AtomicBool,
+}
+
+impl Client {
+    pub(crate) fn new(addr: SocketAddr, now: u64) -> Self {
+        Self {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            addr,
+            query_buf: AtomicUsize::new(0),
+            output_buf: AtomicUsize::new(0),
+            no_evict: AtomicBool::new(false),
+            repl_link: AtomicBool::new(false),
+            asking: AtomicBool::new(false),
+            readonly: AtomicBool::new(false),
+            // Clients the server runs commands as itself, for scripts or the AOF, need no
+            // password. Connections are registered as needing one when it's set.
+            authenticated: AtomicBool::new(true),
+            user: Mutex::new("default".into()),
+            protocol: AtomicU8::new(Protocol::Resp2.version() as u8),
+            last_interaction: AtomicU64::new(now),
+            listening_port: AtomicU16::new(0),
+            sync_offset: AtomicUsize::new(0),
+            announced_ip: Mutex::new(None),
+            kill: Notify::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn no_evict(&self) -> bool {
+        self.no_evict.load(Ordering::Relaxed)
+    }
+
+    pub fn set_no_evict(&self, no_evict: bool) {
+        self.no_evict.store(no_evict, Ordering::Relaxed);
+    }
+
+    /// Whether the connection is a replication link, either to a replica or to the master.
+    pub fn repl_link(&self) -> bool {
+        self.repl_link.load(Ordering::Relaxed)
+    }
+
+    pub fn set_repl_link(&self, repl_link: bool) {
+        self.repl_link.store(repl_link, Ordering::Relaxed);
+    }
+
+    /// Set by ASKING to have the next command served on a slot being imported.
+    pub fn set_asking(&self, asking: bool) {
+        self.asking.store(asking, Ordering::Relaxed);
+    }
+
+    /// Whether the client may run commands other than AUTH.
+    pub fn authenticated(&self) -> bool {
+        self.authenticated.load(Ordering::Relaxed)
+    }
+
+    pub fn set_authenticated(&self, authenticated: bool) {
+        self.authenticated.store(authenticated, Ordering::Relaxed);
+    }
+
+    /// The ACL user whose permissions apply to the commands of the client.
+    pub fn user(&self) -> String {
+        self.user
+            .lock()
+            .map(|user| user.clone())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn set_user(&self, name: &str) {
+        if let Ok(mut user) = self.user.lock() {
+            *user = name.into();
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        Protocol::from_version(self.protocol.load(Ordering::Relaxed).into()).unwrap_or_default()
+    }
+
+    pub fn set_protocol(&self, protocol: Protocol) {
+        self.protocol
+            .store(protocol.version() as u8, Ordering::Relaxed);
+    }
+
+    /// Returns the ASKING flag and clears it, as it applies to one command only.
+    pub fn take_asking(&self) -> bool {
+        self.asking.swap(false, Ordering::Relaxed)
+    }
+
+    /// Set by READONLY to have read commands served by a replica of the slot's master
+    /// in cluster mode, and cleared by READWRITE.
+    pub fn set_readonly(&self, readonly: bool) {
+        self.readonly.store(readonly, Ordering::Relaxed);
+    }
+
+    pub fn readonly(&self) -> bool {
+        self.readonly.load(Ordering::Relaxed)
+    }
+
+    /// Set by REPLCONF listening-port, the port a replica serves its own clients on.
+    pub fn set_listening_port(&self, port: u16) {
+        self.listening_port.store(port, Ordering::Relaxed);
+    }
+
+    /// Set by REPLCONF ip-address, for replicas behind NAT whose peer address isn't the
+    /// one to reach them at.
+    pub fn set_announced_ip(&self, ip: String) {
+        if let Ok(mut announced) = self.announced_ip.lock() {
+            *announced = Some(ip);
+        }
+    }
+
+    /// The offset a replica's full resync starts from, which it counts the stream on from.
+    pub fn sync_offset(&self) -> usize {
+        self.sync_offset.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_sync_offset(&self, offset: usize) {
+        self.sync_offset.store(offset, Ordering::Relaxed);
+    }
+
+    /// The address the client announced as a replica, taking the peer address for what
+    /// it didn't announce.
+    pub fn replica_endpoint(&self) -> (String, u16) {
+        let ip = self
+            .announced_ip
+            .lock()
+            .ok()
+            .and_then(|ip| ip.clone())
+            .unwrap_or_else(|| self.addr.ip().to_string());
+        let port = match self.listening_port.load(Ordering::Relaxed) {
+            0 => self.addr.port(),
+            port => port,
+        };
+        (ip, port)
+    }
+
+    /// Milliseconds since UNIX epoch when the client sent data last.
+    pub fn last_interaction(&self) -> u64 {
+        self.last_interaction.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn touch(&self, now: u64) {
+        self.last_interaction.store(now, Ordering::Relaxed);
+    }
+
+    /// Bytes read from the socket which haven't been executed yet.
+    pub fn query_buf(&self) -> usize {
+        self.query_buf.load(Ordering::Relaxed)
+    }
+
+    /// Bytes queued for the socket which haven't been written yet.
+    pub fn output_buf(&self) -> usize {
+        self.output_buf.load(Ordering::Relaxed)
+    }
+
+    /// Total memory attributed to the client, including its own bookkeeping.
+    pub fn memory(&self) -> usize {
+        mem::size_of::<Self>() + self.query_buf() + self.output_buf()
+    }
+
+    pub(crate) fn add_query_buf(&self, size: usize) {
+        self.query_buf.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reset_query_buf(&self) {
+        self.query_buf.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_output_buf(&self, size: usize) {
+        self.output_buf.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sub_output_buf(&self, size: usize) {
+        let _ = self
+            .output_buf
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+                Some(v.saturating_sub(size))
+            });
+    }
+
+    /// Asks the connection tasks to close the connection.
+    pub(crate) fn kill(&self) {
+        self.kill.notify_one();
+    }
+
+    pub(crate) async fn killed(&self) {
+        self.kill.notified().await
+    }
+
+    /// Whether the connection has been closed. Commands read before it closed are dropped.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_tracks_client_memory() {
+        let client = Client::new("127.0.0.1:6379".parse().unwrap(), 0);
+        let base = client.memory();
+
+        client.add_query_buf(10);
+        client.add_output_buf(100);
+        assert_eq!(client.memory(), base + 110);
+
+        client.sub_output_buf(200);
+        client.reset_query_buf();
+        assert_eq!(client.memory(), base);
+    }
+
+    #[test]
+    fn it_reports_the_announced_replica_endpoint() {
+        let client = Client::new("10.0.0.2:51234".parse().unwrap(), 0);
+        assert_eq!(client.replica_endpoint(), ("10.0.0.2".into(), 51234));
+
+        client.set_listening_port(6380);
+        assert_eq!(client.replica_endpoint(), ("10.0.0.2".into(), 6380));
+
+        client.set_announced_ip("203.0.113.7".into());
+        assert_eq!(client.replica_endpoint(), ("203.0.113.7".into(), 6380));
+    }
+}
@@ -0,0 +1,93 @@
+use super::{cluster, Resp, Store};
+use std::sync::Arc;
+use std::time::Duration;
+
+impl Store {
+    /// Runs the periodic maintenance `hz` times per second, like Redis's serverCron.
+    /// Every background job needing a timer hooks in here instead of spawning its own.
+    pub async fn cron(self: Arc<Self>) {
+        let period = 1000 / self.config.hz;
+        let mut cronloops: u64 = 0;
+
+        loop {
+            self.clock.sleep(Duration::from_millis(period)).await;
+            self.cron_tick(period, cronloops).await;
+            cronloops = cronloops.wrapping_add(1);
+        }
+    }
+
+    async fn cron_tick(&self, period: u64, cronloops: u64) {
+        // Whether a job running every `ms` milliseconds is due on this tick.
+        let every = |ms: u64| ms <= period || cronloops.checked_rem(ms / period) == Some(0);
+
+        self.active_expire_cycle().await;
+
+        if let Some(aof) = &self.aof {
+            aof.flush(self.clock.unix_millis());
+        }
+
+        if every(100) {
+            self.stats.sample(self.clock.unix_millis());
+        }
+
+        if every(1000) {
+            self.close_timedout_clients().await;
+            self.ack_master().await;
+            self.ping_cluster_nodes().await;
+            if self.maxmemory() > 0 {
+                self.measure_used_memory().await;
+            }
+
+            let save = self.lock().await.save.clone();
+            if self.save_state.due(&save, self.clock.unix_millis()) {
+                println!("Save rules met. Saving...");
+                if let Err(err) = self.bgsave().await {
+                    eprintln!("Failed to start saving. {err}");
+                }
+            }
+        }
+
+        if every(self.config.repl_ping_replica_period * 1000) && !self.is_replica() {
+            // Lets replicas tell a live but idle master from a dead link. It isn't a
+            // write, so it skips the AOF.
+            let ping: Resp = vec!["PING".to_string()].into();
+            self.replicas.propagate(ping.into());
+        }
+    }
+
+    /// Exchanges the view of the cluster with every known node.
+    async fn ping_cluster_nodes(&self) {
+        let Some(cluster) = &self.cluster else {
+            return;
+        };
+        for addr in cluster.lock().await.peers() {
+            let cluster = Arc::clone(cluster);
+            tokio::spawn(async move {
+                if let Err(err) = cluster::send(cluster, addr, cluster::MessageType::Ping).await {
+                    eprintln!("Failed to ping cluster node at {addr}. {err}");
+                }
+            });
+        }
+    }
+
+    /// Closes the clients idle for longer than `timeout`. Replication links and blocked
+    /// clients are never closed for being idle.
+    async fn close_timedout_clients(&self) {
+        let timeout = self.config.timeout * 1000;
+        if timeout == 0 {
+            return;
+        }
+
+        let now = self.clock.unix_millis();
+        let inner = self.lock().await;
+        for client in inner.clients.values() {
+            if client.repl_link() || self.blocked.contains(client.id()) {
+                continue;
+            }
+            if now.saturating_sub(client.last_interaction()) > timeout {
+                println!("Closing idle client {}", client.addr());
+                client.kill();
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Keys with an expiry each loop of the cycle samples, as Redis samples them.
+pub(crate) const KEYS_PER_LOOP: usize = 20;
+/// Keys a cycle samples at most, so that a keyspace full of expired keys is worked off
+/// over several ticks rather than in one long pause.
+pub(crate) const KEYS_PER_CYCLE: usize = 10 * KEYS_PER_LOOP;
+pub(crate) const TIME_LIMIT: Duration = Duration::from_millis(25);
+
+/// Ticks a cycle skips at most while it finds few keys to expire.
+const MAX_BACKOFF: u32 = 16;
+/// The percentage of sampled keys found expired below which cycles back off.
+const BACKOFF_STALE_PERC: f64 = 10.0;
+
+/// The state the active expiry cycle carries from one tick to the next.
+#[derive(Debug, Default)]
+pub(crate) struct ExpireCycle(Mutex<State>);
+
+#[derive(Debug, Default)]
+struct State {
+    /// The shard the next cycle starts at, where the previous one stopped.
+    next_shard: usize,
+    backoff: u32,
+    skipping: u32,
+    /// The moving average of the percentage of sampled keys found expired.
+    stale_perc: f64,
+    time_cap_reached: u64,
+}
+
+/// What a cycle did.
+#[derive(Debug, Default)]
+pub(crate) struct CycleRun {
+    pub(crate) sampled: usize,
+    pub(crate) expired: usize,
+    pub(crate) next_shard: usize,
+    pub(crate) timed_out: bool,
+}
+
+impl ExpireCycle {
+    /// The shard to start at when a cycle is due on this tick, which it isn't while the
+    /// cycles back off.
+    pub(crate) fn start(&self) -> Option<usize> {
+        let mut state = self.0.lock().ok()?;
+        if state.skipping > 0 {
+            state.skipping -= 1;
+            return None;
+        }
+        Some(state.next_shard)
+    }
+
+    /// Takes in what the cycle did. Cycles finding less than a tenth of the keys they
+    /// sample expired skip twice as many ticks each time, up to `MAX_BACKOFF`, and run
+    /// every tick again as soon as they find more.
+    pub(crate) fn finish(&self, run: CycleRun) {
+        let Ok(mut state) = self.0.lock() else {
+            return;
+        };
+        state.next_shard = run.next_shard;
+        if run.timed_out {
+            state.time_cap_reached += 1;
+        }
+
+        let perc = if run.sampled == 0 {
+            0.0
+        } else {
+            run.expired as f64 * 100.0 / run.sampled as f64
+        };
+        // Weighted as Redis weighs expired_stale_perc.
+        state.stale_perc = perc * 0.05 + state.stale_perc * 0.95;
+
+        if perc < BACKOFF_STALE_PERC && !run.timed_out {
+            state.backoff = (state.backoff * 2).clamp(1, MAX_BACKOFF);
+            state.skipping = state.backoff;
+        } else {
+            state.backoff = 0;
+        }
+    }
+
+    pub(crate) fn stale_perc(&self) -> f64 {
+        self.0.lock().map(|state| state.stale_perc).unwrap_or(0.0)
+    }
+
+    /// Cycles that stopped at the time limit with keys left to expire.
+    pub(crate) fn time_cap_reached(&self) -> u64 {
+        self.0
+            .lock()
+            .map(|state| state.time_cap_reached)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_backs_off_while_few_keys_expire() {
+        let cycle = ExpireCycle::default();
+        let idle = |next_shard| CycleRun {
+            sampled: 20,
+            expired: 1,
+            next_shard,
+            timed_out: false,
+        };
+
+        assert_eq!(cycle.start(), Some(0));
+        cycle.finish(idle(3));
+        assert_eq!(cycle.start(), None);
+        assert_eq!(cycle.start(), Some(3));
+        cycle.finish(idle(5));
+        let skipped = std::iter::from_fn(|| Some(cycle.start()))
+            .take_while(Option::is_none)
+            .count();
+        assert_eq!(skipped, 2);
+
+        cycle.finish(CycleRun {
+            sampled: 20,
+            expired: 10,
+            next_shard: 7,
+            timed_out: true,
+        });
+        assert_eq!(cycle.start(), Some(7));
+        assert_eq!(cycle.time_cap_reached(), 1);
+        assert!(cycle.stale_perc() > 2.0);
+    }
+}
@@ -0,0 +1,260 @@
+use super::scripts::on_script_thread;
+use super::{OutgoingMessage, RedisError, RedisResult, Resp, Store};
+use crate::script::{self, Library};
+use crate::utils;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+const DUMP_HEADER: &str = "FUNCTIONS1";
+const DUMP_CHECKSUM_LEN: usize = 40;
+
+/// What FUNCTION RESTORE does with the libraries that already exist.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestorePolicy {
+    /// Fails when a library of the payload already exists.
+    Append,
+    /// Replaces the libraries of the same names.
+    Replace,
+    /// Deletes every library first.
+    Flush,
+}
+
+impl RestorePolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Append => "APPEND",
+            Self::Replace => "REPLACE",
+            Self::Flush => "FLUSH",
+        }
+    }
+}
+
+/// Function libraries by name.
+#[derive(Debug, Default)]
+pub(crate) struct Functions {
+    libraries: Mutex<BTreeMap<String, Arc<Library>>>,
+}
+
+impl Functions {
+    /// Loads the libraries saved in an RDB file.
+    pub(crate) fn load_codes(&self, codes: Vec<String>) -> RedisResult<()> {
+        if codes.is_empty() {
+            return Ok(());
+        }
+        let libraries = script::spawn(move || {
+            codes
+                .iter()
+                .map(|code| Library::load(code))
+                .collect::<RedisResult<Vec<Library>>>()
+        })?
+        .join()
+        .map_err(|_| anyhow::anyhow!("ERR script aborted"))??;
+        self.install(libraries, RestorePolicy::Append)
+    }
+
+    /// Adds the libraries all at once, or none of them when one conflicts with the others.
+    pub(crate) fn install(&self, new: Vec<Library>, policy: RestorePolicy) -> RedisResult<()> {
+        let mut libraries = self.lock()?;
+        let mut next = match policy {
+            RestorePolicy::Flush => BTreeMap::new(),
+            _ => libraries.clone(),
+        };
+
+        for library in new {
+            if policy == RestorePolicy::Append && next.contains_key(&library.name) {
+                return Err(
+                    anyhow::anyhow!("ERR Library '{}' already exists", library.name).into(),
+                );
+            }
+            let taken = next
+                .values()
+                .filter(|other| other.name != library.name)
+                .flat_map(|other| other.functions.iter())
+                .find(|f| library.functions.iter().any(|g| g.name == f.name));
+            if let Some(function) = taken {
+                return Err(
+                    anyhow::anyhow!("ERR Function {} already exists", function.name).into(),
+                );
+            }
+            next.insert(library.name.clone(), Arc::new(library));
+        }
+
+        *libraries = next;
+        Ok(())
+    }
+
+    fn lock(&self) -> RedisResult<std::sync::MutexGuard<'_, BTreeMap<String, Arc<Library>>>> {
+        self.libraries
+            .lock()
+            .map_err(|err| RedisError::Lock(err.to_string()))
+    }
+}
+
+impl Store {
+    /// Loads a library and returns its name. Replicas get the library as it's loaded.
+    pub async fn function_load(&self, code: String, replace: bool) -> RedisResult<String> {
+        let loaded = code.clone();
+        let library = on_script_thread(move || Library::load(&loaded)).await??;
+        let name = library.name.clone();
+        let policy = if replace {
+            RestorePolicy::Replace
+        } else {
+            RestorePolicy::Append
+        };
+        self.functions.install(vec![library], policy)?;
+
+        let msg = msg_function(["LOAD", "REPLACE", &code]);
+        self.send_to_replicas(msg).await;
+        Ok(name)
+    }
+
+    /// The libraries whose names match the pattern, in name order.
+    pub(crate) fn function_list(&self, pattern: Option<&str>) -> RedisResult<Vec<Arc<Library>>> {
+        let libraries = self.functions.lock()?;
+        Ok(libraries
+            .values()
+            .filter(|lib| {
+                pattern.is_none_or(|p| utils::glob_match(p.as_bytes(), lib.name.as_bytes(), false))
+            })
+            .cloned()
+            .collect())
+    }
+
+    pub async fn function_delete(&self, name: &str) -> RedisResult<()> {
+        if self.functions.lock()?.remove(name).is_none() {
+            return Err(anyhow::anyhow!("ERR Library not found").into());
+        }
+        self.send_to_replicas(msg_function(["DELETE", name])).await;
+        Ok(())
+    }
+
+    pub async fn function_flush(&self) -> RedisResult<()> {
+        self.functions.lock()?.clear();
+        self.send_to_replicas(msg_function(["FLUSH"])).await;
+        Ok(())
+    }
+
+    /// Serializes the code of every library, followed by its checksum.
+    pub fn function_dump(&self) -> RedisResult<String> {
+        let mut payload = DUMP_HEADER.to_string();
+        for library in self.functions.lock()?.values() {
+            payload.push_str(&format!("\n{}\n{}", library.code.len(), library.code));
+        }
+        let checksum = utils::sha1_hex(payload.as_bytes());
+        payload.push_str(&checksum);
+        Ok(payload)
+    }
+
+    pub async fn function_restore(
+        &self,
+        payload: String,
+        policy: RestorePolicy,
+    ) -> RedisResult<()> {
+        let codes = parse_dump(&payload)
+            .ok_or_else(|| anyhow::anyhow!("ERR payload version or checksum are wrong"))?;
+        let libraries = on_script_thread(move || {
+            codes
+                .iter()
+                .map(|code| Library::load(code))
+                .collect::<RedisResult<Vec<Library>>>()
+        })
+        .await??;
+        self.functions.install(libraries, policy)?;
+
+        let msg = msg_function(["RESTORE", &payload, policy.as_str()]);
+        self.send_to_replicas(msg).await;
+        Ok(())
+    }
+
+    /// Calls a function of the loaded libraries. FCALL_RO only calls functions flagged
+    /// `no-writes`, and those functions fail the write commands they call either way.
+    pub async fn fcall(
+        self: Arc<Self>,
+        function: &str,
+        addr: SocketAddr,
+        keys: Vec<String>,
+        args: Vec<String>,
+        readonly: bool,
+    ) -> RedisResult<Resp> {
+        let library = self
+            .functions
+            .lock()?
+            .values()
+            .find(|lib| lib.functions.iter().any(|f| f.name == function))
+            .cloned()
+            .ok_or_else(|| RedisError::from(anyhow::anyhow!("ERR Function not found")))?;
+
+        let no_writes = library
+            .functions
+            .iter()
+            .any(|f| f.name == function && f.flags.iter().any(|flag| flag == "no-writes"));
+        if readonly && !no_writes {
+            return Err(anyhow::anyhow!(
+                "ERR Can not execute a script with write flag using *_ro command."
+            )
+            .into());
+        }
+
+        let function = function.to_string();
+        self.run_script(addr, no_writes, move |host| {
+            library.call(host, &function, keys, args)
+        })
+        .await
+    }
+
+    /// The code of every library, as saved to RDB files.
+    pub(crate) fn function_codes(&self) -> Vec<String> {
+        self.functions
+            .lock()
+            .map(|libraries| libraries.values().map(|lib| lib.code.clone()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Reads the library codes back from a FUNCTION DUMP payload.
+fn parse_dump(payload: &str) -> Option<Vec<String>> {
+    let split = payload.len().checked_sub(DUMP_CHECKSUM_LEN)?;
+    let (body, checksum) = (payload.get(..split)?, payload.get(split..)?);
+    if utils::sha1_hex(body.as_bytes()) != checksum {
+        return None;
+    }
+
+    let mut rest = body.strip_prefix(DUMP_HEADER)?;
+    let mut codes = vec![];
+    while let Some(entry) = rest.strip_prefix('\n') {
+        let (len, entry) = entry.split_once('\n')?;
+        let len: usize = len.parse().ok()?;
+        codes.push(entry.get(..len)?.to_string());
+        rest = entry.get(len..)?;
+    }
+    rest.is_empty().then_some(codes)
+}
+
+fn msg_function<'a>(args: impl IntoIterator<Item = &'a str>) -> OutgoingMessage {
+    let tokens: Vec<String> = std::iter::once("FUNCTION")
+        .chain(args)
+        .map(String::from)
+        .collect();
+    OutgoingMessage::from(Resp::from(tokens))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_function_dumps() {
+        let codes = ["#!lua name=a\nreturn 1", "#!lua name=b\n-- 12\n"];
+        let mut payload = DUMP_HEADER.to_string();
+        for code in codes {
+            payload.push_str(&format!("\n{}\n{}", code.len(), code));
+        }
+        let checksum = utils::sha1_hex(payload.as_bytes());
+        payload.push_str(&checksum);
+
+        assert_eq!(parse_dump(&payload), Some(codes.map(String::from).to_vec()));
+        assert_eq!(parse_dump(&payload.replace("name=a", "name=c")), None);
+        assert_eq!(parse_dump("FUNCTIONS1"), None);
+    }
+}
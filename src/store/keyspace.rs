@@ -0,0 +1,225 @@
+use super::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, MutexGuard};
+
+const NUM_SHARDS: usize = 16;
+
+/// Values are shared with readers, so reading a key never copies the value. Writers
+/// modify values in place through `Arc::make_mut`, which copies only while a reader
+/// still holds the previous version.
+pub(crate) type Shard = HashMap<String, Arc<Value>>;
+
+/// The keyspace split into shards, each guarded by its own lock, so that commands on
+/// unrelated keys don't wait for each other.
+#[derive(Debug)]
+pub(crate) struct Keyspace {
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl Keyspace {
+    pub(crate) fn new(db: HashMap<String, Value>) -> Self {
+        let mut shards: Vec<Shard> = (0..NUM_SHARDS).map(|_| HashMap::new()).collect();
+        for (key, value) in db {
+            shards[shard_index(&key)].insert(key, Arc::new(value));
+        }
+        Self {
+            shards: shards.into_iter().map(Mutex::new).collect(),
+        }
+    }
+
+    /// Adds the entries of a loaded dataset.
+    pub(crate) async fn load(&self, db: HashMap<String, Value>) {
+        for (key, value) in db {
+            self.shard(&key).await.insert(key, Arc::new(value));
+        }
+    }
+
+    /// Locks the shard the key belongs to.
+    pub(crate) async fn shard(&self, key: &str) -> MutexGuard<'_, Shard> {
+        self.shards[shard_index(key)].lock().await
+    }
+
+    pub(crate) fn shards(&self) -> impl Iterator<Item = &Mutex<Shard>> {
+        self.shards.iter()
+    }
+
+    /// Locks every shard in a fixed order, blocking all keyspace access until the guards drop.
+    pub(crate) async fn lock_all(&self) -> Vec<MutexGuard<'_, Shard>> {
+        let mut guards = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            guards.push(shard.lock().await);
+        }
+        guards
+    }
+
+    /// Goes over every key to count them and the volatile ones.
+    pub(crate) async fn stats(&self) -> KeyspaceStats {
+        let mut stats = KeyspaceStats::default();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().await;
+            stats.keys += shard.len();
+            for exp in shard.values().filter_map(|value| value.expiry()) {
+                stats.expires += 1;
+                stats.expire_sum += unix_millis(exp);
+            }
+        }
+        stats
+    }
+
+    /// At least `count` keys from the cursor on, unless fewer are left, along with the
+    /// cursor to go on from, which is 0 once every key has been visited.
+    ///
+    /// Keys are visited in the order of their hashes and the cursor is the hash to resume
+    /// from, so a key keeps its place however many others are added or removed meanwhile.
+    /// Like the reverse binary cursors Redis uses across table resizes, this has every key
+    /// that exists throughout an iteration returned by it.
+    pub(crate) async fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>) {
+        let mut keys: Vec<(u64, String)> = vec![];
+        for shard in self.shards.iter() {
+            let shard = shard.lock().await;
+            keys.extend(
+                shard
+                    .keys()
+                    .map(|key| (key_hash(key), key))
+                    .filter(|(hash, _)| *hash >= cursor)
+                    .map(|(hash, key)| (hash, key.clone())),
+            );
+        }
+        keys.sort_unstable();
+
+        // Keys sharing a hash go out together, as the cursor can't fall between them.
+        let mut end = count.max(1).min(keys.len());
+        while end < keys.len() && keys[end].0 == keys[end - 1].0 {
+            end += 1;
+        }
+        let next = keys.get(end).map(|(hash, _)| *hash).unwrap_or(0);
+        keys.truncate(end);
+        (next, keys.into_iter().map(|(_, key)| key).collect())
+    }
+
+    pub(crate) async fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = vec![];
+        for shard in self.shards.iter() {
+            keys.extend(shard.lock().await.keys().cloned());
+        }
+        keys
+    }
+}
+
+/// What INFO reports of the keyspace. The UNIX millis the volatile keys expire at are
+/// summed up rather than their TTLs, so that the average TTL stays right as time passes.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct KeyspaceStats {
+    pub(crate) keys: usize,
+    pub(crate) expires: usize,
+    expire_sum: u128,
+}
+
+impl KeyspaceStats {
+    /// The average milliseconds the volatile keys have left, or zero without any.
+    pub(crate) fn avg_ttl(&self, now: u64) -> u64 {
+        if self.expires == 0 {
+            return 0;
+        }
+        let avg = (self.expire_sum / self.expires as u128) as u64;
+        avg.saturating_sub(now)
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+/// The hash of the key, the same in every run.
+fn key_hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn shard_index(key: &str) -> usize {
+    key_hash(key) as usize % NUM_SHARDS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[tokio::test]
+    async fn it_finds_keys_across_shards() {
+        let db: HashMap<String, Value> = (0..100)
+            .map(|n| {
+                let value = Value::String {
+                    value: format!("{n}"),
+                    exp: None,
+                };
+                (format!("key:{n}"), value)
+            })
+            .collect();
+        let keyspace = Keyspace::new(db);
+
+        assert_eq!(keyspace.keys().await.len(), 100);
+        assert!(keyspace.shard("key:42").await.contains_key("key:42"));
+        assert_eq!(keyspace.lock_all().await.len(), NUM_SHARDS);
+    }
+
+    #[tokio::test]
+    async fn it_scans_every_key_kept_while_others_come_and_go() {
+        let value = || Arc::new(Value::List(["x".to_string()].into()));
+        // A fixed LCG, so that a failing run can be replayed.
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut random = move |n: u64| {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % n
+        };
+
+        for round in 0..20 {
+            let keyspace = Keyspace::new(HashMap::new());
+            let kept: Vec<String> = (0..200).map(|n| format!("kept:{n}")).collect();
+            for key in kept.iter() {
+                keyspace.shard(key).await.insert(key.clone(), value());
+            }
+            let mut churn: Vec<String> = vec![];
+            let mut seen: HashSet<String> = HashSet::new();
+            let mut cursor = 0;
+            let mut calls = 0;
+
+            loop {
+                let count = 1 + random(20) as usize;
+                let (next, keys) = keyspace.scan(cursor, count).await;
+                seen.extend(keys);
+                calls += 1;
+
+                // The keyspace grows or shrinks by up to 50 keys between calls.
+                for _ in 0..random(50) {
+                    if random(2) == 0 || churn.is_empty() {
+                        let key = format!("churn:{round}:{}", random(1_000_000));
+                        keyspace.shard(&key).await.insert(key.clone(), value());
+                        churn.push(key);
+                    } else {
+                        let key = churn.swap_remove(random(churn.len() as u64) as usize);
+                        keyspace.shard(&key).await.remove(&key);
+                    }
+                }
+
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
+            }
+
+            assert!(calls > 1);
+            let missed: Vec<&String> = kept.iter().filter(|key| !seen.contains(*key)).collect();
+            assert!(missed.is_empty(), "round {round} missed {missed:?}");
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The percentiles INFO reports for each command, as Redis does by default.
+const PERCENTILES: [f64; 3] = [50.0, 99.0, 99.9];
+
+/// Values below `2^SUB_BUCKET_BITS` get a bucket each, and every larger power of two is
+/// split in half as many buckets, so that a value is off by less than 1/64 of it.
+const SUB_BUCKET_BITS: u32 = 7;
+const HALF: u64 = 1 << (SUB_BUCKET_BITS - 1);
+
+/// Execution times of each command, by the name it has in ACL rules.
+#[derive(Debug, Default)]
+pub(crate) struct Latencies(Mutex<HashMap<&'static str, Histogram>>);
+
+impl Latencies {
+    pub(crate) fn record(&self, name: &'static str, elapsed: Duration) {
+        if let Ok(mut histograms) = self.0.lock() {
+            histograms.entry(name).or_default().record(elapsed);
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        if let Ok(mut histograms) = self.0.lock() {
+            histograms.clear();
+        }
+    }
+
+    /// The lines of the latencystats section of INFO, ordered by command, such as
+    /// `latency_percentiles_usec_get:p50=1.003,p99=2.007,p99.9=5.023`.
+    pub(crate) fn info(&self) -> Vec<String> {
+        let Ok(histograms) = self.0.lock() else {
+            return vec![];
+        };
+        let mut names: Vec<&&str> = histograms.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| {
+                let percentiles: Vec<String> = PERCENTILES
+                    .iter()
+                    .map(|p| {
+                        let nanos = histograms[*name].percentile(*p);
+                        format!("p{p}={:.3}", nanos as f64 / 1000.0)
+                    })
+                    .collect();
+                format!("latency_percentiles_usec_{name}:{}", percentiles.join(","))
+            })
+            .collect()
+    }
+}
+
+/// Counts of nanosecond values in log-linear buckets, as HDR histograms keep them, so
+/// that any percentile comes out within 1/64 of the value at it.
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, elapsed: Duration) {
+        let idx = bucket(elapsed.as_nanos().min(u64::MAX as u128) as u64);
+        if self.counts.len() <= idx {
+            self.counts.resize(idx + 1, 0);
+        }
+        self.counts[idx] += 1;
+        self.total += 1;
+    }
+
+    /// The largest value of the bucket the percentile falls in.
+    fn percentile(&self, p: f64) -> u64 {
+        let rank = ((p / 100.0 * self.total as f64).ceil() as u64).clamp(1, self.total.max(1));
+        let mut seen = 0;
+        for (idx, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return highest(idx);
+            }
+        }
+        0
+    }
+}
+
+/// The bucket of the value: the power of two it's in past the first ones, then its
+/// leading bits.
+fn bucket(value: u64) -> usize {
+    let shift = (64 - value.leading_zeros()).saturating_sub(SUB_BUCKET_BITS);
+    (u64::from(shift) * HALF + (value >> shift)) as usize
+}
+
+/// The largest value going in the bucket.
+fn highest(idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < 2 * HALF {
+        return idx;
+    }
+    let shift = idx / HALF - 1;
+    let leading = idx - shift * HALF;
+    ((u128::from(leading + 1) << shift) - 1).min(u128::from(u64::MAX)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_buckets_values_within_precision() {
+        for value in [0, 1, 127, 128, 129, 1000, 1_000_000, 123_456_789, u64::MAX] {
+            let high = highest(bucket(value));
+            assert!(high >= value, "{value}");
+            assert!(high - value <= value / 64, "{value} {high}");
+        }
+        assert_eq!(bucket(127) + 1, bucket(128));
+    }
+
+    #[test]
+    fn it_reports_percentiles_per_command() {
+        let latencies = Latencies::default();
+        for micros in 1..=1000 {
+            latencies.record("get", Duration::from_micros(micros));
+        }
+        latencies.record("set", Duration::from_nanos(100));
+
+        let info = latencies.info();
+        assert_eq!(info.len(), 2);
+        let (name, percentiles) = info[0].split_once(':').unwrap();
+        assert_eq!(name, "latency_percentiles_usec_get");
+        for (percentile, expected) in percentiles.split(',').zip(["p50", "p99", "p99.9"]) {
+            let (p, usec) = percentile.split_once('=').unwrap();
+            assert_eq!(p, expected);
+            let usec: f64 = usec.parse().unwrap();
+            let exact = p[1..].parse::<f64>().unwrap() * 10.0;
+            assert!(usec >= exact && usec <= exact * 65.0 / 64.0, "{percentile}");
+        }
+        assert_eq!(
+            info[1],
+            "latency_percentiles_usec_set:p50=0.100,p99=0.100,p99.9=0.100"
+        );
+
+        latencies.reset();
+        assert!(latencies.info().is_empty());
+    }
+}
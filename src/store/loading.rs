@@ -0,0 +1,232 @@
+use super::{Aof, Rdb, RedisError, RedisResult, Store};
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Milliseconds between two log lines about the progress of a load.
+const LOG_INTERVAL: u64 = 1000;
+
+/// The progress of loading the dataset at startup. Commands are rejected until it's done.
+#[derive(Debug, Default)]
+pub(crate) struct Loading {
+    loading: AtomicBool,
+    /// When the load started, in unix milliseconds.
+    start: AtomicU64,
+    total_bytes: AtomicU64,
+    loaded_bytes: AtomicU64,
+    last_log: AtomicU64,
+}
+
+impl Loading {
+    fn start(&self, total_bytes: u64, now: u64) {
+        self.total_bytes.store(total_bytes, Ordering::Relaxed);
+        self.loaded_bytes.store(0, Ordering::Relaxed);
+        self.start.store(now, Ordering::Relaxed);
+        self.last_log.store(now, Ordering::Relaxed);
+        self.loading.store(true, Ordering::SeqCst);
+    }
+
+    fn finish(&self) {
+        self.loading.store(false, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_loading(&self) -> bool {
+        self.loading.load(Ordering::SeqCst)
+    }
+
+    /// Takes in the bytes processed so far, along with the keys loaded when they're known,
+    /// and logs them once a second at most.
+    pub(crate) fn progress(&self, loaded_bytes: u64, keys: Option<usize>, now: u64) {
+        self.loaded_bytes.store(loaded_bytes, Ordering::Relaxed);
+        let last = self.last_log.load(Ordering::Relaxed);
+        if now.saturating_sub(last) < LOG_INTERVAL
+            || self
+                .last_log
+                .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+        let keys = keys
+            .map(|keys| format!(", {keys} keys loaded"))
+            .unwrap_or_default();
+        println!(
+            "Loading: {loaded_bytes} of {} bytes processed ({:.2}%){keys}",
+            self.total_bytes.load(Ordering::Relaxed),
+            self.loaded_perc()
+        );
+    }
+
+    pub(crate) fn loaded_bytes(&self) -> u64 {
+        self.loaded_bytes.load(Ordering::Relaxed)
+    }
+
+    fn loaded_perc(&self) -> f64 {
+        let total = self.total_bytes.load(Ordering::Relaxed);
+        let loaded = self.loaded_bytes.load(Ordering::Relaxed);
+        if total == 0 {
+            0.0
+        } else {
+            loaded as f64 * 100.0 / total as f64
+        }
+    }
+
+    /// The lines of INFO persistence about loading. The ETA assumes the rest of the
+    /// dataset loads as fast as what's loaded so far, and is 1 until anything is.
+    pub(crate) fn info(&self, now: u64) -> String {
+        if !self.is_loading() {
+            return "loading:0\r\nasync_loading:0\r\n".into();
+        }
+        let start = self.start.load(Ordering::Relaxed);
+        let total = self.total_bytes.load(Ordering::Relaxed);
+        let loaded = self.loaded_bytes.load(Ordering::Relaxed);
+        let eta = (now.saturating_sub(start) * total.saturating_sub(loaded))
+            .checked_div(loaded)
+            .map(|millis| millis / 1000)
+            .unwrap_or(1);
+        format!(
+            "loading:1\r\nasync_loading:0\r\nloading_start_time:{}\r\n\
+             loading_total_bytes:{total}\r\nloading_loaded_bytes:{loaded}\r\n\
+             loading_loaded_perc:{:.2}\r\nloading_eta_seconds:{eta}\r\n",
+            start / 1000,
+            self.loaded_perc()
+        )
+    }
+}
+
+impl Store {
+    /// Loads the dataset: the RDB file, or with an AOF its base, then the rest of the AOF
+    /// replayed over it. Clients connected meanwhile get LOADING errors.
+    pub async fn load(self: &Arc<Self>) -> RedisResult<()> {
+        let (rdb, replayed) = match self.aof.as_deref().map(Aof::load_files).transpose()? {
+            Some(Some(files)) => files,
+            _ => (Rdb::path(&self.config), vec![]),
+        };
+        let size = |path: &PathBuf| fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+        let total = rdb.iter().chain(replayed.iter()).map(size).sum();
+
+        let start = self.clock.unix_millis();
+        self.loading.start(total, start);
+        let result = async {
+            if let Some(path) = rdb {
+                self.load_rdb(&path).await?;
+            }
+            self.load_aof().await
+        }
+        .await;
+        self.loading.finish();
+        result?;
+
+        let elapsed = self.clock.unix_millis().saturating_sub(start);
+        println!(
+            "DB loaded from disk: {:.3} seconds",
+            elapsed as f64 / 1000.0
+        );
+        Ok(())
+    }
+
+    /// Reads the RDB file off the runtime, then adds its keys and function libraries.
+    async fn load_rdb(self: &Arc<Self>, path: &Path) -> RedisResult<()> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                eprintln!("Not found rdb file");
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let store = Arc::clone(self);
+        let rdb = tokio::task::spawn_blocking(move || {
+            let rdb = Rdb::load(file, |bytes, keys| {
+                store
+                    .loading
+                    .progress(bytes, Some(keys), store.clock.unix_millis())
+            });
+            store
+                .functions
+                .load_codes(rdb.functions().to_vec())
+                .map(|_| rdb)
+        })
+        .await
+        .map_err(|err| RedisError::from(anyhow::anyhow!(err)))??;
+
+        let db = rdb.into_db();
+        println!("Done loading RDB, keys loaded: {}.", db.len());
+        self.keyspace.load(db).await;
+        Ok(())
+    }
+
+    /// Commands are rejected until the dataset is loaded.
+    pub fn is_loading(&self) -> bool {
+        self.loading.is_loading()
+    }
+
+    /// The lines of INFO persistence about loading the dataset.
+    pub fn loading_info(&self) -> String {
+        self.loading.info(self.clock.unix_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Command, CommandMode, Config, Context, Reply, Resp};
+    use std::net::SocketAddr;
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn it_estimates_the_rest_of_the_load() {
+        let loading = Loading::default();
+        assert_eq!(loading.info(0), "loading:0\r\nasync_loading:0\r\n");
+
+        loading.start(1000, 10_000);
+        assert!(loading.info(10_000).contains("loading_eta_seconds:1\r\n"));
+        loading.progress(250, Some(10), 16_000);
+        let info = loading.info(16_000);
+        assert!(info.starts_with("loading:1\r\n"));
+        assert!(info.contains("loading_start_time:10\r\n"));
+        assert!(info.contains("loading_loaded_bytes:250\r\n"));
+        assert!(info.contains("loading_loaded_perc:25.00\r\n"));
+        assert!(info.contains("loading_eta_seconds:18\r\n"));
+
+        loading.finish();
+        assert!(!loading.is_loading());
+    }
+
+    #[tokio::test]
+    async fn it_rejects_commands_while_loading() {
+        let store = Arc::new(Store::new(&Config::new(vec![]).unwrap()).unwrap());
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let client = store.register_client(addr).await;
+        let call = |args: &[&str]| {
+            let store = Arc::clone(&store);
+            let builder = Context::builder(CommandMode::Normal, addr, Arc::clone(&client));
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            async move {
+                let (tx, rx) = oneshot::channel();
+                let cmd = Command::new(Resp::from(args)).unwrap();
+                cmd.execute(store, builder.build(tx)).await;
+                match rx.await.unwrap() {
+                    Reply::Resp(resp) => resp,
+                    Reply::Frames(_) => panic!("unexpected frames"),
+                }
+            }
+        };
+
+        store.loading.start(100, store.clock.unix_millis());
+        assert_eq!(
+            call(&["GET", "a"]).await,
+            Resp::SE("LOADING Redis is loading the dataset in memory".into())
+        );
+        let Resp::BS(Some(info)) = call(&["INFO"]).await else {
+            panic!("INFO should reply while loading");
+        };
+        assert!(info.contains("loading:1\r\n"));
+
+        store.loading.finish();
+        assert_eq!(call(&["GET", "a"]).await, Resp::BS(None));
+    }
+}
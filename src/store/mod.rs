@@ -1,76 +1,582 @@
+mod acl;
+mod aof;
+mod blocking;
+mod client;
+mod cron;
+mod expire;
+mod functions;
+mod keyspace;
+mod latency;
+mod loading;
+mod notify;
 mod replica;
+mod scripts;
+mod snapshot;
+mod state;
+mod stats;
 mod transaction;
+mod versions;
+
+pub use blocking::{Blocked, Unblocked};
+pub use client::Client;
+pub use functions::RestorePolicy;
+pub use notify::{KeyEvent, KeyRemoval, Notification, RemovalReason, Subscription};
+pub use state::ServerState;
+
+pub(crate) use aof::Manifest;
 
 use super::{
+    clock::{Clock, SystemClock},
+    cluster::{self, Cluster, MigrateTarget},
+    hook::{CommandHook, Hooks},
     message::OutgoingMessage,
     rdb::Rdb,
-    value::{RedisStream, StreamEntry, StreamEntryId, StreamEntryIdFactor, Value},
-    Command, Config, RedisError, RedisResult, Resp,
+    uring::{self, Uring},
+    utils,
+    value::{
+        BloomFilter, CuckooFilter, DeletePolicy, ExpireCond, Json, JsonPath, RedisHash,
+        RedisStream, SetCond, StreamEntry, StreamEntryId, StreamEntryIdFactor, TopK, Value,
+        ValueType, VectorQuery, VectorSet,
+    },
+    Command, Config, IoBackend, LogLevel, RedisError, RedisResult, Resp,
 };
-use replica::{Replica, WaitSignal};
+use acl::Acl;
+use aof::Aof;
+use blocking::BlockedClients;
+use bytes::Bytes;
+use expire::{CycleRun, ExpireCycle};
+use functions::Functions;
+use keyspace::{Keyspace, KeyspaceStats, Shard};
+use loading::Loading;
+use notify::Notifier;
+use replica::{ReplicaInfo, Replicas, WaitSignal};
+use scripts::Scripts;
+use snapshot::{SaveState, Snapshot};
+use stats::Stats;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
-use std::time::{Duration, SystemTime};
-use std::{collections::HashMap, time::UNIX_EPOCH};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::{
-    mpsc::{self, Sender},
-    Mutex, MutexGuard,
+    broadcast,
+    mpsc::{error::TrySendError, Sender},
+    Mutex, MutexGuard, Notify, RwLock, RwLockReadGuard, RwLockWriteGuard,
 };
 use transaction::Transaction;
+use versions::Versions;
+
+const REPL_ID_LEN: usize = 40;
+/// Removals a receiver of `subscribe_removals` may lag behind before it misses some.
+const REMOVALS_CAPACITY: usize = 1024;
+const QUICKLIST_PACKED_THRESHOLD: usize = 1 << 30;
+/// The most fields HRANDFIELD picks with a negative count, which may pick a field again.
+const RANDOM_PICKS_MAX: usize = 1 << 20;
 
 #[derive(Debug)]
-pub struct Store(Mutex<Inner>);
+pub struct Store {
+    clock: Arc<dyn Clock>,
+    keyspace: Keyspace,
+    config: Config,
+    replicas: Replicas,
+    notifier: Notifier,
+    removals: broadcast::Sender<KeyRemoval>,
+    versions: Versions,
+    /// Held shared by every write and alone by EXEC, so that no write of another client
+    /// lands between the check of the keys a transaction watches and its commands.
+    exec_lock: RwLock<()>,
+    /// The keyspace stats last measured, along with the last version given then.
+    keyspace_stats: std::sync::Mutex<Option<(u64, KeyspaceStats)>>,
+    blocked: BlockedClients,
+    expire_cycle: ExpireCycle,
+    loading: Loading,
+    save_state: Arc<SaveState>,
+    stats: Stats,
+    scripts: Scripts,
+    functions: Functions,
+    acl: Acl,
+    /// Present only when cluster mode is enabled.
+    cluster: Option<Arc<Mutex<Cluster>>>,
+    /// Present only when appendonly is enabled.
+    aof: Option<Arc<Aof>>,
+    /// Present only when connections do their I/O through io_uring.
+    uring: Option<Uring>,
+    /// Whether connections log the frames they read and write. It starts as configured
+    /// and is switched by CONFIG SET.
+    trace_proto: AtomicBool,
+    /// The discriminant of the configured LogLevel, read on every frame.
+    loglevel: AtomicU8,
+    maxmemory: AtomicUsize,
+    /// Bytes past which an element of a list is kept in a plain node of its own rather
+    /// than packed in a listpack. DEBUG QUICKLIST-PACKED-THRESHOLD changes it.
+    quicklist_packed_threshold: AtomicUsize,
+    /// The bytes the keyspace took when the cron last measured it.
+    used_memory: AtomicUsize,
+    hooks: Hooks,
+    state: Mutex<Inner>,
+    /// Set by SHUTDOWN while it saves the dataset.
+    shutting_down: AtomicBool,
+    /// Notified by SHUTDOWN once the server should stop accepting connections.
+    shutdown: Notify,
+}
 
+/// Server state other than the keyspace.
 #[derive(Debug)]
 struct Inner {
-    db: HashMap<String, Value>,
-    config: Config,
-    replicas: HashMap<SocketAddr, Replica>,
     ack: usize,
-    stream_subscribers: HashMap<String, Vec<Sender<()>>>,
     transactions: HashMap<SocketAddr, Transaction>,
+    /// Where a replica writes to the master, once the handshake is done.
+    master_link: Option<Sender<Bytes>>,
+    /// The keys each client watches, with their versions when it started to.
+    watched: HashMap<SocketAddr, HashMap<String, u64>>,
+    repl_id: String,
+    active_expire: bool,
+    clients: HashMap<SocketAddr, Arc<Client>>,
+    /// Where the RDB file is saved, which CONFIG SET may change.
+    dir: Option<String>,
+    dbfilename: Option<String>,
+    /// The save points and the password, which a reload of the config may change.
+    save: Vec<(u64, u64)>,
+    requirepass: Option<String>,
 }
 
 impl Store {
     pub fn new(config: &Config) -> RedisResult<Self> {
-        Ok(Self(Mutex::new(Inner::new(config)?)))
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(config: &Config, clock: Arc<dyn Clock>) -> RedisResult<Self> {
+        // The dataset is loaded afterwards by `load`.
+        let aof = config.appendonly.then(|| Arc::new(Aof::new(config)));
+        let uring = (config.io_backend == IoBackend::Uring)
+            .then(|| Uring::new(uring::ENTRIES))
+            .transpose()?;
+        let replicas = Replicas::new();
+        let acl = Acl::new(config.requirepass.as_deref());
+        if let Some(path) = config.aclfile.as_deref() {
+            acl.load(path, config.requirepass.as_deref())?;
+        }
+        Ok(Self {
+            save_state: Arc::new(SaveState::new(clock.unix_millis())),
+            stats: Stats::default(),
+            scripts: Scripts::default(),
+            functions: Functions::default(),
+            acl,
+            cluster: config.cluster_enabled.then(|| {
+                let cluster = match config.master {
+                    Some(master) => Cluster::replica(config.socket_addr(), master),
+                    None => Cluster::new(config.socket_addr()),
+                };
+                Arc::new(Mutex::new(
+                    cluster.with_repl_offset(replicas.shared_offset()),
+                ))
+            }),
+            aof,
+            uring,
+            trace_proto: AtomicBool::new(config.trace_proto),
+            loglevel: AtomicU8::new(config.loglevel as u8),
+            maxmemory: AtomicUsize::new(config.maxmemory),
+            quicklist_packed_threshold: AtomicUsize::new(QUICKLIST_PACKED_THRESHOLD),
+            used_memory: AtomicUsize::new(0),
+            hooks: Hooks::default(),
+            clock,
+            keyspace: Keyspace::new(HashMap::new()),
+            config: config.clone(),
+            replicas,
+            notifier: Notifier::default(),
+            removals: broadcast::channel(REMOVALS_CAPACITY).0,
+            versions: Versions::default(),
+            exec_lock: RwLock::new(()),
+            keyspace_stats: std::sync::Mutex::new(None),
+            blocked: BlockedClients::default(),
+            expire_cycle: ExpireCycle::default(),
+            loading: Loading::default(),
+            state: Mutex::new(Inner::new(config)),
+            shutting_down: AtomicBool::new(false),
+            shutdown: Notify::new(),
+        })
     }
 
     pub async fn port(&self) -> u16 {
-        let inner = self.lock().await;
-        inner.config.port
+        self.config.port
+    }
+
+    /// The ring connections do their I/O through, unless they use the runtime's sockets.
+    pub(crate) fn uring(&self) -> Option<&Uring> {
+        self.uring.as_ref()
+    }
+
+    /// The size connections start reading with.
+    pub fn io_buf_size(&self) -> usize {
+        self.config.io_buf_size
+    }
+
+    pub fn proto_max_bulk_len(&self) -> usize {
+        self.config.proto_max_bulk_len
+    }
+
+    pub fn client_query_buffer_limit(&self) -> usize {
+        self.config.client_query_buffer_limit
+    }
+
+    /// How long a client may take to send the rest of a command, if limited.
+    pub fn client_query_timeout(&self) -> Option<Duration> {
+        let millis = self.config.client_query_timeout;
+        (millis > 0).then(|| Duration::from_millis(millis))
+    }
+
+    pub async fn keys(&self, pattern: &str) -> Vec<String> {
+        let mut keys = self.keyspace.keys().await;
+        keys.retain(|key| utils::glob_match(pattern.as_bytes(), key.as_bytes(), false));
+        keys
+    }
+
+    /// Iterates the keys, about `count` keys per call, starting and ending with cursor 0.
+    /// A key there from the first call to the last is returned by one of them at least.
+    pub async fn scan(
+        &self,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+        type_name: Option<&str>,
+    ) -> (u64, Vec<String>) {
+        let (next, keys) = self.keyspace.scan(cursor, count).await;
+
+        let mut found: Vec<String> = vec![];
+        for key in keys {
+            if pattern.is_some_and(|p| !utils::glob_match(p.as_bytes(), key.as_bytes(), false)) {
+                continue;
+            }
+            match self.get(&key).await {
+                Some(v) if type_name.is_none_or(|t| t.eq_ignore_ascii_case(v.type_name())) => {
+                    found.push(key);
+                }
+                _ => {}
+            }
+        }
+        (next, found)
     }
 
-    pub async fn keys(&self) -> Vec<String> {
+    /// Configuration parameters whose names match the pattern.
+    pub async fn config_get(&self, pattern: &str) -> Vec<(&'static str, Option<String>)> {
         let inner = self.lock().await;
-        inner.db.keys().map(|v| v.to_string()).collect()
+        self.config
+            .params()
+            .into_iter()
+            .filter(|(name, _)| utils::glob_match(pattern.as_bytes(), name.as_bytes(), true))
+            .map(|(name, value)| match name {
+                "trace-proto" => {
+                    let enabled = if self.trace_proto() { "yes" } else { "no" };
+                    (name, Some(enabled.into()))
+                }
+                "dir" => (name, inner.dir.clone()),
+                "dbfilename" => (name, inner.dbfilename.clone()),
+                "save" => (name, Some(utils::format_save(&inner.save))),
+                "loglevel" => (name, Some(self.loglevel().as_str().into())),
+                "maxmemory" => (name, Some(self.maxmemory().to_string())),
+                "requirepass" => (name, Some(inner.requirepass.clone().unwrap_or_default())),
+                _ => (name, value),
+            })
+            .collect()
+    }
+
+    /// Sets the parameters as CONFIG SET does, for a client connected from `addr`. Every
+    /// value is checked before any is set, so that a bad one leaves them all as they were.
+    pub async fn config_set(
+        &self,
+        params: &[(String, String)],
+        addr: SocketAddr,
+    ) -> RedisResult<()> {
+        let mut trace_proto = None;
+        let mut dir = None;
+        let mut dbfilename = None;
+
+        for (name, value) in params {
+            let failed = |reason: &str| -> RedisError {
+                anyhow::anyhow!(
+                    "CONFIG SET failed (possibly related to argument '{name}') - {reason}"
+                )
+                .into()
+            };
+            match name.to_lowercase().as_str() {
+                "trace-proto" => {
+                    trace_proto = Some(match value.to_lowercase().as_str() {
+                        "yes" => true,
+                        "no" => false,
+                        _ => return Err(failed("argument must be 'yes' or 'no'")),
+                    });
+                }
+                "dir" | "dbfilename" if !self.config.enable_protected_configs.allows(addr) => {
+                    return Err(failed("can't set protected config"));
+                }
+                "dir" => {
+                    if !Path::new(value).is_dir() {
+                        return Err(failed("No such file or directory"));
+                    }
+                    dir = Some(value.clone());
+                }
+                "dbfilename" => {
+                    if value.is_empty() || value.contains('/') {
+                        return Err(failed("dbfilename can't be a path, just a filename"));
+                    }
+                    dbfilename = Some(value.clone());
+                }
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Unknown option or number of arguments for CONFIG SET - '{name}'"
+                    )
+                    .into())
+                }
+            }
+        }
+
+        if let Some(enabled) = trace_proto {
+            self.trace_proto.store(enabled, Ordering::Relaxed);
+        }
+        let mut inner = self.lock().await;
+        if dir.is_some() {
+            inner.dir = dir;
+        }
+        if dbfilename.is_some() {
+            inner.dbfilename = dbfilename;
+        }
+        Ok(())
     }
 
-    pub async fn get(&self, key: &str) -> Option<Value> {
+    /// Reads the config file again and applies the parameters which can change while the
+    /// server runs: loglevel, save, maxmemory and requirepass. Clients authenticated
+    /// already stay so when the password changes.
+    pub async fn reload_config(&self) -> RedisResult<()> {
+        let Some(path) = self.config.config_file.as_deref() else {
+            return Err(anyhow::anyhow!("ERR The server is running without a config file").into());
+        };
+        let config = self.config.reload()?;
+
+        self.loglevel
+            .store(config.loglevel as u8, Ordering::Relaxed);
+        self.maxmemory.store(config.maxmemory, Ordering::Relaxed);
         let mut inner = self.lock().await;
-        match inner.db.get(key) {
-            Some(v) => {
-                if v.expired() {
-                    inner.db.remove(key);
-                    None
+        inner.save = config.save;
+        // The default user keeps the password the ACL file gave it unless requirepass
+        // itself changed.
+        if inner.requirepass != config.requirepass {
+            self.acl.set_requirepass(config.requirepass.as_deref())?;
+            inner.requirepass = config.requirepass;
+        }
+        println!("Configuration reloaded from {path}");
+        Ok(())
+    }
+
+    pub fn loglevel(&self) -> LogLevel {
+        LogLevel::ALL[self.loglevel.load(Ordering::Relaxed) as usize]
+    }
+
+    /// Whether messages of the level are logged.
+    pub fn log_enabled(&self, level: LogLevel) -> bool {
+        level >= self.loglevel()
+    }
+
+    pub fn maxmemory(&self) -> usize {
+        self.maxmemory.load(Ordering::Relaxed)
+    }
+
+    /// Whether the keyspace takes more than maxmemory, as of the last measure of the
+    /// cron, so that commands adding to it are refused.
+    pub fn over_maxmemory(&self) -> bool {
+        let maxmemory = self.maxmemory();
+        maxmemory > 0 && self.used_memory.load(Ordering::Relaxed) > maxmemory
+    }
+
+    /// Measures the bytes the keys and their values take, as their serialized length.
+    pub(crate) async fn measure_used_memory(&self) {
+        let mut used = 0;
+        for shard in self.keyspace.shards() {
+            let shard = shard.lock().await;
+            used += shard
+                .iter()
+                .map(|(key, value)| key.len() + value.serialized_len())
+                .sum::<usize>();
+        }
+        self.used_memory.store(used, Ordering::Relaxed);
+    }
+
+    /// Registers a hook run around every command dispatched from now on.
+    pub fn add_hook(&self, hook: Arc<dyn CommandHook>) {
+        self.hooks.add(hook);
+    }
+
+    pub(crate) fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    pub fn trace_proto(&self) -> bool {
+        self.trace_proto.load(Ordering::Relaxed)
+    }
+
+    pub async fn get(&self, key: &str) -> Option<Arc<Value>> {
+        let expired = {
+            let mut shard = self.keyspace.shard(key).await;
+            match shard.get(key) {
+                Some(v) if !v.expired(self.clock.now()) => return Some(Arc::clone(v)),
+                _ => self.evict_expired(&mut shard, key),
+            }
+        };
+
+        if expired {
+            self.propagate_expired(key).await;
+        }
+        None
+    }
+
+    /// Removes the value at the key if it has expired, so that a write finds the key
+    /// missing rather than the dead value, and tells whether it did. Replicas wait for
+    /// the DEL from the master instead of expiring locally. A removal is propagated with
+    /// `propagate_expired` once the shard is unlocked.
+    fn evict_expired(&self, shard: &mut Shard, key: &str) -> bool {
+        let expired = shard.get(key).is_some_and(|v| v.expired(self.clock.now()));
+        if expired && !self.is_replica() {
+            shard.remove(key);
+            return true;
+        }
+        false
+    }
+
+    async fn propagate_expired(&self, key: &str) {
+        self.notify(key, KeyEvent::Write);
+        self.notify_removal(key, RemovalReason::Expired);
+        self.send_to_replicas(msg_del(&[key.to_string()])).await;
+    }
+
+    pub async fn del(&self, keys: &[String]) -> i64 {
+        let mut removed: Vec<String> = vec![];
+        let mut num: i64 = 0;
+
+        for key in keys {
+            let mut shard = self.keyspace.shard(key).await;
+            if let Some(v) = shard.remove(key.as_str()) {
+                if self.is_replica() || !v.expired(self.clock.now()) {
+                    num += 1;
                 } else {
-                    Some(v.clone())
+                    self.notify_removal(key, RemovalReason::Expired);
                 }
+                removed.push(key.to_string());
             }
-            _ => None,
         }
+
+        for key in removed.iter() {
+            self.notify(key, KeyEvent::Write);
+        }
+
+        if !removed.is_empty() {
+            self.send_to_replicas(msg_del(&removed)).await;
+        }
+        num
     }
 
-    pub async fn get_string(&self, key: &str) -> Option<String> {
-        self.get(key).await.and_then(|v| match v {
-            Value::String { value, .. } => Some(value),
-            _ => None,
-        })
+    /// Samples keys with an expiry and deletes the expired ones, going on with a shard
+    /// while more than a quarter of the sampled keys turn out to be expired. A cycle
+    /// starts at the shard the previous one stopped at, and samples a bounded number of
+    /// keys; cycles finding few expired keys skip ticks.
+    /// Replicas never expire keys by themselves; they receive DELs from the master.
+    pub async fn active_expire_cycle(&self) {
+        if !self.active_expire().await || self.is_replica() {
+            return;
+        }
+        let Some(first) = self.expire_cycle.start() else {
+            return;
+        };
+
+        let started = Instant::now();
+        let shards: Vec<_> = self.keyspace.shards().collect();
+        let mut run = CycleRun::default();
+
+        for (i, shard) in shards
+            .iter()
+            .enumerate()
+            .cycle()
+            .skip(first)
+            .take(shards.len())
+        {
+            run.next_shard = (i + 1) % shards.len();
+            // The keys are gone over once per shard, from a random one on.
+            let mut volatile: Vec<String> = shard
+                .lock()
+                .await
+                .iter()
+                .filter(|(_, v)| v.has_expiry())
+                .map(|(k, _)| k.clone())
+                .collect();
+            let offset = utils::random_u64() as usize % volatile.len().max(1);
+            volatile.rotate_left(offset);
+
+            let mut rest = volatile.as_slice();
+            let mut stale = true;
+            while stale && !rest.is_empty() && run.sampled < expire::KEYS_PER_CYCLE {
+                let len = expire::KEYS_PER_LOOP
+                    .min(expire::KEYS_PER_CYCLE - run.sampled)
+                    .min(rest.len());
+                let (batch, next) = rest.split_at(len);
+                rest = next;
+                let expired = expire_batch(&mut *shard.lock().await, batch, self.clock.now());
+                run.sampled += batch.len();
+                run.expired += expired.len();
+                stale = expired.len() * 4 > expire::KEYS_PER_LOOP;
+
+                if !expired.is_empty() {
+                    println!("Actively expired {} keys", expired.len());
+                    for key in expired.iter() {
+                        self.notify(key, KeyEvent::Write);
+                        self.notify_removal(key, RemovalReason::Expired);
+                    }
+                    self.send_to_replicas(msg_del(&expired)).await;
+                }
+
+                if started.elapsed() >= expire::TIME_LIMIT {
+                    run.timed_out = stale;
+                    self.expire_cycle.finish(run);
+                    return;
+                }
+            }
+            if run.sampled >= expire::KEYS_PER_CYCLE {
+                break;
+            }
+        }
+        self.expire_cycle.finish(run);
+    }
+
+    /// The moving percentage of keys the active expiry cycle finds expired among those
+    /// it samples, and the number of cycles it stopped at the time limit.
+    pub fn expire_cycle_stats(&self) -> (f64, u64) {
+        (
+            self.expire_cycle.stale_perc(),
+            self.expire_cycle.time_cap_reached(),
+        )
+    }
+
+    /// Runs `f` on the data of the value at the key. A missing key gives `None` and a
+    /// value of another type than `V` gives WRONGTYPE, the same for every command.
+    pub async fn with_value<V, T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&V) -> T,
+    ) -> RedisResult<Option<T>>
+    where
+        V: ValueType + ?Sized,
+    {
+        let value = self.get(key).await;
+        self.stats.record_lookup(value.is_some());
+        match value {
+            Some(value) => value.cast().map(|v| Some(f(v))),
+            None => Ok(None),
+        }
     }
 
     pub async fn set_string(&self, key: &str, value: String, exp: Option<u64>) {
+        let exp = exp.map(|n| self.clock.now() + Duration::from_millis(n));
         let v = Value::String {
             value: value.clone(),
-            exp: exp.map(|n| SystemTime::now() + Duration::from_millis(n)),
+            exp,
         };
         self.set(key, v).await;
 
@@ -79,25 +585,142 @@ impl Store {
     }
 
     pub async fn increment(&self, key: &str) -> RedisResult<i64> {
-        let (value, exp) = match self.get(key).await {
-            Some(Value::String { value, exp }) => {
-                let num = value.parse::<i64>().map_err(|_| {
-                    RedisError::from(anyhow::anyhow!(
-                        "ERR value is not an integer or out of range"
-                    ))
-                })?;
-                let value = (num + 1).to_string();
-                let exp = exp.map(|time| {
-                    time.duration_since(UNIX_EPOCH)
-                        .expect("SystemTime before UNIX EPOCH!")
-                        .as_millis() as u64
-                });
-                (value, exp)
+        let (num, exp) = {
+            let mut shard = self.keyspace.shard(key).await;
+            let now = self.clock.now();
+            let (num, exp) = match shard
+                .get(key)
+                .map(AsRef::as_ref)
+                .filter(|v| !v.expired(now))
+            {
+                Some(Value::String { value, exp }) => {
+                    let num = value
+                        .parse::<i64>()
+                        .ok()
+                        .and_then(|num| num.checked_add(1))
+                        .ok_or(RedisError::NotInteger)?;
+                    (num, *exp)
+                }
+                Some(_) => return Err(RedisError::WrongType),
+                None => (1, None),
+            };
+            let value = Value::String {
+                value: num.to_string(),
+                exp,
+            };
+            shard.insert(key.into(), Arc::new(value));
+            (num, exp)
+        };
+        self.notify(key, KeyEvent::Write);
+
+        let msg = msg_set_string(key, num.to_string(), exp);
+        self.send_to_replicas(msg).await;
+        Ok(num)
+    }
+
+    /// The bytes of the string between the offsets, both included. A missing key reads
+    /// as an empty string. Strings are kept and replied as UTF-8, not as raw bytes, so a
+    /// character the offsets split is replied as U+FFFD.
+    pub async fn getrange(&self, key: &str, start: i64, end: i64) -> RedisResult<String> {
+        let range = self
+            .with_value(key, |value: &String| {
+                let bytes = &value.as_bytes()[utils::byte_range(value.len(), start, end)];
+                String::from_utf8_lossy(bytes).into_owned()
+            })
+            .await?;
+        Ok(range.unwrap_or_default())
+    }
+
+    /// Overwrites the string from the byte offset, padding it with zero bytes up to the
+    /// offset, and returns its new length. Strings are kept as UTF-8, so a write which
+    /// would split a character of the string is refused.
+    pub async fn setrange(&self, key: &str, offset: i64, value: &str) -> RedisResult<i64> {
+        let offset =
+            usize::try_from(offset).map_err(|_| anyhow::anyhow!("ERR offset is out of range"))?;
+        self.write_string(key, value, |_| offset).await
+    }
+
+    /// Appends the value to the string, creating it, and returns its new length.
+    pub async fn append(&self, key: &str, value: &str) -> RedisResult<i64> {
+        self.write_string(key, value, |len| len).await
+    }
+
+    /// Writes the value into the string at the offset picked from its current length,
+    /// and returns the new length. The string only grows once it's known to fit.
+    async fn write_string(
+        &self,
+        key: &str,
+        value: &str,
+        offset: impl FnOnce(usize) -> usize,
+    ) -> RedisResult<i64> {
+        let (updated, exp) = {
+            let mut shard = self.keyspace.shard(key).await;
+            let now = self.clock.now();
+            let (current, exp) = match shard
+                .get(key)
+                .map(AsRef::as_ref)
+                .filter(|v| !v.expired(now))
+            {
+                Some(Value::String { value, exp }) => (value.as_str(), *exp),
+                Some(_) => return Err(RedisError::WrongType),
+                None => ("", None),
+            };
+            // An empty value changes nothing, nor creates the key.
+            if value.is_empty() {
+                return Ok(current.len() as i64);
+            }
+
+            let offset = offset(current.len());
+            let end = offset.saturating_add(value.len());
+            let splits = |i: usize| i < current.len() && !current.is_char_boundary(i);
+            if splits(offset) || splits(end) {
+                return Err(anyhow::anyhow!(
+                    "ERR the write would split a character, and strings are kept as UTF-8"
+                )
+                .into());
+            }
+            self.check_string_growth(current.len(), end)?;
+            let mut bytes = current.as_bytes().to_vec();
+            if bytes.len() < end {
+                bytes.resize(end, 0);
             }
-            _ => ("1".to_string(), None),
+            bytes[offset..end].copy_from_slice(value.as_bytes());
+            let updated = String::from_utf8(bytes).expect("writes on characters keep UTF-8");
+            let stored = Value::String {
+                value: updated.clone(),
+                exp,
+            };
+            shard.insert(key.into(), Arc::new(stored));
+            (updated, exp)
         };
-        self.set_string(key, value.clone(), exp).await;
-        value.parse().map_err(RedisError::from)
+        self.notify(key, KeyEvent::Write);
+
+        let len = updated.len() as i64;
+        let msg = msg_set_string(key, updated, exp);
+        self.send_to_replicas(msg).await;
+        Ok(len)
+    }
+
+    /// Fails when a string of `len` bytes growing to `new_len` would be longer than
+    /// proto-max-bulk-len, or would take the keyspace over maxmemory. The growth let
+    /// through counts as used until the cron measures again, so that writes in between
+    /// can't add up past the limit. Replicas leave maxmemory to their master, whose
+    /// writes they must take.
+    fn check_string_growth(&self, len: usize, new_len: usize) -> RedisResult<()> {
+        if new_len > self.config.proto_max_bulk_len {
+            return Err(anyhow::anyhow!(
+                "ERR string exceeds maximum allowed size (proto-max-bulk-len)"
+            )
+            .into());
+        }
+        let growth = new_len.saturating_sub(len);
+        let used = self.used_memory.fetch_add(growth, Ordering::Relaxed);
+        let maxmemory = self.maxmemory();
+        if maxmemory > 0 && growth > 0 && !self.is_replica() && used + growth > maxmemory {
+            self.used_memory.fetch_sub(growth, Ordering::Relaxed);
+            return Err(RedisError::Oom);
+        }
+        Ok(())
     }
 
     pub async fn start_queuing(&self, addr: SocketAddr) {
@@ -116,6 +739,23 @@ impl Store {
         }
     }
 
+    /// Marks the transaction of the client as aborted, as a command was rejected while
+    /// queued.
+    pub async fn abort_queuing(&self, addr: SocketAddr) {
+        let mut inner = self.lock().await;
+        if let Some(transaction) = inner.transactions.get_mut(&addr) {
+            transaction.abort();
+        }
+    }
+
+    pub async fn queuing_aborted(&self, addr: SocketAddr) -> bool {
+        let inner = self.lock().await;
+        inner
+            .transactions
+            .get(&addr)
+            .is_some_and(Transaction::aborted)
+    }
+
     pub async fn drain_trans(&self, addr: SocketAddr) -> Vec<Command> {
         let mut inner = self.lock().await;
         inner
@@ -125,165 +765,1714 @@ impl Store {
             .unwrap_or_default()
     }
 
+    /// Watches the keys for the next EXEC of the client, keeping the versions a key had
+    /// when it was first watched.
+    pub async fn watch(&self, addr: SocketAddr, keys: &[String]) {
+        let mut inner = self.lock().await;
+        let watched = inner.watched.entry(addr).or_default();
+        for key in keys {
+            watched
+                .entry(key.clone())
+                .or_insert_with(|| self.versions.watch(key));
+        }
+    }
+
+    /// Forgets the keys the client watches, and tells whether one of them changed since.
+    pub async fn unwatch(&self, addr: SocketAddr) -> bool {
+        let watched = self.lock().await.watched.remove(&addr);
+        watched.is_some_and(|keys| self.release_watched(keys))
+    }
+
+    /// Held while a write runs, which waits for the EXEC running meanwhile to finish.
+    pub(crate) async fn write_guard(&self) -> RwLockReadGuard<'_, ()> {
+        self.exec_lock.read().await
+    }
+
+    /// Held while EXEC checks the keys watched and runs the commands queued, for which
+    /// it waits until no write runs.
+    pub(crate) async fn exec_guard(&self) -> RwLockWriteGuard<'_, ()> {
+        self.exec_lock.write().await
+    }
+
+    /// Stops keeping the versions of the keys for the client which watched them, and
+    /// tells whether one of them changed since.
+    fn release_watched(&self, keys: HashMap<String, u64>) -> bool {
+        let mut changed = false;
+        for (key, version) in keys {
+            changed |= self.versions.get(&key) != version;
+            self.versions.unwatch(&key);
+        }
+        changed
+    }
+
+    /// Changes every time the key is written, deleted or expired, so that holding on to
+    /// a version tells whether the key changed since without reading its value. Only the
+    /// keys clients watch are versioned alone; any other key changes version whenever
+    /// some key is written.
+    pub fn version(&self, key: &str) -> u64 {
+        self.versions.get(key)
+    }
+
     pub async fn set_stream(
         &self,
         key: &str,
         id: String,
         values: HashMap<String, String>,
     ) -> RedisResult<StreamEntryId> {
-        let id_factor = StreamEntryIdFactor::new(&id)?;
+        let id_factor = StreamEntryIdFactor::new_at(&id, self.clock.unix_millis())?;
 
-        let mut stream = self.get_stream(key).await?;
-        let id = id_factor.try_into_id(&stream)?;
-        let entry = StreamEntry::new(id, values);
-        stream.push(entry.clone())?;
+        let mut shard = self.keyspace.shard(key).await;
+        let expired = self.evict_expired(&mut shard, key);
+        let entry = add_stream_entry(&mut shard, key, id_factor, values);
+        drop(shard);
 
-        let value = Value::Stream(stream);
-        self.set(key, value).await;
+        if expired {
+            self.propagate_expired(key).await;
+        }
+        let entry = entry?;
+        let id = entry.id();
 
-        let msg = msg_set_stream(key, entry);
+        let msg = msg_set_stream(key, &entry);
         self.send_to_replicas(msg).await;
-        self.notify_subscribers(key).await;
+        self.notify(key, KeyEvent::StreamAdd);
 
         Ok(id)
     }
 
-    pub async fn query_stream(
+    /// Deletes stream entries as XDELEX does. A missing key has none of them.
+    pub async fn delete_stream_entries(
         &self,
         key: &str,
-        start: String,
-        end: String,
-    ) -> RedisResult<Vec<StreamEntry>> {
-        let start = StreamEntryIdFactor::new(&start)?;
-        let end = StreamEntryIdFactor::new(&end)?;
+        ids: &[String],
+        policy: DeletePolicy,
+    ) -> RedisResult<Vec<i64>> {
+        let ids = parse_entry_ids(ids)?;
+        let replies = self
+            .update_stream(key, |stream| stream.delete(&ids, policy))
+            .await?
+            .unwrap_or_else(|| vec![-1; ids.len()]);
 
-        let stream = self.get_stream(key).await?;
-        let mut entries: Vec<StreamEntry> = vec![];
-        for entry in stream.query(start, end)? {
-            entries.push(entry.clone());
+        if replies.contains(&1) {
+            self.notify(key, KeyEvent::Write);
+            let mut tokens = vec!["XDELEX".to_string(), key.into(), format!("{policy}")];
+            tokens.extend(ids_tokens(&ids));
+            self.send_to_replicas(Resp::from(tokens).into()).await;
         }
-        Ok(entries)
-    }
-
-    pub async fn find_stream(&self, key: &str, start: String) -> RedisResult<Option<StreamEntry>> {
-        let start = StreamEntryIdFactor::new(&start)?;
-        self.get_stream(key).await?.find(start).map(|v| v.cloned())
+        Ok(replies)
     }
 
-    pub async fn parse_find_stream_args(
+    /// Acknowledges stream entries in the group and deletes them, as XACKDEL does.
+    pub async fn ack_delete_stream_entries(
         &self,
-        args: Vec<(String, String)>,
-    ) -> RedisResult<Vec<(String, String)>> {
-        let mut responses: Vec<(String, String)> = vec![];
-        for (key, start) in args {
-            if start.as_str() == "$" {
-                let start = self
-                    .get_stream(&key)
-                    .await?
-                    .last_id()
-                    .map(|v| format!("{v}"))
-                    .unwrap_or("0-0".to_string());
-                responses.push((key, start));
-            } else {
-                responses.push((key, start));
-            }
+        key: &str,
+        group: &str,
+        ids: &[String],
+        policy: DeletePolicy,
+    ) -> RedisResult<Vec<i64>> {
+        let ids = parse_entry_ids(ids)?;
+        let replies = self
+            .update_stream(key, |stream| stream.ack_delete(group, &ids, policy))
+            .await?
+            .flatten()
+            .ok_or_else(|| {
+                anyhow::anyhow!("NOGROUP No such key '{key}' or consumer group '{group}'")
+            })?;
+
+        if replies.iter().any(|reply| *reply != -1) {
+            self.notify(key, KeyEvent::Write);
+            let mut tokens = vec![
+                "XACKDEL".to_string(),
+                key.into(),
+                group.into(),
+                format!("{policy}"),
+            ];
+            tokens.extend(ids_tokens(&ids));
+            self.send_to_replicas(Resp::from(tokens).into()).await;
         }
-        Ok(responses)
+        Ok(replies)
     }
 
-    pub async fn rdb_dir(&self) -> Option<String> {
-        let inner = self.lock().await;
-        inner.config.dir.clone()
+    /// Runs `f` on the stream at the key to change it. A missing key gives `None`.
+    async fn update_stream<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut RedisStream) -> T,
+    ) -> RedisResult<Option<T>> {
+        self.update_value(key, None::<fn() -> Value>, f).await
     }
 
-    pub async fn rdb_dbfilename(&self) -> Option<String> {
-        let inner = self.lock().await;
-        inner.config.dbfilename.clone()
+    /// What JSON.GET replies for the paths, written out. A missing key gives `None`.
+    pub async fn json_get(&self, key: &str, paths: &[JsonPath]) -> RedisResult<Option<String>> {
+        let json = self
+            .with_value(key, |json: &Json| json.select(paths))
+            .await?
+            .transpose()?;
+        Ok(json.map(|json| json.to_string()))
     }
 
-    pub async fn role(&self) -> &str {
-        let inner = self.lock().await;
-        match inner.config.master {
-            Some(_) => "slave",
-            None => "master",
+    /// Sets the value at the path as JSON.SET does, and tells whether anything was set.
+    /// A new key can only be set at the root.
+    pub async fn json_set(
+        &self,
+        key: &str,
+        path: &JsonPath,
+        value: Json,
+        cond: Option<SetCond>,
+    ) -> RedisResult<bool> {
+        let mut shard = self.keyspace.shard(key).await;
+        let expired = self.evict_expired(&mut shard, key);
+        let doc = set_json(&mut shard, key, path, value, cond);
+        drop(shard);
+
+        if expired {
+            self.propagate_expired(key).await;
         }
-    }
+        let Some(doc) = doc? else {
+            return Ok(false);
+        };
 
-    pub fn repl_id(&self) -> &str {
-        "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb"
+        self.notify(key, KeyEvent::Write);
+        self.send_to_replicas(Resp::from(json_set_tokens(key, &doc)).into())
+            .await;
+        Ok(true)
     }
 
-    pub fn repl_offset(&self) -> usize {
-        0
+    /// Deletes the values at the path as JSON.DEL does, and tells how many there were.
+    /// Deleting the root deletes the key.
+    pub async fn json_del(&self, key: &str, path: &JsonPath) -> RedisResult<usize> {
+        if path.is_root() {
+            let exists = self.with_value(key, |_: &Json| ()).await?.is_some();
+            let deleted = if exists {
+                self.del(&[key.into()]).await
+            } else {
+                0
+            };
+            return Ok(deleted as usize);
+        }
+
+        let deleted = self
+            .update_json(key, |json| {
+                let deleted = json.delete(path);
+                (deleted > 0).then(|| (deleted, json.to_string()))
+            })
+            .await?
+            .flatten();
+        let Some((deleted, doc)) = deleted else {
+            return Ok(0);
+        };
+
+        self.notify(key, KeyEvent::Write);
+        self.send_to_replicas(Resp::from(json_set_tokens(key, &doc)).into())
+            .await;
+        Ok(deleted)
     }
 
-    pub fn rdb(&self, _offset: usize) -> Vec<u8> {
-        vec![
-            0x52, 0x45, 0x44, 0x49, 0x53, 0x30, 0x30, 0x31, 0x31, 0xfa, 0x09, 0x72, 0x65, 0x64,
-            0x69, 0x73, 0x2d, 0x76, 0x65, 0x72, 0x05, 0x37, 0x2e, 0x32, 0x2e, 0x30, 0xfa, 0x0a,
-            0x72, 0x65, 0x64, 0x69, 0x73, 0x2d, 0x62, 0x69, 0x74, 0x73, 0xc0, 0x40, 0xfa, 0x05,
-            0x63, 0x74, 0x69, 0x6d, 0x65, 0xc2, 0x6d, 0x08, 0xbc, 0x65, 0xfa, 0x08, 0x75, 0x73,
-            0x65, 0x64, 0x2d, 0x6d, 0x65, 0x6d, 0xc2, 0xb0, 0xc4, 0x10, 0x00, 0xfa, 0x08, 0x61,
-            0x6f, 0x66, 0x2d, 0x62, 0x61, 0x73, 0x65, 0xc0, 0x00, 0xff, 0xf0, 0x6e, 0x3b, 0xfe,
-            0xc0, 0xff, 0x5a, 0xa2,
-        ]
+    /// Adds the number to the numbers at the path as JSON.NUMINCRBY does, and gives what
+    /// each match became, or `None` for the ones which aren't numbers.
+    pub async fn json_numincrby(
+        &self,
+        key: &str,
+        path: &JsonPath,
+        by: &Json,
+    ) -> RedisResult<Vec<Option<Json>>> {
+        let (results, doc) = self
+            .update_json(key, |json| {
+                let results = json.incr_by(path, by)?;
+                let doc = results
+                    .iter()
+                    .any(Option::is_some)
+                    .then(|| json.to_string());
+                Ok::<_, RedisError>((results, doc))
+            })
+            .await?
+            .ok_or_else(|| {
+                anyhow::anyhow!("ERR could not perform this operation on a key that doesn't exist")
+            })??;
+
+        if let Some(doc) = doc {
+            self.notify(key, KeyEvent::Write);
+            self.send_to_replicas(Resp::from(json_set_tokens(key, &doc)).into())
+                .await;
+        }
+        Ok(results)
     }
 
-    pub async fn subscribe(&self, addr: SocketAddr, tx: Sender<Vec<u8>>) {
-        let mut inner = self.lock().await;
-        inner.add_replica(addr, tx);
+    /// Runs `f` on the JSON document at the key to change it. A missing key gives `None`.
+    async fn update_json<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut Json) -> T,
+    ) -> RedisResult<Option<T>> {
+        self.update_value(key, None::<fn() -> Value>, f).await
     }
 
-    pub async fn ack_offset(&self) -> usize {
-        let inner = self.lock().await;
-        inner.ack
+    /// Creates an empty sketch at the key, as the RESERVE commands do. `tokens` are the
+    /// command replicas create it with.
+    pub async fn reserve(&self, key: &str, value: Value, tokens: Vec<String>) -> RedisResult<()> {
+        let expired = {
+            let mut shard = self.keyspace.shard(key).await;
+            let expired = self.evict_expired(&mut shard, key);
+            if shard.contains_key(key) {
+                return Err(anyhow::anyhow!("ERR item exists").into());
+            }
+            shard.insert(key.into(), Arc::new(value));
+            expired
+        };
+        if expired {
+            self.propagate_expired(key).await;
+        }
+        self.notify(key, KeyEvent::Write);
+        self.send_to_replicas(Resp::from(tokens).into()).await;
+        Ok(())
     }
 
-    pub async fn add_ack_offset(&self, size: usize) {
-        let mut inner = self.lock().await;
-        inner.ack += size;
+    /// Adds the items to the Bloom filter, creating it as BF.ADD does, and tells for each
+    /// whether it is new or why it couldn't be added.
+    pub async fn bf_add(&self, key: &str, items: &[String]) -> RedisResult<Vec<RedisResult<bool>>> {
+        let new =
+            || Value::Bloom(BloomFilter::new(0.01, 100, Some(2)).expect("the default filter fits"));
+        let added = self
+            .update_value(key, Some(new), |filter: &mut BloomFilter| {
+                items
+                    .iter()
+                    .map(|item| filter.add(item))
+                    .collect::<Vec<_>>()
+            })
+            .await?
+            .unwrap_or_default();
+
+        // Only the new items change the filter.
+        let mut tokens = vec!["BF.MADD".to_string(), key.into()];
+        tokens.extend(
+            items
+                .iter()
+                .zip(added.iter())
+                .filter(|(_, added)| matches!(added, Ok(true)))
+                .map(|(item, _)| item.clone()),
+        );
+        if tokens.len() > 2 {
+            self.notify(key, KeyEvent::Write);
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        Ok(added)
     }
 
-    pub async fn num_of_replicas(&self) -> usize {
-        let inner = self.lock().await;
-        inner.num_of_replicas()
+    /// Whether each item may have been added to the Bloom filter. A missing key has none.
+    pub async fn bf_exists(&self, key: &str, items: &[String]) -> RedisResult<Vec<bool>> {
+        let found = self
+            .with_value(key, |filter: &BloomFilter| {
+                items.iter().map(|item| filter.contains(item)).collect()
+            })
+            .await?;
+        Ok(found.unwrap_or_else(|| vec![false; items.len()]))
     }
 
-    pub async fn receive_replica_ack(&self, addr: SocketAddr, ack: usize) {
-        let mut inner = self.lock().await;
-        if let Some(replica) = inner.replicas.get_mut(&addr) {
-            replica.receive_ack(ack).await;
+    /// Adds the item to the cuckoo filter, creating it as CF.ADD does. With `nx`, an item
+    /// which may be in the filter already isn't added. Tells whether it was added.
+    pub async fn cf_add(&self, key: &str, item: &str, nx: bool) -> RedisResult<bool> {
+        let new =
+            || Value::Cuckoo(CuckooFilter::new(1024, 2, 20, 1).expect("the default filter fits"));
+        let added = self
+            .update_value(key, Some(new), |filter: &mut CuckooFilter| {
+                if nx && filter.contains(item) {
+                    return Ok(false);
+                }
+                filter.add(item).map(|_| true)
+            })
+            .await?
+            .transpose()?
+            .unwrap_or_default();
+
+        if added {
+            self.notify(key, KeyEvent::Write);
+            let tokens = vec!["CF.ADD".to_string(), key.into(), item.into()];
+            self.send_to_replicas(Resp::from(tokens).into()).await;
         }
+        Ok(added)
     }
 
-    pub async fn wait(&self, num_replicas: usize, exp: u64) -> i64 {
-        // NOTE:
-        // You have to release lock not to block any other actions.
-        let (mut synced, mut rx) = {
-            let mut inner = self.lock().await;
-            let unsynced = inner.replicas.iter().filter_map(is_unsynced).count();
+    /// How many times the item may be in the cuckoo filter. A missing key has none.
+    pub async fn cf_count(&self, key: &str, item: &str) -> RedisResult<usize> {
+        let count = self
+            .with_value(key, |filter: &CuckooFilter| filter.count(item))
+            .await?;
+        Ok(count.unwrap_or_default())
+    }
 
-            let (tx, rx) = mpsc::channel::<WaitSignal>(unsynced + 1);
-            for replica in inner.replicas.iter_mut().filter_map(is_unsynced_mut) {
-                let tx = tx.clone();
-                replica.add_wait_callback(tx).await;
-                replica.send_getack().await;
-            }
+    /// Deletes the item from the cuckoo filter once, and tells whether it was there.
+    pub async fn cf_del(&self, key: &str, item: &str) -> RedisResult<bool> {
+        let deleted = self
+            .update_value(key, None::<fn() -> Value>, |filter: &mut CuckooFilter| {
+                filter.delete(item)
+            })
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("ERR Not found"))?;
 
-            tokio::spawn(async move {
-                tokio::time::sleep(Duration::from_millis(exp)).await;
-                if tx.send(WaitSignal::Timeout).await.is_err() {
-                    eprintln!("Receiver dropped before timeout");
-                }
-            });
+        if deleted {
+            self.notify(key, KeyEvent::Write);
+            let tokens = vec!["CF.DEL".to_string(), key.into(), item.into()];
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        Ok(deleted)
+    }
 
-            let synced = inner.replicas.iter().filter_map(is_synced).count();
-            (synced, rx)
-        };
+    /// Counts the items as many more times in the top-k, and gives the items each one
+    /// pushed out of the top.
+    pub async fn topk_incrby(
+        &self,
+        key: &str,
+        items: &[(String, u32)],
+    ) -> RedisResult<Vec<Option<String>>> {
+        let expelled = self
+            .update_value(key, None::<fn() -> Value>, |topk: &mut TopK| {
+                items
+                    .iter()
+                    .map(|(item, by)| topk.incr_by(item, *by))
+                    .collect()
+            })
+            .await?
+            .ok_or_else(topk_missing)?;
 
-        if synced >= num_replicas {
-            return synced as i64;
+        self.notify(key, KeyEvent::Write);
+        let mut tokens = vec!["TOPK.INCRBY".to_string(), key.into()];
+        for (item, by) in items {
+            tokens.extend([item.clone(), by.to_string()]);
         }
+        self.send_to_replicas(Resp::from(tokens).into()).await;
+        Ok(expelled)
+    }
+
+    /// Runs `f` on the top-k at the key to read it.
+    pub async fn with_topk<T>(&self, key: &str, f: impl FnOnce(&TopK) -> T) -> RedisResult<T> {
+        self.with_value(key, f).await?.ok_or_else(topk_missing)
+    }
+
+    /// Runs `f` on the value at the key to change it. A missing key gets the value `new`
+    /// makes, when there is one, and otherwise gives `None`.
+    async fn update_value<V, T>(
+        &self,
+        key: &str,
+        new: Option<impl FnOnce() -> Value>,
+        f: impl FnOnce(&mut V) -> T,
+    ) -> RedisResult<Option<T>>
+    where
+        V: ValueType,
+    {
+        let mut shard = self.keyspace.shard(key).await;
+        let expired = self.evict_expired(&mut shard, key);
+        let out = update_in(&mut shard, key, new, f);
+        drop(shard);
+
+        if expired {
+            self.propagate_expired(key).await;
+        }
+        out
+    }
+
+    /// Pushes the elements one after another to the head of the list, or to its tail,
+    /// creating the list, and returns its length.
+    pub async fn push(&self, key: &str, elements: &[String], head: bool) -> RedisResult<usize> {
+        let new = || Value::List(VecDeque::new());
+        let len = self
+            .update_value(key, Some(new), |list: &mut VecDeque<String>| {
+                for element in elements {
+                    if head {
+                        list.push_front(element.clone());
+                    } else {
+                        list.push_back(element.clone());
+                    }
+                }
+                list.len()
+            })
+            .await?
+            .unwrap_or_default();
+
+        self.notify(key, KeyEvent::ListPush);
+        let mut tokens = vec![if head { "LPUSH" } else { "RPUSH" }.to_string(), key.into()];
+        tokens.extend_from_slice(elements);
+        self.send_to_replicas(Resp::from(tokens).into()).await;
+        Ok(len)
+    }
+
+    /// Pops up to `count` elements off the head of the list, or off its tail, deleting the
+    /// list once it's empty. A missing key gives `None`.
+    pub async fn pop(
+        &self,
+        key: &str,
+        count: usize,
+        head: bool,
+    ) -> RedisResult<Option<Vec<String>>> {
+        let popped = self
+            .edit_list(key, |list| {
+                let count = count.min(list.len());
+                if head {
+                    list.drain(..count).collect::<Vec<_>>()
+                } else {
+                    list.drain(list.len() - count..).rev().collect()
+                }
+            })
+            .await?;
+        let Some(popped) = popped else {
+            return Ok(None);
+        };
+
+        if !popped.is_empty() {
+            self.notify(key, KeyEvent::Write);
+            let tokens = vec![
+                if head { "LPOP" } else { "RPOP" }.to_string(),
+                key.into(),
+                popped.len().to_string(),
+            ];
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        Ok(Some(popped))
+    }
+
+    /// Runs `f` on the list under the lock of its shard, deleting the list if `f` leaves it
+    /// empty. A missing key gives `None`.
+    async fn edit_list<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut VecDeque<String>) -> T,
+    ) -> RedisResult<Option<T>> {
+        let mut shard = self.keyspace.shard(key).await;
+        let evicted = self.evict_expired(&mut shard, key);
+        let out = edit_list_in(&mut shard, key, f);
+        drop(shard);
+
+        if evicted {
+            self.propagate_expired(key).await;
+        }
+        out
+    }
+
+    /// Inserts the element before or after the first occurrence of the pivot and returns
+    /// the new length, which is -1 without the pivot and 0 without the list.
+    pub async fn linsert(
+        &self,
+        key: &str,
+        before: bool,
+        pivot: &str,
+        element: &str,
+    ) -> RedisResult<i64> {
+        let len = self
+            .edit_list(key, |list| {
+                let index = list.iter().position(|e| e == pivot)?;
+                list.insert(if before { index } else { index + 1 }, element.into());
+                Some(list.len())
+            })
+            .await?;
+        let Some(len) = len else {
+            return Ok(0);
+        };
+        let Some(len) = len else {
+            return Ok(-1);
+        };
+
+        self.notify(key, KeyEvent::Write);
+        let tokens = vec![
+            "LINSERT".to_string(),
+            key.into(),
+            if before { "BEFORE" } else { "AFTER" }.into(),
+            pivot.into(),
+            element.into(),
+        ];
+        self.send_to_replicas(Resp::from(tokens).into()).await;
+        Ok(len as i64)
+    }
+
+    /// Replaces the element at the index, counted from the tail when negative.
+    pub async fn lset(&self, key: &str, index: i64, element: &str) -> RedisResult<()> {
+        self.edit_list(key, |list| {
+            let at = if index < 0 {
+                index.checked_add(list.len() as i64)
+            } else {
+                Some(index)
+            };
+            let slot = at
+                .and_then(|at| usize::try_from(at).ok())
+                .and_then(|at| list.get_mut(at))
+                .ok_or(anyhow::anyhow!("ERR index out of range"))?;
+            *slot = element.into();
+            Ok::<_, RedisError>(())
+        })
+        .await?
+        .ok_or(RedisError::NoSuchKey)??;
+
+        self.notify(key, KeyEvent::Write);
+        let tokens = vec![
+            "LSET".to_string(),
+            key.into(),
+            index.to_string(),
+            element.into(),
+        ];
+        self.send_to_replicas(Resp::from(tokens).into()).await;
+        Ok(())
+    }
+
+    /// Removes up to `count` occurrences of the element from the head on, or from the tail
+    /// on when `count` is negative, or all of them when it's 0. Returns how many it removed.
+    pub async fn lrem(&self, key: &str, count: i64, element: &str) -> RedisResult<usize> {
+        let removed = self
+            .edit_list(key, |list| {
+                let limit = match count.unsigned_abs() {
+                    0 => usize::MAX,
+                    n => usize::try_from(n).unwrap_or(usize::MAX),
+                };
+                let mut indexes: Vec<usize> = list
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, e)| *e == element)
+                    .map(|(i, _)| i)
+                    .collect();
+                if count < 0 {
+                    indexes.reverse();
+                }
+                indexes.truncate(limit);
+                indexes.sort_unstable();
+                for index in indexes.iter().rev() {
+                    list.remove(*index);
+                }
+                indexes.len()
+            })
+            .await?
+            .unwrap_or_default();
+
+        if removed > 0 {
+            self.notify(key, KeyEvent::Write);
+            let tokens = vec![
+                "LREM".to_string(),
+                key.into(),
+                count.to_string(),
+                element.into(),
+            ];
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        Ok(removed)
+    }
+
+    /// Keeps the elements between the indexes, both included, and drops the others.
+    pub async fn ltrim(&self, key: &str, start: i64, stop: i64) -> RedisResult<()> {
+        let trimmed = self
+            .edit_list(key, |list| {
+                let len = list.len();
+                let range = utils::index_range(len, start, stop);
+                list.truncate(range.end);
+                list.drain(..range.start);
+                list.len() < len
+            })
+            .await?
+            .unwrap_or_default();
+
+        if trimmed {
+            self.notify(key, KeyEvent::Write);
+            let tokens = vec![
+                "LTRIM".to_string(),
+                key.into(),
+                start.to_string(),
+                stop.to_string(),
+            ];
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        Ok(())
+    }
+
+    /// The elements of the list between the indexes, both included. A missing key reads
+    /// as an empty list.
+    pub async fn lrange(&self, key: &str, start: i64, stop: i64) -> RedisResult<Vec<String>> {
+        let range = self
+            .with_value(key, |list: &VecDeque<String>| {
+                list.range(utils::index_range(list.len(), start, stop))
+                    .cloned()
+                    .collect()
+            })
+            .await?;
+        Ok(range.unwrap_or_default())
+    }
+
+    pub async fn llen(&self, key: &str) -> RedisResult<usize> {
+        let len = self.with_value(key, VecDeque::len).await?;
+        Ok(len.unwrap_or_default())
+    }
+
+    /// Runs `f` on the hash under its shard lock, creating the hash with `create`, after
+    /// removing the fields past their expiry, and deletes the hash left without fields.
+    /// Replicas apply the writes of their master to every field kept and leave the
+    /// expired ones to its HDEL, so they get 0 as the time for `f`.
+    async fn edit_hash<T>(
+        &self,
+        key: &str,
+        create: bool,
+        f: impl FnOnce(&mut RedisHash, u64) -> T,
+    ) -> RedisResult<Option<T>> {
+        let now = (!self.is_replica()).then(|| self.clock.unix_millis());
+        let mut shard = self.keyspace.shard(key).await;
+        let evicted = self.evict_expired(&mut shard, key);
+        let out = edit_hash_in(&mut shard, key, create, now, f);
+        drop(shard);
+
+        if evicted {
+            self.propagate_expired(key).await;
+        }
+        let Some((out, expired)) = out? else {
+            return Ok(None);
+        };
+        self.propagate_expired_fields(key, expired).await;
+        Ok(Some(out))
+    }
+
+    /// Removes the fields of the hash past their expiry, as reads find them. The hash is
+    /// only copied when there are any.
+    async fn expire_hash_fields(&self, key: &str) -> RedisResult<()> {
+        if self.is_replica() {
+            return Ok(());
+        }
+        let now = self.clock.unix_millis();
+        let expired = {
+            let mut shard = self.keyspace.shard(key).await;
+            let Some(value) = shard.get_mut(key) else {
+                return Ok(());
+            };
+            if !value.cast::<RedisHash>()?.has_expired(now) {
+                return Ok(());
+            }
+            let hash = RedisHash::cast_mut(Arc::make_mut(value)).ok_or(RedisError::WrongType)?;
+            let expired = hash.remove_expired(now);
+            if hash.is_empty() {
+                shard.remove(key);
+            }
+            expired
+        };
+        self.propagate_expired_fields(key, expired).await;
+        Ok(())
+    }
+
+    async fn propagate_expired_fields(&self, key: &str, fields: Vec<String>) {
+        if fields.is_empty() {
+            return;
+        }
+        self.notify(key, KeyEvent::Write);
+        let tokens: Vec<String> = ["HDEL".to_string(), key.into()]
+            .into_iter()
+            .chain(fields)
+            .collect();
+        self.send_to_replicas(Resp::from(tokens).into()).await;
+    }
+
+    /// Adds `by` to the integer at the field of the hash, creating both, and returns the
+    /// sum.
+    pub async fn hincrby(&self, key: &str, field: &str, by: i64) -> RedisResult<i64> {
+        let num = self
+            .edit_hash(key, true, |hash, now| {
+                let current = match hash.get(field, now) {
+                    Some(value) => value
+                        .parse::<i64>()
+                        .map_err(|_| anyhow::anyhow!("ERR hash value is not an integer"))?,
+                    None => 0,
+                };
+                let num = current
+                    .checked_add(by)
+                    .ok_or_else(|| anyhow::anyhow!("ERR increment or decrement would overflow"))?;
+                hash.insert(field.into(), num.to_string());
+                Ok::<_, RedisError>(num)
+            })
+            .await?
+            .unwrap_or(Ok(by))?;
+
+        self.notify(key, KeyEvent::Write);
+        let tokens = vec![
+            "HINCRBY".to_string(),
+            key.into(),
+            field.into(),
+            by.to_string(),
+        ];
+        self.send_to_replicas(Resp::from(tokens).into()).await;
+        Ok(num)
+    }
+
+    /// Adds `by` to the number at the field of the hash, creating both, and returns the
+    /// sum as it's kept. Replicas get the sum itself, as HSET, so that they keep the same
+    /// digits, and the expiry of the field again, which HSET removes.
+    pub async fn hincrbyfloat(&self, key: &str, field: &str, by: f64) -> RedisResult<String> {
+        let (num, expiry) = self
+            .edit_hash(key, true, |hash, now| {
+                let current = hash.get(field, now).map_or("0", String::as_str);
+                let sum = current
+                    .parse::<f64>()
+                    .map_err(|_| anyhow::anyhow!("ERR hash value is not a float"))?
+                    + by;
+                if !sum.is_finite() {
+                    return Err(
+                        anyhow::anyhow!("ERR increment would produce NaN or Infinity").into(),
+                    );
+                }
+                let num = utils::format_float(sum);
+                hash.insert(field.into(), num.clone());
+                Ok::<_, RedisError>((num, hash.expiry(field)))
+            })
+            .await?
+            .expect("the hash is created")?;
+
+        self.notify(key, KeyEvent::Write);
+        let tokens = vec!["HSET".to_string(), key.into(), field.into(), num.clone()];
+        self.send_to_replicas(Resp::from(tokens).into()).await;
+        if let Some(at) = expiry {
+            let tokens = hpexpireat_tokens(key, at, vec![field.to_string()]);
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        Ok(num)
+    }
+
+    /// Sets the fields of the hash, creating it, and returns how many are new. The fields
+    /// set lose their expiry.
+    pub async fn hset(&self, key: &str, pairs: &[(String, String)]) -> RedisResult<usize> {
+        let added = self
+            .edit_hash(key, true, |hash, now| {
+                pairs
+                    .iter()
+                    .filter(|(field, value)| {
+                        let added = hash.get(field, now).is_none();
+                        hash.persist(field);
+                        hash.insert(field.clone(), value.clone());
+                        added
+                    })
+                    .count()
+            })
+            .await?
+            .unwrap_or_default();
+
+        self.notify(key, KeyEvent::Write);
+        let mut tokens = vec!["HSET".to_string(), key.into()];
+        tokens.extend(
+            pairs
+                .iter()
+                .flat_map(|(field, value)| [field.clone(), value.clone()]),
+        );
+        self.send_to_replicas(Resp::from(tokens).into()).await;
+        Ok(added)
+    }
+
+    /// Removes the fields from the hash and returns how many it had.
+    pub async fn hdel(&self, key: &str, fields: &[String]) -> RedisResult<usize> {
+        let removed: Vec<String> = self
+            .edit_hash(key, false, |hash, _| {
+                fields
+                    .iter()
+                    .filter(|field| hash.remove(field).is_some())
+                    .cloned()
+                    .collect()
+            })
+            .await?
+            .unwrap_or_default();
+        if !removed.is_empty() {
+            self.notify(key, KeyEvent::Write);
+            let tokens: Vec<String> = ["HDEL".to_string(), key.into()]
+                .into_iter()
+                .chain(removed.iter().cloned())
+                .collect();
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        Ok(removed.len())
+    }
+
+    /// Every field of the hash not past its expiry, along with its value.
+    pub async fn hgetall(&self, key: &str) -> RedisResult<Vec<(String, String)>> {
+        self.expire_hash_fields(key).await?;
+        let now = self.clock.unix_millis();
+        let fields = self
+            .with_value(key, |hash: &RedisHash| {
+                hash.iter(now)
+                    .map(|(field, value)| (field.clone(), value.clone()))
+                    .collect()
+            })
+            .await?;
+        Ok(fields.unwrap_or_default())
+    }
+
+    /// Random fields of the hash along with their values: one without a count, up to
+    /// `count` distinct ones with a positive count, and exactly `-count` which may repeat
+    /// with a negative one. A missing key gives `None`.
+    pub async fn hrandfield(
+        &self,
+        key: &str,
+        count: Option<i64>,
+    ) -> RedisResult<Option<Vec<(String, String)>>> {
+        // Picks of a negative count repeat fields, so their number is bounded before
+        // anything is allocated for them.
+        let picks = match count {
+            Some(count) if count < 0 => Some(
+                usize::try_from(count.unsigned_abs())
+                    .ok()
+                    .filter(|picks| *picks <= RANDOM_PICKS_MAX)
+                    .ok_or_else(|| anyhow::anyhow!("ERR value is out of range"))?,
+            ),
+            _ => None,
+        };
+        self.expire_hash_fields(key).await?;
+        let now = self.clock.unix_millis();
+        let fields = self
+            .with_value(key, |hash: &RedisHash| {
+                hash.iter(now)
+                    .map(|(field, value)| (field.clone(), value.clone()))
+                    .collect::<Vec<(String, String)>>()
+            })
+            .await?;
+        Ok(fields.map(|mut fields| {
+            if fields.is_empty() {
+                return fields;
+            }
+            if let Some(picks) = picks {
+                return (0..picks)
+                    .map(|_| fields[utils::random_u64() as usize % fields.len()].clone())
+                    .collect();
+            }
+            let count = (count.unwrap_or(1) as usize).min(fields.len());
+            // A partial Fisher-Yates shuffle picks the distinct fields.
+            for i in 0..count {
+                let j = i + utils::random_u64() as usize % (fields.len() - i);
+                fields.swap(i, j);
+            }
+            fields.truncate(count);
+            fields
+        }))
+    }
+
+    /// Makes the fields of the hash expire at the unix milliseconds, as HPEXPIREAT does,
+    /// and replies for each field: -2 without it, 0 when the condition isn't met, 1 once
+    /// set, and 2 when the time has passed and the field is deleted instead.
+    pub async fn hexpire(
+        &self,
+        key: &str,
+        at: u64,
+        cond: Option<ExpireCond>,
+        fields: &[String],
+    ) -> RedisResult<Vec<i64>> {
+        let mut set: Vec<String> = vec![];
+        let mut deleted: Vec<String> = vec![];
+        let replies = self
+            .edit_hash(key, false, |hash, now| {
+                fields
+                    .iter()
+                    .map(|field| {
+                        if hash.get(field, now).is_none() {
+                            -2
+                        } else if !cond.is_none_or(|cond| cond.allows(hash.expiry(field), at)) {
+                            0
+                        } else if at <= now {
+                            hash.remove(field);
+                            deleted.push(field.clone());
+                            2
+                        } else {
+                            hash.set_expiry(field, at);
+                            set.push(field.clone());
+                            1
+                        }
+                    })
+                    .collect()
+            })
+            .await?
+            .unwrap_or_else(|| vec![-2; fields.len()]);
+
+        if !set.is_empty() || !deleted.is_empty() {
+            self.notify(key, KeyEvent::Write);
+        }
+        // Replicas get absolute times, so that the fields expire there when they do here.
+        if !set.is_empty() {
+            let tokens = hpexpireat_tokens(key, at, set);
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        if !deleted.is_empty() {
+            let tokens: Vec<String> = ["HDEL".to_string(), key.into()]
+                .into_iter()
+                .chain(deleted)
+                .collect();
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        Ok(replies)
+    }
+
+    /// The milliseconds each field of the hash has left to live: -2 without the field and
+    /// -1 when it doesn't expire.
+    pub async fn hpttl(&self, key: &str, fields: &[String]) -> RedisResult<Vec<i64>> {
+        self.expire_hash_fields(key).await?;
+        let now = self.clock.unix_millis();
+        let ttls = self
+            .with_value(key, |hash: &RedisHash| {
+                fields
+                    .iter()
+                    .map(|field| match (hash.get(field, now), hash.expiry(field)) {
+                        (None, _) => -2,
+                        (Some(_), None) => -1,
+                        (Some(_), Some(at)) => (at - now) as i64,
+                    })
+                    .collect()
+            })
+            .await?;
+        Ok(ttls.unwrap_or_else(|| vec![-2; fields.len()]))
+    }
+
+    /// Removes the expiry of each field of the hash, replying -2 without the field, -1
+    /// when it has no expiry and 1 once removed.
+    pub async fn hpersist(&self, key: &str, fields: &[String]) -> RedisResult<Vec<i64>> {
+        let mut persisted: Vec<String> = vec![];
+        let replies = self
+            .edit_hash(key, false, |hash, now| {
+                fields
+                    .iter()
+                    .map(|field| {
+                        if hash.get(field, now).is_none() {
+                            -2
+                        } else if hash.persist(field) {
+                            persisted.push(field.clone());
+                            1
+                        } else {
+                            -1
+                        }
+                    })
+                    .collect()
+            })
+            .await?
+            .unwrap_or_else(|| vec![-2; fields.len()]);
+
+        if !persisted.is_empty() {
+            self.notify(key, KeyEvent::Write);
+            let tokens: Vec<String> = [
+                "HPERSIST".to_string(),
+                key.into(),
+                "FIELDS".into(),
+                persisted.len().to_string(),
+            ]
+            .into_iter()
+            .chain(persisted)
+            .collect();
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        Ok(replies)
+    }
+
+    /// Adds the members to the set, creating it, and returns how many weren't in it.
+    pub async fn sadd(&self, key: &str, members: &[String]) -> RedisResult<usize> {
+        let new = || Value::Set(HashSet::new());
+        let added: Vec<String> = self
+            .update_value(key, Some(new), |set: &mut HashSet<String>| {
+                members
+                    .iter()
+                    .filter(|member| set.insert(member.to_string()))
+                    .cloned()
+                    .collect()
+            })
+            .await?
+            .unwrap_or_default();
+
+        if !added.is_empty() {
+            self.notify(key, KeyEvent::Write);
+            let tokens: Vec<String> = ["SADD".to_string(), key.into()]
+                .into_iter()
+                .chain(added.iter().cloned())
+                .collect();
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        Ok(added.len())
+    }
+
+    /// Removes the members from the set, deleting it once empty, and returns how many
+    /// were in it.
+    pub async fn srem(&self, key: &str, members: &[String]) -> RedisResult<usize> {
+        let mut shard = self.keyspace.shard(key).await;
+        let expired = self.evict_expired(&mut shard, key);
+        let removed = remove_members(&mut shard, key, members);
+        drop(shard);
+
+        if expired {
+            self.propagate_expired(key).await;
+        }
+        let removed = removed?;
+
+        if !removed.is_empty() {
+            self.notify(key, KeyEvent::Write);
+            let tokens: Vec<String> = ["SREM".to_string(), key.into()]
+                .into_iter()
+                .chain(removed.iter().cloned())
+                .collect();
+            self.send_to_replicas(Resp::from(tokens).into()).await;
+        }
+        Ok(removed.len())
+    }
+
+    /// The members of the set. A missing key reads as an empty set.
+    pub async fn smembers(&self, key: &str) -> RedisResult<Vec<String>> {
+        let members = self
+            .with_value(key, |set: &HashSet<String>| set.iter().cloned().collect())
+            .await?;
+        Ok(members.unwrap_or_default())
+    }
+
+    pub async fn sismember(&self, key: &str, member: &str) -> RedisResult<bool> {
+        let found = self
+            .with_value(key, |set: &HashSet<String>| set.contains(member))
+            .await?;
+        Ok(found.unwrap_or_default())
+    }
+
+    pub async fn scard(&self, key: &str) -> RedisResult<usize> {
+        let len = self.with_value(key, HashSet::<String>::len).await?;
+        Ok(len.unwrap_or_default())
+    }
+
+    /// Adds the member to the vector set, creating the set with the dimension of the
+    /// vector, and tells whether the member is new.
+    pub async fn vadd(&self, key: &str, member: &str, vector: Vec<f32>) -> RedisResult<bool> {
+        let tokens = vadd_tokens(key, member, &vector);
+        let dim = vector.len();
+        let new = || Value::VectorSet(VectorSet::new(dim));
+        let added = self
+            .update_value(key, Some(new), |set: &mut VectorSet| {
+                set.add(member.into(), vector)
+            })
+            .await?
+            .transpose()?
+            .unwrap_or_default();
+
+        self.notify(key, KeyEvent::Write);
+        self.send_to_replicas(Resp::from(tokens).into()).await;
+        Ok(added)
+    }
+
+    /// The members of the vector set most similar to the query, as VSIM finds them. A
+    /// missing key has no members.
+    pub async fn vsim(
+        &self,
+        key: &str,
+        query: &VectorQuery,
+        count: usize,
+    ) -> RedisResult<Vec<(String, f64)>> {
+        let found = self
+            .with_value(key, |set: &VectorSet| set.similar(query, count))
+            .await?
+            .transpose()?;
+        Ok(found.unwrap_or_default())
+    }
+
+    pub async fn vcard(&self, key: &str) -> RedisResult<usize> {
+        let len = self.with_value(key, VectorSet::len).await?;
+        Ok(len.unwrap_or_default())
+    }
+
+    pub async fn query_stream(
+        &self,
+        key: &str,
+        start: String,
+        end: String,
+    ) -> RedisResult<Vec<Arc<StreamEntry>>> {
+        let start = StreamEntryIdFactor::new(&start)?;
+        let end = StreamEntryIdFactor::new(&end)?;
+
+        // Only the handles of the entries in range are copied.
+        let entries = self
+            .with_value(key, |stream: &RedisStream| {
+                stream.query(start, end).map(<[_]>::to_vec)
+            })
+            .await?
+            .transpose()?;
+        Ok(entries.unwrap_or_default())
+    }
+
+    pub async fn find_stream(
+        &self,
+        key: &str,
+        start: String,
+    ) -> RedisResult<Option<Arc<StreamEntry>>> {
+        let start = StreamEntryIdFactor::new(&start)?;
+        let entry = self
+            .with_value(key, |stream: &RedisStream| {
+                stream.find(start).map(|v| v.cloned())
+            })
+            .await?
+            .transpose()?;
+        Ok(entry.flatten())
+    }
+
+    pub async fn parse_find_stream_args(
+        &self,
+        args: Vec<(String, String)>,
+    ) -> RedisResult<Vec<(String, String)>> {
+        let mut responses: Vec<(String, String)> = vec![];
+        for (key, start) in args {
+            if start.as_str() == "$" {
+                let start = self
+                    .with_value(&key, RedisStream::last_id)
+                    .await?
+                    .flatten()
+                    .map(|v| format!("{v}"))
+                    .unwrap_or("0-0".to_string());
+                responses.push((key, start));
+            } else {
+                responses.push((key, start));
+            }
+        }
+        Ok(responses)
+    }
+
+    /// Saves the keyspace to the RDB file in the background. The write lock is held only
+    /// while the snapshot is taken.
+    pub async fn bgsave(&self) -> RedisResult<()> {
+        if !self.save_state.begin() {
+            return Err(anyhow::anyhow!("ERR Background save already in progress").into());
+        }
+
+        let (snapshot, dirty) = self.snapshot().await;
+        let taken_at = self.clock.unix_millis();
+        let path = self.rdb_path().await;
+        let save_state = Arc::clone(&self.save_state);
+
+        tokio::task::spawn_blocking(move || {
+            let saved = match snapshot.save(&path) {
+                Ok(_) => {
+                    println!("Background saving terminated with success");
+                    true
+                }
+                Err(err) => {
+                    eprintln!("Background saving error. {err}");
+                    false
+                }
+            };
+            save_state.finish(saved, dirty, taken_at);
+        });
+        Ok(())
+    }
+
+    /// Saves the keyspace unless told not to, then has the server stop. Without either
+    /// option, the keyspace is saved only when save points are configured.
+    pub async fn shutdown(&self, save: Option<bool>) -> RedisResult<()> {
+        let has_save_points = !self.lock().await.save.is_empty();
+        self.shutting_down.store(true, Ordering::SeqCst);
+        if save.unwrap_or(has_save_points) {
+            let (snapshot, dirty) = self.snapshot().await;
+            let taken_at = self.clock.unix_millis();
+            let path = self.rdb_path().await;
+            let saved = tokio::task::spawn_blocking(move || snapshot.save(&path))
+                .await
+                .map_err(|err| anyhow::anyhow!(err))
+                .and_then(|res| res.map_err(|err| anyhow::anyhow!(err)));
+            if let Err(err) = saved {
+                eprintln!("Error trying to save the DB, can't exit. {err}");
+                self.shutting_down.store(false, Ordering::SeqCst);
+                return Err(anyhow::anyhow!("ERR Errors trying to SHUTDOWN. Check logs.").into());
+            }
+            self.save_state.finish(true, dirty, taken_at);
+        }
+        if let Some(aof) = &self.aof {
+            aof.sync();
+        }
+        self.shutdown.notify_one();
+        Ok(())
+    }
+
+    /// Resolves once SHUTDOWN has been called.
+    pub async fn shutdown_requested(&self) {
+        self.shutdown.notified().await
+    }
+
+    /// Takes a snapshot of the keyspace along with the number of changes it includes.
+    async fn snapshot(&self) -> (Snapshot, u64) {
+        let shards = self.keyspace.lock_all().await;
+        let dirty = self.save_state.dirty();
+        let shards = shards.iter().map(|shard| (**shard).clone()).collect();
+        let functions = self.function_codes();
+        (Snapshot::new(shards, functions, self.clock.now()), dirty)
+    }
+
+    async fn rdb_path(&self) -> PathBuf {
+        let inner = self.lock().await;
+        let dir = inner.dir.as_deref().unwrap_or(".");
+        let dbfilename = inner.dbfilename.as_deref().unwrap_or("dump.rdb");
+        PathBuf::from(dir).join(dbfilename)
+    }
+
+    pub async fn rdb_dir(&self) -> Option<String> {
+        self.lock().await.dir.clone()
+    }
+
+    pub async fn rdb_dbfilename(&self) -> Option<String> {
+        self.lock().await.dbfilename.clone()
+    }
+
+    pub async fn role(&self) -> &str {
+        // A cluster replica which took over its master's slots is a master.
+        let promoted = match &self.cluster {
+            Some(cluster) => !cluster.lock().await.is_replica(),
+            None => false,
+        };
+        match self.config.master {
+            Some(_) if !promoted => "slave",
+            _ => "master",
+        }
+    }
+
+    pub async fn repl_id(&self) -> String {
+        let inner = self.lock().await;
+        inner.repl_id.clone()
+    }
+
+    pub async fn change_repl_id(&self) {
+        let mut inner = self.lock().await;
+        inner.repl_id = utils::random_hex(REPL_ID_LEN);
+    }
+
+    /// Bytes of the replication stream the master sent so far.
+    pub fn repl_offset(&self) -> usize {
+        self.replicas.offset()
+    }
+
+    pub fn rdb(&self, _offset: usize) -> Vec<u8> {
+        vec![
+            0x52, 0x45, 0x44, 0x49, 0x53, 0x30, 0x30, 0x31, 0x31, 0xfa, 0x09, 0x72, 0x65, 0x64,
+            0x69, 0x73, 0x2d, 0x76, 0x65, 0x72, 0x05, 0x37, 0x2e, 0x32, 0x2e, 0x30, 0xfa, 0x0a,
+            0x72, 0x65, 0x64, 0x69, 0x73, 0x2d, 0x62, 0x69, 0x74, 0x73, 0xc0, 0x40, 0xfa, 0x05,
+            0x63, 0x74, 0x69, 0x6d, 0x65, 0xc2, 0x6d, 0x08, 0xbc, 0x65, 0xfa, 0x08, 0x75, 0x73,
+            0x65, 0x64, 0x2d, 0x6d, 0x65, 0x6d, 0xc2, 0xb0, 0xc4, 0x10, 0x00, 0xfa, 0x08, 0x61,
+            0x6f, 0x66, 0x2d, 0x62, 0x61, 0x73, 0x65, 0xc0, 0x00, 0xff, 0xf0, 0x6e, 0x3b, 0xfe,
+            0xc0, 0xff, 0x5a, 0xa2,
+        ]
+    }
+
+    /// Whether a client connected from the address may run DEBUG.
+    pub fn debug_command_allowed(&self, addr: SocketAddr) -> bool {
+        self.config.enable_debug_command.allows(addr)
+    }
+
+    /// Up to `count` keys hashing to the slot.
+    pub async fn keys_in_slot(&self, slot: u16, count: usize) -> Vec<String> {
+        let mut keys = self.keyspace.keys().await;
+        keys.retain(|key| cluster::key_hash_slot(key.as_bytes()) == slot);
+        keys.sort();
+        keys.truncate(count);
+        keys
+    }
+
+    /// Checks this node can serve a command on the keys in cluster mode. Otherwise the
+    /// error redirects the client to the node which can.
+    /// `asking` is whether the client sent ASKING, following an ASK redirection, and
+    /// `replica_read` whether it is a read which a replica of the slot's master may serve.
+    pub async fn route(&self, keys: &[&str], asking: bool, replica_read: bool) -> RedisResult<()> {
+        let Some(cluster) = &self.cluster else {
+            return Ok(());
+        };
+        let Some((first, rest)) = keys.split_first() else {
+            return Ok(());
+        };
+
+        let slot = cluster::key_hash_slot(first.as_bytes());
+        if rest
+            .iter()
+            .any(|key| cluster::key_hash_slot(key.as_bytes()) != slot)
+        {
+            return Err(RedisError::CrossSlot);
+        }
+
+        let target = {
+            let cluster = cluster.lock().await;
+            if asking && cluster.importing(slot) {
+                return Ok(());
+            }
+            let owner = cluster.owner(slot).ok_or(RedisError::ClusterDown)?;
+            if replica_read && self.config.master == Some(owner.addr) {
+                return Ok(());
+            }
+            if !cluster.is_myself(owner) {
+                return Err(RedisError::Moved {
+                    slot,
+                    addr: owner.addr,
+                });
+            }
+            match cluster.migrating_to(slot) {
+                Some(node) => node.addr,
+                None => return Ok(()),
+            }
+        };
+
+        // Keys already moved out of a migrating slot are served by the receiving node.
+        for key in keys {
+            if self.get(key).await.is_none() {
+                return Err(RedisError::Ask { slot, addr: target });
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the cluster bus when cluster mode is enabled.
+    pub async fn cluster_bus(self: Arc<Self>) {
+        let Some(cluster) = &self.cluster else {
+            return;
+        };
+        if let Err(err) = cluster::listen(Arc::clone(cluster), self.config.socket_addr()).await {
+            eprintln!("Cluster bus stopped. {err}");
+        }
+    }
+
+    /// Starts the handshake with the node whose cluster bus is at `addr`.
+    pub(crate) fn cluster_meet(&self, addr: SocketAddr) {
+        if let Some(cluster) = &self.cluster {
+            let cluster = Arc::clone(cluster);
+            tokio::spawn(async move {
+                if let Err(err) = cluster::send(cluster, addr, cluster::MessageType::Meet).await {
+                    eprintln!("Failed to meet {addr}. {err}");
+                }
+            });
+        }
+    }
+
+    /// Has this replica take over the slots of its master, as CLUSTER FAILOVER does.
+    /// Unless `force`, the master is asked to hold writes back first, and the slots are
+    /// taken once this replica has caught up with its offset. The other nodes are then
+    /// told of the new epoch, which makes the slots this node's.
+    pub(crate) async fn cluster_failover(self: &Arc<Self>, force: bool) -> RedisResult<()> {
+        let cluster = Arc::clone(self.cluster_handle()?);
+        let (master_id, bus) = {
+            let cluster = cluster.lock().await;
+            if !cluster.is_replica() {
+                return Err(
+                    anyhow::anyhow!("ERR You should send CLUSTER FAILOVER to a replica").into(),
+                );
+            }
+            let master = cluster.master().ok_or_else(|| {
+                anyhow::anyhow!("ERR I'm a replica but my master is unknown to me")
+            })?;
+            let bus = SocketAddr::new(master.addr.ip(), master.bus_port());
+            (master.id.clone(), bus)
+        };
+
+        let store = Arc::clone(self);
+        tokio::spawn(async move {
+            match store.fail_over(&cluster, &master_id, bus, force).await {
+                Ok(_) => println!("Manual failover: took over the slots of {master_id}"),
+                Err(err) => eprintln!("Manual failover failed. {err}"),
+            }
+        });
+        Ok(())
+    }
+
+    async fn fail_over(
+        &self,
+        cluster: &Arc<Mutex<Cluster>>,
+        master_id: &str,
+        bus: SocketAddr,
+        force: bool,
+    ) -> RedisResult<()> {
+        if !force {
+            cluster::send(Arc::clone(cluster), bus, cluster::MessageType::MfStart).await?;
+            let offset = cluster
+                .lock()
+                .await
+                .node(master_id)
+                .map_or(0, |node| node.repl_offset);
+            let deadline = Instant::now() + cluster::MF_TIMEOUT;
+            while (self.ack_offset().await as u64) < offset {
+                if Instant::now() >= deadline {
+                    return Err(anyhow::anyhow!(
+                        "Timed out catching up with the master offset {offset}"
+                    )
+                    .into());
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        let peers = {
+            let mut cluster = cluster.lock().await;
+            cluster.take_over(master_id)?;
+            cluster.peers()
+        };
+        for peer in peers {
+            let sent = cluster::send(Arc::clone(cluster), peer, cluster::MessageType::Ping).await;
+            if let Err(err) = sent {
+                eprintln!("Failed to tell {peer} of the failover. {err}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Holds a write back while a replica of this master takes over its slots. Once the
+    /// slots are taken, the write is redirected to the replica.
+    pub async fn writes_resumed(&self) {
+        let Some(cluster) = &self.cluster else {
+            return;
+        };
+        while cluster.lock().await.writes_paused(Instant::now()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Moves the keys to the node at `addr` by replaying them there, then deletes them
+    /// here unless `copy`. Returns false when none of the keys exist.
+    pub async fn migrate(
+        &self,
+        addr: SocketAddr,
+        keys: &[String],
+        timeout: Duration,
+        copy: bool,
+        replace: bool,
+    ) -> RedisResult<bool> {
+        let mut values: Vec<(&String, Arc<Value>)> = vec![];
+        for key in keys {
+            if let Some(value) = self.get(key).await {
+                values.push((key, value));
+            }
+        }
+        if values.is_empty() {
+            return Ok(false);
+        }
+
+        // Built up front, so that a value which can't be moved leaves every key here.
+        let now = self.clock.now();
+        let replays = values
+            .iter()
+            .map(|(key, value)| value_tokens(key, value, now))
+            .collect::<RedisResult<Vec<_>>>()?;
+
+        let mut target = MigrateTarget::connect(addr, timeout).await?;
+        for ((key, _), mut commands) in values.iter().zip(replays) {
+            if replace {
+                commands.insert(0, vec!["DEL".into(), key.to_string()]);
+            } else {
+                let reply = target
+                    .call(vec![vec!["TYPE".into(), key.to_string()]])
+                    .await?;
+                if !matches!(reply.as_slice(), [Resp::SS(t)] if t == "none") {
+                    return Err(RedisError::BusyKey);
+                }
+            }
+            target.call(commands).await?;
+        }
+
+        if !copy {
+            let keys: Vec<String> = values.into_iter().map(|(key, _)| key.clone()).collect();
+            self.del(&keys).await;
+        }
+        Ok(true)
+    }
+
+    pub(crate) async fn cluster(&self) -> RedisResult<MutexGuard<'_, Cluster>> {
+        Ok(self.cluster_handle()?.lock().await)
+    }
+
+    fn cluster_handle(&self) -> RedisResult<&Arc<Mutex<Cluster>>> {
+        self.cluster.as_ref().ok_or_else(|| {
+            RedisError::from(anyhow::anyhow!(
+                "ERR This instance has cluster support disabled"
+            ))
+        })
+    }
+
+    pub async fn active_expire(&self) -> bool {
+        let inner = self.lock().await;
+        inner.active_expire
+    }
+
+    pub async fn set_active_expire(&self, enabled: bool) {
+        let mut inner = self.lock().await;
+        inner.active_expire = enabled;
+    }
+
+    pub fn quicklist_packed_threshold(&self) -> usize {
+        self.quicklist_packed_threshold.load(Ordering::Relaxed)
+    }
+
+    pub fn set_quicklist_packed_threshold(&self, bytes: usize) -> RedisResult<()> {
+        if bytes == 0 || bytes > u32::MAX as usize {
+            return Err(anyhow::anyhow!(
+                "ERR argument must be a memory value bigger than 1 and smaller than 4gb"
+            )
+            .into());
+        }
+        self.quicklist_packed_threshold
+            .store(bytes, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Holds every store lock for the given duration so that every other client is blocked.
+    pub async fn block_for(&self, duration: Duration) {
+        let _inner = self.lock().await;
+        let _shards = self.keyspace.lock_all().await;
+        tokio::time::sleep(duration).await;
+    }
+
+    pub async fn subscribe(&self, client: &Client, tx: Sender<Bytes>) {
+        let offset = self.replicas.register(
+            client.addr(),
+            client.replica_endpoint(),
+            tx,
+            self.clock.unix_millis(),
+        );
+        client.set_sync_offset(offset);
+
+        // Replicas are never evicted to save client memory.
+        client.set_no_evict(true);
+        client.set_repl_link(true);
+    }
+
+    /// The connected replicas, at the endpoints they announced.
+    pub(crate) async fn replicas(&self) -> Vec<ReplicaInfo> {
+        self.replicas.list().await
+    }
+
+    pub async fn register_client(&self, addr: SocketAddr) -> Arc<Client> {
+        let mut inner = self.lock().await;
+        let client = Arc::new(Client::new(addr, self.clock.unix_millis()));
+        client.set_authenticated(self.acl.default_nopass());
+        inner.clients.insert(addr, Arc::clone(&client));
+        self.stats.incr_connections();
+        client
+    }
+
+    /// Releases everything the closed connection of the client held: its queued
+    /// transaction, the command it is blocked on and its registration as a replica.
+    pub async fn unregister_client(&self, client: &Client) {
+        client.close();
+        let addr = client.addr();
+
+        let mut inner = self.lock().await;
+        // A killed client is unregistered already, and its address may be reused since.
+        if inner
+            .clients
+            .get(&addr)
+            .is_some_and(|registered| registered.id() == client.id())
+        {
+            inner.clients.remove(&addr);
+        }
+        inner.transactions.remove(&addr);
+        let watched = inner.watched.remove(&addr);
+        drop(inner);
+        if let Some(keys) = watched {
+            self.release_watched(keys);
+        }
+
+        self.blocked.cancel(client.id());
+        self.replicas.unregister(addr);
+    }
+
+    /// Disconnects the clients the filter picks and returns how many there were.
+    pub async fn kill_clients(&self, filter: impl Fn(&Client) -> bool) -> usize {
+        let mut inner = self.lock().await;
+        let killed: Vec<Arc<Client>> = inner
+            .clients
+            .values()
+            .filter(|client| filter(client))
+            .cloned()
+            .collect();
+        for client in killed.iter() {
+            client.kill();
+            inner.clients.remove(&client.addr());
+        }
+        killed.len()
+    }
+
+    /// Disconnects the clients using the most memory until the total memory used by
+    /// all clients fits in `maxmemory-clients`. Clients flagged NO-EVICT are exempt.
+    pub async fn evict_clients(&self) {
+        if self.config.maxmemory_clients > 0 {
+            self.evict(&mut *self.lock().await);
+        }
+    }
+
+    /// Adds the bytes of a batch of commands to the replication offset, then evicts clients
+    /// as `evict_clients` does, under one acquisition of the server state.
+    pub async fn settle_batch(&self, size: usize) {
+        let mut inner = self.lock().await;
+        inner.ack += size;
+        if self.config.maxmemory_clients > 0 {
+            self.evict(&mut inner);
+        }
+    }
+
+    fn evict(&self, inner: &mut Inner) {
+        let limit = self.config.maxmemory_clients;
+        let mut used: usize = inner.clients.values().map(|c| c.memory()).sum();
+        if used <= limit {
+            return;
+        }
+
+        let mut candidates: Vec<Arc<Client>> = inner
+            .clients
+            .values()
+            .filter(|c| !c.no_evict())
+            .cloned()
+            .collect();
+        candidates.sort_by_key(|c| std::cmp::Reverse(c.memory()));
+
+        for client in candidates {
+            if used <= limit {
+                break;
+            }
+            println!(
+                "Evicting client {} using {} bytes (maxmemory-clients: {limit})",
+                client.addr(),
+                client.memory()
+            );
+            used = used.saturating_sub(client.memory());
+            client.kill();
+            inner.clients.remove(&client.addr());
+        }
+    }
+
+    pub async fn ack_offset(&self) -> usize {
+        let inner = self.lock().await;
+        inner.ack
+    }
+
+    pub async fn add_ack_offset(&self, size: usize) {
+        let mut inner = self.lock().await;
+        inner.ack += size;
+    }
+
+    /// Keeps the link to the master, for the cron to send it acks on.
+    pub(crate) async fn link_master(&self, tx: Sender<Bytes>) {
+        self.lock().await.master_link = Some(tx);
+    }
+
+    /// Sends the master the offset processed so far, as replicas do every second besides
+    /// replying to GETACK. A link whose writer can't keep up skips the ack.
+    pub(crate) async fn ack_master(&self) {
+        let mut inner = self.lock().await;
+        let Some(link) = &inner.master_link else {
+            return;
+        };
+        let ack: Resp = vec![
+            "REPLCONF".to_string(),
+            "ACK".to_string(),
+            inner.ack.to_string(),
+        ]
+        .into();
+        if let Err(TrySendError::Closed(_)) = link.try_send(Bytes::from(ack.serialize())) {
+            inner.master_link = None;
+        }
+    }
+
+    pub async fn num_of_replicas(&self) -> usize {
+        self.replicas.count().await
+    }
+
+    pub async fn receive_replica_ack(&self, addr: SocketAddr, ack: usize) {
+        self.replicas
+            .receive_ack(addr, ack, self.clock.unix_millis());
+    }
+
+    pub async fn wait(&self, num_replicas: usize, exp: u64) -> i64 {
+        let Some((mut synced, tx, mut rx)) = self.replicas.wait().await else {
+            return 0;
+        };
+
+        if synced >= num_replicas {
+            return synced as i64;
+        }
+
+        let timeout = self.clock.sleep(Duration::from_millis(exp));
+        tokio::spawn(async move {
+            timeout.await;
+            if tx.send(WaitSignal::Timeout).await.is_err() {
+                eprintln!("Receiver dropped before timeout");
+            }
+        });
 
         while let Some(sig) = rx.recv().await {
             match sig {
@@ -304,112 +2493,397 @@ impl Store {
         synced as i64
     }
 
-    pub async fn subscribe_stream(&self, key: &str, sender: Sender<()>) {
-        let mut inner = self.lock().await;
-        match inner.stream_subscribers.get_mut(key) {
-            Some(senders) => {
-                senders.push(sender);
-            }
-            None => {
-                inner.stream_subscribers.insert(key.into(), vec![sender]);
+    /// Subscribes the event on the keys. Subscribe before reading the keys so that no
+    /// event happening in between is missed.
+    pub fn subscribe_keys(&self, keys: &[String], event: KeyEvent) -> Subscription {
+        self.notifier.subscribe(keys, event)
+    }
+
+    /// Receives every key the server removes by itself from now on, with the reason. A
+    /// receiver lagging more than 1024 removals behind misses the oldest ones, and is
+    /// told so by `RecvError::Lagged`.
+    pub fn subscribe_removals(&self) -> broadcast::Receiver<KeyRemoval> {
+        self.removals.subscribe()
+    }
+
+    /// Registers the client as blocked until the event happens on any of the keys. No
+    /// timeout blocks forever. The client is released when its connection closes.
+    pub fn block_client(
+        &self,
+        client: &Client,
+        keys: &[String],
+        event: KeyEvent,
+        timeout: Option<Duration>,
+    ) -> Blocked {
+        self.blocked.block(
+            &self.notifier,
+            &*self.clock,
+            client.id(),
+            keys,
+            event,
+            timeout,
+        )
+    }
+
+    pub fn blocked_clients(&self) -> usize {
+        self.blocked.len()
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    pub fn incr_commands(&self) {
+        self.stats.incr_commands();
+    }
+
+    /// Adds the time the command took to its latency percentiles.
+    pub fn record_latency(&self, name: &'static str, elapsed: Duration) {
+        self.stats.record_latency(name, elapsed);
+    }
+
+    /// The `latency_percentiles_usec_<command>` lines of INFO.
+    pub fn latency_percentiles(&self) -> Vec<String> {
+        self.stats.latency_percentiles()
+    }
+
+    pub fn total_commands_processed(&self) -> u64 {
+        self.stats.commands()
+    }
+
+    pub fn instantaneous_ops_per_sec(&self) -> u64 {
+        self.stats.instantaneous_ops_per_sec()
+    }
+
+    pub fn total_connections_received(&self) -> u64 {
+        self.stats.connections()
+    }
+
+    pub fn incr_error_replies(&self) {
+        self.stats.incr_error_replies();
+    }
+
+    pub fn total_error_replies(&self) -> u64 {
+        self.stats.error_replies()
+    }
+
+    pub fn incr_query_buffer_disconnections(&self) {
+        self.stats.incr_query_buffer_disconnections();
+    }
+
+    pub fn query_buffer_disconnections(&self) -> u64 {
+        self.stats.query_buffer_disconnections()
+    }
+
+    pub fn incr_query_timeout_disconnections(&self) {
+        self.stats.incr_query_timeout_disconnections();
+    }
+
+    pub fn query_timeout_disconnections(&self) -> u64 {
+        self.stats.query_timeout_disconnections()
+    }
+
+    pub fn keyspace_hits(&self) -> u64 {
+        self.stats.keyspace_hits()
+    }
+
+    pub fn keyspace_misses(&self) -> u64 {
+        self.stats.keyspace_misses()
+    }
+
+    /// The keyspace stats for INFO. Keys are gone over again only when some key has been
+    /// written since they were last.
+    pub(crate) async fn keyspace_stats(&self) -> KeyspaceStats {
+        let last = self.versions.last();
+        let cached = self.keyspace_stats.lock().ok().and_then(|cache| *cache);
+        if let Some((version, stats)) = cached {
+            if version == last {
+                return stats;
             }
         }
+
+        let stats = self.keyspace.stats().await;
+        if let Ok(mut cache) = self.keyspace_stats.lock() {
+            *cache = Some((last, stats));
+        }
+        stats
+    }
+
+    /// Zeroes the statistics INFO reports, as CONFIG RESETSTAT does.
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+
+    pub fn changes_since_last_save(&self) -> u64 {
+        self.save_state.dirty()
+    }
+
+    /// Seconds since UNIX epoch of the last successful save.
+    pub fn lastsave(&self) -> u64 {
+        self.save_state.lastsave() / 1000
+    }
+
+    pub fn bgsave_in_progress(&self) -> bool {
+        self.save_state.in_progress()
     }
 
     async fn lock(&self) -> MutexGuard<'_, Inner> {
-        self.0.lock().await
+        self.state.lock().await
+    }
+
+    fn is_replica(&self) -> bool {
+        self.config.master.is_some()
     }
 
     async fn set(&self, key: &str, value: Value) {
-        let mut inner = self.lock().await;
-        inner.db.insert(key.into(), value);
+        let mut shard = self.keyspace.shard(key).await;
+        shard.insert(key.into(), Arc::new(value));
+        self.notify(key, KeyEvent::Write);
     }
 
-    async fn get_stream(&self, key: &str) -> RedisResult<RedisStream> {
-        match self.get(key).await {
-            Some(Value::Stream(stream)) => Ok(stream),
-            None => Ok(RedisStream::new()),
-            _ => Err(anyhow::anyhow!("Key {key} is not a stream").into()),
+    /// Sends the write to the replicas, and appends it to the AOF.
+    async fn send_to_replicas(&self, msg: OutgoingMessage) {
+        if let Some(aof) = &self.aof {
+            aof.feed(&msg);
         }
+        self.replicas.propagate(msg);
     }
 
-    async fn send_to_replicas(&self, msg: OutgoingMessage) {
-        let mut inner = self.0.lock().await;
-        for msg in msg.into_iter() {
-            for (_, replica) in inner.replicas.iter_mut() {
-                replica.send(msg.clone()).await
-            }
+    fn notify_removal(&self, key: &str, reason: RemovalReason) {
+        // Sending fails only when nobody subscribed.
+        let _ = self.removals.send(KeyRemoval {
+            key: key.into(),
+            reason,
+        });
+    }
+
+    fn notify(&self, key: &str, event: KeyEvent) {
+        // Every write goes through here, so it doubles as the change counter for saves
+        // and the one bumping versions.
+        self.save_state.incr_dirty();
+        self.versions.bump(key);
+        self.notifier.notify(key, event);
+    }
+}
+
+impl Inner {
+    fn new(config: &Config) -> Self {
+        Self {
+            ack: 0,
+            transactions: HashMap::new(),
+            watched: HashMap::new(),
+            master_link: None,
+            repl_id: "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".into(),
+            active_expire: true,
+            clients: HashMap::new(),
+            dir: config.dir.clone(),
+            dbfilename: config.dbfilename.clone(),
+            save: config.save.clone(),
+            requirepass: config.requirepass.clone(),
         }
     }
+}
 
-    async fn notify_subscribers(&self, key: &str) {
-        let subscribers = {
-            let mut inner = self.lock().await;
-            match inner.stream_subscribers.get_mut(key) {
-                Some(values) => {
-                    let mut senders: Vec<Sender<()>> = vec![];
-                    senders.append(values);
-                    senders
-                }
-                None => vec![],
+/// Deletes the expired keys among the sampled ones.
+/// Sets the value at the path of the document at the key in the shard, as
+/// `Store::json_set` does, and gives the whole document when anything was set.
+fn set_json(
+    shard: &mut Shard,
+    key: &str,
+    path: &JsonPath,
+    value: Json,
+    cond: Option<SetCond>,
+) -> RedisResult<Option<String>> {
+    match shard.get_mut(key) {
+        Some(current) => {
+            if !matches!(current.as_ref(), Value::Json(_)) {
+                return Err(RedisError::WrongType);
             }
-        };
-        for sender in subscribers {
-            if sender.send(()).await.is_err() {
-                eprintln!("Receiver has been dropped before sending messsage");
+            let Value::Json(json) = Arc::make_mut(current) else {
+                return Err(RedisError::WrongType);
+            };
+            if json.set(path, &value, cond) == 0 {
+                return Ok(None);
             }
+            Ok(Some(json.to_string()))
+        }
+        None if !path.is_root() => {
+            Err(anyhow::anyhow!("ERR new objects must be created at the root").into())
+        }
+        None if cond == Some(SetCond::Xx) => Ok(None),
+        None => {
+            let doc = value.to_string();
+            shard.insert(key.into(), Arc::new(Value::Json(value)));
+            Ok(Some(doc))
         }
     }
 }
 
-impl Inner {
-    fn new(config: &Config) -> RedisResult<Self> {
-        let rdb = Rdb::from_conf(config)?;
-        Ok(Self {
-            db: rdb.db().clone(),
-            config: config.clone(),
-            replicas: HashMap::new(),
-            ack: 0,
-            stream_subscribers: HashMap::new(),
-            transactions: HashMap::new(),
-        })
+/// Runs `f` on the list at the key in the shard, as `Store::edit_list` does.
+fn edit_list_in<T>(
+    shard: &mut Shard,
+    key: &str,
+    f: impl FnOnce(&mut VecDeque<String>) -> T,
+) -> RedisResult<Option<T>> {
+    let Some(value) = shard.get_mut(key) else {
+        return Ok(None);
+    };
+    value.cast::<VecDeque<String>>()?;
+    let list = VecDeque::<String>::cast_mut(Arc::make_mut(value)).ok_or(RedisError::WrongType)?;
+    let out = f(list);
+    if list.is_empty() {
+        shard.remove(key);
+    }
+    Ok(Some(out))
+}
+
+/// Runs `f` on the hash at the key in the shard, as `Store::edit_hash` does, and gives
+/// the fields removed as past their expiry along with what `f` gives. Without `now`,
+/// as on replicas, no field is expired and `f` gets 0 as the time.
+fn edit_hash_in<T>(
+    shard: &mut Shard,
+    key: &str,
+    create: bool,
+    now: Option<u64>,
+    f: impl FnOnce(&mut RedisHash, u64) -> T,
+) -> RedisResult<Option<(T, Vec<String>)>> {
+    if create && !shard.contains_key(key) {
+        shard.insert(key.into(), Arc::new(Value::Hash(RedisHash::new())));
+    }
+    let Some(value) = shard.get_mut(key) else {
+        return Ok(None);
+    };
+    value.cast::<RedisHash>()?;
+    let hash = RedisHash::cast_mut(Arc::make_mut(value)).ok_or(RedisError::WrongType)?;
+    let (expired, now) = match now {
+        Some(now) => (hash.remove_expired(now), now),
+        None => (vec![], 0),
+    };
+    let out = f(hash, now);
+    if hash.is_empty() {
+        shard.remove(key);
     }
+    Ok(Some((out, expired)))
+}
 
-    fn num_of_replicas(&self) -> usize {
-        self.replicas.len()
+/// Removes the members from the set at the key in the shard, deleting the set once
+/// it's empty, and gives those it had.
+fn remove_members(shard: &mut Shard, key: &str, members: &[String]) -> RedisResult<Vec<String>> {
+    let Some(value) = shard.get_mut(key) else {
+        return Ok(vec![]);
+    };
+    value.cast::<HashSet<String>>()?;
+    let set = HashSet::<String>::cast_mut(Arc::make_mut(value)).ok_or(RedisError::WrongType)?;
+    let removed = members
+        .iter()
+        .filter(|member| set.remove(member.as_str()))
+        .cloned()
+        .collect();
+    if set.is_empty() {
+        shard.remove(key);
     }
+    Ok(removed)
+}
+
+/// Adds an entry to the stream at the key in the shard, creating the stream.
+fn add_stream_entry(
+    shard: &mut Shard,
+    key: &str,
+    id_factor: StreamEntryIdFactor,
+    values: HashMap<String, String>,
+) -> RedisResult<Arc<StreamEntry>> {
+    let id = match shard.get(key) {
+        Some(value) => id_factor.try_into_id(value.cast()?)?,
+        None => id_factor.try_into_id(&RedisStream::new())?,
+    };
+    let entry = Arc::new(StreamEntry::new(id, values));
 
-    fn add_replica(&mut self, addr: SocketAddr, tx: Sender<Vec<u8>>) {
-        self.replicas.insert(addr, Replica::new(tx));
+    let value = shard
+        .entry(key.into())
+        .or_insert_with(|| Arc::new(Value::Stream(RedisStream::new())));
+    if let Value::Stream(stream) = Arc::make_mut(value) {
+        stream.push(Arc::clone(&entry))?;
     }
+    Ok(entry)
 }
 
-fn is_synced<'a>((_, replica): (&'a SocketAddr, &'a Replica)) -> Option<&'a Replica> {
-    if replica.is_synced() {
-        Some(replica)
-    } else {
-        None
+/// Runs `f` on the value at the key in the shard, as `Store::update_value` does.
+fn update_in<V, T>(
+    shard: &mut Shard,
+    key: &str,
+    new: Option<impl FnOnce() -> Value>,
+    f: impl FnOnce(&mut V) -> T,
+) -> RedisResult<Option<T>>
+where
+    V: ValueType,
+{
+    if !shard.contains_key(key) {
+        let Some(new) = new else {
+            return Ok(None);
+        };
+        shard.insert(key.into(), Arc::new(new()));
     }
+    let Some(value) = shard.get_mut(key) else {
+        return Ok(None);
+    };
+    // Checked first, so that a value of another type isn't copied for nothing.
+    value.cast::<V>()?;
+    let value = V::cast_mut(Arc::make_mut(value)).ok_or(RedisError::WrongType)?;
+    Ok(Some(f(value)))
 }
 
-fn is_unsynced<'a>((_, replica): (&'a SocketAddr, &'a Replica)) -> Option<&'a Replica> {
-    if !replica.is_synced() {
-        Some(replica)
-    } else {
-        None
+fn expire_batch(shard: &mut Shard, batch: &[String], now: SystemTime) -> Vec<String> {
+    let expired: Vec<String> = batch
+        .iter()
+        .filter(|k| shard.get(k.as_str()).is_some_and(|v| v.expired(now)))
+        .cloned()
+        .collect();
+
+    for key in expired.iter() {
+        shard.remove(key);
     }
+    expired
 }
 
-fn is_unsynced_mut<'a>((_, replica): (&'a SocketAddr, &'a mut Replica)) -> Option<&'a mut Replica> {
-    if !replica.is_synced() {
-        Some(replica)
-    } else {
-        None
+/// SET with the expiry as a UNIX time, so that replicas and a replayed AOF expire the
+/// key when the master would.
+fn msg_set_string(key: &str, value: String, exp: Option<SystemTime>) -> OutgoingMessage {
+    let mut tokens = vec!["SET".to_string(), key.to_string(), value];
+    if let Some(exp) = exp {
+        let millis = exp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        tokens.extend(["PXAT".to_string(), millis.to_string()]);
     }
+    OutgoingMessage::from(Resp::from(tokens))
+}
+
+fn msg_del(keys: &[String]) -> OutgoingMessage {
+    let tokens: Vec<String> = std::iter::once("DEL".to_string())
+        .chain(keys.iter().cloned())
+        .collect();
+    OutgoingMessage::from(Resp::from(tokens))
+}
+
+fn msg_set_stream(key: &str, entry: &StreamEntry) -> OutgoingMessage {
+    OutgoingMessage::from(Resp::from(xadd_tokens(key, entry)))
+}
+
+fn parse_entry_ids(ids: &[String]) -> RedisResult<Vec<StreamEntryId>> {
+    ids.iter().map(|id| StreamEntryId::parse(id)).collect()
+}
+
+/// The `IDS numids id [id ...]` arguments of XDELEX and XACKDEL.
+fn ids_tokens(ids: &[StreamEntryId]) -> Vec<String> {
+    ["IDS".to_string(), format!("{}", ids.len())]
+        .into_iter()
+        .chain(ids.iter().map(|id| format!("{id}")))
+        .collect()
 }
 
-fn msg_set_string(key: &str, value: String, exp: Option<u64>) -> OutgoingMessage {
-    let resp: Resp = if let Some(exp) = exp {
+fn set_string_tokens(key: &str, value: String, exp: Option<u64>) -> Vec<String> {
+    if let Some(exp) = exp {
         vec![
             "SET".into(),
             format!("{key}"),
@@ -417,19 +2891,676 @@ fn msg_set_string(key: &str, value: String, exp: Option<u64>) -> OutgoingMessage
             "px".into(),
             format!("{exp}"),
         ]
-        .into()
     } else {
-        vec!["SET".into(), format!("{key}"), value].into()
-    };
+        vec!["SET".into(), format!("{key}"), value]
+    }
+}
+
+fn topk_missing() -> RedisError {
+    anyhow::anyhow!("ERR TopK: key does not exist").into()
+}
+
+/// Makes the fields of the hash expire at the unix milliseconds.
+fn hpexpireat_tokens(key: &str, at: u64, fields: Vec<String>) -> Vec<String> {
+    let mut tokens = vec![
+        "HPEXPIREAT".to_string(),
+        key.into(),
+        at.to_string(),
+        "FIELDS".into(),
+        fields.len().to_string(),
+    ];
+    tokens.extend(fields);
+    tokens
+}
+
+/// Sets the whole document, which is how every JSON write is propagated.
+fn json_set_tokens(key: &str, doc: &str) -> Vec<String> {
+    vec!["JSON.SET".into(), key.into(), "$".into(), doc.into()]
+}
 
-    OutgoingMessage::from(resp)
+fn vadd_tokens(key: &str, member: &str, vector: &[f32]) -> Vec<String> {
+    let mut tokens: Vec<String> = vec![
+        "VADD".into(),
+        key.into(),
+        "VALUES".into(),
+        format!("{}", vector.len()),
+    ];
+    tokens.extend(vector.iter().map(|v| format!("{v}")));
+    tokens.push(member.into());
+    tokens
 }
 
-fn msg_set_stream(key: &str, entry: StreamEntry) -> OutgoingMessage {
+fn xadd_tokens(key: &str, entry: &StreamEntry) -> Vec<String> {
     let mut tokens: Vec<String> = vec!["XADD".into(), key.into(), format!("{}", entry.id())];
     for (key, value) in entry.values().iter() {
         tokens.push(key.into());
         tokens.push(value.into());
     }
-    OutgoingMessage::from(Resp::from(tokens))
+    tokens
+}
+
+/// Commands which build the value from scratch under the key. Sketches are the sum of
+/// all the items added to them, which they don't keep, so there are none for them.
+fn value_tokens(key: &str, value: &Value, now: SystemTime) -> RedisResult<Vec<Vec<String>>> {
+    let tokens = match value {
+        Value::String { value, exp } => {
+            let exp = exp.map(|exp| {
+                exp.duration_since(now)
+                    .map(|d| d.as_millis().max(1) as u64)
+                    .unwrap_or(1)
+            });
+            vec![set_string_tokens(key, value.clone(), exp)]
+        }
+        Value::List(list) => {
+            let mut tokens = vec!["RPUSH".to_string(), key.into()];
+            tokens.extend(list.iter().cloned());
+            vec![tokens]
+        }
+        Value::Set(set) => {
+            let mut members: Vec<String> = set.iter().cloned().collect();
+            members.sort_unstable();
+            vec![["SADD".to_string(), key.into()]
+                .into_iter()
+                .chain(members)
+                .collect()]
+        }
+        Value::Stream(stream) => stream
+            .entries()
+            .iter()
+            .map(|entry| xadd_tokens(key, entry))
+            .collect(),
+        Value::VectorSet(set) => set
+            .members()
+            .map(|(member, vector)| vadd_tokens(key, member, vector))
+            .collect(),
+        Value::Json(json) => vec![json_set_tokens(key, &json.to_string())],
+        Value::Hash(hash) => {
+            let mut fields: Vec<_> = hash.entries().collect();
+            fields.sort_unstable();
+            let mut tokens = vec![["HSET".to_string(), key.into()]
+                .into_iter()
+                .chain(
+                    fields
+                        .iter()
+                        .flat_map(|(field, value, _)| [field.to_string(), value.to_string()]),
+                )
+                .collect()];
+            tokens.extend(fields.iter().filter_map(|(field, _, at)| {
+                at.map(|at| hpexpireat_tokens(key, at, vec![field.to_string()]))
+            }));
+            tokens
+        }
+        Value::Bloom(_) | Value::Cuckoo(_) | Value::TopK(_) => {
+            return Err(
+                anyhow::anyhow!("ERR can't migrate the {} at '{key}'", value.type_name()).into(),
+            );
+        }
+    };
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::time::UNIX_EPOCH;
+
+    #[tokio::test]
+    async fn it_expires_keys_on_the_store_clock() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let store = Store::with_clock(
+            &Config::new(vec![]).unwrap(),
+            Arc::clone(&clock) as Arc<dyn Clock>,
+        )
+        .unwrap();
+
+        store.set_string("foo", "bar".into(), Some(100)).await;
+        let get = || store.with_value("foo", String::clone);
+        clock.advance(Duration::from_millis(99));
+        assert_eq!(get().await.unwrap(), Some("bar".into()));
+
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(get().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn it_writes_over_expired_values() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let store = Store::with_clock(
+            &Config::new(vec![]).unwrap(),
+            Arc::clone(&clock) as Arc<dyn Clock>,
+        )
+        .unwrap();
+        let mut removals = store.subscribe_removals();
+
+        for key in [
+            "list", "bloom", "stream", "json", "pivot", "hash", "vectors", "entries", "doc",
+        ] {
+            store.set_string(key, "v".into(), Some(100)).await;
+        }
+        clock.advance(Duration::from_millis(100));
+
+        assert_eq!(store.push("list", &["x".into()], true).await.unwrap(), 1);
+        assert_eq!(removals.recv().await.unwrap().key, "list");
+        let filter = BloomFilter::new(0.01, 100, None).unwrap();
+        let tokens = vec![
+            "BF.RESERVE".into(),
+            "bloom".into(),
+            "0.01".into(),
+            "100".into(),
+        ];
+        store
+            .reserve("bloom", Value::Bloom(filter), tokens)
+            .await
+            .unwrap();
+        assert_eq!(removals.recv().await.unwrap().key, "bloom");
+
+        let id = store
+            .set_stream("stream", "1-1".into(), HashMap::new())
+            .await;
+        assert_eq!(format!("{}", id.unwrap()), "1-1");
+        assert_eq!(removals.recv().await.unwrap().key, "stream");
+        let ids = ["1-1".to_string()];
+        let deleted = store.delete_stream_entries("entries", &ids, DeletePolicy::KeepRef);
+        assert_eq!(deleted.await.unwrap(), [-1]);
+        assert_eq!(removals.recv().await.unwrap().key, "entries");
+
+        let root = JsonPath::parse("$").unwrap();
+        let set = store.json_set("json", &root, Json::Int(1), None).await;
+        assert!(set.unwrap());
+        assert_eq!(removals.recv().await.unwrap().key, "json");
+        let path = JsonPath::parse("$.a").unwrap();
+        assert_eq!(store.json_del("doc", &path).await.unwrap(), 0);
+        assert_eq!(removals.recv().await.unwrap().key, "doc");
+
+        assert_eq!(store.linsert("pivot", true, "a", "b").await.unwrap(), 0);
+        assert_eq!(removals.recv().await.unwrap().key, "pivot");
+        assert_eq!(store.hincrby("hash", "f", 2).await.unwrap(), 2);
+        assert_eq!(removals.recv().await.unwrap().key, "hash");
+
+        assert!(store.vadd("vectors", "a", vec![1.0, 0.0]).await.unwrap());
+        assert_eq!(removals.recv().await.unwrap().key, "vectors");
+    }
+
+    #[tokio::test]
+    async fn it_resolves_absolute_expiries_on_the_store_clock() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH + Duration::from_secs(1000)));
+        let store = Arc::new(
+            Store::with_clock(
+                &Config::new(vec![]).unwrap(),
+                Arc::clone(&clock) as Arc<dyn Clock>,
+            )
+            .unwrap(),
+        );
+        let addr: SocketAddr = "127.0.0.1:50300".parse().unwrap();
+        let client = Arc::new(Client::new(addr, 0));
+        let mut ctx = crate::Context::detached(crate::CommandMode::Normal, addr, client);
+        let args: Vec<String> = ["SET", "foo", "bar", "PXAT", "1000100"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let cmd = Command::new(Resp::from(args)).unwrap();
+        cmd.run(Arc::clone(&store), &mut ctx).await.unwrap();
+
+        let get = || store.with_value("foo", String::clone);
+        clock.advance(Duration::from_millis(99));
+        assert_eq!(get().await.unwrap(), Some("bar".into()));
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(get().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn it_broadcasts_expired_keys() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let store = Store::with_clock(
+            &Config::new(vec![]).unwrap(),
+            Arc::clone(&clock) as Arc<dyn Clock>,
+        )
+        .unwrap();
+        let mut removals = store.subscribe_removals();
+        let expired = |key: &str| KeyRemoval {
+            key: key.into(),
+            reason: RemovalReason::Expired,
+        };
+
+        for key in ["lazy", "active", "deleted"] {
+            store.set_string(key, "v".into(), Some(100)).await;
+        }
+        store.set_string("kept", "v".into(), None).await;
+        clock.advance(Duration::from_millis(100));
+
+        assert!(store.get("lazy").await.is_none());
+        assert_eq!(removals.recv().await.unwrap(), expired("lazy"));
+        assert_eq!(store.del(&["deleted".into(), "kept".into()]).await, 1);
+        assert_eq!(removals.recv().await.unwrap(), expired("deleted"));
+        store.active_expire_cycle().await;
+        assert_eq!(removals.recv().await.unwrap(), expired("active"));
+        assert!(removals.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn it_expires_keys_in_bounded_cycles() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let store = Store::with_clock(
+            &Config::new(vec![]).unwrap(),
+            Arc::clone(&clock) as Arc<dyn Clock>,
+        )
+        .unwrap();
+        for n in 0..1000 {
+            store
+                .set_string(&format!("k{n}"), "v".into(), Some(100))
+                .await;
+        }
+        clock.advance(Duration::from_millis(100));
+
+        let mut cycles = 0;
+        while store.keyspace_stats().await.keys > 0 {
+            let before = store.keyspace_stats().await.keys;
+            store.active_expire_cycle().await;
+            let expired = before - store.keyspace_stats().await.keys;
+            assert!(expired <= expire::KEYS_PER_CYCLE, "{expired}");
+            cycles += 1;
+            assert!(cycles <= 100);
+        }
+        assert!(cycles >= 1000 / expire::KEYS_PER_CYCLE);
+        assert_eq!(store.expire_cycle_stats().1, 0);
+
+        // With nothing left to expire, cycles skip ticks.
+        store.active_expire_cycle().await;
+        assert_eq!(store.expire_cycle.start(), None);
+    }
+
+    #[tokio::test]
+    async fn it_measures_the_keyspace_for_info() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let store = Store::with_clock(
+            &Config::new(vec![]).unwrap(),
+            Arc::clone(&clock) as Arc<dyn Clock>,
+        )
+        .unwrap();
+
+        store.set_string("foo", "v".into(), Some(100)).await;
+        store.set_string("bar", "v".into(), Some(300)).await;
+        store.set_string("baz", "v".into(), None).await;
+        let stats = store.keyspace_stats().await;
+        assert_eq!((stats.keys, stats.expires), (3, 2));
+        assert_eq!(stats.avg_ttl(0), 200);
+
+        // The TTLs run down without the keys being gone over again.
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(store.keyspace_stats().await, stats);
+        assert_eq!(stats.avg_ttl(50), 150);
+
+        store.del(&["bar".into()]).await;
+        let stats = store.keyspace_stats().await;
+        assert_eq!((stats.keys, stats.expires), (2, 1));
+        assert_eq!(stats.avg_ttl(50), 50);
+    }
+
+    #[tokio::test]
+    async fn it_versions_keys_for_watch() {
+        let store = Store::new(&Config::new(vec![]).unwrap()).unwrap();
+        let addr: SocketAddr = "127.0.0.1:50200".parse().unwrap();
+        assert_eq!(store.version("foo"), 0);
+
+        store.set_string("foo", "1".into(), None).await;
+        let version = store.version("foo");
+        assert_ne!(version, 0);
+
+        store.watch(addr, &["foo".into()]).await;
+        store.set_string("bar", "1".into(), None).await;
+        assert_eq!(store.version("foo"), version);
+        assert!(!store.unwatch(addr).await);
+
+        store.watch(addr, &["foo".into(), "missing".into()]).await;
+        store.del(&["foo".into()]).await;
+        assert!(store.version("foo") > version);
+        assert!(store.unwatch(addr).await);
+        // Unwatching forgets the keys.
+        assert!(!store.unwatch(addr).await);
+
+        store.watch(addr, &["missing".into()]).await;
+        store.increment("missing").await.unwrap();
+        assert!(store.unwatch(addr).await);
+    }
+
+    #[tokio::test]
+    async fn it_holds_writes_off_while_exec_runs() {
+        let store = Store::new(&Config::new(vec![]).unwrap()).unwrap();
+        let write = store.write_guard().await;
+        let exec = tokio::time::timeout(Duration::from_millis(20), store.exec_guard());
+        assert!(exec.await.is_err());
+        drop(write);
+
+        let exec = store.exec_guard().await;
+        let write = tokio::time::timeout(Duration::from_millis(20), store.write_guard());
+        assert!(write.await.is_err());
+        drop(exec);
+        let _write = store.write_guard().await;
+        let _other = store.write_guard().await;
+    }
+
+    /// Registers a replica on the store and returns what the store propagates to it, a
+    /// command per frame.
+    async fn fake_replica(store: &Store) -> tokio::sync::mpsc::Receiver<Bytes> {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Bytes>(64);
+        let client = store
+            .register_client("127.0.0.1:50100".parse().unwrap())
+            .await;
+        store.subscribe(&client, tx).await;
+        rx
+    }
+
+    async fn propagated(rx: &mut tokio::sync::mpsc::Receiver<Bytes>) -> Vec<String> {
+        let frame = rx.recv().await.unwrap();
+        crate::cmd::command_args(Resp::new(&frame).unwrap())
+    }
+
+    /// Every write reaches replicas and the AOF in a form which gives the same result
+    /// whenever and wherever it's replayed: no relative expiry, no id left to generate
+    /// and no value computed from what the replica holds.
+    #[tokio::test]
+    async fn it_propagates_writes_in_deterministic_forms() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH + Duration::from_secs(1000)));
+        let store = Store::with_clock(
+            &Config::new(vec![]).unwrap(),
+            Arc::clone(&clock) as Arc<dyn Clock>,
+        )
+        .unwrap();
+        let mut rx = fake_replica(&store).await;
+
+        store.set_string("tmp", "v".into(), Some(100)).await;
+        assert_eq!(
+            propagated(&mut rx).await,
+            ["SET", "tmp", "v", "PXAT", "1000100"]
+        );
+
+        store.increment("counter").await.unwrap();
+        store.increment("counter").await.unwrap();
+        assert_eq!(propagated(&mut rx).await, ["SET", "counter", "1"]);
+        assert_eq!(propagated(&mut rx).await, ["SET", "counter", "2"]);
+
+        store.setrange("counter", 1, "0").await.unwrap();
+        assert_eq!(propagated(&mut rx).await, ["SET", "counter", "20"]);
+
+        let values = [("f".to_string(), "v".to_string())].into();
+        store.set_stream("s", "*".into(), values).await.unwrap();
+        assert_eq!(
+            propagated(&mut rx).await,
+            ["XADD", "s", "1000000-0", "f", "v"]
+        );
+
+        // The increment keeps the absolute expiry of the key it increments.
+        store.set_string("tmp", "1".into(), Some(100)).await;
+        propagated(&mut rx).await;
+        clock.advance(Duration::from_millis(50));
+        store.increment("tmp").await.unwrap();
+        assert_eq!(
+            propagated(&mut rx).await,
+            ["SET", "tmp", "2", "PXAT", "1000100"]
+        );
+
+        // Keys expire on replicas only when the master says so.
+        clock.advance(Duration::from_millis(50));
+        assert!(store.get("tmp").await.is_none());
+        assert_eq!(propagated(&mut rx).await, ["DEL", "tmp"]);
+    }
+
+    #[tokio::test]
+    async fn it_deletes_stream_entries() {
+        let store = Store::new(&Config::new(vec![]).unwrap()).unwrap();
+        let mut rx = fake_replica(&store).await;
+        for id in ["1-1", "1-2"] {
+            store
+                .set_stream("s", id.into(), HashMap::new())
+                .await
+                .unwrap();
+            propagated(&mut rx).await;
+        }
+
+        let ids = ["1-1".to_string(), "3".to_string()];
+        let replies = store.delete_stream_entries("s", &ids, DeletePolicy::DelRef);
+        assert_eq!(replies.await.unwrap(), [1, -1]);
+        assert_eq!(
+            propagated(&mut rx).await,
+            ["XDELEX", "s", "DELREF", "IDS", "2", "1-1", "3-0"]
+        );
+        let replies = store.delete_stream_entries("none", &ids, DeletePolicy::KeepRef);
+        assert_eq!(replies.await.unwrap(), [-1, -1]);
+
+        let err = store
+            .ack_delete_stream_entries("s", "group", &ids, DeletePolicy::KeepRef)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "NOGROUP");
+
+        store.set_string("str", "v".into(), None).await;
+        let replies = store.delete_stream_entries("str", &ids, DeletePolicy::KeepRef);
+        assert!(matches!(replies.await, Err(RedisError::WrongType)));
+    }
+
+    #[tokio::test]
+    async fn it_tells_missing_keys_from_wrong_types() {
+        let store = Store::new(&Config::new(vec![]).unwrap()).unwrap();
+        store.set_string("str", "1".into(), None).await;
+        store
+            .set_stream("s", "1-1".into(), HashMap::new())
+            .await
+            .unwrap();
+
+        let len = |key| store.with_value(key, |stream: &RedisStream| stream.entries().len());
+        assert_eq!(len("s").await.unwrap(), Some(1));
+        assert_eq!(len("missing").await.unwrap(), None);
+        assert!(matches!(len("str").await, Err(RedisError::WrongType)));
+
+        assert!(matches!(
+            store.increment("s").await,
+            Err(RedisError::WrongType)
+        ));
+        let added = store.set_stream("str", "*".into(), HashMap::new()).await;
+        assert!(matches!(added, Err(RedisError::WrongType)));
+    }
+
+    #[tokio::test]
+    async fn it_reads_and_writes_string_ranges() {
+        let store = Store::new(&Config::new(vec![]).unwrap()).unwrap();
+        store.set_string("num", "12345".into(), None).await;
+
+        assert_eq!(store.getrange("num", 1, -2).await.unwrap(), "234");
+        assert_eq!(store.getrange("num", -100, 100).await.unwrap(), "12345");
+        assert_eq!(store.getrange("missing", 0, -1).await.unwrap(), "");
+
+        assert_eq!(store.setrange("num", 3, "xy").await.unwrap(), 5);
+        assert_eq!(store.getrange("num", 0, -1).await.unwrap(), "123xy");
+        assert_eq!(store.setrange("pad", 2, "ab").await.unwrap(), 4);
+        assert_eq!(store.getrange("pad", 0, -1).await.unwrap(), "\0\0ab");
+
+        // An empty value neither creates the key nor changes it.
+        assert_eq!(store.setrange("missing", 5, "").await.unwrap(), 0);
+        assert!(store.get("missing").await.is_none());
+        assert_eq!(store.setrange("num", 10, "").await.unwrap(), 5);
+
+        assert!(store.setrange("num", -1, "a").await.is_err());
+        // A character is never split, which would leave the string invalid UTF-8.
+        store.set_string("utf8", "aéb".into(), None).await;
+        assert!(store.setrange("utf8", 2, "x").await.is_err());
+        assert!(store.setrange("utf8", 0, "xy").await.is_err());
+        assert_eq!(store.setrange("utf8", 1, "ü").await.unwrap(), 4);
+        assert_eq!(store.getrange("utf8", 0, -1).await.unwrap(), "aüb");
+        assert_eq!(store.getrange("utf8", 0, 1).await.unwrap(), "a\u{fffd}");
+        assert!(store.setrange("num", 512 * 1024 * 1024, "a").await.is_err());
+        store
+            .set_stream("s", "1-1".into(), HashMap::new())
+            .await
+            .unwrap();
+        assert!(matches!(
+            store.setrange("s", 0, "a").await,
+            Err(RedisError::WrongType)
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_checks_string_growth_before_writing() {
+        let args: Vec<String> = ["--maxmemory", "100", "--proto-max-bulk-len", "1mb"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let store = Store::new(&Config::new(args).unwrap()).unwrap();
+        assert_eq!(store.append("s", "abc").await.unwrap(), 3);
+        assert_eq!(store.append("s", "de").await.unwrap(), 5);
+
+        // Overwriting doesn't grow the string, so it's fine at any memory use.
+        store.used_memory.store(95, Ordering::Relaxed);
+        assert_eq!(store.setrange("s", 0, "xy").await.unwrap(), 5);
+        assert_eq!(store.append("s", "fgh").await.unwrap(), 8);
+        assert_eq!(store.append("s", "ij").await.unwrap(), 10);
+        assert_eq!(store.used_memory.load(Ordering::Relaxed), 100);
+        assert!(matches!(store.append("s", "k").await, Err(RedisError::Oom)));
+        assert!(matches!(
+            store.setrange("s", 10, "k").await,
+            Err(RedisError::Oom)
+        ));
+        assert_eq!(store.getrange("s", 0, -1).await.unwrap(), "xycdefghij");
+
+        store.used_memory.store(0, Ordering::Relaxed);
+        let err = store.setrange("big", 1024 * 1024, "a").await.unwrap_err();
+        assert!(err.to_string().contains("proto-max-bulk-len"));
+        assert!(store.get("big").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_bounds_the_quicklist_packed_threshold() {
+        let store = Store::new(&Config::new(vec![]).unwrap()).unwrap();
+        assert_eq!(store.quicklist_packed_threshold(), 1 << 30);
+
+        store.set_quicklist_packed_threshold(100).unwrap();
+        assert_eq!(store.quicklist_packed_threshold(), 100);
+        assert!(store.set_quicklist_packed_threshold(0).is_err());
+        assert!(store.set_quicklist_packed_threshold(1 << 32).is_err());
+        assert_eq!(store.quicklist_packed_threshold(), 100);
+    }
+
+    #[tokio::test]
+    async fn it_shares_values_with_readers() {
+        let store = Store::new(&Config::new(vec![]).unwrap()).unwrap();
+        store
+            .set_stream("s", "1-1".into(), HashMap::new())
+            .await
+            .unwrap();
+
+        let first = store.get("s").await.unwrap();
+        let second = store.get("s").await.unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // Writing while readers hold the value leaves their version untouched.
+        store
+            .set_stream("s", "1-2".into(), HashMap::new())
+            .await
+            .unwrap();
+        let third = store.get("s").await.unwrap();
+        assert!(!Arc::ptr_eq(&first, &third));
+        assert!(first.serialized_len() < third.serialized_len());
+    }
+
+    #[tokio::test]
+    async fn it_generates_stream_ids_from_the_store_clock() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH + Duration::from_millis(1000)));
+        let store = Store::with_clock(&Config::new(vec![]).unwrap(), clock).unwrap();
+
+        let id = store.set_stream("s", "*".into(), HashMap::new()).await;
+        assert_eq!(format!("{}", id.unwrap()), "1000-0");
+    }
+
+    #[tokio::test]
+    async fn it_releases_the_state_of_closed_clients() {
+        let store = Store::new(&Config::new(vec![]).unwrap()).unwrap();
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let client = store.register_client(addr).await;
+        store.start_queuing(addr).await;
+
+        store.unregister_client(&client).await;
+        assert!(client.is_closed());
+        assert!(!store.is_queuing(addr).await);
+
+        // A new connection from the same address keeps its registration.
+        let reused = store.register_client(addr).await;
+        store.unregister_client(&client).await;
+        assert!(store.lock().await.clients.contains_key(&reused.addr()));
+    }
+
+    #[tokio::test]
+    async fn it_guards_protected_configs() {
+        let store = Store::new(&Config::new(vec![]).unwrap()).unwrap();
+        let local: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let remote: SocketAddr = "10.0.0.1:50000".parse().unwrap();
+        let dir = std::env::temp_dir().to_string_lossy().into_owned();
+        let set = |name: &str, value: &str| vec![(name.to_string(), value.to_string())];
+
+        let denied = store
+            .config_set(&set("dir", &dir), remote)
+            .await
+            .unwrap_err();
+        assert!(denied.to_string().ends_with("can't set protected config"));
+        assert_eq!(store.rdb_dir().await, None);
+
+        store.config_set(&set("dir", &dir), local).await.unwrap();
+        assert_eq!(store.rdb_dir().await, Some(dir));
+        assert!(store
+            .config_set(&set("dbfilename", "a/b.rdb"), local)
+            .await
+            .is_err());
+        assert!(store
+            .config_set(&set("dir", "/no/such/dir"), local)
+            .await
+            .is_err());
+
+        // A bad value leaves the others of the same call unset.
+        let params = [set("trace-proto", "yes"), set("trace-proto", "maybe")].concat();
+        assert!(store.config_set(&params, local).await.is_err());
+        assert!(!store.trace_proto());
+        // DEBUG is refused to everyone unless enabled.
+        assert!(!store.debug_command_allowed(local));
+        assert!(!store.debug_command_allowed(remote));
+    }
+
+    #[tokio::test]
+    async fn it_reloads_the_config_file() {
+        let path = std::env::temp_dir().join(format!("redis-reload-{}.conf", std::process::id()));
+        std::fs::write(&path, "requirepass foo\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let config = Config::new(vec!["redis-server".into(), path.clone()]).unwrap();
+        let store = Store::new(&config).unwrap();
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+
+        let client = store.register_client(addr).await;
+        assert!(!client.authenticated());
+        let err = store.authenticate(&client, None, "bar").await.unwrap_err();
+        assert!(err.to_string().starts_with("WRONGPASS"));
+        store
+            .authenticate(&client, Some("default"), "foo")
+            .await
+            .unwrap();
+        assert!(client.authenticated());
+
+        std::fs::write(&path, "save 60 1\nmaxmemory 16\nloglevel debug\n").unwrap();
+        store.reload_config().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let store = &store;
+        let param = |name: &'static str| async move {
+            store.config_get(name).await[0]
+                .1
+                .clone()
+                .unwrap_or_default()
+        };
+        assert_eq!(param("save").await, "60 1");
+        assert_eq!(param("requirepass").await, "");
+        assert_eq!(store.loglevel(), LogLevel::Debug);
+        let other: SocketAddr = "127.0.0.1:50001".parse().unwrap();
+        assert!(store.register_client(other).await.authenticated());
+
+        assert!(!store.over_maxmemory());
+        store.set_string("key", "a".repeat(32), None).await;
+        store.measure_used_memory().await;
+        assert!(store.over_maxmemory());
+    }
 }
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+
+/// Kinds of events happening on a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyEvent {
+    /// Entries were appended to a stream.
+    StreamAdd,
+    /// Elements were pushed to a list.
+    ListPush,
+    /// Members were added to a sorted set.
+    ZsetAdd,
+    /// The key was modified in any way, including deletion and expiration.
+    Write,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub key: String,
+    pub event: KeyEvent,
+}
+
+/// Why a key was removed from the keyspace by the server rather than by a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RemovalReason {
+    /// Its expiry passed, and it was found so on access or by the active expiration cycle.
+    Expired,
+}
+
+/// A key removed by the server, as broadcast to the receivers of
+/// [`Store::subscribe_removals`](crate::Store::subscribe_removals).
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyRemoval {
+    pub key: String,
+    pub reason: RemovalReason,
+}
+
+/// Per-key and per-event subscriptions shared by every feature waiting for keys to change.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Notifier(Arc<Mutex<Subscribers>>);
+
+type Topic = (String, KeyEvent);
+
+#[derive(Debug, Default)]
+struct Subscribers {
+    next_id: u64,
+    map: HashMap<Topic, Vec<(u64, Sender<Notification>)>>,
+}
+
+impl Notifier {
+    /// Subscribes the event on every given key. The subscription lasts until it is dropped.
+    pub(crate) fn subscribe(&self, keys: &[String], event: KeyEvent) -> Subscription {
+        let (tx, rx) = mpsc::channel::<Notification>(keys.len().max(1));
+        let topics: Vec<Topic> = keys.iter().map(|key| (key.to_string(), event)).collect();
+
+        let mut id = 0;
+        if let Ok(mut subs) = self.0.lock() {
+            subs.next_id += 1;
+            id = subs.next_id;
+            for topic in topics.iter() {
+                subs.map
+                    .entry(topic.clone())
+                    .or_default()
+                    .push((id, tx.clone()));
+            }
+        }
+
+        Subscription {
+            id,
+            topics,
+            rx,
+            notifier: self.clone(),
+        }
+    }
+
+    /// Notifies the subscribers of the event on the key, in the order they subscribed.
+    /// Subscribers of `KeyEvent::Write` are notified of every event.
+    pub(crate) fn notify(&self, key: &str, event: KeyEvent) {
+        let Ok(mut subs) = self.0.lock() else {
+            return;
+        };
+
+        let mut events = vec![event];
+        if event != KeyEvent::Write {
+            events.push(KeyEvent::Write);
+        }
+
+        for event in events {
+            let topic = (key.to_string(), event);
+            if let Some(senders) = subs.map.get_mut(&topic) {
+                senders.retain(|(_, tx)| {
+                    let notification = Notification {
+                        key: key.to_string(),
+                        event,
+                    };
+                    // A full channel already holds a pending notification for the subscriber.
+                    !matches!(tx.try_send(notification), Err(TrySendError::Closed(_)))
+                });
+                if senders.is_empty() {
+                    subs.map.remove(&topic);
+                }
+            }
+        }
+    }
+
+    fn unsubscribe(&self, id: u64, topics: &[Topic]) {
+        let Ok(mut subs) = self.0.lock() else {
+            return;
+        };
+
+        for topic in topics {
+            if let Some(senders) = subs.map.get_mut(topic) {
+                senders.retain(|(sub_id, _)| *sub_id != id);
+                if senders.is_empty() {
+                    subs.map.remove(topic);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Subscription {
+    id: u64,
+    topics: Vec<Topic>,
+    rx: Receiver<Notification>,
+    notifier: Notifier,
+}
+
+impl Subscription {
+    pub async fn recv(&mut self) -> Option<Notification> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.notifier.unsubscribe(self.id, &self.topics);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_notifies_subscribers() {
+        let notifier = Notifier::default();
+        let mut stream_sub = notifier.subscribe(&["foo".into(), "bar".into()], KeyEvent::StreamAdd);
+        let mut write_sub = notifier.subscribe(&["bar".into()], KeyEvent::Write);
+
+        notifier.notify("bar", KeyEvent::StreamAdd);
+
+        let expected = Notification {
+            key: "bar".into(),
+            event: KeyEvent::StreamAdd,
+        };
+        assert_eq!(stream_sub.recv().await, Some(expected));
+
+        let expected = Notification {
+            key: "bar".into(),
+            event: KeyEvent::Write,
+        };
+        assert_eq!(write_sub.recv().await, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn it_unsubscribes_on_drop() {
+        let notifier = Notifier::default();
+        let sub = notifier.subscribe(&["foo".into()], KeyEvent::StreamAdd);
+        assert_eq!(notifier.0.lock().unwrap().map.len(), 1);
+
+        drop(sub);
+        assert!(notifier.0.lock().unwrap().map.is_empty());
+    }
+}
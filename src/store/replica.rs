@@ -1,5 +1,13 @@
 use super::{OutgoingMessage, Resp};
-use tokio::sync::mpsc::Sender;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
 
 #[derive(Debug, Clone)]
 enum SyncStatus {
@@ -9,16 +17,34 @@ enum SyncStatus {
 
 #[derive(Debug, Clone)]
 pub(crate) struct Replica {
-    sender: Sender<Vec<u8>>,
+    sender: Sender<Bytes>,
+    ip: String,
+    port: u16,
+    /// The offset the replica acked last.
+    acked: usize,
+    /// Milliseconds since UNIX epoch when the replica acked last, or registered.
+    acked_at: u64,
     status: SyncStatus,
     wait_callbacks: Option<Vec<WaitCallback>>,
 }
 
 impl Replica {
-    pub(crate) fn new(sender: Sender<Vec<u8>>) -> Self {
+    /// A replica which has been sent the stream up to `offset`, the replication offset
+    /// when it registered.
+    pub(crate) fn new(
+        sender: Sender<Bytes>,
+        ip: String,
+        port: u16,
+        offset: usize,
+        now: u64,
+    ) -> Self {
         Self {
             sender,
-            status: SyncStatus::Reached(0),
+            ip,
+            port,
+            acked: offset,
+            acked_at: now,
+            status: SyncStatus::Reached(offset),
             wait_callbacks: Some(vec![]),
         }
     }
@@ -44,17 +70,9 @@ impl Replica {
         self.status = SyncStatus::Behind(ack_sent);
     }
 
-    pub(crate) async fn send_getack(&mut self) {
-        let msg: Resp = vec![
-            "REPLCONF".to_string(),
-            "GETACK".to_string(),
-            "*".to_string(),
-        ]
-        .into();
-        self.send(msg).await
-    }
-
-    pub(crate) async fn receive_ack(&mut self, received: usize) {
+    pub(crate) async fn receive_ack(&mut self, received: usize, at: u64) {
+        self.acked = received;
+        self.acked_at = at;
         if self.ack_sent() <= received {
             self.status = SyncStatus::Reached(received);
         } else {
@@ -97,6 +115,15 @@ impl Replica {
         matches!(self.status, SyncStatus::Reached(_))
     }
 
+    fn info(&self) -> ReplicaInfo {
+        ReplicaInfo {
+            ip: self.ip.clone(),
+            port: self.port,
+            offset: self.acked,
+            acked_at: self.acked_at,
+        }
+    }
+
     fn ack_sent(&self) -> usize {
         match self.status {
             SyncStatus::Reached(byte) => byte,
@@ -105,6 +132,23 @@ impl Replica {
     }
 }
 
+/// A replica as INFO reports it: the endpoint it announced, and the offset it acked and
+/// when.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ReplicaInfo {
+    pub(crate) ip: String,
+    pub(crate) port: u16,
+    pub(crate) offset: usize,
+    pub(crate) acked_at: u64,
+}
+
+impl ReplicaInfo {
+    /// Seconds since the replica acked last.
+    pub(crate) fn lag(&self, now: u64) -> u64 {
+        now.saturating_sub(self.acked_at) / 1000
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) enum WaitSignal {
     Synced,
@@ -116,3 +160,204 @@ struct WaitCallback {
     tx: Sender<WaitSignal>,
     target_ack: usize,
 }
+
+/// Handle to the task owning every connected replica.
+///
+/// Propagation only pushes into an unbounded channel, so a slow replica never blocks
+/// the callers (and the locks they hold); the task forwards messages in order.
+#[derive(Debug, Clone)]
+pub(crate) struct Replicas {
+    events: UnboundedSender<ReplicaEvent>,
+    /// Bytes of the replication stream so far, PINGs and GETACKs included. It's counted
+    /// as messages are queued, so that a replica registers at the offset of the messages
+    /// queued before it.
+    offset: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+enum ReplicaEvent {
+    Register {
+        addr: SocketAddr,
+        endpoint: (String, u16),
+        sender: Sender<Bytes>,
+        offset: usize,
+        at: u64,
+    },
+    Unregister(SocketAddr),
+    Propagate(OutgoingMessage),
+    Ack {
+        addr: SocketAddr,
+        offset: usize,
+        at: u64,
+    },
+    Wait(oneshot::Sender<WaitHandle>),
+    Count(oneshot::Sender<usize>),
+    List(oneshot::Sender<Vec<ReplicaInfo>>),
+}
+
+/// The number of replicas already synced, plus a channel receiving a signal each time
+/// another replica gets synced. The sender is for the caller to signal its timeout.
+pub(crate) type WaitHandle = (usize, Sender<WaitSignal>, Receiver<WaitSignal>);
+
+impl Replicas {
+    pub(crate) fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel::<ReplicaEvent>();
+        tokio::spawn(run(rx));
+        Self {
+            events: tx,
+            offset: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// The replication offset, which replicas ack once they processed the stream so far.
+    pub(crate) fn offset(&self) -> usize {
+        self.offset.load(Ordering::Relaxed)
+    }
+
+    /// The counter of the replication offset, for the cluster bus to tell it.
+    pub(crate) fn shared_offset(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.offset)
+    }
+
+    /// Registers the connection from `addr` as a replica, reported at the endpoint it
+    /// announced. `at` is when, in milliseconds since UNIX epoch. Returns the offset the
+    /// replica starts from.
+    pub(crate) fn register(
+        &self,
+        addr: SocketAddr,
+        endpoint: (String, u16),
+        sender: Sender<Bytes>,
+        at: u64,
+    ) -> usize {
+        let offset = self.offset();
+        self.send(ReplicaEvent::Register {
+            addr,
+            endpoint,
+            sender,
+            offset,
+            at,
+        });
+        offset
+    }
+
+    /// Stops propagating to the replica. Does nothing for a connection which isn't one.
+    pub(crate) fn unregister(&self, addr: SocketAddr) {
+        self.send(ReplicaEvent::Unregister(addr));
+    }
+
+    pub(crate) fn propagate(&self, msg: OutgoingMessage) {
+        let len: usize = msg.frames().iter().map(Bytes::len).sum();
+        self.offset.fetch_add(len, Ordering::Relaxed);
+        self.send(ReplicaEvent::Propagate(msg));
+    }
+
+    pub(crate) fn receive_ack(&self, addr: SocketAddr, offset: usize, at: u64) {
+        self.send(ReplicaEvent::Ack { addr, offset, at });
+    }
+
+    /// Asks the replicas for their offset, with a GETACK through the replication stream,
+    /// and returns a handle to wait for the unsynced ones.
+    pub(crate) async fn wait(&self) -> Option<WaitHandle> {
+        let (tx, rx) = oneshot::channel::<WaitHandle>();
+        self.offset
+            .fetch_add(getack().serialize().len(), Ordering::Relaxed);
+        self.send(ReplicaEvent::Wait(tx));
+        rx.await.ok()
+    }
+
+    pub(crate) async fn count(&self) -> usize {
+        let (tx, rx) = oneshot::channel::<usize>();
+        self.send(ReplicaEvent::Count(tx));
+        rx.await.unwrap_or_default()
+    }
+
+    /// The replicas, in the order they registered.
+    pub(crate) async fn list(&self) -> Vec<ReplicaInfo> {
+        let (tx, rx) = oneshot::channel::<Vec<ReplicaInfo>>();
+        self.send(ReplicaEvent::List(tx));
+        rx.await.unwrap_or_default()
+    }
+
+    fn send(&self, event: ReplicaEvent) {
+        if self.events.send(event).is_err() {
+            eprintln!("Replica task has stopped");
+        }
+    }
+}
+
+fn getack() -> Resp {
+    vec![
+        "REPLCONF".to_string(),
+        "GETACK".to_string(),
+        "*".to_string(),
+    ]
+    .into()
+}
+
+async fn run(mut rx: UnboundedReceiver<ReplicaEvent>) {
+    let mut replicas: HashMap<SocketAddr, Replica> = HashMap::new();
+    // Connection addresses in the order the replicas registered, for a stable listing.
+    let mut order: Vec<SocketAddr> = vec![];
+
+    while let Some(event) = rx.recv().await {
+        match event {
+            ReplicaEvent::Register {
+                addr,
+                endpoint: (ip, port),
+                sender,
+                offset,
+                at,
+            } => {
+                println!("Replica {ip}:{port} registered from {addr}");
+                replicas.insert(addr, Replica::new(sender, ip, port, offset, at));
+                order.retain(|a| *a != addr);
+                order.push(addr);
+            }
+            ReplicaEvent::Unregister(addr) => {
+                if let Some(replica) = replicas.remove(&addr) {
+                    println!("Replica {}:{} unregistered", replica.ip, replica.port);
+                }
+                order.retain(|a| *a != addr);
+            }
+            ReplicaEvent::Propagate(msg) => {
+                for (_, replica) in replicas.iter_mut() {
+                    replica.send(msg.clone()).await;
+                }
+            }
+            ReplicaEvent::Ack { addr, offset, at } => {
+                if let Some(replica) = replicas.get_mut(&addr) {
+                    replica.receive_ack(offset, at).await;
+                }
+            }
+            ReplicaEvent::Wait(reply) => {
+                let unsynced = replicas.values().filter(|r| !r.is_synced()).count();
+                let synced = replicas.len() - unsynced;
+
+                let (tx, rx) = mpsc::channel::<WaitSignal>(unsynced + 1);
+                for replica in replicas.values_mut().filter(|r| !r.is_synced()) {
+                    replica.add_wait_callback(tx.clone()).await;
+                }
+                // Like any message of the stream, GETACK goes to every replica, so that
+                // they all stay at the offset of the master.
+                for replica in replicas.values_mut() {
+                    replica.send(getack()).await;
+                }
+
+                if reply.send((synced, tx, rx)).is_err() {
+                    eprintln!("Receiver dropped before getting wait handle");
+                }
+            }
+            ReplicaEvent::Count(reply) => {
+                let _ = reply.send(replicas.len());
+            }
+            ReplicaEvent::List(reply) => {
+                let list = order
+                    .iter()
+                    .filter_map(|addr| replicas.get(addr))
+                    .map(Replica::info)
+                    .collect();
+                let _ = reply.send(list);
+            }
+        }
+    }
+}
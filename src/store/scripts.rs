@@ -0,0 +1,135 @@
+use super::{Client, RedisError, RedisResult, Resp, Store};
+use crate::{script, utils};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Scripts cached by their SHA-1 digest. Scripts reach replicas as the writes they make,
+/// so the cache is never replicated and replicas don't need the scripts to follow.
+#[derive(Debug, Default)]
+pub(crate) struct Scripts {
+    cache: Mutex<HashMap<String, Arc<str>>>,
+    /// Held while a script runs, so that scripts run one at a time.
+    lock: tokio::sync::Mutex<()>,
+    running: Mutex<Option<Arc<script::Running>>>,
+}
+
+impl Store {
+    /// Caches the script and returns its SHA-1 digest.
+    pub fn script_load(&self, source: &str) -> String {
+        let sha = utils::sha1_hex(source.as_bytes());
+        if let Ok(mut cache) = self.scripts.cache.lock() {
+            cache.entry(sha.clone()).or_insert_with(|| source.into());
+        }
+        sha
+    }
+
+    /// Which of the digests have a cached script.
+    pub fn script_exists(&self, shas: &[String]) -> Vec<bool> {
+        let Ok(cache) = self.scripts.cache.lock() else {
+            return vec![false; shas.len()];
+        };
+        shas.iter().map(|sha| cache.contains_key(sha)).collect()
+    }
+
+    pub fn script_flush(&self) {
+        if let Ok(mut cache) = self.scripts.cache.lock() {
+            cache.clear();
+        }
+    }
+
+    /// Runs the cached script of the digest, failing with NOSCRIPT once it's flushed. A
+    /// read-only script fails the write commands it calls.
+    pub async fn eval(
+        self: Arc<Self>,
+        sha: &str,
+        addr: SocketAddr,
+        keys: Vec<String>,
+        args: Vec<String>,
+        readonly: bool,
+    ) -> RedisResult<Resp> {
+        let source = self
+            .scripts
+            .cache
+            .lock()
+            .ok()
+            .and_then(|cache| cache.get(sha).cloned())
+            .ok_or(RedisError::NoScript)?;
+
+        let sha = sha.to_string();
+        self.run_script(addr, readonly, move |host| {
+            script::run(host, &sha, &source, keys, args)
+        })
+        .await
+    }
+
+    /// Runs a script on a thread of its own, one script at a time. Commands called from
+    /// the script run as a client of their own.
+    pub(crate) async fn run_script(
+        self: Arc<Self>,
+        addr: SocketAddr,
+        readonly: bool,
+        job: impl FnOnce(script::Host) -> RedisResult<Resp> + Send + 'static,
+    ) -> RedisResult<Resp> {
+        let _lock = self.scripts.lock.lock().await;
+        let running = Arc::new(script::Running::new(readonly));
+        let host = script::Host {
+            handle: tokio::runtime::Handle::current(),
+            store: Arc::clone(&self),
+            client: Arc::new(Client::new(addr, self.clock.unix_millis())),
+            running: Arc::clone(&running),
+        };
+
+        self.set_running(Some(running));
+        let reply = on_script_thread(move || job(host))
+            .await
+            .and_then(|reply| reply);
+        self.set_running(None);
+        reply
+    }
+
+    /// Whether a script has been running longer than `busy-script-time-limit`.
+    pub fn script_busy(&self) -> bool {
+        let limit = Duration::from_millis(self.config.busy_script_time_limit);
+        self.scripts
+            .running
+            .lock()
+            .is_ok_and(|running| running.as_ref().is_some_and(|r| r.elapsed() > limit))
+    }
+
+    /// Stops the running script, unless it has already written to the keyspace.
+    pub fn script_kill(&self) -> RedisResult<()> {
+        let running = self.scripts.running.lock().ok().and_then(|r| r.clone());
+        match running {
+            None => Err(RedisError::NotBusy),
+            Some(running) if running.wrote() => Err(anyhow::anyhow!(
+                "UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSCRIPT command."
+            )
+            .into()),
+            Some(running) => {
+                running.kill();
+                Ok(())
+            }
+        }
+    }
+
+    fn set_running(&self, running: Option<Arc<script::Running>>) {
+        if let Ok(mut current) = self.scripts.running.lock() {
+            *current = running;
+        }
+    }
+}
+
+/// Runs the closure on a script thread and waits for it without blocking the runtime.
+pub(crate) async fn on_script_thread<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> RedisResult<T> {
+    let (tx, rx) = oneshot::channel();
+    script::spawn(move || {
+        let _ = tx.send(f());
+    })?;
+    rx.await
+        .map_err(|_| anyhow::anyhow!("ERR script aborted").into())
+}
@@ -0,0 +1,156 @@
+use super::{keyspace::Shard, Rdb, RedisResult, Value};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// A point-in-time view of the keyspace. It shares the values with the keyspace, which
+/// copies a value only when it is written while the snapshot is alive, so taking one
+/// costs a pointer copy per key and serializing it holds no lock.
+#[derive(Debug)]
+pub(crate) struct Snapshot {
+    shards: Vec<Shard>,
+    /// The code of the function libraries, saved along with the keys.
+    functions: Vec<String>,
+    taken_at: SystemTime,
+}
+
+impl Snapshot {
+    pub(crate) fn new(shards: Vec<Shard>, functions: Vec<String>, taken_at: SystemTime) -> Self {
+        Self {
+            shards,
+            functions,
+            taken_at,
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.iter())
+            .map(|(key, value)| (key, value.as_ref()))
+    }
+
+    pub(crate) fn to_rdb(&self) -> Vec<u8> {
+        Rdb::dump(self.iter(), &self.functions, self.taken_at)
+    }
+
+    /// Writes the snapshot as an RDB file. It is written to a temporary file first and
+    /// renamed, so the file at `path` is never a partial one.
+    pub(crate) fn save(&self, path: &Path) -> RedisResult<()> {
+        let tmp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+        fs::write(&tmp, self.to_rdb())?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Bookkeeping of RDB saves, shared with the background save tasks.
+#[derive(Debug, Default)]
+pub(crate) struct SaveState {
+    in_progress: AtomicBool,
+    /// Changes to the keyspace since the last successful save.
+    dirty: AtomicU64,
+    /// Milliseconds since UNIX epoch of the last successful save.
+    lastsave: AtomicU64,
+}
+
+impl SaveState {
+    pub(crate) fn new(now: u64) -> Self {
+        Self {
+            lastsave: AtomicU64::new(now),
+            ..Default::default()
+        }
+    }
+
+    /// Marks a save as started. Returns false when another save is in progress.
+    pub(crate) fn begin(&self) -> bool {
+        !self.in_progress.swap(true, Ordering::SeqCst)
+    }
+
+    /// Marks the save started by `begin` as done. A successful save clears the changes
+    /// counted up to `dirty`, the counter when the snapshot was taken.
+    pub(crate) fn finish(&self, saved: bool, dirty: u64, taken_at: u64) {
+        if saved {
+            self.dirty.fetch_sub(dirty, Ordering::SeqCst);
+            self.lastsave.store(taken_at, Ordering::SeqCst);
+        }
+        self.in_progress.store(false, Ordering::SeqCst);
+    }
+
+    pub(crate) fn in_progress(&self) -> bool {
+        self.in_progress.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn incr_dirty(&self) {
+        self.dirty.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn dirty(&self) -> u64 {
+        self.dirty.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn lastsave(&self) -> u64 {
+        self.lastsave.load(Ordering::SeqCst)
+    }
+
+    /// Whether any of the `save` rules, pairs of seconds and changes, is met at `now`.
+    pub(crate) fn due(&self, rules: &[(u64, u64)], now: u64) -> bool {
+        let elapsed = now.saturating_sub(self.lastsave()) / 1000;
+        let dirty = self.dirty();
+        !self.in_progress()
+            && rules
+                .iter()
+                .any(|&(secs, changes)| dirty >= changes && elapsed >= secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn it_keeps_values_of_the_time_taken() {
+        let mut shard: Shard = HashMap::new();
+        let value = Value::String {
+            value: "bar".into(),
+            exp: None,
+        };
+        shard.insert("foo".into(), Arc::new(value));
+
+        let snapshot = Snapshot::new(vec![shard.clone()], vec![], SystemTime::now());
+
+        if let Some(Value::String { value, .. }) = shard.get_mut("foo").map(Arc::make_mut) {
+            *value = "baz".into();
+        }
+
+        let values: Vec<(&String, &Value)> = snapshot.iter().collect();
+        assert!(matches!(
+            values.as_slice(),
+            [(_, Value::String { value, .. })] if value == "bar"
+        ));
+    }
+
+    #[test]
+    fn it_checks_save_rules() {
+        let state = SaveState::new(0);
+        let rules = vec![(60, 2)];
+
+        state.incr_dirty();
+        assert!(!state.due(&rules, 60_000));
+
+        state.incr_dirty();
+        assert!(!state.due(&rules, 59_999));
+        assert!(state.due(&rules, 60_000));
+
+        assert!(state.begin());
+        assert!(!state.begin());
+        state.incr_dirty();
+        state.finish(true, 2, 60_000);
+        assert_eq!(state.dirty(), 1);
+        assert_eq!(state.lastsave(), 60_000);
+        assert!(!state.due(&rules, 120_000));
+    }
+}
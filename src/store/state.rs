@@ -0,0 +1,101 @@
+use super::{Command, RedisError, Store};
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+/// What the server is busy with besides serving commands, which decides whether a
+/// command runs when it's dispatched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerState {
+    Running,
+    /// The dataset is being loaded at startup.
+    Loading,
+    /// A script has run longer than `busy-script-time-limit`.
+    BusyScript,
+    /// Writes are held back while a replica takes over the slots of this master.
+    Pausing,
+    /// SHUTDOWN is saving the dataset before the server stops.
+    ShuttingDown,
+}
+
+impl ServerState {
+    /// The error the command gets in this state instead of running. Writes aren't
+    /// rejected while pausing, but wait for the pause to end.
+    pub fn rejection(self, cmd: &Command) -> Option<RedisError> {
+        match self {
+            Self::Loading if !cmd.allowed_while_loading() => Some(RedisError::Loading),
+            Self::BusyScript if !cmd.allowed_while_busy() => Some(RedisError::Busy),
+            // A write now would be missing from the saved dataset.
+            Self::ShuttingDown if cmd.is_write() => {
+                Some(anyhow::anyhow!("ERR Server is shutting down").into())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Store {
+    /// The state of the server, a shutdown going first, then the load, a busy script and
+    /// a pause of writes.
+    pub async fn server_state(&self) -> ServerState {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            ServerState::ShuttingDown
+        } else if self.is_loading() {
+            ServerState::Loading
+        } else if self.script_busy() {
+            ServerState::BusyScript
+        } else if self.writes_paused().await {
+            ServerState::Pausing
+        } else {
+            ServerState::Running
+        }
+    }
+
+    async fn writes_paused(&self) -> bool {
+        match &self.cluster {
+            Some(cluster) => cluster.lock().await.writes_paused(Instant::now()),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, Resp};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn it_gates_commands_by_the_state_of_the_server() {
+        let store = Arc::new(Store::new(&Config::new(vec![]).unwrap()).unwrap());
+        let cmd = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+            Command::new(Resp::from(args)).unwrap()
+        };
+        assert_eq!(store.server_state().await, ServerState::Running);
+
+        let loading = ServerState::Loading;
+        assert!(matches!(
+            loading.rejection(&cmd(&["GET", "a"])),
+            Some(RedisError::Loading)
+        ));
+        assert!(loading.rejection(&cmd(&["INFO"])).is_none());
+
+        let busy = ServerState::BusyScript;
+        assert!(matches!(
+            busy.rejection(&cmd(&["GET", "a"])),
+            Some(RedisError::Busy)
+        ));
+        assert!(busy.rejection(&cmd(&["SCRIPT", "KILL"])).is_none());
+        assert!(busy.rejection(&cmd(&["SHUTDOWN", "NOSAVE"])).is_none());
+
+        let shutting_down = ServerState::ShuttingDown;
+        assert!(shutting_down.rejection(&cmd(&["SET", "a", "1"])).is_some());
+        assert!(shutting_down.rejection(&cmd(&["GET", "a"])).is_none());
+        assert!(ServerState::Pausing
+            .rejection(&cmd(&["SET", "a", "1"]))
+            .is_none());
+
+        store.shutting_down.store(true, Ordering::SeqCst);
+        assert_eq!(store.server_state().await, ServerState::ShuttingDown);
+    }
+}
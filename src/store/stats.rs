@@ -0,0 +1,198 @@
+use super::latency::Latencies;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const STATS_METRIC_SAMPLES: usize = 16;
+
+/// Server statistics reported by INFO. CONFIG RESETSTAT zeroes them all.
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    commands: AtomicU64,
+    connections: AtomicU64,
+    error_replies: AtomicU64,
+    keyspace_hits: AtomicU64,
+    keyspace_misses: AtomicU64,
+    query_buffer_disconnections: AtomicU64,
+    query_timeout_disconnections: AtomicU64,
+    ops: Mutex<OpsSamples>,
+    latencies: Latencies,
+}
+
+/// Operations per second measured at each sampling, kept in a ring to average them.
+#[derive(Debug, Default)]
+struct OpsSamples {
+    last_time: u64,
+    last_commands: u64,
+    samples: [u64; STATS_METRIC_SAMPLES],
+    idx: usize,
+}
+
+impl Stats {
+    pub(crate) fn incr_commands(&self) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn commands(&self) -> u64 {
+        self.commands.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn incr_connections(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connections(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn incr_error_replies(&self) {
+        self.error_replies.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn error_replies(&self) -> u64 {
+        self.error_replies.load(Ordering::Relaxed)
+    }
+
+    /// Counts a lookup of a key by a command reading it.
+    pub(crate) fn record_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.keyspace_hits
+        } else {
+            &self.keyspace_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn keyspace_hits(&self) -> u64 {
+        self.keyspace_hits.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn keyspace_misses(&self) -> u64 {
+        self.keyspace_misses.load(Ordering::Relaxed)
+    }
+
+    /// Counts a client disconnected for sending a command larger than its query buffer
+    /// may grow to.
+    pub(crate) fn incr_query_buffer_disconnections(&self) {
+        self.query_buffer_disconnections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn query_buffer_disconnections(&self) -> u64 {
+        self.query_buffer_disconnections.load(Ordering::Relaxed)
+    }
+
+    /// Counts a client disconnected for taking too long to send the rest of a command.
+    pub(crate) fn incr_query_timeout_disconnections(&self) {
+        self.query_timeout_disconnections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn query_timeout_disconnections(&self) -> u64 {
+        self.query_timeout_disconnections.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_latency(&self, name: &'static str, elapsed: Duration) {
+        self.latencies.record(name, elapsed);
+    }
+
+    pub(crate) fn latency_percentiles(&self) -> Vec<String> {
+        self.latencies.info()
+    }
+
+    /// Zeroes every counter, and the samples of the ops per second and the latencies
+    /// along with them.
+    pub(crate) fn reset(&self) {
+        for counter in [
+            &self.commands,
+            &self.connections,
+            &self.error_replies,
+            &self.keyspace_hits,
+            &self.keyspace_misses,
+            &self.query_buffer_disconnections,
+            &self.query_timeout_disconnections,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        if let Ok(mut ops) = self.ops.lock() {
+            *ops = OpsSamples::default();
+        }
+        self.latencies.reset();
+    }
+
+    /// Records the commands per second processed since the previous sample.
+    pub(crate) fn sample(&self, now: u64) {
+        let commands = self.commands();
+        let Ok(mut ops) = self.ops.lock() else {
+            return;
+        };
+
+        if ops.last_time > 0 && now > ops.last_time {
+            let rate = (commands - ops.last_commands) * 1000 / (now - ops.last_time);
+            let idx = ops.idx;
+            ops.samples[idx] = rate;
+            ops.idx = (idx + 1) % STATS_METRIC_SAMPLES;
+        }
+        ops.last_time = now;
+        ops.last_commands = commands;
+    }
+
+    pub(crate) fn instantaneous_ops_per_sec(&self) -> u64 {
+        self.ops
+            .lock()
+            .map(|ops| ops.samples.iter().sum::<u64>() / STATS_METRIC_SAMPLES as u64)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_samples_ops_per_sec() {
+        let stats = Stats::default();
+        stats.sample(1000);
+
+        for n in 1..=STATS_METRIC_SAMPLES as u64 {
+            for _ in 0..10 {
+                stats.incr_commands();
+            }
+            stats.sample(1000 + n * 100);
+        }
+
+        assert_eq!(stats.commands(), 160);
+        assert_eq!(stats.instantaneous_ops_per_sec(), 100);
+    }
+
+    #[test]
+    fn it_resets_the_counters() {
+        let stats = Stats::default();
+        stats.sample(1000);
+        for _ in 0..10 {
+            stats.incr_commands();
+        }
+        stats.sample(1100);
+        stats.incr_connections();
+        stats.incr_error_replies();
+        stats.record_lookup(true);
+        stats.record_lookup(false);
+        stats.incr_query_buffer_disconnections();
+        stats.incr_query_timeout_disconnections();
+
+        stats.reset();
+        assert_eq!(stats.commands(), 0);
+        assert_eq!(stats.connections(), 0);
+        assert_eq!(stats.error_replies(), 0);
+        assert_eq!(stats.keyspace_hits(), 0);
+        assert_eq!(stats.query_buffer_disconnections(), 0);
+        assert_eq!(stats.query_timeout_disconnections(), 0);
+        assert_eq!(stats.keyspace_misses(), 0);
+        assert_eq!(stats.instantaneous_ops_per_sec(), 0);
+
+        // The first sample after a reset only sets the baseline.
+        stats.incr_commands();
+        stats.sample(1200);
+        assert_eq!(stats.instantaneous_ops_per_sec(), 0);
+    }
+}
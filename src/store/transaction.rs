@@ -1,18 +1,33 @@
 use super::Command;
 
 #[derive(Debug, Clone)]
-pub struct Transaction(Vec<Command>);
+pub struct Transaction {
+    commands: Vec<Command>,
+    /// Set once a command is rejected while queued, after which EXEC discards the rest.
+    aborted: bool,
+}
 
 impl Transaction {
     pub fn new() -> Self {
-        Self(vec![])
+        Self {
+            commands: vec![],
+            aborted: false,
+        }
     }
 
     pub fn push(&mut self, cmd: Command) {
-        self.0.push(cmd);
+        self.commands.push(cmd);
+    }
+
+    pub fn abort(&mut self) {
+        self.aborted = true;
+    }
+
+    pub fn aborted(&self) -> bool {
+        self.aborted
     }
 
     pub fn unwrap(self) -> Vec<Command> {
-        self.0
+        self.commands
     }
 }
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Versions taken from a single counter bumped by every write. Only the keys some client
+/// watches get versions of their own, which are forgotten once nobody watches them; any
+/// other key is at the version of the whole keyspace, so that nothing is kept of the
+/// keys written and deleted since the server started.
+#[derive(Debug, Default)]
+pub(crate) struct Versions(Mutex<Versioned>);
+
+#[derive(Debug, Default)]
+struct Versioned {
+    last: u64,
+    /// The version of each watched key along with how many watch it.
+    watched: HashMap<String, (u64, usize)>,
+}
+
+impl Versions {
+    pub(crate) fn bump(&self, key: &str) {
+        if let Ok(mut versioned) = self.0.lock() {
+            versioned.last += 1;
+            let version = versioned.last;
+            if let Some((watched, _)) = versioned.watched.get_mut(key) {
+                *watched = version;
+            }
+        }
+    }
+
+    /// The version given last, which changes whenever any key is written.
+    pub(crate) fn last(&self) -> u64 {
+        self.0
+            .lock()
+            .map(|versioned| versioned.last)
+            .unwrap_or_default()
+    }
+
+    /// Changes only when the key is written while it's watched, and whenever any key is
+    /// otherwise.
+    pub(crate) fn get(&self, key: &str) -> u64 {
+        self.0
+            .lock()
+            .map(|versioned| {
+                versioned
+                    .watched
+                    .get(key)
+                    .map_or(versioned.last, |(version, _)| *version)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Keeps the version of the key until it's unwatched as many times, and returns it.
+    pub(crate) fn watch(&self, key: &str) -> u64 {
+        self.0
+            .lock()
+            .map(|mut versioned| {
+                let last = versioned.last;
+                let (version, watchers) = versioned.watched.entry(key.into()).or_insert((last, 0));
+                *watchers += 1;
+                *version
+            })
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn unwatch(&self, key: &str) {
+        if let Ok(mut versioned) = self.0.lock() {
+            if let Some((_, watchers)) = versioned.watched.get_mut(key) {
+                *watchers -= 1;
+                if *watchers == 0 {
+                    versioned.watched.remove(key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_versions_watched_keys() {
+        let versions = Versions::default();
+        assert_eq!(versions.get("foo"), 0);
+
+        let foo = versions.watch("foo");
+        versions.watch("foo");
+        versions.bump("bar");
+        assert_eq!(versions.get("foo"), foo);
+        assert_eq!(versions.get("bar"), versions.last());
+
+        versions.bump("foo");
+        assert!(versions.get("foo") > foo);
+        assert_eq!(versions.last(), versions.get("foo"));
+
+        // Forgotten once unwatched as many times as watched.
+        versions.unwatch("foo");
+        assert_eq!(versions.0.lock().unwrap().watched.len(), 1);
+        versions.unwatch("foo");
+        assert!(versions.0.lock().unwrap().watched.is_empty());
+        versions.bump("bar");
+        assert_eq!(versions.get("foo"), versions.last());
+    }
+}
@@ -0,0 +1,1338 @@
+//! Servers run inside the test process, for tests covering several nodes at once.
+
+use super::{
+    bind_listeners, rdb::Rdb, resp::RespError, utils::Tokens, CommandMode, Config, Connection,
+    ConnectionManager, RedisError, RedisResult, Resp, Store,
+};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+/// How long the harness polls for a condition before giving up.
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A server listening on a free port of the loopback, served as `serve` in main does.
+pub(crate) struct Node {
+    pub(crate) store: Arc<Store>,
+    pub(crate) addr: SocketAddr,
+    accept: JoinHandle<()>,
+}
+
+impl Node {
+    /// Starts a node with the given config arguments. `--port` is picked by the harness.
+    pub(crate) async fn start(args: &[&str]) -> RedisResult<Self> {
+        let listener = bind_listeners("127.0.0.1:0".parse().unwrap(), 1)?.remove(0);
+        let addr = listener.local_addr()?;
+        let mut argv = vec!["--port".to_string(), addr.port().to_string()];
+        argv.extend(args.iter().map(|arg| arg.to_string()));
+        let config = Config::new(argv)?;
+        let store = Arc::new(Store::new(&config)?);
+        store.load().await?;
+        tokio::spawn(Arc::clone(&store).cron());
+
+        if let Some(master) = config.master_addr() {
+            let stream = TcpStream::connect(master).await?;
+            Connection::new(stream, CommandMode::Sync)
+                .start_streaming(&store)
+                .await?;
+        }
+
+        let mut manager = ConnectionManager::new(Arc::clone(&store), config.maxclients);
+        let accept = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if manager.accept(stream).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            store,
+            addr,
+            accept,
+        })
+    }
+
+    pub(crate) async fn connect(&self) -> RedisResult<Client> {
+        let stream = TcpStream::connect(self.addr).await?;
+        Ok(Client {
+            stream,
+            buf: vec![],
+        })
+    }
+
+    /// Sends one command on a connection of its own.
+    pub(crate) async fn call(&self, args: &[&str]) -> RedisResult<Resp> {
+        self.connect().await?.call(args).await
+    }
+
+    /// Every live key with its value encoded as in an RDB file, so that two keyspaces
+    /// compare equal only when their keys, values and expiries all do.
+    pub(crate) async fn keyspace(&self) -> BTreeMap<String, Vec<u8>> {
+        let mut keyspace = BTreeMap::new();
+        for key in self.store.keys("*").await {
+            if let Some(value) = self.store.get(&key).await {
+                let dump = Rdb::dump(std::iter::once((&key, value.as_ref())), &[], UNIX_EPOCH);
+                keyspace.insert(key, dump);
+            }
+        }
+        keyspace
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.accept.abort();
+    }
+}
+
+/// A connection to a node, reading whole replies.
+pub(crate) struct Client {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    pub(crate) async fn call(&mut self, args: &[&str]) -> RedisResult<Resp> {
+        let cmd: Resp = args
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<String>>()
+            .into();
+        self.stream.write_all(&cmd.serialize()).await?;
+
+        let mut chunk = [0u8; 4096];
+        loop {
+            let mut tokens = Tokens::new(&self.buf);
+            match Resp::from_tokens(&mut tokens) {
+                Ok(reply) => {
+                    let consumed = self.buf.len() - tokens.rest().len();
+                    self.buf.drain(..consumed);
+                    return Ok(reply);
+                }
+                Err(RedisError::Protocol(RespError::Incomplete)) => {}
+                Err(err) => return Err(err),
+            }
+            let read = self.stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Err(anyhow::anyhow!("The node closed the connection").into());
+            }
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+/// A master with replicas attached to it, each one a [`Node`] of the test process.
+pub(crate) struct ReplicationGroup {
+    pub(crate) master: Node,
+    pub(crate) replicas: Vec<Node>,
+}
+
+impl ReplicationGroup {
+    /// Starts a master and `count` replicas of it, returning once the master has taken
+    /// every replica in.
+    pub(crate) async fn start(count: usize) -> RedisResult<Self> {
+        Self::start_with(count, &[]).await
+    }
+
+    /// Starts the group with the master configured by the given arguments.
+    pub(crate) async fn start_with(count: usize, master_args: &[&str]) -> RedisResult<Self> {
+        let master = Node::start(master_args).await?;
+        let replicaof = format!("127.0.0.1 {}", master.addr.port());
+        let mut replicas = vec![];
+        for _ in 0..count {
+            replicas.push(Node::start(&["--replicaof", &replicaof]).await?);
+        }
+
+        let group = Self { master, replicas };
+        let store = &group.master.store;
+        settle("replicas to sync", || async {
+            store.num_of_replicas().await == count
+        })
+        .await?;
+        Ok(group)
+    }
+
+    /// Waits until every replica holds the keyspace of the master.
+    pub(crate) async fn converge(&self) -> RedisResult<()> {
+        let converged = settle("replicas to converge", || async {
+            let expected = self.master.keyspace().await;
+            for replica in self.replicas.iter() {
+                if replica.keyspace().await != expected {
+                    return false;
+                }
+            }
+            true
+        })
+        .await;
+        if converged.is_err() {
+            let master = self.master.keyspace().await;
+            for (i, replica) in self.replicas.iter().enumerate() {
+                let keys: Vec<String> = replica.keyspace().await.into_keys().collect();
+                eprintln!("replica {i} has {keys:?}, master has {:?}", master.keys());
+            }
+        }
+        converged
+    }
+}
+
+/// Polls the condition until it holds, failing with what was awaited after a while.
+pub(crate) async fn settle<F, Fut>(what: &str, mut condition: F) -> RedisResult<()>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    let deadline = Instant::now() + SETTLE_TIMEOUT;
+    while !condition().await {
+        if Instant::now() >= deadline {
+            return Err(anyhow::anyhow!("Timed out waiting for {what}").into());
+        }
+        time::sleep(Duration::from_millis(10)).await;
+    }
+    Ok(())
+}
+
+mod tests {
+    use super::*;
+    use crate::BUF_SIZE;
+
+    #[tokio::test]
+    async fn it_propagates_writes_to_every_replica() {
+        let group = ReplicationGroup::start(2).await.unwrap();
+        let master = &group.master;
+
+        assert_eq!(
+            master.call(&["SET", "foo", "bar"]).await.unwrap(),
+            Resp::SS("OK".into())
+        );
+        master
+            .call(&["SET", "tmp", "1", "PX", "60000"])
+            .await
+            .unwrap();
+        master.call(&["INCR", "counter"]).await.unwrap();
+        master.call(&["INCR", "counter"]).await.unwrap();
+        master.call(&["XADD", "s", "1-1", "a", "1"]).await.unwrap();
+        master.call(&["DEL", "foo"]).await.unwrap();
+        group.converge().await.unwrap();
+
+        let replica = &group.replicas[0];
+        assert_eq!(
+            replica.call(&["GET", "counter"]).await.unwrap(),
+            Resp::BS(Some("2".into()))
+        );
+        assert_eq!(replica.call(&["GET", "foo"]).await.unwrap(), Resp::BS(None));
+        assert_eq!(replica.keyspace().await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn it_takes_commands_larger_than_a_read() {
+        let group = ReplicationGroup::start(1).await.unwrap();
+        let master = &group.master;
+        let mut client = master.connect().await.unwrap();
+
+        let value = "x".repeat(100 * BUF_SIZE);
+        client.call(&["SET", "big", &value]).await.unwrap();
+        client.call(&["SET", "small", "y"]).await.unwrap();
+        group.converge().await.unwrap();
+
+        let expected = Resp::BS(Some(value));
+        assert_eq!(client.call(&["GET", "big"]).await.unwrap(), expected);
+        assert_eq!(
+            client.call(&["GET", "small"]).await.unwrap(),
+            Resp::BS(Some("y".into()))
+        );
+        let replica = &group.replicas[0];
+        assert_eq!(replica.call(&["GET", "big"]).await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn it_refuses_bulk_strings_over_the_limit() {
+        let node = Node::start(&["--proto-max-bulk-len", "1mb"]).await.unwrap();
+        let mut client = node.connect().await.unwrap();
+
+        let value = "x".repeat(1024 * 1024);
+        client.call(&["SET", "k", &value]).await.unwrap();
+        let reply = client
+            .call(&["SETRANGE", "k", "1048576", "y"])
+            .await
+            .unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.contains("proto-max-bulk-len")));
+
+        // The length alone is refused, without waiting for the bytes.
+        let header = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1048577\r\n";
+        client.stream.write_all(header).await.unwrap();
+        let mut reply = vec![];
+        client.stream.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, b"-ERR Protocol error: invalid bulk length\r\n");
+
+        assert_eq!(
+            node.call(&["GET", "k"]).await.unwrap(),
+            Resp::BS(Some(value))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_disconnects_clients_sending_what_isnt_resp() {
+        let node = Node::start(&[]).await.unwrap();
+        let mut client = node.connect().await.unwrap();
+
+        client.stream.write_all(b"PING\r\n").await.unwrap();
+        let mut reply = vec![];
+        client.stream.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(
+            reply,
+            b"-ERR Protocol error: expected a type byte, got 'P'\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_disconnects_clients_over_the_query_limits() {
+        let node = Node::start(&[
+            "--client-query-buffer-limit",
+            "1mb",
+            "--client-query-timeout",
+            "100",
+        ])
+        .await
+        .unwrap();
+
+        // Commands sent whole may be as far apart as clients like.
+        let mut client = node.connect().await.unwrap();
+        client.call(&["PING"]).await.unwrap();
+        time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(
+            client.call(&["PING"]).await.unwrap(),
+            Resp::SS("PONG".into())
+        );
+
+        let mut client = node.connect().await.unwrap();
+        client.stream.write_all(b"*1\r\n$4\r\nPI").await.unwrap();
+        let mut reply = vec![];
+        client.stream.read_to_end(&mut reply).await.unwrap();
+        assert!(reply.is_empty());
+        assert_eq!(node.store.query_timeout_disconnections(), 1);
+
+        let mut client = node.connect().await.unwrap();
+        let command = [
+            b"*2\r\n$4\r\nECHO\r\n$2000000\r\n".as_slice(),
+            &[b'x'; 1100 * 1024],
+        ];
+        // The node may close the connection before it has read everything.
+        let _ = client.stream.write_all(&command.concat()).await;
+        let mut reply = vec![];
+        let _ = client.stream.read_to_end(&mut reply).await;
+        assert!(reply.is_empty());
+        settle("the client to be disconnected", || async {
+            node.store.query_buffer_disconnections() == 1
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_keeps_lists() {
+        let group = ReplicationGroup::start(1).await.unwrap();
+        let mut client = group.master.connect().await.unwrap();
+        let elements = |values: &[&str]| {
+            Resp::A(
+                values
+                    .iter()
+                    .map(|v| Resp::BS(Some(v.to_string())))
+                    .collect(),
+            )
+        };
+
+        assert_eq!(
+            client.call(&["RPUSH", "l", "b", "c"]).await.unwrap(),
+            Resp::I(2)
+        );
+        assert_eq!(
+            client.call(&["LPUSH", "l", "a", "z"]).await.unwrap(),
+            Resp::I(4)
+        );
+        assert_eq!(
+            client.call(&["LRANGE", "l", "0", "-1"]).await.unwrap(),
+            elements(&["z", "a", "b", "c"])
+        );
+        assert_eq!(
+            client.call(&["LRANGE", "l", "-3", "1"]).await.unwrap(),
+            elements(&["a"])
+        );
+        assert_eq!(
+            client.call(&["LRANGE", "l", "2", "100"]).await.unwrap(),
+            elements(&["b", "c"])
+        );
+        assert_eq!(
+            client
+                .call(&["LRANGE", "missing", "0", "-1"])
+                .await
+                .unwrap(),
+            elements(&[])
+        );
+        assert_eq!(
+            client.call(&["TYPE", "l"]).await.unwrap(),
+            Resp::SS("list".into())
+        );
+        assert_eq!(
+            client.call(&["OBJECT", "ENCODING", "l"]).await.unwrap(),
+            Resp::BS(Some("listpack".into()))
+        );
+
+        client.call(&["SET", "s", "v"]).await.unwrap();
+        let reply = client.call(&["RPUSH", "s", "x"]).await.unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.starts_with("WRONGTYPE")));
+
+        // A string past its expiry is gone for the pushes too.
+        for push in ["LPUSH", "RPUSH"] {
+            client.call(&["SET", "e", "v", "PX", "50"]).await.unwrap();
+            time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(client.call(&[push, "e", "x"]).await.unwrap(), Resp::I(1));
+            client.call(&["DEL", "e"]).await.unwrap();
+        }
+
+        assert_eq!(
+            client.call(&["LPOP", "l"]).await.unwrap(),
+            Resp::BS(Some("z".into()))
+        );
+        assert_eq!(
+            client.call(&["RPOP", "l", "1"]).await.unwrap(),
+            elements(&["c"])
+        );
+        assert_eq!(
+            client.call(&["LPOP", "missing"]).await.unwrap(),
+            Resp::BS(None)
+        );
+        assert_eq!(
+            client.call(&["RPOP", "missing", "2"]).await.unwrap(),
+            Resp::NullArray
+        );
+
+        client.call(&["RPUSH", "q", "1", "2"]).await.unwrap();
+        assert_eq!(
+            client.call(&["RPOP", "q", "5"]).await.unwrap(),
+            elements(&["2", "1"])
+        );
+        assert_eq!(
+            client.call(&["TYPE", "q"]).await.unwrap(),
+            Resp::SS("none".into())
+        );
+
+        client
+            .call(&["RPUSH", "e", "a", "b", "a", "c", "a"])
+            .await
+            .unwrap();
+        assert_eq!(
+            client
+                .call(&["LINSERT", "e", "AFTER", "b", "x"])
+                .await
+                .unwrap(),
+            Resp::I(6)
+        );
+        assert_eq!(
+            client
+                .call(&["LINSERT", "e", "BEFORE", "y", "x"])
+                .await
+                .unwrap(),
+            Resp::I(-1)
+        );
+        assert_eq!(
+            client
+                .call(&["LINSERT", "missing", "BEFORE", "a", "x"])
+                .await
+                .unwrap(),
+            Resp::I(0)
+        );
+        assert_eq!(
+            client.call(&["LREM", "e", "-2", "a"]).await.unwrap(),
+            Resp::I(2)
+        );
+        assert_eq!(
+            client.call(&["LSET", "e", "-1", "z"]).await.unwrap(),
+            Resp::SS("OK".into())
+        );
+        assert_eq!(
+            client.call(&["LRANGE", "e", "0", "-1"]).await.unwrap(),
+            elements(&["a", "b", "x", "z"])
+        );
+        let reply = client.call(&["LSET", "e", "4", "z"]).await.unwrap();
+        assert_eq!(reply, Resp::SE("ERR index out of range".into()));
+        let reply = client.call(&["LSET", "missing", "0", "z"]).await.unwrap();
+        assert_eq!(reply, Resp::SE("ERR no such key".into()));
+        assert_eq!(
+            client.call(&["LTRIM", "e", "1", "-2"]).await.unwrap(),
+            Resp::SS("OK".into())
+        );
+        client.call(&["RPUSH", "t", "1"]).await.unwrap();
+        client.call(&["LTRIM", "t", "1", "-1"]).await.unwrap();
+
+        group.converge().await.unwrap();
+        let replica = &group.replicas[0];
+        assert_eq!(
+            replica.call(&["LRANGE", "e", "0", "-1"]).await.unwrap(),
+            elements(&["b", "x"])
+        );
+        assert_eq!(
+            replica.call(&["TYPE", "t"]).await.unwrap(),
+            Resp::SS("none".into())
+        );
+        assert_eq!(replica.call(&["LLEN", "l"]).await.unwrap(), Resp::I(2));
+        assert_eq!(
+            replica.call(&["LRANGE", "l", "0", "-1"]).await.unwrap(),
+            elements(&["a", "b"])
+        );
+        assert_eq!(
+            replica.call(&["TYPE", "q"]).await.unwrap(),
+            Resp::SS("none".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn it_increments_and_picks_hash_fields() {
+        let group = ReplicationGroup::start(1).await.unwrap();
+        let mut client = group.master.connect().await.unwrap();
+        let bs = |v: &str| Resp::BS(Some(v.into()));
+
+        assert_eq!(
+            client.call(&["HINCRBY", "h", "n", "5"]).await.unwrap(),
+            Resp::I(5)
+        );
+        assert_eq!(
+            client.call(&["HINCRBY", "h", "n", "-7"]).await.unwrap(),
+            Resp::I(-2)
+        );
+        assert_eq!(
+            client
+                .call(&["HINCRBYFLOAT", "h", "f", "10.5"])
+                .await
+                .unwrap(),
+            bs("10.5")
+        );
+        assert_eq!(
+            client
+                .call(&["HINCRBYFLOAT", "h", "f", "0.1"])
+                .await
+                .unwrap(),
+            bs("10.6")
+        );
+        assert_eq!(
+            client.call(&["HINCRBYFLOAT", "h", "n", "2"]).await.unwrap(),
+            bs("0")
+        );
+        for (by, sum) in [("0.1", "0.1"), ("0.2", "0.3"), ("1e17", "1e+17")] {
+            assert_eq!(
+                client.call(&["HINCRBYFLOAT", "hf", "f", by]).await.unwrap(),
+                bs(sum)
+            );
+        }
+        assert_eq!(
+            client
+                .call(&["HSET", "hs", "a", "1", "b", "2"])
+                .await
+                .unwrap(),
+            Resp::I(2)
+        );
+        assert_eq!(
+            client
+                .call(&["HSET", "hs", "a", "3", "c", "4"])
+                .await
+                .unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(
+            client.call(&["HINCRBY", "h", "f", "1"]).await.unwrap(),
+            Resp::SE("ERR hash value is not an integer".into())
+        );
+        client
+            .call(&["HINCRBY", "h", "max", &i64::MAX.to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            client.call(&["HINCRBY", "h", "max", "1"]).await.unwrap(),
+            Resp::SE("ERR increment or decrement would overflow".into())
+        );
+        assert_eq!(
+            client.call(&["TYPE", "h"]).await.unwrap(),
+            Resp::SS("hash".into())
+        );
+        client.call(&["SET", "s", "v"]).await.unwrap();
+        let reply = client.call(&["HINCRBY", "s", "n", "1"]).await.unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.starts_with("WRONGTYPE")));
+
+        let Resp::BS(Some(field)) = client.call(&["HRANDFIELD", "h"]).await.unwrap() else {
+            panic!("HRANDFIELD should reply with a field");
+        };
+        assert!(["n", "f", "max"].contains(&field.as_str()));
+        let Resp::A(fields) = client.call(&["HRANDFIELD", "h", "5"]).await.unwrap() else {
+            panic!("HRANDFIELD with a count should reply with an array");
+        };
+        assert_eq!(fields.len(), 3);
+        let Resp::A(fields) = client.call(&["HRANDFIELD", "h", "-5"]).await.unwrap() else {
+            panic!("HRANDFIELD with a count should reply with an array");
+        };
+        assert_eq!(fields.len(), 5);
+        for count in [i64::MIN.to_string(), (-(1i64 << 40)).to_string()] {
+            assert_eq!(
+                client.call(&["HRANDFIELD", "h", &count]).await.unwrap(),
+                Resp::SE("ERR value is out of range".into())
+            );
+        }
+        let Resp::A(pairs) = client
+            .call(&["HRANDFIELD", "h", "1", "WITHVALUES"])
+            .await
+            .unwrap()
+        else {
+            panic!("HRANDFIELD WITHVALUES should reply with an array");
+        };
+        assert!([
+            vec![bs("n"), bs("0")],
+            vec![bs("f"), bs("10.6")],
+            vec![bs("max"), bs(&i64::MAX.to_string())],
+        ]
+        .contains(&pairs));
+        assert_eq!(
+            client.call(&["HRANDFIELD", "missing"]).await.unwrap(),
+            Resp::BS(None)
+        );
+        assert_eq!(
+            client.call(&["HRANDFIELD", "missing", "2"]).await.unwrap(),
+            Resp::A(vec![])
+        );
+
+        client.call(&["HELLO", "3"]).await.unwrap();
+        let Resp::A(pairs) = client
+            .call(&["HRANDFIELD", "h", "2", "WITHVALUES"])
+            .await
+            .unwrap()
+        else {
+            panic!("HRANDFIELD WITHVALUES should reply with an array");
+        };
+        assert!(pairs
+            .iter()
+            .all(|pair| matches!(pair, Resp::A(pair) if pair.len() == 2)));
+
+        group.converge().await.unwrap();
+        let replica = &group.replicas[0];
+        let Resp::A(flat) = replica
+            .call(&["HRANDFIELD", "h", "5", "WITHVALUES"])
+            .await
+            .unwrap()
+        else {
+            panic!("HRANDFIELD WITHVALUES should reply with an array");
+        };
+        let mut pairs: Vec<&[Resp]> = flat.chunks(2).collect();
+        pairs.sort_by_key(|pair| format!("{:?}", pair[0]));
+        assert_eq!(
+            pairs,
+            [
+                &[bs("f"), bs("10.6")][..],
+                &[bs("max"), bs(&i64::MAX.to_string())][..],
+                &[bs("n"), bs("0")][..],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_expires_hash_fields() {
+        let group = ReplicationGroup::start(1).await.unwrap();
+        let mut client = group.master.connect().await.unwrap();
+        let ints = |nums: &[i64]| Resp::A(nums.iter().copied().map(Resp::I).collect());
+        for (field, n) in [("a", "1"), ("b", "2"), ("c", "3")] {
+            client.call(&["HINCRBY", "h", field, n]).await.unwrap();
+        }
+
+        assert_eq!(
+            client
+                .call(&["HEXPIRE", "h", "100", "FIELDS", "3", "a", "b", "x"])
+                .await
+                .unwrap(),
+            ints(&[1, 1, -2])
+        );
+        assert_eq!(
+            client
+                .call(&["HEXPIRE", "h", "100", "NX", "FIELDS", "1", "a"])
+                .await
+                .unwrap(),
+            ints(&[0])
+        );
+        assert_eq!(
+            client
+                .call(&["HTTL", "h", "FIELDS", "2", "a", "c"])
+                .await
+                .unwrap(),
+            ints(&[100, -1])
+        );
+        assert_eq!(
+            client
+                .call(&["HPERSIST", "h", "FIELDS", "3", "b", "c", "x"])
+                .await
+                .unwrap(),
+            ints(&[1, -1, -2])
+        );
+        assert_eq!(
+            client
+                .call(&["HPEXPIRE", "h", "500", "LT", "FIELDS", "1", "a"])
+                .await
+                .unwrap(),
+            ints(&[1])
+        );
+        assert_eq!(
+            client
+                .call(&["HEXPIRE", "h", "0", "FIELDS", "1", "b"])
+                .await
+                .unwrap(),
+            ints(&[2])
+        );
+        assert_eq!(
+            client.call(&["OBJECT", "ENCODING", "h"]).await.unwrap(),
+            Resp::BS(Some("listpackex".into()))
+        );
+        assert_eq!(
+            client
+                .call(&["HTTL", "missing", "FIELDS", "1", "a"])
+                .await
+                .unwrap(),
+            ints(&[-2])
+        );
+
+        group.converge().await.unwrap();
+        let replica = &group.replicas[0];
+        let Resp::A(ttls) = replica
+            .call(&["HPTTL", "h", "FIELDS", "1", "a"])
+            .await
+            .unwrap()
+        else {
+            panic!("HPTTL should reply with an array");
+        };
+        assert!(matches!(ttls.as_slice(), [Resp::I(1..=500)]));
+        assert_eq!(
+            client
+                .call(&["HINCRBYFLOAT", "h", "a", "0.5"])
+                .await
+                .unwrap(),
+            Resp::BS(Some("1.5".into()))
+        );
+        group.converge().await.unwrap();
+
+        time::sleep(Duration::from_millis(600)).await;
+        let bs = |v: &str| Resp::BS(Some(v.into()));
+        assert_eq!(
+            client.call(&["HGETALL", "h"]).await.unwrap(),
+            Resp::A(vec![bs("c"), bs("3")])
+        );
+        assert_eq!(
+            client.call(&["HINCRBY", "h", "a", "1"]).await.unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(
+            client.call(&["HDEL", "h", "a", "c", "x"]).await.unwrap(),
+            Resp::I(2)
+        );
+        assert_eq!(
+            client.call(&["TYPE", "h"]).await.unwrap(),
+            Resp::SS("none".into())
+        );
+        group.converge().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_keeps_sets() {
+        let group = ReplicationGroup::start(1).await.unwrap();
+        let mut client = group.master.connect().await.unwrap();
+        let bs = |v: &str| Resp::BS(Some(v.into()));
+
+        assert_eq!(
+            client.call(&["SADD", "s", "a", "b", "a"]).await.unwrap(),
+            Resp::I(2)
+        );
+        assert_eq!(
+            client.call(&["SADD", "s", "b", "c"]).await.unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(client.call(&["SCARD", "s"]).await.unwrap(), Resp::I(3));
+        assert_eq!(
+            client.call(&["SISMEMBER", "s", "c"]).await.unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(
+            client.call(&["SISMEMBER", "s", "x"]).await.unwrap(),
+            Resp::I(0)
+        );
+        assert_eq!(
+            client.call(&["SREM", "s", "a", "x"]).await.unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(
+            client.call(&["TYPE", "s"]).await.unwrap(),
+            Resp::SS("set".into())
+        );
+        assert_eq!(
+            client.call(&["OBJECT", "ENCODING", "s"]).await.unwrap(),
+            bs("listpack")
+        );
+        assert_eq!(
+            client.call(&["SCARD", "missing"]).await.unwrap(),
+            Resp::I(0)
+        );
+        assert_eq!(
+            client.call(&["SMEMBERS", "missing"]).await.unwrap(),
+            Resp::A(vec![])
+        );
+        client.call(&["SET", "str", "v"]).await.unwrap();
+        let reply = client.call(&["SADD", "str", "a"]).await.unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.starts_with("WRONGTYPE")));
+
+        // A string past its expiry is a missing key to both.
+        client.call(&["SET", "e1", "v", "PX", "50"]).await.unwrap();
+        client.call(&["SET", "e2", "v", "PX", "50"]).await.unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(client.call(&["SADD", "e1", "a"]).await.unwrap(), Resp::I(1));
+        assert_eq!(client.call(&["SREM", "e2", "a"]).await.unwrap(), Resp::I(0));
+
+        client.call(&["SADD", "gone", "x"]).await.unwrap();
+        client.call(&["SREM", "gone", "x"]).await.unwrap();
+        assert_eq!(
+            client.call(&["TYPE", "gone"]).await.unwrap(),
+            Resp::SS("none".into())
+        );
+
+        group.converge().await.unwrap();
+        let replica = &group.replicas[0];
+        let Resp::A(mut members) = replica.call(&["SMEMBERS", "s"]).await.unwrap() else {
+            panic!("SMEMBERS should reply with an array over RESP2");
+        };
+        members.sort_by_key(|member| format!("{member:?}"));
+        assert_eq!(members, vec![bs("b"), bs("c")]);
+
+        client.call(&["HELLO", "3"]).await.unwrap();
+        let reply = client.call(&["SMEMBERS", "s"]).await.unwrap();
+        assert!(matches!(reply, Resp::Set(members) if members.len() == 2));
+    }
+
+    #[tokio::test]
+    async fn it_keeps_every_connection_on_the_only_database() {
+        let node = Node::start(&[]).await.unwrap();
+        let mut client = node.connect().await.unwrap();
+        let mut other = node.connect().await.unwrap();
+        client.call(&["SET", "k", "v"]).await.unwrap();
+        client.call(&["RPUSH", "l", "a"]).await.unwrap();
+
+        // The replies of every command reading the keyspace, which a connection trying
+        // another database must keep getting.
+        let reads: &[&[&str]] = &[
+            &["GET", "k"],
+            &["TYPE", "l"],
+            &["LRANGE", "l", "0", "-1"],
+            &["KEYS", "*"],
+            &["SCAN", "0", "COUNT", "100"],
+        ];
+        let mut expected = vec![];
+        for args in reads {
+            expected.push(other.call(args).await.unwrap());
+        }
+
+        for index in ["1", "15", "-1"] {
+            assert_eq!(
+                other.call(&["SELECT", index]).await.unwrap(),
+                Resp::SE("ERR DB index is out of range".into())
+            );
+        }
+        other.call(&["MULTI"]).await.unwrap();
+        other.call(&["SELECT", "1"]).await.unwrap();
+        other.call(&["EXEC"]).await.unwrap();
+        for (args, expected) in reads.iter().zip(expected.iter()) {
+            assert_eq!(&other.call(args).await.unwrap(), expected, "{args:?}");
+        }
+        assert_eq!(
+            other.call(&["SELECT", "0"]).await.unwrap(),
+            Resp::SS("OK".into())
+        );
+        assert_eq!(
+            other.call(&["GET", "k"]).await.unwrap(),
+            Resp::BS(Some("v".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_blocks_list_pops_until_an_element_is_pushed() {
+        let node = Node::start(&[]).await.unwrap();
+        let mut client = node.connect().await.unwrap();
+        let mut other = node.connect().await.unwrap();
+        let popped = |key: &str, element: &str| {
+            Resp::A(vec![
+                Resp::BS(Some(key.into())),
+                Resp::BS(Some(element.into())),
+            ])
+        };
+
+        assert_eq!(
+            client.call(&["BLPOP", "a", "b", "0.01"]).await.unwrap(),
+            Resp::NullArray
+        );
+        other.call(&["RPUSH", "b", "x", "y"]).await.unwrap();
+        assert_eq!(
+            client.call(&["BRPOP", "a", "b", "0"]).await.unwrap(),
+            popped("b", "y")
+        );
+
+        let blocked = tokio::spawn(async move {
+            let reply = client.call(&["BLPOP", "a", "0"]).await;
+            (client, reply)
+        });
+        settle("the client to block", || async {
+            node.store.blocked_clients() == 1
+        })
+        .await
+        .unwrap();
+        other.call(&["LPUSH", "a", "z"]).await.unwrap();
+        let (mut client, reply) = blocked.await.unwrap();
+        assert_eq!(reply.unwrap(), popped("a", "z"));
+        assert_eq!(
+            client.call(&["TYPE", "a"]).await.unwrap(),
+            Resp::SS("none".into())
+        );
+
+        // Transactions never block.
+        client.call(&["MULTI"]).await.unwrap();
+        client.call(&["BLPOP", "a", "0"]).await.unwrap();
+        assert_eq!(
+            client.call(&["EXEC"]).await.unwrap(),
+            Resp::A(vec![Resp::NullArray])
+        );
+    }
+
+    #[tokio::test]
+    async fn it_replies_in_the_protocol_the_client_negotiated() {
+        let node = Node::start(&[]).await.unwrap();
+        let mut client = node.connect().await.unwrap();
+
+        let hello = client.call(&["HELLO"]).await.unwrap();
+        assert!(matches!(hello, Resp::A(fields) if fields[5] == Resp::I(2)));
+        assert_eq!(
+            client.call(&["CONFIG", "GET", "appendonly"]).await.unwrap(),
+            Resp::A(vec![
+                Resp::BS(Some("appendonly".into())),
+                Resp::BS(Some("no".into()))
+            ])
+        );
+
+        let hello = client.call(&["HELLO", "3"]).await.unwrap();
+        assert!(matches!(&hello, Resp::Map(fields) if fields[2].1 == Resp::I(3)));
+        assert_eq!(
+            client.call(&["CONFIG", "GET", "appendonly"]).await.unwrap(),
+            Resp::Map(vec![(
+                Resp::BS(Some("appendonly".into())),
+                Resp::BS(Some("no".into()))
+            )])
+        );
+        assert_eq!(client.call(&["GET", "foo"]).await.unwrap(), Resp::BS(None));
+    }
+
+    #[tokio::test]
+    async fn it_authenticates_with_hello() {
+        let node = Node::start(&["--requirepass", "secret"]).await.unwrap();
+        let mut client = node.connect().await.unwrap();
+        let config = ["CONFIG", "GET", "appendonly"];
+
+        let reply = client.call(&["HELLO", "3"]).await.unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.starts_with("NOAUTH")));
+        let reply = client
+            .call(&["HELLO", "3", "AUTH", "default", "wrong"])
+            .await
+            .unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.starts_with("WRONGPASS")));
+        let reply = client.call(&["GET", "k"]).await.unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.starts_with("NOAUTH")));
+
+        let hello = client
+            .call(&["HELLO", "3", "AUTH", "default", "secret"])
+            .await
+            .unwrap();
+        assert!(matches!(&hello, Resp::Map(fields) if fields[2].1 == Resp::I(3)));
+        assert!(matches!(client.call(&config).await.unwrap(), Resp::Map(_)));
+
+        client
+            .call(&[
+                "ACL",
+                "SETUSER",
+                "alice",
+                "on",
+                ">pw",
+                "allcommands",
+                "allkeys",
+            ])
+            .await
+            .unwrap();
+        // A failed switch keeps both the user and the protocol.
+        let reply = client
+            .call(&["HELLO", "2", "AUTH", "alice", "wrong"])
+            .await
+            .unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.starts_with("WRONGPASS")));
+        assert_eq!(
+            client.call(&["ACL", "WHOAMI"]).await.unwrap(),
+            Resp::BS(Some("default".into()))
+        );
+        assert!(matches!(client.call(&config).await.unwrap(), Resp::Map(_)));
+
+        let hello = client
+            .call(&["HELLO", "2", "AUTH", "alice", "pw"])
+            .await
+            .unwrap();
+        assert!(matches!(hello, Resp::A(fields) if fields[5] == Resp::I(2)));
+        assert_eq!(
+            client.call(&["ACL", "WHOAMI"]).await.unwrap(),
+            Resp::BS(Some("alice".into()))
+        );
+        assert!(matches!(client.call(&config).await.unwrap(), Resp::A(_)));
+    }
+
+    #[tokio::test]
+    async fn it_aborts_transactions_with_commands_rejected_while_queued() {
+        let node = Node::start(&[]).await.unwrap();
+        let mut client = node.connect().await.unwrap();
+        let ok = Resp::SS("OK".into());
+        let queued = Resp::SS("QUEUED".into());
+        let is_err =
+            |reply: Resp, prefix: &str| matches!(reply, Resp::SE(err) if err.starts_with(prefix));
+
+        // There is no pub/sub, so subscriptions are unknown commands, which can't be queued.
+        for rejected in [
+            vec!["SUBSCRIBE", "ch"],
+            vec!["PSUBSCRIBE", "ch.*"],
+            vec!["PSYNC", "?", "-1"],
+            vec!["GET"],
+        ] {
+            assert_eq!(client.call(&["MULTI"]).await.unwrap(), ok);
+            assert_eq!(client.call(&["SET", "foo", "1"]).await.unwrap(), queued);
+            let reply = client.call(&rejected).await.unwrap();
+            assert!(is_err(reply, "ERR"), "{rejected:?}");
+            assert_eq!(client.call(&["INCR", "foo"]).await.unwrap(), queued);
+            let reply = client.call(&["EXEC"]).await.unwrap();
+            assert!(is_err(reply, "EXECABORT"), "{rejected:?}");
+            assert_eq!(client.call(&["GET", "foo"]).await.unwrap(), Resp::BS(None));
+        }
+
+        // Nesting MULTI is an error that leaves the transaction alone.
+        client.call(&["MULTI"]).await.unwrap();
+        assert!(is_err(client.call(&["MULTI"]).await.unwrap(), "ERR MULTI"));
+        client.call(&["SET", "foo", "1"]).await.unwrap();
+        assert_eq!(
+            client.call(&["EXEC"]).await.unwrap(),
+            Resp::A(vec![ok.clone()])
+        );
+        assert_eq!(
+            client.call(&["EXEC"]).await.unwrap(),
+            Resp::SE("ERR EXEC without MULTI".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn it_replies_null_arrays() {
+        let node = Node::start(&[]).await.unwrap();
+        let mut client = node.connect().await.unwrap();
+        let mut other = node.connect().await.unwrap();
+
+        client.call(&["WATCH", "foo"]).await.unwrap();
+        other.call(&["SET", "foo", "bar"]).await.unwrap();
+        client.call(&["MULTI"]).await.unwrap();
+        client.call(&["SET", "foo", "baz"]).await.unwrap();
+        assert_eq!(client.call(&["EXEC"]).await.unwrap(), Resp::NullArray);
+
+        let xread = ["XREAD", "block", "10", "streams", "s", "0"];
+        assert_eq!(client.call(&xread).await.unwrap(), Resp::NullArray);
+        client.call(&["HELLO", "3"]).await.unwrap();
+        assert_eq!(client.call(&xread).await.unwrap(), Resp::BS(None));
+    }
+
+    #[tokio::test]
+    async fn it_searches_vector_sets() {
+        let node = Node::start(&[]).await.unwrap();
+        let mut client = node.connect().await.unwrap();
+        let bs = |v: &str| Resp::BS(Some(v.into()));
+
+        for (member, x, y) in [("east", "1", "0"), ("north", "0", "1"), ("west", "-1", "0")] {
+            let reply = client
+                .call(&["VADD", "v", "VALUES", "2", x, y, member])
+                .await;
+            assert_eq!(reply.unwrap(), Resp::I(1));
+        }
+        let reply = client
+            .call(&["VADD", "v", "VALUES", "2", "-2", "0", "west"])
+            .await;
+        assert_eq!(reply.unwrap(), Resp::I(0));
+        assert_eq!(client.call(&["VCARD", "v"]).await.unwrap(), Resp::I(3));
+        assert_eq!(
+            client.call(&["TYPE", "v"]).await.unwrap(),
+            Resp::SS("vectorset".into())
+        );
+
+        let reply = client
+            .call(&["VSIM", "v", "ELE", "east", "COUNT", "2"])
+            .await;
+        assert_eq!(reply.unwrap(), Resp::A(vec![bs("east"), bs("north")]));
+        let reply = client
+            .call(&[
+                "VSIM",
+                "v",
+                "VALUES",
+                "2",
+                "0",
+                "3",
+                "WITHSCORES",
+                "COUNT",
+                "1",
+            ])
+            .await;
+        assert_eq!(reply.unwrap(), Resp::A(vec![bs("north"), bs("1")]));
+
+        let reply = client.call(&["VADD", "v", "VALUES", "1", "1", "up"]).await;
+        assert!(matches!(reply.unwrap(), Resp::SE(err) if err.contains("dimension mismatch")));
+        client.call(&["SET", "s", "v"]).await.unwrap();
+        let reply = client.call(&["VCARD", "s"]).await.unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.starts_with("WRONGTYPE")));
+    }
+
+    #[tokio::test]
+    async fn it_edits_json_documents() {
+        let node = Node::start(&[]).await.unwrap();
+        let mut client = node.connect().await.unwrap();
+        let bs = |v: &str| Resp::BS(Some(v.into()));
+        let ok = Resp::SS("OK".into());
+
+        let reply = client.call(&["JSON.SET", "doc", "$.a", "1"]).await.unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.contains("root")));
+        let doc = r#"{"a":{"n":1},"b":[1,2],"c":"x"}"#;
+        assert_eq!(
+            client.call(&["JSON.SET", "doc", "$", doc]).await.unwrap(),
+            ok
+        );
+        assert_eq!(
+            client.call(&["TYPE", "doc"]).await.unwrap(),
+            Resp::SS("ReJSON-RL".into())
+        );
+        assert_eq!(client.call(&["JSON.GET", "doc"]).await.unwrap(), bs(doc));
+        assert_eq!(
+            client
+                .call(&["JSON.SET", "doc", "$.a.m", "true"])
+                .await
+                .unwrap(),
+            ok
+        );
+        let reply = client.call(&["JSON.SET", "doc", "$.a.m", "1", "NX"]).await;
+        assert_eq!(reply.unwrap(), Resp::BS(None));
+
+        let reply = client.call(&["JSON.GET", "doc", "$..n", ".c"]).await;
+        assert_eq!(reply.unwrap(), bs(r#"{"$..n":[1],".c":["x"]}"#));
+        assert_eq!(
+            client.call(&["JSON.GET", "doc", ".a.m"]).await.unwrap(),
+            bs("true")
+        );
+        let reply = client.call(&["JSON.GET", "doc", ".z"]).await.unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.contains("does not exist")));
+
+        let reply = client.call(&["JSON.NUMINCRBY", "doc", "$.b[*]", "2"]).await;
+        assert_eq!(reply.unwrap(), bs("[3,4]"));
+        let reply = client.call(&["JSON.NUMINCRBY", "doc", ".a.n", "0.5"]).await;
+        assert_eq!(reply.unwrap(), bs("1.5"));
+        let reply = client.call(&["JSON.NUMINCRBY", "doc", "$.c", "1"]).await;
+        assert_eq!(reply.unwrap(), bs("[null]"));
+
+        assert_eq!(
+            client.call(&["JSON.DEL", "doc", "$..n"]).await.unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(
+            client.call(&["JSON.GET", "doc"]).await.unwrap(),
+            bs(r#"{"a":{"m":true},"b":[3,4],"c":"x"}"#)
+        );
+        assert_eq!(client.call(&["JSON.DEL", "doc"]).await.unwrap(), Resp::I(1));
+        assert_eq!(
+            client.call(&["JSON.GET", "doc"]).await.unwrap(),
+            Resp::BS(None)
+        );
+        assert_eq!(client.call(&["JSON.DEL", "doc"]).await.unwrap(), Resp::I(0));
+    }
+
+    #[tokio::test]
+    async fn it_keeps_sketches() {
+        let node = Node::start(&[]).await.unwrap();
+        let mut client = node.connect().await.unwrap();
+        let ok = Resp::SS("OK".into());
+        let bs = |v: &str| Resp::BS(Some(v.into()));
+
+        assert_eq!(
+            client.call(&["BF.ADD", "bf", "a"]).await.unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(
+            client.call(&["BF.ADD", "bf", "a"]).await.unwrap(),
+            Resp::I(0)
+        );
+        let reply = client.call(&["BF.MEXISTS", "bf", "a", "b"]).await;
+        assert_eq!(reply.unwrap(), Resp::A(vec![Resp::I(1), Resp::I(0)]));
+        assert_eq!(
+            client.call(&["TYPE", "bf"]).await.unwrap(),
+            Resp::SS("MBbloom--".into())
+        );
+        let reply = client
+            .call(&["BF.RESERVE", "bf", "0.1", "10"])
+            .await
+            .unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.contains("item exists")));
+        let reply = client.call(&["BF.RESERVE", "small", "0.1", "1", "NONSCALING"]);
+        assert_eq!(reply.await.unwrap(), ok);
+        let reply = client.call(&["BF.MADD", "small", "a", "b"]).await.unwrap();
+        assert!(matches!(&reply, Resp::A(r) if r[0] == Resp::I(1) && matches!(r[1], Resp::SE(_))));
+        let too_large = Resp::SE("ERR capacity is too large".into());
+        let huge = i64::MAX.to_string();
+        let reply = client.call(&["BF.RESERVE", "huge", "0.01", &huge]).await;
+        assert_eq!(reply.unwrap(), too_large);
+        let reply = client
+            .call(&["BF.RESERVE", "huge", "1e-300", "100000000"])
+            .await;
+        assert_eq!(reply.unwrap(), too_large);
+        let reply = client.call(&["CF.RESERVE", "huge", &huge]).await;
+        assert_eq!(reply.unwrap(), too_large);
+
+        assert_eq!(
+            client.call(&["CF.ADD", "cf", "a"]).await.unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(
+            client.call(&["CF.ADD", "cf", "a"]).await.unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(
+            client.call(&["CF.ADDNX", "cf", "a"]).await.unwrap(),
+            Resp::I(0)
+        );
+        assert_eq!(
+            client.call(&["CF.COUNT", "cf", "a"]).await.unwrap(),
+            Resp::I(2)
+        );
+        assert_eq!(
+            client.call(&["CF.DEL", "cf", "a"]).await.unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(
+            client.call(&["CF.EXISTS", "cf", "a"]).await.unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(
+            client.call(&["CF.DEL", "cf", "a"]).await.unwrap(),
+            Resp::I(1)
+        );
+        assert_eq!(
+            client.call(&["CF.EXISTS", "cf", "a"]).await.unwrap(),
+            Resp::I(0)
+        );
+
+        let reply = client.call(&["TOPK.ADD", "tk", "a"]).await.unwrap();
+        assert!(matches!(reply, Resp::SE(err) if err.contains("does not exist")));
+        let reply = client
+            .call(&["TOPK.RESERVE", "tk", "2", "20", "4", "0.9"])
+            .await;
+        assert_eq!(reply.unwrap(), ok);
+        let reply = client
+            .call(&["TOPK.INCRBY", "tk", "a", "5", "b", "3"])
+            .await;
+        assert_eq!(
+            reply.unwrap(),
+            Resp::A(vec![Resp::BS(None), Resp::BS(None)])
+        );
+        let reply = client.call(&["TOPK.INCRBY", "tk", "c", "10"]).await;
+        assert_eq!(reply.unwrap(), Resp::A(vec![bs("b")]));
+        let reply = client.call(&["TOPK.LIST", "tk", "WITHCOUNT"]).await;
+        assert_eq!(
+            reply.unwrap(),
+            Resp::A(vec![bs("c"), Resp::I(10), bs("a"), Resp::I(5)])
+        );
+        let reply = client.call(&["TOPK.QUERY", "tk", "a", "b"]).await;
+        assert_eq!(reply.unwrap(), Resp::A(vec![Resp::I(1), Resp::I(0)]));
+    }
+
+    #[tokio::test]
+    async fn it_gets_acks_from_replicas_every_second() {
+        let group = ReplicationGroup::start(1).await.unwrap();
+        group.master.call(&["SET", "foo", "bar"]).await.unwrap();
+        group.converge().await.unwrap();
+
+        // No WAIT asks for the offset, so the replica sends it of its own accord.
+        let store = &group.master.store;
+        settle("the replica to ack", || async {
+            store.replicas().await[0].offset > 0
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_counts_pings_into_the_replication_offset() {
+        // Pings come every other ack, so that replicas ack each one while idle.
+        let group = ReplicationGroup::start_with(2, &["--repl-ping-replica-period", "2"])
+            .await
+            .unwrap();
+        let master = &group.master;
+        master.call(&["SET", "foo", "bar"]).await.unwrap();
+        let written = master.store.repl_offset();
+
+        settle("replicas to ack a ping", || async {
+            let offset = master.store.repl_offset();
+            if offset == written {
+                return false;
+            }
+            for replica in group.replicas.iter() {
+                if replica.store.ack_offset().await != offset {
+                    return false;
+                }
+            }
+            let acked = master.store.replicas().await;
+            acked.len() == 2 && acked.iter().all(|info| info.offset == offset)
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_replica_acks() {
+        let group = ReplicationGroup::start(2).await.unwrap();
+        let mut client = group.master.connect().await.unwrap();
+
+        // Nothing has been written yet, so every replica counts at once.
+        assert_eq!(
+            client.call(&["WAIT", "2", "100"]).await.unwrap(),
+            Resp::I(2)
+        );
+
+        client.call(&["SET", "foo", "bar"]).await.unwrap();
+        assert_eq!(
+            client.call(&["WAIT", "2", "2000"]).await.unwrap(),
+            Resp::I(2)
+        );
+        // A third replica never comes, so WAIT gives what acked before the timeout.
+        client.call(&["SET", "foo", "baz"]).await.unwrap();
+        assert_eq!(
+            client.call(&["WAIT", "3", "300"]).await.unwrap(),
+            Resp::I(2)
+        );
+        group.converge().await.unwrap();
+
+        // Every replica ends up acking the same offset, as they ack every second.
+        let store = &group.master.store;
+        settle("replicas to ack the same offset", || async {
+            let offsets: Vec<usize> = store
+                .replicas()
+                .await
+                .iter()
+                .map(|info| info.offset)
+                .collect();
+            offsets.len() == 2 && offsets[0] > 0 && offsets[0] == offsets[1]
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,599 @@
+//! An experimental transport doing the socket reads and writes of connections through
+//! io_uring, so that a batch of reply frames takes one submission and reads wait for data
+//! in the kernel rather than through readiness events. The ring is set up with raw
+//! syscalls, so it's built only for Linux on x86_64 and aarch64, which share the numbers.
+//!
+//! It isn't behind a cargo feature, as the manifest is fixed, so it's compiled into every
+//! Linux build on those targets. It stays off unless `--io-backend io_uring` is given:
+//! no ring is set up and none of the unsafe code below runs otherwise. On a kernel
+//! lacking the features it needs, the server refuses to start with it rather than run a
+//! ring that may misbehave.
+
+pub(crate) use sys::{raw_fd, Uring};
+
+/// Entries of the submission queue. Entries are taken by the kernel as soon as they are
+/// submitted, so this bounds the submissions in flight, not the pending operations.
+pub(crate) const ENTRIES: u32 = 1024;
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod sys {
+    use bytes::Bytes;
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::io;
+    use std::mem::size_of;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::ptr;
+    use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+    use std::thread;
+    use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
+
+    const SYS_IO_URING_SETUP: i64 = 425;
+    const SYS_IO_URING_ENTER: i64 = 426;
+    const IORING_ENTER_GETEVENTS: i64 = 1;
+    const IORING_FEAT_SINGLE_MMAP: u32 = 1;
+    const IORING_FEAT_NODROP: u32 = 1 << 1;
+    const IORING_FEAT_FAST_POLL: u32 = 1 << 5;
+    const IORING_OFF_SQ_RING: i64 = 0;
+    const IORING_OFF_SQES: i64 = 0x10000000;
+    const IORING_OP_WRITEV: u8 = 2;
+    const IORING_OP_ASYNC_CANCEL: u8 = 14;
+    const IORING_OP_RECV: u8 = 27;
+    const PROT_READ: i32 = 0x1;
+    const PROT_WRITE: i32 = 0x2;
+    const MAP_SHARED: i32 = 0x01;
+    const MAP_POPULATE: i32 = 0x8000;
+    const EINTR: i32 = 4;
+    const EAGAIN: i32 = 11;
+    /// The most buffers a vectored write takes.
+    const MAX_IOV: usize = 1024;
+    /// Set on the user data of cancellations, whose completions nobody waits for.
+    const CANCEL: u64 = 1 << 63;
+
+    // SAFETY: the signatures are those of libc, which the standard library links.
+    extern "C" {
+        fn syscall(num: i64, ...) -> i64;
+        fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8;
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Params {
+        sq_entries: u32,
+        cq_entries: u32,
+        flags: u32,
+        sq_thread_cpu: u32,
+        sq_thread_idle: u32,
+        features: u32,
+        wq_fd: u32,
+        resv: [u32; 3],
+        sq_off: SqOffsets,
+        cq_off: CqOffsets,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct SqOffsets {
+        head: u32,
+        tail: u32,
+        ring_mask: u32,
+        ring_entries: u32,
+        flags: u32,
+        dropped: u32,
+        array: u32,
+        resv1: u32,
+        user_addr: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct CqOffsets {
+        head: u32,
+        tail: u32,
+        ring_mask: u32,
+        ring_entries: u32,
+        overflow: u32,
+        cqes: u32,
+        flags: u32,
+        resv1: u32,
+        user_addr: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Sqe {
+        opcode: u8,
+        flags: u8,
+        ioprio: u16,
+        fd: i32,
+        off: u64,
+        addr: u64,
+        len: u32,
+        op_flags: u32,
+        user_data: u64,
+        buf_index: u16,
+        personality: u16,
+        splice_fd_in: i32,
+        addr3: u64,
+        pad: u64,
+    }
+
+    #[repr(C)]
+    struct Cqe {
+        user_data: u64,
+        res: i32,
+        flags: u32,
+    }
+
+    #[repr(C)]
+    struct IoVec {
+        base: *const u8,
+        len: usize,
+    }
+
+    // SAFETY: an IoVec points into a frame held next to it until the write completes.
+    unsafe impl Send for IoVec {}
+
+    /// The memory an operation reads or writes, owned by the ring until the kernel is
+    /// done with it, even when the task waiting for the operation is gone.
+    enum Buf {
+        Read(Vec<u8>),
+        Write {
+            frames: Vec<Bytes>,
+            _iovecs: Vec<IoVec>,
+        },
+    }
+
+    impl Buf {
+        fn into_read(self) -> Vec<u8> {
+            match self {
+                Self::Read(buf) => buf,
+                Self::Write { .. } => Vec::new(),
+            }
+        }
+
+        fn into_frames(self) -> Vec<Bytes> {
+            match self {
+                Self::Read(_) => Vec::new(),
+                Self::Write { frames, .. } => frames,
+            }
+        }
+    }
+
+    struct Pending {
+        tx: oneshot::Sender<(i32, Buf)>,
+        buf: Buf,
+    }
+
+    struct Sq {
+        head: *const AtomicU32,
+        tail: *const AtomicU32,
+        mask: u32,
+        entries: u32,
+        array: *mut u32,
+        sqes: *mut Sqe,
+    }
+
+    struct Cq {
+        head: *const AtomicU32,
+        tail: *const AtomicU32,
+        mask: u32,
+        cqes: *const Cqe,
+    }
+
+    struct Ring {
+        fd: OwnedFd,
+        sq: Mutex<Sq>,
+        cq: Cq,
+        entries: u32,
+        pending: Mutex<HashMap<u64, Pending>>,
+        next_id: AtomicU64,
+    }
+
+    // SAFETY: the pointers are into the mappings of the ring, which are never unmapped,
+    // so they stay valid on whichever thread holds the ring.
+    unsafe impl Send for Ring {}
+    // SAFETY: the SQ is written only under its lock, the CQ is read only by the reaper
+    // thread, and everything else shared is atomic or behind a lock.
+    unsafe impl Sync for Ring {}
+
+    /// A ring shared by every connection. Tasks submit their operations themselves, and a
+    /// thread of its own waits for the completions and wakes the tasks. The ring lives as
+    /// long as the process.
+    #[derive(Clone)]
+    pub(crate) struct Uring {
+        ring: Arc<Ring>,
+    }
+
+    impl fmt::Debug for Uring {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Uring")
+                .field("entries", &self.ring.entries)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl Uring {
+        pub(crate) fn new(entries: u32) -> io::Result<Self> {
+            let mut params = Params::default();
+            // SAFETY: the params outlive the call, which fills them in.
+            let fd = unsafe {
+                syscall(
+                    SYS_IO_URING_SETUP,
+                    i64::from(entries),
+                    &mut params as *mut Params,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: the descriptor was just returned by the kernel, and nothing else owns it.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+
+            let required = IORING_FEAT_SINGLE_MMAP | IORING_FEAT_NODROP | IORING_FEAT_FAST_POLL;
+            if params.features & required != required {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "io_uring of this kernel is too old",
+                ));
+            }
+
+            let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+            let cq_len =
+                params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+            // With a single mmap, both rings are in one mapping.
+            let rings = map(&fd, sq_len.max(cq_len), IORING_OFF_SQ_RING)?;
+            let sqes = map(
+                &fd,
+                params.sq_entries as usize * size_of::<Sqe>(),
+                IORING_OFF_SQES,
+            )? as *mut Sqe;
+
+            let Params { sq_off, cq_off, .. } = &params;
+            // SAFETY: the kernel gives offsets within the mapping of the rings.
+            let (sq, cq) = unsafe {
+                let at = |offset: u32| rings.add(offset as usize);
+                let sq = Sq {
+                    head: at(sq_off.head) as *const AtomicU32,
+                    tail: at(sq_off.tail) as *const AtomicU32,
+                    mask: *(at(sq_off.ring_mask) as *const u32),
+                    entries: params.sq_entries,
+                    array: at(sq_off.array) as *mut u32,
+                    sqes,
+                };
+                let cq = Cq {
+                    head: at(cq_off.head) as *const AtomicU32,
+                    tail: at(cq_off.tail) as *const AtomicU32,
+                    mask: *(at(cq_off.ring_mask) as *const u32),
+                    cqes: at(cq_off.cqes) as *const Cqe,
+                };
+                (sq, cq)
+            };
+
+            let ring = Arc::new(Ring {
+                fd,
+                sq: Mutex::new(sq),
+                cq,
+                entries: params.sq_entries,
+                pending: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(0),
+            });
+            let reaper = Arc::clone(&ring);
+            thread::Builder::new()
+                .name("io_uring".into())
+                .spawn(move || reaper.reap())?;
+            Ok(Self { ring })
+        }
+
+        /// Reads from the socket into the buffer, giving the buffer back with the number
+        /// of bytes read.
+        pub(crate) async fn recv(
+            &self,
+            fd: RawFd,
+            mut buf: Vec<u8>,
+        ) -> io::Result<(usize, Vec<u8>)> {
+            let sqe = Sqe {
+                opcode: IORING_OP_RECV,
+                fd,
+                addr: buf.as_mut_ptr() as u64,
+                len: u32::try_from(buf.len()).unwrap_or(u32::MAX),
+                ..Default::default()
+            };
+            let (read, buf) = self.run(sqe, Buf::Read(buf)).await?;
+            Ok((read, buf.into_read()))
+        }
+
+        /// Writes all the frames to the socket with vectored writes, picking up where a
+        /// partial write stopped.
+        pub(crate) async fn send_frames(&self, fd: RawFd, frames: &[Bytes]) -> io::Result<()> {
+            let mut frames: Vec<Bytes> = frames.iter().filter(|f| !f.is_empty()).cloned().collect();
+
+            while !frames.is_empty() {
+                let iovecs: Vec<IoVec> = frames
+                    .iter()
+                    .take(MAX_IOV)
+                    .map(|frame| IoVec {
+                        base: frame.as_ptr(),
+                        len: frame.len(),
+                    })
+                    .collect();
+                let sqe = Sqe {
+                    opcode: IORING_OP_WRITEV,
+                    fd,
+                    addr: iovecs.as_ptr() as u64,
+                    len: iovecs.len() as u32,
+                    ..Default::default()
+                };
+                let buf = Buf::Write {
+                    frames,
+                    _iovecs: iovecs,
+                };
+                let (mut written, buf) = self.run(sqe, buf).await?;
+                if written == 0 {
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+
+                frames = buf.into_frames();
+                let done = frames
+                    .iter()
+                    .take_while(|frame| {
+                        let whole = frame.len() <= written;
+                        if whole {
+                            written -= frame.len();
+                        }
+                        whole
+                    })
+                    .count();
+                frames.drain(..done);
+                if let Some(frame) = frames.first_mut() {
+                    *frame = frame.slice(written..);
+                }
+            }
+            Ok(())
+        }
+
+        /// Submits the operation and waits for its result. Dropping the future cancels
+        /// the operation, whose memory the ring keeps until the kernel lets it go.
+        async fn run(&self, sqe: Sqe, buf: Buf) -> io::Result<(usize, Buf)> {
+            let (tx, rx) = oneshot::channel();
+            let mut cancel = Cancel {
+                ring: &self.ring,
+                id: Some(self.ring.submit(sqe, Pending { tx, buf })),
+            };
+
+            let result = rx.await;
+            cancel.id = None;
+            match result {
+                Ok((res, _)) if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+                Ok((res, buf)) => Ok((res as usize, buf)),
+                Err(_) => Err(io::Error::other("io_uring has stopped")),
+            }
+        }
+    }
+
+    struct Cancel<'a> {
+        ring: &'a Ring,
+        id: Option<u64>,
+    }
+
+    impl Drop for Cancel<'_> {
+        fn drop(&mut self) {
+            if let Some(id) = self.id {
+                let sqe = Sqe {
+                    opcode: IORING_OP_ASYNC_CANCEL,
+                    fd: -1,
+                    addr: id,
+                    user_data: id | CANCEL,
+                    ..Default::default()
+                };
+                // A cancellation that can't be queued leaves the operation to finish
+                // by itself, which it does once the socket closes.
+                let _ = self.ring.push(sqe);
+            }
+        }
+    }
+
+    impl Ring {
+        fn submit(&self, mut sqe: Sqe, pending: Pending) -> u64 {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed) & !CANCEL;
+            sqe.user_data = id;
+            self.pending().insert(id, pending);
+            if let Err(err) = self.push(sqe) {
+                self.complete(id, -err.raw_os_error().unwrap_or(EAGAIN));
+            }
+            id
+        }
+
+        /// The operations submitted. Nothing panics halfway through changing the map, so
+        /// it's used even if poisoned: a buffer the kernel was given is never dropped until
+        /// its completion comes.
+        fn pending(&self) -> MutexGuard<'_, HashMap<u64, Pending>> {
+            self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        fn push(&self, sqe: Sqe) -> io::Result<()> {
+            {
+                let sq = self
+                    .sq
+                    .lock()
+                    .map_err(|_| io::Error::other("io_uring submission queue poisoned"))?;
+                // SAFETY: the lock makes this the only writer of the tail and of the
+                // entries past it, which the kernel reads only once the tail moves.
+                unsafe {
+                    let head = (*sq.head).load(Ordering::Acquire);
+                    let tail = (*sq.tail).load(Ordering::Relaxed);
+                    if tail.wrapping_sub(head) == sq.entries {
+                        return Err(io::Error::from_raw_os_error(EAGAIN));
+                    }
+                    let index = tail & sq.mask;
+                    sq.sqes.add(index as usize).write(sqe);
+                    sq.array.add(index as usize).write(index);
+                    (*sq.tail).store(tail.wrapping_add(1), Ordering::Release);
+                }
+            }
+            // An entry the kernel doesn't take now is submitted by the next call to
+            // enter, such as the one the reaper waits in.
+            let _ = self.enter(1, 0, 0);
+            Ok(())
+        }
+
+        fn enter(&self, to_submit: u32, min_complete: u32, flags: i64) -> io::Result<u32> {
+            // SAFETY: no signal mask is passed, so the kernel reads no memory of ours.
+            let n = unsafe {
+                syscall(
+                    SYS_IO_URING_ENTER,
+                    i64::from(self.fd.as_raw_fd()),
+                    i64::from(to_submit),
+                    i64::from(min_complete),
+                    flags,
+                    ptr::null::<u8>(),
+                    0i64,
+                )
+            };
+            if n < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(n as u32)
+            }
+        }
+
+        /// Waits for completions and hands each to the task waiting for it.
+        fn reap(&self) {
+            loop {
+                match self.enter(self.entries, 1, IORING_ENTER_GETEVENTS) {
+                    Ok(_) => {}
+                    Err(err) if err.raw_os_error() == Some(EINTR) => continue,
+                    Err(err) => {
+                        eprintln!("io_uring stopped. {err}");
+                        // Dropping the senders fails the operations still waited for. Their
+                        // memory is leaked, as the kernel may still be using it.
+                        for (_, Pending { tx, buf }) in self.pending().drain() {
+                            drop(tx);
+                            std::mem::forget(buf);
+                        }
+                        return;
+                    }
+                }
+
+                // SAFETY: only this thread reads the completions and moves the head.
+                unsafe {
+                    let mut head = (*self.cq.head).load(Ordering::Relaxed);
+                    let tail = (*self.cq.tail).load(Ordering::Acquire);
+                    while head != tail {
+                        let cqe = self.cq.cqes.add((head & self.cq.mask) as usize).read();
+                        head = head.wrapping_add(1);
+                        (*self.cq.head).store(head, Ordering::Release);
+                        self.complete(cqe.user_data, cqe.res);
+                    }
+                }
+            }
+        }
+
+        fn complete(&self, id: u64, res: i32) {
+            if id & CANCEL != 0 {
+                return;
+            }
+            let pending = self.pending().remove(&id);
+            if let Some(Pending { tx, buf }) = pending {
+                // The task may be gone, in which case the memory is freed here.
+                let _ = tx.send((res, buf));
+            }
+        }
+    }
+
+    fn map(fd: &OwnedFd, len: usize, offset: i64) -> io::Result<*mut u8> {
+        // SAFETY: a new shared mapping of the ring, which aliases no memory of ours.
+        let ptr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED | MAP_POPULATE,
+                fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if ptr as usize == usize::MAX {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ptr)
+        }
+    }
+
+    pub(crate) fn raw_fd(stream: &TcpStream) -> RawFd {
+        stream.as_raw_fd()
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+mod sys {
+    use bytes::Bytes;
+    use std::io;
+    use tokio::net::TcpStream;
+
+    #[derive(Debug, Clone)]
+    pub(crate) struct Uring;
+
+    impl Uring {
+        pub(crate) fn new(_entries: u32) -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "io_uring isn't supported on this platform",
+            ))
+        }
+
+        pub(crate) async fn recv(&self, _fd: i32, _buf: Vec<u8>) -> io::Result<(usize, Vec<u8>)> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+
+        pub(crate) async fn send_frames(&self, _fd: i32, _frames: &[Bytes]) -> io::Result<()> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    pub(crate) fn raw_fd(_stream: &TcpStream) -> i32 {
+        -1
+    }
+}
+
+#[cfg(all(
+    test,
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::time::Duration;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn it_sends_and_receives_through_the_ring() {
+        let uring = Uring::new(8).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        // A read dropped before any data comes is cancelled, and leaves the socket usable.
+        let read = uring.recv(raw_fd(&server), vec![0; 16]);
+        assert!(tokio::time::timeout(Duration::from_millis(50), read)
+            .await
+            .is_err());
+
+        let frames = [Bytes::from("+OK\r\n"), Bytes::new(), Bytes::from(":1\r\n")];
+        uring.send_frames(raw_fd(&client), &frames).await.unwrap();
+
+        let (read, buf) = uring.recv(raw_fd(&server), vec![0; 16]).await.unwrap();
+        assert_eq!(&buf[..read], b"+OK\r\n:1\r\n");
+    }
+}
@@ -1,5 +1,9 @@
 use super::{RedisError, RedisResult};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 use std::io::{Cursor, Seek, SeekFrom};
+use std::ops::Range;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub(crate) fn stringify(buf: &[u8]) -> RedisResult<&str> {
     std::str::from_utf8(buf).map_err(RedisError::from)
@@ -13,8 +17,338 @@ pub(crate) fn parse_i64(buf: &[u8]) -> RedisResult<i64> {
     stringify(buf)?.parse().map_err(RedisError::from)
 }
 
+/// Parses memory amounts like "100", "1k", "1kb", "5mb" or "2gb" into bytes.
+pub(crate) fn parse_memory(value: &str) -> Option<usize> {
+    let value = value.to_lowercase();
+    let pos = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (num, unit) = value.split_at(pos);
+    let num = num.parse::<usize>().ok()?;
+
+    let multiplier: usize = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    num.checked_mul(multiplier)
+}
+
+/// Formats save points as the save parameter takes them, `<seconds> <changes>` each.
+pub(crate) fn format_save(save: &[(u64, u64)]) -> String {
+    save.iter()
+        .map(|(secs, changes)| format!("{secs} {changes}"))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Redis-compatible glob matching: `*` matches any sequence, `?` any one byte, `[abc]`,
+/// `[^abc]` and `[a-z]` a byte of the class, and `\` escapes the next byte.
+pub(crate) fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+
+    let (mut p, mut s) = (0, 0);
+    // Where to resume when the pattern after the latest `*` fails to match.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        let matched = match pattern.get(p) {
+            Some(b'*') => {
+                while pattern.get(p + 1) == Some(&b'*') {
+                    p += 1;
+                }
+                backtrack = Some((p, s));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_class(pattern, p + 1, string[s], nocase),
+            Some(b'\\') if p + 1 < pattern.len() => eq(pattern[p + 1], string[s]).then_some(p + 2),
+            Some(&c) => eq(c, string[s]).then_some(p + 1),
+            None => None,
+        };
+
+        match (matched, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                s += 1;
+            }
+            (None, Some((star, from))) => {
+                // Let the `*` swallow one more byte and retry.
+                p = star + 1;
+                s = from + 1;
+                backtrack = Some((star, from + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p.min(pattern.len())..].iter().all(|&c| c == b'*')
+}
+
+/// Matches the byte against the class starting at `p`, just after `[`. Returns the
+/// position after the closing `]` when it matches.
+fn match_class(pattern: &[u8], mut p: usize, c: u8, nocase: bool) -> Option<usize> {
+    let fold = |b: u8| if nocase { b.to_ascii_lowercase() } else { b };
+    let c = fold(c);
+
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+
+    let mut matched = false;
+    loop {
+        match pattern.get(p) {
+            // An unterminated class ends at the end of the pattern, as in Redis.
+            None => break,
+            Some(b']') => {
+                p += 1;
+                break;
+            }
+            Some(b'\\') if p + 1 < pattern.len() => {
+                matched |= fold(pattern[p + 1]) == c;
+                p += 2;
+            }
+            Some(&start) if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() => {
+                let (start, end) = (fold(start), fold(pattern[p + 2]));
+                let (low, high) = if start <= end {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+                matched |= low <= c && c <= high;
+                p += 3;
+            }
+            Some(&class) => {
+                matched |= fold(class) == c;
+                p += 1;
+            }
+        }
+    }
+
+    (matched != negate).then_some(p)
+}
+
 pub(crate) const TERM: &str = "\r\n";
 
+/// The bytes GETRANGE takes of a string of `len` bytes. Negative offsets count from the
+/// end, and both are clamped to the string, the end included.
+pub(crate) fn byte_range(len: usize, start: i64, end: i64) -> Range<usize> {
+    if start < 0 && end < 0 && start > end {
+        return 0..0;
+    }
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { (len + end).max(0) } else { end }.min(len - 1);
+    if start > end {
+        0..0
+    } else {
+        start as usize..end as usize + 1
+    }
+}
+
+/// The elements LRANGE takes of a list of `len` elements. Negative indexes count from
+/// the tail. Unlike GETRANGE, a stop before the head takes nothing.
+pub(crate) fn index_range(len: usize, start: i64, stop: i64) -> Range<usize> {
+    let len = len as i64;
+    let start = if start < 0 {
+        len.saturating_add(start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len.saturating_add(stop)
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop {
+        0..0
+    } else {
+        start as usize..stop as usize + 1
+    }
+}
+
+/// The significant digits HINCRBYFLOAT writes its sums with: as many as a double keeps
+/// of a decimal number, so that 0.1 plus 0.2 reads 0.3. Redis writes 17 of a long double,
+/// which has that many to spare.
+const FLOAT_DIGITS: usize = 15;
+
+/// Writes the number as `%.15g` does: rounded to 15 significant digits, in scientific
+/// notation when its exponent is below -4 or not below 15, and without trailing zeros.
+pub(crate) fn format_float(n: f64) -> String {
+    let sci = format!("{:.*e}", FLOAT_DIGITS - 1, n);
+    let (mantissa, exp) = sci.split_once('e').unwrap_or((&sci, "0"));
+    let exp: i32 = exp.parse().unwrap_or_default();
+    let trim = |s: &str| {
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            s.to_string()
+        }
+    };
+    if exp < -4 || exp >= FLOAT_DIGITS as i32 {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{sign}{:02}", trim(mantissa), exp.unsigned_abs())
+    } else {
+        let decimals = (FLOAT_DIGITS as i32 - 1 - exp) as usize;
+        trim(&format!("{n:.decimals$}"))
+    }
+}
+
+/// Returns a non-cryptographic random number seeded from the std hasher keys and the clock.
+pub(crate) fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    hasher.write_u128(nanos);
+    hasher.finish()
+}
+
+/// Returns a random lowercase hex string such as replication ids.
+pub(crate) fn random_hex(len: usize) -> String {
+    let mut hex = String::with_capacity(len + 16);
+    while hex.len() < len {
+        hex.push_str(&format!("{:016x}", random_u64()));
+    }
+    hex.truncate(len);
+    hex
+}
+
+/// Returns the SHA-1 digest of the bytes as lowercase hex, as script ids are named.
+pub(crate) fn sha1_hex(bytes: &[u8]) -> String {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut msg = bytes.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((bytes.len() as u64) * 8).to_be_bytes());
+
+    for chunk in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    h.iter().map(|v| format!("{v:08x}")).collect()
+}
+
+/// Returns the SHA-256 digest of the bytes as lowercase hex, as ACL passwords are stored.
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut msg = bytes.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((bytes.len() as u64) * 8).to_be_bytes());
+
+    for chunk in msg.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for (k, word) in K.iter().zip(w.iter()) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*k)
+                .wrapping_add(*word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    h.iter().map(|v| format!("{v:08x}")).collect()
+}
+
 #[derive(Debug)]
 pub(crate) struct Tokens<'a> {
     cursor: Cursor<&'a [u8]>,
@@ -27,24 +361,32 @@ impl<'a> Tokens<'a> {
         }
     }
 
-    pub(crate) fn proceed(&mut self, len: usize) -> Option<&'a [u8]> {
-        let current = self.current_position();
-        let bytes = *(self.cursor.get_ref());
-        let buf_size = bytes.len();
-
-        let len = std::cmp::min(len, buf_size - current);
+    /// Takes exactly `len` bytes, or nothing when fewer are left.
+    pub(crate) fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.rest().get(..len)?;
         seek(&mut self.cursor, len)?;
-        Some(&bytes[current..current + len])
+        Some(bytes)
+    }
+
+    /// Takes the bytes up to the next terminator, which is skipped as well. Nothing is
+    /// taken when there is no terminator left, unlike `next`.
+    pub(crate) fn line(&mut self) -> Option<&'a [u8]> {
+        let rest = self.rest();
+        let len = rest
+            .windows(TERM.len())
+            .position(|nums| nums == TERM.as_bytes())?;
+        seek(&mut self.cursor, len + TERM.len())?;
+        rest.get(..len)
     }
 
     pub(crate) fn starts_with(&self, bytes: &[u8]) -> bool {
-        if self.finished() {
-            false
-        } else {
-            let current = self.current_position();
-            let len = bytes.len();
-            *bytes == self.buf()[current..current + len]
-        }
+        self.rest().starts_with(bytes)
+    }
+
+    /// The bytes not read yet.
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        let bytes: &'a [u8] = self.cursor.get_ref();
+        bytes.get(self.current_position()..).unwrap_or_default()
     }
 
     pub(crate) fn finished(&self) -> bool {
@@ -100,10 +442,158 @@ fn seek<T: AsRef<[u8]>>(cursor: &mut Cursor<T>, len: usize) -> Option<()> {
         .ok()
 }
 
+/// Splits a line into arguments, as redis-cli does with what is typed at its prompt and
+/// the server with the lines of its config file. Double quotes
+/// take the escapes `\n`, `\r`, `\t`, `\b`, `\a` and `\xHH`, single quotes only `\'`.
+pub(crate) fn split_args(line: &str) -> RedisResult<Vec<String>> {
+    let invalid = || RedisError::from(anyhow::anyhow!("Invalid argument(s)"));
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            return Ok(args);
+        };
+
+        let mut arg = String::new();
+        match first {
+            '"' => loop {
+                match chars.next().ok_or_else(invalid)? {
+                    '"' => break,
+                    '\\' => match chars.next().ok_or_else(invalid)? {
+                        'n' => arg.push('\n'),
+                        'r' => arg.push('\r'),
+                        't' => arg.push('\t'),
+                        'b' => arg.push('\u{8}'),
+                        'a' => arg.push('\u{7}'),
+                        'x' => {
+                            let hex: String = chars.by_ref().take(2).collect();
+                            match u8::from_str_radix(&hex, 16) {
+                                Ok(byte) if hex.len() == 2 => arg.push(byte as char),
+                                _ => arg.push_str(&format!("x{hex}")),
+                            }
+                        }
+                        other => arg.push(other),
+                    },
+                    other => arg.push(other),
+                }
+            },
+            '\'' => loop {
+                match chars.next().ok_or_else(invalid)? {
+                    '\'' => break,
+                    '\\' if chars.peek() == Some(&'\'') => arg.push(chars.next().unwrap_or('\'')),
+                    other => arg.push(other),
+                }
+            },
+            other => {
+                arg.push(other);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    arg.push(c);
+                }
+                args.push(arg);
+                continue;
+            }
+        }
+        // A closing quote must end the argument.
+        if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+            return Err(invalid());
+        }
+        args.push(arg);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// GETRANGE as the indices it keeps, to check `byte_range` against. An end before the
+    /// string still keeps its first byte, as Redis does.
+    fn reference_range(len: usize, start: i64, end: i64) -> Vec<usize> {
+        let from_end = |offset: i64| {
+            if offset < 0 {
+                len as i64 + offset
+            } else {
+                offset
+            }
+        };
+        if start < 0 && end < 0 && start > end {
+            return vec![];
+        }
+        (0..len)
+            .filter(|&i| i as i64 >= from_end(start) && i as i64 <= from_end(end).max(0))
+            .collect()
+    }
+
+    #[test]
+    fn it_takes_byte_ranges() {
+        assert_eq!(byte_range(10, 0, 3), 0..4);
+        assert_eq!(byte_range(10, -3, -1), 7..10);
+        assert_eq!(byte_range(10, 0, -1), 0..10);
+        assert_eq!(byte_range(10, 5, 100), 5..10);
+        assert_eq!(byte_range(10, -100, 2), 0..3);
+        assert_eq!(byte_range(10, -1, -5), 0..0);
+        assert_eq!(byte_range(10, 20, 30), 0..0);
+        assert_eq!(byte_range(0, 0, -1), 0..0);
+        assert_eq!(byte_range(10, i64::MIN, i64::MAX), 0..10);
+
+        let mut seed: u64 = 0x9e3779b97f4a7c15;
+        let mut next = |bound: i64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % (2 * bound as u64 + 1)) as i64 - bound
+        };
+        for _ in 0..10000 {
+            let len = next(8).unsigned_abs() as usize;
+            let (start, end) = (next(12), next(12));
+            let range = byte_range(len, start, end);
+            assert_eq!(
+                range.collect::<Vec<_>>(),
+                reference_range(len, start, end),
+                "{len} {start} {end}"
+            );
+        }
+    }
+
+    #[test]
+    fn it_takes_index_ranges() {
+        assert_eq!(index_range(10, 0, 3), 0..4);
+        assert_eq!(index_range(10, -3, -1), 7..10);
+        assert_eq!(index_range(10, 0, -1), 0..10);
+        assert_eq!(index_range(10, 5, 100), 5..10);
+        assert_eq!(index_range(10, -100, 2), 0..3);
+        assert_eq!(index_range(10, 0, -100), 0..0);
+        assert_eq!(index_range(10, 20, 30), 0..0);
+        assert_eq!(index_range(0, 0, -1), 0..0);
+        assert_eq!(index_range(10, i64::MIN, i64::MAX), 0..10);
+    }
+
+    #[test]
+    fn it_formats_floats_like_g() {
+        assert_eq!(format_float(0.1 + 0.2), "0.3");
+        assert_eq!(format_float(10.5 + 0.1), "10.6");
+        assert_eq!(format_float(-2.0 + 2.0), "0");
+        assert_eq!(format_float(5.0e3 - 0.5), "4999.5");
+        assert_eq!(format_float(1.0 - 0.0000001), "0.9999999");
+        assert_eq!(format_float(0.00001 + 0.00002), "3e-05");
+        assert_eq!(format_float(0.0001 + 0.00002), "0.00012");
+        assert_eq!(format_float(1e17 + 1.0), "1e+17");
+        assert_eq!(format_float(999999999999999.0), "999999999999999");
+        assert_eq!(format_float(9999999999999999.0), "1e+16");
+        assert_eq!(format_float(123456789.12345679), "123456789.123457");
+        assert_eq!(format_float(-1.5 + 0.25), "-1.25");
+    }
+
+    #[test]
+    fn it_splits_args_like_redis_cli() {
+        let args = split_args(r#"SET  "a \"b\"\x41\n" 'it\'s'  plain"#).unwrap();
+        assert_eq!(args, vec!["SET", "a \"b\"A\n", "it's", "plain"]);
+        assert_eq!(split_args("   ").unwrap(), Vec::<String>::new());
+        assert!(split_args(r#"GET "unterminated"#).is_err());
+        assert!(split_args(r#"GET "a"b"#).is_err());
+    }
+
     #[test]
     fn parse_int() {
         let bytes: &[u8] = b"15";
@@ -119,6 +609,25 @@ mod tests {
         assert_eq!(actual, -15);
     }
 
+    #[test]
+    fn it_parses_memory() {
+        assert_eq!(parse_memory("100"), Some(100));
+        assert_eq!(parse_memory("1k"), Some(1000));
+        assert_eq!(parse_memory("1KB"), Some(1024));
+        assert_eq!(parse_memory("5mb"), Some(5 * 1024 * 1024));
+        assert_eq!(parse_memory("2gb"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_memory("1tb"), None);
+        assert_eq!(parse_memory("mb"), None);
+    }
+
+    #[test]
+    fn it_generates_random_hex() {
+        let hex = random_hex(40);
+        assert_eq!(hex.len(), 40);
+        assert!(hex.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(hex, random_hex(40));
+    }
+
     #[test]
     fn empty_bytes() {
         let bytes = b"";
@@ -246,6 +755,27 @@ mod tests {
         assert_eq!(item, None);
     }
 
+    #[test]
+    fn it_hashes_with_sha1() {
+        assert_eq!(sha1_hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            sha1_hex(b"return 1"),
+            "e0e1f9fabfc9d4800c877a703b823ac0578ff8db"
+        );
+    }
+
+    #[test]
+    fn it_hashes_with_sha256() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"foobar"),
+            "c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2"
+        );
+    }
+
     #[test]
     fn it_checks_starts() {
         let bytes = b"one\r\ntwo";
@@ -257,4 +787,38 @@ mod tests {
 
         assert!(tokens.starts_with(b"t"));
     }
+
+    #[test]
+    fn it_matches_globs() {
+        let cases: Vec<(&str, &str, bool)> = vec![
+            ("*", "", true),
+            ("*", "foo", true),
+            ("f*", "foo", true),
+            ("*o", "foo", true),
+            ("*x*", "foo", false),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h*llo", "heeeello", true),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[a-b]llo", "hcllo", false),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("a*b*c", "aXbYbZc", true),
+            ("a*b*c", "aXbYbZ", false),
+            ("key:[0-9]*", "key:42", true),
+        ];
+
+        for (pattern, string, expected) in cases {
+            let actual = glob_match(pattern.as_bytes(), string.as_bytes(), false);
+            assert_eq!(actual, expected, "{pattern} against {string}");
+        }
+
+        assert!(glob_match(b"DIR", b"dir", true));
+        assert!(glob_match(b"[A-C]ir", b"bir", true));
+        assert!(!glob_match(b"DIR", b"dir", false));
+    }
 }
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+/// The fields of a hash and, for the fields given one, the unix milliseconds they expire
+/// at. Fields past their expiry are hidden from reads until they're removed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedisHash {
+    fields: HashMap<String, String>,
+    expires: HashMap<String, u64>,
+}
+
+impl RedisHash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every field kept, including those past their expiry.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    fn is_live(&self, field: &str, now: u64) -> bool {
+        self.expires.get(field).is_none_or(|at| *at > now)
+    }
+
+    pub fn get(&self, field: &str, now: u64) -> Option<&String> {
+        self.fields.get(field).filter(|_| self.is_live(field, now))
+    }
+
+    /// The fields not past their expiry, along with their values.
+    pub fn iter(&self, now: u64) -> impl Iterator<Item = (&String, &String)> {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| self.is_live(field, now))
+    }
+
+    /// Every field kept with its value and expiry, as they're saved.
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &String, Option<u64>)> {
+        self.fields
+            .iter()
+            .map(|(field, value)| (field, value, self.expires.get(field).copied()))
+    }
+
+    /// Sets the value of the field, which keeps its expiry.
+    pub fn insert(&mut self, field: String, value: String) {
+        self.fields.insert(field, value);
+    }
+
+    pub fn remove(&mut self, field: &str) -> Option<String> {
+        self.expires.remove(field);
+        self.fields.remove(field)
+    }
+
+    pub fn expiry(&self, field: &str) -> Option<u64> {
+        self.expires.get(field).copied()
+    }
+
+    pub fn has_expiries(&self) -> bool {
+        !self.expires.is_empty()
+    }
+
+    /// Makes an existing field expire at the unix milliseconds.
+    pub fn set_expiry(&mut self, field: &str, at: u64) {
+        if self.fields.contains_key(field) {
+            self.expires.insert(field.into(), at);
+        }
+    }
+
+    /// Removes the expiry of the field, telling whether it had one.
+    pub fn persist(&mut self, field: &str) -> bool {
+        self.expires.remove(field).is_some()
+    }
+
+    pub fn has_expired(&self, now: u64) -> bool {
+        self.expires.values().any(|at| *at <= now)
+    }
+
+    /// Removes the fields past their expiry and returns them.
+    pub fn remove_expired(&mut self, now: u64) -> Vec<String> {
+        let expired: Vec<String> = self
+            .expires
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(field, _)| field.clone())
+            .collect();
+        for field in expired.iter() {
+            self.remove(field);
+        }
+        expired
+    }
+}
+
+/// The condition HEXPIRE sets on the current expiry of a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireCond {
+    Nx,
+    Xx,
+    Gt,
+    Lt,
+}
+
+impl ExpireCond {
+    /// Whether a field expiring at `current` may be made to expire at `at`. A field
+    /// without an expiry counts as never expiring.
+    pub fn allows(self, current: Option<u64>, at: u64) -> bool {
+        match (self, current) {
+            (Self::Nx, current) => current.is_none(),
+            (Self::Xx, current) => current.is_some(),
+            (Self::Gt, Some(current)) => at > current,
+            (Self::Gt, None) => false,
+            (Self::Lt, Some(current)) => at < current,
+            (Self::Lt, None) => true,
+        }
+    }
+}
+
+impl From<HashMap<String, String>> for RedisHash {
+    fn from(fields: HashMap<String, String>) -> Self {
+        Self {
+            fields,
+            expires: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_hides_fields_past_their_expiry() {
+        let mut hash = RedisHash::new();
+        hash.insert("a".into(), "1".into());
+        hash.insert("b".into(), "2".into());
+        hash.set_expiry("a", 100);
+        hash.set_expiry("missing", 100);
+        assert_eq!(hash.expiry("missing"), None);
+
+        assert_eq!(hash.get("a", 99), Some(&"1".to_string()));
+        assert_eq!(hash.get("a", 100), None);
+        assert_eq!(hash.iter(100).count(), 1);
+        assert_eq!(hash.len(), 2);
+
+        hash.insert("a".into(), "3".into());
+        assert_eq!(hash.expiry("a"), Some(100));
+        assert_eq!(hash.remove_expired(100), vec!["a".to_string()]);
+        assert_eq!(hash.len(), 1);
+        assert!(!hash.has_expiries());
+
+        hash.set_expiry("b", 200);
+        assert!(!hash.has_expired(199));
+        assert!(ExpireCond::Gt.allows(hash.expiry("b"), 300));
+        assert!(!ExpireCond::Nx.allows(hash.expiry("b"), 300));
+        assert!(hash.persist("b"));
+        assert!(!ExpireCond::Gt.allows(hash.expiry("b"), 300));
+        assert!(ExpireCond::Lt.allows(hash.expiry("b"), 300));
+        assert!(!hash.persist("b"));
+    }
+}
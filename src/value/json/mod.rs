@@ -0,0 +1,618 @@
+mod path;
+pub use path::JsonPath;
+
+use super::{RedisError, RedisResult};
+use path::Segment;
+use std::fmt;
+
+/// Nesting past which documents are refused, as RedisJSON limits them.
+const MAX_DEPTH: usize = 128;
+
+/// A JSON document. Integers are kept apart from other numbers so that they come back
+/// as they were written, and object keys keep the order they were added in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> RedisResult<Self> {
+        let mut parser = Parser {
+            src: text.as_bytes(),
+            pos: 0,
+        };
+        let json = parser.value(0)?;
+        parser.skip_blank();
+        if parser.pos < parser.src.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(json)
+    }
+
+    /// The values the path matches.
+    pub fn get(&self, path: &JsonPath) -> Vec<&Json> {
+        let mut found = vec![self];
+        for segment in path.segments() {
+            found = found
+                .into_iter()
+                .flat_map(|json| json.children(segment))
+                .collect();
+        }
+        found
+    }
+
+    /// What JSON.GET replies for the paths. A legacy path gives the first value it
+    /// matches and fails when there is none, while JSONPath gives an array of all of
+    /// them. Several paths give an object of each path with its result, where legacy
+    /// ones are taken as JSONPath unless all of them are legacy.
+    pub fn select(&self, paths: &[JsonPath]) -> RedisResult<Json> {
+        let legacy = paths.iter().all(JsonPath::is_legacy);
+        let select = |path: &JsonPath| -> RedisResult<Json> {
+            let found = self.get(path);
+            if legacy {
+                found
+                    .first()
+                    .map(|json| (*json).clone())
+                    .ok_or_else(|| anyhow::anyhow!("ERR Path '{path}' does not exist").into())
+            } else {
+                Ok(Json::Array(found.into_iter().cloned().collect()))
+            }
+        };
+        match paths {
+            [path] => select(path),
+            paths => paths
+                .iter()
+                .map(|path| Ok((path.to_string(), select(path)?)))
+                .collect::<RedisResult<_>>()
+                .map(Json::Object),
+        }
+    }
+
+    /// Sets the value at every place the path matches. The last key of the path is added
+    /// to the objects missing it, unless `cond` is XX; with NX only missing keys are set.
+    /// Tells how many places were set.
+    pub fn set(&mut self, path: &JsonPath, value: &Json, cond: Option<SetCond>) -> usize {
+        let Some((last, parents)) = path.segments().split_last() else {
+            if cond == Some(SetCond::Nx) {
+                return 0;
+            }
+            *self = value.clone();
+            return 1;
+        };
+
+        let mut set = 0;
+        self.visit_mut(parents, &mut |parent| match (parent, last) {
+            (Json::Object(pairs), Segment::Key(key)) => {
+                match pairs.iter_mut().find(|(k, _)| k == key) {
+                    Some((_, old)) if cond != Some(SetCond::Nx) => {
+                        *old = value.clone();
+                        set += 1;
+                    }
+                    None if cond != Some(SetCond::Xx) => {
+                        pairs.push((key.clone(), value.clone()));
+                        set += 1;
+                    }
+                    _ => {}
+                }
+            }
+            (parent, segment) if cond != Some(SetCond::Nx) => {
+                for child in parent.children_mut(segment) {
+                    *child = value.clone();
+                    set += 1;
+                }
+            }
+            _ => {}
+        });
+        set
+    }
+
+    /// Deletes the values the path matches, and tells how many there were. The root
+    /// can't be deleted from the document itself.
+    pub fn delete(&mut self, path: &JsonPath) -> usize {
+        let Some((last, parents)) = path.segments().split_last() else {
+            return 0;
+        };
+
+        let mut deleted = 0;
+        self.visit_mut(parents, &mut |parent| {
+            let before = parent.len();
+            match (&mut *parent, last) {
+                (Json::Object(pairs), Segment::Key(key)) => pairs.retain(|(k, _)| k != key),
+                (Json::Object(pairs), Segment::Wildcard) => pairs.clear(),
+                (Json::Array(values), Segment::Index(index)) => {
+                    if let Some(i) = position(*index, values.len()) {
+                        values.remove(i);
+                    }
+                }
+                (Json::Array(values), Segment::Wildcard) => values.clear(),
+                _ => {}
+            }
+            deleted += before - parent.len();
+        });
+        deleted
+    }
+
+    /// Adds the number to every number the path matches, and gives what each match
+    /// became, or `None` for the ones which aren't numbers.
+    pub fn incr_by(&mut self, path: &JsonPath, by: &Json) -> RedisResult<Vec<Option<Json>>> {
+        let mut results: Vec<RedisResult<Option<Json>>> = vec![];
+        self.visit_mut(path.segments(), &mut |json| {
+            let sum = match (&*json, by) {
+                (Json::Int(a), Json::Int(b)) => match a.checked_add(*b) {
+                    Some(sum) => Ok(Some(Json::Int(sum))),
+                    None => Ok(Some(Json::Float(*a as f64 + *b as f64))),
+                },
+                (a, b) => match (a.as_f64(), b.as_f64()) {
+                    (Some(a), Some(b)) if (a + b).is_finite() => Ok(Some(Json::Float(a + b))),
+                    (Some(_), Some(_)) => Err(RedisError::from(anyhow::anyhow!(
+                        "ERR result is an infinite number"
+                    ))),
+                    _ => Ok(None),
+                },
+            };
+            if let Ok(Some(sum)) = &sum {
+                *json = sum.clone();
+            }
+            results.push(sum);
+        });
+        results.into_iter().collect()
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Self::Int(_) | Self::Float(_))
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Int(num) => Some(*num as f64),
+            Self::Float(num) => Some(*num),
+            _ => None,
+        }
+    }
+
+    /// Elements of an array or pairs of an object.
+    fn len(&self) -> usize {
+        match self {
+            Self::Array(values) => values.len(),
+            Self::Object(pairs) => pairs.len(),
+            _ => 0,
+        }
+    }
+
+    fn children(&self, segment: &Segment) -> Vec<&Json> {
+        match (self, segment) {
+            (Self::Object(pairs), Segment::Key(key)) => pairs
+                .iter()
+                .filter(|(k, _)| k == key)
+                .map(|(_, v)| v)
+                .collect(),
+            (Self::Object(pairs), Segment::Wildcard) => pairs.iter().map(|(_, v)| v).collect(),
+            (Self::Array(values), Segment::Index(index)) => position(*index, values.len())
+                .map(|i| vec![&values[i]])
+                .unwrap_or_default(),
+            (Self::Array(values), Segment::Wildcard) => values.iter().collect(),
+            (json, Segment::Descendants) => {
+                let mut found = vec![json];
+                for child in json.children(&Segment::Wildcard) {
+                    found.extend(child.children(&Segment::Descendants));
+                }
+                found
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Like `children`, except that descendants are left to `visit_mut`, as they can't
+    /// be borrowed mutably along with the values they are nested in.
+    fn children_mut(&mut self, segment: &Segment) -> Vec<&mut Json> {
+        match (self, segment) {
+            (Self::Object(pairs), Segment::Key(key)) => pairs
+                .iter_mut()
+                .filter(|(k, _)| k == key)
+                .map(|(_, v)| v)
+                .collect(),
+            (Self::Object(pairs), Segment::Wildcard) => pairs.iter_mut().map(|(_, v)| v).collect(),
+            (Self::Array(values), Segment::Index(index)) => match position(*index, values.len()) {
+                Some(i) => vec![&mut values[i]],
+                None => vec![],
+            },
+            (Self::Array(values), Segment::Wildcard) => values.iter_mut().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Runs `f` on every value the segments lead to.
+    fn visit_mut(&mut self, segments: &[Segment], f: &mut dyn FnMut(&mut Json)) {
+        match segments.split_first() {
+            None => f(self),
+            Some((Segment::Descendants, rest)) => {
+                self.visit_mut(rest, f);
+                for child in self.children_mut(&Segment::Wildcard) {
+                    child.visit_mut(segments, f);
+                }
+            }
+            Some((segment, rest)) => {
+                for child in self.children_mut(segment) {
+                    child.visit_mut(rest, f);
+                }
+            }
+        }
+    }
+}
+
+/// The condition JSON.SET sets on whether the path already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCond {
+    Nx,
+    Xx,
+}
+
+/// The position of an index counted from the end when negative.
+fn position(index: i64, len: usize) -> Option<usize> {
+    let i = if index < 0 {
+        len.checked_sub(index.unsigned_abs() as usize)?
+    } else {
+        index as usize
+    };
+    (i < len).then_some(i)
+}
+
+/// Writes the document compactly, as JSON.GET replies it without formatting options.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(val) => write!(f, "{val}"),
+            Self::Int(num) => write!(f, "{num}"),
+            // Floats keep a fraction, so that they are read back as floats.
+            Self::Float(num) if num.fract() == 0.0 && num.abs() < 1e16 => write!(f, "{num:.1}"),
+            Self::Float(num) => write!(f, "{num}"),
+            Self::String(val) => write_string(f, val),
+            Self::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Object(pairs) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, val: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in val.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self, depth: usize) -> RedisResult<Json> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.skip_blank();
+        match self.src.get(self.pos) {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut pairs: Vec<(String, Json)> = vec![];
+                if self.consume(b'}') {
+                    return Ok(Json::Object(pairs));
+                }
+                loop {
+                    self.skip_blank();
+                    let key = self.string()?;
+                    if !self.consume(b':') {
+                        return Err(self.error("expected ':'"));
+                    }
+                    let value = self.value(depth + 1)?;
+                    // A key given twice keeps the last value, where the first one was.
+                    match pairs.iter_mut().find(|(k, _)| *k == key) {
+                        Some((_, old)) => *old = value,
+                        None => pairs.push((key, value)),
+                    }
+                    if self.consume(b'}') {
+                        return Ok(Json::Object(pairs));
+                    }
+                    if !self.consume(b',') {
+                        return Err(self.error("expected ',' or '}'"));
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values: Vec<Json> = vec![];
+                if self.consume(b']') {
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    if self.consume(b']') {
+                        return Ok(Json::Array(values));
+                    }
+                    if !self.consume(b',') {
+                        return Err(self.error("expected ',' or ']'"));
+                    }
+                }
+            }
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.word("true", Json::Bool(true)),
+            Some(b'f') => self.word("false", Json::Bool(false)),
+            Some(b'n') => self.word("null", Json::Null),
+            Some(c) if *c == b'-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("expected value")),
+            None => Err(self.error("EOF while parsing a value")),
+        }
+    }
+
+    fn word(&mut self, word: &str, json: Json) -> RedisResult<Json> {
+        if self.src[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(json)
+        } else {
+            Err(self.error("expected value"))
+        }
+    }
+
+    fn number(&mut self) -> RedisResult<Json> {
+        let start = self.pos;
+        self.consume(b'-');
+        let digits = self.digits();
+        // No leading zeros, as JSON has no octal numbers.
+        if digits == 0 || (digits > 1 && self.src[self.pos - digits] == b'0') {
+            return Err(self.error("invalid number"));
+        }
+        let mut float = false;
+        if self.consume(b'.') {
+            float = true;
+            if self.digits() == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if self.consume(b'e') || self.consume(b'E') {
+            float = true;
+            if !self.consume(b'+') {
+                self.consume(b'-');
+            }
+            if self.digits() == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+
+        let text = std::str::from_utf8(&self.src[start..self.pos]).unwrap_or_default();
+        if !float {
+            if let Ok(num) = text.parse::<i64>() {
+                return Ok(Json::Int(num));
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(num) if num.is_finite() => Ok(Json::Float(num)),
+            _ => Err(self.error("number out of range")),
+        }
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.src.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn string(&mut self) -> RedisResult<String> {
+        if !self.consume(b'"') {
+            return Err(self.error("expected string"));
+        }
+        let mut bytes: Vec<u8> = vec![];
+        loop {
+            match self.src.get(self.pos) {
+                None => return Err(self.error("EOF while parsing a string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let c = match self.src.get(self.pos) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode()?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.pos += 1;
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(c) if *c < 0x20 => return Err(self.error("control character in string")),
+                Some(c) => {
+                    bytes.push(*c);
+                    self.pos += 1;
+                }
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    /// Reads the four hex digits after `\u`, and the low surrogate after them when they
+    /// are a high one. The position is left on the last digit.
+    fn unicode(&mut self) -> RedisResult<char> {
+        let hex = |parser: &mut Self| -> RedisResult<u32> {
+            let digits = parser
+                .src
+                .get(parser.pos + 1..parser.pos + 5)
+                .and_then(|digits| std::str::from_utf8(digits).ok())
+                .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| parser.error("invalid unicode escape"))?;
+            parser.pos += 4;
+            Ok(digits)
+        };
+
+        let high = hex(self)?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.src[self.pos + 1..].starts_with(b"\\u") {
+                return Err(self.error("lone surrogate"));
+            }
+            self.pos += 2;
+            let low = hex(self)?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(self.error("lone surrogate"));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn consume(&mut self, c: u8) -> bool {
+        self.skip_blank();
+        if self.src.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_blank(&mut self) {
+        while self
+            .src
+            .get(self.pos)
+            .is_some_and(|c| matches!(c, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&self, reason: &str) -> RedisError {
+        anyhow::anyhow!("ERR invalid JSON: {reason} at offset {}", self.pos).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> JsonPath {
+        JsonPath::parse(path).unwrap()
+    }
+
+    #[test]
+    fn it_parses_what_it_writes() {
+        let text = r#"{"a":[1,-2.5,3e2,true,null],"b":{"c":"x\"\\\n\u0001é😀"},"d":1.0}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(
+            json.to_string(),
+            r#"{"a":[1,-2.5,300.0,true,null],"b":{"c":"x\"\\\n\u0001é😀"},"d":1.0}"#
+        );
+        assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+        assert_eq!(
+            Json::parse(r#" { "k" : "\ud83d\ude00" } "#).unwrap(),
+            Json::Object(vec![("k".into(), Json::String("😀".into()))])
+        );
+
+        for invalid in [
+            "",
+            "{",
+            "[1,]",
+            "01",
+            "1.",
+            "\"\\x\"",
+            "tru",
+            "{\"a\" 1}",
+            "1 2",
+        ] {
+            assert!(Json::parse(invalid).is_err(), "{invalid}");
+        }
+        let deep = format!("{}{}", "[".repeat(MAX_DEPTH + 2), "]".repeat(MAX_DEPTH + 2));
+        assert!(Json::parse(&deep).is_err());
+    }
+
+    #[test]
+    fn it_reads_and_writes_at_paths() {
+        let mut json = Json::parse(r#"{"a":{"n":1},"b":{"n":"x"},"c":[1,2,3]}"#).unwrap();
+        let found: Vec<String> = json
+            .get(&path("$..n"))
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        assert_eq!(found, ["1", "\"x\""]);
+        assert_eq!(json.get(&path("$.c[-1]")), [&Json::Int(3)]);
+        assert_eq!(json.get(&path(".a.n")), [&Json::Int(1)]);
+        assert!(json.get(&path("$.c[3]")).is_empty());
+
+        assert_eq!(json.set(&path("$.a.m"), &Json::Null, None), 1);
+        assert_eq!(
+            json.set(&path("$.a.m"), &Json::Int(0), Some(SetCond::Nx)),
+            0
+        );
+        assert_eq!(
+            json.set(&path("$.*.z"), &Json::Int(0), Some(SetCond::Xx)),
+            0
+        );
+        assert_eq!(json.set(&path("$.c[*]"), &Json::Int(0), None), 3);
+        assert_eq!(
+            json.to_string(),
+            r#"{"a":{"n":1,"m":null},"b":{"n":"x"},"c":[0,0,0]}"#
+        );
+
+        let incremented = json.incr_by(&path("$..n"), &Json::Float(0.5)).unwrap();
+        assert_eq!(incremented, [Some(Json::Float(1.5)), None]);
+        let incremented = json.incr_by(&path("$.c[0]"), &Json::Int(2)).unwrap();
+        assert_eq!(incremented, [Some(Json::Int(2))]);
+
+        assert_eq!(json.select(&[path(".a.m")]).unwrap(), Json::Null);
+        assert!(json.select(&[path(".a.x")]).is_err());
+        assert_eq!(
+            json.select(&[path("$.a.n"), path(".a.x")])
+                .unwrap()
+                .to_string(),
+            r#"{"$.a.n":[1.5],".a.x":[]}"#
+        );
+
+        assert_eq!(json.delete(&path("$..n")), 2);
+        assert_eq!(json.delete(&path("$.c[1]")), 1);
+        assert_eq!(json.delete(&path("$")), 0);
+        assert_eq!(json.to_string(), r#"{"a":{"m":null},"b":{},"c":[2,0]}"#);
+    }
+}
@@ -0,0 +1,152 @@
+use super::{RedisError, RedisResult};
+use std::fmt;
+
+/// A path into a JSON document. JSONPath starts with `$` and may match any number of
+/// values. The legacy form starts with `.` or a key and is meant to match one value.
+/// Both take `.key`, `['key']`, `[index]` and `*`; JSONPath also takes `..` to go
+/// through every descendant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    text: String,
+    segments: Vec<Segment>,
+    legacy: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Segment {
+    Key(String),
+    /// Counted from the end when negative.
+    Index(i64),
+    Wildcard,
+    /// The value itself and every value nested in it.
+    Descendants,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> RedisResult<Self> {
+        let invalid = || RedisError::from(anyhow::anyhow!("ERR invalid JSON path '{path}'"));
+        let (legacy, rest) = if let Some(rest) = path.strip_prefix('$') {
+            (false, rest.to_string())
+        } else if path == "." {
+            (true, String::new())
+        } else if path.starts_with(['.', '[']) {
+            (true, path.to_string())
+        } else {
+            // A legacy path may leave out the dot before its first key.
+            (true, format!(".{path}"))
+        };
+
+        let mut segments: Vec<Segment> = vec![];
+        let mut chars = rest.as_str();
+        while !chars.is_empty() {
+            if let Some(after) = chars.strip_prefix("..") {
+                if legacy {
+                    return Err(invalid());
+                }
+                segments.push(Segment::Descendants);
+                // `..key` is short for `..['key']`.
+                chars = after;
+                if chars.starts_with('[') {
+                    continue;
+                }
+                let (segment, after) = member(chars).ok_or_else(invalid)?;
+                segments.push(segment);
+                chars = after;
+            } else if let Some(after) = chars.strip_prefix('.') {
+                let (segment, after) = member(after).ok_or_else(invalid)?;
+                segments.push(segment);
+                chars = after;
+            } else if let Some(after) = chars.strip_prefix('[') {
+                let (segment, after) = bracket(after).ok_or_else(invalid)?;
+                segments.push(segment);
+                chars = after;
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(Self {
+            text: path.into(),
+            segments,
+            legacy,
+        })
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub(crate) fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+}
+
+/// Writes the path as it was given.
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// The key or `*` after a dot, up to the next dot or bracket.
+fn member(chars: &str) -> Option<(Segment, &str)> {
+    let end = chars.find(['.', '[']).unwrap_or(chars.len());
+    let (name, rest) = chars.split_at(end);
+    match name {
+        "" => None,
+        "*" => Some((Segment::Wildcard, rest)),
+        name => Some((Segment::Key(name.into()), rest)),
+    }
+}
+
+/// The quoted key, index or `*` after an opening bracket, up to the closing one.
+fn bracket(chars: &str) -> Option<(Segment, &str)> {
+    if let Some(quote) = chars.chars().next().filter(|c| *c == '\'' || *c == '"') {
+        let end = chars[1..].find(quote)? + 1;
+        let rest = chars[end + 1..].strip_prefix(']')?;
+        return Some((Segment::Key(chars[1..end].into()), rest));
+    }
+    let (inner, rest) = chars.split_once(']')?;
+    match inner.trim() {
+        "*" => Some((Segment::Wildcard, rest)),
+        index => index.parse::<i64>().ok().map(|i| (Segment::Index(i), rest)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_paths() {
+        let key = |k: &str| Segment::Key(k.into());
+        let path = JsonPath::parse("$.a['b.c'][-1][*]..d").unwrap();
+        assert!(!path.is_legacy());
+        assert_eq!(
+            path.segments(),
+            [
+                key("a"),
+                key("b.c"),
+                Segment::Index(-1),
+                Segment::Wildcard,
+                Segment::Descendants,
+                key("d"),
+            ]
+        );
+        assert!(JsonPath::parse("$").unwrap().is_root());
+
+        for legacy in [".a.b", "a.b", "a[\"b\"]"] {
+            let path = JsonPath::parse(legacy).unwrap();
+            assert!(path.is_legacy());
+            assert_eq!(path.segments(), [key("a"), key("b")], "{legacy}");
+        }
+        assert!(JsonPath::parse(".").unwrap().is_root());
+
+        for invalid in ["$.", "$[", "$[x]", "$['a]", "$a", "..a", "$.a..", "$.a[1"] {
+            assert!(JsonPath::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+}
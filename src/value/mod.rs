@@ -1,7 +1,17 @@
+mod hash;
+mod json;
+mod sketch;
 mod stream;
-pub use stream::{RedisStream, StreamEntry, StreamEntryId, StreamEntryIdFactor};
+mod vset;
+pub use hash::{ExpireCond, RedisHash};
+pub use json::{Json, JsonPath, SetCond};
+pub use sketch::{BloomFilter, BloomLayer, CuckooFilter, TopK};
+pub(crate) use stream::{Consumer, ConsumerGroup, PendingEntry};
+pub use stream::{DeletePolicy, RedisStream, StreamEntry, StreamEntryId, StreamEntryIdFactor};
+pub use vset::{VectorQuery, VectorSet};
 
 use super::{RedisError, RedisResult, Resp};
+use std::collections::{HashSet, VecDeque};
 use std::time::SystemTime;
 
 #[derive(Debug, Clone)]
@@ -10,15 +20,195 @@ pub enum Value {
         value: String,
         exp: Option<SystemTime>,
     },
+    List(VecDeque<String>),
+    Hash(RedisHash),
+    Set(HashSet<String>),
     Stream(RedisStream),
+    VectorSet(VectorSet),
+    Json(Json),
+    Bloom(BloomFilter),
+    Cuckoo(CuckooFilter),
+    TopK(TopK),
+}
+
+/// The types a value can hold, to get at the inner data of values of a given type.
+pub trait ValueType {
+    fn cast(value: &Value) -> Option<&Self>;
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self>;
+}
+
+impl ValueType for String {
+    fn cast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::String { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::String { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for VecDeque<String> {
+    fn cast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for RedisHash {
+    fn cast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for HashSet<String> {
+    fn cast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for RedisStream {
+    fn cast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for VectorSet {
+    fn cast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::VectorSet(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::VectorSet(set) => Some(set),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for Json {
+    fn cast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Json(json) => Some(json),
+            _ => None,
+        }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Json(json) => Some(json),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for BloomFilter {
+    fn cast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Bloom(filter) => Some(filter),
+            _ => None,
+        }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Bloom(filter) => Some(filter),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for CuckooFilter {
+    fn cast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Cuckoo(filter) => Some(filter),
+            _ => None,
+        }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Cuckoo(filter) => Some(filter),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for TopK {
+    fn cast(value: &Value) -> Option<&Self> {
+        match value {
+            Value::TopK(topk) => Some(topk),
+            _ => None,
+        }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::TopK(topk) => Some(topk),
+            _ => None,
+        }
+    }
 }
 
 impl Value {
-    pub fn expired(&self) -> bool {
+    /// The inner data, or WRONGTYPE if the value holds another type.
+    pub fn cast<V: ValueType + ?Sized>(&self) -> RedisResult<&V> {
+        V::cast(self).ok_or(RedisError::WrongType)
+    }
+
+    pub fn expired(&self, now: SystemTime) -> bool {
         match self {
             Self::String { exp, .. } => {
                 if let Some(&exp) = exp.as_ref() {
-                    SystemTime::now() >= exp
+                    now >= exp
                 } else {
                     false
                 }
@@ -27,23 +217,185 @@ impl Value {
         }
     }
 
+    pub fn has_expiry(&self) -> bool {
+        self.expiry().is_some()
+    }
+
+    pub fn expiry(&self) -> Option<SystemTime> {
+        match self {
+            Self::String { exp, .. } => *exp,
+            _ => None,
+        }
+    }
+
     pub fn type_name(&self) -> &str {
         match self {
             Self::String { .. } => "string",
+            Self::List(_) => "list",
+            Self::Hash(_) => "hash",
+            Self::Set(_) => "set",
+            Self::Stream(_) => "stream",
+            Self::VectorSet(_) => "vectorset",
+            Self::Json(_) => "ReJSON-RL",
+            Self::Bloom(_) => "MBbloom--",
+            Self::Cuckoo(_) => "MBbloomCF",
+            Self::TopK(_) => "TopK-TYPE",
+        }
+    }
+
+    pub fn encoding(&self) -> &str {
+        match self {
+            Self::String { value, .. } if value.parse::<i64>().is_ok() => "int",
+            Self::String { value, .. } if value.len() <= EMBSTR_SIZE_LIMIT => "embstr",
+            Self::String { .. } => "raw",
+            Self::List(list)
+                if list.iter().map(String::len).sum::<usize>() <= LISTPACK_SIZE_LIMIT =>
+            {
+                "listpack"
+            }
+            Self::List(_) => "quicklist",
+            Self::Hash(hash)
+                if hash.len() <= HASH_LISTPACK_ENTRIES
+                    && hash
+                        .entries()
+                        .all(|(f, v, _)| f.len().max(v.len()) <= HASH_LISTPACK_VALUE) =>
+            {
+                // Field expiries are kept alongside the fields of a listpack.
+                if hash.has_expiries() {
+                    "listpackex"
+                } else {
+                    "listpack"
+                }
+            }
+            Self::Hash(_) => "hashtable",
+            Self::Set(set)
+                if set.len() <= SET_INTSET_ENTRIES
+                    && set.iter().all(|member| member.parse::<i64>().is_ok()) =>
+            {
+                "intset"
+            }
+            Self::Set(set)
+                if set.len() <= SET_LISTPACK_ENTRIES
+                    && set.iter().all(|member| member.len() <= SET_LISTPACK_VALUE) =>
+            {
+                "listpack"
+            }
+            Self::Set(_) => "hashtable",
             Self::Stream(_) => "stream",
+            Self::VectorSet(_) => "vectorset",
+            Self::Json(_) => "json",
+            Self::Bloom(_) => "bloom",
+            Self::Cuckoo(_) => "cuckoo",
+            Self::TopK(_) => "topk",
+        }
+    }
+
+    /// Approximate number of bytes the value takes when it is written to an RDB file.
+    pub fn serialized_len(&self) -> usize {
+        match self {
+            Self::String { value, .. } => match value.parse::<i32>() {
+                Ok(num) if i8::try_from(num).is_ok() => 2,
+                Ok(num) if i16::try_from(num).is_ok() => 3,
+                Ok(_) => 5,
+                Err(_) => size_header_len(value.len()) + value.len(),
+            },
+            Self::List(list) => list
+                .iter()
+                .map(|element| size_header_len(element.len()) + element.len())
+                .sum(),
+            Self::Hash(hash) => hash
+                .entries()
+                .map(|(field, value, _)| {
+                    size_header_len(field.len())
+                        + field.len()
+                        + size_header_len(value.len())
+                        + value.len()
+                })
+                .sum(),
+            Self::Set(set) => set
+                .iter()
+                .map(|member| size_header_len(member.len()) + member.len())
+                .sum(),
+            Self::Stream(stream) => stream.serialized_len(),
+            Self::VectorSet(set) => set
+                .members()
+                .map(|(member, vector)| {
+                    size_header_len(member.len()) + member.len() + 4 * vector.len()
+                })
+                .sum(),
+            Self::Json(json) => json.to_string().len(),
+            Self::Bloom(filter) => filter.layers().iter().map(|l| l.bits.len() * 8).sum(),
+            Self::Cuckoo(filter) => filter.tables().iter().map(Vec::len).sum(),
+            Self::TopK(topk) => {
+                topk.counters().len() * 8
+                    + topk
+                        .list()
+                        .iter()
+                        .map(|(item, _)| item.len() + 4)
+                        .sum::<usize>()
+            }
         }
     }
 }
 
+const EMBSTR_SIZE_LIMIT: usize = 44;
+/// Bytes of elements a list keeps in a single listpack, as with the default
+/// list-max-listpack-size of -2.
+const LISTPACK_SIZE_LIMIT: usize = 8 * 1024;
+/// Fields, and bytes of a field or a value, up to which a hash is a listpack, as with the
+/// default hash-max-listpack-entries and hash-max-listpack-value.
+const HASH_LISTPACK_ENTRIES: usize = 128;
+const HASH_LISTPACK_VALUE: usize = 64;
+/// Members up to which a set of integers is an intset, and members and bytes of a member
+/// up to which a set is a listpack, as with the default set-max-intset-entries,
+/// set-max-listpack-entries and set-max-listpack-value.
+const SET_INTSET_ENTRIES: usize = 512;
+const SET_LISTPACK_ENTRIES: usize = 128;
+const SET_LISTPACK_VALUE: usize = 64;
+
+fn size_header_len(len: usize) -> usize {
+    if len < 1 << 6 {
+        1
+    } else if len < 1 << 14 {
+        2
+    } else {
+        5
+    }
+}
+
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::String { value, .. } => {
                 write!(f, "{value}")
             }
+            Self::List(list) => {
+                write!(f, "{list:?}")
+            }
+            Self::Hash(hash) => {
+                write!(f, "{hash:?}")
+            }
+            Self::Set(set) => {
+                write!(f, "{set:?}")
+            }
             Self::Stream(map) => {
                 write!(f, "{map:?}")
             }
+            Self::VectorSet(set) => {
+                write!(f, "{set}")
+            }
+            Self::Json(json) => {
+                write!(f, "{json}")
+            }
+            Self::Bloom(filter) => {
+                write!(f, "{filter}")
+            }
+            Self::Cuckoo(filter) => {
+                write!(f, "{filter}")
+            }
+            Self::TopK(topk) => {
+                write!(f, "{topk}")
+            }
         }
     }
 }
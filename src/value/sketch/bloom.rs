@@ -0,0 +1,182 @@
+use super::{hash, RedisResult, FILTER_MAX_BYTES};
+use std::f64::consts::LN_2;
+use std::fmt;
+
+/// How much tighter the error rate of each layer added to a scaling filter is than the
+/// last one, so that the error rate of the whole filter stays within the one asked for.
+const TIGHTENING: f64 = 0.5;
+
+/// A Bloom filter, which tells that an item was added, possibly wrongly at the error
+/// rate, or that it surely wasn't. Once full, a scaling filter adds a layer `expansion`
+/// times larger, while a non scaling one refuses more items.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    error_rate: f64,
+    /// `None` when the filter doesn't scale.
+    expansion: Option<u32>,
+    layers: Vec<BloomLayer>,
+}
+
+/// One bit array of a Bloom filter, sized for its capacity at its error rate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomLayer {
+    pub(crate) capacity: u64,
+    pub(crate) error_rate: f64,
+    pub(crate) hashes: u32,
+    pub(crate) items: u64,
+    pub(crate) bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    pub fn new(error_rate: f64, capacity: u64, expansion: Option<u32>) -> RedisResult<Self> {
+        let layer = BloomLayer::new(capacity, error_rate)
+            .ok_or_else(|| anyhow::anyhow!("ERR capacity is too large"))?;
+        Ok(Self {
+            error_rate,
+            expansion,
+            layers: vec![layer],
+        })
+    }
+
+    pub(crate) fn from_layers(
+        error_rate: f64,
+        expansion: Option<u32>,
+        layers: Vec<BloomLayer>,
+    ) -> Self {
+        Self {
+            error_rate,
+            expansion,
+            layers,
+        }
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        self.error_rate
+    }
+
+    pub fn expansion(&self) -> Option<u32> {
+        self.expansion
+    }
+
+    pub(crate) fn layers(&self) -> &[BloomLayer] {
+        &self.layers
+    }
+
+    /// The items added, counting none twice.
+    pub fn len(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.items).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.capacity).sum()
+    }
+
+    /// Adds the item, and tells whether it may not have been added before.
+    pub fn add(&mut self, item: &str) -> RedisResult<bool> {
+        if self.contains(item) {
+            return Ok(false);
+        }
+        let last = self.layers.last().expect("filters have a layer");
+        if last.items >= last.capacity {
+            let Some(expansion) = self.expansion else {
+                return Err(anyhow::anyhow!("ERR non scaling filter is full").into());
+            };
+            let layer = BloomLayer::new(
+                last.capacity.saturating_mul(u64::from(expansion)),
+                last.error_rate * TIGHTENING,
+            )
+            .ok_or_else(|| anyhow::anyhow!("ERR filter is full and can't grow any larger"))?;
+            self.layers.push(layer);
+        }
+
+        let layer = self.layers.last_mut().expect("filters have a layer");
+        for bit in layer.positions(item) {
+            layer.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        layer.items += 1;
+        Ok(true)
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.layers.iter().any(|layer| {
+            layer
+                .positions(item)
+                .all(|bit| layer.bits[bit / 64] & (1 << (bit % 64)) != 0)
+        })
+    }
+}
+
+impl BloomLayer {
+    /// Takes the bits and hashes which keep `capacity` items at the error rate, or gives
+    /// `None` when they'd take more than a filter may.
+    fn new(capacity: u64, error_rate: f64) -> Option<Self> {
+        let bits = (capacity as f64 * -error_rate.ln() / (LN_2 * LN_2)).ceil();
+        let words = (bits / 64.0).ceil().max(1.0);
+        // An error rate too small to size for gives infinitely many bits.
+        if words.is_nan() || words * 8.0 > FILTER_MAX_BYTES as f64 {
+            return None;
+        }
+        let hashes = (-error_rate.log2()).ceil().max(1.0) as u32;
+        Some(Self {
+            capacity,
+            error_rate,
+            hashes,
+            items: 0,
+            bits: vec![0; words as usize],
+        })
+    }
+
+    /// The bits of the item, from two hashes combined as many times as there are hashes.
+    fn positions(&self, item: &str) -> impl Iterator<Item = usize> {
+        let a = hash(item, 0);
+        let b = hash(item, 1);
+        let len = self.bits.len() as u64 * 64;
+        (0..u64::from(self.hashes)).map(move |i| (a.wrapping_add(i.wrapping_mul(b)) % len) as usize)
+    }
+}
+
+impl fmt::Display for BloomFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bloom filter of {} items in {} layers",
+            self.len(),
+            self.layers.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_scales_as_it_fills() {
+        let mut filter = BloomFilter::new(0.01, 10, Some(2)).unwrap();
+        // An item may be taken for one added before, at the error rate.
+        let added = (0..100)
+            .filter(|i| filter.add(&format!("item{i}")).unwrap())
+            .count();
+        assert!(added >= 95, "{added}");
+        assert!(!filter.add("item7").unwrap());
+        assert_eq!(filter.len(), added as u64);
+        assert_eq!(filter.layers().len(), 4);
+        assert_eq!(filter.capacity(), 150);
+        assert!((0..100).all(|i| filter.contains(&format!("item{i}"))));
+
+        // Each layer errs at its own rate, which add up to about 2% here.
+        let false_positives = (100..10_100)
+            .filter(|i| filter.contains(&format!("item{i}")))
+            .count();
+        assert!(false_positives < 250, "{false_positives}");
+
+        let mut filter = BloomFilter::new(0.01, 1, None).unwrap();
+        assert!(filter.add("a").unwrap());
+        assert!(!filter.add("a").unwrap());
+        assert!(filter.add("b").is_err());
+    }
+}
@@ -0,0 +1,269 @@
+use super::{hash, RedisResult, FILTER_MAX_BYTES};
+use std::fmt;
+use std::ops::Range;
+
+/// A cuckoo filter, which keeps a fingerprint of each item in one of two buckets it may
+/// go in, so that items can be deleted and counted as well. An item with both buckets
+/// full kicks the fingerprint of another one to its other bucket, and so on. When that
+/// takes more than `max_iterations` kicks, the filter adds a table `expansion` times
+/// larger, or refuses the item when `expansion` is zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CuckooFilter {
+    bucket_size: usize,
+    max_iterations: u32,
+    expansion: u32,
+    items: u64,
+    deletes: u64,
+    /// Fingerprints bucket by bucket, with zero for an empty slot. The number of buckets
+    /// of each table is a power of two.
+    tables: Vec<Vec<u8>>,
+}
+
+impl CuckooFilter {
+    pub fn new(
+        capacity: u64,
+        bucket_size: usize,
+        max_iterations: u32,
+        expansion: u32,
+    ) -> RedisResult<Self> {
+        let len = capacity
+            .div_ceil(bucket_size as u64)
+            .max(1)
+            .checked_next_power_of_two()
+            .and_then(|buckets| buckets.checked_mul(bucket_size as u64))
+            .filter(|len| *len <= FILTER_MAX_BYTES)
+            .ok_or_else(|| anyhow::anyhow!("ERR capacity is too large"))?;
+        // Tables keep a power of two of buckets.
+        let expansion = if expansion == 0 {
+            0
+        } else {
+            expansion.next_power_of_two()
+        };
+        Ok(Self {
+            bucket_size,
+            max_iterations,
+            expansion,
+            items: 0,
+            deletes: 0,
+            tables: vec![vec![0; len as usize]],
+        })
+    }
+
+    pub(crate) fn from_tables(
+        bucket_size: usize,
+        max_iterations: u32,
+        expansion: u32,
+        items: u64,
+        deletes: u64,
+        tables: Vec<Vec<u8>>,
+    ) -> Self {
+        Self {
+            bucket_size,
+            max_iterations,
+            expansion,
+            items,
+            deletes,
+            tables,
+        }
+    }
+
+    pub fn bucket_size(&self) -> usize {
+        self.bucket_size
+    }
+
+    pub fn max_iterations(&self) -> u32 {
+        self.max_iterations
+    }
+
+    pub fn expansion(&self) -> u32 {
+        self.expansion
+    }
+
+    /// The items in the filter, counting the ones added twice twice.
+    pub fn len(&self) -> u64 {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    pub fn deletes(&self) -> u64 {
+        self.deletes
+    }
+
+    pub(crate) fn tables(&self) -> &[Vec<u8>] {
+        &self.tables
+    }
+
+    /// Adds the item, even when it was added before.
+    pub fn add(&mut self, item: &str) -> RedisResult<()> {
+        let h = hash(item, 0);
+        let fp = fingerprint(h);
+
+        // A free slot in either bucket of any table first, newest tables first.
+        let bucket_size = self.bucket_size;
+        for table in self.tables.iter_mut().rev() {
+            let (i1, i2) = buckets(table, bucket_size, h, fp);
+            for i in [i1, i2] {
+                if let Some(slot) = free_slot(table, bucket_size, i) {
+                    table[slot] = fp;
+                    self.items += 1;
+                    return Ok(());
+                }
+            }
+        }
+
+        if !self.kick(h, fp) {
+            if self.expansion == 0 {
+                return Err(anyhow::anyhow!("ERR Filter is full").into());
+            }
+            let last = self.tables.last().map_or(0, Vec::len);
+            let len = (last as u64)
+                .checked_mul(u64::from(self.expansion))
+                .filter(|len| *len <= FILTER_MAX_BYTES)
+                .ok_or_else(|| anyhow::anyhow!("ERR Filter is full"))?;
+            let mut table = vec![0; len as usize];
+            let (i1, _) = buckets(&table, bucket_size, h, fp);
+            table[i1 * bucket_size] = fp;
+            self.tables.push(table);
+        }
+        self.items += 1;
+        Ok(())
+    }
+
+    /// Kicks fingerprints about the newest table to make room for the one of the item.
+    /// When there is no room after all, the kicks are undone, so that no other item is
+    /// left out of the filter.
+    fn kick(&mut self, h: u64, fp: u8) -> bool {
+        let bucket_size = self.bucket_size;
+        let table = self.tables.last_mut().expect("filters have a table");
+        let (mut i, _) = buckets(table, bucket_size, h, fp);
+        let mut fp = fp;
+        let mut kicked: Vec<(usize, u8)> = vec![];
+        for n in 0..self.max_iterations as usize {
+            let slot = i * bucket_size + n % bucket_size;
+            kicked.push((slot, table[slot]));
+            std::mem::swap(&mut table[slot], &mut fp);
+            i = other_bucket(table, bucket_size, i, fp);
+            if let Some(slot) = free_slot(table, bucket_size, i) {
+                table[slot] = fp;
+                return true;
+            }
+        }
+        for (slot, fp) in kicked.into_iter().rev() {
+            table[slot] = fp;
+        }
+        false
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.count(item) > 0
+    }
+
+    /// How many times the item is in the filter, which may count items sharing its
+    /// fingerprint and buckets.
+    pub fn count(&self, item: &str) -> usize {
+        let h = hash(item, 0);
+        let fp = fingerprint(h);
+        self.tables
+            .iter()
+            .map(|table| {
+                let (i1, i2) = buckets(table, self.bucket_size, h, fp);
+                let mut found: Vec<usize> = slots(self.bucket_size, i1).collect();
+                if i2 != i1 {
+                    found.extend(slots(self.bucket_size, i2));
+                }
+                found.into_iter().filter(|slot| table[*slot] == fp).count()
+            })
+            .sum()
+    }
+
+    /// Deletes the item once, newest tables first, and tells whether it was there.
+    pub fn delete(&mut self, item: &str) -> bool {
+        let h = hash(item, 0);
+        let fp = fingerprint(h);
+        let bucket_size = self.bucket_size;
+        for table in self.tables.iter_mut().rev() {
+            let (i1, i2) = buckets(table, bucket_size, h, fp);
+            let mut found = slots(bucket_size, i1).chain(slots(bucket_size, i2));
+            if let Some(slot) = found.find(|slot| table[*slot] == fp) {
+                table[slot] = 0;
+                self.items -= 1;
+                self.deletes += 1;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// The fingerprint of the item from its hash, never zero, which marks empty slots.
+fn fingerprint(h: u64) -> u8 {
+    ((h >> 32) % 255 + 1) as u8
+}
+
+/// The two buckets of the table an item may go in.
+fn buckets(table: &[u8], bucket_size: usize, h: u64, fp: u8) -> (usize, usize) {
+    let len = table.len() / bucket_size;
+    let i1 = h as usize & (len - 1);
+    (i1, other_bucket(table, bucket_size, i1, fp))
+}
+
+/// The other bucket the fingerprint in bucket `i` may go in. Buckets are paired up by
+/// the fingerprint alone, so that a kicked one can move without knowing its item.
+fn other_bucket(table: &[u8], bucket_size: usize, i: usize, fp: u8) -> usize {
+    let len = table.len() / bucket_size;
+    (i ^ (u64::from(fp).wrapping_mul(0x5bd1_e995) as usize)) & (len - 1)
+}
+
+/// The slots of bucket `i`.
+fn slots(bucket_size: usize, i: usize) -> Range<usize> {
+    i * bucket_size..(i + 1) * bucket_size
+}
+
+fn free_slot(table: &[u8], bucket_size: usize, i: usize) -> Option<usize> {
+    slots(bucket_size, i).find(|slot| table[*slot] == 0)
+}
+
+impl fmt::Display for CuckooFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cuckoo filter of {} items in {} tables",
+            self.items,
+            self.tables.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_adds_counts_and_deletes_items() {
+        let mut filter = CuckooFilter::new(8, 2, 20, 1).unwrap();
+        for i in 0..50 {
+            filter.add(&format!("item{i}")).unwrap();
+        }
+        filter.add("item0").unwrap();
+        assert_eq!(filter.len(), 51);
+        assert!(filter.tables().len() > 1);
+        assert!((0..50).all(|i| filter.contains(&format!("item{i}"))));
+        assert!(filter.count("item0") >= 2);
+
+        assert!(filter.delete("item0"));
+        assert!(filter.delete("item0"));
+        assert_eq!(filter.len(), 49);
+        assert_eq!(filter.deletes(), 2);
+        assert!((1..50).all(|i| filter.contains(&format!("item{i}"))));
+
+        let mut filter = CuckooFilter::new(2, 1, 5, 0).unwrap();
+        let added = (0..10)
+            .filter(|i| filter.add(&format!("item{i}")).is_ok())
+            .count();
+        assert!(added < 10);
+        assert_eq!(filter.len(), added as u64);
+    }
+}
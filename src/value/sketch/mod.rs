@@ -0,0 +1,41 @@
+//! Sketches, which summarize the items added to them in little memory and answer
+//! questions about them with some error: whether an item was added, for the Bloom and
+//! cuckoo filters, and which items were added the most, for top-k.
+mod bloom;
+mod cuckoo;
+mod topk;
+pub use bloom::{BloomFilter, BloomLayer};
+pub use cuckoo::CuckooFilter;
+pub use topk::TopK;
+
+use super::RedisResult;
+
+/// The most bytes a layer of a Bloom filter or a table of a cuckoo filter may take, so
+/// that a filter sized for a huge capacity is refused instead of aborting the server.
+const FILTER_MAX_BYTES: u64 = 1 << 30;
+
+/// The 64 bit hash of the item, FNV-1a finished with the mixer of SplitMix64, so that
+/// every bit of it depends on every byte. Sketches are saved in RDB files and looked up
+/// again with it, so it must never change.
+fn hash(item: &str, seed: u64) -> u64 {
+    let mut h = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for byte in item.as_bytes() {
+        h ^= u64::from(*byte);
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_hashes_the_same_across_builds() {
+        assert_eq!(hash("", 0), 0xf52a_15e9_a9b5_e89b);
+        assert_ne!(hash("a", 0), hash("a", 1));
+        assert_ne!(hash("a", 0), hash("b", 0));
+    }
+}
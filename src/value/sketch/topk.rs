@@ -0,0 +1,185 @@
+use super::hash;
+use std::fmt;
+
+/// Seeds the generator deciding which counters decay, so that a replica applying the
+/// same additions ends up with the same sketch.
+const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// The `k` items added the most, estimated with HeavyKeeper: `depth` rows of `width`
+/// counters, where an item counts in one counter per row. A counter held by another item
+/// decays by one with a probability of `decay` to the power of its count, and is taken
+/// over by the item once it gets to zero, so counters end up held by the heavy items.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopK {
+    k: usize,
+    width: usize,
+    depth: usize,
+    decay: f64,
+    /// The fingerprint of the item holding each counter, and its count, row by row.
+    counters: Vec<(u32, u32)>,
+    /// The top items with their counts, the largest first.
+    top: Vec<(String, u32)>,
+    rng: u64,
+}
+
+impl TopK {
+    pub fn new(k: usize, width: usize, depth: usize, decay: f64) -> Self {
+        Self {
+            k,
+            width,
+            depth,
+            decay,
+            counters: vec![(0, 0); width * depth],
+            top: vec![],
+            rng: SEED,
+        }
+    }
+
+    pub(crate) fn from_parts(
+        (k, width, depth, decay): (usize, usize, usize, f64),
+        counters: Vec<(u32, u32)>,
+        top: Vec<(String, u32)>,
+        rng: u64,
+    ) -> Self {
+        Self {
+            k,
+            width,
+            depth,
+            decay,
+            counters,
+            top,
+            rng,
+        }
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn decay(&self) -> f64 {
+        self.decay
+    }
+
+    pub(crate) fn counters(&self) -> &[(u32, u32)] {
+        &self.counters
+    }
+
+    pub(crate) fn rng(&self) -> u64 {
+        self.rng
+    }
+
+    /// The top items with their estimated counts, the largest first.
+    pub fn list(&self) -> &[(String, u32)] {
+        &self.top
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.top.iter().any(|(top, _)| top == item)
+    }
+
+    /// Counts the item `by` more times, and gives the item it pushed out of the top, if
+    /// any.
+    pub fn incr_by(&mut self, item: &str, by: u32) -> Option<String> {
+        let fp = hash(item, 0) as u32;
+        let mut count = 0;
+        for row in 0..self.depth {
+            let column = (hash(item, row as u64 + 1) % self.width as u64) as usize;
+            let i = row * self.width + column;
+            let (holder, held) = self.counters[i];
+            if held == 0 || holder == fp {
+                self.counters[i] = (fp, held.saturating_add(by));
+            } else {
+                let mut held = held;
+                let mut left = by;
+                while left > 0 && held > 0 {
+                    left -= 1;
+                    if self.next_f64() < self.decay.powf(f64::from(held)) {
+                        held -= 1;
+                    }
+                }
+                self.counters[i] = if held == 0 {
+                    (fp, left)
+                } else {
+                    (holder, held)
+                };
+            }
+            if self.counters[i].0 == fp {
+                count = count.max(self.counters[i].1);
+            }
+        }
+
+        if let Some(top) = self.top.iter_mut().find(|(top, _)| top == item) {
+            top.1 = top.1.max(count);
+            self.sort();
+            return None;
+        }
+        if self.top.len() < self.k {
+            self.top.push((item.into(), count));
+            self.sort();
+            return None;
+        }
+        match self.top.last() {
+            Some((_, least)) if count > *least => {
+                let expelled = self.top.pop().map(|(item, _)| item);
+                self.top.push((item.into(), count));
+                self.sort();
+                expelled
+            }
+            _ => None,
+        }
+    }
+
+    /// Orders the top by count, then by item, so that ties come out the same every time.
+    fn sort(&mut self) {
+        self.top
+            .sort_by(|(i0, c0), (i1, c1)| c1.cmp(c0).then_with(|| i0.cmp(i1)));
+    }
+
+    /// A number in [0, 1) from a xorshift generator.
+    fn next_f64(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl fmt::Display for TopK {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "top-{} of {} items", self.k, self.top.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_keeps_the_heaviest_items() {
+        let mut topk = TopK::new(3, 50, 4, 0.9);
+        for (item, times) in [("a", 100), ("b", 50), ("c", 20), ("d", 10)] {
+            for _ in 0..times {
+                topk.incr_by(item, 1);
+            }
+        }
+        for i in 0..30 {
+            topk.incr_by(&format!("light{i}"), 1);
+        }
+        let items: Vec<&str> = topk.list().iter().map(|(item, _)| item.as_str()).collect();
+        assert_eq!(items, ["a", "b", "c"]);
+        assert_eq!(topk.list()[0].1, 100);
+        assert!(topk.contains("c"));
+        assert!(!topk.contains("d"));
+
+        assert_eq!(topk.incr_by("d", 100), Some("c".into()));
+        assert_eq!(topk.list()[0], ("d".to_string(), 110));
+    }
+}
@@ -2,49 +2,178 @@ use super::{RedisError, RedisResult, Resp};
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
 
-#[derive(Debug, Clone)]
-pub struct RedisStream(Vec<StreamEntry>);
+const STREAM_ID_LEN: usize = 16;
+
+/// Entries sorted by id. Each entry is reference counted so that readers and copies of the
+/// stream share them instead of copying.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedisStream {
+    entries: Vec<Arc<StreamEntry>>,
+    groups: Vec<ConsumerGroup>,
+    /// The id of the last entry added, which stays when the entry is deleted so that
+    /// new ids keep growing.
+    last_id: Option<StreamEntryId>,
+    max_deleted_id: StreamEntryId,
+    entries_added: u64,
+}
 
 impl RedisStream {
-    pub fn new() -> Self {
-        Self(vec![])
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            groups: Vec::new(),
+            last_id: None,
+            max_deleted_id: StreamEntryId(0, 0),
+            entries_added: 0,
+        }
     }
 
-    pub fn push(&mut self, entry: StreamEntry) -> RedisResult<()> {
+    pub fn push(&mut self, entry: Arc<StreamEntry>) -> RedisResult<()> {
         if self.valid_id(entry.id()) {
-            self.0.push(entry);
+            self.last_id = Some(entry.id());
+            self.entries_added += 1;
+            self.entries.push(entry);
             Ok(())
         } else {
             Err(RedisError::SmallerStreamEntryId)
         }
     }
 
+    /// Entries from `start` to `end` inclusive, found by binary search.
     pub fn query(
         &self,
         start: StreamEntryIdFactor,
         end: StreamEntryIdFactor,
-    ) -> RedisResult<impl Iterator<Item = &StreamEntry>> {
+    ) -> RedisResult<&[Arc<StreamEntry>]> {
         let start = start.as_start()?;
         let end = end.as_end()?;
-        Ok(self
-            .0
-            .iter()
-            .filter(move |e| start <= e.id() && e.id() <= end))
+        let from = self.entries.partition_point(|e| e.id() < start);
+        let to = self.entries.partition_point(|e| e.id() <= end).max(from);
+        Ok(&self.entries[from..to])
     }
 
-    pub fn find(&self, start: StreamEntryIdFactor) -> RedisResult<Option<&StreamEntry>> {
+    /// The first entry after `start`.
+    pub fn find(&self, start: StreamEntryIdFactor) -> RedisResult<Option<&Arc<StreamEntry>>> {
         let start = start.as_start()?;
-        Ok(self.0.iter().find(move |e| start < e.id()))
+        let pos = self.entries.partition_point(|e| e.id() <= start);
+        Ok(self.entries.get(pos))
+    }
+
+    pub fn entries(&self) -> &[Arc<StreamEntry>] {
+        &self.entries
     }
 
     pub fn last_id(&self) -> Option<StreamEntryId> {
-        self.0.last().map(StreamEntry::id)
+        self.last_id
+    }
+
+    pub(crate) fn max_deleted_id(&self) -> StreamEntryId {
+        self.max_deleted_id
+    }
+
+    pub(crate) fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    /// Restores what an RDB file tells of the entries added and deleted before it was
+    /// saved. The last id never goes below the last entry's.
+    pub(crate) fn set_meta(
+        &mut self,
+        last_id: StreamEntryId,
+        max_deleted_id: StreamEntryId,
+        entries_added: u64,
+    ) {
+        let last_id = Some(last_id).filter(|id| *id > StreamEntryId(0, 0));
+        self.last_id = self.last_id.max(last_id);
+        self.max_deleted_id = max_deleted_id;
+        self.entries_added = self.entries_added.max(entries_added);
+    }
+
+    /// Deletes the entries, replying for each id -1 when there is no such entry, 1 when
+    /// it's deleted, and 2 when the policy is ACKED and a group still refers to it.
+    pub fn delete(&mut self, ids: &[StreamEntryId], policy: DeletePolicy) -> Vec<i64> {
+        ids.iter()
+            .map(|id| self.delete_entry(*id, policy))
+            .collect()
+    }
+
+    /// Acknowledges the entries in the group, then deletes them as `delete` does. Ids
+    /// not pending in the group get -1. Nothing is done when there's no such group.
+    pub fn ack_delete(
+        &mut self,
+        group: &str,
+        ids: &[StreamEntryId],
+        policy: DeletePolicy,
+    ) -> Option<Vec<i64>> {
+        let index = self.groups.iter().position(|g| g.name == group)?;
+        let replies = ids
+            .iter()
+            .map(|id| {
+                if !self.groups[index].forget(*id) {
+                    return -1;
+                }
+                // An entry deleted before it was acknowledged has nothing left to delete.
+                match self.delete_entry(*id, policy) {
+                    -1 => 1,
+                    reply => reply,
+                }
+            })
+            .collect();
+        Some(replies)
+    }
+
+    fn delete_entry(&mut self, id: StreamEntryId, policy: DeletePolicy) -> i64 {
+        let Ok(pos) = self.entries.binary_search_by_key(&id, |e| e.id()) else {
+            return -1;
+        };
+        if policy == DeletePolicy::Acked && self.referenced(id) {
+            return 2;
+        }
+
+        self.entries.remove(pos);
+        self.max_deleted_id = self.max_deleted_id.max(id);
+        if policy == DeletePolicy::DelRef {
+            for group in self.groups.iter_mut() {
+                group.forget(id);
+            }
+        }
+        1
+    }
+
+    /// Whether a group has yet to read the entry, or has read it without acknowledging.
+    fn referenced(&self, id: StreamEntryId) -> bool {
+        self.groups
+            .iter()
+            .any(|g| g.last_id < id || g.pending.iter().any(|p| p.id == id))
+    }
+
+    pub fn serialized_len(&self) -> usize {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let fields: usize = entry
+                    .values()
+                    .iter()
+                    .map(|(key, value)| key.len() + value.len() + 2)
+                    .sum();
+                STREAM_ID_LEN + fields
+            })
+            .sum()
+    }
+
+    /// The consumer groups, kept as loaded so that they survive saves.
+    pub(crate) fn groups(&self) -> &[ConsumerGroup] {
+        &self.groups
+    }
+
+    pub(crate) fn set_groups(&mut self, groups: Vec<ConsumerGroup>) {
+        self.groups = groups;
     }
 
     fn valid_id(&self, id: StreamEntryId) -> bool {
-        match self.last_id() {
+        match self.last_id {
             Some(last_id) => last_id < id,
             None => true,
         }
@@ -55,7 +184,7 @@ impl fmt::Display for RedisStream {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "entries")?;
 
-        for entry in self.0.iter() {
+        for entry in self.entries.iter() {
             writeln!(f, "{entry}")?;
         }
 
@@ -69,6 +198,83 @@ impl Default for RedisStream {
     }
 }
 
+/// A consumer group of a stream, with the entries delivered to its consumers and not
+/// acknowledged yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ConsumerGroup {
+    pub(crate) name: String,
+    pub(crate) last_id: StreamEntryId,
+    pub(crate) entries_read: Option<u64>,
+    pub(crate) pending: Vec<PendingEntry>,
+    pub(crate) consumers: Vec<Consumer>,
+}
+
+impl ConsumerGroup {
+    /// Drops the entry from the pending entries of the group and of its consumer, and
+    /// tells whether it was pending.
+    fn forget(&mut self, id: StreamEntryId) -> bool {
+        let Some(pos) = self.pending.iter().position(|p| p.id == id) else {
+            return false;
+        };
+        self.pending.remove(pos);
+        for consumer in self.consumers.iter_mut() {
+            consumer.pending.retain(|pending| *pending != id);
+        }
+        true
+    }
+}
+
+/// What deleting a stream entry does with the references consumer groups hold to it,
+/// as XDELEX and XACKDEL take it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeletePolicy {
+    /// Deletes the entry and leaves the pending entries of the groups as they are.
+    #[default]
+    KeepRef,
+    /// Deletes the entry along with the pending entries referring to it.
+    DelRef,
+    /// Deletes the entry only once every group has read and acknowledged it.
+    Acked,
+}
+
+impl DeletePolicy {
+    pub fn parse(token: &str) -> Option<Self> {
+        match token.to_uppercase().as_str() {
+            "KEEPREF" => Some(Self::KeepRef),
+            "DELREF" => Some(Self::DelRef),
+            "ACKED" => Some(Self::Acked),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DeletePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KeepRef => write!(f, "KEEPREF"),
+            Self::DelRef => write!(f, "DELREF"),
+            Self::Acked => write!(f, "ACKED"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PendingEntry {
+    pub(crate) id: StreamEntryId,
+    /// Milliseconds since UNIX epoch of the last delivery.
+    pub(crate) delivery_time: u64,
+    pub(crate) delivery_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Consumer {
+    pub(crate) name: String,
+    pub(crate) seen_time: u64,
+    pub(crate) active_time: u64,
+    /// The ids of the group's pending entries delivered to this consumer.
+    pub(crate) pending: Vec<StreamEntryId>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StreamEntry {
     id: StreamEntryId,
@@ -113,35 +319,43 @@ impl fmt::Display for StreamEntry {
     }
 }
 
-impl From<StreamEntry> for Resp {
-    fn from(entry: StreamEntry) -> Self {
+impl From<&StreamEntry> for Resp {
+    fn from(entry: &StreamEntry) -> Self {
         let StreamEntry { id, values } = entry;
 
         let mut elements: Vec<Resp> = vec![];
 
         for (key, value) in values {
-            elements.push(Resp::BS(Some(key)));
-            elements.push(Resp::BS(Some(value)));
+            elements.push(Resp::BS(Some(key.to_string())));
+            elements.push(Resp::BS(Some(value.to_string())));
         }
 
         Resp::A(vec![Resp::BS(Some(format!("{id}"))), Resp::A(elements)])
     }
 }
 
-impl From<Vec<StreamEntry>> for Resp {
-    fn from(resps: Vec<StreamEntry>) -> Self {
-        Resp::A(resps.into_iter().map(Resp::from).collect())
+impl From<Vec<Arc<StreamEntry>>> for Resp {
+    fn from(resps: Vec<Arc<StreamEntry>>) -> Self {
+        Resp::A(
+            resps
+                .iter()
+                .map(|entry| Resp::from(entry.as_ref()))
+                .collect(),
+        )
     }
 }
 
-impl From<(String, StreamEntry)> for Resp {
-    fn from((key, entry): (String, StreamEntry)) -> Self {
-        Resp::A(vec![Resp::BS(Some(key)), Resp::A(vec![Resp::from(entry)])])
+impl From<(String, Arc<StreamEntry>)> for Resp {
+    fn from((key, entry): (String, Arc<StreamEntry>)) -> Self {
+        Resp::A(vec![
+            Resp::BS(Some(key)),
+            Resp::A(vec![Resp::from(entry.as_ref())]),
+        ])
     }
 }
 
-impl From<Vec<(String, StreamEntry)>> for Resp {
-    fn from(values: Vec<(String, StreamEntry)>) -> Self {
+impl From<Vec<(String, Arc<StreamEntry>)>> for Resp {
+    fn from(values: Vec<(String, Arc<StreamEntry>)>) -> Self {
         Resp::A(values.into_iter().map(Resp::from).collect())
     }
 }
@@ -149,6 +363,30 @@ impl From<Vec<(String, StreamEntry)>> for Resp {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct StreamEntryId(u64, u64);
 
+impl StreamEntryId {
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self(ms, seq)
+    }
+
+    pub fn ms(&self) -> u64 {
+        self.0
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.1
+    }
+
+    /// Parses an id given to a command as `<ms>-<seq>`, or `<ms>` for the sequence 0.
+    pub fn parse(value: &str) -> RedisResult<Self> {
+        let invalid =
+            || anyhow::anyhow!("ERR Invalid stream ID specified as stream command argument");
+        let (ms, seq) = value.split_once('-').unwrap_or((value, "0"));
+        let ms = ms.parse::<u64>().map_err(|_| invalid())?;
+        let seq = seq.parse::<u64>().map_err(|_| invalid())?;
+        Ok(Self(ms, seq))
+    }
+}
+
 impl fmt::Display for StreamEntryId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}-{}", self.0, self.1)
@@ -184,6 +422,15 @@ impl StreamEntryIdFactor {
         value.to_string().try_into()
     }
 
+    /// Same as `new` except that `*` generates the id from `now`, the milliseconds
+    /// since UNIX epoch.
+    pub fn new_at(value: &str, now: u64) -> RedisResult<Self> {
+        if value == "*" {
+            return Ok(Self::Timestamp(now));
+        }
+        Self::new(value)
+    }
+
     pub fn try_into_id(self, stream: &RedisStream) -> RedisResult<StreamEntryId> {
         match self {
             Self::MayValidId(0, 0) => Err(RedisError::InvalidStreamEntryId00),
@@ -247,14 +494,6 @@ impl TryFrom<String> for StreamEntryIdFactor {
     type Error = RedisError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        if value.as_str() == "*" {
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .map_err(|_| anyhow::anyhow!("SystemTime before UNIX EPOCH!"))?
-                .as_millis() as u64;
-            return Ok(Self::Timestamp(now));
-        }
-
         if value.as_str() == "-" {
             return Ok(Self::RangeFromBeginning);
         }
@@ -297,4 +536,96 @@ mod tests {
         let id1 = StreamEntryId(1, 2);
         assert!(id0 < id1);
     }
+
+    #[test]
+    fn it_queries_entries_by_range() {
+        let mut stream = RedisStream::new();
+        for seq in 1..=5 {
+            let entry = StreamEntry::new(StreamEntryId(1, seq), HashMap::new());
+            stream.push(Arc::new(entry)).unwrap();
+        }
+
+        let ids = |entries: &[Arc<StreamEntry>]| -> Vec<StreamEntryId> {
+            entries.iter().map(|e| e.id()).collect()
+        };
+
+        let start = StreamEntryIdFactor::new("1-2").unwrap();
+        let end = StreamEntryIdFactor::new("1-4").unwrap();
+        let entries = stream.query(start, end).unwrap();
+        let expected = vec![
+            StreamEntryId(1, 2),
+            StreamEntryId(1, 3),
+            StreamEntryId(1, 4),
+        ];
+        assert_eq!(ids(entries), expected);
+
+        let start = StreamEntryIdFactor::new("2-0").unwrap();
+        let end = StreamEntryIdFactor::new("+").unwrap();
+        assert!(stream.query(start, end).unwrap().is_empty());
+
+        let start = StreamEntryIdFactor::new("1-5").unwrap();
+        let end = StreamEntryIdFactor::new("1-1").unwrap();
+        assert!(stream.query(start, end).unwrap().is_empty());
+
+        let start = StreamEntryIdFactor::new("1-3").unwrap();
+        let found = stream.find(start).unwrap().map(|e| e.id());
+        assert_eq!(found, Some(StreamEntryId(1, 4)));
+    }
+
+    #[test]
+    fn it_deletes_entries_by_policy() {
+        let mut stream = RedisStream::new();
+        for seq in 1..=5 {
+            let entry = StreamEntry::new(StreamEntryId(1, seq), HashMap::new());
+            stream.push(Arc::new(entry)).unwrap();
+        }
+        // The group has read up to 1-4, and has 1-2 and 1-3 pending.
+        let pending = |seq| PendingEntry {
+            id: StreamEntryId(1, seq),
+            delivery_time: 0,
+            delivery_count: 1,
+        };
+        stream.set_groups(vec![ConsumerGroup {
+            name: "group".into(),
+            last_id: StreamEntryId(1, 4),
+            entries_read: Some(4),
+            pending: vec![pending(2), pending(3)],
+            consumers: vec![Consumer {
+                name: "alice".into(),
+                seen_time: 0,
+                active_time: 0,
+                pending: vec![StreamEntryId(1, 2), StreamEntryId(1, 3)],
+            }],
+        }]);
+        let id = |seq| StreamEntryId(1, seq);
+
+        // Pending and unread entries are referenced; acknowledged ones aren't.
+        let replies = stream.delete(&[id(1), id(2), id(5), id(9)], DeletePolicy::Acked);
+        assert_eq!(replies, [1, 2, 2, -1]);
+
+        assert_eq!(stream.delete(&[id(2)], DeletePolicy::KeepRef), [1]);
+        assert_eq!(stream.groups()[0].pending.len(), 2);
+        assert_eq!(stream.delete(&[id(3)], DeletePolicy::DelRef), [1]);
+        assert_eq!(stream.groups()[0].pending, [pending(2)]);
+        assert_eq!(stream.groups()[0].consumers[0].pending, [id(2)]);
+
+        // 1-2 was deleted while pending, so acknowledging it only drops the reference.
+        let replies = stream.ack_delete("group", &[id(2), id(4)], DeletePolicy::Acked);
+        assert_eq!(replies, Some(vec![1, -1]));
+        assert!(stream.groups()[0].pending.is_empty());
+        assert!(stream
+            .ack_delete("other", &[id(4)], DeletePolicy::Acked)
+            .is_none());
+
+        // Ids keep growing after the last entry is deleted.
+        assert_eq!(stream.delete(&[id(5)], DeletePolicy::KeepRef), [1]);
+        assert_eq!(stream.last_id(), Some(id(5)));
+        assert_eq!(stream.max_deleted_id(), id(5));
+        let entry = StreamEntry::new(id(5), HashMap::new());
+        assert!(stream.push(Arc::new(entry)).is_err());
+
+        assert_eq!(StreamEntryId::parse("7").unwrap(), StreamEntryId(7, 0));
+        assert_eq!(StreamEntryId::parse("7-3").unwrap(), StreamEntryId(7, 3));
+        assert!(StreamEntryId::parse("7-x").is_err());
+    }
 }
@@ -0,0 +1,146 @@
+use super::{RedisError, RedisResult};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Members with an embedding each, all of the same dimension, searched by cosine
+/// similarity. Searches go over every member, so they are exact where Redis's HNSW
+/// graphs are approximate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorSet {
+    dim: usize,
+    members: HashMap<String, Vec<f32>>,
+}
+
+/// What VSIM looks for members similar to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VectorQuery {
+    /// The vector of a member of the set.
+    Element(String),
+    Values(Vec<f32>),
+}
+
+impl VectorSet {
+    pub fn new(dim: usize) -> Self {
+        Self {
+            dim,
+            members: HashMap::new(),
+        }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = (&String, &Vec<f32>)> {
+        self.members.iter()
+    }
+
+    /// Adds the member, or replaces its vector, and tells whether it is new.
+    pub fn add(&mut self, member: String, vector: Vec<f32>) -> RedisResult<bool> {
+        self.check_dim(&vector)?;
+        Ok(self.members.insert(member, vector).is_none())
+    }
+
+    /// The `count` members most similar to the query, most similar first, each with its
+    /// similarity from 0 for the opposite direction to 1 for the same one. Members as
+    /// similar as each other come in the order of their names.
+    pub fn similar(&self, query: &VectorQuery, count: usize) -> RedisResult<Vec<(String, f64)>> {
+        let vector = match query {
+            VectorQuery::Element(member) => self
+                .members
+                .get(member)
+                .ok_or(anyhow::anyhow!("ERR element not found in set"))?,
+            VectorQuery::Values(vector) => {
+                self.check_dim(vector)?;
+                vector
+            }
+        };
+
+        let mut scored: Vec<(String, f64)> = self
+            .members
+            .iter()
+            .map(|(member, other)| (member.clone(), similarity(vector, other)))
+            .collect();
+        scored.sort_by(|(m0, s0), (m1, s1)| {
+            s1.partial_cmp(s0)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| m0.cmp(m1))
+        });
+        scored.truncate(count);
+        Ok(scored)
+    }
+
+    fn check_dim(&self, vector: &[f32]) -> RedisResult<()> {
+        if vector.len() == self.dim {
+            Ok(())
+        } else {
+            Err(RedisError::from(anyhow::anyhow!(
+                "ERR Vector dimension mismatch - got {} but set has {}",
+                vector.len(),
+                self.dim
+            )))
+        }
+    }
+}
+
+impl fmt::Display for VectorSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "vectorset of {} members of dim {}", self.len(), self.dim)
+    }
+}
+
+/// The cosine similarity scaled from [-1, 1] to [0, 1]. A zero vector has no direction,
+/// so it is taken as orthogonal to every other.
+fn similarity(a: &[f32], b: &[f32]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| *x as f64 * *y as f64).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| *x as f64 * *x as f64).sum::<f64>().sqrt();
+    let norms = norm(a) * norm(b);
+    let cosine = if norms == 0.0 { 0.0 } else { dot / norms };
+    (1.0 + cosine.clamp(-1.0, 1.0)) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_similar_members() {
+        let mut set = VectorSet::new(2);
+        assert!(set.add("east".into(), vec![1.0, 0.0]).unwrap());
+        assert!(set.add("north".into(), vec![0.0, 2.0]).unwrap());
+        assert!(set.add("west".into(), vec![-1.0, 0.0]).unwrap());
+        assert!(set.add("northeast".into(), vec![1.0, 1.0]).unwrap());
+        assert!(!set.add("west".into(), vec![-3.0, 0.0]).unwrap());
+        assert!(set.add("up".into(), vec![1.0, 0.0, 0.0]).is_err());
+        assert_eq!(set.len(), 4);
+
+        let found = set
+            .similar(&VectorQuery::Values(vec![2.0, 0.0]), 10)
+            .unwrap();
+        let members: Vec<&str> = found.iter().map(|(m, _)| m.as_str()).collect();
+        assert_eq!(members, ["east", "northeast", "north", "west"]);
+        assert_eq!(found[0].1, 1.0);
+        assert_eq!(found[2].1, 0.5);
+        assert_eq!(found[3].1, 0.0);
+
+        let found = set
+            .similar(&VectorQuery::Element("north".into()), 2)
+            .unwrap();
+        assert_eq!(found[0], ("north".to_string(), 1.0));
+        assert_eq!(found[1].0, "northeast");
+
+        assert!(set
+            .similar(&VectorQuery::Element("south".into()), 1)
+            .is_err());
+        assert!(set.similar(&VectorQuery::Values(vec![1.0]), 1).is_err());
+    }
+}